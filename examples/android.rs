@@ -0,0 +1,15 @@
+// cargo apk run --example android --features android
+#[cfg(target_os = "android")]
+#[path = "../src/viewer.rs"]
+#[allow(dead_code)]
+mod viewer;
+
+#[cfg(target_os = "android")]
+#[no_mangle]
+fn android_main(app: caterpie::android::AndroidApp) {
+    use caterpie::{android, engine::config::EngineConfig, Engine};
+
+    let _logging = android::init(&app);
+    let event_loop = android::event_loop(app);
+    Engine::run(event_loop, EngineConfig::load(), viewer::Viewer::default()).unwrap();
+}
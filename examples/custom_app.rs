@@ -0,0 +1,94 @@
+// A ring of models and generated shapes orbiting the origin, viewed side by side from two cameras.
+// Run with `cargo run --example custom_app`
+use caterpie::{
+    engine::{
+        config::EngineConfig,
+        mesh::{MeshSource, Primitive},
+        scene::{Camera, RenderObject},
+        viewport::{ViewId, ViewportLayout},
+    },
+    CaterpieApp, Engine, InputState, UiContext,
+};
+use cgmath::{point3, vec3, Deg, Matrix4};
+use winit::{event_loop::EventLoop, keyboard::KeyCode};
+
+const RING_SIZE: usize = 8;
+const RING_RADIUS: f32 = 2.5;
+const RING_MESHES: [MeshSource; 4] = [
+    MeshSource::Model,
+    MeshSource::Primitive(Primitive::Cube { size: 1.0 }),
+    MeshSource::Primitive(Primitive::UvSphere {
+        radius: 0.6,
+        slices: 32,
+        stacks: 16,
+    }),
+    MeshSource::Primitive(Primitive::Torus {
+        major_radius: 0.5,
+        minor_radius: 0.2,
+        major_segments: 32,
+        minor_segments: 16,
+    }),
+];
+
+#[derive(Default)]
+struct Orbit {
+    paused: bool,
+    elapsed: f32,
+}
+
+impl CaterpieApp for Orbit {
+    fn setup(&mut self, engine: &mut Engine) {
+        for index in 0..RING_SIZE {
+            let angle = Deg(360.0 / RING_SIZE as f32 * index as f32);
+            let transform = Matrix4::from_angle_z(angle)
+                * Matrix4::from_translation(vec3(RING_RADIUS, 0.0, 0.0))
+                * Matrix4::from_scale(0.6);
+            let mesh = RING_MESHES[index % RING_MESHES.len()].clone();
+            engine.add_object(mesh, RenderObject::new(transform));
+        }
+        engine.set_viewport_layout(ViewId::PRIMARY, ViewportLayout::SplitHorizontal);
+        engine.set_camera(
+            ViewId::PRIMARY,
+            1,
+            Camera {
+                eye: point3(0.0, 0.0, 8.0),
+                up: vec3(0.0, 1.0, 0.0),
+                far: 20.0,
+                ..Camera::default()
+            },
+        );
+    }
+
+    fn update(&mut self, engine: &mut Engine, dt: f32, input: &InputState) {
+        if input.just_pressed(KeyCode::Space) {
+            self.paused = !self.paused;
+        }
+        if !self.paused {
+            self.elapsed += dt;
+        }
+        let angle = Deg(self.elapsed * 20.0);
+        let eye = Matrix4::from_angle_z(angle) * point3(6.0, 0.0, 3.0).to_homogeneous();
+        engine.set_camera(
+            ViewId::PRIMARY,
+            0,
+            Camera {
+                eye: point3(eye.x, eye.y, eye.z),
+                far: 20.0,
+                ..Camera::default()
+            },
+        );
+    }
+
+    fn ui(&mut self, ctx: &mut UiContext) {
+        ctx.label("custom_app");
+        if self.paused {
+            ctx.label("paused (space)");
+        }
+    }
+}
+
+fn main() {
+    let _logging = caterpie::logging::init();
+    let event_loop = EventLoop::new().unwrap();
+    Engine::run(event_loop, EngineConfig::load(), Orbit::default()).unwrap();
+}
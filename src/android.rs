@@ -0,0 +1,20 @@
+use winit::{event_loop::EventLoop, platform::android::EventLoopBuilderExtAndroid};
+
+pub use winit::platform::android::activity::AndroidApp;
+
+use crate::{logging, utils};
+
+// Call first thing in `android_main`: logs to logcat and reads assets from
+// the APK from then on
+pub fn init(app: &AndroidApp) -> logging::LoggingGuard {
+    utils::io::set_android_app(app.clone());
+    logging::init()
+}
+
+// The event loop bound to this activity, hand it to `Engine::run`
+pub fn event_loop(app: AndroidApp) -> EventLoop<()> {
+    EventLoop::builder()
+        .with_android_app(app)
+        .build()
+        .expect("Failed to create the Android event loop")
+}
@@ -1,85 +1,663 @@
-use core::time;
-use std::process::exit;
-use std::{os::unix::thread, thread::sleep};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use log::debug;
+use log::{debug, error, info, warn};
 use winit::application::ApplicationHandler;
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, KeyEvent, MouseButton};
+use winit::event_loop::{ActiveEventLoop, ControlFlow};
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::{
     dpi::PhysicalSize,
-    event::{self, KeyEvent},
-    window::{Window, WindowAttributes},
+    event,
+    monitor::{MonitorHandle, VideoModeHandle},
+    window::{Fullscreen, Icon, Window, WindowAttributes, WindowId},
 };
 
-use crate::engine::Engine;
+use crate::engine::{
+    config::{DisplayMode, EngineConfig},
+    debug_palette::DebugPalette,
+    frame_pacer::PacingMode,
+    present_stats::PresentStats,
+    scene::Camera,
+    viewport::ViewId,
+    BloomSettings, CullingStats, Engine, ShadingPath, SsaoSettings,
+};
+use crate::utils::embedded;
+
+// User code driven by `Engine::run`
+pub trait CaterpieApp {
+    // Called once after the engine has been initialized, before the first frame
+    fn setup(&mut self, engine: &mut Engine);
+
+    // Called before every frame with the seconds elapsed since the last one
+    fn update(&mut self, engine: &mut Engine, dt: f32, input: &InputState);
 
-#[derive(Default)]
-pub struct App {
-    request_redraw: bool,
+    // Called at the configured tick rate with the fixed tick length, zero or
+    // more times before each `update`
+    fn update_fixed(&mut self, _engine: &mut Engine, _dt: f32) {}
+
+    // Describes the overlay for this frame
+    fn ui(&mut self, _ctx: &mut UiContext) {}
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct InputState {
+    held: HashSet<KeyCode>,
+    just_pressed: HashSet<KeyCode>,
+    // In physical pixels of the primary window, None while it is outside
+    cursor: Option<PhysicalPosition<f64>>,
+    mouse_held: HashSet<MouseButton>,
+    mouse_just_pressed: HashSet<MouseButton>,
+    // Files and folders dropped onto the primary window this frame
+    dropped_files: Vec<PathBuf>,
+}
+
+impl InputState {
+    pub fn is_held(&self, key: KeyCode) -> bool {
+        self.held.contains(&key)
+    }
+
+    // Only true for the first frame after the key went down
+    pub fn just_pressed(&self, key: KeyCode) -> bool {
+        self.just_pressed.contains(&key)
+    }
+
+    pub fn cursor_position(&self) -> Option<PhysicalPosition<f64>> {
+        self.cursor
+    }
+
+    pub fn is_mouse_held(&self, button: MouseButton) -> bool {
+        self.mouse_held.contains(&button)
+    }
+
+    pub fn mouse_just_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_just_pressed.contains(&button)
+    }
+
+    pub fn dropped_files(&self) -> &[PathBuf] {
+        &self.dropped_files
+    }
+
+    fn key_event(&mut self, key: KeyCode, state: ElementState, repeat: bool) {
+        match state {
+            ElementState::Pressed => {
+                if !repeat {
+                    self.just_pressed.insert(key);
+                }
+                self.held.insert(key);
+            }
+            ElementState::Released => {
+                self.held.remove(&key);
+            }
+        }
+    }
+
+    fn mouse_event(&mut self, button: MouseButton, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                self.mouse_just_pressed.insert(button);
+                self.mouse_held.insert(button);
+            }
+            ElementState::Released => {
+                self.mouse_held.remove(&button);
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.just_pressed.clear();
+        self.mouse_just_pressed.clear();
+        self.dropped_files.clear();
+    }
+}
+
+// The overlay is the primary window's title until there is an on screen UI
+pub struct UiContext {
+    pub dt: f32,
+    pub fps: Option<f32>,
+    pub pacing_mode: PacingMode,
+    pub render_scale: f32,
+    pub bloom: Option<BloomSettings>,
+    pub ssao: Option<SsaoSettings>,
+    pub shading_path: ShadingPath,
+    pub lights: usize,
+    pub culling: CullingStats,
+    // Video mode of the primary window while it is exclusive fullscreen
+    pub fullscreen: Option<DisplayMode>,
+    // Milliseconds from reading input to the frame being shown, estimated
+    pub input_latency: Option<f32>,
+    pub present: PresentStats,
+    pub debug_palette: DebugPalette,
+    labels: Vec<String>,
+}
+
+impl UiContext {
+    pub fn label(&mut self, text: impl Into<String>) {
+        self.labels.push(text.into());
+    }
+
+    fn text(&self) -> String {
+        self.labels.join(" | ")
+    }
+}
+
+// A missing icon is cosmetic, the window opens without one
+fn window_icon() -> Option<Icon> {
+    let image = match embedded::decode_png_rgba(embedded::ICON_PNG) {
+        Ok(image) => image,
+        Err(err) => {
+            warn!("Failed to decode the window icon: {err}");
+            return None;
+        }
+    };
+    Icon::from_rgba(image.pixels, image.width, image.height)
+        .map_err(|err| warn!("Failed to create the window icon: {err}"))
+        .ok()
+}
+
+// Wayland's app_id and X11's WM_CLASS share the same window attribute
+#[cfg(all(
+    unix,
+    not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "android",
+        target_os = "emscripten"
+    ))
+))]
+fn with_app_id(attributes: WindowAttributes, app_id: &str) -> WindowAttributes {
+    use winit::platform::wayland::WindowAttributesExtWayland;
+    WindowAttributesExtWayland::with_name(attributes, app_id, app_id)
+}
+
+#[cfg(not(all(
+    unix,
+    not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "android",
+        target_os = "emscripten"
+    ))
+)))]
+fn with_app_id(attributes: WindowAttributes, _app_id: &str) -> WindowAttributes {
+    attributes
+}
+
+// Windows groups taskbar buttons per process, not per window
+#[cfg(windows)]
+fn set_app_user_model_id(app_id: &str) {
+    let app_id = app_id
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect::<Vec<u16>>();
+    let result = unsafe {
+        windows_sys::Win32::UI::Shell::SetCurrentProcessExplicitAppUserModelID(app_id.as_ptr())
+    };
+    if result < 0 {
+        warn!("Failed to set the AppUserModelID: {result:#x}");
+    }
+}
+
+// Same size as `display_mode`, the closest refresh rate or the highest one
+// without a preference, deeper colors first
+fn closest_video_mode(
+    modes: &[VideoModeHandle],
+    display_mode: DisplayMode,
+) -> Option<VideoModeHandle> {
+    modes
+        .iter()
+        .filter(|mode| mode.size() == PhysicalSize::new(display_mode.width, display_mode.height))
+        .min_by_key(|mode| {
+            let refresh = mode.refresh_rate_millihertz();
+            let distance = match display_mode.refresh_hz {
+                Some(refresh_hz) => refresh.abs_diff(refresh_hz * 1000),
+                None => u32::MAX - refresh,
+            };
+            (distance, u16::MAX - mode.bit_depth())
+        })
+        .cloned()
+}
+
+// What VK_EXT_full_screen_exclusive needs to know the display by
+#[cfg(windows)]
+fn hmonitor(monitor: &MonitorHandle) -> Option<isize> {
+    use winit::platform::windows::MonitorHandleExtWindows;
+    Some(monitor.hmonitor())
+}
+
+#[cfg(not(windows))]
+fn hmonitor(_monitor: &MonitorHandle) -> Option<isize> {
+    None
+}
+
+pub(crate) struct Runner<A: CaterpieApp> {
+    app: A,
+    config: Option<EngineConfig>,
     window: Option<Window>,
+    // Extra windows the app asked for, each rendering through its own view
+    secondary_windows: HashMap<WindowId, (Window, ViewId)>,
     engine: Option<Engine>,
+    input: InputState,
+    last_update: Option<Instant>,
+    title: String,
+    window_title: String,
+    app_id: String,
+    icon: Option<Icon>,
+    // Last size of the primary window, reused when it is recreated on resume
+    window_size: PhysicalSize<u32>,
+    overlay: bool,
+    transparent: bool,
+    // Configured mode F11 switches to, and the one in use
+    exclusive_fullscreen: Option<DisplayMode>,
+    fullscreen: Option<DisplayMode>,
+    unfocused: bool,
+    occluded: bool,
+    minimized: bool,
 }
 
-const POLL_SLEEP_TIME: std::time::Duration = time::Duration::from_millis(10);
+impl<A: CaterpieApp> Runner<A> {
+    pub(crate) fn new(config: EngineConfig, app: A) -> Self {
+        Self {
+            app,
+            config: Some(config),
+            window: None,
+            secondary_windows: HashMap::new(),
+            engine: None,
+            input: InputState::default(),
+            last_update: None,
+            title: String::new(),
+            window_title: String::new(),
+            app_id: String::new(),
+            icon: None,
+            window_size: PhysicalSize::default(),
+            overlay: true,
+            transparent: false,
+            exclusive_fullscreen: None,
+            fullscreen: None,
+            unfocused: false,
+            occluded: false,
+            minimized: false,
+        }
+    }
 
-impl ApplicationHandler for App {
-    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        self.window.as_ref().unwrap().request_redraw();
-        match event_loop.control_flow() {
-            winit::event_loop::ControlFlow::Poll => {
-                sleep(POLL_SLEEP_TIME);
+    fn resume_engine(&mut self, event_loop: &ActiveEventLoop) {
+        if !self.engine.as_ref().is_some_and(Engine::is_suspended) {
+            return;
+        }
+        let window_attributes = self
+            .window_attributes(&self.title, self.window_size)
+            .with_decorations(true);
+        let window = event_loop.create_window(window_attributes).unwrap();
+        if let Err(err) = self.engine.as_mut().unwrap().resume(&window) {
+            error!("Failed to resume: {err}");
+            if let Some(mut engine) = self.engine.take() {
+                engine.destroy();
             }
-            _ => todo!(),
+            event_loop.exit();
+            return;
+        }
+        self.minimized = false;
+        self.window = Some(window);
+        self.update_throttling();
+        if self.fullscreen.take().is_some() {
+            self.enter_fullscreen();
+        }
+    }
+
+    // Exclusive fullscreen on the primary window's monitor in the video mode
+    // closest to the configured one, the monitor's own resolution without one.
+    // The swapchain follows through the Resized event
+    fn enter_fullscreen(&mut self) {
+        let (Some(window), Some(engine)) = (&self.window, &mut self.engine) else {
+            return;
+        };
+        let Some(monitor) = window.current_monitor() else {
+            warn!("The window isn't on any monitor, staying windowed");
+            return;
+        };
+        let modes = monitor.video_modes().collect::<Vec<VideoModeHandle>>();
+        for mode in &modes {
+            debug!(
+                "Video mode {}x{} @ {:.2} Hz, {} bit",
+                mode.size().width,
+                mode.size().height,
+                mode.refresh_rate_millihertz() as f32 / 1000.0,
+                mode.bit_depth()
+            );
         }
+        let display_mode = self.exclusive_fullscreen.unwrap_or(DisplayMode {
+            width: monitor.size().width,
+            height: monitor.size().height,
+            refresh_hz: None,
+        });
+        let Some(mode) = closest_video_mode(&modes, display_mode) else {
+            warn!(
+                "{} has no {}x{} video mode, staying windowed",
+                monitor.name().unwrap_or_default(),
+                display_mode.width,
+                display_mode.height
+            );
+            return;
+        };
+        let display_mode = DisplayMode {
+            refresh_hz: Some((mode.refresh_rate_millihertz() + 500) / 1000),
+            ..display_mode
+        };
+        info!("Exclusive fullscreen at {display_mode}");
+        window.set_fullscreen(Some(Fullscreen::Exclusive(mode)));
+        engine.set_exclusive_fullscreen(hmonitor(&monitor));
+        self.fullscreen = Some(display_mode);
     }
 
-    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        let window_attributes = WindowAttributes::default()
-            .with_inner_size(PhysicalSize::new(1920, 1080))
+    // The swapchain lets go of the display before the mode switches back
+    fn leave_fullscreen(&mut self) {
+        if self.fullscreen.take().is_none() {
+            return;
+        }
+        if let Some(engine) = &mut self.engine {
+            engine.set_exclusive_fullscreen(None);
+        }
+        if let Some(window) = &self.window {
+            window.set_fullscreen(None);
+        }
+    }
+
+    fn window_attributes(&self, title: &str, size: PhysicalSize<u32>) -> WindowAttributes {
+        let attributes = WindowAttributes::default()
+            .with_title(title)
+            .with_inner_size(size)
+            .with_transparent(self.transparent)
+            .with_window_icon(self.icon.clone());
+        with_app_id(attributes, &self.app_id)
+    }
+
+    fn open_secondary_window(&mut self, event_loop: &ActiveEventLoop, camera: Camera) {
+        if self.engine.is_none() {
+            return;
+        }
+        let window_attributes = self.window_attributes(
+            &format!("{} (secondary view)", self.window_title),
+            PhysicalSize::new(960, 540),
+        );
+        let engine = self.engine.as_mut().unwrap();
+        let window = event_loop.create_window(window_attributes).unwrap();
+        match engine.create_view(&window) {
+            Ok(view) => {
+                engine.set_camera(view, 0, camera);
+                self.secondary_windows.insert(window.id(), (window, view));
+            }
+            Err(err) => warn!("Failed to open a secondary view: {err}"),
+        }
+    }
+
+    fn secondary_window_event(&mut self, window_id: WindowId, event: event::WindowEvent) {
+        let (Some(engine), Some((_, view))) =
+            (&mut self.engine, self.secondary_windows.get(&window_id))
+        else {
+            return;
+        };
+        let view = *view;
+        match event {
+            event::WindowEvent::CloseRequested => {
+                engine.destroy_view(view);
+                self.secondary_windows.remove(&window_id);
+            }
+            event::WindowEvent::Resized(size) => engine.window_resized(view, size),
+            event::WindowEvent::Focused(focused) => {
+                self.unfocused = !focused;
+                self.update_throttling();
+            }
+            _ => {}
+        }
+    }
+
+    fn paused(&self) -> bool {
+        self.engine
+            .as_ref()
+            .is_some_and(|engine| engine.pacing_mode() == PacingMode::Paused)
+    }
+
+    fn update_throttling(&mut self) {
+        if let Some(engine) = &mut self.engine {
+            engine.set_throttled(self.unfocused || self.occluded || self.minimized);
+        }
+    }
+
+    fn frame(&mut self, event_loop: &ActiveEventLoop) {
+        if self.input.just_pressed(KeyCode::F11) {
+            if self.fullscreen.is_some() {
+                self.leave_fullscreen();
+            } else {
+                self.enter_fullscreen();
+            }
+        }
+        let Some(engine) = &mut self.engine else {
+            return;
+        };
+        if engine.low_latency() {
+            engine.wait_for_frame();
+        }
+        let now = Instant::now();
+        let frame_time = engine.fixed_frame_time().unwrap_or_else(|| {
+            self.last_update
+                .map_or(Duration::ZERO, |last_update| now - last_update)
+        });
+        let dt = frame_time.as_secs_f32();
+        self.last_update = Some(now);
+
+        for _ in 0..engine.advance_time(frame_time) {
+            self.app.update_fixed(engine, engine.tick_duration());
+            engine.update_fixed();
+        }
+        self.app.update(engine, dt, &self.input);
+        engine.update(dt);
+        // Shows the frame time graph together with the text stats, and hides
+        // both the next time
+        if self.input.just_pressed(KeyCode::F3) {
+            let show = !engine.show_frame_graph();
+            engine.set_show_frame_graph(show);
+            self.overlay = show;
+        }
+        // The last log records over the top of the window, like a console
+        if self.input.just_pressed(KeyCode::Backquote) {
+            let show = !engine.show_log_view();
+            engine.set_show_log_view(show);
+        }
+        // Drawn with the next `draw_frame`, RenderDoc keeps F12 for itself
+        if self.input.just_pressed(KeyCode::F10) {
+            engine.request_frame_capture();
+        }
+        self.input.end_frame();
+
+        let mut ui = UiContext {
+            dt,
+            fps: engine.fps(),
+            pacing_mode: engine.pacing_mode(),
+            render_scale: engine.render_scale(),
+            bloom: engine.bloom_settings(),
+            ssao: engine.ssao_settings(),
+            shading_path: engine.shading_path(),
+            lights: engine.lights().len(),
+            culling: engine.culling_stats(),
+            fullscreen: self.fullscreen,
+            input_latency: engine.input_latency(),
+            present: engine.present_stats(),
+            debug_palette: engine.debug_palette(),
+            labels: Vec::new(),
+        };
+        self.app.ui(&mut ui);
+        let title = match ui.text() {
+            labels if self.overlay && !labels.is_empty() => {
+                format!("{} | {labels}", self.window_title)
+            }
+            _ => self.window_title.clone(),
+        };
+        if title != self.title {
+            if let Some(window) = &self.window {
+                window.set_title(&title);
+            }
+            self.title = title;
+        }
+
+        engine.draw_frame();
+
+        if engine.exit_requested() {
+            engine.destroy();
+            self.engine = None;
+            event_loop.exit();
+            return;
+        }
+        for camera in engine.take_view_requests() {
+            self.open_secondary_window(event_loop, camera);
+        }
+    }
+}
+
+impl<A: CaterpieApp> ApplicationHandler for Runner<A> {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(engine) = &self.engine else {
+            return;
+        };
+        match engine.pacing_mode() {
+            // Sleep in the event loop instead of spinning, focus and occlusion
+            // events still wake it up immediately
+            // The first update after the pause starts from zero instead of
+            // catching up on the whole pause
+            PacingMode::Paused => {
+                event_loop.set_control_flow(ControlFlow::Wait);
+                self.last_update = None;
+                return;
+            }
+            PacingMode::Throttled => event_loop.set_control_flow(ControlFlow::WaitUntil(
+                Instant::now() + engine.target_frame_time().unwrap_or_default(),
+            )),
+            _ => event_loop.set_control_flow(ControlFlow::Poll),
+        }
+        if let (Some(window), false) = (&self.window, self.minimized) {
+            window.request_redraw();
+        }
+    }
+
+    // Can fire more than once, later calls only give the existing engine a
+    // new surface
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.engine.is_some() {
+            return self.resume_engine(event_loop);
+        }
+        let Some(config) = self.config.take() else {
+            return;
+        };
+        self.window_size = PhysicalSize::new(config.window_width, config.window_height);
+        self.window_title = config.window_title.clone();
+        self.title = config.window_title.clone();
+        self.app_id = config.app_id.clone();
+        self.icon = window_icon();
+        self.overlay = config.overlay;
+        self.transparent = config.transparent_window;
+        self.exclusive_fullscreen = config.exclusive_fullscreen;
+        #[cfg(windows)]
+        set_app_user_model_id(&self.app_id);
+        let window_attributes = self
+            .window_attributes(&config.window_title, self.window_size)
             .with_decorations(true);
         self.window = Some(event_loop.create_window(window_attributes).unwrap());
-        self.engine = Some(Engine::init(&self.window.as_ref().unwrap()).unwrap());
+        let mut engine = match Engine::init(self.window.as_ref().unwrap(), config) {
+            Ok(engine) => engine,
+            Err(err) => {
+                error!("{err}");
+                event_loop.exit();
+                return;
+            }
+        };
+        self.app.setup(&mut engine);
+        self.engine = Some(engine);
+        if self.exclusive_fullscreen.is_some() {
+            self.enter_fullscreen();
+        }
         debug!("App resumed");
     }
 
+    // Mobile platforms and macOS take the surfaces away, everything else
+    // survives until the next `resumed`
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(engine) = &mut self.engine {
+            engine.suspend();
+        }
+        // Surfaces are gone, the windows can follow
+        self.secondary_windows.clear();
+        self.window = None;
+        debug!("App suspended");
+    }
+
     fn window_event(
         &mut self,
-        event_loop: &winit::event_loop::ActiveEventLoop,
-        window_id: winit::window::WindowId,
-        event: winit::event::WindowEvent,
+        event_loop: &ActiveEventLoop,
+        window_id: WindowId,
+        event: event::WindowEvent,
     ) {
-        match &mut self.engine {
-            Some(engine) => {
-                engine.draw_frame();
-                match event {
-                    event::WindowEvent::Destroyed => {
-                        engine.destroy();
-                    }
-                    event::WindowEvent::CloseRequested => {
-                        engine.destroy();
-                        exit(0);
-                    }
-                    event::WindowEvent::Resized(size) => {
-                        engine.window_resized(size);
-                    }
-                    event::WindowEvent::KeyboardInput {
-                        device_id,
-                        event,
-                        is_synthetic,
-                    } => match event {
-                        KeyEvent {
-                            physical_key,
-                            logical_key,
-                            text,
-                            location,
-                            state,
-                            repeat,
-                            ..
-                        } => if logical_key.eq("e") {},
-                    },
-                    _ => {}
+        if let event::WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(key),
+                    state,
+                    repeat,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.input.key_event(key, state, repeat);
+        }
+        if self.secondary_windows.contains_key(&window_id) {
+            return self.secondary_window_event(window_id, event);
+        }
+        match event {
+            // Draws every view, secondary windows don't request their own
+            // redraws. Redraws the system asks for while paused are left out
+            event::WindowEvent::RedrawRequested if !self.minimized && !self.paused() => {
+                self.frame(event_loop);
+            }
+            event::WindowEvent::CloseRequested => {
+                // Dropping the engine here keeps the following Destroyed event
+                // from tearing it down a second time
+                if let Some(mut engine) = self.engine.take() {
+                    engine.destroy();
+                }
+                event_loop.exit();
+            }
+            event::WindowEvent::Resized(size) => {
+                self.minimized = size.width == 0 || size.height == 0;
+                if !self.minimized && self.fullscreen.is_none() {
+                    self.window_size = size;
+                }
+                if let Some(engine) = &mut self.engine {
+                    engine.window_resized(ViewId::PRIMARY, size);
                 }
+                self.update_throttling();
+            }
+            event::WindowEvent::Focused(focused) => {
+                self.unfocused = !focused;
+                self.update_throttling();
+            }
+            event::WindowEvent::Occluded(occluded) => {
+                self.occluded = occluded;
+                self.update_throttling();
+            }
+            event::WindowEvent::CursorMoved { position, .. } => {
+                self.input.cursor = Some(position);
+            }
+            event::WindowEvent::CursorLeft { .. } => {
+                self.input.cursor = None;
+            }
+            event::WindowEvent::MouseInput { state, button, .. } => {
+                self.input.mouse_event(button, state);
+            }
+            event::WindowEvent::DroppedFile(path) => {
+                self.input.dropped_files.push(path);
             }
-            None => {}
+            _ => {}
         }
     }
 }
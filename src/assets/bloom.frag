@@ -0,0 +1,71 @@
+#version 450
+
+// 0 extracts the bright parts of the scene, 1 downsamples, 2 upsamples and
+// 3 adds the bloom to the scene and tonemaps the result
+layout(constant_id = 0) const int PASS = 0;
+
+layout(binding = 0) uniform sampler2D source;
+// Only read by the composite pass
+layout(binding = 1) uniform sampler2D bloom;
+
+layout(push_constant) uniform Params {
+    // Part of `source` holding the image, below 1 when the scene is scaled
+    vec2 sourceScale;
+    float threshold;
+    float intensity;
+} params;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 tap(vec2 uv, vec2 offset) {
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+    return texture(source, uv * params.sourceScale + offset * texel).rgb;
+}
+
+// Four bilinear taps between texels average a 4x4 block
+vec3 downsample(vec2 uv) {
+    return (tap(uv, vec2(-1.0, -1.0)) + tap(uv, vec2(1.0, -1.0))
+        + tap(uv, vec2(-1.0, 1.0)) + tap(uv, vec2(1.0, 1.0))) * 0.25;
+}
+
+// 3x3 tent filter
+vec3 upsample(vec2 uv) {
+    vec3 color = tap(uv, vec2(0.0)) * 4.0;
+    color += (tap(uv, vec2(-1.0, 0.0)) + tap(uv, vec2(1.0, 0.0))
+        + tap(uv, vec2(0.0, -1.0)) + tap(uv, vec2(0.0, 1.0))) * 2.0;
+    color += tap(uv, vec2(-1.0, -1.0)) + tap(uv, vec2(1.0, -1.0))
+        + tap(uv, vec2(-1.0, 1.0)) + tap(uv, vec2(1.0, 1.0));
+    return color / 16.0;
+}
+
+// Soft knee so pixels just below the threshold fade in instead of popping
+vec3 bright(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float knee = params.threshold * 0.5;
+    float soft = clamp(brightness - params.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.0001);
+    return color * max(soft, brightness - params.threshold) / max(brightness, 0.0001);
+}
+
+// Narkowicz's ACES fit
+vec3 tonemap(vec3 color) {
+    return clamp(color * (2.51 * color + 0.03) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    vec2 uv = fragTexCoord;
+    if (PASS == 0) {
+        outColor = vec4(bright(downsample(uv)), 1.0);
+    } else if (PASS == 1) {
+        outColor = vec4(downsample(uv), 1.0);
+    } else if (PASS == 2) {
+        outColor = vec4(upsample(uv), 1.0);
+    } else {
+        // The scene's alpha is kept for transparent windows
+        vec4 scene = texture(source, uv * params.sourceScale);
+        vec3 glow = texture(bloom, uv).rgb * params.intensity;
+        outColor = vec4(tonemap(scene.rgb + glow), scene.a);
+    }
+}
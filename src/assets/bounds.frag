@@ -0,0 +1,12 @@
+#version 450
+
+// Shares the fragment push constant range of the main pipeline
+layout(push_constant) uniform Bounds {
+    vec4 color;
+} bounds;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = bounds.color;
+}
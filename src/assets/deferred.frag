@@ -0,0 +1,25 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#define LIGHTS_BINDING 3
+#include "lights.glsl"
+
+// The G-buffer written by gbuffer.frag
+layout(binding = 0) uniform sampler2D albedoMap;
+layout(binding = 1) uniform sampler2D normalMap;
+layout(binding = 2) uniform sampler2D positionMap;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec4 position = texelFetch(positionMap, texel, 0);
+    // Keeps the clear color, the same as the forward pass'
+    if (position.w == 0.0) {
+        discard;
+    }
+    vec4 albedo = texelFetch(albedoMap, texel, 0);
+    vec4 normal = texelFetch(normalMap, texel, 0);
+    vec3 color = normal.w == 0.0 ? albedo.rgb : shade(albedo.rgb, position.xyz, normal.xyz, albedo.rgb * AMBIENT);
+    outColor = vec4(color, albedo.a);
+}
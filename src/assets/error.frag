@@ -0,0 +1,8 @@
+#version 450
+
+// Flat magenta, hard to mistake for anything a scene shader draws
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = vec4(1.0, 0.0, 1.0, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 450
+
+// The start of shader.vert's UniformBufferObject, the only binding the error
+// pipelines read. Skinning and morphing are left out, every object is drawn
+// in its bind pose
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(location = 0) in vec3 inPosition;
+
+void main() {
+    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,45 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+#ifdef BINDLESS
+#extension GL_EXT_nonuniform_qualifier : require
+#endif
+
+#include "material.glsl"
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+
+// The model texture, only the default material reads it
+layout(binding = 1) uniform sampler2D texSampler;
+
+// rgb is the highlight color, a how strongly it replaces the texture
+layout(push_constant) uniform Highlight {
+    vec4 tint;
+#ifdef BINDLESS
+    // Index into the material table
+    uint material;
+#endif
+} highlight;
+
+layout(location = 0) out vec4 outAlbedo;
+// w is 1 for surfaces the lighting pass shades
+layout(location = 1) out vec4 outNormal;
+// w is 0 where nothing was drawn
+layout(location = 2) out vec4 outPosition;
+
+// The G-buffer has no room for metalness and roughness, the lighting pass
+// shades the base color and the mapped normal only
+void main() {
+#ifdef BINDLESS
+    MaterialData material = loadMaterial(highlight.material);
+#else
+    MaterialData material = loadMaterial(0u);
+#endif
+    vec4 modelColor = material.flags.x != 0u ? texture(texSampler, fragTexCoord) : vec4(1.0);
+    Surface surface = sampleMaterial(material, fragTexCoord, fragPosition, fragNormal, modelColor);
+    outAlbedo = vec4(mix(surface.baseColor.rgb, highlight.tint.rgb, highlight.tint.a), surface.baseColor.a);
+    outNormal = vec4(surface.normal, 1.0);
+    outPosition = vec4(fragPosition, 1.0);
+}
@@ -0,0 +1,19 @@
+#version 450
+
+// Shares the fragment push constant range of the main pipeline
+layout(push_constant) uniform Bounds {
+    vec4 color;
+} bounds;
+
+layout(location = 2) in vec3 fragPosition;
+
+layout(location = 0) out vec4 outAlbedo;
+// Left unlit by the lighting pass
+layout(location = 1) out vec4 outNormal;
+layout(location = 2) out vec4 outPosition;
+
+void main() {
+    outAlbedo = bounds.color;
+    outNormal = vec4(0.0);
+    outPosition = vec4(fragPosition, 1.0);
+}
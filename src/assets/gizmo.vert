@@ -0,0 +1,16 @@
+#version 450
+
+// Editor gizmos, line lists already in world space
+layout(push_constant) uniform Camera {
+    mat4 viewProjection;
+} camera;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inColor;
+
+layout(location = 0) out vec3 fragColor;
+
+void main() {
+    gl_Position = camera.viewProjection * vec4(inPosition, 1.0);
+    fragColor = inColor;
+}
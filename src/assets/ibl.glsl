@@ -0,0 +1,29 @@
+// Split-sum image based lighting from the maps baked in ibl.rs. The
+// including shader defines IBL_BINDING first, the three maps take that
+// binding and the two after it
+
+// ibl.rs' PREFILTERED_LEVELS, roughness 0 to 1 in even steps
+const float IBL_LEVELS = 5.0;
+
+layout(binding = IBL_BINDING) uniform samplerCube irradianceMap;
+layout(binding = IBL_BINDING + 1) uniform samplerCube prefilteredMap;
+layout(binding = IBL_BINDING + 2) uniform sampler2D brdfLut;
+
+// Diffuse light from the irradiance map and specular light from the
+// prefiltered level of `roughness`, `view` points from the surface to the eye
+vec3 environmentLight(vec3 albedo, vec3 normal, vec3 view, float metallic, float roughness) {
+    if (dot(normal, normal) == 0.0) {
+        return albedo * AMBIENT;
+    }
+    vec3 n = normalize(normal);
+    vec3 v = normalize(view);
+    float nDotV = max(dot(n, v), 0.0001);
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    // Schlick's Fresnel, rough surfaces don't brighten as much at grazing
+    // angles
+    vec3 fresnel = f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - nDotV, 5.0);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo * texture(irradianceMap, n).rgb;
+    vec3 prefiltered = textureLod(prefilteredMap, reflect(-v, n), roughness * (IBL_LEVELS - 1.0)).rgb;
+    vec2 brdf = texture(brdfLut, vec2(nDotV, roughness)).rg;
+    return diffuse + prefiltered * (fresnel * brdf.x + brdf.y);
+}
@@ -0,0 +1,41 @@
+// Point lights shared by the forward and deferred paths. The including
+// shader defines LIGHTS_BINDING first
+
+// Added to every lit surface so the sides facing away stay visible
+const float AMBIENT = 0.05;
+
+struct PointLight {
+    // xyz position, w the distance the light reaches
+    vec4 positionRadius;
+    // rgb color, a intensity
+    vec4 color;
+};
+
+layout(std430, binding = LIGHTS_BINDING) readonly buffer Lights {
+    uint count;
+    PointLight lights[];
+} lightBuffer;
+
+// Without any lights the scene is drawn unlit. Surfaces without a normal
+// take every light in range at full strength. `ambient` is added as is,
+// already multiplied by the albedo
+vec3 shade(vec3 albedo, vec3 position, vec3 normal, vec3 ambient) {
+    if (lightBuffer.count == 0u) {
+        return albedo;
+    }
+    bool hasNormal = dot(normal, normal) > 0.0;
+    vec3 light = vec3(0.0);
+    for (uint i = 0u; i < lightBuffer.count; i++) {
+        PointLight pointLight = lightBuffer.lights[i];
+        vec3 toLight = pointLight.positionRadius.xyz - position;
+        float distance = length(toLight);
+        float radius = pointLight.positionRadius.w;
+        if (distance >= radius) {
+            continue;
+        }
+        float falloff = 1.0 - distance / radius;
+        float facing = hasNormal ? max(dot(normalize(normal), toLight / max(distance, 0.0001)), 0.0) : 1.0;
+        light += pointLight.color.rgb * pointLight.color.a * facing * falloff * falloff;
+    }
+    return albedo * light + ambient;
+}
@@ -0,0 +1,122 @@
+// The object's glTF metallic-roughness material, descriptor set 1. Textures
+// the material doesn't have are bound to 1x1 defaults, white or a flat normal.
+// With BINDLESS defined every material sits in one storage buffer and every
+// texture in one array, the push constant's index picks the object's
+
+struct MaterialData {
+    vec4 baseColorFactor;
+    // rgb emissive factor
+    vec4 emissiveFactor;
+    // x metallic, y roughness, z normal scale, w occlusion strength
+    vec4 factors;
+    // x is set for the default material, tinted by the model texture
+    uvec4 flags;
+    // Bindless texture slots: base color, metallic-roughness, normal,
+    // occlusion, then emissive
+    uvec4 textures[2];
+};
+
+#define BASE_COLOR_TEXTURE 0u
+#define METALLIC_ROUGHNESS_TEXTURE 1u
+#define NORMAL_TEXTURE 2u
+#define OCCLUSION_TEXTURE 3u
+#define EMISSIVE_TEXTURE 4u
+
+#ifdef BINDLESS
+layout(std430, set = 1, binding = 0) readonly buffer MaterialTable {
+    MaterialData materials[];
+};
+
+layout(set = 1, binding = 1) uniform sampler2D textures[];
+
+vec4 materialTexture(MaterialData material, uint slot, vec2 uv) {
+    uint index = material.textures[slot / 4u][slot % 4u];
+    return texture(textures[nonuniformEXT(index)], uv);
+}
+#else
+layout(set = 1, binding = 0) uniform MaterialBlock {
+    MaterialData data;
+} materialBlock;
+
+layout(set = 1, binding = 1) uniform sampler2D baseColorMap;
+// Roughness in green, metalness in blue
+layout(set = 1, binding = 2) uniform sampler2D metallicRoughnessMap;
+layout(set = 1, binding = 3) uniform sampler2D normalMap;
+// Red channel
+layout(set = 1, binding = 4) uniform sampler2D occlusionMap;
+layout(set = 1, binding = 5) uniform sampler2D emissiveMap;
+
+vec4 materialTexture(MaterialData material, uint slot, vec2 uv) {
+    switch (slot) {
+    case BASE_COLOR_TEXTURE:
+        return texture(baseColorMap, uv);
+    case METALLIC_ROUGHNESS_TEXTURE:
+        return texture(metallicRoughnessMap, uv);
+    case NORMAL_TEXTURE:
+        return texture(normalMap, uv);
+    case OCCLUSION_TEXTURE:
+        return texture(occlusionMap, uv);
+    default:
+        return texture(emissiveMap, uv);
+    }
+}
+#endif
+
+// `index` is the push constant's material index, unused without BINDLESS
+MaterialData loadMaterial(uint index) {
+#ifdef BINDLESS
+    return materials[index];
+#else
+    return materialBlock.data;
+#endif
+}
+
+struct Surface {
+    vec4 baseColor;
+    // World space, zero for meshes without normals
+    vec3 normal;
+    float metallic;
+    float roughness;
+    // Multiplies the ambient light
+    float occlusion;
+    vec3 emissive;
+};
+
+// Tangent frame from the screen space derivatives of the position and the
+// texture coordinates, so meshes don't need tangents
+vec3 perturbNormal(MaterialData material, vec3 normal, vec3 position, vec2 uv) {
+    vec3 mapped = materialTexture(material, NORMAL_TEXTURE, uv).xyz * 2.0 - 1.0;
+    mapped.xy *= material.factors.z;
+    vec3 n = normalize(normal);
+    vec3 dp1 = dFdx(position);
+    vec3 dp2 = dFdy(position);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+    vec3 dp2perp = cross(dp2, n);
+    vec3 dp1perp = cross(n, dp1);
+    vec3 t = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 b = dp2perp * duv1.y + dp1perp * duv2.y;
+    float size = max(dot(t, t), dot(b, b));
+    // Texture coordinates that don't change across the triangle
+    if (size <= 0.0) {
+        return n;
+    }
+    float scale = inversesqrt(size);
+    return normalize(mat3(t * scale, b * scale, n) * mapped);
+}
+
+// `modelColor` is only used by the default material
+Surface sampleMaterial(MaterialData material, vec2 uv, vec3 position, vec3 normal, vec4 modelColor) {
+    Surface surface;
+    surface.baseColor = material.baseColorFactor * materialTexture(material, BASE_COLOR_TEXTURE, uv);
+    if (material.flags.x != 0u) {
+        surface.baseColor *= modelColor;
+    }
+    surface.normal = dot(normal, normal) > 0.0 ? perturbNormal(material, normal, position, uv) : normal;
+    vec4 metallicRoughness = materialTexture(material, METALLIC_ROUGHNESS_TEXTURE, uv);
+    surface.metallic = material.factors.x * metallicRoughness.b;
+    surface.roughness = material.factors.y * metallicRoughness.g;
+    surface.occlusion = 1.0 + material.factors.w * (materialTexture(material, OCCLUSION_TEXTURE, uv).r - 1.0);
+    surface.emissive = material.emissiveFactor.rgb * materialTexture(material, EMISSIVE_TEXTURE, uv).rgb;
+    return surface;
+}
@@ -0,0 +1,60 @@
+// Cook-Torrance shading of the point lights in lights.glsl, include that
+// first. GGX distribution, Smith's geometry term with Schlick's
+// approximation and Schlick's Fresnel
+
+const float PI = 3.14159265359;
+// Below this the highlight of a point light collapses to nothing
+const float MIN_ROUGHNESS = 0.04;
+
+float distributionGgx(float nDotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = nDotH * nDotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float geometrySchlickGgx(float nDotX, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return nDotX / (nDotX * (1.0 - k) + k);
+}
+
+// `view` points from the surface to the eye. Light colors are scaled by PI so
+// a white dielectric facing a light is about as bright as with `shade`.
+// Surfaces without a normal fall back to `shade`
+vec3 shadePbr(vec3 albedo, float metallic, float roughness, vec3 position, vec3 normal, vec3 view, vec3 ambient) {
+    if (lightBuffer.count == 0u) {
+        return albedo;
+    }
+    if (dot(normal, normal) == 0.0) {
+        return shade(albedo, position, normal, ambient);
+    }
+    vec3 n = normalize(normal);
+    vec3 v = normalize(view);
+    float nDotV = max(dot(n, v), 0.0001);
+    float r = clamp(roughness, MIN_ROUGHNESS, 1.0);
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 light = vec3(0.0);
+    for (uint i = 0u; i < lightBuffer.count; i++) {
+        PointLight pointLight = lightBuffer.lights[i];
+        vec3 toLight = pointLight.positionRadius.xyz - position;
+        float distance = length(toLight);
+        float radius = pointLight.positionRadius.w;
+        if (distance >= radius) {
+            continue;
+        }
+        vec3 l = toLight / max(distance, 0.0001);
+        float nDotL = dot(n, l);
+        if (nDotL <= 0.0) {
+            continue;
+        }
+        vec3 h = normalize(l + v);
+        vec3 fresnel = f0 + (1.0 - f0) * pow(1.0 - max(dot(h, v), 0.0), 5.0);
+        float geometry = geometrySchlickGgx(nDotV, r) * geometrySchlickGgx(nDotL, r);
+        vec3 specular = distributionGgx(max(dot(n, h), 0.0), r) * geometry * fresnel / (4.0 * nDotV * nDotL);
+        vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+        float falloff = 1.0 - distance / radius;
+        vec3 radiance = pointLight.color.rgb * pointLight.color.a * falloff * falloff * PI;
+        light += (diffuse + specular) * radiance * nDotL;
+    }
+    return light + ambient;
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// 0 is left for the background, objects are written as their index + 1
+layout(push_constant) uniform Picking {
+    uint objectId;
+} picking;
+
+layout(location = 0) out uint outId;
+
+void main() {
+    outId = picking.objectId + 1;
+}
@@ -1,12 +1,47 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
+#ifdef BINDLESS
+#extension GL_EXT_nonuniform_qualifier : require
+#endif
+
+#define LIGHTS_BINDING 2
+#include "lights.glsl"
+#include "pbr.glsl"
+#define IBL_BINDING 5
+#include "ibl.glsl"
+#include "material.glsl"
 
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragPosition;
+layout(location = 3) in vec3 fragNormal;
+layout(location = 4) in vec3 fragCameraPosition;
 
+// The model texture, only the default material reads it
 layout(binding = 1) uniform sampler2D texSampler;
 
+// rgb is the highlight color, a how strongly it replaces the texture
+layout(push_constant) uniform Highlight {
+    vec4 tint;
+#ifdef BINDLESS
+    // Index into the material table
+    uint material;
+#endif
+} highlight;
+
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    outColor = texture(texSampler, fragTexCoord);
+#ifdef BINDLESS
+    MaterialData material = loadMaterial(highlight.material);
+#else
+    MaterialData material = loadMaterial(0u);
+#endif
+    vec4 modelColor = material.flags.x != 0u ? texture(texSampler, fragTexCoord) : vec4(1.0);
+    Surface surface = sampleMaterial(material, fragTexCoord, fragPosition, fragNormal, modelColor);
+    vec3 albedo = mix(surface.baseColor.rgb, highlight.tint.rgb, highlight.tint.a);
+    vec3 view = fragCameraPosition - fragPosition;
+    vec3 ambient = surface.occlusion * environmentLight(albedo, surface.normal, view, surface.metallic, surface.roughness);
+    vec3 color = shadePbr(albedo, surface.metallic, surface.roughness, fragPosition, surface.normal, view, ambient);
+    outColor = vec4(color + surface.emissive, surface.baseColor.a);
 }
@@ -1,20 +1,173 @@
 #version 450
+#ifdef BUFFER_ADDRESS
+#extension GL_EXT_buffer_reference : require
+#endif
+
+// Set by the skinned and morphed pipeline variants
+layout(constant_id = 0) const bool SKINNED = false;
+layout(constant_id = 1) const bool MORPHED = false;
+// Set on the GPU culling path, the object's entry is the draw's first
+// instance instead of the dynamic offset
+layout(constant_id = 2) const bool INDIRECT = false;
+// Distance between uniform entries in vec4s
+layout(constant_id = 3) const uint ENTRY_STRIDE = 16u;
+// Set for the packed vertex layouts, the normal is octahedron encoded in
+// inNormal.xy
+layout(constant_id = 4) const bool PACKED_NORMALS = false;
+// Set by the outline pipelines, how far the selected object is pushed out
+// along its normals in clip space w units
+layout(constant_id = 5) const float OUTLINE_WIDTH = 0.0;
 
 layout(binding = 0) uniform UniformBufferObject {
     mat4 model;
     mat4 view;
     mat4 proj;
+    // x first joint in the joint buffer, y joint count
+    uvec4 joints;
+    // x first delta in the morph buffer, y vertices per target, z active
+    // targets
+    uvec4 morph;
+    // Indices and weights of the active targets, four per vector
+    uvec4 morphTargets[2];
+    vec4 morphWeights[2];
 } ubo;
 
+#ifdef BUFFER_ADDRESS
+// UniformBufferObject's layout, read from the object's entry in the uniform
+// buffer. Set 0 binding 0 stays declared so the layout matches the
+// descriptor path
+layout(buffer_reference, std140, buffer_reference_align = 16) readonly buffer ObjectData {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    uvec4 joints;
+    uvec4 morph;
+    uvec4 morphTargets[2];
+    vec4 morphWeights[2];
+};
+
+// After the fragment stage's highlight and material constants
+layout(push_constant) uniform ObjectAddress {
+    layout(offset = 32) ObjectData object;
+} objectAddress;
+
+#define OBJECT objectAddress.object
+#else
+#define OBJECT ubo
+#endif
+
+// The whole uniform buffer, every entry UniformBufferObject's std140 layout
+layout(std430, binding = 8) readonly buffer Entries {
+    vec4 words[];
+} entries;
+
+struct Object {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    uvec4 joints;
+    uvec4 morph;
+    uvec4 morphTargets[2];
+    vec4 morphWeights[2];
+};
+
+Object loadObject() {
+    if (!INDIRECT) {
+        return Object(OBJECT.model, OBJECT.view, OBJECT.proj, OBJECT.joints, OBJECT.morph, OBJECT.morphTargets, OBJECT.morphWeights);
+    }
+    uint base = uint(gl_InstanceIndex) * ENTRY_STRIDE;
+    Object object;
+    object.model = mat4(entries.words[base], entries.words[base + 1u], entries.words[base + 2u], entries.words[base + 3u]);
+    object.view = mat4(entries.words[base + 4u], entries.words[base + 5u], entries.words[base + 6u], entries.words[base + 7u]);
+    object.proj = mat4(entries.words[base + 8u], entries.words[base + 9u], entries.words[base + 10u], entries.words[base + 11u]);
+    object.joints = floatBitsToUint(entries.words[base + 12u]);
+    object.morph = floatBitsToUint(entries.words[base + 13u]);
+    object.morphTargets[0] = floatBitsToUint(entries.words[base + 14u]);
+    object.morphTargets[1] = floatBitsToUint(entries.words[base + 15u]);
+    object.morphWeights[0] = entries.words[base + 16u];
+    object.morphWeights[1] = entries.words[base + 17u];
+    return object;
+}
+
+// Joint palettes of every skinned object this frame
+layout(std430, binding = 3) readonly buffer Joints {
+    mat4 matrices[];
+} jointBuffer;
+
+struct MorphDelta {
+    vec4 position;
+    vec4 normal;
+};
+
+// Every morphed mesh's targets, a delta per target and vertex
+layout(std430, binding = 4) readonly buffer MorphDeltas {
+    MorphDelta deltas[];
+} morphBuffer;
+
 layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inColor;
 layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec3 inNormal;
+layout(location = 4) in uvec4 inJoints;
+layout(location = 5) in vec4 inWeights;
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec2 fragTexCoord;
+// World space, for lighting
+layout(location = 2) out vec3 fragPosition;
+layout(location = 3) out vec3 fragNormal;
+layout(location = 4) out vec3 fragCameraPosition;
+
+// Inverse of vertex.rs' octahedron_encode
+vec3 decodeNormal(vec3 normal) {
+    if (!PACKED_NORMALS) {
+        return normal;
+    }
+    vec3 decoded = vec3(normal.xy, 1.0 - abs(normal.x) - abs(normal.y));
+    float fold = max(-decoded.z, 0.0);
+    decoded.x += decoded.x >= 0.0 ? -fold : fold;
+    decoded.y += decoded.y >= 0.0 ? -fold : fold;
+    return normalize(decoded);
+}
+
+// Blend of up to four joints, vertices without weights only follow the object
+mat4 skinMatrix(Object object) {
+    if (!SKINNED || object.joints.y == 0u || dot(inWeights, vec4(1.0)) <= 0.0) {
+        return mat4(1.0);
+    }
+    uvec4 joints = object.joints.x + min(inJoints, uvec4(object.joints.y - 1u));
+    return inWeights.x * jointBuffer.matrices[joints.x]
+        + inWeights.y * jointBuffer.matrices[joints.y]
+        + inWeights.z * jointBuffer.matrices[joints.z]
+        + inWeights.w * jointBuffer.matrices[joints.w];
+}
 
 void main() {
-    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition, 1.0);
+    Object object = loadObject();
+    // Targets are blended in bind space, before the skin moves the vertex
+    vec3 morphedPosition = inPosition;
+    vec3 morphedNormal = decodeNormal(inNormal);
+    if (MORPHED) {
+        for (uint i = 0u; i < object.morph.z; i++) {
+            uint target = object.morphTargets[i / 4u][i % 4u];
+            float weight = object.morphWeights[i / 4u][i % 4u];
+            MorphDelta delta = morphBuffer.deltas[object.morph.x + target * object.morph.y + uint(gl_VertexIndex)];
+            morphedPosition += weight * delta.position.xyz;
+            morphedNormal += weight * delta.normal.xyz;
+        }
+    }
+    mat4 model = object.model * skinMatrix(object);
+    vec4 position = model * vec4(morphedPosition, 1.0);
+    if (OUTLINE_WIDTH > 0.0) {
+        // Scaled by the distance so the outline keeps its width on screen
+        float w = (object.proj * object.view * position).w;
+        position.xyz += normalize(mat3(model) * morphedNormal) * OUTLINE_WIDTH * w;
+    }
+    gl_Position = object.proj * object.view * position;
     fragColor = inColor;
     fragTexCoord = inTexCoord;
+    fragPosition = position.xyz;
+    fragNormal = mat3(model) * morphedNormal;
+    // The view matrix is a rotation and translation, its inverse is cheap
+    fragCameraPosition = -transpose(mat3(object.view)) * object.view[3].xyz;
 }
@@ -0,0 +1,98 @@
+#version 450
+
+// 0 computes the occlusion from the depth buffer, 1 blurs it and 2 outputs
+// the blurred occlusion to be multiplied onto the scene
+layout(constant_id = 0) const int PASS = 0;
+
+const int KERNEL_SIZE = 16;
+// MAX_VIEWS * MAX_VIEWPORTS
+const int MAX_REGIONS = 8;
+
+layout(binding = 0) uniform sampler2D depthMap;
+// Random rotations around the normal in xy, tiled every 4 pixels
+layout(binding = 1) uniform sampler2D noise;
+// The occlusion read by the blur and apply passes
+layout(binding = 2) uniform sampler2D source;
+
+layout(binding = 3) uniform Ssao {
+    // Hemisphere around +z, denser towards the center
+    vec4 kernel[KERNEL_SIZE];
+    mat4 projection[MAX_REGIONS];
+    mat4 inverseProjection[MAX_REGIONS];
+} ssao;
+
+layout(push_constant) uniform Params {
+    // Offset and size of the drawn region in framebuffer pixels
+    vec4 region;
+    int index;
+    float radius;
+    float bias;
+} params;
+
+layout(location = 0) out vec4 outColor;
+
+// Texels outside the region belong to another camera
+ivec2 texel(vec2 position) {
+    vec2 lower = params.region.xy;
+    vec2 upper = params.region.xy + params.region.zw - 1.0;
+    return ivec2(clamp(position, lower, upper));
+}
+
+vec3 viewPosition(vec2 position) {
+    float depth = texelFetch(depthMap, texel(position), 0).r;
+    // Framebuffer y points down, clip space y up
+    vec2 ndc = ((position - params.region.xy) / params.region.zw * 2.0 - 1.0) * vec2(1.0, -1.0);
+    vec4 view = ssao.inverseProjection[params.index] * vec4(ndc, depth, 1.0);
+    return view.xyz / view.w;
+}
+
+float occlusion() {
+    vec3 position = viewPosition(gl_FragCoord.xy);
+    // Derivatives have to be taken before anything returns early
+    vec3 normal = normalize(cross(dFdx(position), dFdy(position)));
+    if (dot(normal, position) > 0.0) {
+        normal = -normal;
+    }
+    if (texelFetch(depthMap, texel(gl_FragCoord.xy), 0).r >= 1.0) {
+        return 1.0;
+    }
+
+    vec2 rotation = texelFetch(noise, ivec2(gl_FragCoord.xy) % 4, 0).xy * 2.0 - 1.0;
+    vec3 random = vec3(rotation, 0.0);
+    vec3 tangent = normalize(random - normal * dot(random, normal));
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float occluded = 0.0;
+    for (int i = 0; i < KERNEL_SIZE; i++) {
+        vec3 samplePosition = position + tbn * ssao.kernel[i].xyz * params.radius;
+        vec4 clip = ssao.projection[params.index] * vec4(samplePosition, 1.0);
+        vec2 uv = clip.xy / clip.w * vec2(0.5, -0.5) + 0.5;
+        float sceneDepth = viewPosition(params.region.xy + uv * params.region.zw).z;
+        // Geometry far in front of the sample doesn't occlude it
+        float range = smoothstep(0.0, 1.0, params.radius / abs(position.z - sceneDepth));
+        occluded += (sceneDepth >= samplePosition.z + params.bias ? 1.0 : 0.0) * range;
+    }
+    return 1.0 - occluded / float(KERNEL_SIZE);
+}
+
+// 4x4 box, matching the noise tile so its pattern averages out
+float blur() {
+    float sum = 0.0;
+    for (int y = -2; y < 2; y++) {
+        for (int x = -2; x < 2; x++) {
+            sum += texelFetch(source, texel(gl_FragCoord.xy + vec2(x, y)), 0).r;
+        }
+    }
+    return sum / 16.0;
+}
+
+void main() {
+    if (PASS == 0) {
+        outColor = vec4(occlusion(), 0.0, 0.0, 1.0);
+    } else if (PASS == 1) {
+        outColor = vec4(blur(), 0.0, 0.0, 1.0);
+    } else {
+        float ao = texelFetch(source, texel(gl_FragCoord.xy), 0).r;
+        outColor = vec4(vec3(ao), 1.0);
+    }
+}
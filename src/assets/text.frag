@@ -0,0 +1,19 @@
+#version 450
+
+// The atlas' alpha is the distance to the glyph's outline, 0.5 on it and
+// growing inwards. The edge is smoothed over one screen pixel whatever the
+// text is scaled to
+layout(set = 0, binding = 0) uniform texture2D glyphAtlas;
+layout(set = 0, binding = 1) uniform sampler glyphSampler;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float distance = texture(sampler2D(glyphAtlas, glyphSampler), fragTexCoord).a;
+    float width = max(fwidth(distance), 1e-4);
+    float coverage = smoothstep(0.5 - width, 0.5 + width, distance);
+    outColor = vec4(fragColor, coverage);
+}
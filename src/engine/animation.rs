@@ -0,0 +1,430 @@
+use std::ops::{Add, Mul, Sub};
+
+use cgmath::{vec3, InnerSpace, Matrix4, Point3, Quaternion, Vector3, VectorSpace};
+
+use crate::engine::scene::ObjectId;
+
+// What a track moves. Lights only take the translation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationTarget {
+    Object(ObjectId),
+    // Index into `Engine::lights`
+    Light(usize),
+    // A joint of the object's skin, relative to its parent joint
+    Joint { object: ObjectId, joint: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    // Catmull-Rom, passes through every key with tangents from its neighbours
+    Cubic,
+}
+
+// What happens once the clip runs past either end
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaybackMode {
+    #[default]
+    Loop,
+    // Holds the last pose
+    Clamp,
+    // Plays backwards to the start, then forwards again
+    PingPong,
+}
+
+// Values blended by keyframe interpolation
+pub trait Keyable: Clone {
+    fn lerp(self, other: Self, t: f32) -> Self;
+    // Catmull-Rom between `p1` and `p2`
+    fn cubic(p0: Self, p1: Self, p2: Self, p3: Self, t: f32) -> Self;
+}
+
+impl Keyable for Vector3<f32> {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        VectorSpace::lerp(self, other, t)
+    }
+
+    fn cubic(p0: Self, p1: Self, p2: Self, p3: Self, t: f32) -> Self {
+        catmull_rom(p0, p1, p2, p3, t)
+    }
+}
+
+impl Keyable for Quaternion<f32> {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        slerp(self, other, t)
+    }
+
+    // Catmull-Rom on the components, each key flipped into the hemisphere of
+    // the previous one, then renormalized
+    fn cubic(p0: Self, p1: Self, p2: Self, p3: Self, t: f32) -> Self {
+        let p0 = if p0.dot(p1) < 0.0 { -p0 } else { p0 };
+        let p2 = if p1.dot(p2) < 0.0 { -p2 } else { p2 };
+        let p3 = if p2.dot(p3) < 0.0 { -p3 } else { p3 };
+        catmull_rom(p0, p1, p2, p3, t).normalize()
+    }
+}
+
+// Morph target weights, a missing weight counts as zero
+impl Keyable for Vec<f32> {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        (0..self.len().max(other.len()))
+            .map(|index| {
+                let from = self.get(index).copied().unwrap_or(0.0);
+                let to = other.get(index).copied().unwrap_or(0.0);
+                from + (to - from) * t
+            })
+            .collect()
+    }
+
+    fn cubic(p0: Self, p1: Self, p2: Self, p3: Self, t: f32) -> Self {
+        let len = p0.len().max(p1.len()).max(p2.len()).max(p3.len());
+        let weight = |weights: &Self, index: usize| weights.get(index).copied().unwrap_or(0.0);
+        (0..len)
+            .map(|index| {
+                catmull_rom(
+                    weight(&p0, index),
+                    weight(&p1, index),
+                    weight(&p2, index),
+                    weight(&p3, index),
+                    t,
+                )
+            })
+            .collect()
+    }
+}
+
+fn catmull_rom<V>(p0: V, p1: V, p2: V, p3: V, t: f32) -> V
+where
+    V: Copy + Add<Output = V> + Sub<Output = V> + Mul<f32, Output = V>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+// Spherical interpolation along the shorter arc. Nearly parallel rotations
+// fall back to a normalized lerp, where slerp loses precision
+pub fn slerp(from: Quaternion<f32>, to: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let mut cos = from.dot(to);
+    // q and -q are the same rotation, going to the closer one takes the
+    // short way round
+    let to = if cos < 0.0 {
+        cos = -cos;
+        -to
+    } else {
+        to
+    };
+    if cos > 0.9995 {
+        return (from * (1.0 - t) + to * t).normalize();
+    }
+    let angle = cos.acos();
+    let sin = angle.sin();
+    from * (((1.0 - t) * angle).sin() / sin) + to * ((t * angle).sin() / sin)
+}
+
+// Built from (time, value) pairs only, so there is always one value per time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframes<T> {
+    // Seconds, ascending
+    times: Vec<f32>,
+    values: Vec<T>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Keyable> Keyframes<T> {
+    pub fn new(keys: impl IntoIterator<Item = (f32, T)>, interpolation: Interpolation) -> Self {
+        let (times, values) = keys.into_iter().unzip();
+        Self {
+            times,
+            values,
+            interpolation,
+        }
+    }
+
+    pub fn times(&self) -> &[f32] {
+        &self.times
+    }
+
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    // Holds the first and last value outside the keyed range, None without
+    // keys
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let last = self.values.len().checked_sub(1)?;
+        let next = self.times.partition_point(|key| *key <= time);
+        if next == 0 {
+            return Some(self.values[0].clone());
+        }
+        if next > last {
+            return Some(self.values[last].clone());
+        }
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let t = if span > 0.0 {
+            (time - self.times[previous]) / span
+        } else {
+            0.0
+        };
+        let value = |index: usize| self.values[index.min(last)].clone();
+        Some(match self.interpolation {
+            Interpolation::Linear => value(previous).lerp(value(next), t),
+            Interpolation::Cubic => T::cubic(
+                value(previous.saturating_sub(1)),
+                value(previous),
+                value(next),
+                value(next + 1),
+                t,
+            ),
+        })
+    }
+}
+
+// Translation, rotation and scale applied in the glTF order, scale first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: vec3(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: vec3(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    pub fn position(&self) -> Point3<f32> {
+        Point3::new(self.translation.x, self.translation.y, self.translation.z)
+    }
+}
+
+// Keyframed channels of one target
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransformTrack {
+    pub target: AnimationTarget,
+    pub translation: Option<Keyframes<Vector3<f32>>>,
+    pub rotation: Option<Keyframes<Quaternion<f32>>>,
+    pub scale: Option<Keyframes<Vector3<f32>>>,
+}
+
+impl TransformTrack {
+    pub fn new(target: AnimationTarget) -> Self {
+        Self {
+            target,
+            translation: None,
+            rotation: None,
+            scale: None,
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        [
+            self.translation.as_ref().map(Keyframes::duration),
+            self.rotation.as_ref().map(Keyframes::duration),
+            self.scale.as_ref().map(Keyframes::duration),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(0.0, f32::max)
+    }
+
+    // Channels without keys keep their part of `base`
+    pub fn sample(&self, time: f32, base: Transform) -> Transform {
+        Transform {
+            translation: self
+                .translation
+                .as_ref()
+                .and_then(|keys| keys.sample(time))
+                .unwrap_or(base.translation),
+            rotation: self
+                .rotation
+                .as_ref()
+                .and_then(|keys| keys.sample(time))
+                .unwrap_or(base.rotation),
+            scale: self
+                .scale
+                .as_ref()
+                .and_then(|keys| keys.sample(time))
+                .unwrap_or(base.scale),
+        }
+    }
+}
+
+// Keyframed morph target weights of one object, one weight per target
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightsTrack {
+    pub object: ObjectId,
+    pub weights: Keyframes<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AnimationClip {
+    pub tracks: Vec<TransformTrack>,
+    pub weight_tracks: Vec<WeightsTrack>,
+}
+
+impl AnimationClip {
+    // Up to the last key of any track
+    pub fn duration(&self) -> f32 {
+        self.tracks
+            .iter()
+            .map(TransformTrack::duration)
+            .chain(
+                self.weight_tracks
+                    .iter()
+                    .map(|track| track.weights.duration()),
+            )
+            .fold(0.0, f32::max)
+    }
+}
+
+// Plays a clip, advanced by the engine every frame while it isn't paused
+#[derive(Debug, Clone)]
+pub struct Animator {
+    pub clip: AnimationClip,
+    pub mode: PlaybackMode,
+    // Multiplies the frame time, negative plays backwards
+    pub speed: f32,
+    pub playing: bool,
+    // Seconds since the start, wrapped to the clip for looping modes
+    time: f32,
+}
+
+impl Animator {
+    pub fn new(clip: AnimationClip, mode: PlaybackMode) -> Self {
+        Self {
+            clip,
+            mode,
+            speed: 1.0,
+            playing: true,
+            time: 0.0,
+        }
+    }
+
+    pub fn advance(&mut self, dt: f32) {
+        if self.playing {
+            self.seek(self.time + dt * self.speed);
+        }
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        let duration = self.clip.duration();
+        self.time = match self.mode {
+            _ if duration <= 0.0 => 0.0,
+            PlaybackMode::Loop => time.rem_euclid(duration),
+            PlaybackMode::Clamp => time.clamp(0.0, duration),
+            PlaybackMode::PingPong => time.rem_euclid(2.0 * duration),
+        };
+    }
+
+    // Where in the clip the pose is sampled
+    pub fn clip_time(&self) -> f32 {
+        let duration = self.clip.duration();
+        match self.mode {
+            PlaybackMode::PingPong if self.time > duration => 2.0 * duration - self.time,
+            _ => self.time,
+        }
+    }
+
+    // Every track's target with its current pose, unkeyed channels taken
+    // from the target's `base` transform
+    pub fn sample<'a>(
+        &'a self,
+        base: impl Fn(AnimationTarget) -> Transform + 'a,
+    ) -> impl Iterator<Item = (AnimationTarget, Transform)> + 'a {
+        let time = self.clip_time();
+        self.clip
+            .tracks
+            .iter()
+            .map(move |track| (track.target, track.sample(time, base(track.target))))
+    }
+
+    // Every weight track's object with its current weights
+    pub fn sample_weights(&self) -> impl Iterator<Item = (ObjectId, Vec<f32>)> + '_ {
+        let time = self.clip_time();
+        self.clip
+            .weight_tracks
+            .iter()
+            .filter_map(move |track| Some((track.object, track.weights.sample(time)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Rotation3};
+
+    use super::*;
+
+    fn close(a: Quaternion<f32>, b: Quaternion<f32>) -> bool {
+        // q and -q are the same rotation
+        a.dot(b).abs() > 1.0 - 1e-5
+    }
+
+    #[test]
+    fn slerp_halves_the_angle() {
+        let from = Quaternion::from_angle_z(Deg(0.0));
+        let to = Quaternion::from_angle_z(Deg(90.0));
+        assert!(close(
+            slerp(from, to, 0.5),
+            Quaternion::from_angle_z(Deg(45.0))
+        ));
+        assert!(close(slerp(from, to, 0.0), from));
+        assert!(close(slerp(from, to, 1.0), to));
+        let halfway = slerp(from, to, 0.5);
+        assert!((halfway.magnitude() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn slerp_takes_the_shorter_arc() {
+        let from = Quaternion::from_angle_z(Deg(0.0));
+        // The same rotation as 90 degrees, on the far side of the sphere
+        let to = -Quaternion::from_angle_z(Deg(90.0));
+        assert!(close(
+            slerp(from, to, 0.5),
+            Quaternion::from_angle_z(Deg(45.0))
+        ));
+        // 300 degrees one way is 60 the other
+        let to = Quaternion::from_angle_z(Deg(300.0));
+        assert!(close(
+            slerp(from, to, 0.5),
+            Quaternion::from_angle_z(Deg(-30.0))
+        ));
+    }
+
+    #[test]
+    fn samples_hold_the_ends_and_blend_between_keys() {
+        let keys = Keyframes::new(
+            [(1.0, vec3(0.0, 0.0, 0.0)), (3.0, vec3(4.0, 0.0, 0.0))],
+            Interpolation::Linear,
+        );
+        assert_eq!(keys.times(), [1.0, 3.0]);
+        assert_eq!(keys.values().len(), keys.times().len());
+        assert_eq!(keys.sample(0.0), Some(vec3(0.0, 0.0, 0.0)));
+        assert_eq!(keys.sample(2.0), Some(vec3(2.0, 0.0, 0.0)));
+        assert_eq!(keys.sample(5.0), Some(vec3(4.0, 0.0, 0.0)));
+        let empty = Keyframes::<Vector3<f32>>::new([], Interpolation::Cubic);
+        assert_eq!(empty.sample(1.0), None);
+    }
+}
@@ -0,0 +1,676 @@
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ash::vk::DriverId;
+use log::{info, warn};
+
+use super::{
+    configuration::MAX_FLIGHT_FENCES, debug_palette::DebugPalette, mesh::CpuMeshData,
+    rng::DEFAULT_SEED,
+};
+use crate::utils;
+
+const SYNC_VALIDATION_ENV: &str = "CATERPIE_SYNC_VALIDATION";
+const VALIDATION_ENV: &str = "CATERPIE_VALIDATION";
+const SYNCHRONIZATION2_ENV: &str = "CATERPIE_SYNCHRONIZATION2";
+const TRANSFER_QUEUE_ENV: &str = "CATERPIE_TRANSFER_QUEUE";
+const BINDLESS_ENV: &str = "CATERPIE_BINDLESS";
+const BUFFER_DEVICE_ADDRESS_ENV: &str = "CATERPIE_BUFFER_DEVICE_ADDRESS";
+const GPU_CULLING_ENV: &str = "CATERPIE_GPU_CULLING";
+const PACKED_VERTICES_ENV: &str = "CATERPIE_PACKED_VERTICES";
+const LODS_ENV: &str = "CATERPIE_LODS";
+const STENCIL_ENV: &str = "CATERPIE_STENCIL";
+const FPS_LIMIT_ENV: &str = "CATERPIE_FPS_LIMIT";
+const BACKGROUND_FPS_ENV: &str = "CATERPIE_BACKGROUND_FPS";
+const BACKGROUND_BEHAVIOR_ENV: &str = "CATERPIE_BACKGROUND";
+const RESIZE_DEBOUNCE_ENV: &str = "CATERPIE_RESIZE_DEBOUNCE";
+const LOW_LATENCY_ENV: &str = "CATERPIE_LOW_LATENCY";
+const VSYNC_ENV: &str = "CATERPIE_VSYNC";
+const MSAA_ENV: &str = "CATERPIE_MSAA";
+const FRAMES_IN_FLIGHT_ENV: &str = "CATERPIE_FRAMES_IN_FLIGHT";
+const MIN_IMAGE_COUNT_ENV: &str = "CATERPIE_MIN_IMAGE_COUNT";
+const DRIVER_ENV: &str = "CATERPIE_DRIVER";
+const TICK_RATE_ENV: &str = "CATERPIE_TICK_RATE";
+const PICKING_ENV: &str = "CATERPIE_PICKING";
+const FRAME_BUDGET_ENV: &str = "CATERPIE_FRAME_BUDGET";
+const BLOOM_ENV: &str = "CATERPIE_BLOOM";
+const BLOOM_INTENSITY_ENV: &str = "CATERPIE_BLOOM_INTENSITY";
+const BLOOM_THRESHOLD_ENV: &str = "CATERPIE_BLOOM_THRESHOLD";
+const SSAO_ENV: &str = "CATERPIE_SSAO";
+const SSAO_RADIUS_ENV: &str = "CATERPIE_SSAO_RADIUS";
+const SSAO_BIAS_ENV: &str = "CATERPIE_SSAO_BIAS";
+const DEFERRED_ENV: &str = "CATERPIE_DEFERRED";
+const MODEL_ENV: &str = "CATERPIE_MODEL";
+const TEXTURE_ENV: &str = "CATERPIE_TEXTURE";
+const VERTEX_SHADER_ENV: &str = "CATERPIE_VERTEX_SHADER";
+const FRAGMENT_SHADER_ENV: &str = "CATERPIE_FRAGMENT_SHADER";
+const MESH_CPU_DATA_ENV: &str = "CATERPIE_MESH_CPU_DATA";
+const OVERLAY_ENV: &str = "CATERPIE_OVERLAY";
+const TEXT_FONT_ENV: &str = "CATERPIE_TEXT_FONT";
+const DEBUG_PALETTE_ENV: &str = "CATERPIE_DEBUG_PALETTE";
+const HOT_RELOAD_ENV: &str = "CATERPIE_HOT_RELOAD";
+const CAPTURE_FRAME_ENV: &str = "CATERPIE_CAPTURE_FRAME";
+const TRANSPARENT_WINDOW_ENV: &str = "CATERPIE_TRANSPARENT_WINDOW";
+const FULLSCREEN_ENV: &str = "CATERPIE_FULLSCREEN";
+const ASSET_ROOT_ENV: &str = "CATERPIE_ASSET_ROOT";
+const SEED_ENV: &str = "CATERPIE_SEED";
+const ENVIRONMENT_INTENSITY_ENV: &str = "CATERPIE_ENVIRONMENT_INTENSITY";
+
+pub const CONFIG_FILE: &str = "caterpie.toml";
+
+// What the engine does while the window is unfocused or occluded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackgroundBehavior {
+    // Keep rendering at full rate, e.g. for timelapse recordings
+    Continue,
+    // Drop to a handful of frames per second
+    #[default]
+    Throttle,
+    // Stop drawing and freeze the animation until focus returns
+    Pause,
+}
+
+impl BackgroundBehavior {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "continue" => Some(BackgroundBehavior::Continue),
+            "throttle" => Some(BackgroundBehavior::Throttle),
+            "pause" => Some(BackgroundBehavior::Pause),
+            _ => None,
+        }
+    }
+}
+
+// Resolution and refresh rate for exclusive fullscreen, written as
+// "1920x1080" or "1920x1080@144"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    // The monitor's highest rate at that size without one
+    pub refresh_hz: Option<u32>,
+}
+
+impl FromStr for DisplayMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{value:?} is not a display mode like 1920x1080@144");
+        let (size, refresh_hz) = match value.trim().split_once('@') {
+            Some((size, refresh)) => (
+                size,
+                Some(
+                    refresh
+                        .trim()
+                        .trim_end_matches("Hz")
+                        .parse()
+                        .map_err(|_| invalid())?,
+                ),
+            ),
+            None => (value.trim(), None),
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        Ok(DisplayMode {
+            width: width.trim().parse().map_err(|_| invalid())?,
+            height: height.trim().parse().map_err(|_| invalid())?,
+            refresh_hz,
+        })
+    }
+}
+
+impl fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        match self.refresh_hz {
+            Some(refresh_hz) => write!(f, "@{refresh_hz}"),
+            None => Ok(()),
+        }
+    }
+}
+
+// Highest VkDriverId there is a name for, Vulkan 1.3.280
+const LAST_DRIVER_ID: i32 = DriverId::MESA_AGXV.as_raw();
+
+// A Vulkan driver by the name of its VkDriverId without the prefix, like
+// "mesa_llvmpipe", by its number, or by one of the usual short names
+pub(crate) fn parse_driver_id(value: &str) -> Option<DriverId> {
+    let value = value.trim().to_ascii_lowercase();
+    let alias = match value.as_str() {
+        "lavapipe" | "llvmpipe" => Some(DriverId::MESA_LLVMPIPE),
+        "radv" => Some(DriverId::MESA_RADV),
+        "anv" => Some(DriverId::INTEL_OPEN_SOURCE_MESA),
+        "nvk" => Some(DriverId::MESA_NVK),
+        "turnip" => Some(DriverId::MESA_TURNIP),
+        "venus" => Some(DriverId::MESA_VENUS),
+        "dozen" => Some(DriverId::MESA_DOZEN),
+        "nvidia" => Some(DriverId::NVIDIA_PROPRIETARY),
+        "amdvlk" => Some(DriverId::AMD_OPEN_SOURCE),
+        "swiftshader" => Some(DriverId::GOOGLE_SWIFTSHADER),
+        "moltenvk" => Some(DriverId::MOLTENVK),
+        _ => None,
+    };
+    if alias.is_some() {
+        return alias;
+    }
+    if let Ok(raw) = value.parse() {
+        return (1..=LAST_DRIVER_ID)
+            .contains(&raw)
+            .then(|| DriverId::from_raw(raw));
+    }
+    (1..=LAST_DRIVER_ID)
+        .map(DriverId::from_raw)
+        .find(|driver_id| format!("{driver_id:?}").eq_ignore_ascii_case(&value))
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    // Enables VK_LAYER_KHRONOS_validation when it is installed.
+    // Defaults to on for debug builds, override with CATERPIE_VALIDATION=0/1
+    pub validation: bool,
+    // Turns on VK_VALIDATION_FEATURE_ENABLE_SYNCHRONIZATION_VALIDATION_EXT on top
+    // of `validation` to report read/write hazards between submissions.
+    // Off by default as it is expensive, enable with CATERPIE_SYNC_VALIDATION=1
+    pub sync_validation: bool,
+    // Uses VK_KHR_synchronization2 barriers and submits when the device
+    // supports them, CATERPIE_SYNCHRONIZATION2=0 forces the legacy path
+    pub synchronization2: bool,
+    // Streams textures on a dedicated transfer queue, ordered against the
+    // frames through a timeline semaphore, when the device has both.
+    // CATERPIE_TRANSFER_QUEUE=0 keeps the uploads on the graphics queue
+    pub transfer_queue: bool,
+    // Binds every material texture through one descriptor array when the
+    // device supports VK_EXT_descriptor_indexing, CATERPIE_BINDLESS=0 keeps
+    // a descriptor set per material
+    pub bindless: bool,
+    // Experimental, the vertex shader reads the object's uniform entry
+    // through a pushed VK_KHR_buffer_device_address pointer instead of the
+    // dynamic offset. Off by default, CATERPIE_BUFFER_DEVICE_ADDRESS=1 turns
+    // it on where the device supports it
+    pub buffer_device_address: bool,
+    // Frustum culls objects in a compute pass that writes indirect draws
+    // when the device supports VK_KHR_draw_indirect_count, otherwise on the
+    // CPU. CATERPIE_GPU_CULLING=0 forces the CPU path
+    pub gpu_culling: bool,
+    // Uploads meshes with quantized normals, UVs, colors and weights, about
+    // half the vertex buffer memory. Off by default,
+    // CATERPIE_PACKED_VERTICES=1 turns it on
+    pub packed_vertices: bool,
+    // Simplifies meshes into a few levels of detail at load time, distant
+    // objects are drawn with fewer triangles. CATERPIE_LODS=0 keeps every
+    // object at full detail
+    pub lods: bool,
+    // Picks a depth format with a stencil aspect, the selected object is
+    // outlined through it. CATERPIE_STENCIL=1
+    pub stencil: bool,
+    // Foreground frame rate limit, independent of the present mode. With FIFO
+    // a limit at or above the refresh rate has no effect.
+    // None or 0 renders uncapped, override with CATERPIE_FPS_LIMIT=<fps>
+    pub fps_limit: Option<u32>,
+    // CATERPIE_BACKGROUND=continue|throttle|pause
+    pub background_behavior: BackgroundBehavior,
+    // Frame rate used by BackgroundBehavior::Throttle, CATERPIE_BACKGROUND_FPS=<fps>
+    pub background_fps: u32,
+    // How long a window has to stop resizing before its swapchain is
+    // rebuilt. 0 rebuilds at most once per presented frame, around 100 keeps
+    // dragging a window corner from rebuilding it dozens of times a second.
+    // CATERPIE_RESIZE_DEBOUNCE=<ms>
+    pub resize_debounce_ms: u32,
+    // Waits for the frame slot before the app reads its input instead of
+    // after, and with vsync starts the frame as close to the next refresh as
+    // the last frame's timing allows. Costs some throughput.
+    // CATERPIE_LOW_LATENCY=0/1
+    pub low_latency: bool,
+    // Mesh and texture every RenderObject is drawn with
+    pub model_path: PathBuf,
+    pub texture_path: PathBuf,
+    // What meshes keep on the CPU after their upload when the load call
+    // doesn't say, CATERPIE_MESH_CPU_DATA=discard|keep|positions
+    pub mesh_cpu_data: CpuMeshData,
+    // SPIR-V drawing the scene on the forward path in place of the built-in
+    // shaders, with the same bindings. A shader that fails to build is
+    // drawn as flat magenta until it is fixed, and with `hot_reload` both
+    // are rebuilt once they change. CATERPIE_VERTEX_SHADER=<path>,
+    // CATERPIE_FRAGMENT_SHADER=<path>
+    pub vertex_shader: Option<PathBuf>,
+    pub fragment_shader: Option<PathBuf>,
+    // Equirectangular PNG the ambient light and reflections come from, a
+    // procedural sky without one. Not drawn as a background yet
+    pub skybox_path: Option<PathBuf>,
+    // Scales the skybox's light, CATERPIE_ENVIRONMENT_INTENSITY=<factor>
+    pub environment_intensity: f32,
+    pub window_width: u32,
+    pub window_height: u32,
+    pub window_title: String,
+    // Wayland app_id, X11 WM_CLASS and Windows AppUserModelID, groups the
+    // windows in taskbars and docks and matches them to a .desktop file
+    pub app_id: String,
+    // The window shows the desktop where nothing was drawn, on compositors
+    // that blend swapchains. Opaque with a warning elsewhere.
+    // CATERPIE_TRANSPARENT_WINDOW=0/1
+    pub transparent_window: bool,
+    // Starts in exclusive fullscreen with the closest video mode of the
+    // window's monitor, F11 switches back and forth.
+    // CATERPIE_FULLSCREEN=1920x1080@144
+    pub exclusive_fullscreen: Option<DisplayMode>,
+    // FIFO when set, otherwise MAILBOX where available. CATERPIE_VSYNC=0/1
+    pub vsync: bool,
+    // Only single sampling is implemented, other counts are logged and ignored
+    pub msaa_samples: u32,
+    // 1 up to MAX_FLIGHT_FENCES, CATERPIE_FRAMES_IN_FLIGHT=<n>
+    pub frames_in_flight: u32,
+    // Swapchain images asked for, 2 for double buffering with less latency
+    // or more to keep compositors from stalling. Clamped to what the surface
+    // allows, one above its minimum when unset. The driver may still create
+    // more. CATERPIE_MIN_IMAGE_COUNT=<n>
+    pub min_image_count: Option<u32>,
+    // Picks a device run by this driver over the first suitable one, for
+    // machines with several ICDs installed. Every device and its driver is
+    // logged when none is, the first suitable device is used then.
+    // CATERPIE_DRIVER=lavapipe, see `parse_driver_id` for the names
+    pub driver_preference: Option<DriverId>,
+    // Fixed updates per second, independent of the frame rate.
+    // CATERPIE_TICK_RATE=<hz>
+    pub tick_rate: u32,
+    // Builds the object ID pass behind Engine::pick, CATERPIE_PICKING=0/1
+    pub picking: bool,
+    // GPU milliseconds per frame above which the scene is rendered at a
+    // lower resolution and upscaled. None keeps full resolution,
+    // CATERPIE_FRAME_BUDGET=<ms>
+    pub frame_budget_ms: Option<f32>,
+    // Renders the scene to an HDR target and adds a glow around pixels
+    // brighter than `bloom_threshold` before tonemapping. Windows only,
+    // CATERPIE_BLOOM=0/1
+    pub bloom: bool,
+    // CATERPIE_BLOOM_INTENSITY=<factor>
+    pub bloom_intensity: f32,
+    // CATERPIE_BLOOM_THRESHOLD=<brightness>
+    pub bloom_threshold: f32,
+    // Darkens creases and corners with ambient occlusion estimated from the
+    // depth buffer. Windows only, CATERPIE_SSAO=0/1
+    pub ssao: bool,
+    // View space distance the occlusion is sampled within,
+    // CATERPIE_SSAO_RADIUS=<units>
+    pub ssao_radius: f32,
+    // Depth difference ignored to avoid self occlusion on flat surfaces,
+    // CATERPIE_SSAO_BIAS=<units>
+    pub ssao_bias: f32,
+    // Creates the G-buffer and lighting pass so the deferred shading path can
+    // be switched to. Windows only, CATERPIE_DEFERRED=0/1
+    pub deferred: bool,
+    // Shows the app's UiContext labels in the window title
+    pub overlay: bool,
+    // A TrueType or OpenType font that toasts, the log view and debug text
+    // are drawn with as signed distance fields, crisp at any scale. The
+    // built-in stroke font when unset or when it fails to load,
+    // CATERPIE_TEXT_FONT=<path>
+    pub text_font: Option<PathBuf>,
+    // Colors of the debug lines, bounds and tints. A built-in palette by
+    // name, CATERPIE_DEBUG_PALETTE=default/color-blind, or single colors
+    // from the config file
+    pub debug_palette: DebugPalette,
+    // Watches the bound texture and the scene shader overrides on disk and
+    // loads them again when they change. Defaults to on for debug builds, CATERPIE_HOT_RELOAD=0/1
+    pub hot_reload: bool,
+    // Captures this frame, counted from 1, when running under RenderDoc.
+    // Together with the viewer's --benchmark it is the same frame every run.
+    // CATERPIE_CAPTURE_FRAME=<n>
+    pub capture_frame: Option<u64>,
+    // Every random stream is forked off this, rng::DEFAULT_SEED unless set.
+    // CATERPIE_SEED=<n>
+    pub seed: u64,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            validation: cfg!(debug_assertions),
+            sync_validation: false,
+            synchronization2: true,
+            transfer_queue: true,
+            bindless: true,
+            buffer_device_address: false,
+            gpu_culling: true,
+            packed_vertices: false,
+            lods: true,
+            stencil: false,
+            fps_limit: None,
+            background_behavior: BackgroundBehavior::default(),
+            background_fps: 5,
+            resize_debounce_ms: 0,
+            low_latency: false,
+            model_path: PathBuf::from("src/resources/viking_room.obj"),
+            texture_path: PathBuf::from("src/resources/viking_room.png"),
+            mesh_cpu_data: CpuMeshData::default(),
+            vertex_shader: None,
+            fragment_shader: None,
+            skybox_path: None,
+            environment_intensity: 0.3,
+            window_width: 1920,
+            window_height: 1080,
+            window_title: "caterpie".to_string(),
+            app_id: "caterpie".to_string(),
+            transparent_window: false,
+            exclusive_fullscreen: None,
+            vsync: false,
+            msaa_samples: 1,
+            frames_in_flight: MAX_FLIGHT_FENCES,
+            min_image_count: None,
+            driver_preference: None,
+            tick_rate: 60,
+            picking: false,
+            frame_budget_ms: None,
+            bloom: false,
+            bloom_intensity: 0.1,
+            bloom_threshold: 0.8,
+            ssao: false,
+            ssao_radius: 0.5,
+            ssao_bias: 0.025,
+            deferred: false,
+            overlay: true,
+            text_font: None,
+            debug_palette: DebugPalette::DEFAULT,
+            hot_reload: cfg!(debug_assertions),
+            capture_frame: None,
+            seed: DEFAULT_SEED,
+        }
+    }
+}
+
+fn env_flag(name: &str) -> Option<bool> {
+    env::var(name)
+        .ok()
+        .map(|value| matches!(value.as_str(), "1" | "true" | "on" | "yes"))
+}
+
+// Where caterpie.toml and the relative asset paths in it are looked up,
+// CATERPIE_ASSET_ROOT or the working directory
+pub fn asset_root() -> PathBuf {
+    env::var_os(ASSET_ROOT_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+impl EngineConfig {
+    // Defaults, then caterpie.toml from the asset root, then CATERPIE_*
+    // variables. A missing file is not an error
+    pub fn load() -> Self {
+        Self::load_layers().finish()
+    }
+
+    // `load` without `finish`, for callers with overrides of their own such
+    // as command line arguments
+    pub fn load_layers() -> Self {
+        let mut config = Self::default();
+        let root = asset_root();
+        let path = root.join(CONFIG_FILE);
+        let text =
+            utils::io::read_asset(&path).map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+        match text {
+            Ok(text) => match config.apply_file(&text, &root) {
+                Ok(()) => info!("Loaded {}", path.display()),
+                Err(err) => warn!("Ignoring {}: {err}", path.display()),
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => warn!("Failed to read {}: {err}", path.display()),
+        }
+        config.apply_env();
+        config
+    }
+
+    // Overlays the keys present in a caterpie.toml document onto `self`
+    #[cfg(feature = "config-file")]
+    pub fn apply_file(&mut self, text: &str, root: &Path) -> Result<(), String> {
+        super::config_file::ConfigFile::parse(text)?.apply(self, root);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "config-file"))]
+    pub fn apply_file(&mut self, _text: &str, _root: &Path) -> Result<(), String> {
+        Err("built without the config-file feature".to_string())
+    }
+
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        config.apply_env();
+        config.finish()
+    }
+
+    pub fn apply_env(&mut self) {
+        if let Some(validation) = env_flag(VALIDATION_ENV) {
+            self.validation = validation;
+        }
+        if let Some(sync_validation) = env_flag(SYNC_VALIDATION_ENV) {
+            self.sync_validation = sync_validation;
+        }
+        if let Some(synchronization2) = env_flag(SYNCHRONIZATION2_ENV) {
+            self.synchronization2 = synchronization2;
+        }
+        if let Some(transfer_queue) = env_flag(TRANSFER_QUEUE_ENV) {
+            self.transfer_queue = transfer_queue;
+        }
+        if let Some(bindless) = env_flag(BINDLESS_ENV) {
+            self.bindless = bindless;
+        }
+        if let Some(buffer_device_address) = env_flag(BUFFER_DEVICE_ADDRESS_ENV) {
+            self.buffer_device_address = buffer_device_address;
+        }
+        if let Some(gpu_culling) = env_flag(GPU_CULLING_ENV) {
+            self.gpu_culling = gpu_culling;
+        }
+        if let Some(packed_vertices) = env_flag(PACKED_VERTICES_ENV) {
+            self.packed_vertices = packed_vertices;
+        }
+        if let Some(lods) = env_flag(LODS_ENV) {
+            self.lods = lods;
+        }
+        if let Some(stencil) = env_flag(STENCIL_ENV) {
+            self.stencil = stencil;
+        }
+        if let Some(picking) = env_flag(PICKING_ENV) {
+            self.picking = picking;
+        }
+        if let Ok(frame_budget) = env::var(FRAME_BUDGET_ENV) {
+            self.frame_budget_ms = frame_budget.parse().ok();
+        }
+        if let Some(bloom) = env_flag(BLOOM_ENV) {
+            self.bloom = bloom;
+        }
+        if let Some(bloom_intensity) = env::var(BLOOM_INTENSITY_ENV)
+            .ok()
+            .and_then(|intensity| intensity.parse().ok())
+        {
+            self.bloom_intensity = bloom_intensity;
+        }
+        if let Some(environment_intensity) = env::var(ENVIRONMENT_INTENSITY_ENV)
+            .ok()
+            .and_then(|intensity| intensity.parse().ok())
+        {
+            self.environment_intensity = environment_intensity;
+        }
+        if let Some(bloom_threshold) = env::var(BLOOM_THRESHOLD_ENV)
+            .ok()
+            .and_then(|threshold| threshold.parse().ok())
+        {
+            self.bloom_threshold = bloom_threshold;
+        }
+        if let Some(ssao) = env_flag(SSAO_ENV) {
+            self.ssao = ssao;
+        }
+        if let Some(ssao_radius) = env::var(SSAO_RADIUS_ENV)
+            .ok()
+            .and_then(|radius| radius.parse().ok())
+        {
+            self.ssao_radius = ssao_radius;
+        }
+        if let Some(ssao_bias) = env::var(SSAO_BIAS_ENV)
+            .ok()
+            .and_then(|bias| bias.parse().ok())
+        {
+            self.ssao_bias = ssao_bias;
+        }
+        if let Some(deferred) = env_flag(DEFERRED_ENV) {
+            self.deferred = deferred;
+        }
+        if let Ok(fps_limit) = env::var(FPS_LIMIT_ENV) {
+            self.fps_limit = fps_limit.parse().ok();
+        }
+        if let Some(background_fps) = env::var(BACKGROUND_FPS_ENV)
+            .ok()
+            .and_then(|background_fps| background_fps.parse().ok())
+        {
+            self.background_fps = background_fps;
+        }
+        if let Some(low_latency) = env_flag(LOW_LATENCY_ENV) {
+            self.low_latency = low_latency;
+        }
+        if let Some(resize_debounce_ms) = env::var(RESIZE_DEBOUNCE_ENV)
+            .ok()
+            .and_then(|resize_debounce_ms| resize_debounce_ms.parse().ok())
+        {
+            self.resize_debounce_ms = resize_debounce_ms;
+        }
+        if let Ok(background_behavior) = env::var(BACKGROUND_BEHAVIOR_ENV) {
+            match BackgroundBehavior::parse(&background_behavior) {
+                Some(background_behavior) => self.background_behavior = background_behavior,
+                None => warn!(
+                    "Ignoring unknown {}={}",
+                    BACKGROUND_BEHAVIOR_ENV, background_behavior
+                ),
+            }
+        }
+        if let Some(vsync) = env_flag(VSYNC_ENV) {
+            self.vsync = vsync;
+        }
+        if let Some(msaa_samples) = env::var(MSAA_ENV).ok().and_then(|msaa| msaa.parse().ok()) {
+            self.msaa_samples = msaa_samples;
+        }
+        if let Some(frames_in_flight) = env::var(FRAMES_IN_FLIGHT_ENV)
+            .ok()
+            .and_then(|frames| frames.parse().ok())
+        {
+            self.frames_in_flight = frames_in_flight;
+        }
+        if let Ok(min_image_count) = env::var(MIN_IMAGE_COUNT_ENV) {
+            self.min_image_count = min_image_count.parse().ok();
+        }
+        if let Ok(driver) = env::var(DRIVER_ENV) {
+            match parse_driver_id(&driver) {
+                Some(driver_id) => self.driver_preference = Some(driver_id),
+                None => warn!("Ignoring unknown {DRIVER_ENV}={driver:?}"),
+            }
+        }
+        if let Some(tick_rate) = env::var(TICK_RATE_ENV)
+            .ok()
+            .and_then(|tick_rate| tick_rate.parse().ok())
+        {
+            self.tick_rate = tick_rate;
+        }
+        if let Some(model_path) = env::var_os(MODEL_ENV) {
+            self.model_path = PathBuf::from(model_path);
+        }
+        if let Some(texture_path) = env::var_os(TEXTURE_ENV) {
+            self.texture_path = PathBuf::from(texture_path);
+        }
+        if let Some(vertex_shader) = env::var_os(VERTEX_SHADER_ENV) {
+            self.vertex_shader = Some(PathBuf::from(vertex_shader));
+        }
+        if let Some(fragment_shader) = env::var_os(FRAGMENT_SHADER_ENV) {
+            self.fragment_shader = Some(PathBuf::from(fragment_shader));
+        }
+        if let Ok(mesh_cpu_data) = env::var(MESH_CPU_DATA_ENV) {
+            match CpuMeshData::parse(&mesh_cpu_data) {
+                Some(cpu_data) => self.mesh_cpu_data = cpu_data,
+                None => warn!("Ignoring unknown {MESH_CPU_DATA_ENV}={mesh_cpu_data:?}"),
+            }
+        }
+        if let Some(transparent_window) = env_flag(TRANSPARENT_WINDOW_ENV) {
+            self.transparent_window = transparent_window;
+        }
+        if let Ok(display_mode) = env::var(FULLSCREEN_ENV) {
+            match display_mode.parse() {
+                Ok(display_mode) => self.exclusive_fullscreen = Some(display_mode),
+                Err(err) => warn!("Ignoring {FULLSCREEN_ENV}: {err}"),
+            }
+        }
+        if let Some(overlay) = env_flag(OVERLAY_ENV) {
+            self.overlay = overlay;
+        }
+        if let Some(text_font) = env::var_os(TEXT_FONT_ENV) {
+            self.text_font = Some(PathBuf::from(text_font));
+        }
+        if let Ok(palette) = env::var(DEBUG_PALETTE_ENV) {
+            match DebugPalette::parse(&palette) {
+                Some(debug_palette) => self.debug_palette = debug_palette,
+                None => warn!("Ignoring unknown {DEBUG_PALETTE_ENV}={palette:?}"),
+            }
+        }
+        if let Some(hot_reload) = env_flag(HOT_RELOAD_ENV) {
+            self.hot_reload = hot_reload;
+        }
+        if let Ok(capture_frame) = env::var(CAPTURE_FRAME_ENV) {
+            self.capture_frame = capture_frame.parse().ok();
+        }
+        if let Some(seed) = env::var(SEED_ENV).ok().and_then(|seed| seed.parse().ok()) {
+            self.seed = seed;
+        }
+    }
+
+    // Resolves settings that depend on each other, run after every override
+    pub fn finish(mut self) -> Self {
+        // Synchronization validation is a feature of the validation layer
+        if self.sync_validation {
+            self.validation = true;
+        }
+        if !(1..=MAX_FLIGHT_FENCES).contains(&self.frames_in_flight) {
+            warn!(
+                "frames_in_flight must be between 1 and {MAX_FLIGHT_FENCES}, got {}",
+                self.frames_in_flight
+            );
+            self.frames_in_flight = self.frames_in_flight.clamp(1, MAX_FLIGHT_FENCES);
+        }
+        if self.tick_rate == 0 {
+            warn!("tick_rate must be at least 1, using the default of 60");
+            self.tick_rate = 60;
+        }
+        if self.msaa_samples != 1 {
+            warn!(
+                "MSAA is not implemented yet, rendering single sampled instead of {}x",
+                self.msaa_samples
+            );
+            self.msaa_samples = 1;
+        }
+        if let Some(frame_budget) = self.frame_budget_ms.filter(|budget| *budget <= 0.0) {
+            warn!("frame_budget_ms must be positive, got {frame_budget}");
+            self.frame_budget_ms = None;
+        }
+        if self.bloom_intensity < 0.0 || self.bloom_threshold < 0.0 {
+            warn!(
+                "bloom_intensity and bloom_threshold can't be negative, got {} and {}",
+                self.bloom_intensity, self.bloom_threshold
+            );
+            self.bloom_intensity = self.bloom_intensity.max(0.0);
+            self.bloom_threshold = self.bloom_threshold.max(0.0);
+        }
+        if self.ssao_radius <= 0.0 || self.ssao_bias < 0.0 {
+            warn!(
+                "ssao_radius must be positive and ssao_bias can't be negative, got {} and {}",
+                self.ssao_radius, self.ssao_bias
+            );
+            if self.ssao_radius <= 0.0 {
+                self.ssao_radius = EngineConfig::default().ssao_radius;
+            }
+            self.ssao_bias = self.ssao_bias.max(0.0);
+        }
+        if self.environment_intensity < 0.0 {
+            warn!(
+                "environment_intensity can't be negative, got {}",
+                self.environment_intensity
+            );
+            self.environment_intensity = 0.0;
+        }
+        self
+    }
+}
@@ -0,0 +1,459 @@
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::Deserialize;
+
+use super::{
+    config::{parse_driver_id, BackgroundBehavior, EngineConfig},
+    debug_palette::DebugPalette,
+    mesh::CpuMeshData,
+};
+
+// Layout of caterpie.toml, every key is optional and falls back to the
+// defaults (or whatever was set before the file was applied)
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct ConfigFile {
+    window: WindowSection,
+    renderer: RendererSection,
+    assets: AssetsSection,
+    debug: DebugSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WindowSection {
+    width: Option<u32>,
+    height: Option<u32>,
+    title: Option<String>,
+    app_id: Option<String>,
+    transparent: Option<bool>,
+    fullscreen: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RendererSection {
+    vsync: Option<bool>,
+    msaa: Option<u32>,
+    frames_in_flight: Option<u32>,
+    min_image_count: Option<u32>,
+    driver: Option<String>,
+    tick_rate: Option<u32>,
+    fps_limit: Option<u32>,
+    background: Option<String>,
+    background_fps: Option<u32>,
+    resize_debounce_ms: Option<u32>,
+    low_latency: Option<bool>,
+    synchronization2: Option<bool>,
+    transfer_queue: Option<bool>,
+    bindless: Option<bool>,
+    buffer_device_address: Option<bool>,
+    gpu_culling: Option<bool>,
+    packed_vertices: Option<bool>,
+    lods: Option<bool>,
+    stencil: Option<bool>,
+    picking: Option<bool>,
+    frame_budget_ms: Option<f32>,
+    bloom: Option<bool>,
+    bloom_intensity: Option<f32>,
+    bloom_threshold: Option<f32>,
+    ssao: Option<bool>,
+    ssao_radius: Option<f32>,
+    ssao_bias: Option<f32>,
+    deferred: Option<bool>,
+    seed: Option<u64>,
+    environment_intensity: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct AssetsSection {
+    model: Option<PathBuf>,
+    texture: Option<PathBuf>,
+    skybox: Option<PathBuf>,
+    mesh_cpu_data: Option<String>,
+    vertex_shader: Option<PathBuf>,
+    fragment_shader: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DebugSection {
+    validation: Option<bool>,
+    sync_validation: Option<bool>,
+    overlay: Option<bool>,
+    text_font: Option<PathBuf>,
+    hot_reload: Option<bool>,
+    capture_frame: Option<u64>,
+    palette: Option<String>,
+    colors: DebugColors,
+}
+
+// A TOML array of exactly N items. Fixed size arrays on their own ignore
+// anything past the Nth
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "Vec<T>")]
+struct Exactly<T, const N: usize>([T; N]);
+
+impl<T, const N: usize> TryFrom<Vec<T>> for Exactly<T, N> {
+    type Error = String;
+
+    fn try_from(items: Vec<T>) -> Result<Self, String> {
+        let len = items.len();
+        <[T; N]>::try_from(items)
+            .map(Exactly)
+            .map_err(|_| format!("expected {N} items, got {len}"))
+    }
+}
+
+type Rgb = Exactly<f32, 3>;
+
+// `[debug.colors]`, linear RGB triples replacing single colors of the palette
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DebugColors {
+    selection: Option<Rgb>,
+    hovered: Option<Rgb>,
+    bounds: Option<Rgb>,
+    axes: Option<Exactly<Rgb, 3>>,
+    frustum: Option<Rgb>,
+    ray: Option<Rgb>,
+    lods: Option<Exactly<Rgb, 3>>,
+    graph_cpu: Option<Rgb>,
+    graph_gpu: Option<Rgb>,
+    graph_guide: Option<Rgb>,
+    graph_border: Option<Rgb>,
+    text: Option<Rgb>,
+    caution: Option<Rgb>,
+    warning: Option<Rgb>,
+}
+
+impl DebugColors {
+    fn apply(self, palette: &mut DebugPalette) {
+        let Self {
+            selection,
+            hovered,
+            bounds,
+            axes,
+            frustum,
+            ray,
+            lods,
+            graph_cpu,
+            graph_gpu,
+            graph_guide,
+            graph_border,
+            text,
+            caution,
+            warning,
+        } = self;
+        let color = |color: Option<Rgb>, current| color.map_or(current, |Exactly(rgb)| rgb);
+        let colors = |colors: Option<Exactly<Rgb, 3>>, current| {
+            colors.map_or(current, |Exactly(colors)| colors.map(|Exactly(rgb)| rgb))
+        };
+        palette.selection = color(selection, palette.selection);
+        palette.hovered = color(hovered, palette.hovered);
+        palette.bounds = color(bounds, palette.bounds);
+        palette.axes = colors(axes, palette.axes);
+        palette.frustum = color(frustum, palette.frustum);
+        palette.ray = color(ray, palette.ray);
+        palette.lods = colors(lods, palette.lods);
+        palette.graph_cpu = color(graph_cpu, palette.graph_cpu);
+        palette.graph_gpu = color(graph_gpu, palette.graph_gpu);
+        palette.graph_guide = color(graph_guide, palette.graph_guide);
+        palette.graph_border = color(graph_border, palette.graph_border);
+        palette.text = color(text, palette.text);
+        palette.caution = color(caution, palette.caution);
+        palette.warning = color(warning, palette.warning);
+    }
+}
+
+impl ConfigFile {
+    // Unknown keys are reported and skipped so configs written for newer
+    // versions still load
+    pub(super) fn parse(text: &str) -> Result<Self, String> {
+        let deserializer = toml::Deserializer::new(text);
+        serde_ignored::deserialize(deserializer, |path| {
+            warn!("Ignoring unknown key `{path}` in the config file");
+        })
+        .map_err(|err| err.to_string())
+    }
+
+    // Relative asset paths are resolved against `root`
+    pub(super) fn apply(self, config: &mut EngineConfig, root: &Path) {
+        let Self {
+            window,
+            renderer,
+            assets,
+            debug,
+        } = self;
+
+        if let Some(width) = window.width {
+            config.window_width = width;
+        }
+        if let Some(height) = window.height {
+            config.window_height = height;
+        }
+        if let Some(title) = window.title {
+            config.window_title = title;
+        }
+        if let Some(app_id) = window.app_id {
+            config.app_id = app_id;
+        }
+        if let Some(transparent) = window.transparent {
+            config.transparent_window = transparent;
+        }
+        if let Some(fullscreen) = window.fullscreen {
+            match fullscreen.parse() {
+                Ok(display_mode) => config.exclusive_fullscreen = Some(display_mode),
+                Err(err) => warn!("Ignoring window.fullscreen: {err}"),
+            }
+        }
+
+        if let Some(vsync) = renderer.vsync {
+            config.vsync = vsync;
+        }
+        if let Some(msaa) = renderer.msaa {
+            config.msaa_samples = msaa;
+        }
+        if let Some(frames_in_flight) = renderer.frames_in_flight {
+            config.frames_in_flight = frames_in_flight;
+        }
+        if let Some(min_image_count) = renderer.min_image_count {
+            config.min_image_count = Some(min_image_count);
+        }
+        if let Some(driver) = renderer.driver {
+            match parse_driver_id(&driver) {
+                Some(driver_id) => config.driver_preference = Some(driver_id),
+                None => warn!("Ignoring unknown renderer.driver = {driver:?}"),
+            }
+        }
+        if let Some(tick_rate) = renderer.tick_rate {
+            config.tick_rate = tick_rate;
+        }
+        if let Some(fps_limit) = renderer.fps_limit {
+            config.fps_limit = Some(fps_limit);
+        }
+        if let Some(background) = renderer.background {
+            match BackgroundBehavior::parse(&background) {
+                Some(background_behavior) => config.background_behavior = background_behavior,
+                None => warn!("Ignoring unknown renderer.background = {background:?}"),
+            }
+        }
+        if let Some(background_fps) = renderer.background_fps {
+            config.background_fps = background_fps;
+        }
+        if let Some(resize_debounce_ms) = renderer.resize_debounce_ms {
+            config.resize_debounce_ms = resize_debounce_ms;
+        }
+        if let Some(low_latency) = renderer.low_latency {
+            config.low_latency = low_latency;
+        }
+        if let Some(synchronization2) = renderer.synchronization2 {
+            config.synchronization2 = synchronization2;
+        }
+        if let Some(transfer_queue) = renderer.transfer_queue {
+            config.transfer_queue = transfer_queue;
+        }
+        if let Some(bindless) = renderer.bindless {
+            config.bindless = bindless;
+        }
+        if let Some(buffer_device_address) = renderer.buffer_device_address {
+            config.buffer_device_address = buffer_device_address;
+        }
+        if let Some(gpu_culling) = renderer.gpu_culling {
+            config.gpu_culling = gpu_culling;
+        }
+        if let Some(packed_vertices) = renderer.packed_vertices {
+            config.packed_vertices = packed_vertices;
+        }
+        if let Some(lods) = renderer.lods {
+            config.lods = lods;
+        }
+        if let Some(stencil) = renderer.stencil {
+            config.stencil = stencil;
+        }
+        if let Some(picking) = renderer.picking {
+            config.picking = picking;
+        }
+        if let Some(frame_budget_ms) = renderer.frame_budget_ms {
+            config.frame_budget_ms = Some(frame_budget_ms);
+        }
+        if let Some(bloom) = renderer.bloom {
+            config.bloom = bloom;
+        }
+        if let Some(bloom_intensity) = renderer.bloom_intensity {
+            config.bloom_intensity = bloom_intensity;
+        }
+        if let Some(bloom_threshold) = renderer.bloom_threshold {
+            config.bloom_threshold = bloom_threshold;
+        }
+        if let Some(ssao) = renderer.ssao {
+            config.ssao = ssao;
+        }
+        if let Some(ssao_radius) = renderer.ssao_radius {
+            config.ssao_radius = ssao_radius;
+        }
+        if let Some(ssao_bias) = renderer.ssao_bias {
+            config.ssao_bias = ssao_bias;
+        }
+        if let Some(deferred) = renderer.deferred {
+            config.deferred = deferred;
+        }
+        if let Some(seed) = renderer.seed {
+            config.seed = seed;
+        }
+        if let Some(environment_intensity) = renderer.environment_intensity {
+            config.environment_intensity = environment_intensity;
+        }
+
+        if let Some(model) = assets.model {
+            config.model_path = root.join(model);
+        }
+        if let Some(texture) = assets.texture {
+            config.texture_path = root.join(texture);
+        }
+        if let Some(skybox) = assets.skybox {
+            config.skybox_path = Some(root.join(skybox));
+        }
+        if let Some(mesh_cpu_data) = assets.mesh_cpu_data {
+            match CpuMeshData::parse(&mesh_cpu_data) {
+                Some(cpu_data) => config.mesh_cpu_data = cpu_data,
+                None => warn!("Ignoring unknown assets.mesh_cpu_data = {mesh_cpu_data:?}"),
+            }
+        }
+        if let Some(vertex_shader) = assets.vertex_shader {
+            config.vertex_shader = Some(root.join(vertex_shader));
+        }
+        if let Some(fragment_shader) = assets.fragment_shader {
+            config.fragment_shader = Some(root.join(fragment_shader));
+        }
+
+        if let Some(validation) = debug.validation {
+            config.validation = validation;
+        }
+        if let Some(sync_validation) = debug.sync_validation {
+            config.sync_validation = sync_validation;
+        }
+        if let Some(overlay) = debug.overlay {
+            config.overlay = overlay;
+        }
+        if let Some(text_font) = debug.text_font {
+            config.text_font = Some(root.join(text_font));
+        }
+        if let Some(hot_reload) = debug.hot_reload {
+            config.hot_reload = hot_reload;
+        }
+        if let Some(capture_frame) = debug.capture_frame {
+            config.capture_frame = Some(capture_frame);
+        }
+        if let Some(palette) = debug.palette {
+            match DebugPalette::parse(&palette) {
+                Some(debug_palette) => config.debug_palette = debug_palette,
+                None => warn!("Ignoring unknown debug.palette = {palette:?}"),
+            }
+        }
+        debug.colors.apply(&mut config.debug_palette);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn the_example_file_spells_out_the_defaults() {
+        let text = include_str!("../../caterpie.example.toml");
+        let mut config = EngineConfig::default();
+        ConfigFile::parse(text)
+            .unwrap()
+            .apply(&mut config, Path::new(""));
+        assert_eq!(
+            format!("{config:?}"),
+            format!("{:?}", EngineConfig::default())
+        );
+    }
+
+    #[test]
+    fn present_keys_replace_and_missing_keys_keep_the_defaults() {
+        let text = r#"
+            unknown = 1
+
+            [window]
+            width = 640
+
+            [renderer]
+            driver = "radv"
+            background = "pause"
+            seed = 7
+
+            [assets]
+            model = "models/cube.obj"
+
+            [debug.colors]
+            text = [0.5, 0.25, 0.125]
+        "#;
+        let mut config = EngineConfig::default();
+        ConfigFile::parse(text)
+            .unwrap()
+            .apply(&mut config, Path::new("assets"));
+
+        let defaults = EngineConfig::default();
+        assert_eq!(config.window_width, 640);
+        assert_eq!(config.window_height, defaults.window_height);
+        assert!(config.driver_preference.is_some());
+        assert_eq!(config.background_behavior, BackgroundBehavior::Pause);
+        assert_eq!(config.seed, 7);
+        assert_eq!(config.model_path, Path::new("assets/models/cube.obj"));
+        assert_eq!(config.texture_path, defaults.texture_path);
+        assert_eq!(config.debug_palette.text, [0.5, 0.25, 0.125]);
+        assert_eq!(
+            config.debug_palette.selection,
+            defaults.debug_palette.selection
+        );
+    }
+
+    #[test]
+    fn mistyped_values_fail_the_whole_file() {
+        assert!(ConfigFile::parse("[window]\nwidth = \"wide\"").is_err());
+        assert!(ConfigFile::parse("[renderer\nvsync = true").is_err());
+    }
+
+    #[test]
+    fn malformed_palettes_are_skipped_or_rejected() {
+        // Unknown palette names keep the current one
+        let mut config = EngineConfig::default();
+        ConfigFile::parse("[debug]\npalette = \"sepia\"")
+            .unwrap()
+            .apply(&mut config, Path::new(""));
+        assert_eq!(config.debug_palette, DebugPalette::DEFAULT);
+        // Colors need exactly three components, three of them for the axes
+        for colors in [
+            "text = [1.0, 0.0]",
+            "text = [1.0, 0.0, 0.0, 1.0]",
+            "text = \"red\"",
+            "axes = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]",
+            "lods = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0, 1.0]]",
+        ] {
+            let text = format!("[debug.colors]\n{colors}");
+            assert!(ConfigFile::parse(&text).is_err(), "{colors}");
+        }
+    }
+
+    #[test]
+    fn files_override_defaults_and_variables_override_files() {
+        // The only test touching CATERPIE_SEED, so it can't race another
+        let mut config = EngineConfig::default();
+        config
+            .apply_file("[renderer]\nseed = 7\nbloom_intensity = 2.0", Path::new(""))
+            .unwrap();
+        assert_eq!((config.seed, config.bloom_intensity), (7, 2.0));
+
+        env::set_var("CATERPIE_SEED", "11");
+        config.apply_env();
+        env::remove_var("CATERPIE_SEED");
+        assert_eq!((config.seed, config.bloom_intensity), (11, 2.0));
+    }
+}
@@ -0,0 +1,526 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+};
+
+use anyhow::Error;
+use ash::vk::{Buffer, BufferUsageFlags, DeviceMemory, DeviceSize, Image, ImageView, IndexType};
+use log::{debug, info};
+
+use crate::{
+    engine::{
+        mesh::{CpuMeshData, MorphTarget, Primitive},
+        raycast::{Bvh, Ray, BVH_MIN_TRIANGLES},
+        scene::Aabb,
+    },
+    logging,
+    utils::embedded::RgbaImage,
+};
+
+use super::{
+    buffer_types::{
+        gpu_buffer::GpuBuffer,
+        vertex::{PackedVertex, Vertex, VertexLayout},
+    },
+    deletion_queue::{DeletionQueue, PendingDeletion},
+    mesh::{Mesh, RetainedMesh},
+    morph::MorphTargets,
+    textures::{ColorSpaceHint, DecodedImage},
+    Configuration,
+};
+
+// Levels of detail per mesh, the full one included
+pub(super) const MAX_LODS: usize = 4;
+
+// A sampled texture on the GPU, destroyed through the deletion queue once the
+// last handle is dropped
+#[derive(Debug)]
+pub struct TextureResource {
+    pub(super) image: Image,
+    pub(super) memory: DeviceMemory,
+    pub(super) view: ImageView,
+    pub(super) size: DeviceSize,
+    pub(super) deletion_queue: DeletionQueue,
+}
+
+impl TextureResource {
+    pub fn view(&self) -> ImageView {
+        self.view
+    }
+
+    // Bytes of pixel data uploaded
+    pub fn size(&self) -> DeviceSize {
+        self.size
+    }
+}
+
+impl Drop for TextureResource {
+    fn drop(&mut self) {
+        self.deletion_queue.push(PendingDeletion::Image {
+            image: self.image,
+            memory: self.memory,
+            view: self.view,
+        });
+    }
+}
+
+// A mesh with its vertex and index buffers. The buffers are kept as raw
+// handles since they are never mapped again and only the queue frees them
+pub struct MeshResource {
+    // What `CpuMeshData` kept after the upload
+    mesh: RetainedMesh,
+    // For CPU ray casts, built at load time unless the CPU copy is discarded
+    bounds: Option<Aabb>,
+    bvh: Option<Bvh>,
+    morph_targets: Option<MorphTargets>,
+    vertex_buffer: (Buffer, DeviceMemory),
+    // A kept CPU copy always has the full vertices
+    vertex_layout: VertexLayout,
+    vertex_count: usize,
+    index_buffer: (Buffer, DeviceMemory),
+    index_type: IndexType,
+    index_count: u32,
+    // First index and index count of every level of detail, the full mesh
+    // first. The simplified levels follow it in the index buffer
+    lods: Vec<(u32, u32)>,
+    size: DeviceSize,
+    deletion_queue: DeletionQueue,
+}
+
+impl MeshResource {
+    pub fn vertex_buffer(&self) -> Buffer {
+        self.vertex_buffer.0
+    }
+
+    pub fn vertex_layout(&self) -> VertexLayout {
+        self.vertex_layout
+    }
+
+    pub fn index_buffer(&self) -> Buffer {
+        self.index_buffer.0
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    pub fn lod_count(&self) -> usize {
+        self.lods.len()
+    }
+
+    // The index range of `level`, clamped to the coarsest one
+    pub fn lod(&self, level: usize) -> (u32, u32) {
+        self.lods[level.min(self.lods.len() - 1)]
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_count
+    }
+
+    pub fn cpu_data(&self) -> CpuMeshData {
+        self.mesh.cpu_data()
+    }
+
+    // Bytes of the CPU copy kept after the upload
+    pub fn cpu_size(&self) -> usize {
+        self.mesh.size()
+    }
+
+    pub fn morph_targets(&self) -> Option<MorphTargets> {
+        self.morph_targets
+    }
+
+    // Model space, None for meshes without vertices
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    pub fn size(&self) -> DeviceSize {
+        self.size
+    }
+
+    // Distance along the model space `ray` to the closest triangle facing
+    // it. Misses of the bounds skip the triangles, big meshes go through
+    // their BVH. Without a CPU copy the bounds are hit instead
+    pub fn raycast(&self, ray: &Ray) -> Option<f32> {
+        let box_distance = ray.intersect_aabb(self.bounds.as_ref()?, f32::INFINITY)?;
+        match (&self.bvh, &self.mesh) {
+            (Some(bvh), _) => bvh.intersect(ray),
+            (None, RetainedMesh::Discarded) => Some(box_distance),
+            (None, mesh) => mesh
+                .triangles()
+                .iter()
+                .filter_map(|triangle| ray.intersect_triangle(triangle))
+                .min_by(f32::total_cmp),
+        }
+    }
+}
+
+impl Drop for MeshResource {
+    fn drop(&mut self) {
+        for (buffer, memory) in [self.vertex_buffer, self.index_buffer] {
+            self.deletion_queue
+                .push(PendingDeletion::Buffer { buffer, memory });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AssetStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub live_textures: usize,
+    pub live_meshes: usize,
+    // GPU memory held by the live textures and meshes
+    pub live_bytes: u64,
+    // Mesh data the live meshes kept on the CPU
+    pub retained_cpu_bytes: u64,
+}
+
+// Hands out shared handles so an asset requested twice is only loaded and
+// uploaded once. Only weak references are kept, dropping the last handle
+// frees the asset
+#[derive(Default)]
+pub struct AssetCache {
+    // The same file read in both color spaces is two different images
+    textures: HashMap<(PathBuf, ColorSpaceHint), Weak<TextureResource>>,
+    meshes: HashMap<PathBuf, Weak<MeshResource>>,
+    // Primitives are compared by their parameters, there are only ever a few
+    primitives: Vec<(Primitive, Weak<MeshResource>)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl AssetCache {
+    pub(super) fn texture(
+        &mut self,
+        path: &Path,
+        color_space: ColorSpaceHint,
+    ) -> Option<Arc<TextureResource>> {
+        let texture = self
+            .textures
+            .get(&(path.to_path_buf(), color_space))
+            .and_then(Weak::upgrade);
+        self.count(texture.is_some());
+        texture
+    }
+
+    // Misses meshes that kept less than `cpu_data`
+    fn mesh(&mut self, path: &Path, cpu_data: CpuMeshData) -> Option<Arc<MeshResource>> {
+        let mesh = self
+            .meshes
+            .get(path)
+            .and_then(Weak::upgrade)
+            .filter(|mesh| mesh.cpu_data().covers(cpu_data));
+        self.count(mesh.is_some());
+        mesh
+    }
+
+    fn primitive(
+        &mut self,
+        primitive: &Primitive,
+        cpu_data: CpuMeshData,
+    ) -> Option<Arc<MeshResource>> {
+        let mesh = self
+            .primitives
+            .iter()
+            .filter(|(cached, _)| cached == primitive)
+            .filter_map(|(_, mesh)| mesh.upgrade())
+            .find(|mesh| mesh.cpu_data().covers(cpu_data));
+        self.count(mesh.is_some());
+        mesh
+    }
+
+    pub(super) fn insert_texture(
+        &mut self,
+        path: &Path,
+        color_space: ColorSpaceHint,
+        texture: &Arc<TextureResource>,
+    ) {
+        self.textures
+            .insert((path.to_path_buf(), color_space), Arc::downgrade(texture));
+    }
+
+    fn count(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+
+    pub fn stats(&self) -> AssetStats {
+        let textures = self
+            .textures
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<Arc<TextureResource>>>();
+        let meshes = self
+            .meshes
+            .values()
+            .chain(self.primitives.iter().map(|(_, mesh)| mesh))
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<Arc<MeshResource>>>();
+        AssetStats {
+            hits: self.hits,
+            misses: self.misses,
+            live_textures: textures.len(),
+            live_meshes: meshes.len(),
+            live_bytes: textures.iter().map(|texture| texture.size()).sum::<u64>()
+                + meshes.iter().map(|mesh| mesh.size()).sum::<u64>(),
+            retained_cpu_bytes: meshes.iter().map(|mesh| mesh.cpu_size() as u64).sum(),
+        }
+    }
+}
+
+impl Configuration {
+    pub fn load_texture(
+        &mut self,
+        path: &Path,
+        color_space: ColorSpaceHint,
+    ) -> Result<Arc<TextureResource>, Error> {
+        if let Some(texture) = self.asset_cache.texture(path, color_space) {
+            debug!(target: logging::UPLOAD, "Texture cache hit: {}", path.display());
+            return Ok(texture);
+        }
+        let texture = Arc::new(self.upload_texture(path, color_space)?);
+        self.asset_cache.insert_texture(path, color_space, &texture);
+        Ok(texture)
+    }
+
+    // Uploads an image already decoded from `path` and caches it under that
+    // path
+    pub(super) fn insert_decoded_texture(
+        &mut self,
+        path: &Path,
+        color_space: ColorSpaceHint,
+        image: DecodedImage,
+    ) -> Result<Arc<TextureResource>, Error> {
+        let supported_formats = self.supported_texture_formats();
+        let image = image.select_format(path, color_space, &supported_formats)?;
+        let texture = Arc::new(self.upload_decoded(&image)?);
+        self.asset_cache.insert_texture(path, color_space, &texture);
+        Ok(texture)
+    }
+
+    pub fn create_texture(
+        &mut self,
+        image: &RgbaImage,
+        color_space: ColorSpaceHint,
+    ) -> Result<Arc<TextureResource>, Error> {
+        Ok(Arc::new(self.upload_rgba(image, color_space)?))
+    }
+
+    pub fn load_mesh(
+        &mut self,
+        path: &Path,
+        cpu_data: CpuMeshData,
+    ) -> Result<Arc<MeshResource>, Error> {
+        if let Some(mesh) = self.asset_cache.mesh(path, cpu_data) {
+            debug!(target: logging::UPLOAD, "Mesh cache hit: {}", path.display());
+            return Ok(mesh);
+        }
+        self.insert_mesh(path, Self::read_mesh(path)?, cpu_data)
+    }
+
+    // Uploads a mesh already read from `path` and caches it under that path
+    pub(super) fn insert_mesh(
+        &mut self,
+        path: &Path,
+        mesh: Mesh,
+        cpu_data: CpuMeshData,
+    ) -> Result<Arc<MeshResource>, Error> {
+        let mesh = self.upload_mesh(mesh, None, cpu_data)?;
+        self.asset_cache
+            .meshes
+            .insert(path.to_path_buf(), Arc::downgrade(&mesh));
+        Ok(mesh)
+    }
+
+    pub fn load_primitive(
+        &mut self,
+        primitive: Primitive,
+        cpu_data: CpuMeshData,
+    ) -> Result<Arc<MeshResource>, Error> {
+        if let Some(mesh) = self.asset_cache.primitive(&primitive, cpu_data) {
+            debug!(target: logging::UPLOAD, "Mesh cache hit: {primitive:?}");
+            return Ok(mesh);
+        }
+        let (vertices, indices) = primitive.generate();
+        let mesh = self.upload_mesh(Mesh::new(vertices, indices), None, cpu_data)?;
+        self.asset_cache
+            .primitives
+            .retain(|(_, mesh)| mesh.strong_count() > 0);
+        self.asset_cache
+            .primitives
+            .push((primitive, Arc::downgrade(&mesh)));
+        Ok(mesh)
+    }
+
+    // Not cached, every call uploads its own copy
+    pub fn load_geometry(
+        &mut self,
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        morph_targets: &[MorphTarget],
+        cpu_data: CpuMeshData,
+    ) -> Result<Arc<MeshResource>, Error> {
+        let morph_targets = self.upload_morph_targets(vertices.len(), morph_targets)?;
+        self.upload_mesh(Mesh::new(vertices, indices), morph_targets, cpu_data)
+    }
+
+    fn upload_mesh(
+        &self,
+        mesh: Mesh,
+        morph_targets: Option<MorphTargets>,
+        cpu_data: CpuMeshData,
+    ) -> Result<Arc<MeshResource>, Error> {
+        let (vertex_layout, vertex_size, vertex_buffer) = if self.packs_vertices() {
+            let layout = VertexLayout::packed_for(&mesh.vertices);
+            let vertices = mesh
+                .vertices
+                .iter()
+                .map(|vertex| PackedVertex::new(vertex, layout))
+                .collect::<Vec<PackedVertex>>();
+            let buffer = GpuBuffer::device_local(
+                &self.gpu_context(),
+                &vertices,
+                BufferUsageFlags::VERTEX_BUFFER,
+            )?;
+            (layout, buffer.size(), buffer.into_raw())
+        } else {
+            let buffer = GpuBuffer::device_local(
+                &self.gpu_context(),
+                &mesh.vertices,
+                BufferUsageFlags::VERTEX_BUFFER,
+            )?;
+            (VertexLayout::Full, buffer.size(), buffer.into_raw())
+        };
+        let simplified = if self.config.lods {
+            mesh.lods(MAX_LODS - 1)
+        } else {
+            Vec::new()
+        };
+        let mut lods = vec![(0, mesh.indices.len() as u32)];
+        for indices in &simplified {
+            let (first, count) = lods[lods.len() - 1];
+            lods.push((first + count, indices.len() as u32));
+        }
+        let index_buffer = GpuBuffer::device_local(
+            &self.gpu_context(),
+            mesh.indices.with_lods(&simplified).as_bytes(),
+            BufferUsageFlags::INDEX_BUFFER,
+        )?;
+        let size = vertex_size + index_buffer.size();
+        info!(
+            target: logging::UPLOAD,
+            "Mesh buffers have been created ({size} bytes, {vertex_layout:?} vertices)"
+        );
+        if lods.len() > 1 {
+            debug!(
+                target: logging::UPLOAD,
+                "Mesh LOD triangles: {:?}",
+                lods.iter().map(|(_, count)| count / 3).collect::<Vec<u32>>()
+            );
+        }
+        let bvh = if cpu_data == CpuMeshData::Discard {
+            None
+        } else {
+            let triangles = mesh.triangles();
+            (triangles.len() >= BVH_MIN_TRIANGLES)
+                .then(|| Bvh::build(triangles))
+                .flatten()
+        };
+        let (bounds, vertex_count) = (mesh.bounds(), mesh.vertices.len());
+        let (index_type, index_count) = (mesh.index_type(), mesh.indices.len() as u32);
+        let mesh = RetainedMesh::new(mesh, cpu_data);
+        debug!(
+            target: logging::UPLOAD,
+            "Mesh CPU copy: {cpu_data:?}, {} bytes",
+            mesh.size()
+        );
+        Ok(Arc::new(MeshResource {
+            mesh,
+            bounds,
+            bvh,
+            morph_targets,
+            vertex_buffer,
+            vertex_layout,
+            vertex_count,
+            index_buffer: index_buffer.into_raw(),
+            index_type,
+            index_count,
+            lods,
+            size,
+            deletion_queue: self.deletion_queue.clone(),
+        }))
+    }
+
+    pub fn asset_stats(&self) -> AssetStats {
+        self.asset_cache.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Handle, PipelineStageFlags, Semaphore};
+
+    use super::*;
+    use crate::engine::gpu_device::{mock::MockGpuDevice, GpuDevice};
+
+    #[test]
+    fn dropping_the_last_handle_defers_the_free() {
+        let gpu = MockGpuDevice::default();
+        let queue = DeletionQueue::default();
+        let mut cache = AssetCache::default();
+        let path = Path::new("brick.png");
+        let texture = Arc::new(TextureResource {
+            image: Image::from_raw(1),
+            memory: DeviceMemory::from_raw(2),
+            view: ImageView::from_raw(3),
+            size: 64,
+            deletion_queue: queue.clone(),
+        });
+        cache.insert_texture(path, ColorSpaceHint::Srgb, &texture);
+        let shared = cache.texture(path, ColorSpaceHint::Srgb).unwrap();
+
+        // Frame 0 draws with the texture and is still in flight
+        let slot = MockGpuDevice::slot(0);
+        gpu.reset_fences(&[slot.in_flight]).unwrap();
+        gpu.submit(
+            slot.command_buffer,
+            Semaphore::null(),
+            PipelineStageFlags::ALL_COMMANDS,
+            Semaphore::null(),
+            slot.in_flight,
+        );
+
+        let mut destroyed = Vec::new();
+        let mut flush = |frame: usize| {
+            queue.flush_with(frame, 2, |deletion| {
+                assert!(!gpu.is_pending(slot.in_flight));
+                destroyed.push(deletion);
+            })
+        };
+        drop(texture);
+        flush(1);
+        assert_eq!(cache.stats().live_textures, 1);
+
+        drop(shared);
+        assert!(cache.texture(path, ColorSpaceHint::Srgb).is_none());
+        assert_eq!(cache.stats().live_bytes, 0);
+        // Parked on slot 0, freed once its fence has been waited on
+        flush(1);
+        gpu.wait_for_fences(&[slot.in_flight]).unwrap();
+        flush(0);
+        assert!(matches!(
+            destroyed[..],
+            [PendingDeletion::Image { image, .. }] if image == Image::from_raw(1)
+        ));
+    }
+}
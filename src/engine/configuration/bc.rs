@@ -0,0 +1,151 @@
+use std::io::{Error, ErrorKind};
+
+use ash::vk::Format;
+
+use super::textures::{block_layout, DecodedImage, MipLevel};
+
+type Block = [[u8; 4]; 16];
+
+fn rgb565(color: u16) -> [u8; 4] {
+    let r = ((color >> 11) & 0x1f) as u8;
+    let g = ((color >> 5) & 0x3f) as u8;
+    let b = (color & 0x1f) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+        0xff,
+    ]
+}
+
+fn mix(a: [u8; 4], b: [u8; 4], weight_a: u32, weight_b: u32) -> [u8; 4] {
+    let total = weight_a + weight_b;
+    std::array::from_fn(|i| ((a[i] as u32 * weight_a + b[i] as u32 * weight_b) / total) as u8)
+}
+
+// BC3 color blocks always use four colors, BC1 switches to three and
+// transparent black when the first endpoint isn't the larger one
+fn decode_color(block: &[u8], four_colors: bool, out: &mut Block) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let palette = if four_colors || c0 > c1 {
+        [e0, e1, mix(e0, e1, 2, 1), mix(e0, e1, 1, 2)]
+    } else {
+        [e0, e1, mix(e0, e1, 1, 1), [0, 0, 0, 0]]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, pixel) in out.iter_mut().enumerate() {
+        *pixel = palette[(indices >> (2 * i) & 0b11) as usize];
+    }
+}
+
+// The single channel block shared by BC3 alpha and both BC5 channels
+fn decode_channel(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i as usize + 1] = (((7 - i) * a0 + i * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i as usize + 1] = (((5 - i) * a0 + i * a1) / 5) as u8;
+        }
+        palette[7] = 0xff;
+    }
+    let indices = block[2..8]
+        .iter()
+        .rev()
+        .fold(0u64, |bits, byte| (bits << 8) | *byte as u64);
+    std::array::from_fn(|i| palette[(indices >> (3 * i) & 0b111) as usize])
+}
+
+fn decode_block(format: Format, block: &[u8], out: &mut Block) -> Result<(), Error> {
+    match format {
+        Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGB_SRGB_BLOCK => {
+            decode_color(block, false, out);
+            out.iter_mut().for_each(|pixel| pixel[3] = 0xff);
+        }
+        Format::BC1_RGBA_UNORM_BLOCK | Format::BC1_RGBA_SRGB_BLOCK => {
+            decode_color(block, false, out)
+        }
+        Format::BC3_UNORM_BLOCK | Format::BC3_SRGB_BLOCK => {
+            decode_color(&block[8..], true, out);
+            let alpha = decode_channel(&block[..8]);
+            out.iter_mut()
+                .zip(alpha)
+                .for_each(|(pixel, alpha)| pixel[3] = alpha);
+        }
+        Format::BC5_UNORM_BLOCK => {
+            let red = decode_channel(&block[..8]);
+            let green = decode_channel(&block[8..]);
+            for (i, pixel) in out.iter_mut().enumerate() {
+                *pixel = [red[i], green[i], 0, 0xff];
+            }
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{format:?} can't be decompressed, the device needs BC support for it"),
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn is_srgb(format: Format) -> bool {
+    matches!(
+        format,
+        Format::BC1_RGB_SRGB_BLOCK
+            | Format::BC1_RGBA_SRGB_BLOCK
+            | Format::BC3_SRGB_BLOCK
+            | Format::BC7_SRGB_BLOCK
+    )
+}
+
+// For devices that can't sample the block compressed format, keeps the mip
+// levels of the original
+pub(super) fn decompress(image: &DecodedImage) -> Result<DecodedImage, Error> {
+    let (_, block_size) = block_layout(image.format);
+    let format = if is_srgb(image.format) {
+        Format::R8G8B8A8_SRGB
+    } else {
+        Format::R8G8B8A8_UNORM
+    };
+    let mut data = Vec::new();
+    let mut levels = Vec::new();
+    let mut block = Block::default();
+    for level in &image.levels {
+        let offset = data.len();
+        let stride = level.width as usize * 4;
+        data.resize(offset + stride * level.height as usize, 0);
+        let compressed = &image.data[level.offset..level.offset + level.size(image.format)];
+        let blocks_wide = level.width.div_ceil(4) as usize;
+        for (index, bytes) in compressed.chunks_exact(block_size as usize).enumerate() {
+            decode_block(image.format, bytes, &mut block)?;
+            let (block_x, block_y) = (index % blocks_wide * 4, index / blocks_wide * 4);
+            for (i, pixel) in block.iter().enumerate() {
+                let (x, y) = (block_x + i % 4, block_y + i / 4);
+                if x < level.width as usize && y < level.height as usize {
+                    let start = offset + y * stride + x * 4;
+                    data[start..start + 4].copy_from_slice(pixel);
+                }
+            }
+        }
+        levels.push(MipLevel {
+            offset,
+            width: level.width,
+            height: level.height,
+        });
+    }
+    Ok(DecodedImage {
+        width: image.width,
+        height: image.height,
+        format,
+        data,
+        levels,
+    })
+}
@@ -0,0 +1,263 @@
+use std::sync::{Arc, Weak};
+
+use anyhow::{anyhow, Error};
+use ash::{
+    vk::{
+        BufferUsageFlags, DescriptorBindingFlags, DescriptorBufferInfo, DescriptorImageInfo,
+        DescriptorPool, DescriptorPoolCreateFlags, DescriptorPoolCreateInfo, DescriptorPoolSize,
+        DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayoutBinding,
+        DescriptorSetVariableDescriptorCountAllocateInfo, DescriptorType, ImageLayout,
+        PhysicalDeviceDescriptorIndexingFeatures, PhysicalDeviceDescriptorIndexingProperties,
+        PhysicalDeviceFeatures2, PhysicalDeviceProperties2, WriteDescriptorSet, WHOLE_SIZE,
+    },
+    Device,
+};
+use log::{debug, info};
+
+use super::{
+    asset_cache::TextureResource, buffer_types::gpu_buffer::GpuBuffer, materials::GpuMaterial,
+    Configuration,
+};
+use crate::{engine::error::VkContext, logging};
+
+// Upper bound of the texture array, lowered to what the device allows
+const MAX_BINDLESS_TEXTURES: u32 = 4096;
+// Left to the other sets of the pipeline layout
+const RESERVED_SAMPLED_IMAGES: u32 = 16;
+pub(super) const MAX_BINDLESS_MATERIALS: usize = 1024;
+// material.glsl's set 1 with BINDLESS defined
+pub(super) const BINDLESS_DESCRIPTOR_BINDINGS: [(u32, DescriptorType); 2] = [
+    (0, DescriptorType::STORAGE_BUFFER),
+    (1, DescriptorType::COMBINED_IMAGE_SAMPLER),
+];
+const TEXTURE_BINDING: u32 = 1;
+
+// Every material's factors in one storage buffer and every material texture
+// in one descriptor array, bound once per frame. Slots are only ever
+// appended, so frames in flight never see one change under them
+pub(super) struct BindlessTable {
+    pool: DescriptorPool,
+    pub set: DescriptorSet,
+    capacity: u32,
+    textures: Vec<Weak<TextureResource>>,
+    materials: GpuBuffer<GpuMaterial>,
+    material_count: usize,
+}
+
+// Lets the features below be enabled at device creation
+pub(super) fn descriptor_indexing_features() -> PhysicalDeviceDescriptorIndexingFeatures<'static> {
+    PhysicalDeviceDescriptorIndexingFeatures::default()
+        .runtime_descriptor_array(true)
+        .shader_sampled_image_array_non_uniform_indexing(true)
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_variable_descriptor_count(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+        .descriptor_binding_update_unused_while_pending(true)
+}
+
+// The descriptor binding flags of a reflected set 1, the texture array is the
+// variable count binding
+pub(super) fn binding_flags(
+    bindings: &[DescriptorSetLayoutBinding],
+) -> Vec<DescriptorBindingFlags> {
+    bindings
+        .iter()
+        .map(|binding| {
+            if binding.binding == TEXTURE_BINDING {
+                DescriptorBindingFlags::PARTIALLY_BOUND
+                    | DescriptorBindingFlags::UPDATE_AFTER_BIND
+                    | DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING
+                    | DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+            } else {
+                DescriptorBindingFlags::empty()
+            }
+        })
+        .collect()
+}
+
+impl Configuration {
+    // Textures the bindless array can hold, None when the extension or one
+    // of the features is missing or bindless is turned off
+    pub(super) fn supported_bindless_capacity(&self) -> Option<u32> {
+        if !self.config.bindless {
+            return None;
+        }
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let extensions = unsafe {
+            instance
+                .enumerate_device_extension_properties(physical_device)
+                .ok()?
+        };
+        let required = [
+            ash::ext::descriptor_indexing::NAME,
+            ash::khr::maintenance3::NAME,
+        ];
+        if !required.iter().all(|name| {
+            extensions
+                .iter()
+                .any(|property| property.extension_name_as_c_str() == Ok(*name))
+        }) {
+            debug!("VK_EXT_descriptor_indexing is not available");
+            return None;
+        }
+
+        let properties2 = ash::khr::get_physical_device_properties2::Instance::new(
+            self.vulkan_entry.as_ref().unwrap(),
+            instance,
+        );
+        let mut indexing_features = PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut features = PhysicalDeviceFeatures2::default().push_next(&mut indexing_features);
+        unsafe { properties2.get_physical_device_features2(physical_device, &mut features) };
+        let supported = [
+            indexing_features.runtime_descriptor_array,
+            indexing_features.shader_sampled_image_array_non_uniform_indexing,
+            indexing_features.descriptor_binding_partially_bound,
+            indexing_features.descriptor_binding_variable_descriptor_count,
+            indexing_features.descriptor_binding_sampled_image_update_after_bind,
+            indexing_features.descriptor_binding_update_unused_while_pending,
+        ]
+        .iter()
+        .all(|feature| *feature != 0);
+        if !supported {
+            debug!("Descriptor indexing lacks the features bindless textures need");
+            return None;
+        }
+
+        let mut indexing_properties = PhysicalDeviceDescriptorIndexingProperties::default();
+        let mut properties =
+            PhysicalDeviceProperties2::default().push_next(&mut indexing_properties);
+        unsafe { properties2.get_physical_device_properties2(physical_device, &mut properties) };
+        let limit = indexing_properties
+            .max_per_stage_descriptor_update_after_bind_sampled_images
+            .min(indexing_properties.max_descriptor_set_update_after_bind_sampled_images);
+        let capacity = MAX_BINDLESS_TEXTURES.min(limit.saturating_sub(RESERVED_SAMPLED_IMAGES));
+        (capacity > 0).then_some(capacity)
+    }
+
+    pub(super) fn create_bindless_table(&self, capacity: u32) -> Result<BindlessTable, Error> {
+        let device = self.device.as_ref().unwrap();
+        let pool_sizes = [
+            DescriptorPoolSize::default()
+                .ty(DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1),
+            DescriptorPoolSize::default()
+                .ty(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(capacity),
+        ];
+        let pool_create_info = DescriptorPoolCreateInfo::default()
+            .flags(DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .pool_sizes(&pool_sizes)
+            .max_sets(1);
+        let pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_create_info, None)
+                .vk_ctx(
+                    "vkCreateDescriptorPool",
+                    format_args!("creating a bindless table of {capacity} textures"),
+                )?
+        };
+        let counts = [capacity];
+        let mut variable_count =
+            DescriptorSetVariableDescriptorCountAllocateInfo::default().descriptor_counts(&counts);
+        let layouts = [self.descriptor_set_layout[1]];
+        let allocate_info = DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts)
+            .push_next(&mut variable_count);
+        let set = unsafe { device.allocate_descriptor_sets(&allocate_info) }
+            .inspect_err(|_| unsafe { device.destroy_descriptor_pool(pool, None) })
+            .vk_ctx(
+                "vkAllocateDescriptorSets",
+                format_args!("creating a bindless table of {capacity} textures"),
+            )?[0];
+        let materials = GpuBuffer::host_visible(
+            &self.gpu_context(),
+            MAX_BINDLESS_MATERIALS,
+            BufferUsageFlags::STORAGE_BUFFER,
+        )
+        .map_err(Error::msg)?;
+        let buffer_info = [DescriptorBufferInfo::default()
+            .buffer(materials.buffer())
+            .offset(0)
+            .range(WHOLE_SIZE)];
+        let write = [WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(0)
+            .descriptor_type(DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_info)];
+        unsafe { device.update_descriptor_sets(&write, &[]) };
+        info!(
+            target: logging::UPLOAD,
+            "Bindless table has been created ({capacity} textures, {MAX_BINDLESS_MATERIALS} materials)"
+        );
+        Ok(BindlessTable {
+            pool,
+            set,
+            capacity,
+            textures: Vec::new(),
+            materials,
+            material_count: 0,
+        })
+    }
+
+    // The texture's slot, written into the array the first time it is seen
+    pub(super) fn register_texture(
+        &self,
+        table: &mut BindlessTable,
+        texture: &Arc<TextureResource>,
+    ) -> Result<u32, Error> {
+        if let Some(slot) = table
+            .textures
+            .iter()
+            .position(|registered| std::ptr::eq(registered.as_ptr(), Arc::as_ptr(texture)))
+            .filter(|slot| table.textures[*slot].strong_count() > 0)
+        {
+            return Ok(slot as u32);
+        }
+        let slot = table.textures.len() as u32;
+        if slot >= table.capacity {
+            return Err(anyhow!(
+                "All {} bindless texture slots are taken",
+                table.capacity
+            ));
+        }
+        let image_info = [DescriptorImageInfo::default()
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(texture.view())
+            .sampler(self.texture_sampler)];
+        let write = [WriteDescriptorSet::default()
+            .dst_set(table.set)
+            .dst_binding(TEXTURE_BINDING)
+            .dst_array_element(slot)
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info)];
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .update_descriptor_sets(&write, &[])
+        };
+        table.textures.push(Arc::downgrade(texture));
+        Ok(slot)
+    }
+}
+
+impl BindlessTable {
+    // The material's index in the storage buffer
+    pub fn push_material(&mut self, material: GpuMaterial) -> Result<u32, Error> {
+        if self.material_count >= MAX_BINDLESS_MATERIALS {
+            return Err(anyhow!(
+                "All {MAX_BINDLESS_MATERIALS} bindless material slots are taken"
+            ));
+        }
+        let index = self.material_count;
+        self.materials.write_at(index, &[material]);
+        self.material_count += 1;
+        Ok(index as u32)
+    }
+
+    pub fn destroy(self, device: &Device) {
+        unsafe { device.destroy_descriptor_pool(self.pool, None) };
+    }
+}
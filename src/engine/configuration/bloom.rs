@@ -0,0 +1,528 @@
+use anyhow::Error;
+use ash::vk::{
+    AttachmentLoadOp, BlendFactor, BorderColor, CommandBuffer, CompareOp, DescriptorImageInfo,
+    DescriptorSet, DescriptorSetLayout, DescriptorSetLayoutCreateInfo, DescriptorType, Extent2D,
+    Filter, Format, Framebuffer, Image, ImageLayout, ImageUsageFlags, ImageView, Offset2D,
+    Pipeline, PipelineBindPoint, PipelineLayout, PipelineLayoutCreateInfo, Rect2D, RenderPass,
+    RenderPassBeginInfo, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode,
+    ShaderStageFlags, SubpassContents, Viewport, WriteDescriptorSet,
+};
+use log::info;
+
+use crate::engine::error::{EngineError, VkContext};
+
+use super::{
+    descriptor_allocator::DescriptorAllocator,
+    frame_graph::{Access, FrameGraph},
+    render_target::{RenderTarget, FULLSCREEN_SHADER_PATH},
+    shader_reflection::ShaderReflection,
+    surface_context::SurfaceContext,
+    ui_pass::{FramePass, FrameResource},
+    Configuration,
+};
+
+pub(super) const BLOOM_SHADER_PATH: &str = "src/assets/bloom.spv";
+const BLOOM_DESCRIPTOR_BINDINGS: [(u32, DescriptorType); 2] = [
+    (0, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (1, DescriptorType::COMBINED_IMAGE_SAMPLER),
+];
+// Format of the scene target and the mip chain once bloom is enabled
+pub const HDR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+// Targets in the chain, the first is half the surface size. Fewer on small
+// surfaces, a mip never drops below a pixel
+const BLOOM_LEVELS: usize = 6;
+
+// Tweakable while running, both only change push constants
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    // How much of the blurred light is added back onto the scene
+    pub intensity: f32,
+    // Brightness a pixel needs before it starts to glow
+    pub threshold: f32,
+}
+
+// Value of the shader's PASS specialization constant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BloomStage {
+    Extract,
+    Downsample,
+    Upsample,
+    Composite,
+}
+
+const BLOOM_STAGES: [BloomStage; 4] = [
+    BloomStage::Extract,
+    BloomStage::Downsample,
+    BloomStage::Upsample,
+    BloomStage::Composite,
+];
+
+// The shader's push constants: source scale twice, threshold and intensity
+type BloomParams = [f32; 4];
+
+// Pipelines and render passes shared by every surface's chain
+pub struct BloomPass {
+    // Mip targets are overwritten by the extract and downsample passes and
+    // blended onto by the upsample ones, both leave them ready to sample
+    write_pass: RenderPass,
+    blend_pass: RenderPass,
+    // Draws the tonemapped scene into the swapchain image
+    pub composite_pass: RenderPass,
+    descriptor_set_layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+    // One variant of the bloom shader per BloomStage
+    pipelines: [Pipeline; 4],
+    sampler: Sampler,
+    pub settings: BloomSettings,
+}
+
+// A surface's mip chain, rebuilt with its swapchain
+pub struct BloomChain {
+    mips: Vec<RenderTarget>,
+    descriptor_allocator: DescriptorAllocator,
+    // Samples the scene target for the extract pass
+    scene_set: DescriptorSet,
+    // `mip_sets[i]` samples `mips[i]`
+    mip_sets: Vec<DescriptorSet>,
+    // The scene target and the first mip
+    composite_set: DescriptorSet,
+}
+
+impl BloomChain {
+    pub(super) fn mip_image(&self, level: usize) -> Option<Image> {
+        self.mips.get(level).map(|mip| mip.image)
+    }
+}
+
+// Extents of the mip chain of a surface `extent` in size
+fn mip_extents(mut extent: Extent2D) -> Vec<Extent2D> {
+    let mut extents = Vec::with_capacity(BLOOM_LEVELS);
+    while extents.len() < BLOOM_LEVELS && extent.width > 1 && extent.height > 1 {
+        extent = Extent2D {
+            width: extent.width / 2,
+            height: extent.height / 2,
+        };
+        extents.push(extent);
+    }
+    extents
+}
+
+impl Configuration {
+    // The scene render pass and targets switch to HDR_FORMAT with bloom, so
+    // this has to be known before they are created
+    pub(super) fn bloom_requested(&self) -> bool {
+        self.config.bloom && !self.headless()
+    }
+
+    pub fn create_bloom_pass(&mut self) -> Result<&mut Configuration, Error> {
+        if !self.bloom_requested() {
+            return Ok(self);
+        }
+        let mut reflection = ShaderReflection::reflect(
+            self.shader_cache.code(FULLSCREEN_SHADER_PATH)?,
+            ShaderStageFlags::VERTEX,
+        )?;
+        reflection.merge(ShaderReflection::reflect(
+            self.shader_cache.code(BLOOM_SHADER_PATH)?,
+            ShaderStageFlags::FRAGMENT,
+        )?)?;
+        reflection.validate(0, &BLOOM_DESCRIPTOR_BINDINGS)?;
+
+        let write_pass = self.create_color_pass(
+            HDR_FORMAT,
+            AttachmentLoadOp::DONT_CARE,
+            ImageLayout::UNDEFINED,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+        let blend_pass = self.create_color_pass(
+            HDR_FORMAT,
+            AttachmentLoadOp::LOAD,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+        let composite_pass = self.create_color_pass(
+            self.surface_format.unwrap().format,
+            AttachmentLoadOp::DONT_CARE,
+            ImageLayout::UNDEFINED,
+            ImageLayout::PRESENT_SRC_KHR,
+        )?;
+
+        let device = self.device.as_ref().unwrap();
+        let bindings = reflection.set_layout_bindings(0, &[]);
+        let descriptor_set_layout_create_info =
+            DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .vk_ctx("vkCreateDescriptorSetLayout", "creating the bloom pass")?
+        };
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_create_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .vk_ctx("vkCreatePipelineLayout", "creating the bloom pass")?
+        };
+        // Mips are sampled between texels, clamping keeps the edges from
+        // picking up the opposite side
+        let sampler_create_info = SamplerCreateInfo::default()
+            .mag_filter(Filter::LINEAR)
+            .min_filter(Filter::LINEAR)
+            .mipmap_mode(SamplerMipmapMode::NEAREST)
+            .address_mode_u(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(BorderColor::FLOAT_TRANSPARENT_BLACK)
+            .compare_op(CompareOp::ALWAYS)
+            .max_lod(0.0);
+        let sampler = unsafe {
+            device
+                .create_sampler(&sampler_create_info, None)
+                .vk_ctx("vkCreateSampler", "creating the bloom pass")?
+        };
+
+        let vertex_shader_module = self.shader_cache.acquire(device, FULLSCREEN_SHADER_PATH)?;
+        let fragment_shader_module = self.shader_cache.acquire(device, BLOOM_SHADER_PATH)?;
+        let pipelines = BLOOM_STAGES.map(|stage| {
+            let render_pass = match stage {
+                BloomStage::Composite => composite_pass,
+                _ => write_pass,
+            };
+            // Only the upsample variant blends, adding onto what the
+            // downsample left in the target
+            let blend =
+                (stage == BloomStage::Upsample).then_some((BlendFactor::ONE, BlendFactor::ONE));
+            self.create_fullscreen_pipeline(
+                (vertex_shader_module, fragment_shader_module),
+                stage as u32,
+                blend,
+                render_pass,
+                pipeline_layout,
+            )
+        });
+        self.shader_cache.release(vertex_shader_module);
+        self.shader_cache.release(fragment_shader_module);
+        self.shader_cache.purge(device);
+
+        self.bloom = Some(BloomPass {
+            write_pass,
+            blend_pass,
+            composite_pass,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipelines: pipelines
+                .into_iter()
+                .collect::<Result<Vec<Pipeline>, _>>()?
+                .try_into()
+                .unwrap(),
+            sampler,
+            settings: BloomSettings {
+                intensity: self.config.bloom_intensity,
+                threshold: self.config.bloom_threshold,
+            },
+        });
+        info!("Bloom pass has been created!");
+        Ok(self)
+    }
+
+    pub(super) fn destroy_bloom_pass(&mut self) {
+        let Some(bloom) = self.bloom.take() else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            for pipeline in bloom.pipelines {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(bloom.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(bloom.descriptor_set_layout, None);
+            device.destroy_sampler(bloom.sampler, None);
+            for render_pass in [bloom.write_pass, bloom.blend_pass, bloom.composite_pass] {
+                device.destroy_render_pass(render_pass, None);
+            }
+        }
+    }
+
+    pub fn bloom_settings(&self) -> Option<BloomSettings> {
+        self.bloom.as_ref().map(|bloom| bloom.settings)
+    }
+
+    pub fn set_bloom_settings(&mut self, settings: BloomSettings) {
+        if let Some(bloom) = &mut self.bloom {
+            bloom.settings = BloomSettings {
+                intensity: settings.intensity.max(0.0),
+                threshold: settings.threshold.max(0.0),
+            };
+        }
+    }
+
+    // Needs the scene target, so runs after it in create_surface_framebuffers
+    pub(super) fn create_surface_bloom_chain(
+        &self,
+        ctx: &mut SurfaceContext,
+    ) -> Result<(), EngineError> {
+        let (Some(bloom), Some(scene)) = (&self.bloom, &ctx.scene_target) else {
+            return Ok(());
+        };
+        let mips = mip_extents(ctx.extent)
+            .into_iter()
+            .map(|extent| {
+                self.create_render_target(
+                    extent,
+                    HDR_FORMAT,
+                    ImageUsageFlags::SAMPLED,
+                    bloom.write_pass,
+                    None,
+                )
+            })
+            .collect::<Result<Vec<RenderTarget>, EngineError>>()?;
+        if mips.is_empty() {
+            return Ok(());
+        }
+
+        let device = self.device.as_ref().unwrap();
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            mips.len() as u32 + 2,
+            vec![(DescriptorType::COMBINED_IMAGE_SAMPLER, 2.0)],
+        );
+        let layouts = vec![bloom.descriptor_set_layout; mips.len() + 2];
+        let mut sets = descriptor_allocator
+            .allocate(device, &layouts)
+            .vk_ctx("vkAllocateDescriptorSets", "creating the bloom chain")?;
+        let composite_set = sets.pop().unwrap();
+        let scene_set = sets.pop().unwrap();
+        let mip_sets = sets;
+
+        let sources = [(scene_set, scene.view, scene.view)]
+            .into_iter()
+            .chain(
+                mip_sets
+                    .iter()
+                    .zip(&mips)
+                    .map(|(set, mip)| (*set, mip.view, mip.view)),
+            )
+            .chain([(composite_set, scene.view, mips[0].view)]);
+        for (set, source, glow) in sources {
+            self.write_bloom_descriptor_set(bloom, set, source, glow);
+        }
+        ctx.bloom_chain = Some(BloomChain {
+            mips,
+            descriptor_allocator,
+            scene_set,
+            mip_sets,
+            composite_set,
+        });
+        Ok(())
+    }
+
+    fn write_bloom_descriptor_set(
+        &self,
+        bloom: &BloomPass,
+        set: DescriptorSet,
+        source: ImageView,
+        glow: ImageView,
+    ) {
+        let image_infos = [source, glow].map(|view| {
+            [DescriptorImageInfo::default()
+                .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(view)
+                .sampler(bloom.sampler)]
+        });
+        let writes = BLOOM_DESCRIPTOR_BINDINGS.map(|(binding, descriptor_type)| {
+            WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(binding)
+                .descriptor_type(descriptor_type)
+                .image_info(&image_infos[binding as usize])
+        });
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .update_descriptor_sets(&writes, &[])
+        };
+    }
+
+    pub(super) fn destroy_surface_bloom_chain(&self, ctx: &mut SurfaceContext) {
+        let Some(mut chain) = ctx.bloom_chain.take() else {
+            return;
+        };
+        chain
+            .descriptor_allocator
+            .destroy(self.device.as_ref().unwrap());
+        for mip in chain.mips {
+            self.destroy_render_target(mip);
+        }
+    }
+
+    // Extracts the bright parts of the scene target into the first mip,
+    // blurs them down the chain and back up, then composites into the
+    // swapchain image. Each stage is a pass of its own, the graph puts the
+    // barriers between them
+    pub(super) fn add_bloom_passes(
+        &self,
+        graph: &mut FrameGraph<FramePass, FrameResource>,
+        ctx: &SurfaceContext,
+        scene_layout: ImageLayout,
+    ) {
+        let levels = mip_extents(ctx.extent).len();
+        if levels == 0 {
+            return;
+        }
+        let scene = (FrameResource::SceneColor, Access::read(scene_layout));
+        let sample = |level| {
+            (
+                FrameResource::BloomMip(level),
+                Access::read(ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            )
+        };
+        let overwrite = |level| {
+            (
+                FrameResource::BloomMip(level),
+                Access::write(ImageLayout::UNDEFINED)
+                    .leaving(ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            )
+        };
+        let blend = |level| {
+            (
+                FrameResource::BloomMip(level),
+                Access::read_write(ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            )
+        };
+        graph.add_pass(
+            FramePass::Bloom(BloomStage::Extract, 0),
+            &[scene, overwrite(0)],
+        );
+        // Each mip is filtered from the one above it
+        for level in 1..levels {
+            graph.add_pass(
+                FramePass::Bloom(BloomStage::Downsample, level),
+                &[sample(level - 1), overwrite(level)],
+            );
+        }
+        // and on the way back up blended onto the one above it
+        for level in (0..levels - 1).rev() {
+            graph.add_pass(
+                FramePass::Bloom(BloomStage::Upsample, level),
+                &[sample(level + 1), blend(level)],
+            );
+        }
+        graph.add_pass(
+            FramePass::Bloom(BloomStage::Composite, 0),
+            &[
+                scene,
+                sample(0),
+                (
+                    FrameResource::Swapchain,
+                    Access::write(ImageLayout::UNDEFINED).leaving(ImageLayout::PRESENT_SRC_KHR),
+                ),
+            ],
+        );
+    }
+
+    // One stage drawing into mip `level`, the composite into the swapchain
+    // image
+    pub(super) fn cmd_bloom(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        image_index: u32,
+        (stage, level): (BloomStage, usize),
+    ) {
+        let ctx = &self.surfaces[surface_index];
+        let (Some(bloom), Some(chain)) = (&self.bloom, &ctx.bloom_chain) else {
+            return;
+        };
+        let source_scale = match stage {
+            BloomStage::Extract | BloomStage::Composite => self.surface_render_scale(ctx),
+            BloomStage::Downsample | BloomStage::Upsample => 1.0,
+        };
+        let params = [
+            source_scale,
+            source_scale,
+            bloom.settings.threshold,
+            bloom.settings.intensity,
+        ];
+        let (target, source_set) = match stage {
+            BloomStage::Extract => (&chain.mips[level], chain.scene_set),
+            BloomStage::Downsample => (&chain.mips[level], chain.mip_sets[level - 1]),
+            BloomStage::Upsample => (&chain.mips[level], chain.mip_sets[level + 1]),
+            BloomStage::Composite => {
+                self.cmd_bloom_stage(
+                    command_buffer,
+                    stage,
+                    (ctx.swapchain.framebuffers[image_index as usize], ctx.extent),
+                    chain.composite_set,
+                    params,
+                );
+                return;
+            }
+        };
+        self.cmd_bloom_stage(
+            command_buffer,
+            stage,
+            (target.framebuffer, target.extent),
+            source_set,
+            params,
+        );
+    }
+
+    fn cmd_bloom_stage(
+        &self,
+        command_buffer: CommandBuffer,
+        stage: BloomStage,
+        (framebuffer, extent): (Framebuffer, Extent2D),
+        descriptor_set: DescriptorSet,
+        params: BloomParams,
+    ) {
+        let bloom = self.bloom.as_ref().unwrap();
+        let render_pass = match stage {
+            BloomStage::Extract | BloomStage::Downsample => bloom.write_pass,
+            BloomStage::Upsample => bloom.blend_pass,
+            BloomStage::Composite => bloom.composite_pass,
+        };
+        let render_area = Rect2D::default().offset(Offset2D::default()).extent(extent);
+        let viewport = Viewport::default()
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .max_depth(1.0);
+        let render_pass_begin_info = RenderPassBeginInfo::default()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(render_area);
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                bloom.pipelines[stage as usize],
+            );
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                bloom.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                bloom.pipeline_layout,
+                ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&params),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
+}
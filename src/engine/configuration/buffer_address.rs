@@ -0,0 +1,111 @@
+use ash::vk::{
+    CommandBuffer, PhysicalDeviceBufferDeviceAddressFeatures, PhysicalDeviceFeatures2,
+    ShaderStageFlags,
+};
+use log::{debug, info};
+
+use super::Configuration;
+
+// Where the object's address follows the fragment stage's push constants,
+// shader.vert's ObjectAddress block
+const OBJECT_ADDRESS_OFFSET: u32 = 32;
+
+impl Configuration {
+    // VK_KHR_buffer_device_address needs VK_KHR_device_group on Vulkan 1.0,
+    // which in turn needs its instance extension
+    pub(super) fn buffer_device_address_instance_extension(
+        &self,
+    ) -> Option<&'static std::ffi::CStr> {
+        if !self.config.buffer_device_address {
+            return None;
+        }
+        let name = ash::khr::device_group_creation::NAME;
+        let available = unsafe {
+            self.vulkan_entry
+                .as_ref()
+                .unwrap()
+                .enumerate_instance_extension_properties(None)
+                .ok()?
+                .iter()
+                .any(|property| property.extension_name_as_c_str() == Ok(name))
+        };
+        available.then_some(name)
+    }
+
+    pub(super) fn supports_buffer_device_address(&self) -> bool {
+        if !self.config.buffer_device_address
+            || !self.instance_extensions.iter().any(|extension| {
+                extension.as_bytes() == ash::khr::device_group_creation::NAME.to_bytes()
+            })
+        {
+            return false;
+        }
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let Ok(extensions) =
+            (unsafe { instance.enumerate_device_extension_properties(physical_device) })
+        else {
+            return false;
+        };
+        let required = [
+            ash::khr::device_group::NAME,
+            ash::khr::buffer_device_address::NAME,
+        ];
+        if !required.iter().all(|name| {
+            extensions
+                .iter()
+                .any(|property| property.extension_name_as_c_str() == Ok(*name))
+        }) {
+            debug!("VK_KHR_buffer_device_address is not available");
+            return false;
+        }
+
+        let properties2 = ash::khr::get_physical_device_properties2::Instance::new(
+            self.vulkan_entry.as_ref().unwrap(),
+            instance,
+        );
+        let mut address_features = PhysicalDeviceBufferDeviceAddressFeatures::default();
+        let mut features = PhysicalDeviceFeatures2::default().push_next(&mut address_features);
+        unsafe { properties2.get_physical_device_features2(physical_device, &mut features) };
+        address_features.buffer_device_address != 0
+    }
+
+    pub(super) fn enable_buffer_device_address(&mut self) {
+        self.buffer_device_address = Some(ash::khr::buffer_device_address::Device::new(
+            self.instance.as_ref().unwrap(),
+            self.device.as_ref().unwrap(),
+        ));
+        info!("Object data is read through buffer device addresses");
+    }
+
+    // Points the vertex shader at the object's uniform entry, a no-op on the
+    // descriptor path
+    pub(super) fn cmd_push_object_address(
+        &self,
+        command_buffer: CommandBuffer,
+        current_frame: usize,
+        dynamic_offset: u32,
+    ) {
+        if self.buffer_device_address.is_none() {
+            return;
+        }
+        let (Some(address), Some(entries)) = (
+            self.upload_ring
+                .as_ref()
+                .and_then(|ring| ring.device_address(current_frame)),
+            self.uniform_entries.get(current_frame),
+        ) else {
+            return;
+        };
+        let address = address + entries.offset + dynamic_offset as u64;
+        unsafe {
+            self.device.as_ref().unwrap().cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                ShaderStageFlags::VERTEX,
+                OBJECT_ADDRESS_OFFSET,
+                bytemuck::bytes_of(&address),
+            );
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use std::{ffi::c_void, fmt, marker::PhantomData};
+
+use ash::{
+    vk::{
+        Buffer, BufferCopy, BufferCreateInfo, BufferDeviceAddressInfo, BufferUsageFlags,
+        CommandBuffer, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel,
+        CommandBufferUsageFlags, CommandPool, DeviceAddress, DeviceMemory, DeviceSize,
+        FenceCreateInfo, MemoryAllocateFlags, MemoryAllocateFlagsInfo, MemoryAllocateInfo,
+        MemoryMapFlags, MemoryPropertyFlags, PhysicalDevice, PhysicalDeviceType, Queue,
+        SharingMode, SubmitInfo,
+    },
+    Device, Instance,
+};
+use bytemuck::Pod;
+
+use crate::{
+    engine::{
+        configuration::Configuration,
+        error::{ByteSize, EngineError, VkContext},
+    },
+    logging::span,
+};
+
+#[derive(Clone, Copy)]
+pub struct GpuContext<'a> {
+    pub instance: &'a Instance,
+    pub physical_device: PhysicalDevice,
+    pub device: &'a Device,
+    pub command_pool: CommandPool,
+    pub queue: Queue,
+    // Set when VK_KHR_buffer_device_address is enabled, shader visible
+    // buffers are then allocated with an address
+    pub buffer_device_address: Option<&'a ash::khr::buffer_device_address::Device>,
+}
+
+// What shaders can read through a device address
+const ADDRESSABLE_USAGE: BufferUsageFlags = BufferUsageFlags::from_raw(
+    BufferUsageFlags::UNIFORM_BUFFER.as_raw()
+        | BufferUsageFlags::STORAGE_BUFFER.as_raw()
+        | BufferUsageFlags::VERTEX_BUFFER.as_raw()
+        | BufferUsageFlags::INDEX_BUFFER.as_raw(),
+);
+
+// Ends, submits and waits for the command buffer when dropped so early
+// returns can't leak it
+pub struct SingleTimeCommand<'a> {
+    ctx: GpuContext<'a>,
+    command_buffer: CommandBuffer,
+    submitted: bool,
+}
+
+impl SingleTimeCommand<'_> {
+    pub fn command_buffer(&self) -> CommandBuffer {
+        self.command_buffer
+    }
+
+    pub fn submit(mut self) {
+        self.submit_and_wait();
+    }
+
+    fn submit_and_wait(&mut self) {
+        if self.submitted {
+            return;
+        }
+        self.submitted = true;
+
+        let device = self.ctx.device;
+        let command_buffers = [self.command_buffer];
+        let detail = "running a one time command";
+        unsafe {
+            device
+                .end_command_buffer(self.command_buffer)
+                .vk_expect("vkEndCommandBuffer", detail);
+            let fence = device
+                .create_fence(&FenceCreateInfo::default(), None)
+                .vk_expect("vkCreateFence", detail);
+            let submit_info = [SubmitInfo::default().command_buffers(&command_buffers)];
+            device
+                .queue_submit(self.ctx.queue, &submit_info, fence)
+                .vk_expect("vkQueueSubmit", detail);
+            device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .vk_expect("vkWaitForFences", detail);
+            device.destroy_fence(fence, None);
+            device.free_command_buffers(self.ctx.command_pool, &command_buffers);
+        }
+    }
+}
+
+impl Drop for SingleTimeCommand<'_> {
+    fn drop(&mut self) {
+        self.submit_and_wait();
+    }
+}
+
+impl<'a> GpuContext<'a> {
+    pub fn begin_single_time_command(&self) -> SingleTimeCommand<'a> {
+        let command_buffer_allocate_info = CommandBufferAllocateInfo::default()
+            .level(CommandBufferLevel::PRIMARY)
+            .command_pool(self.command_pool)
+            .command_buffer_count(1);
+        let command_buffer_begin_info =
+            CommandBufferBeginInfo::default().flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        let command_buffer = unsafe {
+            let detail = "starting a one time command";
+            let command_buffers = self
+                .device
+                .allocate_command_buffers(&command_buffer_allocate_info)
+                .vk_expect("vkAllocateCommandBuffers", detail);
+            self.device
+                .begin_command_buffer(command_buffers[0], &command_buffer_begin_info)
+                .vk_expect("vkBeginCommandBuffer", detail);
+            command_buffers[0]
+        };
+        SingleTimeCommand {
+            ctx: *self,
+            command_buffer,
+            submitted: false,
+        }
+    }
+
+    fn is_integrated_gpu(&self) -> bool {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+        };
+        properties.device_type == PhysicalDeviceType::INTEGRATED_GPU
+    }
+
+    fn addressable(&self, usage: BufferUsageFlags) -> bool {
+        self.buffer_device_address.is_some() && usage.intersects(ADDRESSABLE_USAGE)
+    }
+
+    // Picks the first of `memory_property_flags` the buffer can live in
+    fn allocate_buffer(
+        &self,
+        size: DeviceSize,
+        usage: BufferUsageFlags,
+        memory_property_flags: &[MemoryPropertyFlags],
+    ) -> Result<(Buffer, DeviceMemory, MemoryPropertyFlags), EngineError> {
+        let addressable = self.addressable(usage);
+        let usage = if addressable {
+            usage | BufferUsageFlags::SHADER_DEVICE_ADDRESS
+        } else {
+            usage
+        };
+        let buffer_create_info = BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(SharingMode::EXCLUSIVE);
+        let detail = format!("allocating a {usage:?} buffer ({})", ByteSize(size));
+        unsafe {
+            let buffer = self
+                .device
+                .create_buffer(&buffer_create_info, None)
+                .vk_ctx("vkCreateBuffer", &detail)?;
+
+            let mem_requirements = self.device.get_buffer_memory_requirements(buffer);
+            let (memory_type_index, properties) = memory_property_flags
+                .iter()
+                .find_map(|properties| {
+                    Configuration::find_memory_type(
+                        self.instance,
+                        self.physical_device,
+                        mem_requirements.memory_type_bits,
+                        *properties,
+                    )
+                    .map(|index| (index, *properties))
+                })
+                .expect("FAILED TO FIND MEMORY TYPE");
+            let mut allocate_flags_info =
+                MemoryAllocateFlagsInfo::default().flags(MemoryAllocateFlags::DEVICE_ADDRESS);
+            let mut memory_alloc_info = MemoryAllocateInfo::default()
+                .allocation_size(mem_requirements.size)
+                .memory_type_index(memory_type_index);
+            if addressable {
+                memory_alloc_info = memory_alloc_info.push_next(&mut allocate_flags_info);
+            }
+
+            let memory = self
+                .device
+                .allocate_memory(&memory_alloc_info, None)
+                .inspect_err(|_| self.device.destroy_buffer(buffer, None))
+                .vk_ctx("vkAllocateMemory", &detail)?;
+            self.device
+                .bind_buffer_memory(buffer, memory, 0)
+                .inspect_err(|_| {
+                    self.device.destroy_buffer(buffer, None);
+                    self.device.free_memory(memory, None);
+                })
+                .vk_ctx("vkBindBufferMemory", &detail)?;
+            Ok((buffer, memory, properties))
+        }
+    }
+
+    // None for buffers allocated without an address
+    fn device_address(&self, buffer: Buffer, usage: BufferUsageFlags) -> Option<DeviceAddress> {
+        let loader = self
+            .buffer_device_address
+            .filter(|_| self.addressable(usage))?;
+        let info = BufferDeviceAddressInfo::default().buffer(buffer);
+        Some(unsafe { loader.get_buffer_device_address(&info) })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuBufferError {
+    Empty { usage: BufferUsageFlags },
+    // Always EngineError::Vulkan
+    Vulkan(Box<EngineError>),
+}
+
+impl fmt::Display for GpuBufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuBufferError::Empty { usage } => {
+                write!(f, "Refusing to create a zero sized {:?} buffer", usage)
+            }
+            GpuBufferError::Vulkan(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<EngineError> for GpuBufferError {
+    fn from(err: EngineError) -> Self {
+        GpuBufferError::Vulkan(Box::new(err))
+    }
+}
+
+impl std::error::Error for GpuBufferError {}
+
+pub struct GpuBuffer<T: Pod> {
+    device: Option<Device>,
+    buffer: Buffer,
+    memory: DeviceMemory,
+    properties: MemoryPropertyFlags,
+    mapped: Option<*mut c_void>,
+    len: usize,
+    size: DeviceSize,
+    address: Option<DeviceAddress>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> Default for GpuBuffer<T> {
+    fn default() -> Self {
+        Self {
+            device: None,
+            buffer: Buffer::null(),
+            memory: DeviceMemory::null(),
+            properties: MemoryPropertyFlags::empty(),
+            mapped: None,
+            len: 0,
+            size: 0,
+            address: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Pod> GpuBuffer<T> {
+    fn allocate(
+        ctx: &GpuContext,
+        len: usize,
+        usage: BufferUsageFlags,
+        memory_property_flags: &[MemoryPropertyFlags],
+    ) -> Result<Self, GpuBufferError> {
+        let size = (len * size_of::<T>()) as DeviceSize;
+        if size == 0 {
+            return Err(GpuBufferError::Empty { usage });
+        }
+        let (buffer, memory, properties) =
+            ctx.allocate_buffer(size, usage, memory_property_flags)?;
+        Ok(Self {
+            device: Some(ctx.device.clone()),
+            buffer,
+            memory,
+            properties,
+            mapped: None,
+            len,
+            size,
+            address: ctx.device_address(buffer, usage),
+            _marker: PhantomData,
+        })
+    }
+
+    fn map(&mut self, ctx: &GpuContext) -> Result<(), EngineError> {
+        self.mapped = Some(unsafe {
+            ctx.device
+                .map_memory(self.memory, 0, self.size, MemoryMapFlags::empty())
+                .vk_ctx(
+                    "vkMapMemory",
+                    format_args!("mapping a buffer ({})", ByteSize(self.size)),
+                )?
+        });
+        Ok(())
+    }
+
+    pub fn host_visible(
+        ctx: &GpuContext,
+        len: usize,
+        usage: BufferUsageFlags,
+    ) -> Result<Self, GpuBufferError> {
+        let mut gpu_buffer = Self::allocate(
+            ctx,
+            len,
+            usage,
+            &[MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT],
+        )?;
+        gpu_buffer.map(ctx)?;
+        Ok(gpu_buffer)
+    }
+
+    pub fn device_local(
+        ctx: &GpuContext,
+        data: &[T],
+        usage: BufferUsageFlags,
+    ) -> Result<Self, GpuBufferError> {
+        span!("upload_buffer");
+        let host_visible_device_local = MemoryPropertyFlags::DEVICE_LOCAL
+            | MemoryPropertyFlags::HOST_VISIBLE
+            | MemoryPropertyFlags::HOST_COHERENT;
+        let memory_property_flags = if ctx.is_integrated_gpu() {
+            vec![host_visible_device_local, MemoryPropertyFlags::DEVICE_LOCAL]
+        } else {
+            vec![MemoryPropertyFlags::DEVICE_LOCAL]
+        };
+        let mut gpu_buffer = Self::allocate(
+            ctx,
+            data.len(),
+            BufferUsageFlags::TRANSFER_DST | usage,
+            &memory_property_flags,
+        )?;
+
+        // Unified memory can be written directly, no staging copy needed
+        if gpu_buffer.properties.contains(host_visible_device_local) {
+            gpu_buffer.map(ctx)?;
+            gpu_buffer.write(data);
+            gpu_buffer.unmap();
+            return Ok(gpu_buffer);
+        }
+
+        let mut staging = Self::host_visible(ctx, data.len(), BufferUsageFlags::TRANSFER_SRC)?;
+        staging.write(data);
+
+        let command = ctx.begin_single_time_command();
+        let buffer_copy = vec![BufferCopy::default()
+            .src_offset(0)
+            .dst_offset(0)
+            .size(gpu_buffer.size)];
+        unsafe {
+            ctx.device.cmd_copy_buffer(
+                command.command_buffer(),
+                staging.buffer,
+                gpu_buffer.buffer,
+                &buffer_copy,
+            )
+        };
+        // The staging buffer is dropped only after the copy has completed
+        command.submit();
+
+        Ok(gpu_buffer)
+    }
+
+    pub fn write(&mut self, data: &[T]) {
+        self.write_at(0, data);
+    }
+
+    // Leaves the elements before `first` and after the data alone
+    pub fn write_at(&mut self, first: usize, data: &[T]) {
+        let mapped = self
+            .mapped
+            .expect("Tried to write into a buffer that is not host visible");
+        assert!(
+            first + data.len() <= self.len,
+            "Tried to write {} elements from {first} into a buffer of {}",
+            data.len(),
+            self.len
+        );
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                mapped.cast::<u8>().add(first * size_of::<T>()),
+                bytes.len(),
+            );
+        }
+    }
+
+    // Only meaningful once the GPU writes into it have completed
+    pub fn read(&self) -> Vec<T> {
+        let mapped = self
+            .mapped
+            .expect("Tried to read from a buffer that is not host visible");
+        let mut data = vec![T::zeroed(); self.len];
+        let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut data);
+        unsafe {
+            std::ptr::copy_nonoverlapping(mapped.cast::<u8>(), bytes.as_mut_ptr(), bytes.len());
+        }
+        data
+    }
+
+    fn unmap(&mut self) {
+        if let (Some(device), Some(_)) = (&self.device, self.mapped.take()) {
+            unsafe { device.unmap_memory(self.memory) };
+        }
+    }
+
+    pub fn buffer(&self) -> Buffer {
+        self.buffer
+    }
+
+    // Start of the persistent mapping, None unless the buffer is host visible
+    pub fn mapped_ptr(&self) -> Option<*mut u8> {
+        self.mapped.map(|mapped| mapped.cast::<u8>())
+    }
+
+    // Where shaders find the buffer through VK_KHR_buffer_device_address,
+    // None when the device doesn't have it enabled
+    pub fn device_address(&self) -> Option<DeviceAddress> {
+        self.address
+    }
+
+    // Hands the buffer and its memory over to the caller to destroy
+    pub fn into_raw(mut self) -> (Buffer, DeviceMemory) {
+        self.unmap();
+        self.device = None;
+        (self.buffer, self.memory)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn size(&self) -> DeviceSize {
+        self.size
+    }
+}
+
+impl<T: Pod> Drop for GpuBuffer<T> {
+    fn drop(&mut self) {
+        self.unmap();
+        if let Some(device) = self.device.take() {
+            unsafe {
+                device.destroy_buffer(self.buffer, None);
+                device.free_memory(self.memory, None);
+            }
+        }
+    }
+}
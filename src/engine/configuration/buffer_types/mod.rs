@@ -1,2 +1,3 @@
+pub mod gpu_buffer;
 pub mod uniform_buffer_types;
 pub mod vertex;
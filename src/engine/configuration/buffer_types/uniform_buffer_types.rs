@@ -1,3 +1,5 @@
+use ash::vk::DeviceSize;
+use bytemuck::{Pod, Zeroable};
 use cgmath::Matrix4;
 
 #[repr(C)]
@@ -6,5 +8,95 @@ pub struct UniformBufferObject {
     pub model: Matrix4<f32>,
     pub view: Matrix4<f32>,
     pub projection: Matrix4<f32>,
+    // First joint in the frame's joint buffer and the joint count, zero for
+    // objects drawn without a skin
+    pub joints: [u32; 4],
+    // First delta of the mesh's targets in the morph pool, its vertex count
+    // and how many of the targets below are blended
+    pub morph: [u32; 4],
+    pub morph_targets: [u32; 8],
+    pub morph_weights: [f32; 8],
 }
 
+unsafe impl Zeroable for UniformBufferObject {}
+unsafe impl Pod for UniformBufferObject {}
+
+impl UniformBufferObject {
+    // Bits of the scene pipeline variant the entry needs, see
+    // `Configuration::create_pipeline_variant`
+    pub fn vertex_variant(&self) -> u8 {
+        u8::from(self.joints[1] > 0) | u8::from(self.morph[2] > 0) << 1
+    }
+}
+
+pub fn aligned_stride(size: DeviceSize, alignment: DeviceSize) -> DeviceSize {
+    if alignment == 0 {
+        return size;
+    }
+    size.div_ceil(alignment) * alignment
+}
+
+// What a buffer of `capacity` entries grows to so `needed` fit, None when
+// they already do. Doubles to a power of two so a growing scene doesn't
+// reallocate every frame, and stops at `limit`
+pub fn grown_capacity(capacity: usize, needed: usize, limit: usize) -> Option<usize> {
+    let needed = needed.min(limit);
+    (needed > capacity).then(|| needed.next_power_of_two().min(limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stride_rounds_up_to_the_alignment() {
+        for alignment in [16, 64, 256] {
+            assert_eq!(aligned_stride(1, alignment), alignment);
+            assert_eq!(aligned_stride(alignment - 1, alignment), alignment);
+            assert_eq!(aligned_stride(alignment + 1, alignment), 2 * alignment);
+        }
+    }
+
+    #[test]
+    fn aligned_sizes_are_kept() {
+        for alignment in [16, 64, 256] {
+            assert_eq!(aligned_stride(alignment, alignment), alignment);
+            assert_eq!(aligned_stride(4 * alignment, alignment), 4 * alignment);
+        }
+    }
+
+    #[test]
+    fn uniform_entry_strides() {
+        let size = size_of::<UniformBufferObject>() as DeviceSize;
+        for alignment in [16, 64, 256] {
+            let stride = aligned_stride(size, alignment);
+            assert_eq!(stride % alignment, 0);
+            assert!(stride >= size && stride - size < alignment);
+        }
+    }
+
+    #[test]
+    fn no_alignment_keeps_the_size() {
+        assert_eq!(aligned_stride(100, 0), 100);
+    }
+
+    #[test]
+    fn capacities_that_fit_are_kept() {
+        assert_eq!(grown_capacity(1024, 0, 4096), None);
+        assert_eq!(grown_capacity(1024, 1024, 4096), None);
+    }
+
+    #[test]
+    fn capacities_grow_to_a_power_of_two() {
+        assert_eq!(grown_capacity(1024, 1025, 1 << 20), Some(2048));
+        assert_eq!(grown_capacity(1024, 50_000, 1 << 20), Some(65536));
+    }
+
+    // Past the limit only the limit is allocated, and nothing once there
+    #[test]
+    fn growth_stops_at_the_limit() {
+        assert_eq!(grown_capacity(1024, 5000, 4096), Some(4096));
+        assert_eq!(grown_capacity(1024, 3000, 3500), Some(3500));
+        assert_eq!(grown_capacity(4096, 5000, 4096), None);
+    }
+}
@@ -3,52 +3,398 @@ use std::mem::offset_of;
 use ash::vk::{
     Format, VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate,
 };
+use bytemuck::{Pod, Zeroable};
 use cgmath::{Vector2, Vector3};
 
-#[derive(Debug, Clone)]
+// How a mesh's vertices are stored in its vertex buffer. The packed layouts
+// quantize everything but the position, UVs outside of [0, 1] are kept as
+// half floats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum VertexLayout {
+    #[default]
+    Full,
+    Packed,
+    PackedHalfUv,
+}
+
+pub const VERTEX_LAYOUTS: [VertexLayout; 3] = [
+    VertexLayout::Full,
+    VertexLayout::Packed,
+    VertexLayout::PackedHalfUv,
+];
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vertex {
     pos: Vector3<f32>,
     color: Vector3<f32>,
     texture_coords: Vector2<f32>,
+    normal: Vector3<f32>,
+    // Skin joints and their weights, all zero for vertices that only follow
+    // their object
+    joints: [u16; 4],
+    weights: [f32; 4],
 }
 
+// Tightly packed 4 byte aligned fields, so there is no padding to leak
+unsafe impl Zeroable for Vertex {}
+unsafe impl Pod for Vertex {}
+
 impl Vertex {
     pub fn new(pos: Vector3<f32>, color: Vector3<f32>, texture_coords: Vector2<f32>) -> Self {
         Vertex {
             pos,
             color,
             texture_coords,
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            joints: [0; 4],
+            weights: [0.0; 4],
+        }
+    }
+
+    pub fn with_normal(mut self, normal: Vector3<f32>) -> Self {
+        self.normal = normal;
+        self
+    }
+
+    // Keeps the four heaviest of the (joint, weight) pairs, renormalized to
+    // add up to one
+    pub fn with_influences(mut self, influences: &[(u16, f32)]) -> Self {
+        let mut heaviest = influences
+            .iter()
+            .copied()
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect::<Vec<(u16, f32)>>();
+        heaviest.sort_by(|a, b| b.1.total_cmp(&a.1));
+        heaviest.truncate(4);
+        let total = heaviest.iter().map(|(_, weight)| weight).sum::<f32>();
+        self.joints = [0; 4];
+        self.weights = [0.0; 4];
+        for (slot, (joint, weight)) in heaviest.into_iter().enumerate() {
+            self.joints[slot] = joint;
+            self.weights[slot] = weight / total;
         }
+        self
+    }
+
+    pub fn position(&self) -> Vector3<f32> {
+        self.pos
+    }
+
+    pub fn normal(&self) -> Vector3<f32> {
+        self.normal
+    }
+
+    pub fn texture_coords(&self) -> Vector2<f32> {
+        self.texture_coords
     }
 
     pub fn get_binding_description() -> Vec<VertexInputBindingDescription> {
-        return vec![VertexInputBindingDescription::default()
-            .binding(0)
-            .stride(size_of::<Vertex>() as u32)
-            .input_rate(VertexInputRate::VERTEX)];
+        VertexLayout::Full.binding_description()
     }
 
     pub fn get_attribute_description() -> Vec<VertexInputAttributeDescription> {
-        let mut attribute_descriptons: [VertexInputAttributeDescription; 3] =
-            [Default::default(); 3];
-        attribute_descriptons[0] = attribute_descriptons[0]
-            .binding(0)
-            .location(0)
-            .format(Format::R32G32B32_SFLOAT)
-            .offset(offset_of!(Vertex, pos) as u32);
+        VertexLayout::Full.attribute_description()
+    }
+}
 
-        attribute_descriptons[1] = attribute_descriptons[1]
-            .binding(0)
-            .location(1)
-            .format(Format::R32G32B32_SFLOAT)
-            .offset(offset_of!(Vertex, color) as u32);
+impl VertexLayout {
+    // The packed layout the vertices fit into
+    pub fn packed_for(vertices: &[Vertex]) -> Self {
+        let unit_uvs = vertices.iter().all(|vertex| {
+            (0.0..=1.0).contains(&vertex.texture_coords.x)
+                && (0.0..=1.0).contains(&vertex.texture_coords.y)
+        });
+        if unit_uvs {
+            VertexLayout::Packed
+        } else {
+            VertexLayout::PackedHalfUv
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    // Whether the normal arrives octahedron encoded in its first two
+    // components
+    pub fn packed_normals(self) -> bool {
+        self != VertexLayout::Full
+    }
+
+    pub fn stride(self) -> usize {
+        match self {
+            VertexLayout::Full => size_of::<Vertex>(),
+            VertexLayout::Packed | VertexLayout::PackedHalfUv => size_of::<PackedVertex>(),
+        }
+    }
+
+    // Format and offset of locations 0 to 5, position, color, UV, normal,
+    // joints and weights
+    fn attributes(self) -> [(Format, usize); 6] {
+        match self {
+            VertexLayout::Full => [
+                (Format::R32G32B32_SFLOAT, offset_of!(Vertex, pos)),
+                (Format::R32G32B32_SFLOAT, offset_of!(Vertex, color)),
+                (Format::R32G32_SFLOAT, offset_of!(Vertex, texture_coords)),
+                (Format::R32G32B32_SFLOAT, offset_of!(Vertex, normal)),
+                (Format::R16G16B16A16_UINT, offset_of!(Vertex, joints)),
+                (Format::R32G32B32A32_SFLOAT, offset_of!(Vertex, weights)),
+            ],
+            VertexLayout::Packed | VertexLayout::PackedHalfUv => [
+                (Format::R32G32B32_SFLOAT, offset_of!(PackedVertex, pos)),
+                (Format::R8G8B8A8_UNORM, offset_of!(PackedVertex, color)),
+                (
+                    if self == VertexLayout::Packed {
+                        Format::R16G16_UNORM
+                    } else {
+                        Format::R16G16_SFLOAT
+                    },
+                    offset_of!(PackedVertex, texture_coords),
+                ),
+                (Format::R16G16_SNORM, offset_of!(PackedVertex, normal)),
+                (Format::R16G16B16A16_UINT, offset_of!(PackedVertex, joints)),
+                (Format::R8G8B8A8_UNORM, offset_of!(PackedVertex, weights)),
+            ],
+        }
+    }
 
-        attribute_descriptons[2] = attribute_descriptons[2]
+    pub fn binding_description(self) -> Vec<VertexInputBindingDescription> {
+        vec![VertexInputBindingDescription::default()
             .binding(0)
-            .location(2)
-            .format(Format::R32G32_SFLOAT)
-            .offset(offset_of!(Vertex, texture_coords) as u32);
+            .stride(self.stride() as u32)
+            .input_rate(VertexInputRate::VERTEX)]
+    }
+
+    pub fn attribute_description(self) -> Vec<VertexInputAttributeDescription> {
+        self.attributes()
+            .into_iter()
+            .enumerate()
+            .map(|(location, (format, offset))| {
+                VertexInputAttributeDescription::default()
+                    .binding(0)
+                    .location(location as u32)
+                    .format(format)
+                    .offset(offset as u32)
+            })
+            .collect()
+    }
+}
+
+// A vertex of the packed layouts, 36 bytes instead of 68
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PackedVertex {
+    pos: [f32; 3],
+    // Octahedron encoded
+    normal: [i16; 2],
+    // Unsigned normalized for VertexLayout::Packed, half float bits otherwise
+    texture_coords: [u16; 2],
+    color: [u8; 4],
+    joints: [u16; 4],
+    weights: [u8; 4],
+}
 
-        attribute_descriptons.to_vec()
+// 4 byte aligned fields of 12, 4, 4, 4, 8 and 4 bytes, no padding
+unsafe impl Zeroable for PackedVertex {}
+unsafe impl Pod for PackedVertex {}
+
+impl PackedVertex {
+    pub fn new(vertex: &Vertex, layout: VertexLayout) -> Self {
+        let texture_coords = if layout == VertexLayout::Packed {
+            [vertex.texture_coords.x, vertex.texture_coords.y].map(unorm16)
+        } else {
+            [vertex.texture_coords.x, vertex.texture_coords.y].map(half_bits)
+        };
+        let color = [vertex.color.x, vertex.color.y, vertex.color.z, 1.0].map(unorm8);
+        PackedVertex {
+            pos: vertex.pos.into(),
+            normal: octahedron_encode(vertex.normal),
+            texture_coords,
+            color,
+            joints: vertex.joints,
+            weights: quantize_weights(vertex.weights),
+        }
+    }
+}
+
+fn unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn unorm16(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+fn snorm16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * 32767.0).round() as i16
+}
+
+// Projects the unit normal onto the octahedron and folds the lower half over
+// the upper one, shader.vert undoes it with PACKED_NORMALS set
+fn octahedron_encode(normal: Vector3<f32>) -> [i16; 2] {
+    let length = normal.x.abs() + normal.y.abs() + normal.z.abs();
+    if length == 0.0 {
+        return [0; 2];
+    }
+    let normal = normal / length;
+    let (x, y) = if normal.z < 0.0 {
+        (
+            (1.0 - normal.y.abs()) * normal.x.signum(),
+            (1.0 - normal.x.abs()) * normal.y.signum(),
+        )
+    } else {
+        (normal.x, normal.y)
+    };
+    [snorm16(x), snorm16(y)]
+}
+
+// Rounded so the weights still add up to exactly one, the heaviest takes the
+// difference
+fn quantize_weights(weights: [f32; 4]) -> [u8; 4] {
+    let mut quantized = weights.map(unorm8);
+    let total = quantized.iter().map(|weight| *weight as i32).sum::<i32>();
+    if total > 0 {
+        let heaviest = (0..4).max_by_key(|slot| quantized[*slot]).unwrap();
+        quantized[heaviest] = (quantized[heaviest] as i32 + 255 - total).clamp(0, 255) as u8;
+    }
+    quantized
+}
+
+// The nearest IEEE half float, overflowing to infinity
+fn half_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    // Subnormal halves keep the implicit bit in the mantissa
+    let (half, shift) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        (0, (14 - exponent) as u32)
+    } else {
+        ((exponent as u32) << 10, 13)
+    };
+    let mantissa = if exponent <= 0 {
+        mantissa | 0x80_0000
+    } else {
+        mantissa
+    };
+    let half = half | (mantissa >> shift);
+    let remainder = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    // Round to nearest even, a carry into the exponent is still correct
+    let round_up = remainder > halfway || (remainder == halfway && half & 1 == 1);
+    sign | (half + u32::from(round_up)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{vec3, InnerSpace};
+
+    use super::*;
+
+    // What the R16G16_SNORM attribute and shader.vert make of the encoding
+    fn octahedron_decode([x, y]: [i16; 2]) -> Vector3<f32> {
+        let [x, y] = [x, y].map(|value| (value as f32 / 32767.0).max(-1.0));
+        let z = 1.0 - x.abs() - y.abs();
+        let (x, y) = if z < 0.0 {
+            ((1.0 - y.abs()) * x.signum(), (1.0 - x.abs()) * y.signum())
+        } else {
+            (x, y)
+        };
+        vec3(x, y, z).normalize()
+    }
+
+    fn half_value(bits: u16) -> f32 {
+        let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = i32::from((bits >> 10) & 0x1f);
+        let mantissa = f32::from(bits & 0x3ff);
+        sign * match exponent {
+            0 => mantissa * 2f32.powi(-24),
+            0x1f if mantissa == 0.0 => f32::INFINITY,
+            0x1f => f32::NAN,
+            _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    #[test]
+    fn unorms_round_to_the_nearest_step() {
+        // Half a step, plus the float error of the division
+        let bound = |steps: f32| 0.5 / steps + 1e-7;
+        for step in 0..=1000 {
+            let value = step as f32 / 1000.0;
+            assert!((unorm8(value) as f32 / 255.0 - value).abs() <= bound(255.0));
+            assert!((unorm16(value) as f32 / 65535.0 - value).abs() <= bound(65535.0));
+        }
+        assert_eq!((unorm8(-1.0), unorm8(2.0)), (0, 255));
+    }
+
+    #[test]
+    fn octahedron_normals_round_trip_within_1e_4() {
+        for latitude in 0..=36 {
+            for longitude in 0..72 {
+                let (sin_phi, cos_phi) = (latitude as f32 * 5.0).to_radians().sin_cos();
+                let (sin_theta, cos_theta) = (longitude as f32 * 5.0).to_radians().sin_cos();
+                let normal = vec3(sin_phi * cos_theta, sin_phi * sin_theta, cos_phi);
+                let decoded = octahedron_decode(octahedron_encode(normal));
+                // About the angle in radians at this size
+                let error = (decoded - normal).magnitude();
+                assert!(error <= 1e-4, "{normal:?} came back as {decoded:?}");
+            }
+        }
+        // Unnormalized normals keep their direction
+        let decoded = octahedron_decode(octahedron_encode(vec3(0.0, -3.0, 0.0)));
+        assert!((decoded - vec3(0.0, -1.0, 0.0)).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn half_floats_round_to_nearest() {
+        assert_eq!(half_bits(1.0), 0x3c00);
+        assert_eq!(half_bits(-2.0), 0xc000);
+        assert_eq!(half_bits(65504.0), 0x7bff);
+        assert_eq!(half_bits(65520.0), 0x7c00);
+        assert_eq!(half_bits(2f32.powi(-24)), 0x0001);
+        assert_eq!(half_bits(2f32.powi(-26)), 0x0000);
+        assert_eq!(half_bits(f32::NAN) & 0x7e00, 0x7e00);
+        // Normal halves have 11 significant bits, subnormals a fixed step
+        for step in -4000..=4000 {
+            let value = step as f32 * 0.013;
+            let error = (half_value(half_bits(value)) - value).abs();
+            assert!(
+                error <= value.abs() * 2f32.powi(-11) + 2f32.powi(-25),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn quantized_weights_add_up_to_one() {
+        for weights in [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.5, 0.5, 0.0, 0.0],
+            [1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0, 0.0],
+            [0.25; 4],
+            [0.7, 0.1, 0.1, 0.1],
+        ] {
+            let quantized = quantize_weights(weights);
+            assert_eq!(
+                quantized.iter().map(|weight| *weight as u32).sum::<u32>(),
+                255
+            );
+            for (weight, quantized) in weights.iter().zip(quantized) {
+                assert!((quantized as f32 / 255.0 - weight).abs() <= 2.0 / 255.0);
+            }
+        }
+        assert_eq!(quantize_weights([0.0; 4]), [0; 4]);
     }
 }
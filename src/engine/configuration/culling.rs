@@ -0,0 +1,972 @@
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+use anyhow::Error;
+use ash::{
+    vk::{
+        AccessFlags, Buffer, BufferCopy, BufferUsageFlags, CommandBuffer,
+        ComputePipelineCreateInfo, DescriptorBufferInfo, DescriptorSet, DescriptorSetLayout,
+        DescriptorSetLayoutCreateInfo, DescriptorType, DeviceSize, Pipeline, PipelineBindPoint,
+        PipelineCache, PipelineLayout, PipelineLayoutCreateInfo, PipelineShaderStageCreateInfo,
+        PipelineStageFlags, ShaderStageFlags, WriteDescriptorSet, WHOLE_SIZE,
+    },
+    Device,
+};
+use bytemuck::{Pod, Zeroable};
+use log::{debug, info, warn};
+
+use super::{
+    asset_cache::{MeshResource, MAX_LODS},
+    buffer_types::{
+        gpu_buffer::{GpuBuffer, GpuBufferError, GpuContext},
+        uniform_buffer_types::{grown_capacity, UniformBufferObject},
+        vertex::VertexLayout,
+    },
+    descriptor_allocator::DescriptorAllocator,
+    render_queue::{key_queue, queue_draws, sort_key, QueueStats, QueuedDraw, RenderQueue},
+    scene_pipeline,
+    shader_reflection::ShaderReflection,
+    synchronization::BufferBarrier,
+    upload_ring::UploadRange,
+    Configuration, MAX_FLIGHT_FENCES, MAX_OBJECTS, MIN_UNIFORM_ENTRIES, UNIFORM_BUFFER_ENTRIES,
+};
+use crate::{
+    engine::{
+        error::VkContext,
+        scene::Frustum,
+        viewport::{MAX_VIEWPORTS, MAX_VIEWS},
+    },
+    logging::span,
+};
+
+pub(super) const CULL_SHADER_PATH: &str = "src/assets/cull.spv";
+// cull.comp's set 0
+const CULL_DESCRIPTOR_BINDINGS: [(u32, DescriptorType); 4] = [
+    (0, DescriptorType::STORAGE_BUFFER),
+    (1, DescriptorType::STORAGE_BUFFER),
+    (2, DescriptorType::STORAGE_BUFFER),
+    (3, DescriptorType::STORAGE_BUFFER),
+];
+const WORKGROUP_SIZE: u32 = 64;
+// Every region of every surface is culled against its own planes
+const MAX_BLOCKS: usize = (MAX_VIEWPORTS * MAX_VIEWS) as usize;
+// VkDrawIndexedIndirectCommand's words, the last is the first instance
+const COMMAND_WORDS: usize = 5;
+const COMMAND_SIZE: DeviceSize = (COMMAND_WORDS * size_of::<u32>()) as DeviceSize;
+const COUNT_SIZE: DeviceSize = size_of::<u32>() as DeviceSize;
+// Left in the readback until a dispatch overwrites it, batches of surfaces
+// that weren't drawn keep it
+const UNREAD_COUNT: u32 = u32::MAX;
+// Screen sizes, as a fraction of the viewport height, below which each
+// coarser level of detail takes over
+const LOD_SCREEN_SIZES: [f32; MAX_LODS - 1] = [0.25, 0.1, 0.04];
+
+// Upload ring bytes the records and planes of `entries` uniform entries take
+// at most
+pub(super) fn culling_upload_size(entries: usize) -> DeviceSize {
+    (entries * size_of::<DrawRecord>() + MAX_BLOCKS * 6 * size_of::<[f32; 4]>()) as DeviceSize
+}
+
+// cull.comp's DrawRecord
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct DrawRecord {
+    center: [f32; 4],
+    extent: [f32; 4],
+    draw: [u32; 4],
+    slot: [u32; 4],
+}
+
+unsafe impl Zeroable for DrawRecord {}
+unsafe impl Pod for DrawRecord {}
+
+// Entries of one region drawn from the same mesh with the same pipeline
+// variant and material, culled and drawn with one indirect call
+#[derive(Debug, Clone, Copy)]
+struct CullBatch {
+    // Index into the scene pipelines
+    pipeline: usize,
+    // The first object, every draw shares its mesh and material
+    object_index: u32,
+    entry: u32,
+    // The batch's records, the compute pass fills the commands at the same
+    // indices from the front
+    first_record: u32,
+    record_count: u32,
+}
+
+// What `update_culling` made of a frame slot's entries
+#[derive(Debug, Clone, Default)]
+pub(super) struct CulledFrame {
+    // Per uniform entry, whether its bounds are inside its region's frustum.
+    // Entries without bounds and skinned or morphed ones always are
+    visible: Vec<bool>,
+    // Per uniform entry, the level of detail its screen size calls for
+    lods: Vec<u8>,
+    // The entry of every record
+    record_entries: Vec<u32>,
+    batches: Vec<CullBatch>,
+    // The visible entries the per object loop draws, sorted by queue within
+    // each region block
+    draws: Vec<QueuedDraw>,
+    // Per region block, its records, batches and draws
+    blocks: Vec<(Range<u32>, Range<u32>, Range<u32>)>,
+    // What was uploaded, kept so the next frame in the slot reuses the
+    // allocations
+    records: Vec<DrawRecord>,
+    planes: Vec<[f32; 4]>,
+}
+
+impl CulledFrame {
+    // Every entry visible, nothing batched, with the allocations kept
+    fn reset(&mut self, entry_count: usize) {
+        self.visible.clear();
+        self.visible.resize(entry_count, true);
+        self.lods.clear();
+        self.lods.resize(entry_count, 0);
+        self.record_entries.clear();
+        self.batches.clear();
+        self.draws.clear();
+        self.blocks.clear();
+        self.records.clear();
+        self.planes.clear();
+    }
+}
+
+struct CullingBuffers {
+    // Uploaded through the ring every frame
+    records: UploadRange,
+    planes: UploadRange,
+    commands: GpuBuffer<u32>,
+    counts: GpuBuffer<u32>,
+    // Copies of the commands and counts, read when the frame slot comes
+    // around again
+    readback_commands: GpuBuffer<u32>,
+    readback_counts: GpuBuffer<u32>,
+    descriptor_set: DescriptorSet,
+}
+
+impl CullingBuffers {
+    // Room for `records` draw records' commands and counts. The records and
+    // planes point at `uploads` until the first frame uploads them
+    fn new(
+        ctx: &GpuContext,
+        records: usize,
+        (descriptor_set, uploads): (DescriptorSet, UploadRange),
+    ) -> Result<Self, GpuBufferError> {
+        let indirect_usage = BufferUsageFlags::STORAGE_BUFFER
+            | BufferUsageFlags::INDIRECT_BUFFER
+            | BufferUsageFlags::TRANSFER_SRC;
+        let mut readback_counts =
+            GpuBuffer::host_visible(ctx, records, BufferUsageFlags::TRANSFER_DST)?;
+        readback_counts.write(&vec![UNREAD_COUNT; records]);
+        Ok(CullingBuffers {
+            records: uploads,
+            planes: uploads,
+            commands: GpuBuffer::device_local(
+                ctx,
+                &vec![0; records * COMMAND_WORDS],
+                indirect_usage,
+            )?,
+            counts: GpuBuffer::device_local(ctx, &vec![0; records], indirect_usage)?,
+            readback_commands: GpuBuffer::host_visible(
+                ctx,
+                records * COMMAND_WORDS,
+                BufferUsageFlags::TRANSFER_DST,
+            )?,
+            readback_counts,
+            descriptor_set,
+        })
+    }
+
+    fn write_descriptors(&self, device: &Device) {
+        let buffer_infos = [
+            self.records.info(),
+            self.planes.info(),
+            DescriptorBufferInfo::default()
+                .buffer(self.commands.buffer())
+                .range(WHOLE_SIZE),
+            DescriptorBufferInfo::default()
+                .buffer(self.counts.buffer())
+                .range(WHOLE_SIZE),
+        ]
+        .map(|buffer_info| [buffer_info]);
+        let writes = buffer_infos
+            .iter()
+            .zip(CULL_DESCRIPTOR_BINDINGS)
+            .map(|(buffer_info, (binding, descriptor_type))| {
+                WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(binding)
+                    .descriptor_type(descriptor_type)
+                    .buffer_info(buffer_info)
+            })
+            .collect::<Vec<WriteDescriptorSet>>();
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+}
+
+// The compute pre-pass and its buffers per frame in flight
+pub(super) struct CullingPass {
+    descriptor_set_layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline: Pipeline,
+    descriptor_allocator: DescriptorAllocator,
+    frames: Vec<CullingBuffers>,
+    // Warned about until the GPU and CPU agree again
+    parity_mismatch: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    // Uniform entries with geometry inside their frustum, out of all of them
+    pub visible: u32,
+    pub total: u32,
+    // What the compute pass kept of the batched entries, read back a few
+    // frames late. None on the CPU path
+    pub gpu_visible: Option<u32>,
+    // Submitted by the visible entries at their levels of detail
+    pub triangles: u64,
+    // In `RenderQueue::ALL`'s order, every batch counts as an opaque draw
+    // and state change
+    pub queues: [QueueStats; RenderQueue::ALL.len()],
+}
+
+// Whether the pass that gave `drawn` draws the object. Objects past its end,
+// added since it was made, are
+pub(super) fn is_drawn(drawn: &[bool], object_index: usize) -> bool {
+    drawn.get(object_index).copied().unwrap_or(true)
+}
+
+// Splits a region block's objects into those the draw list is built from,
+// drawn by the main pass and with a mesh to draw, and those that are hidden
+// or on none of its layers
+fn draw_list_objects(
+    drawn: &[bool],
+    object_count: usize,
+    has_mesh: impl Fn(usize) -> bool,
+) -> (Vec<usize>, Vec<usize>) {
+    let (drawn, hidden): (Vec<usize>, Vec<usize>) =
+        (0..object_count).partition(|index| is_drawn(drawn, *index));
+    let listed = drawn.into_iter().filter(|index| has_mesh(*index)).collect();
+    (listed, hidden)
+}
+
+impl Configuration {
+    // VK_KHR_draw_indirect_count and the core features a batch needs to be
+    // drawn with one call at its objects' entries
+    pub(super) fn supports_gpu_culling(&self) -> bool {
+        if !self.config.gpu_culling {
+            return false;
+        }
+        let features = self.physical_device_features.unwrap_or_default();
+        if features.multi_draw_indirect == 0 || features.draw_indirect_first_instance == 0 {
+            debug!("Indirect draws lack multiDrawIndirect or drawIndirectFirstInstance");
+            return false;
+        }
+        let instance = self.instance.as_ref().unwrap();
+        let Ok(extensions) = (unsafe {
+            instance.enumerate_device_extension_properties(self.physical_device.unwrap())
+        }) else {
+            return false;
+        };
+        let available = extensions.iter().any(|property| {
+            property.extension_name_as_c_str() == Ok(ash::khr::draw_indirect_count::NAME)
+        });
+        if !available {
+            debug!("VK_KHR_draw_indirect_count is not available");
+        }
+        available
+    }
+
+    pub(super) fn enable_gpu_culling(&mut self) {
+        self.draw_indirect_count = Some(ash::khr::draw_indirect_count::Device::new(
+            self.instance.as_ref().unwrap(),
+            self.device.as_ref().unwrap(),
+        ));
+        info!("Objects are culled on the GPU into indirect draws");
+    }
+
+    pub fn create_culling_pass(&mut self) -> Result<&mut Configuration, Error> {
+        self.culled_frames = vec![CulledFrame::default(); MAX_FLIGHT_FENCES as usize];
+        if self.draw_indirect_count.is_none() {
+            return Ok(self);
+        }
+        let reflection = ShaderReflection::reflect(
+            self.shader_cache.code(CULL_SHADER_PATH)?,
+            ShaderStageFlags::COMPUTE,
+        )?;
+        reflection.validate(0, &CULL_DESCRIPTOR_BINDINGS)?;
+
+        let device = self.device.as_ref().unwrap();
+        let bindings = reflection.set_layout_bindings(0, &[]);
+        let descriptor_set_layout_create_info =
+            DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .vk_ctx("vkCreateDescriptorSetLayout", "creating the culling pass")?
+        };
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_create_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .vk_ctx("vkCreatePipelineLayout", "creating the culling pass")?
+        };
+        let shader_module = self.shader_cache.acquire(device, CULL_SHADER_PATH)?;
+        let stage = PipelineShaderStageCreateInfo::default()
+            .module(shader_module)
+            .stage(ShaderStageFlags::COMPUTE)
+            .name(c"main");
+        let pipeline_create_infos = [ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(pipeline_layout)];
+        let pipeline = unsafe {
+            device.create_compute_pipelines(PipelineCache::null(), &pipeline_create_infos, None)
+        }
+        .map_err(|(_, result)| result)
+        .vk_ctx("vkCreateComputePipelines", "creating the culling pass")?[0];
+        self.shader_cache.release(shader_module);
+        self.shader_cache.purge(device);
+
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            MAX_FLIGHT_FENCES,
+            vec![(
+                DescriptorType::STORAGE_BUFFER,
+                CULL_DESCRIPTOR_BINDINGS.len() as f32,
+            )],
+        );
+        let descriptor_sets = descriptor_allocator.allocate(
+            device,
+            &vec![descriptor_set_layout; MAX_FLIGHT_FENCES as usize],
+        )?;
+        let ctx = self.gpu_context();
+        let ring = self.upload_ring.as_ref().unwrap();
+        let frames = descriptor_sets
+            .into_iter()
+            .enumerate()
+            .map(|(frame, descriptor_set)| {
+                let buffers = CullingBuffers::new(
+                    &ctx,
+                    MIN_UNIFORM_ENTRIES as usize,
+                    (descriptor_set, ring.whole(frame)),
+                )?;
+                buffers.write_descriptors(device);
+                Ok(buffers)
+            })
+            .collect::<Result<Vec<CullingBuffers>, GpuBufferError>>()?;
+        self.culling = Some(CullingPass {
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            descriptor_allocator,
+            frames,
+            parity_mismatch: false,
+        });
+        info!("Culling pass has been created!");
+        Ok(self)
+    }
+
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats
+    }
+
+    pub fn gpu_culling(&self) -> bool {
+        self.culling.is_some()
+    }
+
+    // Tests every uniform entry against its region's frustum and, on the GPU
+    // path, groups the entries into batches and uploads their records.
+    // Objects that aren't `drawn` are left out of both. Runs after the frame
+    // slot's fence, so the previous readback is complete
+    pub fn update_culling(
+        &mut self,
+        current_frame: usize,
+        objects: &[UniformBufferObject],
+        (object_meshes, drawn): (&[Option<Arc<MeshResource>>], &[bool]),
+    ) {
+        span!("update_culling");
+        self.check_culling_parity(current_frame);
+        let entry_count = objects
+            .len()
+            .min(self.uniform_entry_capacity(current_frame) as usize);
+        let entry_count = self.reserve_culling_records(current_frame, entry_count);
+        let object_count = object_meshes.len().min(MAX_OBJECTS as usize);
+        let mut frame = self
+            .culled_frames
+            .get_mut(current_frame)
+            .map(std::mem::take)
+            .unwrap_or_default();
+        frame.reset(entry_count);
+        let mut stats = CullingStats {
+            gpu_visible: self.culling_stats.gpu_visible,
+            ..Default::default()
+        };
+        let mut records = std::mem::take(&mut frame.records);
+        let mut planes = std::mem::take(&mut frame.planes);
+        // Indices into `groups`, looked up once per object
+        let mut group_indices: HashMap<BatchKey, usize> = HashMap::new();
+        let blocks = objects[..entry_count]
+            .chunks(object_count.max(1))
+            .take(MAX_BLOCKS)
+            .enumerate();
+        for (block, block_objects) in blocks.filter(|_| object_count > 0) {
+            let first_entry = block * object_count;
+            let frustum = Frustum::from_matrix(block_objects[0].projection * block_objects[0].view);
+            planes.extend(frustum.planes.map(Into::<[f32; 4]>::into));
+            let first_record = records.len() as u32;
+            let first_batch = frame.batches.len() as u32;
+            let first_draw = frame.draws.len() as u32;
+            let mut groups: Vec<(BatchKey, Vec<usize>)> = Vec::new();
+            group_indices.clear();
+            let (listed, hidden) = draw_list_objects(drawn, block_objects.len(), |index| {
+                self.object_mesh(object_meshes, index as u32).is_some()
+            });
+            for object_index in hidden {
+                frame.visible[first_entry + object_index] = false;
+            }
+            for object_index in listed {
+                let object = &block_objects[object_index];
+                let mesh = self
+                    .object_mesh(object_meshes, object_index as u32)
+                    .unwrap();
+                let variant = object.vertex_variant() as usize;
+                let bounds = mesh.bounds().filter(|_| variant == 0);
+                let visible =
+                    bounds.is_none_or(|bounds| frustum.intersects(&bounds, &object.model));
+                let lod = bounds.map_or(0, |bounds| {
+                    lod_level(bounds.screen_size(&object.model, &object.view, &object.projection))
+                });
+                let lod = lod.min(mesh.lod_count() - 1);
+                frame.visible[first_entry + object_index] = visible;
+                frame.lods[first_entry + object_index] = lod as u8;
+                stats.total += 1;
+                stats.visible += u32::from(visible);
+                if visible {
+                    stats.triangles += u64::from(mesh.lod(lod).1 / 3);
+                }
+                // Highlighted objects keep their tint, they are drawn on their
+                // own. So is every object while their LODs are tinted, and
+                // those outside the opaque queue to be sorted with it
+                let order = self.object_order(current_frame, object_index as u32);
+                if self.culling.is_none()
+                    || self.show_lods
+                    || self.highlighted_objects.contains(&(object_index as u32))
+                    || order.queue != RenderQueue::Opaque
+                {
+                    if visible {
+                        let center = bounds.map_or(object.model.w.truncate(), |bounds| {
+                            bounds.transformed(&object.model).0
+                        });
+                        let view_depth = -(object.view * center.extend(1.0)).z;
+                        frame.draws.push(QueuedDraw {
+                            key: sort_key(
+                                order.queue,
+                                view_depth,
+                                (order.id, order.priority),
+                                object_index as u32,
+                            ),
+                            entry: (first_entry + object_index) as u32,
+                            object_index: object_index as u32,
+                        });
+                    }
+                    continue;
+                }
+                let key = BatchKey {
+                    variant,
+                    vertex_layout: mesh.vertex_layout(),
+                    vertex_buffer: mesh.vertex_buffer(),
+                    material: self.object_material(current_frame, object_index as u32),
+                };
+                match group_indices.get(&key) {
+                    Some(group) => groups[*group].1.push(object_index),
+                    None => {
+                        group_indices.insert(key, groups.len());
+                        groups.push((key, vec![object_index]));
+                    }
+                }
+            }
+            for (key, members) in groups {
+                let batch_record = records.len() as u32;
+                for object_index in &members {
+                    let entry = first_entry + object_index;
+                    let object = &block_objects[*object_index];
+                    let mesh = self
+                        .object_mesh(object_meshes, *object_index as u32)
+                        .unwrap();
+                    let (center, extent) = match mesh.bounds().filter(|_| key.variant == 0) {
+                        Some(bounds) => {
+                            let (center, extent) = bounds.transformed(&object.model);
+                            (center.extend(1.0).into(), extent.extend(1.0).into())
+                        }
+                        None => ([0.0; 4], [0.0; 4]),
+                    };
+                    let (first_index, index_count) = mesh.lod(frame.lods[entry] as usize);
+                    records.push(DrawRecord {
+                        center,
+                        extent,
+                        draw: [
+                            index_count,
+                            entry as u32,
+                            block as u32,
+                            frame.batches.len() as u32,
+                        ],
+                        slot: [batch_record, first_index, 0, 0],
+                    });
+                    frame.record_entries.push(entry as u32);
+                }
+                frame.batches.push(CullBatch {
+                    pipeline: scene_pipeline(key.vertex_layout, key.variant),
+                    object_index: members[0] as u32,
+                    entry: (first_entry + members[0]) as u32,
+                    first_record: batch_record,
+                    record_count: members.len() as u32,
+                });
+            }
+            let draws = first_draw as usize..frame.draws.len();
+            frame.draws[draws.clone()].sort_unstable_by_key(|draw| draw.key);
+            self.count_queue_draws(
+                current_frame,
+                (&frame.draws[draws], block_objects),
+                object_meshes,
+                &mut stats.queues,
+            );
+            let batch_count = frame.batches.len() as u32 - first_batch;
+            let opaque = &mut stats.queues[RenderQueue::Opaque as usize];
+            opaque.draws += batch_count;
+            opaque.state_changes += batch_count;
+            frame.blocks.push((
+                first_record..records.len() as u32,
+                first_batch..frame.batches.len() as u32,
+                first_draw..frame.draws.len() as u32,
+            ));
+        }
+        if let (Some(buffers), Some(ring)) = (
+            self.culling
+                .as_mut()
+                .and_then(|culling| culling.frames.get_mut(current_frame)),
+            self.upload_ring.as_mut(),
+        ) {
+            if let (Some(records), Some(planes)) = (
+                ring.push_bound(current_frame, &records),
+                ring.push_bound(current_frame, &planes),
+            ) {
+                buffers.records = records;
+                buffers.planes = planes;
+                buffers.write_descriptors(self.device.as_ref().unwrap());
+            }
+        }
+        frame.records = records;
+        frame.planes = planes;
+        if let Some(culled) = self.culled_frames.get_mut(current_frame) {
+            *culled = frame;
+        }
+        self.culling_stats = stats;
+    }
+
+    // Grows the frame slot's commands and counts to one per entry. Runs
+    // after the slot's fence, the old buffers are done with. Returns the
+    // entries that can be culled, all of them unless growing failed
+    fn reserve_culling_records(&mut self, current_frame: usize, entries: usize) -> usize {
+        let Some(capacity) = self
+            .culling
+            .as_ref()
+            .and_then(|culling| culling.frames.get(current_frame))
+            .map(|buffers| buffers.counts.len())
+        else {
+            return entries;
+        };
+        let Some(grown) = grown_capacity(capacity, entries, UNIFORM_BUFFER_ENTRIES as usize) else {
+            return entries;
+        };
+        let old = &self.culling.as_ref().unwrap().frames[current_frame];
+        let uploads = (old.descriptor_set, old.records);
+        match CullingBuffers::new(&self.gpu_context(), grown, uploads) {
+            Ok(buffers) => {
+                // Pointed at them once this frame's records are uploaded
+                debug!("Culling buffers {current_frame} grown to {grown} records");
+                self.culling.as_mut().unwrap().frames[current_frame] = buffers;
+                entries
+            }
+            Err(err) => {
+                warn!("Only the first {capacity} of {entries} entries are culled and drawn: {err}");
+                capacity
+            }
+        }
+    }
+
+    // Counts the sorted draws of one region block and every change of
+    // pipeline, mesh or material between them, as the per object loop binds
+    // them
+    fn count_queue_draws(
+        &self,
+        current_frame: usize,
+        (draws, block_objects): (&[QueuedDraw], &[UniformBufferObject]),
+        object_meshes: &[Option<Arc<MeshResource>>],
+        queues: &mut [QueueStats; RenderQueue::ALL.len()],
+    ) {
+        let mut bound = None;
+        for draw in draws {
+            let Some(mesh) = self.object_mesh(object_meshes, draw.object_index) else {
+                continue;
+            };
+            let queue = key_queue(draw.key);
+            let variant = block_objects[draw.object_index as usize].vertex_variant() as usize;
+            let state = (
+                queue,
+                scene_pipeline(mesh.vertex_layout(), variant),
+                mesh.vertex_buffer(),
+                self.object_order(current_frame, draw.object_index).id,
+            );
+            let stats = &mut queues[queue as usize];
+            stats.draws += 1;
+            if bound != Some(state) {
+                stats.state_changes += 1;
+                bound = Some(state);
+            }
+        }
+    }
+
+    // Compares what the compute pass kept the last time the frame slot was
+    // drawn with the CPU's verdict on the same entries
+    fn check_culling_parity(&mut self, current_frame: usize) {
+        let (Some(culling), Some(frame)) =
+            (self.culling.as_mut(), self.culled_frames.get(current_frame))
+        else {
+            return;
+        };
+        let buffers = &mut culling.frames[current_frame];
+        let counts = buffers.readback_counts.read();
+        let commands = buffers.readback_commands.read();
+        let mut gpu_visible = None;
+        let mut mismatched_batches = 0;
+        for (batch_index, batch) in frame.batches.iter().enumerate() {
+            let count = counts[batch_index];
+            if count == UNREAD_COUNT {
+                continue;
+            }
+            *gpu_visible.get_or_insert(0) += count;
+            let records =
+                batch.first_record as usize..(batch.first_record + batch.record_count) as usize;
+            let mut expected = frame.record_entries[records.clone()]
+                .iter()
+                .copied()
+                .filter(|entry| frame.visible[*entry as usize])
+                .collect::<Vec<u32>>();
+            let mut kept = records
+                .take(count as usize)
+                .map(|record| commands[record * COMMAND_WORDS + COMMAND_WORDS - 1])
+                .collect::<Vec<u32>>();
+            expected.sort_unstable();
+            kept.sort_unstable();
+            if expected != kept {
+                mismatched_batches += 1;
+            }
+        }
+        if mismatched_batches > 0 && !culling.parity_mismatch {
+            warn!("GPU culling disagrees with the CPU on {mismatched_batches} batches");
+        }
+        if gpu_visible.is_some() {
+            culling.parity_mismatch = mismatched_batches > 0;
+        }
+        buffers
+            .readback_counts
+            .write(&vec![UNREAD_COUNT; counts.len()]);
+        self.culling_stats.gpu_visible = gpu_visible;
+    }
+
+    // Whether the entry is drawn at all, entries that weren't culled this
+    // frame are
+    pub(super) fn entry_visible(&self, current_frame: usize, entry: u32) -> bool {
+        self.culled_frames
+            .get(current_frame)
+            .and_then(|frame| frame.visible.get(entry as usize))
+            .is_none_or(|visible| *visible)
+    }
+
+    // The entry's level of detail, the full mesh for entries that weren't
+    // culled this frame
+    pub(super) fn entry_lod(&self, current_frame: usize, entry: u32) -> usize {
+        self.culled_frames
+            .get(current_frame)
+            .and_then(|frame| frame.lods.get(entry as usize))
+            .map_or(0, |lod| *lod as usize)
+    }
+
+    // The region block's draws of `queue` in the order they are made, none
+    // for blocks that weren't culled this frame
+    pub(super) fn queued_draws(
+        &self,
+        current_frame: usize,
+        block: usize,
+        queue: RenderQueue,
+    ) -> &[QueuedDraw] {
+        let Some(frame) = self.culled_frames.get(current_frame) else {
+            return &[];
+        };
+        let Some((_, _, draws)) = frame.blocks.get(block) else {
+            return &[];
+        };
+        queue_draws(
+            &frame.draws[draws.start as usize..draws.end as usize],
+            queue,
+        )
+    }
+
+    // Clears the counts of the region blocks' batches and culls their
+    // records into indirect commands, then copies both out for
+    // `check_culling_parity`. Recorded before the render pass begins
+    pub(super) fn cmd_cull(
+        &self,
+        command_buffer: CommandBuffer,
+        current_frame: usize,
+        blocks: Range<usize>,
+    ) {
+        let (Some(culling), Some(frame)) =
+            (self.culling.as_ref(), self.culled_frames.get(current_frame))
+        else {
+            return;
+        };
+        let blocks =
+            &frame.blocks[blocks.start.min(frame.blocks.len())..blocks.end.min(frame.blocks.len())];
+        let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+            return;
+        };
+        let records = first.0.start..last.0.end;
+        let batches = first.1.start..last.1.end;
+        if records.is_empty() {
+            return;
+        }
+        let buffers = &culling.frames[current_frame];
+        let counts = (
+            batches.start as DeviceSize * COUNT_SIZE,
+            batches.len() as DeviceSize * COUNT_SIZE,
+        );
+        let commands = (
+            records.start as DeviceSize * COMMAND_SIZE,
+            records.len() as DeviceSize * COMMAND_SIZE,
+        );
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_fill_buffer(
+                command_buffer,
+                buffers.counts.buffer(),
+                counts.0,
+                counts.1,
+                0,
+            );
+        }
+        self.cmd_buffer_barrier(
+            command_buffer,
+            BufferBarrier {
+                buffer: buffers.counts.buffer(),
+                offset: counts.0,
+                size: counts.1,
+                src_stage: PipelineStageFlags::TRANSFER,
+                src_access: AccessFlags::TRANSFER_WRITE,
+                dst_stage: PipelineStageFlags::COMPUTE_SHADER,
+                dst_access: AccessFlags::SHADER_READ | AccessFlags::SHADER_WRITE,
+            },
+        );
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, PipelineBindPoint::COMPUTE, culling.pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::COMPUTE,
+                culling.pipeline_layout,
+                0,
+                &[buffers.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                culling.pipeline_layout,
+                ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::cast_slice(&[records.start, records.len() as u32]),
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                (records.len() as u32).div_ceil(WORKGROUP_SIZE),
+                1,
+                1,
+            );
+        }
+        for (buffer, (offset, size)) in [
+            (buffers.counts.buffer(), counts),
+            (buffers.commands.buffer(), commands),
+        ] {
+            self.cmd_buffer_barrier(
+                command_buffer,
+                BufferBarrier {
+                    buffer,
+                    offset,
+                    size,
+                    src_stage: PipelineStageFlags::COMPUTE_SHADER,
+                    src_access: AccessFlags::SHADER_WRITE,
+                    dst_stage: PipelineStageFlags::DRAW_INDIRECT | PipelineStageFlags::TRANSFER,
+                    dst_access: AccessFlags::INDIRECT_COMMAND_READ | AccessFlags::TRANSFER_READ,
+                },
+            );
+        }
+        self.cmd_copy_culled(command_buffer, buffers, [counts, commands]);
+    }
+
+    fn cmd_copy_culled(
+        &self,
+        command_buffer: CommandBuffer,
+        buffers: &CullingBuffers,
+        [counts, commands]: [(DeviceSize, DeviceSize); 2],
+    ) {
+        let device = self.device.as_ref().unwrap();
+        let copies: [(Buffer, Buffer, (DeviceSize, DeviceSize)); 2] = [
+            (
+                buffers.counts.buffer(),
+                buffers.readback_counts.buffer(),
+                counts,
+            ),
+            (
+                buffers.commands.buffer(),
+                buffers.readback_commands.buffer(),
+                commands,
+            ),
+        ];
+        for (src, dst, (offset, size)) in copies {
+            let region = [BufferCopy::default()
+                .src_offset(offset)
+                .dst_offset(offset)
+                .size(size)];
+            unsafe { device.cmd_copy_buffer(command_buffer, src, dst, &region) };
+            self.cmd_buffer_barrier(
+                command_buffer,
+                BufferBarrier {
+                    buffer: dst,
+                    offset,
+                    size,
+                    src_stage: PipelineStageFlags::TRANSFER,
+                    src_access: AccessFlags::TRANSFER_WRITE,
+                    dst_stage: PipelineStageFlags::HOST,
+                    dst_access: AccessFlags::HOST_READ,
+                },
+            );
+        }
+    }
+
+    // Draws the batches of a region block, one indirect call each with as
+    // many draws as the compute pass kept. The region's viewport is set
+    pub(super) fn cmd_draw_batches(
+        &self,
+        command_buffer: CommandBuffer,
+        (descriptor_set, current_frame): (DescriptorSet, usize),
+        block: usize,
+        pipelines: &[Pipeline],
+        object_meshes: &[Option<Arc<MeshResource>>],
+    ) {
+        let (Some(culling), Some(loader), Some(frame)) = (
+            self.culling.as_ref(),
+            self.draw_indirect_count.as_ref(),
+            self.culled_frames.get(current_frame),
+        ) else {
+            return;
+        };
+        let Some((_, batches, _)) = frame.blocks.get(block) else {
+            return;
+        };
+        let buffers = &culling.frames[current_frame];
+        let device = self.device.as_ref().unwrap();
+        for batch_index in batches.clone() {
+            let batch = &frame.batches[batch_index as usize];
+            let Some(mesh) = self.object_mesh(object_meshes, batch.object_index) else {
+                continue;
+            };
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    PipelineBindPoint::GRAPHICS,
+                    pipelines[batch.pipeline],
+                );
+            }
+            self.cmd_bind_mesh(command_buffer, mesh);
+            self.cmd_bind_object(
+                command_buffer,
+                (descriptor_set, current_frame),
+                batch.entry * self.uniform_buffer_stride as u32,
+                [0.0; 4],
+            );
+            self.cmd_bind_material(command_buffer, current_frame, batch.object_index);
+            unsafe {
+                loader.cmd_draw_indexed_indirect_count(
+                    command_buffer,
+                    buffers.commands.buffer(),
+                    batch.first_record as DeviceSize * COMMAND_SIZE,
+                    buffers.counts.buffer(),
+                    batch_index as DeviceSize * COUNT_SIZE,
+                    batch.record_count,
+                    COMMAND_SIZE as u32,
+                );
+            }
+        }
+    }
+
+    pub(super) fn destroy_culling_pass(&mut self) {
+        let Some(mut culling) = self.culling.take() else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        culling.frames.clear();
+        culling.descriptor_allocator.destroy(device);
+        unsafe {
+            device.destroy_pipeline(culling.pipeline, None);
+            device.destroy_pipeline_layout(culling.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(culling.descriptor_set_layout, None);
+        }
+    }
+}
+
+// What entries of a batch have in common
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct BatchKey {
+    variant: usize,
+    vertex_layout: VertexLayout,
+    vertex_buffer: Buffer,
+    material: Option<(DescriptorSet, u32)>,
+}
+
+fn lod_level(screen_size: f32) -> usize {
+    LOD_SCREEN_SIZES
+        .iter()
+        .filter(|size| screen_size < **size)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undrawn_objects_are_left_out() {
+        let drawn = [true, false, true, false];
+        let (listed, hidden) = draw_list_objects(&drawn, 4, |_| true);
+        assert_eq!(listed, [0, 2]);
+        assert_eq!(hidden, [1, 3]);
+    }
+
+    // Objects added since the masks were applied are drawn
+    #[test]
+    fn objects_past_the_mask_list_are_drawn() {
+        let (listed, hidden) = draw_list_objects(&[false], 3, |_| true);
+        assert_eq!(listed, [1, 2]);
+        assert_eq!(hidden, [0]);
+        assert!(is_drawn(&[], 7));
+    }
+
+    // Nothing to draw isn't hidden, the entry keeps its visibility
+    #[test]
+    fn objects_without_a_mesh_are_neither() {
+        let (listed, hidden) = draw_list_objects(&[true, true, false], 3, |index| index != 1);
+        assert_eq!(listed, [0]);
+        assert_eq!(hidden, [2]);
+    }
+}
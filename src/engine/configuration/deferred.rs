@@ -0,0 +1,426 @@
+use anyhow::Error;
+use ash::vk::{
+    AttachmentLoadOp, BorderColor, ClearColorValue, ClearValue, CommandBuffer, CompareOp,
+    DescriptorImageInfo, DescriptorSet, DescriptorSetLayout, DescriptorSetLayoutCreateInfo,
+    DescriptorType, Filter, Format, Framebuffer, ImageLayout, ImageUsageFlags, Offset2D, Pipeline,
+    PipelineBindPoint, PipelineLayout, PipelineLayoutCreateInfo, Rect2D, RenderPass,
+    RenderPassBeginInfo, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode,
+    ShaderStageFlags, SubpassContents, WriteDescriptorSet,
+};
+use log::info;
+
+use super::{
+    descriptor_allocator::DescriptorAllocator,
+    render_target::{AttachmentSet, FULLSCREEN_SHADER_PATH},
+    shader_reflection::ShaderReflection,
+    surface_context::SurfaceContext,
+    Configuration, PipelineOptions, MAX_FLIGHT_FENCES,
+};
+use crate::engine::{
+    error::{EngineError, VkContext},
+    viewport::fullscreen_viewport,
+};
+
+pub(super) const GBUFFER_SHADER_PATH: &str = "src/assets/gbuffer.spv";
+// gbuffer.frag with BINDLESS defined
+pub(super) const GBUFFER_BINDLESS_SHADER_PATH: &str = "src/assets/gbuffer_bindless.spv";
+pub(super) const GBUFFER_BOUNDS_SHADER_PATH: &str = "src/assets/gbuffer_bounds.spv";
+pub(super) const DEFERRED_SHADER_PATH: &str = "src/assets/deferred.spv";
+const DEFERRED_DESCRIPTOR_BINDINGS: [(u32, DescriptorType); 4] = [
+    (0, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (1, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (2, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (3, DescriptorType::STORAGE_BUFFER),
+];
+// Albedo, world space normal and world space position, the locations
+// gbuffer.frag writes
+const GBUFFER_FORMATS: [Format; 3] = [
+    Format::R8G8B8A8_SRGB,
+    Format::R16G16B16A16_SFLOAT,
+    Format::R32G32B32A32_SFLOAT,
+];
+
+// How the scene is lit, switchable while running when the deferred pass
+// exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadingPath {
+    // Every fragment loops over the lights while the scene is drawn
+    Forward,
+    // The scene only fills the G-buffer, a fullscreen pass loops over the
+    // lights once per pixel
+    Deferred,
+}
+
+// Pipelines and render passes shared by every surface
+pub struct DeferredPass {
+    // Clears and fills the G-buffer and depth, leaving both ready to sample
+    gbuffer_pass: RenderPass,
+    // Clear the scene color and keep the G-buffer's depth attached, one for
+    // the main scene pass and one for the scaled one
+    lighting_pass: RenderPass,
+    scaled_lighting_pass: Option<RenderPass>,
+    // The scene's triangle variants and bounds pipeline writing the G-buffer
+    gbuffer_pipelines: Vec<Pipeline>,
+    descriptor_set_layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+    lighting_pipeline: Pipeline,
+    sampler: Sampler,
+    pub active: bool,
+}
+
+// A surface's G-buffer, rebuilt with its swapchain
+pub struct GBuffer {
+    targets: AttachmentSet,
+    descriptor_allocator: DescriptorAllocator,
+    // Per frame in flight, pointed at the frame's lights every frame
+    sets: Vec<DescriptorSet>,
+}
+
+impl Configuration {
+    // Depth has to stay sampled after the G-buffer pass, so this has to be
+    // known before the scene passes are created
+    pub(super) fn deferred_requested(&self) -> bool {
+        self.config.deferred && !self.headless()
+    }
+
+    pub fn create_deferred_pass(&mut self) -> Result<&mut Configuration, Error> {
+        if !self.deferred_requested() {
+            return Ok(self);
+        }
+        let mut reflection = ShaderReflection::reflect(
+            self.shader_cache.code(FULLSCREEN_SHADER_PATH)?,
+            ShaderStageFlags::VERTEX,
+        )?;
+        reflection.merge(ShaderReflection::reflect(
+            self.shader_cache.code(DEFERRED_SHADER_PATH)?,
+            ShaderStageFlags::FRAGMENT,
+        )?)?;
+        reflection.validate(0, &DEFERRED_DESCRIPTOR_BINDINGS)?;
+
+        let gbuffer_pass = self.scene_render_pass(
+            &GBUFFER_FORMATS,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            AttachmentLoadOp::CLEAR,
+            AttachmentLoadOp::CLEAR,
+        );
+        let (scene_format, scene_final_layout) = self.scene_color();
+        let lighting_pass = self.scene_render_pass(
+            &[scene_format],
+            scene_final_layout,
+            AttachmentLoadOp::CLEAR,
+            AttachmentLoadOp::LOAD,
+        );
+        let scaled_lighting_pass = self.scaled_render_pass.map(|_| {
+            self.scene_render_pass(
+                &[scene_format],
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                AttachmentLoadOp::CLEAR,
+                AttachmentLoadOp::LOAD,
+            )
+        });
+        // The scene's pipeline layout, the G-buffer shaders bind what the
+        // forward ones do
+        let gbuffer_shader_path = if self.bindless_capacity.is_some() {
+            GBUFFER_BINDLESS_SHADER_PATH
+        } else {
+            GBUFFER_SHADER_PATH
+        };
+        // Always the built-in vertex shader, the overrides are forward only
+        let gbuffer_pipelines = self.create_scene_pipelines(
+            (
+                self.vertex_shader_path(),
+                gbuffer_shader_path,
+                GBUFFER_BOUNDS_SHADER_PATH,
+            ),
+            (gbuffer_pass, GBUFFER_FORMATS.len()),
+            PipelineOptions::default(),
+        )?;
+
+        let device = self.device.as_ref().unwrap();
+        let bindings = reflection.set_layout_bindings(0, &[]);
+        let descriptor_set_layout_create_info =
+            DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .vk_ctx(
+                    "vkCreateDescriptorSetLayout",
+                    "creating the deferred lighting pass",
+                )?
+        };
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_create_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .vk_ctx(
+                    "vkCreatePipelineLayout",
+                    "creating the deferred lighting pass",
+                )?
+        };
+        // Every texel is fetched directly, the sampler only has to exist
+        let sampler_create_info = SamplerCreateInfo::default()
+            .mag_filter(Filter::NEAREST)
+            .min_filter(Filter::NEAREST)
+            .mipmap_mode(SamplerMipmapMode::NEAREST)
+            .address_mode_u(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(BorderColor::FLOAT_OPAQUE_BLACK)
+            .compare_op(CompareOp::ALWAYS)
+            .max_lod(0.0);
+        let sampler = unsafe {
+            device
+                .create_sampler(&sampler_create_info, None)
+                .vk_ctx("vkCreateSampler", "creating the deferred lighting pass")?
+        };
+
+        let vertex_shader_module = self.shader_cache.acquire(device, FULLSCREEN_SHADER_PATH)?;
+        let fragment_shader_module = self.shader_cache.acquire(device, DEFERRED_SHADER_PATH)?;
+        // Works with both lighting passes, their attachments only differ in
+        // layout
+        let lighting_pipeline = self.create_fullscreen_pipeline(
+            (vertex_shader_module, fragment_shader_module),
+            0,
+            None,
+            lighting_pass,
+            pipeline_layout,
+        );
+        self.shader_cache.release(vertex_shader_module);
+        self.shader_cache.release(fragment_shader_module);
+        self.shader_cache.purge(device);
+
+        self.deferred = Some(DeferredPass {
+            gbuffer_pass,
+            lighting_pass,
+            scaled_lighting_pass,
+            gbuffer_pipelines,
+            descriptor_set_layout,
+            pipeline_layout,
+            lighting_pipeline: lighting_pipeline?,
+            sampler,
+            active: true,
+        });
+        info!("Deferred shading pass has been created!");
+        Ok(self)
+    }
+
+    pub(super) fn destroy_deferred_pass(&mut self) {
+        let Some(deferred) = self.deferred.take() else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            for pipeline in deferred
+                .gbuffer_pipelines
+                .into_iter()
+                .chain([deferred.lighting_pipeline])
+            {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(deferred.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(deferred.descriptor_set_layout, None);
+            device.destroy_sampler(deferred.sampler, None);
+            for render_pass in [deferred.gbuffer_pass, deferred.lighting_pass]
+                .into_iter()
+                .chain(deferred.scaled_lighting_pass)
+            {
+                device.destroy_render_pass(render_pass, None);
+            }
+        }
+    }
+
+    pub fn shading_path(&self) -> ShadingPath {
+        match &self.deferred {
+            Some(deferred) if deferred.active => ShadingPath::Deferred,
+            _ => ShadingPath::Forward,
+        }
+    }
+
+    // Switching only changes what the next recorded frames draw with
+    pub fn set_shading_path(&mut self, shading_path: ShadingPath) -> Result<(), String> {
+        match (&mut self.deferred, shading_path) {
+            (Some(deferred), _) => {
+                deferred.active = shading_path == ShadingPath::Deferred;
+                Ok(())
+            }
+            (None, ShadingPath::Forward) => Ok(()),
+            (None, ShadingPath::Deferred) => {
+                Err("the deferred path is off, enable it in the config".to_string())
+            }
+        }
+    }
+
+    // The G-buffer pass, framebuffer and pipelines to draw the scene with
+    // when the deferred path is active
+    pub(super) fn gbuffer_drawing(
+        &self,
+        ctx: &SurfaceContext,
+    ) -> Option<(RenderPass, Framebuffer, &[Pipeline])> {
+        let deferred = self.deferred.as_ref().filter(|deferred| deferred.active)?;
+        let gbuffer = ctx.gbuffer.as_ref()?;
+        Some((
+            deferred.gbuffer_pass,
+            gbuffer.targets.framebuffer,
+            &deferred.gbuffer_pipelines,
+        ))
+    }
+
+    // Shares the surface's depth buffer, so runs after it in
+    // create_surface_framebuffers
+    pub(super) fn create_surface_gbuffer(
+        &self,
+        ctx: &mut SurfaceContext,
+    ) -> Result<(), EngineError> {
+        let Some(deferred) = &self.deferred else {
+            return Ok(());
+        };
+        let targets = self.create_attachment_set(
+            ctx.extent,
+            &GBUFFER_FORMATS,
+            ImageUsageFlags::SAMPLED,
+            deferred.gbuffer_pass,
+            Some(ctx.depth_image_view),
+        )?;
+
+        let device = self.device.as_ref().unwrap();
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            MAX_FLIGHT_FENCES,
+            vec![
+                (DescriptorType::COMBINED_IMAGE_SAMPLER, 3.0),
+                (DescriptorType::STORAGE_BUFFER, 1.0),
+            ],
+        );
+        let layouts = vec![deferred.descriptor_set_layout; MAX_FLIGHT_FENCES as usize];
+        let sets = descriptor_allocator
+            .allocate(device, &layouts)
+            .vk_ctx("vkAllocateDescriptorSets", "creating the G-buffer")?;
+        let image_infos = [0, 1, 2].map(|index| {
+            [DescriptorImageInfo::default()
+                .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(targets.views[index])
+                .sampler(deferred.sampler)]
+        });
+        for (frame, set) in sets.iter().enumerate() {
+            let buffer_info = [self.light_buffer_info(frame)];
+            let writes = DEFERRED_DESCRIPTOR_BINDINGS.map(|(binding, descriptor_type)| {
+                let write = WriteDescriptorSet::default()
+                    .dst_set(*set)
+                    .dst_binding(binding)
+                    .descriptor_type(descriptor_type);
+                match descriptor_type {
+                    DescriptorType::STORAGE_BUFFER => write.buffer_info(&buffer_info),
+                    _ => write.image_info(&image_infos[binding as usize]),
+                }
+            });
+            unsafe { device.update_descriptor_sets(&writes, &[]) };
+        }
+        ctx.gbuffer = Some(GBuffer {
+            targets,
+            descriptor_allocator,
+            sets,
+        });
+        Ok(())
+    }
+
+    // Points every surface's G-buffer set of the frame slot at its lights
+    pub(super) fn write_gbuffer_lights(&self, current_frame: usize) {
+        let buffer_info = [self.light_buffer_info(current_frame)];
+        let writes = self
+            .surfaces
+            .iter()
+            .filter_map(|ctx| ctx.gbuffer.as_ref()?.sets.get(current_frame))
+            .map(|&set| {
+                WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(DEFERRED_DESCRIPTOR_BINDINGS[3].0)
+                    .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&buffer_info)
+            })
+            .collect::<Vec<WriteDescriptorSet>>();
+        if !writes.is_empty() {
+            unsafe {
+                self.device
+                    .as_ref()
+                    .unwrap()
+                    .update_descriptor_sets(&writes, &[])
+            };
+        }
+    }
+
+    pub(super) fn destroy_surface_gbuffer(&self, ctx: &mut SurfaceContext) {
+        let Some(mut gbuffer) = ctx.gbuffer.take() else {
+            return;
+        };
+        gbuffer
+            .descriptor_allocator
+            .destroy(self.device.as_ref().unwrap());
+        self.destroy_attachment_set(gbuffer.targets);
+    }
+
+    // Ends the G-buffer pass and begins the scene pass in its place, shading
+    // every pixel at once. Whatever follows draws on top of the lit scene
+    pub(super) fn cmd_deferred_lighting(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        image_index: u32,
+        current_frame: usize,
+    ) {
+        let ctx = &self.surfaces[surface_index];
+        let (Some(deferred), Some(gbuffer)) = (&self.deferred, &ctx.gbuffer) else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+        for image in &gbuffer.targets.images {
+            self.cmd_target_written(command_buffer, *image);
+        }
+
+        let (framebuffer, scaled) =
+            self.scene_framebuffer(ctx, ctx.swapchain.framebuffers[image_index as usize]);
+        let lighting_pass = match deferred.scaled_lighting_pass {
+            Some(scaled_lighting_pass) if scaled => scaled_lighting_pass,
+            _ => deferred.lighting_pass,
+        };
+        let render_area = Rect2D::default()
+            .offset(Offset2D::default())
+            .extent(gbuffer.targets.extent);
+        // The depth attachment is loaded, its clear value is never read
+        let clear_values = [ClearValue {
+            color: ClearColorValue {
+                float32: ctx.clear_color(),
+            },
+        }; 2];
+        let render_pass_begin_info = RenderPassBeginInfo::default()
+            .render_pass(lighting_pass)
+            .framebuffer(framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                deferred.lighting_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                deferred.pipeline_layout,
+                0,
+                &[gbuffer.sets[current_frame]],
+                &[],
+            );
+            device.cmd_set_viewport(command_buffer, 0, &[fullscreen_viewport(&render_area)]);
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+}
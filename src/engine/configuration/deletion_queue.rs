@@ -0,0 +1,217 @@
+use std::sync::{Arc, Mutex};
+
+use ash::{
+    vk::{Buffer, DeviceMemory, Image, ImageView, Pipeline},
+    Device,
+};
+use log::debug;
+
+use super::{Configuration, MAX_FLIGHT_FENCES};
+
+// Raw handles of a resource whose owner went away
+#[derive(Debug, Clone, Copy)]
+pub enum PendingDeletion {
+    Image {
+        image: Image,
+        memory: DeviceMemory,
+        view: ImageView,
+    },
+    Buffer {
+        buffer: Buffer,
+        memory: DeviceMemory,
+    },
+    Pipeline(Pipeline),
+}
+
+impl PendingDeletion {
+    unsafe fn destroy(self, device: &Device) {
+        match self {
+            PendingDeletion::Image {
+                image,
+                memory,
+                view,
+            } => {
+                device.destroy_image_view(view, None);
+                device.destroy_image(image, None);
+                device.free_memory(memory, None);
+            }
+            PendingDeletion::Buffer { buffer, memory } => {
+                device.destroy_buffer(buffer, None);
+                device.free_memory(memory, None);
+            }
+            PendingDeletion::Pipeline(pipeline) => device.destroy_pipeline(pipeline, None),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    // Dropped since the last flush
+    incoming: Vec<PendingDeletion>,
+    // Waiting for the fence of a frame slot that may still use them
+    frames: [Vec<PendingDeletion>; MAX_FLIGHT_FENCES as usize],
+}
+
+// Resources can be dropped while frames in flight still reference them. Their
+// handles are parked here and destroyed once those frames are done. Clones
+// share the same queue so resources can carry one into their Drop
+#[derive(Debug, Clone, Default)]
+pub struct DeletionQueue {
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl DeletionQueue {
+    pub fn push(&self, deletion: PendingDeletion) {
+        self.queue.lock().unwrap().incoming.push(deletion);
+    }
+
+    // Called once the fences of `current_frame` have been waited on. What was
+    // dropped before this frame was last used by the previous slot, so it is
+    // freed when that slot comes around again
+    pub fn flush(&self, device: &Device, current_frame: usize, frames_in_flight: usize) {
+        self.flush_with(current_frame, frames_in_flight, |deletion| unsafe {
+            deletion.destroy(device)
+        });
+    }
+
+    pub(super) fn flush_with(
+        &self,
+        current_frame: usize,
+        frames_in_flight: usize,
+        mut destroy: impl FnMut(PendingDeletion),
+    ) {
+        let mut queue = self.queue.lock().unwrap();
+        let done = std::mem::take(&mut queue.frames[current_frame]);
+        if !done.is_empty() {
+            debug!("Destroying {} deferred resources", done.len());
+        }
+        for deletion in done {
+            destroy(deletion);
+        }
+        let previous_frame = (current_frame + frames_in_flight - 1) % frames_in_flight;
+        let incoming = std::mem::take(&mut queue.incoming);
+        queue.frames[previous_frame].extend(incoming);
+    }
+
+    // Only once the device is idle
+    pub fn destroy_all(&self, device: &Device) {
+        let mut queue = self.queue.lock().unwrap();
+        let Queue { incoming, frames } = &mut *queue;
+        for deletion in incoming
+            .drain(..)
+            .chain(frames.iter_mut().flat_map(|frame| frame.drain(..)))
+        {
+            unsafe { deletion.destroy(device) };
+        }
+    }
+}
+
+impl Configuration {
+    pub fn flush_deletions(&self, current_frame: usize, frames_in_flight: u32) {
+        self.deletion_queue.flush(
+            self.device.as_ref().unwrap(),
+            current_frame,
+            frames_in_flight as usize,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Handle, PipelineStageFlags, Semaphore};
+
+    use super::*;
+    use crate::engine::gpu_device::{mock::MockGpuDevice, GpuDevice};
+
+    fn pipeline(deletion: PendingDeletion) -> u64 {
+        match deletion {
+            PendingDeletion::Pipeline(pipeline) => pipeline.as_raw(),
+            _ => unreachable!(),
+        }
+    }
+
+    // Frames submit to the mock the way the engine does, resources are
+    // dropped while earlier frames are in flight and may only be destroyed
+    // once every one of those is done
+    fn run(frames_in_flight: usize) {
+        let gpu = MockGpuDevice::default();
+        let queue = DeletionQueue::default();
+        // Frames that may draw with each drop, and the latest frame each
+        // slot submitted
+        let mut in_use_by = Vec::new();
+        let mut submitted = [0; MAX_FLIGHT_FENCES as usize];
+        let mut destroyed = Vec::new();
+        for frame in 0..12 {
+            let current_frame = frame % frames_in_flight;
+            let slot = MockGpuDevice::slot(current_frame);
+            gpu.wait_for_fences(&[slot.in_flight]).unwrap();
+            queue.flush_with(current_frame, frames_in_flight, |deletion| {
+                let id = pipeline(deletion);
+                let frames: &Vec<usize> = &in_use_by[id as usize];
+                assert!(
+                    frames.iter().all(|frame| {
+                        let slot = frame % frames_in_flight;
+                        submitted[slot] > *frame
+                            || !gpu.is_pending(MockGpuDevice::slot(slot).in_flight)
+                    }),
+                    "pipeline {id} destroyed while a frame using it is in flight"
+                );
+                destroyed.push((id, frame));
+            });
+            // Dropped before this frame records, the earlier ones may still
+            // draw with it
+            in_use_by.push(
+                (frame.saturating_sub(frames_in_flight)..frame)
+                    .filter(|frame| {
+                        gpu.is_pending(MockGpuDevice::slot(frame % frames_in_flight).in_flight)
+                    })
+                    .collect(),
+            );
+            queue.push(PendingDeletion::Pipeline(Pipeline::from_raw(frame as u64)));
+            gpu.reset_fences(&[slot.in_flight]).unwrap();
+            gpu.submit(
+                slot.command_buffer,
+                Semaphore::null(),
+                PipelineStageFlags::ALL_COMMANDS,
+                Semaphore::null(),
+                slot.in_flight,
+            );
+            submitted[current_frame] = frame;
+        }
+        // Each drop is destroyed exactly once, when the slot of the frame it
+        // was dropped in comes around after the next flush. Only the last
+        // frames' drops are left
+        let delay = frames_in_flight.max(2) as u64;
+        let ids = destroyed.iter().map(|(id, _)| *id).collect::<Vec<u64>>();
+        assert_eq!(ids, (0..12 - delay).collect::<Vec<_>>());
+        for (id, frame) in destroyed {
+            assert_eq!(frame as u64, id + delay);
+        }
+    }
+
+    #[test]
+    fn deletions_wait_for_frames_in_flight() {
+        for frames_in_flight in 1..=MAX_FLIGHT_FENCES as usize {
+            run(frames_in_flight);
+        }
+    }
+
+    #[test]
+    fn deletions_are_kept_until_flushed() {
+        let queue = DeletionQueue::default();
+        queue.push(PendingDeletion::Pipeline(Pipeline::from_raw(1)));
+        let mut destroyed = Vec::new();
+        for frame in 0..2 {
+            queue.flush_with(frame, 2, |deletion| destroyed.push(pipeline(deletion)));
+        }
+        // Parked on slot 1 by the first flush, freed when slot 1 comes round
+        assert_eq!(destroyed, [1]);
+        // Clones share the queue
+        queue
+            .clone()
+            .push(PendingDeletion::Pipeline(Pipeline::from_raw(2)));
+        queue.flush_with(0, 2, |_| {});
+        queue.flush_with(1, 2, |deletion| destroyed.push(pipeline(deletion)));
+        assert_eq!(destroyed, [1, 2]);
+    }
+}
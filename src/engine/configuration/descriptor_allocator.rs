@@ -0,0 +1,277 @@
+use ash::{
+    prelude::VkResult,
+    vk::{
+        self, DescriptorPool, DescriptorPoolCreateInfo, DescriptorPoolResetFlags,
+        DescriptorPoolSize, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout,
+        DescriptorType,
+    },
+    Device,
+};
+use log::{debug, info};
+
+use crate::engine::error::VkContext;
+
+const DEFAULT_SETS_PER_POOL: u32 = 32;
+
+const DEFAULT_POOL_RATIOS: [(DescriptorType, f32); 5] = [
+    (DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+    (DescriptorType::UNIFORM_BUFFER, 1.0),
+    (DescriptorType::COMBINED_IMAGE_SAMPLER, 4.0),
+    (DescriptorType::STORAGE_BUFFER, 1.0),
+    (DescriptorType::STORAGE_IMAGE, 0.5),
+];
+
+// The pool calls the allocator makes, so its growth can be tested without a
+// device
+pub trait DescriptorPoolDevice {
+    fn create_descriptor_pool(&self, info: &DescriptorPoolCreateInfo) -> VkResult<DescriptorPool>;
+
+    fn allocate_descriptor_sets(
+        &self,
+        info: &DescriptorSetAllocateInfo,
+    ) -> VkResult<Vec<DescriptorSet>>;
+
+    fn reset_descriptor_pool(&self, pool: DescriptorPool) -> VkResult<()>;
+
+    fn destroy_descriptor_pool(&self, pool: DescriptorPool);
+}
+
+impl DescriptorPoolDevice for Device {
+    fn create_descriptor_pool(&self, info: &DescriptorPoolCreateInfo) -> VkResult<DescriptorPool> {
+        unsafe { Device::create_descriptor_pool(self, info, None) }
+    }
+
+    fn allocate_descriptor_sets(
+        &self,
+        info: &DescriptorSetAllocateInfo,
+    ) -> VkResult<Vec<DescriptorSet>> {
+        unsafe { Device::allocate_descriptor_sets(self, info) }
+    }
+
+    fn reset_descriptor_pool(&self, pool: DescriptorPool) -> VkResult<()> {
+        unsafe { Device::reset_descriptor_pool(self, pool, DescriptorPoolResetFlags::empty()) }
+    }
+
+    fn destroy_descriptor_pool(&self, pool: DescriptorPool) {
+        unsafe { Device::destroy_descriptor_pool(self, pool, None) }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DescriptorAllocator {
+    sets_per_pool: u32,
+    pool_ratios: Vec<(DescriptorType, f32)>,
+    current_pool: Option<DescriptorPool>,
+    used_pools: Vec<DescriptorPool>,
+    free_pools: Vec<DescriptorPool>,
+}
+
+impl Default for DescriptorAllocator {
+    fn default() -> Self {
+        Self::new(DEFAULT_SETS_PER_POOL, DEFAULT_POOL_RATIOS.to_vec())
+    }
+}
+
+impl DescriptorAllocator {
+    pub fn new(sets_per_pool: u32, pool_ratios: Vec<(DescriptorType, f32)>) -> Self {
+        Self {
+            sets_per_pool,
+            pool_ratios,
+            current_pool: None,
+            used_pools: Vec::new(),
+            free_pools: Vec::new(),
+        }
+    }
+
+    fn create_pool(
+        &self,
+        device: &impl DescriptorPoolDevice,
+    ) -> Result<DescriptorPool, vk::Result> {
+        let pool_sizes = self
+            .pool_ratios
+            .iter()
+            .map(|(ty, ratio)| {
+                DescriptorPoolSize::default()
+                    .ty(*ty)
+                    .descriptor_count(((self.sets_per_pool as f32 * ratio).ceil() as u32).max(1))
+            })
+            .collect::<Vec<DescriptorPoolSize>>();
+
+        let pool_create_info = DescriptorPoolCreateInfo::default()
+            .pool_sizes(&pool_sizes)
+            .max_sets(self.sets_per_pool);
+
+        let pool = device.create_descriptor_pool(&pool_create_info)?;
+        info!(
+            "Descriptor Pool has been created! ({} sets)",
+            self.sets_per_pool
+        );
+        Ok(pool)
+    }
+
+    fn grab_pool(
+        &mut self,
+        device: &impl DescriptorPoolDevice,
+    ) -> Result<DescriptorPool, vk::Result> {
+        match self.free_pools.pop() {
+            Some(pool) => Ok(pool),
+            None => self.create_pool(device),
+        }
+    }
+
+    pub fn allocate(
+        &mut self,
+        device: &impl DescriptorPoolDevice,
+        layouts: &[DescriptorSetLayout],
+    ) -> Result<Vec<DescriptorSet>, vk::Result> {
+        let pool = match self.current_pool {
+            Some(pool) => pool,
+            None => {
+                let pool = self.grab_pool(device)?;
+                self.current_pool = Some(pool);
+                pool
+            }
+        };
+
+        let allocate_info = DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(layouts);
+        match device.allocate_descriptor_sets(&allocate_info) {
+            Ok(sets) => Ok(sets),
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                debug!("Descriptor pool exhausted, growing allocator");
+                self.used_pools.push(pool);
+                let pool = self.grab_pool(device)?;
+                self.current_pool = Some(pool);
+
+                let allocate_info = DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(pool)
+                    .set_layouts(layouts);
+                device.allocate_descriptor_sets(&allocate_info)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn reset(&mut self, device: &impl DescriptorPoolDevice) {
+        let pools = self
+            .used_pools
+            .drain(..)
+            .chain(self.current_pool.take())
+            .collect::<Vec<DescriptorPool>>();
+        for pool in pools {
+            device.reset_descriptor_pool(pool).vk_expect(
+                "vkResetDescriptorPool",
+                "resetting the descriptor allocator",
+            );
+            self.free_pools.push(pool);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &impl DescriptorPoolDevice) {
+        self.reset(device);
+        for pool in self.free_pools.drain(..) {
+            device.destroy_descriptor_pool(pool);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use ash::vk::Handle;
+
+    use super::*;
+
+    // Pools that hand out `max_sets` sets each, then run out of memory
+    #[derive(Default)]
+    struct MockPools {
+        // Sets each pool holds and has left, indexed by the raw handle minus
+        // one
+        pools: RefCell<Vec<(u32, u32)>>,
+        destroyed: RefCell<Vec<DescriptorPool>>,
+    }
+
+    impl DescriptorPoolDevice for MockPools {
+        fn create_descriptor_pool(
+            &self,
+            info: &DescriptorPoolCreateInfo,
+        ) -> VkResult<DescriptorPool> {
+            let mut pools = self.pools.borrow_mut();
+            pools.push((info.max_sets, info.max_sets));
+            Ok(DescriptorPool::from_raw(pools.len() as u64))
+        }
+
+        fn allocate_descriptor_sets(
+            &self,
+            info: &DescriptorSetAllocateInfo,
+        ) -> VkResult<Vec<DescriptorSet>> {
+            let mut pools = self.pools.borrow_mut();
+            let (_, left) = &mut pools[info.descriptor_pool.as_raw() as usize - 1];
+            if *left < info.descriptor_set_count {
+                return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY);
+            }
+            *left -= info.descriptor_set_count;
+            Ok(vec![
+                DescriptorSet::null();
+                info.descriptor_set_count as usize
+            ])
+        }
+
+        fn reset_descriptor_pool(&self, pool: DescriptorPool) -> VkResult<()> {
+            let (max_sets, left) = &mut self.pools.borrow_mut()[pool.as_raw() as usize - 1];
+            *left = *max_sets;
+            Ok(())
+        }
+
+        fn destroy_descriptor_pool(&self, pool: DescriptorPool) {
+            self.destroyed.borrow_mut().push(pool);
+        }
+    }
+
+    fn allocator() -> DescriptorAllocator {
+        DescriptorAllocator::new(2, vec![(DescriptorType::UNIFORM_BUFFER, 1.0)])
+    }
+
+    #[test]
+    fn exhausted_pools_grow_into_a_new_one() {
+        let device = MockPools::default();
+        let mut allocator = allocator();
+        let layouts = [DescriptorSetLayout::null(); 2];
+        assert_eq!(allocator.allocate(&device, &layouts).unwrap().len(), 2);
+        assert_eq!(device.pools.borrow().len(), 1);
+        // The first pool is out of sets, the allocation lands in a second one
+        assert_eq!(allocator.allocate(&device, &layouts[..1]).unwrap().len(), 1);
+        assert_eq!(*device.pools.borrow(), [(2, 0), (2, 1)]);
+        assert_eq!(allocator.used_pools, [DescriptorPool::from_raw(1)]);
+    }
+
+    #[test]
+    fn reset_pools_are_reused_before_creating_more() {
+        let device = MockPools::default();
+        let mut allocator = allocator();
+        let layouts = [DescriptorSetLayout::null(); 2];
+        for _ in 0..3 {
+            allocator.allocate(&device, &layouts).unwrap();
+        }
+        allocator.reset(&device);
+        for _ in 0..3 {
+            allocator.allocate(&device, &layouts).unwrap();
+        }
+        assert_eq!(device.pools.borrow().len(), 3);
+        allocator.destroy(&device);
+        assert_eq!(device.destroyed.borrow().len(), 3);
+    }
+
+    #[test]
+    fn sets_larger_than_a_pool_fail() {
+        let device = MockPools::default();
+        let mut allocator = allocator();
+        let layouts = [DescriptorSetLayout::null(); 3];
+        assert_eq!(
+            allocator.allocate(&device, &layouts),
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+        );
+    }
+}
@@ -0,0 +1,442 @@
+use std::fmt;
+
+use anyhow::{anyhow, Error};
+use ash::vk::{DescriptorType, PhysicalDeviceLimits, SampleCountFlags};
+use log::{info, warn};
+
+use crate::engine::{config::EngineConfig, diagnostics::CapabilityReport};
+
+use super::{
+    bindless::BINDLESS_DESCRIPTOR_BINDINGS, buffer_types::vertex::VertexLayout,
+    materials::MATERIAL_DESCRIPTOR_BINDINGS, Configuration, ENGINE_DESCRIPTOR_BINDINGS,
+};
+
+// The shaders' push constant blocks are laid out for Vulkan's guaranteed
+// minimum
+const PUSH_CONSTANTS_SIZE: u32 = 128;
+// Buffers and images the engine allocates for itself, every distinct mesh
+// can bring a vertex and an index buffer on top
+const ENGINE_ALLOCATIONS: u32 = 256;
+// Meshes the requirement leaves room for. Objects drawing the same file or
+// primitive share its buffers, so this isn't the object limit
+const MESH_ALLOCATIONS: u32 = 1024;
+
+// The limits of the picked device that the engine's own numbers have to
+// fit. Read once in `pick_physical_device`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceLimits {
+    pub max_push_constants_size: u32,
+    pub max_per_stage_sampled_images: u32,
+    pub max_per_stage_storage_buffers: u32,
+    pub max_bound_descriptor_sets: u32,
+    pub max_vertex_input_attributes: u32,
+    pub max_framebuffer_size: [u32; 2],
+    // Supported by both color and depth attachments
+    pub framebuffer_sample_counts: SampleCountFlags,
+    pub max_memory_allocation_count: u32,
+    // Bytes one storage buffer descriptor can bind, the scene set views
+    // every uniform entry through one
+    pub max_storage_buffer_range: u32,
+}
+
+impl Default for DeviceLimits {
+    // Vulkan's guaranteed minimum
+    fn default() -> Self {
+        DeviceLimits {
+            max_push_constants_size: 128,
+            max_per_stage_sampled_images: 16,
+            max_per_stage_storage_buffers: 4,
+            max_bound_descriptor_sets: 4,
+            max_vertex_input_attributes: 16,
+            max_framebuffer_size: [4096, 4096],
+            framebuffer_sample_counts: SampleCountFlags::TYPE_1 | SampleCountFlags::TYPE_4,
+            max_memory_allocation_count: 4096,
+            max_storage_buffer_range: 1 << 27,
+        }
+    }
+}
+
+impl From<&PhysicalDeviceLimits> for DeviceLimits {
+    fn from(limits: &PhysicalDeviceLimits) -> Self {
+        DeviceLimits {
+            max_push_constants_size: limits.max_push_constants_size,
+            max_per_stage_sampled_images: limits.max_per_stage_descriptor_sampled_images,
+            max_per_stage_storage_buffers: limits.max_per_stage_descriptor_storage_buffers,
+            max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+            max_vertex_input_attributes: limits.max_vertex_input_attributes,
+            max_framebuffer_size: [limits.max_framebuffer_width, limits.max_framebuffer_height],
+            framebuffer_sample_counts: limits.framebuffer_color_sample_counts
+                & limits.framebuffer_depth_sample_counts,
+            max_memory_allocation_count: limits.max_memory_allocation_count,
+            max_storage_buffer_range: limits.max_storage_buffer_range,
+        }
+    }
+}
+
+// What the engine's shaders and layouts use, fixed at compile time apart
+// from the bindless set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineRequirements {
+    pub push_constants_size: u32,
+    // Sets 0 and 1 with the per material set, and with the bindless one
+    pub per_stage_sampled_images: u32,
+    pub per_stage_storage_buffers: u32,
+    pub bindless_per_stage_storage_buffers: u32,
+    pub descriptor_sets: u32,
+    pub vertex_input_attributes: u32,
+    pub memory_allocations: u32,
+}
+
+fn count(bindings: &[(u32, DescriptorType)], ty: DescriptorType) -> u32 {
+    bindings
+        .iter()
+        .filter(|(_, binding)| *binding == ty)
+        .count() as u32
+}
+
+impl Default for EngineRequirements {
+    fn default() -> Self {
+        let sampled = |set: &[(u32, DescriptorType)]| {
+            count(
+                &ENGINE_DESCRIPTOR_BINDINGS,
+                DescriptorType::COMBINED_IMAGE_SAMPLER,
+            ) + count(set, DescriptorType::COMBINED_IMAGE_SAMPLER)
+        };
+        let storage = |set: &[(u32, DescriptorType)]| {
+            count(&ENGINE_DESCRIPTOR_BINDINGS, DescriptorType::STORAGE_BUFFER)
+                + count(set, DescriptorType::STORAGE_BUFFER)
+        };
+        EngineRequirements {
+            push_constants_size: PUSH_CONSTANTS_SIZE,
+            // The bindless texture array is sized to the update after bind
+            // limits on its own, see `supported_bindless_capacity`
+            per_stage_sampled_images: sampled(&MATERIAL_DESCRIPTOR_BINDINGS),
+            per_stage_storage_buffers: storage(&MATERIAL_DESCRIPTOR_BINDINGS),
+            bindless_per_stage_storage_buffers: storage(&BINDLESS_DESCRIPTOR_BINDINGS),
+            descriptor_sets: 2,
+            vertex_input_attributes: [VertexLayout::Full, VertexLayout::Packed]
+                .into_iter()
+                .map(|layout| layout.attribute_description().len() as u32)
+                .max()
+                .unwrap_or(0),
+            memory_allocations: ENGINE_ALLOCATIONS + 2 * MESH_ALLOCATIONS,
+        }
+    }
+}
+
+// A configured value that was lowered to what the device allows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitAdjustment {
+    pub setting: &'static str,
+    pub requested: String,
+    pub applied: String,
+    // The device limit that forced it
+    pub limit: &'static str,
+}
+
+impl fmt::Display for LimitAdjustment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} lowered from {} to {} ({})",
+            self.setting, self.requested, self.applied, self.limit
+        )
+    }
+}
+
+impl DeviceLimits {
+    // Lowers what `config` asks for where the engine runs fine with less,
+    // errors on what it can't do without
+    pub fn validate(
+        &self,
+        requirements: &EngineRequirements,
+        config: &mut EngineConfig,
+    ) -> Result<Vec<LimitAdjustment>, Error> {
+        let required = [
+            (
+                "push constant bytes",
+                requirements.push_constants_size,
+                self.max_push_constants_size,
+                "maxPushConstantsSize",
+            ),
+            (
+                "sampled images per shader stage",
+                requirements.per_stage_sampled_images,
+                self.max_per_stage_sampled_images,
+                "maxPerStageDescriptorSampledImages",
+            ),
+            (
+                "storage buffers per shader stage",
+                requirements.per_stage_storage_buffers,
+                self.max_per_stage_storage_buffers,
+                "maxPerStageDescriptorStorageBuffers",
+            ),
+            (
+                "descriptor sets",
+                requirements.descriptor_sets,
+                self.max_bound_descriptor_sets,
+                "maxBoundDescriptorSets",
+            ),
+            (
+                "vertex input attributes",
+                requirements.vertex_input_attributes,
+                self.max_vertex_input_attributes,
+                "maxVertexInputAttributes",
+            ),
+            (
+                "memory allocations",
+                requirements.memory_allocations,
+                self.max_memory_allocation_count,
+                "maxMemoryAllocationCount",
+            ),
+        ];
+        for (what, needed, allowed, limit) in required {
+            if needed > allowed {
+                return Err(anyhow!(
+                    "The engine needs {needed} {what}, the device allows {allowed} ({limit})"
+                ));
+            }
+        }
+
+        let mut adjustments = Vec::new();
+        if config.bindless
+            && requirements.bindless_per_stage_storage_buffers > self.max_per_stage_storage_buffers
+        {
+            config.bindless = false;
+            adjustments.push(LimitAdjustment {
+                setting: "bindless",
+                requested: "true".to_string(),
+                applied: "false".to_string(),
+                limit: "maxPerStageDescriptorStorageBuffers",
+            });
+        }
+        let samples = self.supported_samples(config.msaa_samples);
+        if samples != config.msaa_samples {
+            adjustments.push(LimitAdjustment {
+                setting: "msaa_samples",
+                requested: config.msaa_samples.to_string(),
+                applied: samples.to_string(),
+                limit: "framebufferColorSampleCounts",
+            });
+            config.msaa_samples = samples;
+        }
+        let [max_width, max_height] = self.max_framebuffer_size;
+        if config.window_width > max_width || config.window_height > max_height {
+            let (width, height) = (
+                config.window_width.min(max_width),
+                config.window_height.min(max_height),
+            );
+            adjustments.push(LimitAdjustment {
+                setting: "window size",
+                requested: format!("{}x{}", config.window_width, config.window_height),
+                applied: format!("{width}x{height}"),
+                limit: "maxFramebufferWidth/Height",
+            });
+            config.window_width = width;
+            config.window_height = height;
+        }
+        Ok(adjustments)
+    }
+
+    // The highest sample count up to `samples` that color and depth
+    // attachments both support, 1 always is
+    pub fn supported_samples(&self, samples: u32) -> u32 {
+        (0..7)
+            .map(|bit| 1 << bit)
+            .filter(|count| {
+                *count <= samples
+                    && self
+                        .framebuffer_sample_counts
+                        .contains(SampleCountFlags::from_raw(*count))
+            })
+            .max()
+            .unwrap_or(1)
+    }
+
+    pub fn capabilities(&self) -> Vec<CapabilityReport> {
+        [
+            (
+                "max_push_constants_size",
+                self.max_push_constants_size.to_string(),
+            ),
+            (
+                "max_per_stage_sampled_images",
+                self.max_per_stage_sampled_images.to_string(),
+            ),
+            (
+                "max_per_stage_storage_buffers",
+                self.max_per_stage_storage_buffers.to_string(),
+            ),
+            (
+                "max_bound_descriptor_sets",
+                self.max_bound_descriptor_sets.to_string(),
+            ),
+            (
+                "max_vertex_input_attributes",
+                self.max_vertex_input_attributes.to_string(),
+            ),
+            (
+                "max_framebuffer_size",
+                format!(
+                    "{}x{}",
+                    self.max_framebuffer_size[0], self.max_framebuffer_size[1]
+                ),
+            ),
+            (
+                "framebuffer_sample_counts",
+                format!("{:?}", self.framebuffer_sample_counts),
+            ),
+            (
+                "max_memory_allocation_count",
+                self.max_memory_allocation_count.to_string(),
+            ),
+            (
+                "max_storage_buffer_range",
+                self.max_storage_buffer_range.to_string(),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| CapabilityReport {
+            name: name.to_string(),
+            value,
+        })
+        .collect()
+    }
+}
+
+impl Configuration {
+    // Right after the device is picked, before anything is sized from the
+    // configuration
+    pub fn validate_device_limits(&mut self) -> Result<&mut Configuration, Error> {
+        let adjustments = self
+            .device_limits
+            .validate(&EngineRequirements::default(), &mut self.config)?;
+        for adjustment in &adjustments {
+            warn!("{adjustment}");
+        }
+        info!("Device limits: {:?}", self.device_limits);
+        self.limit_adjustments = adjustments;
+        Ok(self)
+    }
+
+    pub fn device_limits(&self) -> DeviceLimits {
+        self.device_limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small mobile part: enough for the engine itself, not for bindless,
+    // 2x MSAA at most and a 1280x720 framebuffer
+    fn low_end(requirements: &EngineRequirements) -> DeviceLimits {
+        DeviceLimits {
+            max_per_stage_storage_buffers: requirements.per_stage_storage_buffers,
+            max_framebuffer_size: [1280, 720],
+            framebuffer_sample_counts: SampleCountFlags::TYPE_1 | SampleCountFlags::TYPE_2,
+            max_memory_allocation_count: requirements.memory_allocations,
+            ..DeviceLimits::default()
+        }
+    }
+
+    #[test]
+    fn the_guaranteed_minimum_runs_the_engine_without_bindless() {
+        let requirements = EngineRequirements::default();
+        // Bindless needs more storage buffers than guaranteed
+        let mut config = EngineConfig {
+            bindless: false,
+            msaa_samples: 4,
+            ..EngineConfig::default()
+        };
+        let before = format!("{config:?}");
+        let adjustments = DeviceLimits::default()
+            .validate(&requirements, &mut config)
+            .unwrap();
+        // 4x MSAA and a 1920x1080 window are guaranteed
+        assert!(adjustments.is_empty(), "{adjustments:?}");
+        assert_eq!(format!("{config:?}"), before);
+    }
+
+    #[test]
+    fn low_end_limits_lower_the_config() {
+        let requirements = EngineRequirements::default();
+        assert!(
+            requirements.bindless_per_stage_storage_buffers
+                > requirements.per_stage_storage_buffers
+        );
+        let mut config = EngineConfig {
+            bindless: true,
+            msaa_samples: 8,
+            window_width: 1920,
+            window_height: 1080,
+            ..EngineConfig::default()
+        };
+        let adjustments = low_end(&requirements)
+            .validate(&requirements, &mut config)
+            .unwrap();
+
+        assert!(!config.bindless);
+        assert_eq!(config.msaa_samples, 2);
+        assert_eq!((config.window_width, config.window_height), (1280, 720));
+        let settings: Vec<_> = adjustments.iter().map(|a| a.setting).collect();
+        assert_eq!(settings, ["bindless", "msaa_samples", "window size"]);
+        assert_eq!(
+            adjustments[2].to_string(),
+            "window size lowered from 1920x1080 to 1280x720 (maxFramebufferWidth/Height)"
+        );
+
+        // Validating again changes nothing
+        let again = low_end(&requirements)
+            .validate(&requirements, &mut config)
+            .unwrap();
+        assert!(again.is_empty(), "{again:?}");
+    }
+
+    #[test]
+    fn only_the_side_over_the_limit_is_clamped() {
+        let requirements = EngineRequirements::default();
+        let mut config = EngineConfig {
+            window_width: 800,
+            window_height: 1080,
+            ..EngineConfig::default()
+        };
+        low_end(&requirements)
+            .validate(&requirements, &mut config)
+            .unwrap();
+        assert_eq!((config.window_width, config.window_height), (800, 720));
+    }
+
+    #[test]
+    fn missing_requirements_are_errors() {
+        let requirements = EngineRequirements::default();
+        let mut config = EngineConfig::default();
+        let limits = DeviceLimits {
+            max_memory_allocation_count: requirements.memory_allocations - 1,
+            ..low_end(&requirements)
+        };
+        let error = limits.validate(&requirements, &mut config).unwrap_err();
+        assert!(error.to_string().contains("maxMemoryAllocationCount"));
+
+        let limits = DeviceLimits {
+            max_per_stage_storage_buffers: requirements.per_stage_storage_buffers - 1,
+            ..low_end(&requirements)
+        };
+        assert!(limits.validate(&requirements, &mut config).is_err());
+    }
+
+    #[test]
+    fn samples_round_down_to_a_supported_count() {
+        let limits = DeviceLimits {
+            framebuffer_sample_counts: SampleCountFlags::TYPE_1
+                | SampleCountFlags::TYPE_4
+                | SampleCountFlags::TYPE_16,
+            ..DeviceLimits::default()
+        };
+        let supported: Vec<_> = [0, 1, 2, 3, 4, 8, 16, 64]
+            .into_iter()
+            .map(|samples| limits.supported_samples(samples))
+            .collect();
+        assert_eq!(supported, [1, 1, 1, 1, 4, 4, 16, 16]);
+    }
+}
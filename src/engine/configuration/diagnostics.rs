@@ -0,0 +1,190 @@
+use std::ffi::CStr;
+
+use ash::vk::{self, Extent2D, PhysicalDeviceType, SurfaceCapabilitiesKHR};
+
+use crate::engine::diagnostics::{
+    AssetReport, DeviceReport, DiagnosticsReport, QueueFamilyReport, SurfaceCapabilitiesReport,
+    SurfaceReport,
+};
+
+use super::{Configuration, LimitAdjustment};
+
+const NVIDIA_VENDOR_ID: u32 = 0x10de;
+
+fn version_string(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+// NVIDIA packs its driver version as 10.8.8.6 bits, everyone else mostly
+// follows the Vulkan version layout
+fn driver_version_string(vendor_id: u32, version: u32) -> String {
+    if vendor_id == NVIDIA_VENDOR_ID {
+        return format!(
+            "{}.{}.{}.{}",
+            version >> 22,
+            (version >> 14) & 0xff,
+            (version >> 6) & 0xff,
+            version & 0x3f
+        );
+    }
+    version_string(version)
+}
+
+fn device_type_name(device_type: PhysicalDeviceType) -> String {
+    match device_type {
+        PhysicalDeviceType::DISCRETE_GPU => "discrete".to_string(),
+        PhysicalDeviceType::INTEGRATED_GPU => "integrated".to_string(),
+        PhysicalDeviceType::VIRTUAL_GPU => "virtual".to_string(),
+        PhysicalDeviceType::CPU => "cpu".to_string(),
+        other => format!("{other:?}"),
+    }
+}
+
+// Also used for layer names
+pub(super) fn extension_names(names: &[*const i8]) -> Vec<String> {
+    let mut names = names
+        .iter()
+        .map(|name| {
+            unsafe { CStr::from_ptr(*name) }
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect::<Vec<String>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+impl Configuration {
+    // Only reads what has been set up so far, safe to call on a configuration
+    // whose initialization failed
+    pub fn diagnostics(&self) -> DiagnosticsReport {
+        let mut report = DiagnosticsReport {
+            instance_extensions: self.instance_extensions.clone(),
+            layers: self.enabled_layers.clone(),
+            device_extensions: extension_names(&self.device_extensions),
+            graphics_queue_family: self.queue_family_indices.and_then(|q| q.graphics_queue),
+            presentation_queue_family: self.queue_family_indices.and_then(|q| q.presentation_queue),
+            msaa_samples: self.config.msaa_samples,
+            ..Default::default()
+        };
+        if let Some(entry) = &self.vulkan_entry {
+            report.instance_version = unsafe { entry.try_enumerate_instance_version() }
+                .ok()
+                .map(|version| version_string(version.unwrap_or(vk::API_VERSION_1_0)));
+        }
+
+        if let (Some(instance), Some(physical_device)) = (&self.instance, self.physical_device) {
+            let (properties, queue_families) = unsafe {
+                (
+                    instance.get_physical_device_properties(physical_device),
+                    instance.get_physical_device_queue_family_properties(physical_device),
+                )
+            };
+            let limits = properties.limits;
+            let driver = self.driver_description(physical_device);
+            report.device = Some(DeviceReport {
+                name: properties
+                    .device_name_as_c_str()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                device_type: device_type_name(properties.device_type),
+                vendor_id: properties.vendor_id,
+                device_id: properties.device_id,
+                api_version: version_string(properties.api_version),
+                driver_version: driver_version_string(
+                    properties.vendor_id,
+                    properties.driver_version,
+                ),
+                driver_id: driver
+                    .as_ref()
+                    .map(|driver| format!("{:?}", driver.driver_id))
+                    .unwrap_or_default(),
+                driver_name: driver
+                    .as_ref()
+                    .map(|driver| driver.name.clone())
+                    .unwrap_or_default(),
+                driver_info: driver.map(|driver| driver.info).unwrap_or_default(),
+                max_image_dimension_2d: limits.max_image_dimension2_d,
+                max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+                max_push_constants_size: limits.max_push_constants_size,
+                max_uniform_buffer_range: limits.max_uniform_buffer_range,
+                min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+                max_sampler_anisotropy: limits.max_sampler_anisotropy,
+                max_viewports: limits.max_viewports,
+                timestamp_period: limits.timestamp_period,
+                framebuffer_color_sample_counts: format!(
+                    "{:?}",
+                    limits.framebuffer_color_sample_counts
+                ),
+            });
+            report.capabilities = self.platform_quirks.capabilities();
+            report.limits = self.device_limits.capabilities();
+            report.limit_adjustments = self
+                .limit_adjustments
+                .iter()
+                .map(LimitAdjustment::to_string)
+                .collect();
+            report.queue_families = queue_families
+                .iter()
+                .enumerate()
+                .map(|(index, family)| QueueFamilyReport {
+                    index: index as u32,
+                    flags: format!("{:?}", family.queue_flags),
+                    queue_count: family.queue_count,
+                    timestamp_valid_bits: family.timestamp_valid_bits,
+                })
+                .collect();
+        }
+
+        let surface_format = self.surface_format.unwrap_or_default();
+        report.surfaces = self
+            .surfaces
+            .iter()
+            .map(|ctx| SurfaceReport {
+                view: ctx.id.0,
+                format: format!("{:?}", surface_format.format),
+                color_space: format!("{:?}", surface_format.color_space),
+                present_mode: format!("{:?}", ctx.present_mode),
+                width: ctx.extent.width,
+                height: ctx.extent.height,
+                image_count: ctx.image_count(),
+                requested_image_count: ctx.requested_image_count,
+                capabilities: capabilities_report(&ctx.capabilities),
+            })
+            .collect();
+
+        let assets = self.asset_cache.stats();
+        report.assets = AssetReport {
+            cache_hits: assets.hits,
+            cache_misses: assets.misses,
+            live_textures: assets.live_textures,
+            live_meshes: assets.live_meshes,
+            live_bytes: assets.live_bytes,
+            retained_cpu_bytes: assets.retained_cpu_bytes,
+        };
+        report
+    }
+}
+
+fn capabilities_report(capabilities: &SurfaceCapabilitiesKHR) -> SurfaceCapabilitiesReport {
+    let size = |extent: Extent2D| [extent.width, extent.height];
+    SurfaceCapabilitiesReport {
+        min_image_count: capabilities.min_image_count,
+        max_image_count: (capabilities.max_image_count > 0).then_some(capabilities.max_image_count),
+        current_extent: (capabilities.current_extent.width != u32::MAX)
+            .then(|| size(capabilities.current_extent)),
+        min_image_extent: size(capabilities.min_image_extent),
+        max_image_extent: size(capabilities.max_image_extent),
+        max_image_array_layers: capabilities.max_image_array_layers,
+        supported_transforms: format!("{:?}", capabilities.supported_transforms),
+        current_transform: format!("{:?}", capabilities.current_transform),
+        supported_composite_alpha: format!("{:?}", capabilities.supported_composite_alpha),
+        supported_usage_flags: format!("{:?}", capabilities.supported_usage_flags),
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+
+use ash::vk::{
+    DriverId, PhysicalDevice, PhysicalDeviceDriverProperties, PhysicalDeviceProperties2,
+};
+use log::{info, warn};
+
+use super::Configuration;
+
+// Which driver runs a device, as told by VK_KHR_driver_properties
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct DriverDescription {
+    pub(super) driver_id: DriverId,
+    // Like "llvmpipe" or "NVIDIA"
+    pub(super) name: String,
+    // Free form, mostly the version and build
+    pub(super) info: String,
+}
+
+impl fmt::Display for DriverDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.name, self.driver_id)?;
+        if !self.info.is_empty() {
+            write!(f, " {}", self.info)?;
+        }
+        Ok(())
+    }
+}
+
+impl Configuration {
+    // None when the device doesn't report VK_KHR_driver_properties
+    pub(super) fn driver_description(
+        &self,
+        physical_device: PhysicalDevice,
+    ) -> Option<DriverDescription> {
+        let instance = self.instance.as_ref().unwrap();
+        let extensions =
+            unsafe { instance.enumerate_device_extension_properties(physical_device) }.ok()?;
+        if !extensions.iter().any(|property| {
+            property.extension_name_as_c_str() == Ok(ash::khr::driver_properties::NAME)
+        }) {
+            return None;
+        }
+        let properties2 = ash::khr::get_physical_device_properties2::Instance::new(
+            self.vulkan_entry.as_ref().unwrap(),
+            instance,
+        );
+        let mut driver_properties = PhysicalDeviceDriverProperties::default();
+        let mut properties = PhysicalDeviceProperties2::default().push_next(&mut driver_properties);
+        unsafe { properties2.get_physical_device_properties2(physical_device, &mut properties) };
+        let text = |name: Result<&std::ffi::CStr, _>| {
+            name.map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        Some(DriverDescription {
+            driver_id: driver_properties.driver_id,
+            name: text(driver_properties.driver_name_as_c_str()),
+            info: text(driver_properties.driver_info_as_c_str()),
+        })
+    }
+
+    // `physical_devices` with the ones run by the preferred driver first,
+    // otherwise in the order the loader listed them
+    pub(super) fn order_by_driver_preference(
+        &self,
+        physical_devices: &[PhysicalDevice],
+    ) -> Vec<PhysicalDevice> {
+        let Some(preference) = self.config.driver_preference else {
+            return physical_devices.to_vec();
+        };
+        let mut ordered = physical_devices.to_vec();
+        ordered.sort_by_key(|physical_device| {
+            self.driver_description(*physical_device)
+                .is_none_or(|driver| driver.driver_id != preference)
+        });
+        ordered
+    }
+
+    // Logs which driver the picked device runs on, and every device there is
+    // when it isn't the preferred one
+    pub(super) fn report_driver(&self, physical_devices: &[PhysicalDevice]) {
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let driver = self.driver_description(physical_device);
+        match &driver {
+            Some(driver) => info!("Running on the {driver} driver"),
+            None => info!("The device doesn't report its driver"),
+        }
+        let Some(preference) = self.config.driver_preference else {
+            return;
+        };
+        if driver.is_some_and(|driver| driver.driver_id == preference) {
+            return;
+        }
+        warn!("No suitable device runs the preferred {preference:?} driver, available are:");
+        for physical_device in physical_devices {
+            let properties = unsafe { instance.get_physical_device_properties(*physical_device) };
+            let name = properties
+                .device_name_as_c_str()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            match self.driver_description(*physical_device) {
+                Some(driver) => warn!("  {name}: {driver}"),
+                None => warn!("  {name}: unknown driver"),
+            }
+        }
+    }
+}
@@ -0,0 +1,735 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use ash::vk::{Extent2D, Format, ImageLayout, ImageUsageFlags};
+
+// How a pass uses one resource. `layout` is what the pass expects the image
+// in when it starts, UNDEFINED when the old contents are thrown away, and
+// `final_layout` the one it leaves it in. Buffers use UNDEFINED for both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Access {
+    pub(super) layout: ImageLayout,
+    pub(super) final_layout: ImageLayout,
+    read: bool,
+    write: bool,
+}
+
+impl Access {
+    pub(super) const fn read(layout: ImageLayout) -> Self {
+        Access {
+            layout,
+            final_layout: layout,
+            read: true,
+            write: false,
+        }
+    }
+
+    // Overwrites everything, so nothing written before is needed
+    pub(super) const fn write(layout: ImageLayout) -> Self {
+        Access {
+            layout,
+            final_layout: layout,
+            read: false,
+            write: true,
+        }
+    }
+
+    // Draws on top of what is there, like a LOAD render pass
+    pub(super) const fn read_write(layout: ImageLayout) -> Self {
+        Access {
+            layout,
+            final_layout: layout,
+            read: true,
+            write: true,
+        }
+    }
+
+    pub(super) const fn leaving(mut self, final_layout: ImageLayout) -> Self {
+        self.final_layout = final_layout;
+        self
+    }
+}
+
+// A barrier recorded before a pass, or after the last one for outputs. It
+// changes the layout, makes the writes of an earlier pass visible or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Transition<R> {
+    pub(super) resource: R,
+    pub(super) from: ImageLayout,
+    pub(super) to: ImageLayout,
+    // An earlier pass wrote the resource and no barrier has followed since
+    pub(super) written: bool,
+}
+
+// Images with the same description can share memory when their lifetimes
+// in the frame don't overlap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct TransientDesc {
+    pub(super) extent: Extent2D,
+    pub(super) format: Format,
+    pub(super) usage: ImageUsageFlags,
+}
+
+#[derive(Debug)]
+struct PassNode<P, R> {
+    pass: P,
+    accesses: Vec<(R, Access)>,
+    // Kept even when nothing reads what it writes, like a readback
+    side_effect: bool,
+}
+
+// The passes of one frame and the resources they read and write. Resources
+// written by several passes are written in the order the passes are added,
+// every resource comes into the frame UNDEFINED
+#[derive(Debug)]
+pub(super) struct FrameGraph<P, R> {
+    passes: Vec<PassNode<P, R>>,
+    outputs: Vec<(R, ImageLayout)>,
+    transients: Vec<(R, TransientDesc)>,
+}
+
+#[derive(Debug)]
+pub(super) struct ScheduledPass<P, R> {
+    pub(super) pass: P,
+    pub(super) transitions: Vec<Transition<R>>,
+}
+
+#[derive(Debug)]
+pub(super) struct Schedule<P, R> {
+    pub(super) passes: Vec<ScheduledPass<P, R>>,
+    // Brings the outputs into the layout they are handed on in
+    pub(super) final_transitions: Vec<Transition<R>>,
+    // Pool slot of each transient that is used, and the slot descriptions
+    pub(super) aliases: Vec<(R, usize)>,
+    pub(super) slots: Vec<TransientDesc>,
+}
+
+impl<P: Copy, R: Copy + Eq + Hash> FrameGraph<P, R> {
+    pub(super) fn new() -> Self {
+        FrameGraph {
+            passes: Vec::new(),
+            outputs: Vec::new(),
+            transients: Vec::new(),
+        }
+    }
+
+    // Kept alive past the frame, in `layout`
+    pub(super) fn output(&mut self, resource: R, layout: ImageLayout) -> &mut Self {
+        self.outputs.push((resource, layout));
+        self
+    }
+
+    // Only lives within the frame and can come from the transient pool
+    pub(super) fn transient(&mut self, resource: R, desc: TransientDesc) -> &mut Self {
+        self.transients.push((resource, desc));
+        self
+    }
+
+    pub(super) fn add_pass(&mut self, pass: P, accesses: &[(R, Access)]) -> &mut Self {
+        self.push_pass(pass, accesses, false)
+    }
+
+    pub(super) fn add_side_effect_pass(&mut self, pass: P, accesses: &[(R, Access)]) -> &mut Self {
+        self.push_pass(pass, accesses, true)
+    }
+
+    fn push_pass(&mut self, pass: P, accesses: &[(R, Access)], side_effect: bool) -> &mut Self {
+        assert!(
+            accesses
+                .iter()
+                .enumerate()
+                .all(|(index, (resource, _))| accesses[..index]
+                    .iter()
+                    .all(|(other, _)| other != resource)),
+            "A frame graph pass uses a resource twice"
+        );
+        self.passes.push(PassNode {
+            pass,
+            accesses: accesses.to_vec(),
+            side_effect,
+        });
+        self
+    }
+
+    // Orders the passes after what they depend on, drops the ones whose
+    // writes nobody reads and works out the layout transitions between them
+    pub(super) fn compile(&self) -> Schedule<P, R> {
+        let dependencies = self.dependencies();
+        let live = self.live_passes(&dependencies);
+        let order = topological_order(&dependencies, &live);
+
+        let mut layouts = HashMap::new();
+        let mut unflushed = HashSet::new();
+        let mut lifetimes: HashMap<R, (usize, usize)> = HashMap::new();
+        let passes = order
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| {
+                let node = &self.passes[index];
+                let mut transitions = Vec::new();
+                for &(resource, access) in &node.accesses {
+                    let current = layouts
+                        .get(&resource)
+                        .copied()
+                        .unwrap_or(ImageLayout::UNDEFINED);
+                    // UNDEFINED means the pass takes the image in any layout
+                    let relayout =
+                        access.layout != ImageLayout::UNDEFINED && access.layout != current;
+                    let written = access.read && unflushed.remove(&resource);
+                    if relayout || written {
+                        transitions.push(Transition {
+                            resource,
+                            from: if access.read {
+                                current
+                            } else {
+                                ImageLayout::UNDEFINED
+                            },
+                            to: if relayout { access.layout } else { current },
+                            written,
+                        });
+                    }
+                    if access.write {
+                        unflushed.insert(resource);
+                    }
+                    layouts.insert(resource, access.final_layout);
+                    lifetimes
+                        .entry(resource)
+                        .and_modify(|(_, last)| *last = position)
+                        .or_insert((position, position));
+                }
+                ScheduledPass {
+                    pass: node.pass,
+                    transitions,
+                }
+            })
+            .collect();
+        let final_transitions = self
+            .outputs
+            .iter()
+            .filter_map(|&(resource, layout)| {
+                let current = layouts
+                    .get(&resource)
+                    .copied()
+                    .unwrap_or(ImageLayout::UNDEFINED);
+                (current != layout).then_some(Transition {
+                    resource,
+                    from: current,
+                    to: layout,
+                    written: unflushed.contains(&resource),
+                })
+            })
+            .collect();
+        let (aliases, slots) = self.alias_transients(&lifetimes);
+        Schedule {
+            passes,
+            final_transitions,
+            aliases,
+            slots,
+        }
+    }
+
+    // For every pass, the earlier passes it has to run after. Reads wait for
+    // the last write, writes for the last write and every read since. The
+    // flag says whether the earlier pass produced something this one needs
+    fn dependencies(&self) -> Vec<Vec<(usize, bool)>> {
+        let mut last_write: HashMap<R, usize> = HashMap::new();
+        let mut reads_since: HashMap<R, Vec<usize>> = HashMap::new();
+        self.passes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let mut dependencies = Vec::new();
+                for &(resource, access) in &node.accesses {
+                    if let Some(&writer) = last_write.get(&resource) {
+                        dependencies.push((writer, access.read));
+                    }
+                    if access.write {
+                        for &reader in reads_since.remove(&resource).iter().flatten() {
+                            dependencies.push((reader, false));
+                        }
+                        last_write.insert(resource, index);
+                    } else {
+                        reads_since.entry(resource).or_default().push(index);
+                    }
+                }
+                dependencies
+            })
+            .collect()
+    }
+
+    // Walks back from the outputs and the side effect passes
+    fn live_passes(&self, dependencies: &[Vec<(usize, bool)>]) -> Vec<bool> {
+        let mut live = vec![false; self.passes.len()];
+        let mut pending = Vec::new();
+        for (index, node) in self.passes.iter().enumerate() {
+            if node.side_effect {
+                pending.push(index);
+            }
+        }
+        for &(resource, _) in &self.outputs {
+            let writer = self.passes.iter().rposition(|node| {
+                node.accesses
+                    .iter()
+                    .any(|(other, access)| *other == resource && access.write)
+            });
+            pending.extend(writer);
+        }
+        while let Some(index) = pending.pop() {
+            if live[index] {
+                continue;
+            }
+            live[index] = true;
+            pending.extend(
+                dependencies[index]
+                    .iter()
+                    .filter(|(_, needed)| *needed)
+                    .map(|(dependency, _)| *dependency),
+            );
+        }
+        live
+    }
+
+    // Hands each used transient the first pool slot with the same
+    // description that is free again by the time it is first used
+    fn alias_transients(
+        &self,
+        lifetimes: &HashMap<R, (usize, usize)>,
+    ) -> (Vec<(R, usize)>, Vec<TransientDesc>) {
+        let mut used = self
+            .transients
+            .iter()
+            .filter_map(|&(resource, desc)| {
+                lifetimes
+                    .get(&resource)
+                    .map(|&lifetime| (resource, desc, lifetime))
+            })
+            .collect::<Vec<_>>();
+        used.sort_by_key(|(_, _, (first, _))| *first);
+        let mut slots: Vec<(TransientDesc, usize)> = Vec::new();
+        let aliases = used
+            .into_iter()
+            .map(|(resource, desc, (first, last))| {
+                let free = slots
+                    .iter()
+                    .position(|(slot_desc, busy_until)| *slot_desc == desc && *busy_until < first);
+                let slot = match free {
+                    Some(slot) => {
+                        slots[slot].1 = last;
+                        slot
+                    }
+                    None => {
+                        slots.push((desc, last));
+                        slots.len() - 1
+                    }
+                };
+                (resource, slot)
+            })
+            .collect();
+        (aliases, slots.into_iter().map(|(desc, _)| desc).collect())
+    }
+}
+
+// Kahn's algorithm over the live passes, taking the earliest added pass
+// whenever several are ready so independent passes keep their order
+fn topological_order(dependencies: &[Vec<(usize, bool)>], live: &[bool]) -> Vec<usize> {
+    let mut remaining = dependencies
+        .iter()
+        .map(|dependencies| {
+            dependencies
+                .iter()
+                .filter(|(dependency, _)| live[*dependency])
+                .count()
+        })
+        .collect::<Vec<usize>>();
+    let mut dependents = vec![Vec::new(); dependencies.len()];
+    for (index, dependencies) in dependencies.iter().enumerate() {
+        for &(dependency, _) in dependencies {
+            dependents[dependency].push(index);
+        }
+    }
+    let mut ready = (0..dependencies.len())
+        .filter(|&index| live[index] && remaining[index] == 0)
+        .collect::<Vec<usize>>();
+    let mut order = Vec::new();
+    while let Some(position) = (0..ready.len()).min_by_key(|&position| ready[position]) {
+        let index = ready.swap_remove(position);
+        order.push(index);
+        for &dependent in &dependents[index] {
+            if !live[dependent] {
+                continue;
+            }
+            remaining[dependent] -= 1;
+            if remaining[dependent] == 0 {
+                ready.push(dependent);
+            }
+        }
+    }
+    order
+}
+
+// The images behind the transient slots of a schedule, kept from one build
+// of a surface's targets to the next. A slot takes a pooled image of the
+// same description before a new one is made
+#[derive(Debug)]
+pub(super) struct TransientPool<R, T> {
+    slots: Vec<(TransientDesc, T)>,
+    aliases: HashMap<R, usize>,
+}
+
+impl<R: Copy + Eq + Hash, T> TransientPool<R, T> {
+    pub(super) fn new() -> Self {
+        TransientPool {
+            slots: Vec::new(),
+            aliases: HashMap::new(),
+        }
+    }
+
+    // Gives every slot of `schedule` an image, made by `create` where the
+    // pool has none of its description left. Returns the images no slot
+    // takes anymore, for the caller to destroy
+    pub(super) fn allocate<P, E>(
+        &mut self,
+        schedule: &Schedule<P, R>,
+        mut create: impl FnMut(TransientDesc) -> Result<T, E>,
+    ) -> Result<Vec<T>, E> {
+        let mut free: HashMap<TransientDesc, Vec<T>> = HashMap::new();
+        for (desc, image) in self.slots.drain(..).rev() {
+            free.entry(desc).or_default().push(image);
+        }
+        self.aliases.clear();
+        for &desc in &schedule.slots {
+            let image = match free.get_mut(&desc).and_then(Vec::pop) {
+                Some(image) => image,
+                None => create(desc)?,
+            };
+            self.slots.push((desc, image));
+        }
+        self.aliases.extend(schedule.aliases.iter().copied());
+        Ok(free.into_values().flatten().collect())
+    }
+
+    // The image of a transient the last allocated schedule uses
+    pub(super) fn get(&self, resource: R) -> Option<&T> {
+        self.aliases.get(&resource).map(|&slot| &self.slots[slot].1)
+    }
+
+    // Empties the pool, for when the surface goes away
+    pub(super) fn drain(&mut self) -> Vec<T> {
+        self.aliases.clear();
+        self.slots.drain(..).map(|(_, image)| image).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLED: ImageLayout = ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+    const PRESENT: ImageLayout = ImageLayout::PRESENT_SRC_KHR;
+
+    fn desc(width: u32) -> TransientDesc {
+        TransientDesc {
+            extent: Extent2D { width, height: 8 },
+            format: Format::R16G16B16A16_SFLOAT,
+            usage: ImageUsageFlags::SAMPLED,
+        }
+    }
+
+    fn passes(schedule: &Schedule<&'static str, &'static str>) -> Vec<&'static str> {
+        schedule
+            .passes
+            .iter()
+            .map(|scheduled| scheduled.pass)
+            .collect()
+    }
+
+    fn transitions(
+        schedule: &Schedule<&'static str, &'static str>,
+        pass: &str,
+    ) -> Vec<Transition<&'static str>> {
+        schedule
+            .passes
+            .iter()
+            .find(|scheduled| scheduled.pass == pass)
+            .unwrap()
+            .transitions
+            .clone()
+    }
+
+    fn transition(
+        resource: &'static str,
+        from: ImageLayout,
+        to: ImageLayout,
+        written: bool,
+    ) -> Transition<&'static str> {
+        Transition {
+            resource,
+            from,
+            to,
+            written,
+        }
+    }
+
+    #[test]
+    fn reads_wait_for_the_last_write_and_writes_for_the_reads() {
+        let mut graph = FrameGraph::new();
+        graph
+            .add_pass("draw", &[("color", Access::write(SAMPLED))])
+            .add_pass("blur", &[("color", Access::read(SAMPLED))])
+            .add_pass("tint", &[("color", Access::read_write(SAMPLED))])
+            .add_pass("clear", &[("color", Access::write(SAMPLED))]);
+        // Only what a pass reads makes the earlier pass needed
+        assert_eq!(
+            graph.dependencies(),
+            [
+                vec![],
+                vec![(0, true)],
+                vec![(0, true), (1, false)],
+                vec![(2, false)],
+            ]
+        );
+    }
+
+    #[test]
+    fn passes_nobody_reads_are_culled() {
+        let mut graph = FrameGraph::new();
+        graph
+            .output("swapchain", PRESENT)
+            .add_pass("scene", &[("color", Access::write(SAMPLED))])
+            .add_pass("unused", &[("debug", Access::write(SAMPLED))])
+            .add_pass(
+                "composite",
+                &[
+                    ("color", Access::read(SAMPLED)),
+                    ("swapchain", Access::write(PRESENT)),
+                ],
+            )
+            // Overwritten before anything reads it
+            .add_pass("overdrawn", &[("color", Access::write(SAMPLED))]);
+        assert_eq!(passes(&graph.compile()), ["scene", "composite"]);
+    }
+
+    // Kept for what they do outside the frame, with everything they read
+    #[test]
+    fn side_effect_passes_are_kept() {
+        let mut graph = FrameGraph::new();
+        graph
+            .add_pass("scene", &[("color", Access::write(SAMPLED))])
+            .add_side_effect_pass(
+                "readback",
+                &[
+                    ("color", Access::read(ImageLayout::TRANSFER_SRC_OPTIMAL)),
+                    ("buffer", Access::write(ImageLayout::UNDEFINED)),
+                ],
+            );
+        assert_eq!(passes(&graph.compile()), ["scene", "readback"]);
+    }
+
+    // Every pass comes after the ones it depends on, independent passes
+    // keep the order they were added in
+    #[test]
+    fn passes_are_ordered_by_their_dependencies() {
+        let mut graph = FrameGraph::new();
+        graph
+            .output("a", SAMPLED)
+            .output("b", SAMPLED)
+            .add_pass("write a", &[("a", Access::write(SAMPLED))])
+            .add_pass("write b", &[("b", Access::write(SAMPLED))])
+            .add_pass("tint a", &[("a", Access::read_write(SAMPLED))])
+            .add_pass(
+                "mix",
+                &[
+                    ("a", Access::read(SAMPLED)),
+                    ("b", Access::read_write(SAMPLED)),
+                ],
+            );
+        assert_eq!(
+            passes(&graph.compile()),
+            ["write a", "write b", "tint a", "mix"]
+        );
+    }
+
+    #[test]
+    fn layout_changes_become_transitions() {
+        let mut graph = FrameGraph::new();
+        graph
+            .output("swapchain", PRESENT)
+            .add_pass(
+                "draw",
+                &[(
+                    "swapchain",
+                    Access::write(ImageLayout::UNDEFINED).leaving(PRESENT),
+                )],
+            )
+            .add_side_effect_pass(
+                "copy",
+                &[("swapchain", Access::read(ImageLayout::TRANSFER_SRC_OPTIMAL))],
+            )
+            .add_side_effect_pass(
+                "overwrite",
+                &[(
+                    "swapchain",
+                    Access::write(ImageLayout::TRANSFER_DST_OPTIMAL),
+                )],
+            );
+        let schedule = graph.compile();
+        assert_eq!(
+            transitions(&schedule, "copy"),
+            [transition(
+                "swapchain",
+                PRESENT,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                true
+            )]
+        );
+        // What is overwritten is thrown away on the way
+        assert_eq!(
+            transitions(&schedule, "overwrite"),
+            [transition(
+                "swapchain",
+                ImageLayout::UNDEFINED,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                false
+            )]
+        );
+        assert_eq!(
+            schedule.final_transitions,
+            [transition(
+                "swapchain",
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                PRESENT,
+                true
+            )]
+        );
+    }
+
+    // A read after a write gets a barrier even in the same layout, one is
+    // enough for every read until the next write
+    #[test]
+    fn reads_of_written_resources_get_a_barrier() {
+        let mut graph = FrameGraph::new();
+        graph
+            .add_pass(
+                "draw",
+                &[(
+                    "color",
+                    Access::write(ImageLayout::UNDEFINED).leaving(SAMPLED),
+                )],
+            )
+            .add_side_effect_pass("first read", &[("color", Access::read(SAMPLED))])
+            .add_side_effect_pass("second read", &[("color", Access::read(SAMPLED))]);
+        let schedule = graph.compile();
+        assert!(transitions(&schedule, "draw").is_empty());
+        assert_eq!(
+            transitions(&schedule, "first read"),
+            [transition("color", SAMPLED, SAMPLED, true)]
+        );
+        assert!(transitions(&schedule, "second read").is_empty());
+    }
+
+    // A and B of the same description live one after the other and share a
+    // slot, C overlaps them and D is described differently
+    #[test]
+    fn transients_alias_when_their_lifetimes_do_not_overlap() {
+        let mut graph = FrameGraph::new();
+        graph
+            .transient("a", desc(8))
+            .transient("b", desc(8))
+            .transient("c", desc(8))
+            .transient("d", desc(4))
+            .transient("unused", desc(8))
+            .output("out", SAMPLED)
+            .add_pass(
+                "1",
+                &[("a", Access::write(SAMPLED)), ("c", Access::write(SAMPLED))],
+            )
+            .add_pass(
+                "2",
+                &[("a", Access::read(SAMPLED)), ("d", Access::write(SAMPLED))],
+            )
+            .add_pass(
+                "3",
+                &[("d", Access::read(SAMPLED)), ("b", Access::write(SAMPLED))],
+            )
+            .add_pass(
+                "4",
+                &[
+                    ("b", Access::read(SAMPLED)),
+                    ("c", Access::read(SAMPLED)),
+                    ("out", Access::write(SAMPLED)),
+                ],
+            );
+        let schedule = graph.compile();
+        let slot = |resource| {
+            schedule
+                .aliases
+                .iter()
+                .find(|(other, _)| *other == resource)
+                .map(|(_, slot)| *slot)
+        };
+        assert_eq!(slot("a"), slot("b"));
+        assert_ne!(slot("a"), slot("c"));
+        assert_ne!(slot("a"), slot("d"));
+        assert_eq!(slot("unused"), None);
+        assert_eq!(schedule.slots.len(), 3);
+        for (resource, slot) in &schedule.aliases {
+            let declared = graph
+                .transients
+                .iter()
+                .find(|(other, _)| other == resource)
+                .unwrap()
+                .1;
+            assert_eq!(schedule.slots[*slot], declared);
+        }
+    }
+
+    fn schedule(transients: &[(&'static str, u32)]) -> Schedule<&'static str, &'static str> {
+        let mut graph = FrameGraph::new();
+        for &(resource, width) in transients {
+            graph
+                .transient(resource, desc(width))
+                .add_side_effect_pass(resource, &[(resource, Access::write(SAMPLED))]);
+        }
+        graph.compile()
+    }
+
+    // Images are handed on by description, whichever transient took them
+    // before, and the ones left over are given back
+    #[test]
+    fn the_pool_reuses_images_of_the_same_description() {
+        let mut pool = TransientPool::new();
+        let mut created = 0;
+        let mut create = |desc: TransientDesc| {
+            created += 1;
+            Ok::<_, ()>((desc.extent.width, created))
+        };
+        let released = pool
+            .allocate(&schedule(&[("a", 8), ("b", 4)]), &mut create)
+            .unwrap();
+        assert!(released.is_empty());
+        assert_eq!(pool.get("a"), Some(&(8, 1)));
+        assert_eq!(pool.get("b"), Some(&(4, 2)));
+
+        let released = pool
+            .allocate(&schedule(&[("c", 8), ("d", 16)]), &mut create)
+            .unwrap();
+        assert_eq!(released, [(4, 2)]);
+        assert_eq!(pool.get("c"), Some(&(8, 1)));
+        assert_eq!(pool.get("d"), Some(&(16, 3)));
+        assert_eq!(pool.get("a"), None);
+
+        let mut drained = pool.drain();
+        drained.sort();
+        assert_eq!(drained, [(8, 1), (16, 3)]);
+        assert_eq!(pool.get("c"), None);
+    }
+
+    #[test]
+    fn failed_creations_are_passed_on() {
+        let mut pool = TransientPool::<&str, u32>::new();
+        let result = pool.allocate(&schedule(&[("a", 8)]), |_| Err("out of memory"));
+        assert_eq!(result, Err("out of memory"));
+    }
+}
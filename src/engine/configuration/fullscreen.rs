@@ -0,0 +1,118 @@
+use std::ffi::CStr;
+
+use ash::{ext::full_screen_exclusive, vk::SwapchainKHR};
+use log::{debug, info, warn};
+
+use super::{surface_context::SurfaceContext, Configuration};
+use crate::{engine::viewport::ViewId, logging};
+
+// VK_EXT_full_screen_exclusive lets the engine decide when a fullscreen
+// window owns its display, instead of the driver guessing from the window's
+// size. Only Windows drivers offer it
+impl Configuration {
+    // The device extension needs VK_KHR_get_surface_capabilities2
+    pub(super) fn full_screen_exclusive_instance_extension(&self) -> Option<&'static CStr> {
+        let name = ash::khr::get_surface_capabilities2::NAME;
+        let available = unsafe {
+            self.vulkan_entry
+                .as_ref()
+                .unwrap()
+                .enumerate_instance_extension_properties(None)
+                .ok()?
+                .iter()
+                .any(|property| property.extension_name_as_c_str() == Ok(name))
+        };
+        available.then_some(name)
+    }
+
+    pub(super) fn supports_full_screen_exclusive(&self) -> bool {
+        if !self.instance_extensions.iter().any(|extension| {
+            extension.as_bytes() == ash::khr::get_surface_capabilities2::NAME.to_bytes()
+        }) {
+            return false;
+        }
+        let instance = self.instance.as_ref().unwrap();
+        let Ok(extensions) = (unsafe {
+            instance.enumerate_device_extension_properties(self.physical_device.unwrap())
+        }) else {
+            return false;
+        };
+        let available = extensions
+            .iter()
+            .any(|property| property.extension_name_as_c_str() == Ok(full_screen_exclusive::NAME));
+        if !available {
+            debug!("VK_EXT_full_screen_exclusive is not available");
+        }
+        available
+    }
+
+    pub(super) fn enable_full_screen_exclusive(&mut self) {
+        self.full_screen_exclusive = Some(full_screen_exclusive::Device::new(
+            self.instance.as_ref().unwrap(),
+            self.device.as_ref().unwrap(),
+        ));
+        info!("Exclusive fullscreen goes through VK_EXT_full_screen_exclusive");
+    }
+
+    // Whether the surface's next swapchain is created for exclusive use of
+    // its monitor
+    pub(super) fn wants_full_screen_exclusive(&self, ctx: &SurfaceContext) -> bool {
+        self.full_screen_exclusive.is_some() && ctx.exclusive_monitor.is_some()
+    }
+
+    // Right after a swapchain was created for exclusive use. Failing only
+    // costs the latency win, the window stays fullscreen either way
+    pub(super) fn acquire_full_screen_exclusive(&self, ctx: &mut SurfaceContext) {
+        let Some(full_screen_exclusive) = &self.full_screen_exclusive else {
+            return;
+        };
+        match unsafe {
+            full_screen_exclusive.acquire_full_screen_exclusive_mode(ctx.swapchain.handle)
+        } {
+            Ok(()) => {
+                ctx.exclusive_acquired = true;
+                info!(target: logging::SWAPCHAIN, "{:?} owns its display", ctx.id);
+            }
+            Err(err) => warn!(
+                target: logging::SWAPCHAIN,
+                "Failed to take exclusive control of {:?}'s display: {err}",
+                ctx.id
+            ),
+        }
+    }
+
+    // Before the swapchain that holds the display is destroyed
+    pub(super) fn release_full_screen_exclusive(&self, ctx: &mut SurfaceContext) {
+        let Some(full_screen_exclusive) = &self.full_screen_exclusive else {
+            return;
+        };
+        if !ctx.exclusive_acquired || ctx.swapchain.handle == SwapchainKHR::null() {
+            return;
+        }
+        ctx.exclusive_acquired = false;
+        if let Err(err) = unsafe {
+            full_screen_exclusive.release_full_screen_exclusive_mode(ctx.swapchain.handle)
+        } {
+            warn!(target: logging::SWAPCHAIN, "Failed to release {:?}'s display: {err}", ctx.id);
+        }
+    }
+
+    // `hmonitor` is the Win32 monitor the surface's window went exclusive
+    // fullscreen on, None once it left. The swapchain is rebuilt right away,
+    // before the window changes modes when leaving
+    pub fn set_exclusive_monitor(&mut self, id: ViewId, hmonitor: Option<isize>) {
+        let Some(index) = self.surfaces.iter().position(|ctx| ctx.id == id) else {
+            return;
+        };
+        let ctx = &mut self.surfaces[index];
+        if ctx.exclusive_monitor == hmonitor {
+            return;
+        }
+        ctx.exclusive_monitor = hmonitor;
+        if self.full_screen_exclusive.is_some() && !ctx.is_minimized() {
+            if let Err(err) = self.recreate_swapchain(index) {
+                warn!(target: logging::SWAPCHAIN, "Failed to rebuild {id:?}'s swapchain: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::Error;
+use ash::vk::{
+    Buffer, CommandBuffer, DeviceSize, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineLayoutCreateInfo, PrimitiveTopology, Rect2D, ShaderStageFlags,
+};
+use cgmath::{vec2, vec3, EuclideanSpace, Matrix4, Point3};
+use log::{info, warn};
+
+use super::{
+    buffer_types::vertex::Vertex, shader_reflection::ShaderReflection, Configuration, DepthStencil,
+    MAX_FLIGHT_FENCES,
+};
+use crate::engine::{
+    error::VkContext,
+    viewport::{viewport, MAX_VIEWPORTS, MAX_VIEWS},
+};
+
+pub(super) const GIZMO_VERTEX_SHADER_PATH: &str = "src/assets/gizmo_vertices.spv";
+pub(super) const GIZMO_SHADER_PATH: &str = "src/assets/gizmo.spv";
+// Line endpoints per frame, enough for a sphere around every light. They
+// are uploaded through the upload ring
+const MAX_GIZMO_VERTICES: usize = 1 << 16;
+
+// How many endpoints of the overlay, depth tested and screen lines fit in
+// the frame's vertices, filled in that order. Only whole lines are kept
+fn gizmo_counts(lengths: [usize; 3]) -> [usize; 3] {
+    let mut left = MAX_GIZMO_VERTICES;
+    lengths.map(|length| {
+        let count = length.min(left) & !1;
+        left -= count;
+        count
+    })
+}
+
+// Lines drawn over the finished scene, rebuilt every frame from the engine's
+// lights, gizmo target and debug draw calls
+pub struct GizmoPass {
+    pipeline_layout: PipelineLayout,
+    // Without depth testing
+    pipeline: Pipeline,
+    // Depth tested against the scene, for debug lines that ask for it
+    tested_pipeline: Pipeline,
+    // For the screen lines, in the UI pass
+    screen_pipeline: Pipeline,
+    // Per frame in flight, the ring buffer, offset and the vertex counts of
+    // the frame's overlay, depth tested and screen space lines, in that order
+    vertices: Vec<Option<(Buffer, DeviceSize, [u32; 3])>>,
+    // Per surface and region, as of the last update
+    view_projections: Vec<Vec<Matrix4<f32>>>,
+}
+
+impl Configuration {
+    // The world lines are compatible with every scene pass, so they only
+    // need the main one
+    pub fn create_gizmo_pass(&mut self) -> Result<&mut Configuration, Error> {
+        if self.headless() {
+            return Ok(self);
+        }
+        let reflection = ShaderReflection::reflect(
+            self.shader_cache.code(GIZMO_VERTEX_SHADER_PATH)?,
+            ShaderStageFlags::VERTEX,
+        )?;
+        let pipeline_layout_create_info = PipelineLayoutCreateInfo::default()
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        let pipeline_layout = unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .vk_ctx("vkCreatePipelineLayout", "creating the gizmo pass")?
+        };
+        let pipeline = self.create_pipeline(
+            (GIZMO_VERTEX_SHADER_PATH, GIZMO_SHADER_PATH),
+            PrimitiveTopology::LINE_LIST,
+            (self.render_pass.unwrap(), 1),
+            pipeline_layout,
+            DepthStencil::NONE,
+        )?;
+        let tested_pipeline = self.create_pipeline(
+            (GIZMO_VERTEX_SHADER_PATH, GIZMO_SHADER_PATH),
+            PrimitiveTopology::LINE_LIST,
+            (self.render_pass.unwrap(), 1),
+            pipeline_layout,
+            DepthStencil::READ_ONLY,
+        )?;
+        let screen_pipeline = self.create_pipeline(
+            (GIZMO_VERTEX_SHADER_PATH, GIZMO_SHADER_PATH),
+            PrimitiveTopology::LINE_LIST,
+            (self.ui_pass.unwrap(), 1),
+            pipeline_layout,
+            DepthStencil::NONE,
+        )?;
+        self.gizmos = Some(GizmoPass {
+            pipeline_layout,
+            pipeline,
+            tested_pipeline,
+            screen_pipeline,
+            vertices: vec![None; MAX_FLIGHT_FENCES as usize],
+            view_projections: Vec::new(),
+        });
+        info!("Gizmo pass has been created!");
+        Ok(self)
+    }
+
+    pub(super) fn destroy_gizmo_pass(&mut self) {
+        let Some(gizmos) = self.gizmos.take() else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.destroy_pipeline(gizmos.pipeline, None);
+            device.destroy_pipeline(gizmos.tested_pipeline, None);
+            device.destroy_pipeline(gizmos.screen_pipeline, None);
+            device.destroy_pipeline_layout(gizmos.pipeline_layout, None);
+        }
+    }
+
+    // `overlay` and `depth_tested` are line list endpoints in world space,
+    // `view_projections` every surface's region cameras without the surface's
+    // pre-rotation. `screen` lines are drawn once over the whole primary
+    // window, in 0..1 window coordinates with y up
+    pub fn update_gizmos(
+        &mut self,
+        current_frame: usize,
+        overlay: &[(Point3<f32>, [f32; 3])],
+        depth_tested: &[(Point3<f32>, [f32; 3])],
+        screen: &[(Point3<f32>, [f32; 3])],
+        view_projections: Vec<Vec<Matrix4<f32>>>,
+    ) {
+        let (Some(gizmos), Some(ring)) = (&mut self.gizmos, &mut self.upload_ring) else {
+            return;
+        };
+        let total = overlay.len() + depth_tested.len() + screen.len();
+        if total > MAX_GIZMO_VERTICES {
+            warn!("{total} gizmo vertices, only drawing the first {MAX_GIZMO_VERTICES}");
+        }
+        let [overlay_count, tested_count, screen_count] =
+            gizmo_counts([overlay.len(), depth_tested.len(), screen.len()]);
+        let vertices = overlay[..overlay_count]
+            .iter()
+            .chain(&depth_tested[..tested_count])
+            .chain(&screen[..screen_count])
+            .map(|(position, color)| {
+                Vertex::new(position.to_vec(), (*color).into(), vec2(0.0, 0.0))
+            })
+            .collect::<Vec<Vertex>>();
+        gizmos.vertices[current_frame] = (!vertices.is_empty())
+            .then(|| ring.push(current_frame, &vertices, size_of::<Vertex>() as DeviceSize))
+            .flatten()
+            .map(|allocation| {
+                (
+                    allocation.buffer,
+                    allocation.offset,
+                    [
+                        overlay_count as u32,
+                        tested_count as u32,
+                        screen_count as u32,
+                    ],
+                )
+            });
+        gizmos.view_projections = view_projections;
+    }
+
+    // Whether the frame has screen lines to draw
+    pub(super) fn screen_gizmos_pending(&self, current_frame: usize) -> bool {
+        self.gizmos.as_ref().is_some_and(|gizmos| {
+            gizmos.vertices[current_frame]
+                .is_some_and(|(_, _, [_, _, screen_count])| screen_count > 0)
+        })
+    }
+
+    // Inside the scene pass, after everything else in it
+    pub(super) fn cmd_gizmos(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        current_frame: usize,
+        regions: &[Rect2D],
+    ) {
+        let Some(gizmos) = &self.gizmos else {
+            return;
+        };
+        let Some((vertex_buffer, offset, [overlay_count, tested_count, _])) =
+            gizmos.vertices[current_frame]
+        else {
+            return;
+        };
+        let Some(view_projections) = gizmos.view_projections.get(surface_index) else {
+            return;
+        };
+        if surface_index >= MAX_VIEWS as usize {
+            return;
+        }
+        let ctx = &self.surfaces[surface_index];
+        let pre_rotation = ctx.rotation.matrix();
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[offset]);
+            for (region, view_projection) in self
+                .framebuffer_regions(ctx, regions)
+                .zip(view_projections)
+                .take(MAX_VIEWPORTS as usize)
+            {
+                let view_projection: [[f32; 4]; 4] = (pre_rotation * view_projection).into();
+                device.cmd_set_viewport(command_buffer, 0, &[viewport(&region)]);
+                device.cmd_set_scissor(command_buffer, 0, &[region]);
+                device.cmd_push_constants(
+                    command_buffer,
+                    gizmos.pipeline_layout,
+                    ShaderStageFlags::VERTEX,
+                    0,
+                    bytemuck::bytes_of(&view_projection),
+                );
+                // Tested first so the overlay lines stay on top of them
+                for (pipeline, vertex_count, first_vertex) in [
+                    (gizmos.tested_pipeline, tested_count, overlay_count),
+                    (gizmos.pipeline, overlay_count, 0),
+                ] {
+                    if vertex_count > 0 {
+                        device.cmd_bind_pipeline(
+                            command_buffer,
+                            PipelineBindPoint::GRAPHICS,
+                            pipeline,
+                        );
+                        device.cmd_draw(command_buffer, vertex_count, 1, first_vertex, 0);
+                    }
+                }
+            }
+        }
+    }
+
+    // Inside the UI pass of the primary window, over the whole window at
+    // full resolution
+    pub(super) fn cmd_screen_gizmos(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        current_frame: usize,
+    ) {
+        let Some(gizmos) = &self.gizmos else {
+            return;
+        };
+        let Some((vertex_buffer, offset, [overlay_count, tested_count, screen_count])) =
+            gizmos.vertices[current_frame]
+        else {
+            return;
+        };
+        if surface_index != 0 || screen_count == 0 {
+            return;
+        }
+        let ctx = &self.surfaces[surface_index];
+        let region = self.ui_region(ctx);
+        // 0..1 onto clip space, y already points up through the viewport
+        let screen = Matrix4::from_translation(vec3(-1.0, -1.0, 0.0))
+            * Matrix4::from_nonuniform_scale(2.0, 2.0, 1.0);
+        let view_projection: [[f32; 4]; 4] = (ctx.rotation.matrix() * screen).into();
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[offset]);
+            device.cmd_set_viewport(command_buffer, 0, &[viewport(&region)]);
+            device.cmd_set_scissor(command_buffer, 0, &[region]);
+            device.cmd_push_constants(
+                command_buffer,
+                gizmos.pipeline_layout,
+                ShaderStageFlags::VERTEX,
+                0,
+                bytemuck::bytes_of(&view_projection),
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                gizmos.screen_pipeline,
+            );
+            device.cmd_draw(
+                command_buffer,
+                screen_count,
+                1,
+                overlay_count + tested_count,
+                0,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gizmo_vertices_fill_up_in_order() {
+        assert_eq!(gizmo_counts([4, 6, 2]), [4, 6, 2]);
+        // Half a line is dropped
+        assert_eq!(gizmo_counts([3, 5, 1]), [2, 4, 0]);
+        let max = MAX_GIZMO_VERTICES;
+        assert_eq!(gizmo_counts([max + 10, 8, 8]), [max, 0, 0]);
+        assert_eq!(gizmo_counts([max - 4, 8, 8]), [max - 4, 4, 0]);
+        assert_eq!(gizmo_counts([2, max, 8]), [2, max - 2, 0]);
+    }
+}
@@ -0,0 +1,273 @@
+use std::{cell::RefCell, ffi::CStr};
+
+use ash::{
+    ext::calibrated_timestamps,
+    vk::{
+        CalibratedTimestampInfoEXT, CommandBuffer, PipelineStageFlags, QueryPool,
+        QueryPoolCreateInfo, QueryResultFlags, QueryType, TimeDomainEXT,
+    },
+    Device,
+};
+use log::{info, warn};
+use tracy_client::{Client, GpuContext, GpuContextType, GpuSpan};
+
+use crate::engine::{error::VkContext, viewport::MAX_VIEWS};
+
+use super::{Configuration, MAX_FLIGHT_FENCES};
+
+// Spans timed separately within a surface's command buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum GpuZone {
+    // The whole command buffer
+    Frame,
+    Ssao,
+}
+
+const GPU_ZONES: u32 = 2;
+
+// A begin and end timestamp for every zone of every frame in flight of every
+// surface
+const QUERY_COUNT: u32 = MAX_FLIGHT_FENCES * MAX_VIEWS * GPU_ZONES * 2;
+
+// Feeds the command buffer timestamps of every surface into a Tracy GPU context
+pub struct GpuProfiler {
+    query_pool: QueryPool,
+    context: GpuContext,
+    // Zones still waiting for their timestamps, read back once the frame slot
+    // comes around again and its fence has been waited on
+    pending: RefCell<Vec<Option<GpuSpan>>>,
+}
+
+fn first_query(current_frame: usize, surface_index: usize, zone: GpuZone) -> Option<u32> {
+    if surface_index >= MAX_VIEWS as usize {
+        return None;
+    }
+    let surface = current_frame as u32 * MAX_VIEWS + surface_index as u32;
+    Some((surface * GPU_ZONES + zone as u32) * 2)
+}
+
+impl GpuProfiler {
+    fn read_back(&self, device: &Device, query: u32, span: &mut GpuSpan) {
+        let mut timestamps = [0u64; 2];
+        let available = unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                query,
+                &mut timestamps,
+                QueryResultFlags::TYPE_64,
+            )
+        };
+        // Dropping a span without timestamps parks it at the context start
+        if available.is_ok() {
+            span.upload_timestamp_start(timestamps[0] as i64);
+            span.upload_timestamp_end(timestamps[1] as i64);
+        }
+    }
+
+    fn destroy(&self, device: &Device) {
+        self.pending.borrow_mut().clear();
+        unsafe { device.destroy_query_pool(self.query_pool, None) };
+    }
+}
+
+impl Configuration {
+    pub(super) fn supports_calibrated_timestamps(&self) -> bool {
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let extension_available = unsafe {
+            instance
+                .enumerate_device_extension_properties(physical_device)
+                .unwrap()
+                .iter()
+                .any(|property| {
+                    property.extension_name_as_c_str() == Ok(calibrated_timestamps::NAME)
+                })
+        };
+        if !extension_available {
+            return false;
+        }
+        let calibrated_instance =
+            calibrated_timestamps::Instance::new(self.vulkan_entry.as_ref().unwrap(), instance);
+        unsafe {
+            calibrated_instance.get_physical_device_calibrateable_time_domains(physical_device)
+        }
+        .is_ok_and(|domains| domains.contains(&TimeDomainEXT::DEVICE))
+    }
+
+    fn calibrated_timestamps_enabled(&self) -> bool {
+        self.device_extensions
+            .iter()
+            .any(|name| unsafe { CStr::from_ptr(*name) } == calibrated_timestamps::NAME)
+    }
+
+    // The device timestamp Tracy lines the CPU timeline up against
+    fn current_gpu_timestamp(&self, query_pool: QueryPool) -> i64 {
+        let device = self.device.as_ref().unwrap();
+        if self.calibrated_timestamps_enabled() {
+            let calibrated_device =
+                calibrated_timestamps::Device::new(self.instance.as_ref().unwrap(), device);
+            let infos = [CalibratedTimestampInfoEXT::default().time_domain(TimeDomainEXT::DEVICE)];
+            if let Ok((timestamps, _)) =
+                unsafe { calibrated_device.get_calibrated_timestamps(&infos) }
+            {
+                return timestamps[0] as i64;
+            }
+        }
+
+        // Without the extension a lone timestamp is written and waited for
+        let command = self.gpu_context().begin_single_time_command();
+        unsafe {
+            device.cmd_reset_query_pool(command.command_buffer(), query_pool, 0, 1);
+            device.cmd_write_timestamp(
+                command.command_buffer(),
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                query_pool,
+                0,
+            );
+        }
+        command.submit();
+        let mut timestamp = [0u64; 1];
+        unsafe {
+            device
+                .get_query_pool_results(
+                    query_pool,
+                    0,
+                    &mut timestamp,
+                    QueryResultFlags::TYPE_64 | QueryResultFlags::WAIT,
+                )
+                .unwrap();
+        }
+        timestamp[0] as i64
+    }
+
+    pub(super) fn create_gpu_profiler(&mut self) {
+        let Some(client) = Client::running() else {
+            return;
+        };
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let graphics_family = self.queue_family_indices.unwrap().graphics_queue.unwrap();
+        let (timestamp_period, timestamp_valid_bits) = unsafe {
+            let properties = instance.get_physical_device_properties(physical_device);
+            let queue_families =
+                instance.get_physical_device_queue_family_properties(physical_device);
+            (
+                properties.limits.timestamp_period,
+                queue_families[graphics_family as usize].timestamp_valid_bits,
+            )
+        };
+        if timestamp_valid_bits == 0 {
+            warn!("The graphics queue has no timestamp support, GPU zones are disabled");
+            return;
+        }
+
+        let device = self.device.as_ref().unwrap();
+        let query_pool_info = QueryPoolCreateInfo::default()
+            .query_type(QueryType::TIMESTAMP)
+            .query_count(QUERY_COUNT);
+        let query_pool = unsafe {
+            device
+                .create_query_pool(&query_pool_info, None)
+                .vk_expect("vkCreateQueryPool", "starting the GPU profiler")
+        };
+        let gpu_timestamp = self.current_gpu_timestamp(query_pool);
+        let context = match client.new_gpu_context(
+            Some("graphics"),
+            GpuContextType::Vulkan,
+            gpu_timestamp,
+            timestamp_period,
+        ) {
+            Ok(context) => context,
+            Err(err) => {
+                warn!("Failed to create the Tracy GPU context: {err}");
+                unsafe { device.destroy_query_pool(query_pool, None) };
+                return;
+            }
+        };
+        info!(
+            "Tracy GPU zones enabled ({} ns per tick, calibrated: {})",
+            timestamp_period,
+            self.calibrated_timestamps_enabled()
+        );
+        self.gpu_profiler = Some(GpuProfiler {
+            query_pool,
+            context,
+            pending: RefCell::new((0..QUERY_COUNT / 2).map(|_| None).collect()),
+        });
+    }
+
+    // Has to be recorded outside of a render pass
+    pub(super) fn begin_gpu_zone(
+        &self,
+        command_buffer: CommandBuffer,
+        current_frame: usize,
+        surface_index: usize,
+        zone: GpuZone,
+    ) {
+        let (Some(profiler), Some(query)) = (
+            &self.gpu_profiler,
+            first_query(current_frame, surface_index, zone),
+        ) else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        let mut pending = profiler.pending.borrow_mut();
+        let slot = &mut pending[query as usize / 2];
+        if let Some(mut span) = slot.take() {
+            profiler.read_back(device, query, &mut span);
+        }
+        unsafe {
+            device.cmd_reset_query_pool(command_buffer, profiler.query_pool, query, 2);
+            device.cmd_write_timestamp(
+                command_buffer,
+                PipelineStageFlags::TOP_OF_PIPE,
+                profiler.query_pool,
+                query,
+            );
+        }
+        *slot = profiler
+            .context
+            .span_alloc(
+                &match zone {
+                    GpuZone::Frame => format!("view {surface_index}"),
+                    GpuZone::Ssao => format!("ssao {surface_index}"),
+                },
+                "record_command_buffer",
+                file!(),
+                line!(),
+            )
+            .ok();
+    }
+
+    pub(super) fn end_gpu_zone(
+        &self,
+        command_buffer: CommandBuffer,
+        current_frame: usize,
+        surface_index: usize,
+        zone: GpuZone,
+    ) {
+        let (Some(profiler), Some(query)) = (
+            &self.gpu_profiler,
+            first_query(current_frame, surface_index, zone),
+        ) else {
+            return;
+        };
+        unsafe {
+            self.device.as_ref().unwrap().cmd_write_timestamp(
+                command_buffer,
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                profiler.query_pool,
+                query + 1,
+            );
+        }
+        if let Some(span) = &mut profiler.pending.borrow_mut()[query as usize / 2] {
+            span.end_zone();
+        }
+    }
+
+    pub(super) fn destroy_gpu_profiler(&mut self) {
+        if let Some(profiler) = self.gpu_profiler.take() {
+            profiler.destroy(self.device.as_ref().unwrap());
+        }
+    }
+}
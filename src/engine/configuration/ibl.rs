@@ -0,0 +1,642 @@
+use std::{f32::consts::PI, path::Path};
+
+use anyhow::{anyhow, Error};
+use ash::vk::{
+    BufferImageCopy, BufferUsageFlags, DeviceSize, Extent3D, Filter, Format, ImageAspectFlags,
+    ImageCreateFlags, ImageCreateInfo, ImageLayout, ImageSubresourceLayers, ImageSubresourceRange,
+    ImageTiling, ImageType, ImageUsageFlags, ImageViewCreateInfo, ImageViewType,
+    MemoryAllocateInfo, MemoryPropertyFlags, SampleCountFlags, Sampler, SamplerAddressMode,
+    SamplerCreateInfo, SamplerMipmapMode, SharingMode, LOD_CLAMP_NONE,
+};
+use cgmath::{vec3, InnerSpace, Vector3, VectorSpace};
+use log::{info, warn};
+
+use crate::{engine::error::VkContext, logging};
+
+use super::{
+    asset_cache::TextureResource, buffer_types::gpu_buffer::GpuBuffer,
+    synchronization::subresource_range, textures::DecodedImage, Configuration,
+};
+
+// Roughness 0 to 1 in even steps, matched by ibl.glsl's IBL_LEVELS
+pub const PREFILTERED_LEVELS: u32 = 5;
+const PREFILTERED_SIZE: u32 = 64;
+const IRRADIANCE_SIZE: u32 = 16;
+// Faces of the environment cube the irradiance is integrated over, every
+// texel of it contributes
+const IRRADIANCE_SOURCE_SIZE: u32 = 8;
+const BRDF_LUT_SIZE: u32 = 64;
+const PREFILTER_SAMPLES: u32 = 128;
+const BRDF_SAMPLES: u32 = 256;
+
+// The sky used without a skybox, z is up
+const ZENITH: [f32; 3] = [0.25, 0.4, 0.8];
+const HORIZON: [f32; 3] = [0.8, 0.85, 0.9];
+const GROUND: [f32; 3] = [0.2, 0.18, 0.16];
+const SUN_DIRECTION: [f32; 3] = [0.4, 0.3, 0.87];
+const SUN_COLOR: [f32; 3] = [8.0, 7.2, 6.0];
+
+// Where the scene's ambient and reflected light comes from
+pub(super) enum Environment {
+    Sky,
+    // Linear RGB, longitude along x and latitude along y, z up
+    Equirectangular {
+        width: u32,
+        height: u32,
+        texels: Vec<Vector3<f32>>,
+    },
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Environment {
+    // An equirectangular PNG, the procedural sky without one or when it
+    // can't be read
+    pub fn load(path: Option<&Path>) -> Environment {
+        let Some(path) = path else {
+            return Environment::Sky;
+        };
+        match Self::read_equirectangular(path) {
+            Ok(environment) => environment,
+            Err(err) => {
+                warn!(
+                    target: logging::UPLOAD,
+                    "Lighting with the default sky, {} can't be used: {err}",
+                    path.display()
+                );
+                Environment::Sky
+            }
+        }
+    }
+
+    fn read_equirectangular(path: &Path) -> Result<Environment, Error> {
+        if !path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+        {
+            return Err(anyhow!("only equirectangular PNGs are supported"));
+        }
+        let image = DecodedImage::decode_png(path)?;
+        if image.data.len() != (image.width * image.height * 4) as usize {
+            return Err(anyhow!("expected 8 bit RGBA pixels"));
+        }
+        let texels = image
+            .data
+            .chunks_exact(4)
+            .map(|pixel| {
+                vec3(
+                    srgb_to_linear(pixel[0]),
+                    srgb_to_linear(pixel[1]),
+                    srgb_to_linear(pixel[2]),
+                )
+            })
+            .collect();
+        Ok(Environment::Equirectangular {
+            width: image.width,
+            height: image.height,
+            texels,
+        })
+    }
+
+    fn radiance(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            Environment::Sky => {
+                let up = direction.z;
+                let sky = if up >= 0.0 {
+                    Vector3::from(HORIZON).lerp(Vector3::from(ZENITH), up.sqrt())
+                } else {
+                    Vector3::from(HORIZON).lerp(Vector3::from(GROUND), (-up * 4.0).min(1.0))
+                };
+                let sun = direction
+                    .dot(Vector3::from(SUN_DIRECTION).normalize())
+                    .max(0.0)
+                    .powf(512.0);
+                sky + Vector3::from(SUN_COLOR) * sun
+            }
+            Environment::Equirectangular {
+                width,
+                height,
+                texels,
+            } => {
+                let longitude = direction.y.atan2(direction.x);
+                let latitude = direction.z.clamp(-1.0, 1.0).asin();
+                let u = 0.5 + longitude / (2.0 * PI);
+                let v = 0.5 - latitude / PI;
+                let x = ((u * *width as f32) as u32).min(width - 1);
+                let y = ((v * *height as f32) as u32).min(height - 1);
+                texels[(y * width + x) as usize]
+            }
+        }
+    }
+}
+
+// Direction through the center of a texel, faces in the +x -x +y -y +z -z
+// order of cubemap array layers
+fn face_direction(face: u32, x: u32, y: u32, size: u32) -> Vector3<f32> {
+    let s = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+    let t = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+    let direction = match face {
+        0 => vec3(1.0, -t, -s),
+        1 => vec3(-1.0, -t, s),
+        2 => vec3(s, 1.0, t),
+        3 => vec3(s, -1.0, -t),
+        4 => vec3(s, -t, 1.0),
+        _ => vec3(-s, -t, -1.0),
+    };
+    direction.normalize()
+}
+
+// RGB texels of the six faces, face after face
+struct Cube {
+    size: u32,
+    texels: Vec<Vector3<f32>>,
+}
+
+impl Cube {
+    fn from_fn(size: u32, mut texel: impl FnMut(Vector3<f32>) -> Vector3<f32>) -> Cube {
+        let texels = (0..6)
+            .flat_map(|face| (0..size * size).map(move |index| (face, index % size, index / size)))
+            .map(|(face, x, y)| texel(face_direction(face, x, y, size)))
+            .collect();
+        Cube { size, texels }
+    }
+
+    // Nearest texel, the inverse of `face_direction`
+    fn sample(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let abs = vec3(direction.x.abs(), direction.y.abs(), direction.z.abs());
+        let (face, s, t, major) = if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x > 0.0 {
+                (0, -direction.z, -direction.y, abs.x)
+            } else {
+                (1, direction.z, -direction.y, abs.x)
+            }
+        } else if abs.y >= abs.z {
+            if direction.y > 0.0 {
+                (2, direction.x, direction.z, abs.y)
+            } else {
+                (3, direction.x, -direction.z, abs.y)
+            }
+        } else if direction.z > 0.0 {
+            (4, direction.x, -direction.y, abs.z)
+        } else {
+            (5, -direction.x, -direction.y, abs.z)
+        };
+        let texel = |coordinate: f32| {
+            let texel =
+                ((coordinate / major.max(f32::EPSILON) + 1.0) * 0.5 * self.size as f32) as u32;
+            texel.min(self.size - 1)
+        };
+        let (x, y) = (texel(s), texel(t));
+        self.texels[((face * self.size + y) * self.size + x) as usize]
+    }
+
+    // Half the size, every texel the average of the four it covers
+    fn downsample(&self) -> Cube {
+        let size = (self.size / 2).max(1);
+        let texels = (0..6)
+            .flat_map(|face| (0..size * size).map(move |index| (face, index % size, index / size)))
+            .map(|(face, x, y)| {
+                let texel = |dx: u32, dy: u32| {
+                    let (sx, sy) = (
+                        (2 * x + dx).min(self.size - 1),
+                        (2 * y + dy).min(self.size - 1),
+                    );
+                    self.texels[((face * self.size + sy) * self.size + sx) as usize]
+                };
+                (texel(0, 0) + texel(1, 0) + texel(0, 1) + texel(1, 1)) * 0.25
+            })
+            .collect();
+        Cube { size, texels }
+    }
+}
+
+// Van der Corput sequence in y, evenly spread sample points
+fn hammersley(index: u32, count: u32) -> (f32, f32) {
+    (
+        index as f32 / count as f32,
+        index.reverse_bits() as f32 / 4_294_967_296.0,
+    )
+}
+
+// A GGX distributed half vector around `normal`
+fn importance_sample_ggx(xi: (f32, f32), normal: Vector3<f32>, roughness: f32) -> Vector3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.0;
+    let cos_theta = ((1.0 - xi.1) / (1.0 + (a * a - 1.0) * xi.1)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let up = if normal.z.abs() < 0.999 {
+        vec3(0.0, 0.0, 1.0)
+    } else {
+        vec3(1.0, 0.0, 0.0)
+    };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent * (phi.cos() * sin_theta) + bitangent * (phi.sin() * sin_theta) + normal * cos_theta)
+        .normalize()
+}
+
+// Cosine weighted average of every texel of `source` in the hemisphere
+// around each direction, diffuse light is this times the albedo
+fn irradiance(source: &Cube) -> Cube {
+    let size = source.size;
+    let samples = (0..6)
+        .flat_map(|face| (0..size * size).map(move |index| (face, index % size, index / size)))
+        .map(|(face, x, y)| {
+            let s = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+            let t = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+            // Solid angle of the texel, relative to its face's center
+            let solid_angle = (1.0 + s * s + t * t).powf(-1.5);
+            let index = ((face * size + y) * size + x) as usize;
+            (
+                face_direction(face, x, y, size),
+                source.texels[index],
+                solid_angle,
+            )
+        })
+        .collect::<Vec<(Vector3<f32>, Vector3<f32>, f32)>>();
+    Cube::from_fn(IRRADIANCE_SIZE, |normal| {
+        let mut sum = vec3(0.0, 0.0, 0.0);
+        let mut weight = 0.0;
+        for (direction, radiance, solid_angle) in &samples {
+            let cosine = normal.dot(*direction);
+            if cosine > 0.0 {
+                sum += radiance * (cosine * solid_angle);
+                weight += cosine * solid_angle;
+            }
+        }
+        sum / weight.max(f32::EPSILON)
+    })
+}
+
+// The environment as seen by a GGX lobe of `roughness`, with the view along
+// the normal. `source` is the environment level of about the same size, so
+// few samples don't alias
+fn prefilter(source: &Cube, size: u32, roughness: f32) -> Cube {
+    Cube::from_fn(size, |normal| {
+        let mut sum = vec3(0.0, 0.0, 0.0);
+        let mut weight = 0.0;
+        for index in 0..PREFILTER_SAMPLES {
+            let half =
+                importance_sample_ggx(hammersley(index, PREFILTER_SAMPLES), normal, roughness);
+            let light = half * (2.0 * normal.dot(half)) - normal;
+            let n_dot_l = normal.dot(light);
+            if n_dot_l > 0.0 {
+                sum += source.sample(light) * n_dot_l;
+                weight += n_dot_l;
+            }
+        }
+        sum / weight.max(f32::EPSILON)
+    })
+}
+
+// Schlick-GGX with the k of image based lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    let g = |n_dot_x: f32| n_dot_x / (n_dot_x * (1.0 - k) + k);
+    g(n_dot_v) * g(n_dot_l)
+}
+
+// Scale and bias to F0 of the specular integral, by n·v along x and
+// roughness along y
+fn brdf_lut() -> Vec<[f32; 2]> {
+    let normal = vec3(0.0, 0.0, 1.0);
+    (0..BRDF_LUT_SIZE * BRDF_LUT_SIZE)
+        .map(|index| {
+            let n_dot_v = ((index % BRDF_LUT_SIZE) as f32 + 0.5) / BRDF_LUT_SIZE as f32;
+            let roughness = ((index / BRDF_LUT_SIZE) as f32 + 0.5) / BRDF_LUT_SIZE as f32;
+            let view = vec3((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+            let (mut scale, mut bias) = (0.0, 0.0);
+            for sample in 0..BRDF_SAMPLES {
+                let half =
+                    importance_sample_ggx(hammersley(sample, BRDF_SAMPLES), normal, roughness);
+                let v_dot_h = view.dot(half).max(0.0);
+                let light = half * (2.0 * v_dot_h) - view;
+                let n_dot_l = light.z.max(0.0);
+                if n_dot_l > 0.0 {
+                    let n_dot_h = half.z.max(f32::EPSILON);
+                    let visibility =
+                        geometry_smith(n_dot_v, n_dot_l, roughness) * v_dot_h / (n_dot_h * n_dot_v);
+                    let fresnel = (1.0 - v_dot_h).powi(5);
+                    scale += (1.0 - fresnel) * visibility;
+                    bias += fresnel * visibility;
+                }
+            }
+            [scale / BRDF_SAMPLES as f32, bias / BRDF_SAMPLES as f32]
+        })
+        .collect()
+}
+
+// Rounds to the nearest half float, too large values become infinity
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent >= 31 {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = (mantissa | 0x80_0000) >> (1 - exponent);
+        return sign | ((mantissa + 0x1000) >> 13) as u16;
+    }
+    sign | (((exponent as u32) << 10) + ((mantissa + 0x1000) >> 13)) as u16
+}
+
+// Half float texels ready to upload, every level with all of its layers
+pub(super) struct BakedImage {
+    size: u32,
+    // Six for cubemaps
+    layers: u32,
+    format: Format,
+    // Largest first, layer after layer within a level
+    levels: Vec<Vec<u16>>,
+}
+
+impl BakedImage {
+    fn cube(levels: &[Cube]) -> BakedImage {
+        BakedImage {
+            size: levels[0].size,
+            layers: 6,
+            format: Format::R16G16B16A16_SFLOAT,
+            levels: levels
+                .iter()
+                .map(|cube| {
+                    cube.texels
+                        .iter()
+                        .flat_map(|texel| [texel.x, texel.y, texel.z, 1.0].map(f16_bits))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+// The three image based lighting inputs, baked on the CPU
+pub(super) struct EnvironmentMaps {
+    irradiance: BakedImage,
+    prefiltered: BakedImage,
+    brdf_lut: BakedImage,
+}
+
+impl EnvironmentMaps {
+    // Slow in debug builds, meant to run on a worker thread
+    pub fn bake(environment: &Environment, intensity: f32) -> EnvironmentMaps {
+        // Supersampled so the sun isn't lost between texel centers
+        let base = Cube::from_fn(PREFILTERED_SIZE * 2, |direction| {
+            environment.radiance(direction) * intensity
+        })
+        .downsample();
+        let mut levels = vec![base];
+        while levels.len() < PREFILTERED_LEVELS as usize {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+        let irradiance_source = levels
+            .iter()
+            .find(|level| level.size <= IRRADIANCE_SOURCE_SIZE)
+            .map_or_else(
+                || levels.last().unwrap().downsample(),
+                |level| Cube {
+                    size: level.size,
+                    texels: level.texels.clone(),
+                },
+            );
+        let prefiltered = levels
+            .iter()
+            .enumerate()
+            .map(|(level, source)| {
+                let roughness = level as f32 / (PREFILTERED_LEVELS - 1) as f32;
+                if level == 0 {
+                    // A mirror reflects the environment as is
+                    Cube {
+                        size: source.size,
+                        texels: source.texels.clone(),
+                    }
+                } else {
+                    prefilter(source, source.size, roughness)
+                }
+            })
+            .collect::<Vec<Cube>>();
+        EnvironmentMaps {
+            irradiance: BakedImage::cube(&[irradiance(&irradiance_source)]),
+            prefiltered: BakedImage::cube(&prefiltered),
+            brdf_lut: BakedImage {
+                size: BRDF_LUT_SIZE,
+                layers: 1,
+                format: Format::R16G16_SFLOAT,
+                levels: vec![brdf_lut()
+                    .into_iter()
+                    .flat_map(|texel| texel.map(f16_bits))
+                    .collect()],
+            },
+        }
+    }
+}
+
+// What the forward shader samples for ambient and reflected light
+pub(super) struct IblMaps {
+    pub irradiance: TextureResource,
+    pub prefiltered: TextureResource,
+    pub brdf_lut: TextureResource,
+    pub sampler: Sampler,
+}
+
+impl Configuration {
+    // Bakes the maps here unless they were already baked during
+    // `preload_assets`
+    pub fn create_environment_maps(&mut self) -> Result<&mut Configuration, Error> {
+        let maps = match self.preload.take_environment() {
+            Some(job) => job.join(),
+            None => EnvironmentMaps::bake(
+                &Environment::load(self.config.skybox_path.as_deref()),
+                self.config.environment_intensity,
+            ),
+        };
+        let device = self.device.as_ref().unwrap();
+        let sampler_info = SamplerCreateInfo::default()
+            .mag_filter(Filter::LINEAR)
+            .min_filter(Filter::LINEAR)
+            .mipmap_mode(SamplerMipmapMode::LINEAR)
+            .address_mode_u(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(LOD_CLAMP_NONE);
+        let sampler = unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .vk_ctx("vkCreateSampler", "creating the environment maps")?
+        };
+        self.ibl = Some(IblMaps {
+            irradiance: self.upload_baked(&maps.irradiance)?,
+            prefiltered: self.upload_baked(&maps.prefiltered)?,
+            brdf_lut: self.upload_baked(&maps.brdf_lut)?,
+            sampler,
+        });
+        info!(
+            target: logging::UPLOAD,
+            "Environment maps have been created ({} roughness levels)", PREFILTERED_LEVELS
+        );
+        Ok(self)
+    }
+
+    // 2D or cubemap image with every level of `baked`, in
+    // SHADER_READ_ONLY_OPTIMAL
+    fn upload_baked(&self, baked: &BakedImage) -> Result<TextureResource, Error> {
+        let device = self.device.as_ref().unwrap();
+        let data = baked.levels.concat();
+        let mut staging = GpuBuffer::host_visible(
+            &self.gpu_context(),
+            data.len(),
+            BufferUsageFlags::TRANSFER_SRC,
+        )?;
+        staging.write(&data);
+
+        let cube = baked.layers == 6;
+        let image_info = ImageCreateInfo::default()
+            .flags(if cube {
+                ImageCreateFlags::CUBE_COMPATIBLE
+            } else {
+                ImageCreateFlags::empty()
+            })
+            .image_type(ImageType::TYPE_2D)
+            .extent(Extent3D {
+                width: baked.size,
+                height: baked.size,
+                depth: 1,
+            })
+            .mip_levels(baked.levels.len() as u32)
+            .array_layers(baked.layers)
+            .format(baked.format)
+            .tiling(ImageTiling::OPTIMAL)
+            .initial_layout(ImageLayout::UNDEFINED)
+            .usage(ImageUsageFlags::TRANSFER_DST | ImageUsageFlags::SAMPLED)
+            .samples(SampleCountFlags::TYPE_1)
+            .sharing_mode(SharingMode::EXCLUSIVE);
+        let detail = format!(
+            "uploading a {size}x{size} {:?} environment map",
+            baked.format,
+            size = baked.size
+        );
+        let (image, memory) = unsafe {
+            let image = device
+                .create_image(&image_info, None)
+                .vk_ctx("vkCreateImage", &detail)?;
+            let requirements = device.get_image_memory_requirements(image);
+            let allocate_info = MemoryAllocateInfo::default()
+                .allocation_size(requirements.size)
+                .memory_type_index(
+                    Self::find_memory_type(
+                        self.instance.as_ref().unwrap(),
+                        self.physical_device.unwrap(),
+                        requirements.memory_type_bits,
+                        MemoryPropertyFlags::DEVICE_LOCAL,
+                    )
+                    .ok_or_else(|| anyhow!("No device local memory for an environment map"))?,
+                );
+            let memory = device
+                .allocate_memory(&allocate_info, None)
+                .vk_ctx("vkAllocateMemory", &detail)?;
+            device
+                .bind_image_memory(image, memory, 0)
+                .vk_ctx("vkBindImageMemory", &detail)?;
+            (image, memory)
+        };
+
+        let range = subresource_range(ImageAspectFlags::COLOR, 0, baked.levels.len() as u32);
+        self.transition_image_layout(
+            image,
+            ImageLayout::UNDEFINED,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            range,
+            None,
+        )
+        .map_err(Error::msg)?;
+        let mut offset = 0;
+        let regions = baked
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, texels)| {
+                let size = (baked.size >> level).max(1);
+                let region = BufferImageCopy::default()
+                    .buffer_offset(offset as DeviceSize)
+                    .image_subresource(
+                        ImageSubresourceLayers::default()
+                            .aspect_mask(ImageAspectFlags::COLOR)
+                            .mip_level(level as u32)
+                            .base_array_layer(0)
+                            .layer_count(baked.layers),
+                    )
+                    .image_extent(Extent3D {
+                        width: size,
+                        height: size,
+                        depth: 1,
+                    });
+                offset += size_of_val(texels.as_slice());
+                region
+            })
+            .collect::<Vec<BufferImageCopy>>();
+        self.copy_buffer_to_image(staging.buffer(), image, &regions);
+        self.transition_image_layout(
+            image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            range,
+            None,
+        )
+        .map_err(Error::msg)?;
+
+        let view_info = ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(if cube {
+                ImageViewType::CUBE
+            } else {
+                ImageViewType::TYPE_2D
+            })
+            .format(baked.format)
+            .subresource_range(
+                ImageSubresourceRange::default()
+                    .aspect_mask(ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(baked.levels.len() as u32)
+                    .base_array_layer(0)
+                    .layer_count(baked.layers),
+            );
+        let view = unsafe {
+            device
+                .create_image_view(&view_info, None)
+                .vk_ctx("vkCreateImageView", &detail)?
+        };
+        Ok(TextureResource {
+            image,
+            memory,
+            view,
+            size: size_of_val(data.as_slice()) as DeviceSize,
+            deletion_queue: self.deletion_queue.clone(),
+        })
+    }
+
+    pub(super) fn destroy_environment_maps(&mut self) {
+        if let Some(ibl) = self.ibl.take() {
+            unsafe {
+                self.device
+                    .as_ref()
+                    .unwrap()
+                    .destroy_sampler(ibl.sampler, None)
+            };
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    panic,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use anyhow::Error;
+use log::debug;
+
+use crate::logging;
+
+use super::{
+    ibl::{Environment, EnvironmentMaps},
+    mesh::Mesh,
+    textures::DecodedImage,
+    Configuration,
+};
+
+// Work running on a thread of its own until `join` is called where its
+// result is first needed. A panic in the job carries over to the joining
+// thread
+pub(super) struct Job<T> {
+    name: String,
+    handle: JoinHandle<T>,
+}
+
+impl<T: Send + 'static> Job<T> {
+    pub fn spawn(name: &str, work: impl FnOnce() -> T + Send + 'static) -> Job<T> {
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(work)
+            .expect("Failed to spawn a job thread");
+        Job {
+            name: name.to_string(),
+            handle,
+        }
+    }
+
+    pub fn join(self) -> T {
+        let start = Instant::now();
+        let result = self
+            .handle
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic));
+        debug!(
+            target: logging::UPLOAD,
+            "Waited {:.1} ms for {}",
+            start.elapsed().as_secs_f64() * 1000.0,
+            self.name
+        );
+        result
+    }
+}
+
+// The startup model and texture, read and decoded while the instance, device
+// and swapchain are created. Only the GPU uploads wait for them
+#[derive(Default)]
+pub(super) struct Preload {
+    model: Option<(PathBuf, Job<Result<Mesh, Error>>)>,
+    texture: Option<(PathBuf, Job<Result<DecodedImage, std::io::Error>>)>,
+    environment: Option<Job<EnvironmentMaps>>,
+}
+
+impl Preload {
+    // None once taken, or when `path` isn't what was preloaded
+    pub fn take_model(&mut self, path: &Path) -> Option<Job<Result<Mesh, Error>>> {
+        self.model
+            .take()
+            .filter(|(preloaded, _)| preloaded == path)
+            .map(|(_, job)| job)
+    }
+
+    pub fn take_texture(
+        &mut self,
+        path: &Path,
+    ) -> Option<Job<Result<DecodedImage, std::io::Error>>> {
+        self.texture
+            .take()
+            .filter(|(preloaded, _)| preloaded == path)
+            .map(|(_, job)| job)
+    }
+
+    pub fn take_environment(&mut self) -> Option<Job<EnvironmentMaps>> {
+        self.environment.take()
+    }
+}
+
+impl Configuration {
+    // Starts reading the configured model, texture and every shader and
+    // baking the environment maps, has to come before the device is created
+    // to overlap with it
+    pub fn preload_assets(&mut self) -> &mut Configuration {
+        let model_path = self.config.model_path.clone();
+        let texture_path = self.config.texture_path.clone();
+        let model = Job::spawn("preload-model", {
+            let path = model_path.clone();
+            move || Self::read_mesh(&path)
+        });
+        let texture = Job::spawn("preload-texture", {
+            let path = texture_path.clone();
+            move || DecodedImage::read(&path)
+        });
+        let skybox_path = self.config.skybox_path.clone();
+        let intensity = self.config.environment_intensity;
+        let environment = Job::spawn("preload-environment", move || {
+            EnvironmentMaps::bake(&Environment::load(skybox_path.as_deref()), intensity)
+        });
+        self.preload = Preload {
+            model: Some((model_path, model)),
+            texture: Some((texture_path, texture)),
+            environment: Some(environment),
+        };
+        self.shader_cache.preload(super::STARTUP_SHADERS);
+        self
+    }
+}
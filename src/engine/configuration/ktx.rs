@@ -0,0 +1,73 @@
+use std::io::{Error, ErrorKind};
+
+use ash::vk::Format;
+
+use super::textures::{DecodedImage, MipLevel};
+
+// Block compressed formats read from KTX2 files
+pub(super) const COMPRESSED_FORMATS: [Format; 9] = [
+    Format::BC1_RGB_UNORM_BLOCK,
+    Format::BC1_RGB_SRGB_BLOCK,
+    Format::BC1_RGBA_UNORM_BLOCK,
+    Format::BC1_RGBA_SRGB_BLOCK,
+    Format::BC3_UNORM_BLOCK,
+    Format::BC3_SRGB_BLOCK,
+    Format::BC5_UNORM_BLOCK,
+    Format::BC7_UNORM_BLOCK,
+    Format::BC7_SRGB_BLOCK,
+];
+
+// Uncompressed formats any device can sample from
+pub(super) const RGBA_FORMATS: [Format; 2] = [Format::R8G8B8A8_UNORM, Format::R8G8B8A8_SRGB];
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+// Only single 2D images without supercompression, with the mip levels the
+// file comes with
+pub(super) fn decode_ktx2(bytes: &[u8]) -> Result<DecodedImage, Error> {
+    let reader = ktx2::Reader::new(bytes).map_err(|err| invalid(err.to_string()))?;
+    let header = reader.header();
+    if let Some(scheme) = header.supercompression_scheme {
+        return Err(invalid(format!("unsupported supercompression {scheme:?}")));
+    }
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        return Err(invalid("only 2D textures are supported"));
+    }
+    let format = header
+        .format
+        .map(|format| Format::from_raw(format.value() as i32))
+        .filter(|format| COMPRESSED_FORMATS.contains(format) || RGBA_FORMATS.contains(format))
+        .ok_or_else(|| invalid(format!("unsupported format {:?}", header.format)))?;
+    let height = header.pixel_height.max(1);
+
+    let mut data = Vec::new();
+    let mut levels = Vec::new();
+    for (index, level) in reader.levels().enumerate() {
+        let mip = MipLevel {
+            offset: data.len(),
+            width: (header.pixel_width >> index).max(1),
+            height: (height >> index).max(1),
+        };
+        let size = mip.size(format);
+        if level.data.len() < size {
+            return Err(invalid(format!(
+                "mip level {index} has {} bytes, expected {size}",
+                level.data.len()
+            )));
+        }
+        data.extend_from_slice(&level.data[..size]);
+        levels.push(mip);
+    }
+    if levels.is_empty() {
+        return Err(invalid("no mip levels"));
+    }
+    Ok(DecodedImage {
+        width: header.pixel_width,
+        height,
+        format,
+        data,
+        levels,
+    })
+}
@@ -0,0 +1,78 @@
+use ash::vk::{DescriptorBufferInfo, DescriptorType, WriteDescriptorSet};
+use bytemuck::{Pod, Zeroable};
+
+use super::Configuration;
+use crate::engine::scene::PointLight;
+
+// Lights beyond this are dropped
+pub const MAX_LIGHTS: usize = 1024;
+// The light count, padded to the std430 alignment of the array after it
+const LIGHTS_HEADER: usize = 16;
+// Upload ring bytes the lights of one frame take at most
+pub(super) const LIGHTS_SIZE: usize = LIGHTS_HEADER + MAX_LIGHTS * size_of::<GpuLight>();
+
+// lights.glsl's PointLight
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpuLight {
+    position_radius: [f32; 4],
+    color: [f32; 4],
+}
+
+unsafe impl Zeroable for GpuLight {}
+unsafe impl Pod for GpuLight {}
+
+impl Configuration {
+    // Uploaded through the ring, the scene set and every G-buffer set of the
+    // frame slot are pointed at them right away
+    pub fn update_lights(&mut self, current_frame: usize, lights: &[PointLight]) {
+        let lights = &lights[..lights.len().min(MAX_LIGHTS)];
+        let mut data = vec![0u8; LIGHTS_HEADER + lights.len() * size_of::<GpuLight>()];
+        data[..4].copy_from_slice(&(lights.len() as u32).to_ne_bytes());
+        for (light, bytes) in lights
+            .iter()
+            .zip(data[LIGHTS_HEADER..].chunks_exact_mut(size_of::<GpuLight>()))
+        {
+            let [r, g, b] = light.color;
+            let gpu_light = GpuLight {
+                position_radius: [
+                    light.position.x,
+                    light.position.y,
+                    light.position.z,
+                    light.radius,
+                ],
+                color: [r, g, b, light.intensity],
+            };
+            bytes.copy_from_slice(bytemuck::bytes_of(&gpu_light));
+        }
+        let Some(range) = self
+            .upload_ring
+            .as_mut()
+            .and_then(|ring| ring.push_bound(current_frame, &data))
+        else {
+            return;
+        };
+        self.light_ranges[current_frame] = range;
+        if let Some(&descriptor_set) = self.descriptor_sets.get(current_frame) {
+            let buffer_info = [range.info()];
+            let write = WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(2)
+                .dst_array_element(0)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_info);
+            unsafe {
+                self.device
+                    .as_ref()
+                    .unwrap()
+                    .update_descriptor_sets(&[write], &[])
+            };
+        }
+        self.write_gbuffer_lights(current_frame);
+    }
+
+    // Where the frame slot's lights were last uploaded
+    pub(super) fn light_buffer_info(&self, frame: usize) -> DescriptorBufferInfo {
+        self.light_ranges[frame].info()
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    io::{BufReader, Cursor},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Error};
+use ash::vk::{
+    Buffer, BufferUsageFlags, CommandBuffer, DescriptorBufferInfo, DescriptorImageInfo,
+    DescriptorSet, DescriptorType, DeviceMemory, ImageLayout, PipelineBindPoint, ShaderStageFlags,
+    WriteDescriptorSet, WHOLE_SIZE,
+};
+use bytemuck::{Pod, Zeroable};
+use log::{debug, info, warn};
+
+use super::{
+    asset_cache::TextureResource,
+    bindless::BindlessTable,
+    buffer_types::gpu_buffer::GpuBuffer,
+    deletion_queue::{DeletionQueue, PendingDeletion},
+    descriptor_allocator::DescriptorAllocator,
+    render_queue::{RenderQueue, RENDER_QUEUES},
+    textures::TextureSlot,
+    Configuration, MAX_FLIGHT_FENCES,
+};
+use crate::{
+    engine::material::Material,
+    logging,
+    utils::{self, embedded::RgbaImage},
+};
+
+// material.glsl's set 1
+pub(super) const MATERIAL_DESCRIPTOR_BINDINGS: [(u32, DescriptorType); 6] = [
+    (0, DescriptorType::UNIFORM_BUFFER),
+    // Base color, metallic-roughness, normal, occlusion and emissive
+    (1, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (2, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (3, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (4, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (5, DescriptorType::COMBINED_IMAGE_SAMPLER),
+];
+const MATERIAL_SETS_PER_POOL: u32 = 64;
+// Where the bindless material index follows the highlight tint in the
+// fragment push constants
+const MATERIAL_INDEX_OFFSET: u32 = 16;
+
+// What objects without a material of their own are drawn with, a rough
+// dielectric in the model texture
+const DEFAULT_METALLIC: f32 = 0.0;
+const DEFAULT_ROUGHNESS: f32 = 0.6;
+
+// material.glsl's MaterialData
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct GpuMaterial {
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    // Metallic, roughness, normal scale and occlusion strength
+    factors: [f32; 4],
+    // x multiplies the base color with the model texture
+    flags: [u32; 4],
+    // Bindless texture slots in material.glsl's order, unused otherwise
+    textures: [[u32; 4]; 2],
+}
+
+unsafe impl Zeroable for GpuMaterial {}
+unsafe impl Pod for GpuMaterial {}
+
+// A material's uniform block and descriptor set, or its index into the
+// bindless table. The textures are held so they outlive the set. Sets and
+// table slots aren't handed back when the material is dropped, only its
+// buffer is freed
+#[derive(Debug)]
+pub struct MaterialResource {
+    descriptor_set: DescriptorSet,
+    index: u32,
+    order: MaterialOrder,
+    buffer: Option<(Buffer, DeviceMemory)>,
+    _textures: Vec<Arc<TextureResource>>,
+    deletion_queue: DeletionQueue,
+}
+
+impl MaterialResource {
+    pub fn descriptor_set(&self) -> DescriptorSet {
+        self.descriptor_set
+    }
+
+    // The material's slot in the bindless table, 0 without one
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn queue(&self) -> RenderQueue {
+        self.order.queue
+    }
+}
+
+impl Drop for MaterialResource {
+    fn drop(&mut self) {
+        if let Some((buffer, memory)) = self.buffer {
+            self.deletion_queue
+                .push(PendingDeletion::Buffer { buffer, memory });
+        }
+    }
+}
+
+// What the draws of an object are sorted by. Ids are handed out in
+// creation order, the default material's is 0
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct MaterialOrder {
+    pub(super) queue: RenderQueue,
+    pub(super) priority: u16,
+    pub(super) id: u32,
+}
+
+pub(super) struct Materials {
+    // Only freed with the engine, reload_assets resets the scene's allocator
+    allocator: DescriptorAllocator,
+    // Bound in place of missing textures
+    white: Arc<TextureResource>,
+    flat_normal: Arc<TextureResource>,
+    default: Arc<MaterialResource>,
+    // Bound once per frame in place of the per material sets
+    bindless: Option<BindlessTable>,
+    // Per frame in flight and object, the set and table index it is drawn
+    // with, and how its draws are sorted
+    frame_sets: Vec<Vec<(DescriptorSet, u32)>>,
+    frame_orders: Vec<Vec<MaterialOrder>>,
+    next_id: u32,
+}
+
+fn solid(pixel: [u8; 4]) -> RgbaImage {
+    RgbaImage {
+        width: 1,
+        height: 1,
+        pixels: pixel.to_vec(),
+    }
+}
+
+impl Configuration {
+    pub fn create_materials(&mut self) -> Result<&mut Configuration, Error> {
+        // White reads the same in either color space
+        let white = Arc::new(self.upload_rgba(
+            &solid([0xff; 4]),
+            TextureSlot::OcclusionRoughnessMetalness.color_space(),
+        )?);
+        let flat_normal = Arc::new(self.upload_rgba(
+            &solid([0x80, 0x80, 0xff, 0xff]),
+            TextureSlot::Normal.color_space(),
+        )?);
+        let mut allocator = DescriptorAllocator::new(
+            MATERIAL_SETS_PER_POOL,
+            vec![
+                (DescriptorType::UNIFORM_BUFFER, 1.0),
+                (DescriptorType::COMBINED_IMAGE_SAMPLER, 5.0),
+            ],
+        );
+        let mut bindless = match self.bindless_capacity {
+            Some(capacity) => Some(self.create_bindless_table(capacity)?),
+            None => None,
+        };
+        let default = Material {
+            metallic_factor: DEFAULT_METALLIC,
+            roughness_factor: DEFAULT_ROUGHNESS,
+            ..Default::default()
+        };
+        let default = self.upload_material(
+            (&mut allocator, bindless.as_mut()),
+            [&white, &flat_normal],
+            (&default, 0),
+            true,
+        )?;
+        self.materials = Some(Materials {
+            allocator,
+            white,
+            flat_normal,
+            default: Arc::new(default),
+            bindless,
+            frame_sets: vec![Vec::new(); MAX_FLIGHT_FENCES as usize],
+            frame_orders: vec![Vec::new(); MAX_FLIGHT_FENCES as usize],
+            next_id: 1,
+        });
+        info!(target: logging::UPLOAD, "Default material has been created");
+        Ok(self)
+    }
+
+    pub fn create_material(&mut self, material: &Material) -> Result<Arc<MaterialResource>, Error> {
+        let mut materials = self
+            .materials
+            .take()
+            .ok_or_else(|| anyhow!("Materials haven't been created"))?;
+        let resource = self.upload_material(
+            (&mut materials.allocator, materials.bindless.as_mut()),
+            [&materials.white, &materials.flat_normal],
+            (material, materials.next_id),
+            false,
+        );
+        materials.next_id += 1;
+        self.materials = Some(materials);
+        Ok(Arc::new(resource?))
+    }
+
+    // Writes the uniform block or table entry once, materials don't change
+    // after creation
+    fn upload_material(
+        &self,
+        (allocator, bindless): (&mut DescriptorAllocator, Option<&mut BindlessTable>),
+        [white, flat_normal]: [&Arc<TextureResource>; 2],
+        (material, id): (&Material, u32),
+        model_texture: bool,
+    ) -> Result<MaterialResource, Error> {
+        let order = MaterialOrder {
+            queue: material.queue,
+            priority: material.priority,
+            id,
+        };
+        let [r, g, b] = material.emissive_factor;
+        let textures = [
+            (&material.base_color_texture, white),
+            (&material.metallic_roughness_texture, white),
+            (&material.normal_texture, flat_normal),
+            (&material.occlusion_texture, white),
+            (&material.emissive_texture, white),
+        ]
+        .map(|(texture, fallback)| texture.as_ref().unwrap_or(fallback).clone());
+        let mut block = GpuMaterial {
+            base_color: material.base_color_factor,
+            emissive: [r, g, b, 0.0],
+            factors: [
+                material.metallic_factor.clamp(0.0, 1.0),
+                material.roughness_factor.clamp(0.0, 1.0),
+                material.normal_scale,
+                material.occlusion_strength.clamp(0.0, 1.0),
+            ],
+            flags: [model_texture as u32, 0, 0, 0],
+            textures: [[0; 4]; 2],
+        };
+
+        if let Some(table) = bindless {
+            for (slot, texture) in textures.iter().enumerate() {
+                block.textures[slot / 4][slot % 4] = self.register_texture(table, texture)?;
+            }
+            let index = table.push_material(block)?;
+            debug!("Material {index} has been created: {material:?}");
+            return Ok(MaterialResource {
+                descriptor_set: table.set,
+                index,
+                order,
+                buffer: None,
+                _textures: textures.into(),
+                deletion_queue: self.deletion_queue.clone(),
+            });
+        }
+
+        let buffer = GpuBuffer::device_local(
+            &self.gpu_context(),
+            &[block],
+            BufferUsageFlags::UNIFORM_BUFFER,
+        )
+        .map_err(Error::msg)?;
+
+        let device = self.device.as_ref().unwrap();
+        let descriptor_set = allocator.allocate(device, &[self.descriptor_set_layout[1]])?[0];
+        let buffer_info = [DescriptorBufferInfo::default()
+            .buffer(buffer.buffer())
+            .offset(0)
+            .range(WHOLE_SIZE)];
+        let image_infos = textures.each_ref().map(|texture| {
+            [DescriptorImageInfo::default()
+                .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(texture.view())
+                .sampler(self.texture_sampler)]
+        });
+        let writes = std::iter::once(
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_info),
+        )
+        .chain(image_infos.iter().enumerate().map(|(index, image_info)| {
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(index as u32 + 1)
+                .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info)
+        }))
+        .collect::<Vec<WriteDescriptorSet>>();
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+        debug!("Material has been created: {material:?}");
+        Ok(MaterialResource {
+            descriptor_set,
+            index: 0,
+            order,
+            buffer: Some(buffer.into_raw()),
+            _textures: textures.into(),
+            deletion_queue: self.deletion_queue.clone(),
+        })
+    }
+
+    // Called with the objects' materials before the frame is recorded, None
+    // draws with the default material. The pipelines of queues used for the
+    // first time are created here
+    pub fn update_materials(
+        &mut self,
+        current_frame: usize,
+        object_materials: &[Option<Arc<MaterialResource>>],
+    ) {
+        let Some(materials) = self.materials.as_mut() else {
+            return;
+        };
+        let default = &materials.default;
+        let mut queues = [false; RENDER_QUEUES];
+        if let (Some(sets), Some(orders)) = (
+            materials.frame_sets.get_mut(current_frame),
+            materials.frame_orders.get_mut(current_frame),
+        ) {
+            sets.clear();
+            orders.clear();
+            for material in object_materials {
+                let material = material.as_ref().unwrap_or(default);
+                sets.push((material.descriptor_set, material.index));
+                orders.push(material.order);
+                queues[material.order.queue as usize] = true;
+            }
+        }
+        self.create_queue_pipelines(queues);
+    }
+
+    // How the object's draws are sorted, by the default material for
+    // objects that weren't listed this frame
+    pub(super) fn object_order(&self, current_frame: usize, object_index: u32) -> MaterialOrder {
+        let Some(materials) = self.materials.as_ref() else {
+            return MaterialOrder::default();
+        };
+        materials
+            .frame_orders
+            .get(current_frame)
+            .and_then(|orders| orders.get(object_index as usize))
+            .copied()
+            .unwrap_or(materials.default.order)
+    }
+
+    // The set and table index the object is drawn with, the default
+    // material's for objects that weren't listed this frame
+    pub(super) fn object_material(
+        &self,
+        current_frame: usize,
+        object_index: u32,
+    ) -> Option<(DescriptorSet, u32)> {
+        let materials = self.materials.as_ref()?;
+        Some(
+            materials
+                .frame_sets
+                .get(current_frame)
+                .and_then(|sets| sets.get(object_index as usize))
+                .copied()
+                .unwrap_or((materials.default.descriptor_set, materials.default.index)),
+        )
+    }
+
+    // Binds the bindless table for the whole frame, a no-op with per
+    // material sets
+    pub(super) fn cmd_bind_material_table(&self, command_buffer: CommandBuffer) {
+        let Some(table) = self
+            .materials
+            .as_ref()
+            .and_then(|materials| materials.bindless.as_ref())
+        else {
+            return;
+        };
+        unsafe {
+            self.device.as_ref().unwrap().cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                1,
+                &[table.set],
+                &[],
+            );
+        }
+    }
+
+    // With the bindless table only the index is pushed, after the highlight
+    // tint
+    pub(super) fn cmd_bind_material(
+        &self,
+        command_buffer: CommandBuffer,
+        current_frame: usize,
+        object_index: u32,
+    ) {
+        let Some((descriptor_set, index)) = self.object_material(current_frame, object_index)
+        else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        if self
+            .materials
+            .as_ref()
+            .is_some_and(|materials| materials.bindless.is_some())
+        {
+            unsafe {
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    ShaderStageFlags::FRAGMENT,
+                    MATERIAL_INDEX_OFFSET,
+                    bytemuck::bytes_of(&index),
+                );
+            }
+            return;
+        }
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                1,
+                &[descriptor_set],
+                &[],
+            );
+        }
+    }
+
+    // Maps the materials of an MTL file onto metallic-roughness, with the
+    // PBR extension's Pr, Pm, Ke, map_Pr, map_Pm, map_Ke and norm where
+    // present. Textures are loaded relative to the file through the asset
+    // cache
+    pub fn load_mtl_materials(&mut self, path: &Path) -> Result<Vec<(String, Material)>, Error> {
+        let mut reader = BufReader::new(Cursor::new(utils::io::read_asset(path)?));
+        let (mtl_materials, _) = tobj::load_mtl_buf(&mut reader)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let mut materials = Vec::with_capacity(mtl_materials.len());
+        for mtl in mtl_materials {
+            let param = |key: &str| mtl.unknown_param.get(key).map(String::as_str);
+            let factor = |key: &str| param(key).and_then(|value| value.trim().parse::<f32>().ok());
+            let mut texture = |file: Option<&str>, slot: TextureSlot| {
+                let file = file.map(str::trim).filter(|file| !file.is_empty())?;
+                let texture_path = directory.join(file);
+                match self.load_texture(&texture_path, slot.color_space()) {
+                    Ok(texture) => Some(texture),
+                    Err(err) => {
+                        warn!(
+                            target: logging::UPLOAD,
+                            "Material {} drops {}: {err}",
+                            mtl.name,
+                            texture_path.display()
+                        );
+                        None
+                    }
+                }
+            };
+            let emissive = param("Ke")
+                .map(|value| {
+                    value
+                        .split_whitespace()
+                        .filter_map(|part| part.parse::<f32>().ok())
+                        .collect::<Vec<f32>>()
+                })
+                .filter(|parts| parts.len() == 3)
+                .map_or(
+                    // An emissive map alone glows at its own color
+                    if param("map_Ke").is_some() {
+                        [1.0; 3]
+                    } else {
+                        [0.0; 3]
+                    },
+                    |parts| [parts[0], parts[1], parts[2]],
+                );
+            // Without Pr the Phong exponent stands in, 0 to 1000 mapped onto
+            // rough to smooth
+            let roughness = factor("Pr")
+                .unwrap_or_else(|| 1.0 - (mtl.shininess.clamp(0.0, 1000.0) / 1000.0).sqrt());
+            let normal_file = param("norm").or(Some(mtl.normal_texture.as_str()));
+            let material = Material {
+                base_color_factor: [mtl.diffuse[0], mtl.diffuse[1], mtl.diffuse[2], mtl.dissolve],
+                metallic_factor: factor("Pm").unwrap_or(0.0),
+                roughness_factor: roughness,
+                emissive_factor: emissive,
+                base_color_texture: texture(Some(&mtl.diffuse_texture), TextureSlot::Albedo),
+                metallic_roughness_texture: texture(
+                    param("map_Pr").or(param("map_Pm")),
+                    TextureSlot::OcclusionRoughnessMetalness,
+                ),
+                normal_texture: texture(normal_file, TextureSlot::Normal),
+                occlusion_texture: texture(
+                    Some(&mtl.ambient_texture),
+                    TextureSlot::OcclusionRoughnessMetalness,
+                ),
+                emissive_texture: texture(param("map_Ke"), TextureSlot::Emissive),
+                // Partly dissolved surfaces are blended over the others
+                queue: if mtl.dissolve < 1.0 {
+                    RenderQueue::Transparent
+                } else {
+                    RenderQueue::Opaque
+                },
+                ..Default::default()
+            };
+            materials.push((mtl.name.clone(), material));
+        }
+        info!(
+            target: logging::UPLOAD,
+            "Loaded {} materials from {}",
+            materials.len(),
+            path.display()
+        );
+        Ok(materials)
+    }
+
+    pub(super) fn destroy_materials(&mut self) {
+        if let Some(mut materials) = self.materials.take() {
+            let device = self.device.as_ref().unwrap();
+            materials.allocator.destroy(device);
+            if let Some(table) = materials.bindless {
+                table.destroy(device);
+            }
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+
+use ash::vk::IndexType;
+use cgmath::{EuclideanSpace, InnerSpace, Point3};
+
+use crate::engine::{
+    mesh::{simplify::simplify_lods, CpuMeshData},
+    scene::Aabb,
+};
+
+use super::buffer_types::vertex::Vertex;
+
+#[derive(Debug, Clone)]
+pub enum MeshIndices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Default for MeshIndices {
+    fn default() -> Self {
+        MeshIndices::U16(Vec::new())
+    }
+}
+
+impl MeshIndices {
+    pub fn new(indices: Vec<u32>, vertex_count: usize) -> Self {
+        if vertex_count <= u16::MAX as usize + 1 {
+            MeshIndices::U16(indices.into_iter().map(|index| index as u16).collect())
+        } else {
+            MeshIndices::U32(indices)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            MeshIndices::U16(indices) => indices.len(),
+            MeshIndices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            MeshIndices::U16(indices) => bytemuck::cast_slice(indices),
+            MeshIndices::U32(indices) => bytemuck::cast_slice(indices),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = u32> + '_> {
+        match self {
+            MeshIndices::U16(indices) => Box::new(indices.iter().map(|index| *index as u32)),
+            MeshIndices::U32(indices) => Box::new(indices.iter().copied()),
+        }
+    }
+
+    // These indices followed by the `lods`, in the same index type
+    pub fn with_lods(&self, lods: &[Vec<u32>]) -> Self {
+        let lods = lods.iter().flatten().copied();
+        match self {
+            MeshIndices::U16(indices) => MeshIndices::U16(
+                indices
+                    .iter()
+                    .copied()
+                    .chain(lods.map(|index| index as u16))
+                    .collect(),
+            ),
+            MeshIndices::U32(indices) => {
+                MeshIndices::U32(indices.iter().copied().chain(lods).collect())
+            }
+        }
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        match self {
+            MeshIndices::U16(_) => IndexType::UINT16,
+            MeshIndices::U32(_) => IndexType::UINT32,
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        match self {
+            MeshIndices::U16(indices) => indices.shrink_to_fit(),
+            MeshIndices::U32(indices) => indices.shrink_to_fit(),
+        }
+    }
+
+    pub fn capacity_bytes(&self) -> usize {
+        match self {
+            MeshIndices::U16(indices) => indices.capacity() * size_of::<u16>(),
+            MeshIndices::U32(indices) => indices.capacity() * size_of::<u32>(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: MeshIndices,
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        let indices = MeshIndices::new(indices, vertices.len());
+        Mesh { vertices, indices }
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.indices.index_type()
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        Aabb::from_points(
+            self.vertices
+                .iter()
+                .map(|vertex| Point3::from_vec(vertex.position())),
+        )
+    }
+
+    // Index lists of the simplified levels of detail, empty for small meshes
+    pub fn lods(&self, levels: usize) -> Vec<Vec<u32>> {
+        // Vertices that only differ in their normal are one to the
+        // simplifier, flat shaded faces would lock every vertex otherwise
+        let mut welded = HashMap::<[u32; 5], u32>::new();
+        let mut positions = Vec::new();
+        let mut variants: Vec<Vec<u32>> = Vec::new();
+        let remap = self
+            .vertices
+            .iter()
+            .enumerate()
+            .map(|(index, vertex)| {
+                let (position, uv) = (vertex.position(), vertex.texture_coords());
+                let key = [position.x, position.y, position.z, uv.x, uv.y].map(f32::to_bits);
+                let welded_index = *welded.entry(key).or_insert_with(|| {
+                    positions.push(position);
+                    variants.push(Vec::new());
+                    (positions.len() - 1) as u32
+                });
+                variants[welded_index as usize].push(index as u32);
+                welded_index
+            })
+            .collect::<Vec<u32>>();
+        let indices = self
+            .indices
+            .iter()
+            .filter_map(|index| remap.get(index as usize).copied())
+            .collect::<Vec<u32>>();
+        // Back to the vertices whose normals best match their triangle
+        simplify_lods(&positions, &indices, levels)
+            .into_iter()
+            .map(|lod| {
+                lod.chunks_exact(3)
+                    .flat_map(|triangle| {
+                        let [a, b, c] =
+                            [0, 1, 2].map(|corner| positions[triangle[corner] as usize]);
+                        let normal = (b - a).cross(c - a);
+                        triangle
+                            .iter()
+                            .map(|welded_index| {
+                                *variants[*welded_index as usize]
+                                    .iter()
+                                    .max_by(|first, second| {
+                                        let facing = |index: &u32| {
+                                            self.vertices[*index as usize].normal().dot(normal)
+                                        };
+                                        facing(first).total_cmp(&facing(second))
+                                    })
+                                    .unwrap()
+                            })
+                            .collect::<Vec<u32>>()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Corner positions of every complete triangle of the index list
+    pub fn triangles(&self) -> Vec<[Point3<f32>; 3]> {
+        triangles(&self.indices, |index| {
+            self.vertices
+                .get(index as usize)
+                .map(|vertex| Point3::from_vec(vertex.position()))
+        })
+    }
+}
+
+fn triangles(
+    indices: &MeshIndices,
+    corner: impl Fn(u32) -> Option<Point3<f32>>,
+) -> Vec<[Point3<f32>; 3]> {
+    let indices = indices.iter().collect::<Vec<u32>>();
+    indices
+        .chunks_exact(3)
+        .filter_map(|triangle| {
+            Some([
+                corner(triangle[0])?,
+                corner(triangle[1])?,
+                corner(triangle[2])?,
+            ])
+        })
+        .collect()
+}
+
+// What is left of a mesh on the CPU after its upload, see `CpuMeshData`
+#[derive(Debug, Clone, Default)]
+pub enum RetainedMesh {
+    #[default]
+    Discarded,
+    Full(Mesh),
+    Positions {
+        positions: Vec<Point3<f32>>,
+        indices: MeshIndices,
+    },
+}
+
+impl RetainedMesh {
+    // Frees what `cpu_data` doesn't keep, the kept vectors are shrunk to
+    // their length
+    pub fn new(mesh: Mesh, cpu_data: CpuMeshData) -> Self {
+        match cpu_data {
+            CpuMeshData::Discard => RetainedMesh::Discarded,
+            CpuMeshData::Keep => {
+                let Mesh {
+                    mut vertices,
+                    mut indices,
+                } = mesh;
+                vertices.shrink_to_fit();
+                indices.shrink_to_fit();
+                RetainedMesh::Full(Mesh { vertices, indices })
+            }
+            CpuMeshData::KeepPositionsOnly => {
+                let positions = mesh
+                    .vertices
+                    .iter()
+                    .map(|vertex| Point3::from_vec(vertex.position()))
+                    .collect();
+                let mut indices = mesh.indices;
+                indices.shrink_to_fit();
+                RetainedMesh::Positions { positions, indices }
+            }
+        }
+    }
+
+    pub fn cpu_data(&self) -> CpuMeshData {
+        match self {
+            RetainedMesh::Discarded => CpuMeshData::Discard,
+            RetainedMesh::Full(_) => CpuMeshData::Keep,
+            RetainedMesh::Positions { .. } => CpuMeshData::KeepPositionsOnly,
+        }
+    }
+
+    // Empty once discarded
+    pub fn triangles(&self) -> Vec<[Point3<f32>; 3]> {
+        match self {
+            RetainedMesh::Discarded => Vec::new(),
+            RetainedMesh::Full(mesh) => mesh.triangles(),
+            RetainedMesh::Positions { positions, indices } => {
+                triangles(indices, |index| positions.get(index as usize).copied())
+            }
+        }
+    }
+
+    // Heap bytes held, by capacity
+    pub fn size(&self) -> usize {
+        match self {
+            RetainedMesh::Discarded => 0,
+            RetainedMesh::Full(mesh) => {
+                mesh.vertices.capacity() * size_of::<Vertex>() + mesh.indices.capacity_bytes()
+            }
+            RetainedMesh::Positions { positions, indices } => {
+                positions.capacity() * size_of::<Point3<f32>>() + indices.capacity_bytes()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::mesh::primitives;
+
+    // A cube whose vectors were grown past their length, like a loader's
+    fn loaded_cube() -> Mesh {
+        let (mut vertices, mut indices) = primitives::cube(1.0);
+        vertices.reserve(100);
+        indices.reserve(100);
+        Mesh::new(vertices, indices)
+    }
+
+    #[test]
+    fn meshes_up_to_65536_vertices_use_16_bit_indices() {
+        let indices = MeshIndices::new(vec![0, 1, 65535], 65536);
+        assert_eq!(indices.index_type(), IndexType::UINT16);
+        assert_eq!(indices.iter().collect::<Vec<u32>>(), [0, 1, 65535]);
+        assert_eq!(indices.as_bytes().len(), 3 * size_of::<u16>());
+    }
+
+    #[test]
+    fn larger_meshes_use_32_bit_indices() {
+        let indices = MeshIndices::new(vec![0, 1, 65536], 65537);
+        assert_eq!(indices.index_type(), IndexType::UINT32);
+        assert_eq!(indices.iter().collect::<Vec<u32>>(), [0, 1, 65536]);
+        assert_eq!(indices.as_bytes().len(), 3 * size_of::<u32>());
+    }
+
+    #[test]
+    fn lods_keep_the_index_type() {
+        let indices = MeshIndices::new(vec![0, 1, 2], 65536).with_lods(&[vec![65535, 0, 1]]);
+        assert_eq!(indices.index_type(), IndexType::UINT16);
+        assert_eq!(indices.iter().collect::<Vec<u32>>(), [0, 1, 2, 65535, 0, 1]);
+    }
+
+    #[test]
+    fn discarded_meshes_hold_nothing() {
+        let retained = RetainedMesh::new(loaded_cube(), CpuMeshData::Discard);
+        assert!(matches!(retained, RetainedMesh::Discarded));
+        assert_eq!(retained.cpu_data(), CpuMeshData::Discard);
+        assert_eq!(retained.size(), 0);
+        assert!(retained.triangles().is_empty());
+    }
+
+    #[test]
+    fn kept_meshes_are_shrunk_to_their_length() {
+        let mesh = loaded_cube();
+        let triangles = mesh.triangles();
+        assert!(mesh.vertices.capacity() > mesh.vertices.len());
+
+        let retained = RetainedMesh::new(mesh, CpuMeshData::Keep);
+        let RetainedMesh::Full(kept) = &retained else {
+            panic!("{retained:?}");
+        };
+        assert_eq!(kept.vertices.capacity(), kept.vertices.len());
+        assert_eq!(kept.indices.capacity_bytes(), kept.indices.as_bytes().len());
+        assert_eq!(
+            retained.size(),
+            kept.vertices.len() * size_of::<Vertex>() + kept.indices.as_bytes().len()
+        );
+        assert_eq!(retained.triangles(), triangles);
+    }
+
+    #[test]
+    fn positions_only_keeps_the_triangles_in_less() {
+        let mesh = loaded_cube();
+        let triangles = mesh.triangles();
+        let full = RetainedMesh::new(mesh.clone(), CpuMeshData::Keep).size();
+
+        let retained = RetainedMesh::new(mesh, CpuMeshData::KeepPositionsOnly);
+        let RetainedMesh::Positions { positions, indices } = &retained else {
+            panic!("{retained:?}");
+        };
+        assert_eq!(positions.capacity(), positions.len());
+        assert_eq!(indices.capacity_bytes(), indices.as_bytes().len());
+        assert!(retained.size() < full);
+        assert_eq!(retained.triangles(), triangles);
+    }
+}
@@ -1,73 +1,240 @@
 use std::{
+    collections::{HashMap, HashSet},
+    env,
     ffi::{c_void, CStr, CString},
-    fs::File,
-    io::{BufReader, Cursor},
-    path::Path,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use ash::vk::{
-    AccessFlags, Buffer, BufferCopy, BufferCreateInfo, BufferImageCopy, BufferMemoryBarrier,
-    BufferUsageFlags, ClearColorValue, ClearDepthStencilValue, ClearValue, CommandBufferBeginInfo,
-    CommandBufferUsageFlags, CompareOp, DependencyFlags, DescriptorBufferInfo, DescriptorImageInfo,
-    DescriptorPool, DescriptorPoolCreateInfo, DescriptorPoolSize, DescriptorSet,
-    DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding,
-    DescriptorSetLayoutCreateInfo, DescriptorType, DeviceMemory, DeviceSize, Extent3D, Fence,
+    AccessFlags, Buffer, BufferImageCopy, BufferUsageFlags, ClearColorValue,
+    ClearDepthStencilValue, ClearValue, CommandBufferBeginInfo, CommandBufferUsageFlags, CompareOp,
+    DescriptorImageInfo, DescriptorSet, DescriptorSetLayout,
+    DescriptorSetLayoutBindingFlagsCreateInfo, DescriptorSetLayoutCreateFlags,
+    DescriptorSetLayoutCreateInfo, DescriptorType, DeviceMemory, DeviceSize, Fence,
     FenceCreateFlags, FenceCreateInfo, FormatFeatureFlags, ImageCreateFlags, ImageCreateInfo,
-    ImageMemoryBarrier, ImageSubresourceLayers, ImageTiling, ImageType, IndexType,
-    MemoryAllocateInfo, MemoryBarrier, MemoryMapFlags, MemoryPropertyFlags, Offset3D,
+    ImageTiling, ImageType, MemoryAllocateInfo, MemoryPropertyFlags,
     PipelineInputAssemblyStateCreateInfo, PipelineLayout, PipelineStageFlags, RenderPassBeginInfo,
-    Sampler, Semaphore, SemaphoreCreateFlags, SemaphoreCreateInfo, SubmitInfo, SubpassContents,
-    SubpassDependency, WriteDescriptorSet, QUEUE_FAMILY_IGNORED, SUBPASS_EXTERNAL,
+    Sampler, Semaphore, SemaphoreCreateFlags, SemaphoreCreateInfo, SubpassContents,
+    SubpassDependency, WriteDescriptorSet, REMAINING_MIP_LEVELS, SUBPASS_EXTERNAL,
 };
 use ash::{
-    util::read_spv,
     vk::{
         ApplicationInfo, AttachmentDescription, AttachmentLoadOp, AttachmentReference,
         AttachmentStoreOp, BlendFactor, BlendOp, ColorComponentFlags, ColorSpaceKHR, CommandBuffer,
-        CommandBufferAllocateInfo, CommandBufferLevel, CommandPool, CommandPoolCreateFlags,
-        CommandPoolCreateInfo, ComponentMapping, ComponentSwizzle, CompositeAlphaFlagsKHR,
-        CullModeFlags, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT,
+        CommandPool, CommandPoolCreateFlags, CommandPoolCreateInfo,
+        DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT,
         DebugUtilsMessengerCallbackDataEXT, DebugUtilsMessengerCreateInfoEXT,
         DebugUtilsMessengerEXT, DeviceCreateInfo, DeviceQueueCreateInfo, DynamicState, Extent2D,
-        Format, Framebuffer, FramebufferCreateInfo, FrontFace, GraphicsPipelineCreateInfo, Image,
-        ImageAspectFlags, ImageLayout, ImageSubresourceRange, ImageUsageFlags, ImageView,
-        ImageViewCreateInfo, ImageViewType, InstanceCreateFlags, InstanceCreateInfo, LogicOp,
-        Offset2D, PhysicalDevice, PhysicalDeviceFeatures, Pipeline, PipelineBindPoint,
-        PipelineCache, PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo,
-        PipelineDepthStencilStateCreateInfo, PipelineDynamicStateCreateFlags,
-        PipelineDynamicStateCreateInfo, PipelineLayoutCreateInfo,
+        Format, Framebuffer, GraphicsPipelineCreateInfo, Image, ImageAspectFlags, ImageLayout,
+        ImageSubresourceRange, ImageUsageFlags, ImageView, ImageViewCreateInfo, ImageViewType,
+        InstanceCreateFlags, InstanceCreateInfo, LogicOp, Offset2D, PhysicalDevice,
+        PhysicalDeviceBufferDeviceAddressFeatures, PhysicalDeviceFeatures,
+        PhysicalDevicePresentIdFeaturesKHR, PhysicalDevicePresentWaitFeaturesKHR,
+        PhysicalDeviceSynchronization2Features, PhysicalDeviceTimelineSemaphoreFeatures, Pipeline,
+        PipelineBindPoint, PipelineCache, PipelineColorBlendAttachmentState,
+        PipelineColorBlendStateCreateInfo, PipelineDepthStencilStateCreateInfo,
+        PipelineDynamicStateCreateFlags, PipelineDynamicStateCreateInfo, PipelineLayoutCreateInfo,
         PipelineMultisampleStateCreateInfo, PipelineRasterizationStateCreateInfo,
         PipelineShaderStageCreateInfo, PipelineVertexInputStateCreateInfo,
         PipelineViewportStateCreateInfo, PolygonMode, PresentModeKHR, PrimitiveTopology, Queue,
-        QueueFlags, Rect2D, RenderPass, RenderPassCreateInfo, SampleCountFlags, ShaderModule,
-        ShaderModuleCreateInfo, ShaderStageFlags, SharingMode, SubpassDescription,
-        SurfaceFormatKHR, SurfaceKHR, SwapchainCreateInfoKHR, SwapchainKHR, Viewport,
-        EXT_DEBUG_UTILS_NAME, KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_NAME,
-        KHR_PORTABILITY_ENUMERATION_NAME, KHR_SWAPCHAIN_NAME,
+        QueueFlags, Rect2D, RenderPass, RenderPassCreateInfo, SampleCountFlags, ShaderStageFlags,
+        SharingMode, SpecializationInfo, SpecializationMapEntry, StencilFaceFlags, StencilOp,
+        StencilOpState, SubpassDescription, SurfaceFormatKHR, SurfaceKHR,
+        ValidationFeatureEnableEXT, ValidationFeaturesEXT, Viewport, EXT_DEBUG_UTILS_NAME,
+        KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_NAME, KHR_PORTABILITY_ENUMERATION_NAME,
+        KHR_SYNCHRONIZATION2_NAME,
     },
     Device, Entry, Instance,
 };
 
-use buffer_types::{uniform_buffer_types::UniformBufferObject, vertex::Vertex};
-use cgmath::{vec2, vec3, Matrix4, Vector3, Zero};
+use asset_cache::AssetCache;
+use buffer_types::{
+    gpu_buffer::{GpuBuffer, GpuBufferError, GpuContext},
+    uniform_buffer_types::{aligned_stride, UniformBufferObject},
+    vertex::{Vertex, VertexLayout, VERTEX_LAYOUTS},
+};
+use cgmath::{vec2, vec3, EuclideanSpace};
+use deletion_queue::DeletionQueue;
+use descriptor_allocator::DescriptorAllocator;
+#[cfg(feature = "profiling")]
+use gpu_profiler::GpuZone;
 use log::*;
+use mesh::Mesh;
+use shader_cache::ShaderCache;
+use shader_reflection::ShaderReflection;
+use synchronization::ImageBarrier;
 use textures::Texture;
-use tobj::{LoadOptions, Model};
-use winit::{
-    dpi::PhysicalSize,
-    raw_window_handle::{HasDisplayHandle, HasWindowHandle},
-    window::{self, Window},
+use ui_pass::FramePass;
+use winit::{raw_window_handle::HasDisplayHandle, window::Window};
+
+use surface_context::SurfaceContext;
+
+use crate::{
+    engine::{
+        config::EngineConfig,
+        debug_palette::DebugPalette,
+        error::{ByteSize, EngineError, VkContext},
+        mesh::CpuMeshData,
+        present_stats::PresentStatsAccumulator,
+        viewport::{viewport, ViewId, MAX_VIEWPORTS, MAX_VIEWS},
+    },
+    logging::{self, span},
+    utils::{self, file_watcher::FileWatcher},
 };
-
-use crate::utils;
+pub use asset_cache::{AssetStats, MeshResource, TextureResource};
+pub use bloom::BloomSettings;
+pub use culling::CullingStats;
+pub use deferred::ShadingPath;
+pub use device_limits::{DeviceLimits, EngineRequirements, LimitAdjustment};
+pub use lights::MAX_LIGHTS;
+pub use materials::MaterialResource;
+pub use morph::{active_morph_targets, MAX_ACTIVE_MORPH_TARGETS};
+pub use pipeline_options::PipelineOptions;
+pub use platform_quirks::PlatformQuirks;
+
+pub use recording::RecordOverflow;
+pub use render_queue::{QueueStats, RenderQueue};
+pub use ssao::SsaoSettings;
+pub use textures::{ColorSpaceHint, TextureSlot};
+mod asset_cache;
+mod bc;
+mod bindless;
+mod bloom;
+mod buffer_address;
 pub mod buffer_types;
+mod culling;
+mod deferred;
+mod deletion_queue;
+mod descriptor_allocator;
+mod device_limits;
+mod diagnostics;
+mod drivers;
+mod frame_graph;
+mod fullscreen;
+mod gizmo;
+#[cfg(feature = "profiling")]
+mod gpu_profiler;
+mod ibl;
+mod jobs;
+mod ktx;
+mod lights;
+mod materials;
+mod mesh;
+mod morph;
+mod offscreen;
+mod picking;
+mod pipeline_options;
+mod platform_quirks;
+mod present_timing;
+mod recording;
+mod render_queue;
+mod render_scale;
+mod render_target;
+mod scene_shaders;
+mod screenshot;
+mod shader_cache;
+mod shader_reflection;
+mod skinning;
+mod ssao;
+mod surface_context;
+mod synchronization;
+mod text;
+mod texture_streaming;
 mod textures;
+mod transfer;
+mod ui_pass;
+mod upload_ring;
 pub const MAX_FLIGHT_FENCES: u32 = 3;
+// Object indices take 16 bits of the draw sort keys
+pub const MAX_OBJECTS: u32 = 1 << 16;
+// One uniform entry per object, viewport region and window. The buffers
+// start at MIN_UNIFORM_ENTRIES and grow to what the scene needs
+pub const UNIFORM_BUFFER_ENTRIES: u32 = MAX_OBJECTS * MAX_VIEWPORTS * MAX_VIEWS;
+pub(super) const MIN_UNIFORM_ENTRIES: u32 = 1024;
+const VALIDATION_LAYER_NAME: &CStr = c"VK_LAYER_KHRONOS_validation";
+const VERTEX_SHADER_PATH: &str = "src/assets/vertices.spv";
+// shader.vert with BUFFER_ADDRESS defined
+const VERTEX_ADDRESS_SHADER_PATH: &str = "src/assets/vertices_address.spv";
+const FRAGMENT_SHADER_PATH: &str = "src/assets/fragment.spv";
+// shader.frag with BINDLESS defined
+const FRAGMENT_BINDLESS_SHADER_PATH: &str = "src/assets/fragment_bindless.spv";
+const BOUNDS_SHADER_PATH: &str = "src/assets/bounds.spv";
+// Read on a worker thread while the device is created, whether or not their
+// pass ends up enabled
+const STARTUP_SHADERS: [&str; 16] = [
+    VERTEX_SHADER_PATH,
+    VERTEX_ADDRESS_SHADER_PATH,
+    FRAGMENT_SHADER_PATH,
+    FRAGMENT_BINDLESS_SHADER_PATH,
+    BOUNDS_SHADER_PATH,
+    picking::PICKING_SHADER_PATH,
+    gizmo::GIZMO_VERTEX_SHADER_PATH,
+    gizmo::GIZMO_SHADER_PATH,
+    render_target::FULLSCREEN_SHADER_PATH,
+    bloom::BLOOM_SHADER_PATH,
+    ssao::SSAO_SHADER_PATH,
+    deferred::GBUFFER_SHADER_PATH,
+    deferred::GBUFFER_BINDLESS_SHADER_PATH,
+    deferred::GBUFFER_BOUNDS_SHADER_PATH,
+    deferred::DEFERRED_SHADER_PATH,
+    culling::CULL_SHADER_PATH,
+];
+// shader.vert's SKINNED and MORPHED specialization constants. The scene
+// passes build a triangle pipeline for every combination, indexed by the
+// variant bits, followed by the bounds pipeline
+const SKINNED_CONSTANT_ID: u32 = 0;
+const MORPHED_CONSTANT_ID: u32 = 1;
+pub(super) const SKINNED_VARIANT: usize = 1;
+pub(super) const MORPHED_VARIANT: usize = 2;
+pub(super) const VERTEX_VARIANTS: usize = 4;
+// shader.vert's INDIRECT and ENTRY_STRIDE, set on every scene pipeline when
+// objects are culled on the GPU. Not part of the pipeline index
+const INDIRECT_CONSTANT_ID: u32 = 2;
+const ENTRY_STRIDE_CONSTANT_ID: u32 = 3;
+const INDIRECT_VARIANT: usize = VERTEX_VARIANTS;
+const BOUNDS_PIPELINE: usize = VERTEX_VARIANTS;
+// shader.vert's PACKED_NORMALS. The variant bits from LAYOUT_VARIANT_SHIFT
+// up are the index of the vertex layout, whose triangle pipelines follow the
+// bounds pipeline when packed vertices are enabled
+const PACKED_NORMALS_CONSTANT_ID: u32 = 4;
+const LAYOUT_VARIANT_SHIFT: usize = 4;
+// shader.vert's OUTLINE_WIDTH, set by the pipelines that redraw the selected
+// object extruded along its normals. Not part of the pipeline index either
+const OUTLINE_WIDTH_CONSTANT_ID: u32 = 5;
+const OUTLINE_VARIANT: usize = 2 * INDIRECT_VARIANT;
+// In clip space w, a few pixels at the default field of view
+const OUTLINE_WIDTH: f32 = 0.004;
+// What the selected objects leave in the stencil buffer, cleared to 0
+const OUTLINE_STENCIL_REFERENCE: u32 = 1;
+// Strength of the selection color blended over the highlighted objects'
+// textures, and of the level of detail tints while they are shown
+const HIGHLIGHT_TINT_ALPHA: f32 = 0.35;
+const LOD_TINT_ALPHA: f32 = 0.4;
+// Bounds and gizmo lines, where the device draws wide lines
+const LINE_WIDTH: f32 = 2.0;
+// Encodes like the usual sRGB swapchain formats and is already in PNG byte order
+const OFFSCREEN_FORMAT: Format = Format::R8G8B8A8_SRGB;
+const ENGINE_DESCRIPTOR_BINDINGS: [(u32, DescriptorType); 9] = [
+    (0, DescriptorType::UNIFORM_BUFFER_DYNAMIC),
+    (1, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (2, DescriptorType::STORAGE_BUFFER),
+    (3, DescriptorType::STORAGE_BUFFER),
+    (4, DescriptorType::STORAGE_BUFFER),
+    // Irradiance, prefiltered environment and BRDF lookup table
+    (5, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (6, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (7, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    // The uniform buffer again, indexed by the draw's first instance
+    (8, DescriptorType::STORAGE_BUFFER),
+];
 
 #[allow(clippy::pedantic)]
-#[derive(Default, Clone)]
+#[derive(Default)]
 pub struct Configuration {
+    config: EngineConfig,
     vulkan_entry: Option<Entry>,
     instance: Option<Instance>,
     physical_device: Option<PhysicalDevice>,
@@ -76,69 +243,179 @@ pub struct Configuration {
     pub device: Option<Device>,
     pub graphics_queue: Option<Queue>,
     pub presentation_queue: Option<Queue>,
+    // Only with `EngineConfig::transfer_queue` on a device that has a
+    // dedicated transfer family and timeline semaphores
+    transfer_queue: Option<Queue>,
+    timeline_semaphore: Option<ash::khr::timeline_semaphore::Device>,
     device_extensions: Vec<*const i8>,
+    // Names kept for diagnostics
+    instance_extensions: Vec<String>,
+    enabled_layers: Vec<String>,
+    synchronization2: Option<ash::khr::synchronization2::Device>,
+    // Set when object data is read through buffer device addresses
+    buffer_device_address: Option<ash::khr::buffer_device_address::Device>,
+    // Set when objects are culled on the GPU, the scene pipelines read their
+    // entry through the first instance then
+    draw_indirect_count: Option<ash::khr::draw_indirect_count::Device>,
+    // Where the driver offers VK_EXT_full_screen_exclusive, Windows only
+    full_screen_exclusive: Option<ash::ext::full_screen_exclusive::Device>,
+    // At most one of the two, whichever reports presentation timing best
+    display_timing: Option<ash::google::display_timing::Device>,
+    present_wait: Option<ash::khr::present_wait::Device>,
+    present_stats: PresentStatsAccumulator,
     surface_instance: Option<ash::khr::surface::Instance>,
-    pub surface: Option<SurfaceKHR>,
+    // Shared by every surface since they render through the same render pass
     surface_format: Option<SurfaceFormatKHR>,
-    present_mode: Option<PresentModeKHR>,
-    pub extent: Option<Extent2D>,
-    image_count: u32,
-    swapchain_support_details: Option<SwapchainSupportDetails>,
     pub swapchain_device: Option<ash::khr::swapchain::Device>,
-    pub swapchain: Option<SwapchainKHR>,
-    swapchain_images: Vec<Image>,
-    image_views: Vec<ImageView>,
+    pub surfaces: Vec<SurfaceContext>,
+    // Every surface's swapchain rebuilds since startup
+    swapchain_recreations: u64,
+    // Set at runtime, takes precedence over `config.vsync` for every surface
+    // that supports it
+    present_mode: Option<PresentModeKHR>,
     viewports: Vec<Viewport>,
     scissors: Vec<Rect2D>,
 
     render_pass: Option<RenderPass>,
+    // Leaves the color attachment ready to be blitted, for frames rendered
+    // below full resolution
+    scaled_render_pass: Option<RenderPass>,
+    // Screen text and overlay lines over the finished image, at full
+    // resolution whatever the render scale
+    ui_pass: Option<RenderPass>,
     pipeline_layout: PipelineLayout,
+    // The textured triangle pipeline followed by the line list one
     graphics_pipelines: Vec<Pipeline>,
+    pipeline_options: PipelineOptions,
+    // Scene pipelines by the options they were created with
+    pipeline_variants: HashMap<PipelineOptions, scene_shaders::SceneVariant>,
+    // Drawn with in place of the scene pipelines of options whose shaders
+    // failed to build
+    error_pipelines: Vec<Pipeline>,
+    // Only polled with `EngineConfig::hot_reload` and a scene shader override
+    shader_watcher: FileWatcher,
+    // The scene pipelines mask the selected objects in the stencil buffer,
+    // `outline_pipelines` draw around them. Only with `config.stencil` and a
+    // depth format that has a stencil aspect
+    outline_stencil: bool,
+    outline_pipelines: Vec<Pipeline>,
 
-    pub framebuffers: Vec<Framebuffer>,
     pub command_pool: Option<CommandPool>,
-    pub command_buffer: Vec<CommandBuffer>,
-
-    pub image_available_semaphores: Vec<Semaphore>,
-    pub render_finished_semaphores: Vec<Semaphore>,
-    pub in_flight_fences: Vec<Fence>,
-
-    vertices: Vec<Vertex>,
-    vertex_buffer: Buffer,
-    vertex_buffer_memory: DeviceMemory,
-
-    pub uniform_buffers: Vec<Buffer>,
-    pub uniform_buffer_memory: Vec<DeviceMemory>,
+    transient_command_pool: Option<CommandPool>,
+
+    // What every object is drawn with
+    model: Option<Arc<MeshResource>>,
+    texture: Option<Arc<TextureResource>>,
+    // Startup assets still being decoded, taken by `load_model` and
+    // `create_texture_image`
+    preload: jobs::Preload,
+    asset_cache: AssetCache,
+    deletion_queue: DeletionQueue,
+    texture_streamer: Option<texture_streaming::TextureStreamer>,
+    // Bound while a streamed texture is on its way
+    fallback_texture: Option<Arc<TextureResource>>,
+    // Frame slots whose descriptor set still points at a replaced texture
+    stale_texture_descriptors: [bool; MAX_FLIGHT_FENCES as usize],
+    // Only polled with `EngineConfig::hot_reload`
+    texture_watcher: FileWatcher,
+
+    // Timestamps around the primary window's command buffers
+    frame_timer: Option<render_scale::FrameTimer>,
+    // Only with `EngineConfig::frame_budget_ms`
+    resolution_scaler: Option<render_scale::ResolutionScaler>,
+    render_scale_override: Option<f32>,
+    // Milliseconds, the last value read back
+    gpu_frame_time: Option<f32>,
+
+    // Only with `EngineConfig::bloom` on a windowed surface
+    bloom: Option<bloom::BloomPass>,
+    // Only with `EngineConfig::ssao` on a windowed surface
+    ssao: Option<ssao::SsaoPass>,
+    // Only with `EngineConfig::deferred` on a windowed surface
+    deferred: Option<deferred::DeferredPass>,
+
+    // Per frame in flight, where its uniform entries landed in the upload
+    // ring
+    uniform_entries: Vec<upload_ring::UploadRange>,
+    // Everything uploaded per frame, the uniform entries, lights and culling
+    // records included
+    upload_ring: Option<upload_ring::PerFrameUploadRing>,
+    // Per frame in flight, the point lights of both shading paths
+    light_ranges: Vec<upload_ring::UploadRange>,
+    // Joint palettes of the skinned objects, per frame in flight
+    joint_buffers: Vec<GpuBuffer<[[f32; 4]; 4]>>,
+    // Every mesh's morph target deltas
+    morph_pool: Option<morph::MorphPool>,
+    // Ambient and reflected light of the forward pass
+    ibl: Option<ibl::IblMaps>,
+    // Descriptor set 1, what each object's surface is made of
+    materials: Option<materials::Materials>,
+    // Textures the bindless material table holds, None when the device
+    // binds a descriptor set per material
+    bindless_capacity: Option<u32>,
+    // Per frame in flight and uniform entry, the pipeline variant it is
+    // drawn with
+    entry_variants: Vec<Vec<u8>>,
+    // Only with `draw_indirect_count`
+    culling: Option<culling::CullingPass>,
+    // Per frame in flight, which entries are drawn and how
+    culled_frames: Vec<culling::CulledFrame>,
+    culling_stats: CullingStats,
+    pub uniform_buffer_stride: DeviceSize,
+
+    // Edges of the mesh bounds, uploaded the first time they are shown
+    bounds_buffer: Option<GpuBuffer<Vertex>>,
+    show_bounds: bool,
+    // Tints every object by the level of detail it is drawn with
+    show_lods: bool,
+
+    // Detected right after the device is picked
+    platform_quirks: PlatformQuirks,
+    device_limits: DeviceLimits,
+    // What `validate_device_limits` lowered in `config`
+    limit_adjustments: Vec<LimitAdjustment>,
 
-    indices: Vec<u32>,
-    index_buffer: Buffer,
-    index_buffer_memory: DeviceMemory,
-    width: u32,
-    height: u32,
-
-    texture_image: Image,
-    texture_image_view: ImageView,
-    texture_image_memory: DeviceMemory,
     texture_sampler: Sampler,
 
-    depth_image: Image,
-    depth_image_view: ImageView,
-    depth_image_memory: DeviceMemory,
+    // Keeps depth after the main pass in DEPTH_STENCIL_READ_ONLY_OPTIMAL so
+    // later passes can sample each surface's `depth_image_view`
+    pub sampled_depth: bool,
 
-    descriptor_pool: DescriptorPool,
+    descriptor_allocator: DescriptorAllocator,
     descriptor_set_layout: Vec<DescriptorSetLayout>,
     descriptor_sets: Vec<DescriptorSet>,
-
-    pub window_resized: bool,
+    shader_reflection: ShaderReflection,
+    shader_cache: ShaderCache,
 
     debug_instance: Option<ash::ext::debug_utils::Instance>,
     debug_messenger: Option<DebugUtilsMessengerEXT>,
+
+    screenshot: Option<screenshot::PendingScreenshot>,
+    recording: Option<recording::Recording>,
+
+    picking: Option<picking::PickingPass>,
+    // Only on a windowed surface
+    gizmos: Option<gizmo::GizmoPass>,
+    // Only with a signed distance field font
+    text: Option<text::TextPass>,
+    // Indices of the objects tinted with the palette's selection color
+    pub highlighted_objects: HashSet<u32>,
+    // Set by `Engine::request_test_panic`
+    pub test_panic: bool,
+    // Already logged, shown as toasts once the engine takes them
+    notifications: Vec<(Level, String)>,
+
+    #[cfg(feature = "profiling")]
+    gpu_profiler: Option<gpu_profiler::GpuProfiler>,
 }
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct QueueFamilyIndices {
     pub graphics_queue: Option<u32>,
     pub presentation_queue: Option<u32>,
+    // A family without graphics for streaming uploads, when the device has
+    // one. Not part of `unique_families`, only textures use it
+    pub transfer_queue: Option<u32>,
 }
 
 impl QueueFamilyIndices {
@@ -154,6 +431,38 @@ impl QueueFamilyIndices {
         self.graphics_queue.is_some() && self.presentation_queue.is_some()
     }
 
+    // Every family in use exactly once. Queue create infos and CONCURRENT
+    // sharing both reject repeated family indices
+    pub fn unique_families(&self) -> Vec<u32> {
+        let mut families = [self.graphics_queue, self.presentation_queue]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<u32>>();
+        families.sort_unstable();
+        families.dedup();
+        families
+    }
+
+    // Without a surface nothing is presented, the graphics family stands in
+    // for presentation so both queues resolve to the same one
+    fn find_graphics_queue_family(
+        instance: &Instance,
+        physical_device: PhysicalDevice,
+    ) -> QueueFamilyIndices {
+        let mut queue_family_indices = QueueFamilyIndices::default();
+        let queue_family_properties =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        if let Some(index) = queue_family_properties
+            .iter()
+            .position(|family| family.queue_flags.contains(QueueFlags::GRAPHICS))
+        {
+            queue_family_indices.graphics_family_index(index as u32);
+            queue_family_indices.presentation_queue(index as u32);
+        }
+        queue_family_indices.transfer_queue = transfer::transfer_family(&queue_family_properties);
+        queue_family_indices
+    }
+
     fn find_queue_family_indices(
         instance: Instance,
         surface_instance: ash::khr::surface::Instance,
@@ -172,6 +481,8 @@ impl QueueFamilyIndices {
                 Some(res) => queue_family_indices.graphics_family_index(res.0 as u32),
                 None => return Some(queue_family_indices),
             }
+            queue_family_indices.transfer_queue =
+                transfer::transfer_family(&queue_family_properties);
 
             let physical_device_surface_support = surface_instance
                 .get_physical_device_surface_support(
@@ -198,7 +509,6 @@ pub struct SwapchainSupportDetails {
 
 impl SwapchainSupportDetails {
     pub fn query_swapchain_support(
-        instance: &Instance,
         surface_instance: &ash::khr::surface::Instance,
         surface: &SurfaceKHR,
         physical_device: &PhysicalDevice,
@@ -227,30 +537,46 @@ impl SwapchainSupportDetails {
                 && format.color_space.eq(&ColorSpaceKHR::SRGB_NONLINEAR)
         });
 
-        if surface_format_khr.is_some() {
-            return *surface_format_khr.unwrap();
-        } else {
-            SurfaceFormatKHR::default()
+        match surface_format_khr {
+            Some(surface_format_khr) => *surface_format_khr,
+            None => SurfaceFormatKHR::default()
                 .format(Format::R8G8B8A8_SRGB)
-                .color_space(ColorSpaceKHR::SRGB_NONLINEAR)
+                .color_space(ColorSpaceKHR::SRGB_NONLINEAR),
         }
     }
 
-    pub fn choose_present_mode(&self) -> PresentModeKHR {
+    // FIFO is the only mode every implementation has to support
+    pub fn choose_present_mode(&self, vsync: bool) -> PresentModeKHR {
+        if vsync {
+            return PresentModeKHR::FIFO;
+        }
         let present_mode = self
             .present_modes
             .iter()
             .find(|&present_mode| *present_mode == PresentModeKHR::MAILBOX);
-        if present_mode.is_some() {
-            return *present_mode.unwrap();
+        match present_mode {
+            Some(present_mode) => *present_mode,
+            None => PresentModeKHR::FIFO,
         }
+    }
 
-        return PresentModeKHR::FIFO;
+    // `requested` or one above the minimum, within what the surface allows.
+    // A maximum of 0 means there is none
+    pub fn choose_image_count(&self, requested: Option<u32>) -> u32 {
+        let capabilities = &self.capabilities;
+        let max_image_count = if capabilities.max_image_count > 0 {
+            capabilities.max_image_count
+        } else {
+            u32::MAX
+        };
+        requested
+            .unwrap_or(capabilities.min_image_count + 1)
+            .clamp(capabilities.min_image_count, max_image_count)
     }
 
     pub fn choose_swap_extent(&self, buffer_width: u32, buffer_height: u32) -> Extent2D {
-        if self.capabilities.current_extent.width != u32::max_value() {
-            return self.capabilities.current_extent;
+        if self.capabilities.current_extent.width != u32::MAX {
+            self.capabilities.current_extent
         } else {
             let mut extent_2d = Extent2D::default()
                 .width(buffer_width)
@@ -264,52 +590,78 @@ impl SwapchainSupportDetails {
                 self.capabilities.max_image_extent.height,
             );
 
-            return extent_2d;
+            extent_2d
         }
     }
 }
 
 impl Configuration {
     pub fn default() -> Self {
-        return Self {
-            width: 1920,
-            height: 1080,
-            window_resized: false,
+        Self {
             debug_instance: None,
-            in_flight_fences: Vec::new(),
-            render_finished_semaphores: Vec::new(),
-            image_available_semaphores: Vec::new(),
-            command_buffer: Vec::new(),
-            framebuffers: Vec::new(),
             graphics_pipelines: Vec::new(),
+            pipeline_options: PipelineOptions::default(),
+            pipeline_variants: HashMap::new(),
             scissors: Vec::new(),
             viewports: Vec::new(),
-            image_views: Vec::new(),
-            swapchain_images: Vec::new(),
+            surfaces: Vec::new(),
             device: None,
             swapchain_device: None,
-            swapchain_support_details: None,
             surface_instance: None,
             device_extensions: Vec::new(),
             instance: None,
             vulkan_entry: None,
-            vertices: Vec::new(),
-            indices: Vec::new(),
-            uniform_buffers: Vec::new(),
-            uniform_buffer_memory: Vec::new(),
+            uniform_entries: Vec::new(),
+            upload_ring: None,
             descriptor_sets: Vec::new(),
             descriptor_set_layout: Vec::new(),
 
             ..Default::default()
+        }
+    }
+
+    // The system loader first (libvulkan.so.1, vulkan-1.dll, libvulkan.dylib,
+    // Android's libvulkan.so), then the one in $VULKAN_SDK/lib
+    fn load_vulkan_entry() -> Result<Entry, EngineError> {
+        let system_error = match unsafe { Entry::load() } {
+            Ok(entry) => return Ok(entry),
+            Err(err) => err,
         };
+        let sdk_loader = env::var_os("VULKAN_SDK").map(|sdk| {
+            let library = if cfg!(target_os = "macos") {
+                "libvulkan.dylib"
+            } else if cfg!(windows) {
+                "vulkan-1.dll"
+            } else {
+                "libvulkan.so.1"
+            };
+            Path::new(&sdk).join("lib").join(library)
+        });
+        if let Some(sdk_loader) = sdk_loader {
+            match unsafe { Entry::load_from(&sdk_loader) } {
+                Ok(entry) => {
+                    info!("Loaded the Vulkan loader from {}", sdk_loader.display());
+                    return Ok(entry);
+                }
+                Err(err) => warn!("Failed to load {}: {err}", sdk_loader.display()),
+            }
+        }
+        Err(EngineError::LoaderMissing(system_error.to_string()))
+    }
+
+    pub fn with_config(&mut self, config: EngineConfig) -> &mut Configuration {
+        self.config = config;
+        self
     }
 
-    pub fn create_instance(&mut self, window: &Window) -> Result<&mut Configuration, &str> {
+    // Without a window no surface extensions are enabled and only offscreen
+    // rendering is possible
+    pub fn create_instance(
+        &mut self,
+        window: Option<&Window>,
+    ) -> Result<&mut Configuration, EngineError> {
+        self.vulkan_entry = Some(Self::load_vulkan_entry()?);
         unsafe {
-            self.vulkan_entry = Some(
-                Entry::load_from("/Users/tufan/VulkanSDK/1.3.296.0/macOS/lib/libvulkan.dylib")
-                    .expect("Failed to find vulkan library on this machine"),
-            );
             let application_version = 1;
             let application_name = CString::new("Caterpie").unwrap();
             let engine_name = CString::new("Caterpie Engine").unwrap();
@@ -336,14 +688,26 @@ impl Configuration {
                 .as_ref()
                 .unwrap()
                 .enumerate_instance_extension_properties(None)
-                .unwrap();
-            let mut instance_extension_properties = ash_window::enumerate_required_extensions(
-                window.display_handle().unwrap().as_raw(),
-            )
-            .unwrap()
-            .to_vec();
+                .vk_ctx(
+                    "vkEnumerateInstanceExtensionProperties",
+                    "creating the instance",
+                )?;
+            let mut instance_extension_properties = match window {
+                Some(window) => ash_window::enumerate_required_extensions(
+                    window.display_handle().unwrap().as_raw(),
+                )
+                .unwrap()
+                .to_vec(),
+                None => Vec::new(),
+            };
             instance_extension_properties.push(KHR_PORTABILITY_ENUMERATION_NAME.as_ptr());
             instance_extension_properties.push(KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_NAME.as_ptr());
+            if let Some(extension) = self.buffer_device_address_instance_extension() {
+                instance_extension_properties.push(extension.as_ptr());
+            }
+            if let Some(extension) = self.full_screen_exclusive_instance_extension() {
+                instance_extension_properties.push(extension.as_ptr());
+            }
 
             for extension in entry_enumerated_instance_extensions {
                 if instance_extension_properties.contains(&extension.extension_name.as_ptr()) {
@@ -351,25 +715,41 @@ impl Configuration {
                 }
             }
 
+            let mut enabled_layers = Vec::new();
             match self.check_validation_layer_support() {
             Ok(_) => {
-                    instance_extension_properties.push(EXT_DEBUG_UTILS_NAME.as_ptr());},
+                    instance_extension_properties.push(EXT_DEBUG_UTILS_NAME.as_ptr());
+                    if self.config.validation {
+                        enabled_layers.push(VALIDATION_LAYER_NAME.as_ptr());
+                    }
+                },
             Err(_) => error!("ERROR: VALIDATION LAYERS ARE NOT PRESENT ON THIS MACHINE, PROCEEDING WITHOUT SETTING UP DEBUG MESSENGER")
         }
-            let instance_create_info = InstanceCreateInfo::default()
+            let enabled_validation_features =
+                [ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION];
+            let mut validation_features = ValidationFeaturesEXT::default()
+                .enabled_validation_features(&enabled_validation_features);
+            let mut instance_create_info = InstanceCreateInfo::default()
                 .application_info(&app_info)
                 .flags(InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR)
                 .enabled_extension_names(&instance_extension_properties)
+                .enabled_layer_names(&enabled_layers)
                 .push_next(&mut debug_messenger_create_info);
+            if self.config.sync_validation && !enabled_layers.is_empty() {
+                info!("Synchronization validation is enabled");
+                instance_create_info = instance_create_info.push_next(&mut validation_features);
+            }
             self.instance = Some(
                 self.vulkan_entry
                     .as_ref()
                     .unwrap()
                     .create_instance(&instance_create_info, None)
-                    .unwrap(),
+                    .vk_expect("vkCreateInstance", "creating the instance"),
             );
 
             info!("Instance has been created!");
+            self.instance_extensions = diagnostics::extension_names(&instance_extension_properties);
+            self.enabled_layers = diagnostics::extension_names(&enabled_layers);
 
             self.debug_instance = Some(ash::ext::debug_utils::Instance::new(
                 self.vulkan_entry.as_ref().unwrap(),
@@ -380,7 +760,10 @@ impl Configuration {
                     .as_ref()
                     .unwrap()
                     .create_debug_utils_messenger(&debug_messenger_create_info, None)
-                    .unwrap(),
+                    .vk_expect(
+                        "vkCreateDebugUtilsMessengerEXT",
+                        "creating the debug messenger",
+                    ),
             );
             info!("Debug messenger has been created!");
         }
@@ -392,19 +775,36 @@ impl Configuration {
             self.vulkan_entry.as_ref().unwrap(),
             self.instance.as_ref().unwrap(),
         ));
-        unsafe {
-            self.surface = Some(
-                ash_window::create_surface(
-                    self.vulkan_entry.as_ref().unwrap(),
-                    self.instance.as_ref().unwrap(),
-                    window.display_handle().unwrap().as_raw(),
-                    window.window_handle().unwrap().as_raw(),
-                    None,
-                )
-                .unwrap(),
-            );
+        let ctx = self.create_surface_context(window, ViewId::PRIMARY);
+        self.surfaces.push(ctx);
+        Ok(self)
+    }
+
+    // Device selection and queue families are based on the first window
+    fn primary_surface(&self) -> &SurfaceContext {
+        &self.surfaces[0]
+    }
+
+    fn headless(&self) -> bool {
+        self.surfaces
+            .first()
+            .is_some_and(SurfaceContext::is_offscreen)
+    }
+
+    // Stands in for `create_surface` when rendering without a window
+    pub fn create_offscreen_target(
+        &mut self,
+        extent: Extent2D,
+    ) -> Result<&mut Configuration, &str> {
+        if extent.width == 0 || extent.height == 0 {
+            return Err("Offscreen targets can't be empty");
         }
-        info!("Surface has been created");
+        self.surface_format = Some(SurfaceFormatKHR {
+            format: OFFSCREEN_FORMAT,
+            color_space: ColorSpaceKHR::SRGB_NONLINEAR,
+        });
+        self.surfaces
+            .push(SurfaceContext::offscreen(ViewId::PRIMARY, extent));
         Ok(self)
     }
 
@@ -413,16 +813,24 @@ impl Configuration {
             let instance = self.instance.as_ref().unwrap();
             let physical_devices = instance
                 .enumerate_physical_devices()
-                .expect("Failed to enumerate physical devices");
+                .vk_expect("vkEnumeratePhysicalDevices", "picking a device");
 
-            let physical_device = physical_devices
-                .iter()
-                .find(|&p_device| self.is_device_suitable(p_device));
+            let physical_device = self
+                .order_by_driver_preference(&physical_devices)
+                .into_iter()
+                .find(|p_device| self.is_device_suitable(p_device));
             if physical_device.is_none() {
                 error!("No physical device has been found, abort initialization!");
                 return Err("Aborting initialization as there were no physical devices found");
             }
-            self.physical_device = Some(physical_device.unwrap()).copied();
+            self.physical_device = physical_device;
+            self.report_driver(&physical_devices);
+            let properties = self
+                .instance
+                .as_ref()
+                .unwrap()
+                .get_physical_device_properties(self.physical_device.unwrap());
+            self.device_limits = DeviceLimits::from(&properties.limits);
 
             Ok(self)
         }
@@ -430,10 +838,16 @@ impl Configuration {
 
     pub fn is_device_suitable(&mut self, physical_device: &PhysicalDevice) -> bool {
         let instance = self.instance.as_ref().unwrap();
+        if self.headless() {
+            let features = unsafe { instance.get_physical_device_features(*physical_device) };
+            return QueueFamilyIndices::find_graphics_queue_family(instance, *physical_device)
+                .is_complete()
+                && features.sampler_anisotropy != 0;
+        }
         let queue_family_indices = QueueFamilyIndices::find_queue_family_indices(
             self.instance.as_ref().unwrap().clone(),
             self.surface_instance.as_ref().unwrap().clone(),
-            self.surface.unwrap(),
+            self.primary_surface().surface,
             *physical_device,
         )
         .expect("Failed to gather queue family indices");
@@ -445,12 +859,10 @@ impl Configuration {
         let extensions_enabled = self.check_device_extension_support(physical_device);
         if extensions_enabled {
             let swapchain_support_details = SwapchainSupportDetails::query_swapchain_support(
-                self.instance.as_ref().unwrap(),
                 self.surface_instance.as_ref().unwrap(),
-                self.surface.as_ref().unwrap(),
+                &self.primary_surface().surface,
                 physical_device,
             );
-            self.swapchain_support_details = Some(swapchain_support_details.clone());
             adequate_swapchain = !(swapchain_support_details.formats.is_empty()
                 && swapchain_support_details.present_modes.is_empty())
                 && physical_device_features.sampler_anisotropy != 0
@@ -460,7 +872,8 @@ impl Configuration {
     }
 
     pub fn check_device_extension_support(&mut self, physical_device: &PhysicalDevice) -> bool {
-        let device_extensions = vec![ash::khr::swapchain::NAME.to_str().unwrap()];
+        // maintenance1 allows the negative viewport height that points y up
+        let device_extensions = [ash::khr::swapchain::NAME, ash::khr::maintenance1::NAME];
         let mut flag = true;
         unsafe {
             let enumerate_device_extension_properties = self
@@ -481,14 +894,15 @@ impl Configuration {
                 .collect::<Vec<&str>>();
 
             for extension in device_extensions {
-                if !device_extension_properties.contains(&extension) {
+                if !device_extension_properties.contains(&extension.to_str().unwrap()) {
                     flag = false;
                 }
             }
         }
 
         if flag {
-            self.device_extensions.push(KHR_SWAPCHAIN_NAME.as_ptr());
+            self.device_extensions
+                .extend(device_extensions.map(|extension| extension.as_ptr()));
         }
         flag
     }
@@ -520,28 +934,38 @@ impl Configuration {
 
     pub fn create_device(&mut self) -> Result<&mut Configuration, &str> {
         let instance = self.instance.as_ref().unwrap();
-        self.queue_family_indices = QueueFamilyIndices::find_queue_family_indices(
-            instance.clone(),
-            self.surface_instance.as_ref().unwrap().clone(),
-            self.surface.as_ref().unwrap().clone(),
-            self.physical_device
-                .expect("Couldn't find appropriate queue family indices"),
-        );
+        let physical_device = self
+            .physical_device
+            .expect("Couldn't find appropriate queue family indices");
+        self.queue_family_indices = if self.headless() {
+            Some(QueueFamilyIndices::find_graphics_queue_family(
+                instance,
+                physical_device,
+            ))
+        } else {
+            QueueFamilyIndices::find_queue_family_indices(
+                instance.clone(),
+                self.surface_instance.as_ref().unwrap().clone(),
+                self.primary_surface().surface,
+                physical_device,
+            )
+        };
         unsafe {
             let queue_priorities = [1.0];
             let queue_family_indices = self.queue_family_indices.unwrap();
-            let queue_indices = [
-                queue_family_indices.graphics_queue.unwrap(),
-                queue_family_indices.presentation_queue.unwrap(),
-            ];
+            let queue_families = queue_family_indices.unique_families();
 
             self.physical_device_features = Some(
                 instance
                     .get_physical_device_features(self.physical_device.unwrap())
                     .sampler_anisotropy(true),
             );
+            let transfer_queue = self.supports_transfer_queue();
             let mut device_queue_create_infos = Vec::new();
-            for queue_index in queue_indices {
+            let transfer_family = queue_family_indices
+                .transfer_queue
+                .filter(|_| transfer_queue);
+            for queue_index in queue_families.into_iter().chain(transfer_family) {
                 device_queue_create_infos.push(
                     DeviceQueueCreateInfo::default()
                         .queue_family_index(queue_index)
@@ -549,20 +973,106 @@ impl Configuration {
                 );
             }
 
-            let device_create_info = DeviceCreateInfo::default()
+            let synchronization2 = self.supports_synchronization2();
+            let bindless_capacity = self.supported_bindless_capacity();
+            let buffer_device_address = self.supports_buffer_device_address();
+            let gpu_culling = self.supports_gpu_culling();
+            let full_screen_exclusive = self.supports_full_screen_exclusive();
+            let display_timing = self.supports_display_timing();
+            let present_wait = !display_timing && self.supports_present_wait();
+            let mut device_extensions = self.device_extensions.clone();
+            let mut synchronization2_features =
+                PhysicalDeviceSynchronization2Features::default().synchronization2(true);
+            let mut descriptor_indexing_features = bindless::descriptor_indexing_features();
+            let mut buffer_device_address_features =
+                PhysicalDeviceBufferDeviceAddressFeatures::default().buffer_device_address(true);
+            let mut timeline_semaphore_features =
+                PhysicalDeviceTimelineSemaphoreFeatures::default().timeline_semaphore(true);
+            let mut present_id_features =
+                PhysicalDevicePresentIdFeaturesKHR::default().present_id(true);
+            let mut present_wait_features =
+                PhysicalDevicePresentWaitFeaturesKHR::default().present_wait(true);
+            let mut device_create_info = DeviceCreateInfo::default()
                 .queue_create_infos(&device_queue_create_infos)
-                .enabled_features(self.physical_device_features.as_ref().unwrap())
-                .enabled_extension_names(&self.device_extensions);
+                .enabled_features(self.physical_device_features.as_ref().unwrap());
+            if synchronization2 {
+                device_extensions.push(KHR_SYNCHRONIZATION2_NAME.as_ptr());
+                device_create_info = device_create_info.push_next(&mut synchronization2_features);
+            }
+            if bindless_capacity.is_some() {
+                device_extensions.push(ash::khr::maintenance3::NAME.as_ptr());
+                device_extensions.push(ash::ext::descriptor_indexing::NAME.as_ptr());
+                device_create_info =
+                    device_create_info.push_next(&mut descriptor_indexing_features);
+            }
+            if buffer_device_address {
+                device_extensions.push(ash::khr::device_group::NAME.as_ptr());
+                device_extensions.push(ash::khr::buffer_device_address::NAME.as_ptr());
+                device_create_info =
+                    device_create_info.push_next(&mut buffer_device_address_features);
+            }
+            if gpu_culling {
+                device_extensions.push(ash::khr::draw_indirect_count::NAME.as_ptr());
+            }
+            if transfer_queue {
+                device_extensions.push(ash::khr::timeline_semaphore::NAME.as_ptr());
+                device_create_info = device_create_info.push_next(&mut timeline_semaphore_features);
+            }
+            if self.platform_quirks.needs_portability_subset {
+                device_extensions.push(ash::khr::portability_subset::NAME.as_ptr());
+            }
+            if full_screen_exclusive {
+                device_extensions.push(ash::ext::full_screen_exclusive::NAME.as_ptr());
+            }
+            if display_timing {
+                device_extensions.push(ash::google::display_timing::NAME.as_ptr());
+            }
+            if present_wait {
+                device_extensions.push(ash::khr::present_id::NAME.as_ptr());
+                device_extensions.push(ash::khr::present_wait::NAME.as_ptr());
+                device_create_info = device_create_info
+                    .push_next(&mut present_id_features)
+                    .push_next(&mut present_wait_features);
+            }
+            // Lines Tracy's GPU zones up with the CPU timeline
+            #[cfg(feature = "profiling")]
+            if self.supports_calibrated_timestamps() {
+                device_extensions.push(ash::ext::calibrated_timestamps::NAME.as_ptr());
+            }
+            device_create_info = device_create_info.enabled_extension_names(&device_extensions);
             self.device = Some(
-                instance
+                self.instance
+                    .as_ref()
+                    .unwrap()
                     .create_device(self.physical_device.unwrap(), &device_create_info, None)
-                    .unwrap(),
+                    .vk_expect("vkCreateDevice", "creating the logical device"),
             );
+            self.device_extensions = device_extensions;
+            if synchronization2 {
+                self.enable_synchronization2();
+            }
+            self.bindless_capacity = bindless_capacity;
+            if buffer_device_address {
+                self.enable_buffer_device_address();
+            }
+            if gpu_culling {
+                self.enable_gpu_culling();
+            }
+            if full_screen_exclusive {
+                self.enable_full_screen_exclusive();
+            }
+            self.enable_present_timing(display_timing, present_wait);
+            if let Some(capacity) = bindless_capacity {
+                info!("Material textures are bound through a descriptor array of {capacity}");
+            }
 
             self.graphics_queue =
                 self.find_device_queue(queue_family_indices.graphics_queue.unwrap());
             self.presentation_queue =
                 self.find_device_queue(queue_family_indices.presentation_queue.unwrap());
+            if transfer_queue {
+                self.enable_transfer_queue();
+            }
         }
         Ok(self)
     }
@@ -579,108 +1089,19 @@ impl Configuration {
     }
 
     pub fn create_swap_chain(&mut self) -> Result<&mut Configuration, &str> {
-        self.swapchain_support_details = Some(SwapchainSupportDetails::query_swapchain_support(
+        if self.headless() {
+            self.for_each_surface(Self::create_offscreen_images);
+            return Ok(self);
+        }
+        self.swapchain_device = Some(ash::khr::swapchain::Device::new(
             self.instance.as_ref().unwrap(),
-            self.surface_instance.as_ref().unwrap(),
-            self.surface.as_ref().unwrap(),
-            self.physical_device.as_ref().unwrap(),
+            self.device.as_ref().unwrap(),
         ));
-
         self.surface_format = Some(
-            self.swapchain_support_details
-                .as_ref()
-                .unwrap()
+            self.query_swapchain_support(self.primary_surface().surface)
                 .choose_swap_chain_format(),
         );
-        self.present_mode = Some(
-            self.swapchain_support_details
-                .as_ref()
-                .unwrap()
-                .choose_present_mode(),
-        );
-        self.extent = Some(
-            self.swapchain_support_details
-                .as_ref()
-                .unwrap()
-                .choose_swap_extent(self.width, self.height),
-        );
-
-        self.image_count = self
-            .swapchain_support_details
-            .as_ref()
-            .unwrap()
-            .capabilities
-            .min_image_count
-            + 1;
-        let max_image_count = self
-            .swapchain_support_details
-            .as_ref()
-            .unwrap()
-            .capabilities
-            .max_image_count;
-        if max_image_count > 0 && self.image_count > max_image_count {
-            self.image_count = max_image_count;
-        }
-
-        let queue_families = [
-            self.queue_family_indices.unwrap().graphics_queue.unwrap(),
-            self.queue_family_indices
-                .unwrap()
-                .presentation_queue
-                .unwrap(),
-        ];
-
-        let mut swapchain_create_info = SwapchainCreateInfoKHR::default()
-            .surface(self.surface.unwrap())
-            .min_image_count(self.image_count)
-            .image_format(self.surface_format.unwrap().format)
-            .image_color_space(self.surface_format.unwrap().color_space)
-            .image_extent(self.extent.unwrap())
-            .image_array_layers(1)
-            .image_usage(ImageUsageFlags::COLOR_ATTACHMENT)
-            .pre_transform(
-                self.swapchain_support_details
-                    .as_ref()
-                    .unwrap()
-                    .capabilities
-                    .current_transform,
-            )
-            .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(self.present_mode.unwrap())
-            .clipped(true);
-        //          .old_swapchain(...);
-
-        self.swapchain_device = Some(ash::khr::swapchain::Device::new(
-            self.instance.as_ref().unwrap(),
-            self.device.as_ref().unwrap(),
-        ));
-
-        if queue_families[0] != queue_families[1] {
-            swapchain_create_info = swapchain_create_info
-                .image_sharing_mode(SharingMode::CONCURRENT)
-                .queue_family_indices(&queue_families);
-        } else {
-            swapchain_create_info =
-                swapchain_create_info.image_sharing_mode(SharingMode::EXCLUSIVE);
-        }
-        unsafe {
-            self.swapchain = Some(
-                self.swapchain_device
-                    .as_ref()
-                    .unwrap()
-                    .create_swapchain(&swapchain_create_info, None)
-                    .expect("Failed to create swapchain"),
-            );
-
-            info!("Swapchain created!");
-            self.swapchain_images = self
-                .swapchain_device
-                .as_ref()
-                .unwrap()
-                .get_swapchain_images(self.swapchain.unwrap())
-                .expect("Failed to retrieve swapchain images");
-        }
-        info!("Swapchain images retrieved");
+        self.for_each_surface(Self::create_surface_swapchain);
         Ok(self)
     }
 
@@ -691,13 +1112,13 @@ impl Configuration {
         tiling: ImageTiling,
         usage: ImageUsageFlags,
         properties: MemoryPropertyFlags,
-    ) -> Result<(Image, DeviceMemory), Error> {
+    ) -> Result<(Image, DeviceMemory), EngineError> {
         let device = self.device.as_ref().unwrap();
         let instance = self.instance.as_ref().unwrap();
         let image_create_info = ImageCreateInfo::default()
             .image_type(ImageType::TYPE_2D)
             .extent(texture.into())
-            .mip_levels(1)
+            .mip_levels(texture.mip_levels())
             .array_layers(1)
             .format(format)
             .tiling(tiling)
@@ -706,8 +1127,15 @@ impl Configuration {
             .samples(SampleCountFlags::TYPE_1)
             .flags(ImageCreateFlags::empty())
             .sharing_mode(SharingMode::EXCLUSIVE);
+        let extent = image_create_info.extent;
+        let detail = format!(
+            "creating a {}x{} {format:?} image",
+            extent.width, extent.height
+        );
         unsafe {
-            let image = device.create_image(&image_create_info, None).unwrap();
+            let image = device
+                .create_image(&image_create_info, None)
+                .vk_ctx("vkCreateImage", &detail)?;
 
             let memory_requirements = device.get_image_memory_requirements(image);
 
@@ -723,8 +1151,18 @@ impl Configuration {
                     .unwrap(),
                 );
 
-            let image_memory = device.allocate_memory(&memory_allocate_info, None).unwrap();
-            device.bind_image_memory(image, image_memory, 0).unwrap();
+            let detail = format!("{detail} ({})", ByteSize(memory_requirements.size));
+            let image_memory = device
+                .allocate_memory(&memory_allocate_info, None)
+                .inspect_err(|_| device.destroy_image(image, None))
+                .vk_ctx("vkAllocateMemory", &detail)?;
+            device
+                .bind_image_memory(image, image_memory, 0)
+                .inspect_err(|_| {
+                    device.destroy_image(image, None);
+                    device.free_memory(image_memory, None);
+                })
+                .vk_ctx("vkBindImageMemory", &detail)?;
 
             Ok((image, image_memory))
         }
@@ -735,12 +1173,12 @@ impl Configuration {
         image: &Image,
         format: Format,
         aspect_flags: ImageAspectFlags,
-    ) -> Result<ImageView, ash::vk::Result> {
+    ) -> Result<ImageView, EngineError> {
         let device = self.device.as_ref().unwrap();
         let sub_resource_range = ImageSubresourceRange::default()
             .aspect_mask(aspect_flags)
             .base_mip_level(0)
-            .level_count(1)
+            .level_count(REMAINING_MIP_LEVELS)
             .base_array_layer(0)
             .layer_count(1);
 
@@ -750,118 +1188,195 @@ impl Configuration {
             .format(format)
             .subresource_range(sub_resource_range);
 
-        let image_view = unsafe { device.create_image_view(&create_info, None) };
-        image_view
+        unsafe { device.create_image_view(&create_info, None) }.vk_ctx(
+            "vkCreateImageView",
+            format_args!("creating a {format:?} image view"),
+        )
     }
 
     pub fn create_swapchain_image_views(&mut self) -> Result<&mut Configuration, &str> {
-        let device = self.device.as_ref().unwrap();
-        /* let component_mapping = ComponentMapping::default()
-            .r(ComponentSwizzle::IDENTITY)
-            .g(ComponentSwizzle::IDENTITY)
-            .b(ComponentSwizzle::IDENTITY)
-            .a(ComponentSwizzle::IDENTITY);
-
-        let subresource_range = ImageSubresourceRange::default()
-            .aspect_mask(ImageAspectFlags::COLOR)
-            .base_mip_level(0)
-            .level_count(1)
-            .base_array_layer(0)
-            .layer_count(1);*/
-
-        self.image_views = self
-            .clone()
-            .swapchain_images
-            .iter()
-            .map(|image| {
-                self.create_image_view(
-                    image,
-                    self.surface_format.unwrap().format,
-                    ImageAspectFlags::COLOR,
-                )
-                .unwrap()
-            })
-            .collect::<Vec<ImageView>>();
+        self.for_each_surface(Self::create_surface_image_views);
         Ok(self)
     }
 
-    pub fn create_shader_module<P: AsRef<Path> + std::fmt::Debug + ToString>(
-        &mut self,
-        path: P,
-    ) -> Result<ShaderModule, &str> {
-        let device = self.device.as_ref().unwrap();
-
-        let shader_binding = utils::io::read_file(&path).unwrap();
-        let mut shader_as_byte_arr = Cursor::new(&shader_binding);
-        let shader_spv: Vec<u32> =
-            read_spv(&mut shader_as_byte_arr).expect("Failed to convert shader shader to spv");
-
-        let shader_spv_c_info = ShaderModuleCreateInfo::default().code(&shader_spv);
-
-        unsafe {
-            let shader_module = device.create_shader_module(&shader_spv_c_info, None);
+    pub fn create_render_pass(&mut self) -> Result<&mut Configuration, &str> {
+        // SSAO reads the depth buffer once the scene is drawn, the deferred
+        // lighting pass keeps the G-buffer's depth attached
+        self.sampled_depth |= self.ssao_requested() || self.deferred_requested();
+        let (format, color_final_layout) = self.scene_color();
+        self.render_pass = Some(self.scene_render_pass(
+            &[format],
+            color_final_layout,
+            AttachmentLoadOp::CLEAR,
+            AttachmentLoadOp::CLEAR,
+        ));
+        // Same attachments, so the pipelines work with both. Scaled frames
+        // are blitted to the swapchain image afterwards. With bloom the
+        // composite takes care of upscaling instead
+        if self.supports_scaled_rendering() && !self.bloom_requested() {
+            self.scaled_render_pass = Some(self.scene_render_pass(
+                &[format],
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                AttachmentLoadOp::CLEAR,
+                AttachmentLoadOp::CLEAR,
+            ));
+        }
+        self.create_ui_render_pass()
+            .map_err(|_| "Failed to create the UI render pass")?;
+        info!("Renderpass has been initialized!");
+        Ok(self)
+    }
 
-            match shader_module {
-                Ok(module) => Ok(module),
-                Err(_) => {
-                    error!("Failed to create shader module with path {:?}", path);
-                    Err("Failed to create shader module")
-                }
-            }
+    // Format and final layout of the main scene pass' color attachment. With
+    // bloom the scene is always drawn into an HDR target that the bloom
+    // passes sample, offscreen images are copied out instead of presented
+    fn scene_color(&self) -> (Format, ImageLayout) {
+        if self.bloom_requested() {
+            (bloom::HDR_FORMAT, ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        } else if self.headless() {
+            (
+                self.surface_format.unwrap().format,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )
+        } else {
+            (
+                self.surface_format.unwrap().format,
+                ImageLayout::PRESENT_SRC_KHR,
+            )
         }
     }
 
-    pub fn create_render_pass(&mut self) -> Result<&mut Configuration, &str> {
-        let mut attachment_description = vec![AttachmentDescription::default()
-            .format(self.surface_format.as_ref().unwrap().format)
-            .samples(SampleCountFlags::TYPE_1)
-            .load_op(AttachmentLoadOp::CLEAR)
-            .store_op(AttachmentStoreOp::STORE)
-            .stencil_load_op(AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(AttachmentStoreOp::DONT_CARE)
-            .initial_layout(ImageLayout::UNDEFINED)
-            .final_layout(ImageLayout::PRESENT_SRC_KHR)];
+    // One color attachment per entry of `color_formats`, followed by the
+    // depth buffer. Loading the color draws on top of a finished scene pass
+    // with the same final layout. Loading the depth keeps what an earlier
+    // pass left in it read only, which needs `sampled_depth`. Every variant
+    // with the same formats is compatible, so they share framebuffers and
+    // pipelines
+    fn scene_render_pass(
+        &self,
+        color_formats: &[Format],
+        color_final_layout: ImageLayout,
+        color_load_op: AttachmentLoadOp,
+        depth_load_op: AttachmentLoadOp,
+    ) -> RenderPass {
+        let color_initial_layout = if color_load_op == AttachmentLoadOp::LOAD {
+            color_final_layout
+        } else {
+            ImageLayout::UNDEFINED
+        };
+        let mut attachment_description = color_formats
+            .iter()
+            .map(|format| {
+                AttachmentDescription::default()
+                    .format(*format)
+                    .samples(SampleCountFlags::TYPE_1)
+                    .load_op(color_load_op)
+                    .store_op(AttachmentStoreOp::STORE)
+                    .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+                    .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(color_initial_layout)
+                    .final_layout(color_final_layout)
+            })
+            .collect::<Vec<_>>();
 
-        let attachment_reference = vec![AttachmentReference::default()
-            .attachment(0)
-            .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let attachment_reference = (0..color_formats.len() as u32)
+            .map(|attachment| {
+                AttachmentReference::default()
+                    .attachment(attachment)
+                    .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            })
+            .collect::<Vec<_>>();
 
+        let (depth_store_op, depth_final_layout) = if self.sampled_depth {
+            (
+                AttachmentStoreOp::STORE,
+                ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            )
+        } else {
+            (
+                AttachmentStoreOp::DONT_CARE,
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            )
+        };
+        let (depth_initial_layout, depth_layout) = if depth_load_op == AttachmentLoadOp::LOAD {
+            (
+                depth_final_layout,
+                ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            )
+        } else {
+            (
+                ImageLayout::UNDEFINED,
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            )
+        };
+        // A stencil aspect is cleared, loaded and kept along with the depth
+        let depth_format = self.find_depth_format();
+        let (stencil_load_op, stencil_store_op) = if Self::has_stencil_component(depth_format) {
+            (depth_load_op, depth_store_op)
+        } else {
+            (AttachmentLoadOp::DONT_CARE, AttachmentStoreOp::DONT_CARE)
+        };
         let depth_stencil_attachment = AttachmentDescription::default()
-            .format(self.find_depth_format())
+            .format(depth_format)
             .samples(SampleCountFlags::TYPE_1)
-            .load_op(AttachmentLoadOp::CLEAR)
-            .store_op(AttachmentStoreOp::DONT_CARE)
-            .stencil_load_op(AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(AttachmentStoreOp::DONT_CARE)
-            .initial_layout(ImageLayout::UNDEFINED)
-            .final_layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+            .load_op(depth_load_op)
+            .store_op(depth_store_op)
+            .stencil_load_op(stencil_load_op)
+            .stencil_store_op(stencil_store_op)
+            .initial_layout(depth_initial_layout)
+            .final_layout(depth_final_layout);
 
         attachment_description.push(depth_stencil_attachment);
 
         let depth_stencil_attachment_ref = AttachmentReference::default()
-            .attachment(1)
-            .layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+            .attachment(color_formats.len() as u32)
+            .layout(depth_layout);
 
         let subpass_description = vec![SubpassDescription::default()
             .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
             .color_attachments(&attachment_reference)
             .depth_stencil_attachment(&depth_stencil_attachment_ref)];
 
-        let subpass_dependency = vec![SubpassDependency::default()
+        // The render pass owns the depth layout transitions, so the external
+        // dependency has to cover both fragment test stages. Scene targets are
+        // still read by the previous frame's blit or bloom passes
+        let mut subpass_dependency = vec![SubpassDependency::default()
             .src_subpass(SUBPASS_EXTERNAL)
             .dst_subpass(0)
             .src_stage_mask(
                 PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
-                    | PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                    | PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | PipelineStageFlags::FRAGMENT_SHADER
+                    | PipelineStageFlags::TRANSFER,
             )
             .dst_stage_mask(
                 PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
-                    | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                    | PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | PipelineStageFlags::LATE_FRAGMENT_TESTS,
             )
-            .src_access_mask(AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
-            .dst_access_mask(
+            .src_access_mask(
                 AccessFlags::COLOR_ATTACHMENT_WRITE | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .dst_access_mask(
+                AccessFlags::COLOR_ATTACHMENT_READ
+                    | AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
             )];
+        if self.sampled_depth {
+            subpass_dependency.push(
+                SubpassDependency::default()
+                    .src_subpass(0)
+                    .dst_subpass(SUBPASS_EXTERNAL)
+                    .src_stage_mask(PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                    .dst_stage_mask(
+                        PipelineStageFlags::FRAGMENT_SHADER | PipelineStageFlags::COMPUTE_SHADER,
+                    )
+                    .src_access_mask(AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_access_mask(AccessFlags::SHADER_READ),
+            );
+        }
 
         let render_pass_create_info = RenderPassCreateInfo::default()
             .attachments(&attachment_description)
@@ -869,27 +1384,211 @@ impl Configuration {
             .dependencies(&subpass_dependency);
 
         unsafe {
-            self.render_pass = Some(
-                self.device
-                    .as_ref()
-                    .unwrap()
-                    .create_render_pass(&render_pass_create_info, None)
-                    .unwrap(),
-            );
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_render_pass(&render_pass_create_info, None)
+                .vk_expect("vkCreateRenderPass", "creating the scene render pass")
         }
-        info!("Renderpass has been initialized!");
+    }
+
+    pub fn create_graphics_pipeline(&mut self) -> Result<&mut Configuration, Error> {
+        self.shader_reflection
+            .validate(0, &ENGINE_DESCRIPTOR_BINDINGS)?;
+        if self.bindless_capacity.is_some() {
+            self.shader_reflection
+                .validate(1, &bindless::BINDLESS_DESCRIPTOR_BINDINGS)?;
+        } else {
+            self.shader_reflection
+                .validate(1, &materials::MATERIAL_DESCRIPTOR_BINDINGS)?;
+        }
+        self.outline_stencil = self.config.stencil;
+        if self.outline_stencil && !Self::has_stencil_component(self.find_depth_format()) {
+            warn!("No depth format with a stencil aspect, the selection isn't outlined");
+            self.outline_stencil = false;
+        }
+        self.create_error_pipelines()?;
+        let options = self.pipeline_options;
+        let variant = self.create_scene_variant(options);
+        self.pipeline_variants.insert(options, variant);
+        self.graphics_pipelines = self.variant_pipelines(options);
+        self.rebuild_outline_pipelines();
         Ok(self)
     }
 
-    pub fn create_graphics_pipeline(&mut self) -> Result<&mut Configuration, &str> {
-        let fragment_shader_module = self
-            .create_shader_module(Path::new("src/assets/fragment.spv").to_str().unwrap())
-            .unwrap();
-        let vertex_shader_module = self
-            .create_shader_module(Path::new("src/assets/vertices.spv").to_str().unwrap())
-            .unwrap();
+    // The selected objects again, extruded and only outside of their
+    // stencil mask. One per vertex layout and variant, indexed like the scene
+    // pipelines without the bounds pipeline
+    fn create_outline_pipelines(&mut self) -> Result<Vec<Pipeline>, Error> {
+        let indirect = if self.draw_indirect_count.is_some() {
+            INDIRECT_VARIANT
+        } else {
+            0
+        };
+        let (vertex_shader_path, _) = self.scene_shader_paths();
+        let mut specs = Vec::new();
+        for layout in self.vertex_layouts() {
+            for variant in 0..VERTEX_VARIANTS {
+                specs.push((
+                    (vertex_shader_path.as_str(), BOUNDS_SHADER_PATH),
+                    PrimitiveTopology::TRIANGLE_LIST,
+                    (DepthStencil::OUTSIDE_MASK, PipelineOptions::default()),
+                    variant | indirect | OUTLINE_VARIANT | layout.index() << LAYOUT_VARIANT_SHIFT,
+                ));
+            }
+        }
+        self.create_pipelines((self.render_pass.unwrap(), 1), specs)
+    }
+
+    // The layouts meshes are uploaded with, the full one always comes first
+    fn vertex_layouts(&self) -> Vec<VertexLayout> {
+        if self.packs_vertices() {
+            VERTEX_LAYOUTS.to_vec()
+        } else {
+            vec![VertexLayout::Full]
+        }
+    }
+
+    // Whether meshes are uploaded packed. Portability drivers can require
+    // strides the packed vertex doesn't have
+    fn packs_vertices(&self) -> bool {
+        let alignment = self.platform_quirks.vertex_stride_alignment.max(1) as usize;
+        self.config.packed_vertices
+            && VERTEX_LAYOUTS
+                .iter()
+                .all(|layout| layout.stride() % alignment == 0)
+    }
+
+    // A triangle pipeline per vertex variant, then the bounds pipeline and
+    // the triangle pipelines of the packed layouts. All
+    // share the scene's layout, the bounds shader only uses the push
+    // constants. `options` only apply to the triangles
+    fn create_scene_pipelines(
+        &mut self,
+        (vertex_shader_path, fragment_shader_path, bounds_shader_path): (&str, &str, &str),
+        target: (RenderPass, usize),
+        options: PipelineOptions,
+    ) -> Result<Vec<Pipeline>, Error> {
+        let indirect = if self.draw_indirect_count.is_some() {
+            INDIRECT_VARIANT
+        } else {
+            0
+        };
+        let depth_stencil = DepthStencil {
+            depth_test: options.depth_test,
+            depth_write: options.depth_write,
+            ..if self.outline_stencil {
+                DepthStencil::MASK
+            } else {
+                DepthStencil::TESTED
+            }
+        };
+        let triangles = |variant| {
+            (
+                (vertex_shader_path, fragment_shader_path),
+                PrimitiveTopology::TRIANGLE_LIST,
+                (depth_stencil, options),
+                variant,
+            )
+        };
+        let mut specs = (0..VERTEX_VARIANTS)
+            .map(|variant| triangles(variant | indirect))
+            .collect::<Vec<PipelineSpec>>();
+        specs.push((
+            (vertex_shader_path, bounds_shader_path),
+            PrimitiveTopology::LINE_LIST,
+            (DepthStencil::TESTED, PipelineOptions::default()),
+            indirect,
+        ));
+        for layout in self.vertex_layouts().into_iter().skip(1) {
+            for variant in 0..VERTEX_VARIANTS {
+                specs.push(triangles(
+                    variant | indirect | layout.index() << LAYOUT_VARIANT_SHIFT,
+                ));
+            }
+        }
+        self.create_pipelines(target, specs)
+    }
+
+    // Every pipeline of `specs` on the scene's layout, or none of them when
+    // one fails
+    fn create_pipelines(
+        &mut self,
+        target: (RenderPass, usize),
+        specs: Vec<PipelineSpec>,
+    ) -> Result<Vec<Pipeline>, Error> {
+        let mut pipelines = Vec::with_capacity(specs.len());
+        for (shader_paths, topology, state, variant) in specs {
+            match self.create_pipeline_variant(
+                shader_paths,
+                topology,
+                target,
+                self.pipeline_layout,
+                state,
+                variant,
+            ) {
+                Ok(pipeline) => pipelines.push(pipeline),
+                Err(err) => {
+                    let device = self.device.as_ref().unwrap();
+                    for pipeline in pipelines {
+                        unsafe { device.destroy_pipeline(pipeline, None) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(pipelines)
+    }
+
+    // Scene vertices through both shaders, writing `color_attachments`
+    // attachments of `render_pass` without blending. Without a depth test the
+    // primitives are drawn on top of everything and leave the depth alone
+    fn create_pipeline(
+        &mut self,
+        shader_paths: (&str, &str),
+        topology: PrimitiveTopology,
+        target: (RenderPass, usize),
+        layout: PipelineLayout,
+        depth_stencil: DepthStencil,
+    ) -> Result<Pipeline, Error> {
+        self.create_pipeline_variant(
+            shader_paths,
+            topology,
+            target,
+            layout,
+            (depth_stencil, PipelineOptions::default()),
+            0,
+        )
+    }
+
+    // `variant` sets the specialization constants of the scene vertex
+    // shader, SKINNED_VARIANT blends every vertex with its joints' matrices
+    // and MORPHED_VARIANT adds the object's morph targets first.
+    // INDIRECT_VARIANT reads the object at the draw's first instance and
+    // OUTLINE_VARIANT extrudes it by OUTLINE_WIDTH. The bits from
+    // LAYOUT_VARIANT_SHIFT up pick the vertex layout. Only the rasterizer
+    // and blend state of `options` is used, the depth state is
+    // `depth_stencil`'s
+    fn create_pipeline_variant(
+        &mut self,
+        (vertex_shader_path, fragment_shader_path): (&str, &str),
+        topology: PrimitiveTopology,
+        (render_pass, color_attachments): (RenderPass, usize),
+        layout: PipelineLayout,
+        (depth_stencil, options): (DepthStencil, PipelineOptions),
+        variant: usize,
+    ) -> Result<Pipeline, Error> {
+        let device = self.device.as_ref().unwrap();
+        let fragment_shader_module = self.shader_cache.acquire(device, fragment_shader_path)?;
+        let vertex_shader_module = match self.shader_cache.acquire(device, vertex_shader_path) {
+            Ok(module) => module,
+            Err(err) => {
+                self.shader_cache.release(fragment_shader_module);
+                return Err(err);
+            }
+        };
 
-       /* self.vertices = vec![
+        /* self.vertices = vec![
             Vertex::new(vec3(-0.5, -0.5, 0.0), vec3(1.0, 0.0, 0.0), vec2(1.0, 0.0)),
             Vertex::new(vec3(0.5, -0.5, 0.0), vec3(0.0, 1.0, 0.0), vec2(0.0, 0.0)),
             Vertex::new(vec3(0.5, 0.5, 0.0), vec3(0.0, 0.0, 1.0), vec2(0.0, 1.0)),
@@ -910,36 +1609,90 @@ impl Configuration {
             .stage(ShaderStageFlags::FRAGMENT)
             .name(name_main);
 
-        let vert_shader_create_info = PipelineShaderStageCreateInfo::default()
+        let specialization_entries = [
+            SpecializationMapEntry::default()
+                .constant_id(SKINNED_CONSTANT_ID)
+                .offset(0)
+                .size(size_of::<u32>()),
+            SpecializationMapEntry::default()
+                .constant_id(MORPHED_CONSTANT_ID)
+                .offset(size_of::<u32>() as u32)
+                .size(size_of::<u32>()),
+            SpecializationMapEntry::default()
+                .constant_id(INDIRECT_CONSTANT_ID)
+                .offset(2 * size_of::<u32>() as u32)
+                .size(size_of::<u32>()),
+            SpecializationMapEntry::default()
+                .constant_id(ENTRY_STRIDE_CONSTANT_ID)
+                .offset(3 * size_of::<u32>() as u32)
+                .size(size_of::<u32>()),
+            SpecializationMapEntry::default()
+                .constant_id(PACKED_NORMALS_CONSTANT_ID)
+                .offset(4 * size_of::<u32>() as u32)
+                .size(size_of::<u32>()),
+            SpecializationMapEntry::default()
+                .constant_id(OUTLINE_WIDTH_CONSTANT_ID)
+                .offset(5 * size_of::<u32>() as u32)
+                .size(size_of::<f32>()),
+        ];
+        let vertex_layout = VERTEX_LAYOUTS
+            .get(variant >> LAYOUT_VARIANT_SHIFT)
+            .copied()
+            .unwrap_or_default();
+        // In vec4s
+        let entry_stride = (self.uniform_entry_stride() / 16) as u32;
+        let outline_width = if variant & OUTLINE_VARIANT != 0 {
+            OUTLINE_WIDTH
+        } else {
+            0.0
+        };
+        let specialization_data: Vec<u8> = [SKINNED_VARIANT, MORPHED_VARIANT, INDIRECT_VARIANT]
+            .into_iter()
+            .map(|flag| u32::from(variant & flag != 0))
+            .chain([
+                entry_stride,
+                u32::from(vertex_layout.packed_normals()),
+                outline_width.to_bits(),
+            ])
+            .flat_map(u32::to_ne_bytes)
+            .collect();
+        let specialization_info = SpecializationInfo::default()
+            .map_entries(&specialization_entries)
+            .data(&specialization_data);
+        let mut vert_shader_create_info = PipelineShaderStageCreateInfo::default()
             .module(vertex_shader_module)
             .stage(ShaderStageFlags::VERTEX)
             .name(name_main);
+        if variant != 0 {
+            vert_shader_create_info =
+                vert_shader_create_info.specialization_info(&specialization_info);
+        }
 
         let pipeline_shader_create_infos = vec![vert_shader_create_info, frag_shader_create_info];
 
-        let dynamic_states = vec![DynamicState::VIEWPORT, DynamicState::SCISSOR];
+        let mut dynamic_states = vec![DynamicState::VIEWPORT, DynamicState::SCISSOR];
+        if depth_stencil.stencil.is_some() {
+            dynamic_states.extend([
+                DynamicState::STENCIL_REFERENCE,
+                DynamicState::STENCIL_WRITE_MASK,
+            ]);
+        }
 
-        let binding_description = Vertex::get_binding_description();
-        let attribute_description = Vertex::get_attribute_description();
+        let binding_description = vertex_layout.binding_description();
+        let attribute_description = vertex_layout.attribute_description();
         let vertex_input_state = PipelineVertexInputStateCreateInfo::default()
             .vertex_binding_descriptions(&binding_description)
             .vertex_attribute_descriptions(&attribute_description);
 
         let input_assembly_create_info = PipelineInputAssemblyStateCreateInfo::default()
-            .topology(PrimitiveTopology::TRIANGLE_LIST)
+            .topology(topology)
             .primitive_restart_enable(false);
 
-        self.viewports = vec![Viewport::default()
-            .x(0.0)
-            .y(0.0)
-            .width(self.extent.unwrap().width as f32)
-            .height(self.extent.unwrap().height as f32)
-            .min_depth(0.0)
-            .max_depth(1.0)];
-
+        let extent = self.primary_surface().extent;
         self.scissors = vec![Rect2D::default()
             .offset(Offset2D::default().x(0).y(0))
-            .extent(self.extent.unwrap())];
+            .extent(extent)];
+        self.viewports = vec![viewport(&self.scissors[0])];
 
         let pipeline_dynamic_states_create_info = PipelineDynamicStateCreateInfo::default()
             .dynamic_states(&dynamic_states)
@@ -952,10 +1705,18 @@ impl Configuration {
         let rasterizer_create_info = PipelineRasterizationStateCreateInfo::default()
             .depth_clamp_enable(false)
             .rasterizer_discard_enable(false)
-            .polygon_mode(PolygonMode::FILL)
-            .line_width(1.0)
-            .cull_mode(CullModeFlags::BACK)
-            .front_face(FrontFace::COUNTER_CLOCKWISE)
+            .polygon_mode(options.polygon_mode)
+            .line_width(
+                if topology == PrimitiveTopology::LINE_LIST
+                    || options.polygon_mode == PolygonMode::LINE
+                {
+                    self.platform_quirks.line_width(LINE_WIDTH)
+                } else {
+                    1.0
+                },
+            )
+            .cull_mode(options.cull_mode)
+            .front_face(options.front_face)
             .depth_bias_enable(false)
             .depth_bias_constant_factor(0.0)
             .depth_bias_clamp(0.0)
@@ -968,16 +1729,32 @@ impl Configuration {
             .alpha_to_coverage_enable(false)
             .alpha_to_one_enable(false);
 
-        let pipeline_color_blend_attachment_state =
-            vec![PipelineColorBlendAttachmentState::default()
-                .color_write_mask(ColorComponentFlags::RGBA)
-                .blend_enable(false)
-                .src_color_blend_factor(BlendFactor::ONE)
-                .dst_color_blend_factor(BlendFactor::ZERO)
-                .color_blend_op(BlendOp::ADD)
-                .src_alpha_blend_factor(BlendFactor::ONE)
-                .dst_alpha_blend_factor(BlendFactor::ZERO)
-                .alpha_blend_op(BlendOp::ADD)];
+        // Blending keeps the destination's alpha
+        let (src_color, dst_color, src_alpha, dst_alpha) = if options.alpha_blend {
+            (
+                BlendFactor::SRC_ALPHA,
+                BlendFactor::ONE_MINUS_SRC_ALPHA,
+                BlendFactor::ZERO,
+                BlendFactor::ONE,
+            )
+        } else {
+            (
+                BlendFactor::ONE,
+                BlendFactor::ZERO,
+                BlendFactor::ONE,
+                BlendFactor::ZERO,
+            )
+        };
+        let blend_attachment_state = PipelineColorBlendAttachmentState::default()
+            .color_write_mask(ColorComponentFlags::RGBA)
+            .blend_enable(options.alpha_blend)
+            .src_color_blend_factor(src_color)
+            .dst_color_blend_factor(dst_color)
+            .color_blend_op(BlendOp::ADD)
+            .src_alpha_blend_factor(src_alpha)
+            .dst_alpha_blend_factor(dst_alpha)
+            .alpha_blend_op(BlendOp::ADD);
+        let pipeline_color_blend_attachment_state = vec![blend_attachment_state; color_attachments];
 
         let color_blend_state_create_info = PipelineColorBlendStateCreateInfo::default()
             .logic_op_enable(false)
@@ -985,24 +1762,10 @@ impl Configuration {
             .attachments(&pipeline_color_blend_attachment_state)
             .blend_constants([0.0, 0.0, 0.0, 0.0]); // OPTIONAL
 
-        let depth_stencil_state = PipelineDepthStencilStateCreateInfo::default()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_bounds_test_enable(false)
-            .min_depth_bounds(0.0)
-            .max_depth_bounds(1.0)
-            .depth_compare_op(CompareOp::LESS);
+        let depth_stencil_state = depth_stencil.create_info();
 
-        let pipeline_layout_create_info =
-            PipelineLayoutCreateInfo::default().set_layouts(&self.descriptor_set_layout);
+        let pipelines;
         unsafe {
-            self.pipeline_layout = self
-                .device
-                .as_ref()
-                .unwrap()
-                .create_pipeline_layout(&pipeline_layout_create_info, None)
-                .unwrap();
-
             let graphics_pipeline_create_infos = vec![GraphicsPipelineCreateInfo::default()
                 .vertex_input_state(&vertex_input_state)
                 .input_assembly_state(&input_assembly_create_info)
@@ -1011,49 +1774,41 @@ impl Configuration {
                 .multisample_state(&pipeline_multisample_state_create_info)
                 .color_blend_state(&color_blend_state_create_info)
                 .dynamic_state(&pipeline_dynamic_states_create_info)
-                .render_pass(self.render_pass.unwrap())
-                .layout(self.pipeline_layout)
+                .render_pass(render_pass)
+                .layout(layout)
                 .base_pipeline_handle(Pipeline::null())
                 .stages(&pipeline_shader_create_infos)
                 .subpass(0)
                 .depth_stencil_state(&depth_stencil_state)];
 
             info!("Graphics Pipeline Create Info created!");
-            self.graphics_pipelines = self
-                .device
-                .as_ref()
-                .unwrap()
-                .create_graphics_pipelines(
-                    PipelineCache::null(),
-                    &graphics_pipeline_create_infos,
-                    None,
-                )
-                .unwrap();
+            pipelines = self.device.as_ref().unwrap().create_graphics_pipelines(
+                PipelineCache::null(),
+                &graphics_pipeline_create_infos,
+                None,
+            );
         }
-        Ok(self)
+        // Pipelines keep their own copy of the shader code
+        self.shader_cache.release(fragment_shader_module);
+        self.shader_cache.release(vertex_shader_module);
+        self.shader_cache.purge(self.device.as_ref().unwrap());
+        let pipelines = pipelines.map_err(|(_, result)| result).vk_ctx(
+            "vkCreateGraphicsPipelines",
+            format_args!(
+                "building the pipeline of {vertex_shader_path} and {fragment_shader_path}"
+            ),
+        )?;
+        Ok(pipelines[0])
     }
 
-    pub fn create_framebuffers(&mut self) -> Result<&mut Configuration, &str> {
-        let extent = self.extent.unwrap();
-        for image_view in self.image_views.clone() {
-            let attachments = [image_view, self.depth_image_view];
-            let framebuffer_create_info = FramebufferCreateInfo::default()
-                .attachments(&attachments)
-                .render_pass(self.render_pass.unwrap())
-                .width(extent.width)
-                .height(extent.height)
-                .layers(1);
-            unsafe {
-                self.framebuffers.push(
-                    self.device
-                        .as_ref()
-                        .unwrap()
-                        .create_framebuffer(&framebuffer_create_info, None)
-                        .expect("Failed to create framebuffer"),
-                );
+    pub fn create_framebuffers(&mut self) -> Result<&mut Configuration, EngineError> {
+        let mut created = Ok(());
+        self.for_each_surface(|config, ctx| {
+            if created.is_ok() {
+                created = config.create_surface_framebuffers(ctx);
             }
-        }
-        info!("Framebuffers created");
+        });
+        created?;
         Ok(self)
     }
 
@@ -1063,13 +1818,22 @@ impl Configuration {
         let command_pool_create_info = CommandPoolCreateInfo::default()
             .queue_family_index(queue_family_indices.graphics_queue.unwrap())
             .flags(CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        // One-shot upload and transition commands get their own pool so they
+        // don't contend with the per-frame command buffers
+        let transient_command_pool_create_info = CommandPoolCreateInfo::default()
+            .queue_family_index(queue_family_indices.graphics_queue.unwrap())
+            .flags(CommandPoolCreateFlags::TRANSIENT);
         unsafe {
+            let device = self.device.as_ref().unwrap();
             self.command_pool = Some(
-                self.device
-                    .as_ref()
-                    .unwrap()
+                device
                     .create_command_pool(&command_pool_create_info, None)
-                    .unwrap(),
+                    .vk_expect("vkCreateCommandPool", "creating the command pool"),
+            );
+            self.transient_command_pool = Some(
+                device
+                    .create_command_pool(&transient_command_pool_create_info, None)
+                    .vk_expect("vkCreateCommandPool", "creating the transient command pool"),
             );
         }
         info!("Command pool has been created");
@@ -1077,31 +1841,16 @@ impl Configuration {
     }
 
     pub fn create_command_buffer(&mut self) -> Result<&mut Configuration, &str> {
-        let command_buffer_allocate_info = CommandBufferAllocateInfo::default()
-            .command_pool(self.command_pool.unwrap())
-            .level(CommandBufferLevel::PRIMARY)
-            .command_buffer_count(MAX_FLIGHT_FENCES);
-
-        self.command_buffer = unsafe {
-            self.device
-                .as_ref()
-                .unwrap()
-                .allocate_command_buffers(&command_buffer_allocate_info)
-                .unwrap()
-        };
+        self.for_each_surface(Self::create_surface_command_buffers);
         info!("Command Buffers have been allocated");
         Ok(self)
     }
 
     pub fn create_sync_objects(&mut self) -> Result<&mut Configuration, &str> {
-        for i in 0..MAX_FLIGHT_FENCES {
-            self.image_available_semaphores
-                .push(self.create_semaphore().unwrap());
-            self.render_finished_semaphores
-                .push(self.create_semaphore().unwrap());
-            self.in_flight_fences.push(self.create_fence().unwrap());
-        }
-
+        self.for_each_surface(Self::create_surface_sync_objects);
+        self.surfaces
+            .iter()
+            .for_each(|ctx| self.check_swapchain(ctx));
         info!("Sync Object (Semaphores, Fences) have been created");
         Ok(self)
     }
@@ -1109,20 +1858,32 @@ impl Configuration {
     fn create_semaphore(&self) -> Option<Semaphore> {
         let device = self.device.as_ref().unwrap();
         let sci = SemaphoreCreateInfo::default().flags(SemaphoreCreateFlags::default());
-        unsafe { Some(device.create_semaphore(&sci, None).unwrap()) }
+        unsafe {
+            Some(
+                device
+                    .create_semaphore(&sci, None)
+                    .vk_expect("vkCreateSemaphore", "creating a frame semaphore"),
+            )
+        }
     }
 
     fn create_fence(&self) -> Option<Fence> {
         let device = self.device.as_ref().unwrap();
         let fci = FenceCreateInfo::default().flags(FenceCreateFlags::SIGNALED);
-        unsafe { Some(device.create_fence(&fci, None).unwrap()) }
+        unsafe {
+            Some(
+                device
+                    .create_fence(&fci, None)
+                    .vk_expect("vkCreateFence", "creating a frame fence"),
+            )
+        }
     }
 
     unsafe extern "system" fn debug_callback(
         message_severity: DebugUtilsMessageSeverityFlagsEXT,
         message_type: DebugUtilsMessageTypeFlagsEXT,
         callback_data: *const DebugUtilsMessengerCallbackDataEXT<'_>,
-        user_data: *mut c_void,
+        _user_data: *mut c_void,
     ) -> u32 {
         unsafe {
             let p_callback_data = *callback_data;
@@ -1139,21 +1900,25 @@ impl Configuration {
             match message_severity {
                 DebugUtilsMessageSeverityFlagsEXT::WARNING => {
                     warn!(
+                        target: logging::VULKAN,
                         "{message_type:?} [{message_id_name} ({message_id_number})] : {message}\n"
                     );
                 }
                 DebugUtilsMessageSeverityFlagsEXT::ERROR => {
                     error!(
+                        target: logging::VULKAN,
                         "{message_type:?} [{message_id_name} ({message_id_number})] : {message}\n"
                     )
                 }
-                _ => {
-                    info!(
+                DebugUtilsMessageSeverityFlagsEXT::INFO => {
+                    debug!(
+                        target: logging::VULKAN,
                         "{message_type:?} [{message_id_name} ({message_id_number})] : {message}\n"
                     );
                 }
                 _ => {
-                    info!(
+                    trace!(
+                        target: logging::VULKAN,
                         "{message_type:?} [{message_id_name} ({message_id_number})] : {message}\n"
                     );
                 }
@@ -1162,121 +1927,690 @@ impl Configuration {
         0
     }
 
-    fn single_time_command(&self) -> Result<CommandBuffer, ()> {
-        let command_buffer_allocate_info = CommandBufferAllocateInfo::default()
-            .level(CommandBufferLevel::PRIMARY)
-            .command_pool(self.command_pool.unwrap())
-            .command_buffer_count(1);
-
-        let command_buffers = unsafe {
-            self.device
-                .as_ref()
-                .unwrap()
-                .allocate_command_buffers(&command_buffer_allocate_info)
-                .unwrap()
-        };
-
-        let command_buffer_begin_info =
-            CommandBufferBeginInfo::default().flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-        unsafe {
-            self.device
-                .as_ref()
-                .unwrap()
-                .begin_command_buffer(command_buffers[0], &command_buffer_begin_info)
-                .unwrap()
-        };
-
-        Ok(command_buffers[0])
+    pub fn seed(&self) -> u64 {
+        self.config.seed
     }
 
-    fn end_single_time_command(&self, command_buffer: CommandBuffer) {
-        let command_buffers = vec![command_buffer];
-        let device = self.device.as_ref().unwrap();
-        unsafe {
-            device.end_command_buffer(command_buffer).unwrap();
-            let submit_info = vec![SubmitInfo::default().command_buffers(&command_buffers)];
-            device
-                .queue_submit(self.graphics_queue.unwrap(), &submit_info, Fence::null())
-                .unwrap();
-            device
-                .queue_wait_idle(self.graphics_queue.unwrap())
-                .unwrap();
-            device.free_command_buffers(self.command_pool.unwrap(), &command_buffers);
-        };
+    pub fn gpu_context(&self) -> GpuContext<'_> {
+        GpuContext {
+            instance: self.instance.as_ref().unwrap(),
+            physical_device: self.physical_device.unwrap(),
+            device: self.device.as_ref().unwrap(),
+            command_pool: self.transient_command_pool.unwrap(),
+            queue: self.graphics_queue.unwrap(),
+            buffer_device_address: self.buffer_device_address.as_ref(),
+        }
     }
 
-    pub fn record_command_buffer(&mut self, command_buffer: &CommandBuffer, image_index: u32) {
+    // `first_entry` is where this surface's uniform entries start in the
+    // per-frame uniform buffer
+    pub fn record_command_buffer(
+        &self,
+        surface_index: usize,
+        image_index: u32,
+        current_frame: usize,
+        object_meshes: &[Option<Arc<MeshResource>>],
+        regions: &[Rect2D],
+        first_entry: u32,
+    ) -> Result<(), EngineError> {
+        span!("record_command_buffer");
+        if self.test_panic {
+            panic!("Test panic requested while recording a frame");
+        }
+        let ctx = &self.surfaces[surface_index];
+        // Checked before anything is recorded so a failure leaves the command
+        // buffer untouched
+        ctx.swapchain.framebuffers.get(image_index as usize).ok_or(
+            EngineError::ImageIndexOutOfRange {
+                index: image_index,
+                framebuffers: ctx.swapchain.framebuffers.len(),
+            },
+        )?;
+        let object_count = object_meshes.len() as u32;
+        let has_geometry =
+            (0..object_count).any(|index| self.object_mesh(object_meshes, index).is_some());
+        let descriptor_set = match self.descriptor_sets.get(current_frame) {
+            Some(descriptor_set) => *descriptor_set,
+            None if !has_geometry => DescriptorSet::null(),
+            None => {
+                return Err(EngineError::FrameOutOfRange {
+                    frame: current_frame,
+                    descriptor_sets: self.descriptor_sets.len(),
+                })
+            }
+        };
+        let command_buffer = &ctx.command_buffers[current_frame];
         let command_buffer_begin_info =
             CommandBufferBeginInfo::default().flags(CommandBufferUsageFlags::empty());
         let device = self.device.as_ref().unwrap();
         unsafe {
             device
                 .begin_command_buffer(*command_buffer, &command_buffer_begin_info)
-                .unwrap();
+                .vk_ctx("vkBeginCommandBuffer", "recording a frame")?;
         }
-        let framebuffer = self
-            .framebuffers
-            .get(image_index as usize)
-            .expect("Failed to get framebuffer at given image index");
+        #[cfg(feature = "profiling")]
+        self.begin_gpu_zone(
+            *command_buffer,
+            current_frame,
+            surface_index,
+            GpuZone::Frame,
+        );
+        self.cmd_begin_frame_timer(*command_buffer, surface_index, current_frame);
+        // Entries are laid out a block of every object per region
+        let first_block = (first_entry / object_count.clamp(1, MAX_OBJECTS)) as usize;
+        self.cmd_cull(
+            *command_buffer,
+            current_frame,
+            first_block..first_block + regions.len().min(MAX_VIEWPORTS as usize),
+        );
 
-        let clear_color = vec![
-            ClearValue {
+        let schedule = self.frame_passes(surface_index, current_frame);
+        for scheduled in schedule.passes {
+            self.cmd_frame_transitions(*command_buffer, ctx, image_index, &scheduled.transitions);
+            match scheduled.pass {
+                FramePass::Scene => self.cmd_scene(
+                    *command_buffer,
+                    (surface_index, image_index, current_frame),
+                    (object_meshes, has_geometry.then_some(descriptor_set)),
+                    regions,
+                    first_entry,
+                ),
+                FramePass::Ssao => self.cmd_ssao(
+                    *command_buffer,
+                    surface_index,
+                    image_index,
+                    current_frame,
+                    regions,
+                ),
+                FramePass::Bloom(stage, level) => {
+                    self.cmd_bloom(*command_buffer, surface_index, image_index, (stage, level))
+                }
+                FramePass::Upscale => self.cmd_upscale(*command_buffer, surface_index, image_index),
+                FramePass::Ui => {
+                    self.cmd_ui(*command_buffer, surface_index, image_index, current_frame)
+                }
+                FramePass::Screenshot => self.record_screenshot_copy(
+                    *command_buffer,
+                    surface_index,
+                    image_index,
+                    current_frame,
+                ),
+                FramePass::Record => self.record_frame_copy(
+                    *command_buffer,
+                    surface_index,
+                    image_index,
+                    current_frame,
+                ),
+            }
+        }
+        self.cmd_frame_transitions(
+            *command_buffer,
+            ctx,
+            image_index,
+            &schedule.final_transitions,
+        );
+        #[cfg(feature = "profiling")]
+        self.end_gpu_zone(
+            *command_buffer,
+            current_frame,
+            surface_index,
+            GpuZone::Frame,
+        );
+        self.cmd_end_frame_timer(*command_buffer, surface_index, current_frame);
+        unsafe {
+            device
+                .end_command_buffer(*command_buffer)
+                .vk_ctx("vkEndCommandBuffer", "recording a frame")?
+        };
+        Ok(())
+    }
+
+    // Every region's objects into the scene framebuffer, `first_entry` is
+    // where the surface's uniform entries start. `descriptor_set` is None
+    // when no object has a mesh, the pass only clears then
+    fn cmd_scene(
+        &self,
+        command_buffer: CommandBuffer,
+        (surface_index, image_index, current_frame): (usize, u32, usize),
+        (object_meshes, descriptor_set): (&[Option<Arc<MeshResource>>], Option<DescriptorSet>),
+        regions: &[Rect2D],
+        first_entry: u32,
+    ) {
+        let ctx = &self.surfaces[surface_index];
+        let device = self.device.as_ref().unwrap();
+        let object_count = object_meshes.len() as u32;
+        let first_block = (first_entry / object_count.clamp(1, MAX_OBJECTS)) as usize;
+        let (framebuffer, scaled) =
+            self.scene_framebuffer(ctx, ctx.swapchain.framebuffers[image_index as usize]);
+        let render_pass = if scaled {
+            self.scaled_render_pass.unwrap()
+        } else {
+            self.render_pass.unwrap()
+        };
+        // The deferred path draws the same objects into the G-buffer, the
+        // lighting pass then fills the scene framebuffer
+        let gbuffer_drawing = self.gbuffer_drawing(ctx);
+        let (render_pass, framebuffer, pipelines) = match &gbuffer_drawing {
+            Some((gbuffer_pass, gbuffer_framebuffer, pipelines)) => {
+                (*gbuffer_pass, *gbuffer_framebuffer, *pipelines)
+            }
+            None => (render_pass, framebuffer, &self.graphics_pipelines[..]),
+        };
+        let color_attachments = if gbuffer_drawing.is_some() { 3 } else { 1 };
+        let clear_color = (0..color_attachments)
+            .map(|_| ClearValue {
                 color: ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 1.0],
+                    float32: ctx.clear_color(),
                 },
-            },
-            ClearValue {
+            })
+            .chain([ClearValue {
                 depth_stencil: ClearDepthStencilValue {
                     depth: 1.0,
                     stencil: 0,
                 },
-            },
-        ];
+            }])
+            .collect::<Vec<ClearValue>>();
 
         let render_pass_begin_info = RenderPassBeginInfo::default()
-            .render_pass(self.render_pass.unwrap())
-            .framebuffer(*framebuffer)
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
             .render_area(
                 Rect2D::default()
-                    .extent(self.extent.unwrap())
+                    .extent(ctx.extent)
                     .offset(ash::vk::Offset2D { x: 0, y: 0 }),
             )
             .clear_values(&clear_color);
         unsafe {
             device.cmd_begin_render_pass(
-                *command_buffer,
+                command_buffer,
                 &render_pass_begin_info,
                 SubpassContents::INLINE,
             );
-            device.cmd_set_viewport(*command_buffer, 0, &self.viewports);
-            device.cmd_set_scissor(*command_buffer, 0, &self.scissors);
-            device.cmd_bind_pipeline(
-                *command_buffer,
-                PipelineBindPoint::GRAPHICS,
-                self.graphics_pipelines[0],
+            device.cmd_bind_pipeline(command_buffer, PipelineBindPoint::GRAPHICS, pipelines[0]);
+            self.cmd_set_stencil(command_buffer, false);
+            self.cmd_bind_material_table(command_buffer);
+
+            // Nothing to bind the vertex and index buffers for
+            let Some(descriptor_set) = descriptor_set else {
+                self.cmd_end_scene(
+                    command_buffer,
+                    (surface_index, image_index, current_frame),
+                    regions,
+                );
+                return;
+            };
+
+            // Region by region, each queue's draws in their sorted order. The
+            // batches are opaque and drawn right after the opaque queue. The
+            // G-buffer has nothing to blend with, every queue is drawn with
+            // its pipelines
+            let mut bound = None;
+            let mut bound_pipeline = Some(pipelines[0]);
+            let stride = self.uniform_buffer_stride as u32;
+            for (region_index, region) in self.framebuffer_regions(ctx, regions).enumerate() {
+                let block = first_block + region_index;
+                device.cmd_set_viewport(command_buffer, 0, &[viewport(&region)]);
+                device.cmd_set_scissor(command_buffer, 0, &[region]);
+                for queue in RenderQueue::ALL {
+                    let queue_pipelines = match gbuffer_drawing {
+                        Some(_) => pipelines,
+                        None => self.queue_pipelines(queue).unwrap_or(pipelines),
+                    };
+                    for draw in self.queued_draws(current_frame, block, queue) {
+                        let (entry, object_index) = (draw.entry, draw.object_index);
+                        let Some(mesh) = self.object_mesh(object_meshes, object_index) else {
+                            continue;
+                        };
+                        let pipeline = queue_pipelines[scene_pipeline(
+                            mesh.vertex_layout(),
+                            self.entry_variant(current_frame, entry),
+                        )];
+                        if bound_pipeline != Some(pipeline) {
+                            device.cmd_bind_pipeline(
+                                command_buffer,
+                                PipelineBindPoint::GRAPHICS,
+                                pipeline,
+                            );
+                            bound_pipeline = Some(pipeline);
+                        }
+                        if bound != Some(mesh.vertex_buffer()) {
+                            self.cmd_bind_mesh(command_buffer, mesh);
+                            bound = Some(mesh.vertex_buffer());
+                        }
+                        let lod = self.entry_lod(current_frame, entry);
+                        let palette = &self.config.debug_palette;
+                        let tint = if self.highlighted_objects.contains(&object_index) {
+                            DebugPalette::rgba(palette.selection, HIGHLIGHT_TINT_ALPHA)
+                        } else if self.show_lods && lod > 0 {
+                            DebugPalette::rgba(palette.lods[lod - 1], LOD_TINT_ALPHA)
+                        } else {
+                            [0.0; 4]
+                        };
+                        self.cmd_bind_object(
+                            command_buffer,
+                            (descriptor_set, current_frame),
+                            entry * stride,
+                            tint,
+                        );
+                        self.cmd_bind_material(command_buffer, current_frame, object_index);
+                        let (first_index, index_count) = mesh.lod(lod);
+                        let masked = self.highlighted_objects.contains(&object_index);
+                        if masked {
+                            self.cmd_set_stencil(command_buffer, true);
+                        }
+                        device.cmd_draw_indexed(
+                            command_buffer,
+                            index_count,
+                            1,
+                            first_index,
+                            0,
+                            entry,
+                        );
+                        if masked {
+                            self.cmd_set_stencil(command_buffer, false);
+                        }
+                    }
+                    if queue == RenderQueue::Opaque {
+                        self.cmd_draw_batches(
+                            command_buffer,
+                            (descriptor_set, current_frame),
+                            block,
+                            pipelines,
+                            object_meshes,
+                        );
+                        // The batches bind their own
+                        bound = None;
+                        bound_pipeline = None;
+                    }
+                }
+            }
+
+            // Drawn with the object's uniform entry so the box follows its
+            // transform, the depth test hides the edges behind other objects.
+            // The box is the model's, objects with their own mesh get none
+            if let Some(bounds_buffer) = self.bounds_buffer.as_ref().filter(|_| self.show_bounds) {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    PipelineBindPoint::GRAPHICS,
+                    pipelines[BOUNDS_PIPELINE],
+                );
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[bounds_buffer.buffer()], &[0]);
+                self.cmd_draw_objects(
+                    command_buffer,
+                    (ctx, regions),
+                    (current_frame, first_entry),
+                    object_count,
+                    |object_index, dynamic_offset| {
+                        if object_meshes
+                            .get(object_index as usize)
+                            .is_some_and(Option::is_some)
+                        {
+                            return;
+                        }
+                        let palette = &self.config.debug_palette;
+                        let color = if self.highlighted_objects.contains(&object_index) {
+                            DebugPalette::rgba(palette.selection, 1.0)
+                        } else {
+                            DebugPalette::rgba(palette.bounds, 1.0)
+                        };
+                        self.cmd_bind_object(
+                            command_buffer,
+                            (descriptor_set, current_frame),
+                            dynamic_offset,
+                            color,
+                        );
+                        device.cmd_draw(
+                            command_buffer,
+                            bounds_buffer.len() as u32,
+                            1,
+                            0,
+                            dynamic_offset / stride,
+                        );
+                    },
+                );
+            }
+
+            // Only drawn forward, the G-buffer pipelines still mask but
+            // nothing reads it
+            if !self.highlighted_objects.is_empty()
+                && gbuffer_drawing.is_none()
+                && !self.outline_pipelines.is_empty()
+            {
+                let mut bound_pipeline = None;
+                self.cmd_draw_objects(
+                    command_buffer,
+                    (ctx, regions),
+                    (current_frame, first_entry),
+                    object_count,
+                    |object_index, dynamic_offset| {
+                        if !self.highlighted_objects.contains(&object_index) {
+                            return;
+                        }
+                        let Some(mesh) = self.object_mesh(object_meshes, object_index) else {
+                            return;
+                        };
+                        let entry = dynamic_offset / stride;
+                        let pipeline = self.outline_pipelines[outline_pipeline(
+                            mesh.vertex_layout(),
+                            self.entry_variant(current_frame, entry),
+                        )];
+                        if bound_pipeline != Some(pipeline) {
+                            device.cmd_bind_pipeline(
+                                command_buffer,
+                                PipelineBindPoint::GRAPHICS,
+                                pipeline,
+                            );
+                            self.cmd_set_stencil(command_buffer, false);
+                            bound_pipeline = Some(pipeline);
+                        }
+                        self.cmd_bind_mesh(command_buffer, mesh);
+                        self.cmd_bind_object(
+                            command_buffer,
+                            (descriptor_set, current_frame),
+                            dynamic_offset,
+                            DebugPalette::rgba(self.config.debug_palette.selection, 1.0),
+                        );
+                        let (first_index, index_count) =
+                            mesh.lod(self.entry_lod(current_frame, entry));
+                        device.cmd_draw_indexed(
+                            command_buffer,
+                            index_count,
+                            1,
+                            first_index,
+                            0,
+                            entry,
+                        );
+                    },
+                );
+            }
+        }
+        self.cmd_end_scene(
+            command_buffer,
+            (surface_index, image_index, current_frame),
+            regions,
+        );
+    }
+
+    // Stencil pipelines compare against OUTLINE_STENCIL_REFERENCE, `write`
+    // lets the next draws mask with it. A no-op without them
+    fn cmd_set_stencil(&self, command_buffer: CommandBuffer, write: bool) {
+        if !self.outline_stencil {
+            return;
+        }
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_set_stencil_reference(
+                command_buffer,
+                StencilFaceFlags::FRONT_AND_BACK,
+                OUTLINE_STENCIL_REFERENCE,
             );
+            device.cmd_set_stencil_write_mask(
+                command_buffer,
+                StencilFaceFlags::FRONT_AND_BACK,
+                if write { 0xff } else { 0 },
+            );
+        }
+    }
 
-            let vertex_buffers = vec![self.vertex_buffer];
-            let offsets = vec![0];
+    // The framebuffer the scene is drawn into, and whether it is the scene
+    // target drawn with the scaled render pass. With bloom or below full
+    // resolution the scene goes into the surface's scene target first
+    fn scene_framebuffer(
+        &self,
+        ctx: &SurfaceContext,
+        swapchain_framebuffer: Framebuffer,
+    ) -> (Framebuffer, bool) {
+        match ctx.scene_target.as_ref() {
+            Some(target) if self.bloom.is_some() => (target.framebuffer, false),
+            Some(target) if self.surface_render_scale(ctx) < 1.0 => (target.framebuffer, true),
+            _ => (swapchain_framebuffer, false),
+        }
+    }
 
-            device.cmd_bind_vertex_buffers(*command_buffer, 0, &vertex_buffers, &offsets);
-            device.cmd_bind_index_buffer(*command_buffer, self.index_buffer, 0, IndexType::UINT32);
+    // On the deferred path the G-buffer is lit into the scene framebuffer
+    // first, gizmos go on top of either path. Ends the scene render pass
+    fn cmd_end_scene(
+        &self,
+        command_buffer: CommandBuffer,
+        (surface_index, image_index, current_frame): (usize, u32, usize),
+        regions: &[Rect2D],
+    ) {
+        if self
+            .gbuffer_drawing(&self.surfaces[surface_index])
+            .is_some()
+        {
+            self.cmd_deferred_lighting(command_buffer, surface_index, image_index, current_frame);
+        }
+        self.cmd_gizmos(command_buffer, surface_index, current_frame, regions);
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .cmd_end_render_pass(command_buffer)
+        };
+    }
+
+    // Uniform entries are laid out region by region, each region redraws
+    // every object with its own camera. `draw` gets the object index and the
+    // dynamic offset of its entry with the region's viewport already set,
+    // entries culled this frame are skipped
+    fn cmd_draw_objects(
+        &self,
+        command_buffer: CommandBuffer,
+        (ctx, regions): (&SurfaceContext, &[Rect2D]),
+        (current_frame, first_entry): (usize, u32),
+        object_count: u32,
+        mut draw: impl FnMut(u32, u32),
+    ) {
+        let device = self.device.as_ref().unwrap();
+        let object_count = object_count.min(MAX_OBJECTS);
+        let capacity = self.uniform_entry_capacity(current_frame);
+        for (region_index, region) in self.framebuffer_regions(ctx, regions).enumerate() {
+            unsafe {
+                device.cmd_set_viewport(command_buffer, 0, &[viewport(&region)]);
+                device.cmd_set_scissor(command_buffer, 0, &[region]);
+            }
+            for object_index in 0..object_count {
+                let entry = first_entry + region_index as u32 * object_count + object_index;
+                if entry >= capacity {
+                    break;
+                }
+                if !self.entry_visible(current_frame, entry) {
+                    continue;
+                }
+                draw(object_index, entry * self.uniform_buffer_stride as u32);
+            }
+        }
+    }
+
+    // The regions as drawn into the surface's framebuffer, rotated with the
+    // surface and shrunk to the render scale
+    fn framebuffer_regions<'a>(
+        &self,
+        ctx: &'a SurfaceContext,
+        regions: &'a [Rect2D],
+    ) -> impl Iterator<Item = Rect2D> + 'a {
+        let window_extent = ctx.window_extent();
+        let render_scale = self.surface_render_scale(ctx);
+        regions
+            .iter()
+            .take(MAX_VIEWPORTS as usize)
+            .map(move |region| {
+                render_scale::scale_rect(
+                    ctx.rotation.framebuffer_region(region, window_extent),
+                    render_scale,
+                )
+            })
+    }
+
+    // The object's own mesh, or the model for objects without one. Meshes
+    // without indices aren't drawn
+    pub fn object_mesh<'a>(
+        &'a self,
+        object_meshes: &'a [Option<Arc<MeshResource>>],
+        object_index: u32,
+    ) -> Option<&'a MeshResource> {
+        object_meshes
+            .get(object_index as usize)
+            .and_then(Option::as_ref)
+            .or(self.model.as_ref())
+            .map(|mesh| &**mesh)
+            .filter(|mesh| mesh.index_count() > 0)
+    }
+
+    fn cmd_bind_mesh(&self, command_buffer: CommandBuffer, mesh: &MeshResource) {
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertex_buffer()], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, mesh.index_buffer(), 0, mesh.index_type());
+        }
+    }
+
+    // Binds the object's uniform entry and the fragment push constant every
+    // pipeline on the main layout shares, `descriptor_set` is the frame's
+    fn cmd_bind_object(
+        &self,
+        command_buffer: CommandBuffer,
+        (descriptor_set, current_frame): (DescriptorSet, usize),
+        dynamic_offset: u32,
+        color: [f32; 4],
+    ) {
+        let device = self.device.as_ref().unwrap();
+        unsafe {
             device.cmd_bind_descriptor_sets(
-                *command_buffer,
+                command_buffer,
                 PipelineBindPoint::GRAPHICS,
                 self.pipeline_layout,
                 0,
-                &[self.descriptor_sets[image_index as usize]],
-                &[],
+                &[descriptor_set],
+                &[dynamic_offset],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&color),
             );
-            device.cmd_draw_indexed(*command_buffer, self.indices.len() as u32, 1, 0, 0, 0);
-            device.cmd_end_render_pass(*command_buffer);
-            device.end_command_buffer(*command_buffer).unwrap();
         }
+        self.cmd_push_object_address(command_buffer, current_frame, dynamic_offset);
+    }
+
+    // Uploads the edges of the mesh bounds on first use
+    pub fn set_show_bounds(&mut self, show_bounds: bool) -> Result<(), GpuBufferError> {
+        if show_bounds && self.bounds_buffer.is_none() {
+            let Some(bounds) = self.model.as_ref().and_then(|model| model.bounds()) else {
+                return Ok(());
+            };
+            let white = vec3(1.0, 1.0, 1.0);
+            let vertices = bounds
+                .edges()
+                .map(|corner| Vertex::new(corner.to_vec(), white, vec2(0.0, 0.0)));
+            let mut buffer = GpuBuffer::host_visible(
+                &self.gpu_context(),
+                vertices.len(),
+                BufferUsageFlags::VERTEX_BUFFER,
+            )?;
+            buffer.write(&vertices);
+            self.bounds_buffer = Some(buffer);
+            debug!("Mesh bounds: {bounds:?}");
+        }
+        self.show_bounds = show_bounds;
+        Ok(())
+    }
+
+    pub fn show_bounds(&self) -> bool {
+        self.show_bounds
+    }
+
+    pub fn set_show_lods(&mut self, show_lods: bool) {
+        self.show_lods = show_lods;
+    }
+
+    pub fn show_lods(&self) -> bool {
+        self.show_lods
+    }
+
+    pub fn debug_palette(&self) -> DebugPalette {
+        self.config.debug_palette
+    }
+
+    pub fn set_debug_palette(&mut self, debug_palette: DebugPalette) {
+        self.config.debug_palette = debug_palette;
+    }
+
+    // Logs `message` and has the engine show it as a toast
+    pub(crate) fn notify(&mut self, level: Level, message: String) {
+        log!(level, "{message}");
+        self.notifications.push((level, message));
+    }
+
+    pub(crate) fn take_notifications(&mut self) -> Vec<(Level, String)> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    pub fn model_path(&self) -> &Path {
+        &self.config.model_path
+    }
+
+    pub fn texture_path(&self) -> &Path {
+        &self.config.texture_path
+    }
+
+    // What meshes keep on the CPU unless their load call says otherwise
+    pub fn mesh_cpu_data(&self) -> CpuMeshData {
+        self.config.mesh_cpu_data
+    }
+
+    pub fn capture_frame(&self) -> Option<u64> {
+        self.config.capture_frame
+    }
+
+    // Swaps the mesh and texture every object is drawn with. Either both are
+    // replaced or, when loading fails, neither
+    pub fn reload_assets(
+        &mut self,
+        model_path: PathBuf,
+        texture_path: PathBuf,
+    ) -> Result<(), Error> {
+        let model = self.load_mesh(&model_path, self.config.mesh_cpu_data)?;
+        let texture = self.load_texture(&texture_path, TextureSlot::Albedo.color_space())?;
+        // The descriptor sets of frames in flight can't be rewritten
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .device_wait_idle()
+                .vk_ctx("vkDeviceWaitIdle", "reloading the assets")?
+        };
+        self.config.model_path = model_path;
+        self.config.texture_path = texture_path;
+        // The previous assets go through the deletion queue if nothing else
+        // holds on to them
+        self.model = Some(model);
+        self.texture = Some(texture);
+        self.descriptor_allocator
+            .reset(self.device.as_ref().unwrap());
+        self.create_descriptor_sets().unwrap();
+        self.bounds_buffer = None;
+        if self.show_bounds {
+            self.set_show_bounds(true)?;
+        }
+        Ok(())
     }
 
     pub fn load_model(&mut self) -> Result<&mut Configuration, Error> {
-        let mut reader = BufReader::new(File::open("src/resources/viking_room.obj")?);
+        let path = self.config.model_path.clone();
+        self.model = Some(match self.preload.take_model(&path) {
+            Some(job) => self.insert_mesh(&path, job.join()?, self.config.mesh_cpu_data)?,
+            None => self.load_mesh(&path, self.config.mesh_cpu_data)?,
+        });
+        Ok(self)
+    }
+
+    // Only reads the file, safe to call from any thread
+    fn read_mesh(path: &Path) -> Result<Mesh, Error> {
+        let mut reader = Cursor::new(utils::io::read_asset(path)?);
         let (model_buf, _) = tobj::load_obj_buf(
             &mut reader,
             &tobj::LoadOptions {
@@ -1285,28 +2619,66 @@ impl Configuration {
             },
             |_| Ok(Default::default()),
         )?;
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut unique_vertices: HashMap<[u32; 8], u32> = HashMap::new();
         for model in &model_buf {
-            for index in &model.mesh.indices {
+            // Normals have their own indices unless the file has none
+            let normal_indices = if model.mesh.normal_indices.is_empty() {
+                &model.mesh.indices
+            } else {
+                &model.mesh.normal_indices
+            };
+            for (index, normal_index) in model.mesh.indices.iter().zip(normal_indices) {
                 let pos_offset = (3 * index) as usize;
                 let tex_coord_offset = (2 * index) as usize;
-                let vertex = Vertex::new(
-                    vec3(
-                        model.mesh.positions[pos_offset],
-                        model.mesh.positions[pos_offset + 1],
-                        model.mesh.positions[pos_offset + 2],
-                    ),
-                    vec3(1.0, 1.0, 1.0),
-                    vec2(
-                        model.mesh.texcoords[tex_coord_offset],
-                        1.0 - model.mesh.texcoords[tex_coord_offset + 1],
-                    ),
+                let pos = vec3(
+                    model.mesh.positions[pos_offset],
+                    model.mesh.positions[pos_offset + 1],
+                    model.mesh.positions[pos_offset + 2],
+                );
+                let tex_coord = vec2(
+                    model.mesh.texcoords[tex_coord_offset],
+                    1.0 - model.mesh.texcoords[tex_coord_offset + 1],
                 );
-                self.vertices.push(vertex);
-                self.indices.push(self.indices.len() as u32);
+                let normal_offset = (3 * normal_index) as usize;
+                let normal = model
+                    .mesh
+                    .normals
+                    .get(normal_offset..normal_offset + 3)
+                    .map_or(vec3(0.0, 0.0, 0.0), |normal| {
+                        vec3(normal[0], normal[1], normal[2])
+                    });
+                let key = [
+                    pos.x.to_bits(),
+                    pos.y.to_bits(),
+                    pos.z.to_bits(),
+                    tex_coord.x.to_bits(),
+                    tex_coord.y.to_bits(),
+                    normal.x.to_bits(),
+                    normal.y.to_bits(),
+                    normal.z.to_bits(),
+                ];
+                let vertex_index = *unique_vertices.entry(key).or_insert_with(|| {
+                    vertices
+                        .push(Vertex::new(pos, vec3(1.0, 1.0, 1.0), tex_coord).with_normal(normal));
+                    (vertices.len() - 1) as u32
+                });
+                indices.push(vertex_index);
             }
         }
-
-        Ok(self)
+        if vertices.is_empty() || indices.is_empty() {
+            return Err(anyhow!("model produced no geometry: {}", path.display()));
+        }
+        let mesh = Mesh::new(vertices, indices);
+        info!(
+            target: logging::UPLOAD,
+            "Loaded model with {} unique vertices and {} indices ({:?})",
+            mesh.vertices.len(),
+            mesh.indices.len(),
+            mesh.index_type()
+        );
+        Ok(mesh)
     }
 
     fn find_memory_type(
@@ -1320,8 +2692,7 @@ impl Configuration {
             let memory_types = memory_properties.memory_types.to_vec();
             for i in 0..memory_properties.memory_type_count {
                 if type_filter & (1 << i) != 0
-                    && (memory_types[i as usize].property_flags & properties)
-                        != MemoryPropertyFlags::empty()
+                    && memory_types[i as usize].property_flags.contains(properties)
                 {
                     return Some(i);
                 }
@@ -1330,292 +2701,299 @@ impl Configuration {
         None
     }
 
-    fn allocate_buffer(
-        instance: &Instance,
-        physical_device: PhysicalDevice,
-        device: &Device,
-        device_size: DeviceSize,
-        usage: BufferUsageFlags,
-        memory_property_flags: MemoryPropertyFlags,
-        buffer_memory: &mut DeviceMemory,
-    ) -> Buffer {
-        let buffer_create_info = BufferCreateInfo::default()
-            .size(device_size)
-            .usage(usage)
-            .sharing_mode(SharingMode::EXCLUSIVE);
-
-        unsafe {
-            let buffer = device.create_buffer(&buffer_create_info, None).unwrap();
-
-            let mem_requirements = device.get_buffer_memory_requirements(buffer);
-            let memory_alloc_info = MemoryAllocateInfo::default()
-                .allocation_size(mem_requirements.size)
-                .memory_type_index(
-                    Self::find_memory_type(
-                        &instance,
-                        physical_device,
-                        mem_requirements.memory_type_bits,
-                        memory_property_flags,
-                    )
-                    .expect("FAILED TO FIND MEMORY TYPE"),
-                );
-
-            *buffer_memory = device.allocate_memory(&memory_alloc_info, None).unwrap();
-            device
-                .bind_buffer_memory(buffer, *buffer_memory, 0)
-                .unwrap();
-            buffer
-        }
+    // Distance between uniform entries, the entry rounded up to the device's
+    // dynamic offset alignment. Known before the buffers are, the pipelines
+    // are specialized with it
+    fn uniform_entry_stride(&self) -> DeviceSize {
+        let instance = self.instance.as_ref().unwrap();
+        let limits = unsafe {
+            instance
+                .get_physical_device_properties(self.physical_device.unwrap())
+                .limits
+        };
+        aligned_stride(
+            size_of::<UniformBufferObject>() as DeviceSize,
+            limits.min_uniform_buffer_offset_alignment,
+        )
     }
 
-    pub fn create_buffer<T>(
-        &self,
-        instance: &Instance,
-        physical_device: &PhysicalDevice,
-        device: &Device,
-        buffer_type: &Vec<T>,
-        command_pool: &CommandPool,
-        buffer_usage_flags: BufferUsageFlags,
-        memory_property_flags: MemoryPropertyFlags,
-        queue: &Queue,
-    ) -> Result<(Buffer, DeviceMemory), ()>
-    where
-        T: std::fmt::Debug,
-    {
-        let buffer_size = (size_of::<T>() * buffer_type.len()) as u64;
-        let mut staging_memory = DeviceMemory::default();
-        let mut buffer_memory = DeviceMemory::default();
-
-        let staging_buffer = Self::allocate_buffer(
-            instance,
-            *physical_device,
-            device,
-            buffer_size,
-            BufferUsageFlags::TRANSFER_SRC,
-            memory_property_flags,
-            &mut staging_memory,
+    // The entries themselves are uploaded through the ring every frame
+    pub fn create_uniform_buffer(&mut self) -> Result<&mut Configuration, GpuBufferError> {
+        self.uniform_buffer_stride = self.uniform_entry_stride();
+        self.entry_variants = vec![Vec::new(); MAX_FLIGHT_FENCES as usize];
+        info!(
+            target: logging::UPLOAD,
+            "Uniform entries are {} bytes apart", self.uniform_buffer_stride
         );
-
-        unsafe {
-            let data = device
-                .map_memory(staging_memory, 0, buffer_size, MemoryMapFlags::empty())
-                .expect("Failed to map memory");
-
-            // Fix: Use std::ptr::copy_nonoverlapping for raw memory copy
-            std::ptr::copy_nonoverlapping(buffer_type.as_ptr(), data as *mut T, buffer_type.len());
-
-            device.unmap_memory(staging_memory);
-
-            let buffer = Self::allocate_buffer(
-                instance,
-                *physical_device,
-                device,
-                buffer_size,
-                BufferUsageFlags::TRANSFER_DST | buffer_usage_flags,
-                memory_property_flags,
-                &mut buffer_memory,
-            );
-
-            self.copy_buffer(staging_buffer, buffer, buffer_size);
-
-            // Cleanup should only happen after GPU is done using the buffer
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_memory, None);
-
-            Ok((buffer, buffer_memory))
-        }
-    }
-
-    pub fn create_vertex_buffer(&mut self) -> Result<&mut Configuration, ()> {
-        (self.vertex_buffer, self.vertex_buffer_memory) = self
-            .create_buffer(
-                self.instance.as_ref().unwrap(),
-                self.physical_device.as_ref().unwrap(),
-                self.device.as_ref().unwrap(),
-                &self.vertices,
-                self.command_pool.as_ref().unwrap(),
-                BufferUsageFlags::VERTEX_BUFFER,
-                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
-                self.graphics_queue.as_ref().unwrap(),
-            )
-            .unwrap();
-        info!("Vertex buffers have been created");
         Ok(self)
     }
 
-    pub fn create_index_buffer(&mut self) -> Result<&mut Configuration, ()> {
-        (self.index_buffer, self.index_buffer_memory) = self
-            .create_buffer(
-                self.instance.as_ref().unwrap(),
-                self.physical_device.as_ref().unwrap(),
-                self.device.as_ref().unwrap(),
-                &self.indices,
-                self.command_pool.as_ref().unwrap(),
-                BufferUsageFlags::INDEX_BUFFER,
-                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
-                self.graphics_queue.as_ref().unwrap(),
-            )
-            .unwrap();
-        info!("Index buffers have been created");
-        Ok(self)
+    // Entries uploaded for the frame slot, those past it aren't written or
+    // drawn
+    pub(super) fn uniform_entry_capacity(&self, current_frame: usize) -> u32 {
+        self.entry_variants
+            .get(current_frame)
+            .map_or(0, |variants| variants.len() as u32)
     }
 
-    pub fn create_uniform_buffer(&mut self) -> Result<&mut Configuration, ()> {
-        let device = self.device.as_ref().unwrap();
-        let buffer_size_dummy: Vec<UniformBufferObject> = vec![
-            UniformBufferObject {
-                model: Matrix4::zero(),
-                view: Matrix4::zero(),
-                projection: Matrix4::zero(),
-            };
-            self.swapchain_images.len()
+    // The scene set's bindings of the frame slot's uniform entries, the
+    // dynamic entry and the storage view of every entry
+    fn write_entry_descriptors(&self, descriptor_set: DescriptorSet, current_frame: usize) {
+        let entries = self.uniform_entries[current_frame];
+        let buffer_info = [entries
+            .info()
+            .range(size_of::<UniformBufferObject>() as u64)];
+        let entries_info = [entries.info()];
+        let writes = [
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&buffer_info),
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(8)
+                .dst_array_element(0)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&entries_info),
         ];
-
-        self.uniform_buffers.clear();
-        self.uniform_buffer_memory.clear();
-
-        for _i in 0..self.swapchain_images.len() {
-            let (uniform_buffer, uniform_buffer_memory) = self
-                .create_buffer(
-                    self.instance.as_ref().unwrap(),
-                    self.physical_device.as_ref().unwrap(),
-                    device,
-                    &buffer_size_dummy,
-                    self.command_pool.as_ref().unwrap(),
-                    BufferUsageFlags::UNIFORM_BUFFER,
-                    MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
-                    self.graphics_queue.as_ref().unwrap(),
-                )
-                .unwrap();
-            self.uniform_buffers.push(uniform_buffer);
-            self.uniform_buffer_memory.push(uniform_buffer_memory);
-        }
-        info!("Uniform buffers have been created");
-        Ok(self)
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .update_descriptor_sets(&writes, &[])
+        };
     }
 
-    fn copy_buffer(&self, src_buffer: Buffer, dst_buffer: Buffer, size: DeviceSize) {
-        unsafe {
-            let command_buffer = self.single_time_command().unwrap();
-            let device = self.device.as_ref().unwrap();
-            let buffer_copy = vec![BufferCopy::default().src_offset(0).dst_offset(0).size(size)];
+    // Which triangle pipeline draws the entry, the plain one for entries
+    // that weren't written this frame
+    fn entry_variant(&self, current_frame: usize, entry: u32) -> usize {
+        self.entry_variants
+            .get(current_frame)
+            .and_then(|variants| variants.get(entry as usize))
+            .map_or(0, |variant| *variant as usize)
+    }
 
-            self.device.as_ref().unwrap().cmd_copy_buffer(
-                command_buffer,
-                src_buffer,
-                dst_buffer,
-                &buffer_copy,
+    // The frame's first upload, straight into the ring entry by entry so no
+    // frame builds a copy of every entry first. The scene set is pointed at
+    // them right away
+    pub fn update_uniform_buffer(&mut self, current_frame: usize, objects: &[UniformBufferObject]) {
+        let stride = self.uniform_buffer_stride as usize;
+        let object_count = self.reserve_uploads(current_frame, objects.len());
+        let allocation = self.upload_ring.as_mut().and_then(|ring| {
+            let size = (object_count.max(1) * stride) as DeviceSize;
+            ring.allocate(current_frame, size, ring.descriptor_alignment())
+        });
+        let Some(allocation) = allocation else {
+            if let Some(variants) = self.entry_variants.get_mut(current_frame) {
+                variants.clear();
+            }
+            return;
+        };
+        if let Some(variants) = self.entry_variants.get_mut(current_frame) {
+            variants.clear();
+            variants.extend(
+                objects[..object_count]
+                    .iter()
+                    .map(UniformBufferObject::vertex_variant),
             );
+        }
+        for (index, object) in objects.iter().take(object_count).enumerate() {
+            let bytes = bytemuck::bytes_of(object);
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    bytes.as_ptr(),
+                    allocation.ptr.add(index * stride),
+                    bytes.len(),
+                )
+            };
+        }
+        self.uniform_entries[current_frame] = allocation.range();
+        if let Some(&descriptor_set) = self.descriptor_sets.get(current_frame) {
+            self.write_entry_descriptors(descriptor_set, current_frame);
+        }
+    }
 
-            self.end_single_time_command(command_buffer)
-        };
+    // The forward shader, reading materials through the bindless table when
+    // the device has one
+    fn fragment_shader_path(&self) -> &'static str {
+        if self.bindless_capacity.is_some() {
+            FRAGMENT_BINDLESS_SHADER_PATH
+        } else {
+            FRAGMENT_SHADER_PATH
+        }
     }
 
-    pub fn window_resized(&mut self, size: PhysicalSize<u32>) {
-        self.window_resized = true;
-        self.width = size.width;
-        self.height = size.height;
+    // The scene's vertex shader, reading the object's uniform entry through a
+    // pushed address when buffer device addresses are enabled
+    fn vertex_shader_path(&self) -> &'static str {
+        if self.buffer_device_address.is_some() {
+            VERTEX_ADDRESS_SHADER_PATH
+        } else {
+            VERTEX_SHADER_PATH
+        }
     }
 
-    pub fn create_descriptor_set_layout(&mut self) -> Result<&mut Configuration, ()> {
+    pub fn create_descriptor_set_layout(&mut self) -> Result<&mut Configuration, Error> {
+        let mut reflection = ShaderReflection::reflect(
+            self.shader_cache.code(self.vertex_shader_path())?,
+            ShaderStageFlags::VERTEX,
+        )?;
+        reflection.merge(ShaderReflection::reflect(
+            self.shader_cache.code(self.fragment_shader_path())?,
+            ShaderStageFlags::FRAGMENT,
+        )?)?;
+        debug!("Reflected shader bindings: {:?}", reflection.bindings);
+        self.shader_reflection = reflection;
+
         unsafe {
-            let bindings = vec![
-                DescriptorSetLayoutBinding::default()
-                    .binding(0)
-                    .descriptor_type(DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(1)
-                    .stage_flags(ShaderStageFlags::VERTEX),
-                DescriptorSetLayoutBinding::default()
-                    .binding(1)
-                    .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .descriptor_count(1)
-                    .stage_flags(ShaderStageFlags::FRAGMENT),
+            let dynamic_uniform_bindings = ENGINE_DESCRIPTOR_BINDINGS
+                .iter()
+                .filter(|(_, ty)| *ty == DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .map(|(binding, _)| *binding)
+                .collect::<Vec<u32>>();
+            // Set 0 is the engine's, set 1 the object's material
+            let mut set_bindings = [
+                self.shader_reflection
+                    .set_layout_bindings(0, &dynamic_uniform_bindings),
+                self.shader_reflection.set_layout_bindings(1, &[]),
             ];
-
-            let descriptor_set_create_info =
-                DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
-
-            match self
-                .device
-                .as_ref()
-                .unwrap()
-                .create_descriptor_set_layout(&descriptor_set_create_info, None)
-            {
-                Ok(d) => {
-                    self.descriptor_set_layout = vec![d];
+            // The texture array is sized by the device, not the shader
+            let mut binding_flags = [Vec::new(), Vec::new()];
+            if let Some(capacity) = self.bindless_capacity {
+                for binding in &mut set_bindings[1] {
+                    if binding.descriptor_type == DescriptorType::COMBINED_IMAGE_SAMPLER {
+                        binding.descriptor_count = capacity;
+                    }
                 }
-                Err(e) => {
-                    error!("{:?}", e);
+                binding_flags[1] = bindless::binding_flags(&set_bindings[1]);
+            }
+            let mut layouts = Vec::new();
+            for (bindings, flags) in set_bindings.iter().zip(&binding_flags) {
+                let mut binding_flags_create_info =
+                    DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(flags);
+                let mut descriptor_set_create_info =
+                    DescriptorSetLayoutCreateInfo::default().bindings(bindings);
+                if !flags.is_empty() {
+                    descriptor_set_create_info = descriptor_set_create_info
+                        .flags(DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+                        .push_next(&mut binding_flags_create_info);
                 }
+                layouts.push(
+                    self.device
+                        .as_ref()
+                        .unwrap()
+                        .create_descriptor_set_layout(&descriptor_set_create_info, None)
+                        .vk_ctx(
+                            "vkCreateDescriptorSetLayout",
+                            "creating the scene descriptor set layouts",
+                        )?,
+                );
             }
-            info!("Descriptor Set Layout has been created!");
+            self.descriptor_set_layout = layouts;
+            info!("Descriptor Set Layouts have been created!");
         }
 
         Ok(self)
     }
 
-    pub fn create_descriptor_pool(&mut self) -> Result<&mut Configuration, ()> {
-        let ubo_size = vec![
-            DescriptorPoolSize::default()
-                .ty(DescriptorType::UNIFORM_BUFFER)
-                .descriptor_count(MAX_FLIGHT_FENCES),
-            DescriptorPoolSize::default()
-                .ty(DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .descriptor_count(MAX_FLIGHT_FENCES),
-        ];
-
-        let pool_create_info = DescriptorPoolCreateInfo::default()
-            .pool_sizes(&ubo_size)
-            .max_sets(MAX_FLIGHT_FENCES);
-
+    // Only depends on the descriptor set layout and push constants, so every
+    // pipeline built afterwards shares it
+    pub fn create_pipeline_layout(&mut self) -> Result<&mut Configuration, Error> {
+        self.platform_quirks
+            .check_push_constants(&self.shader_reflection.push_constant_ranges)?;
+        let device = self.device.as_ref().unwrap();
+        let pipeline_layout_create_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(&self.descriptor_set_layout)
+            .push_constant_ranges(&self.shader_reflection.push_constant_ranges);
         unsafe {
-            self.descriptor_pool = self
-                .device
-                .as_ref()
-                .unwrap()
-                .create_descriptor_pool(&pool_create_info, None)
-                .unwrap()
-        };
-        info!("Descriptor Pool has been created!");
+            if self.pipeline_layout != PipelineLayout::null() {
+                device.destroy_pipeline_layout(self.pipeline_layout, None);
+            }
+            self.pipeline_layout = device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .vk_ctx(
+                    "vkCreatePipelineLayout",
+                    "creating the scene pipeline layout",
+                )?;
+        }
+        Ok(self)
+    }
+
+    pub fn create_descriptor_pool(&mut self) -> Result<&mut Configuration, ()> {
+        self.descriptor_allocator = DescriptorAllocator::default();
         Ok(self)
     }
 
     pub fn create_descriptor_sets(&mut self) -> Result<&mut Configuration, ()> {
         let layouts = vec![self.descriptor_set_layout[0]; MAX_FLIGHT_FENCES as usize];
-        let descriptor_set_allocate_info = DescriptorSetAllocateInfo::default()
-            .descriptor_pool(self.descriptor_pool)
-            .set_layouts(&layouts);
-
-        self.descriptor_sets = unsafe {
-            self.device
-                .as_ref()
-                .unwrap()
-                .allocate_descriptor_sets(&descriptor_set_allocate_info)
-                .expect("Failed to allocate descriptor sets")
-        };
+        self.descriptor_sets = self
+            .descriptor_allocator
+            .allocate(self.device.as_ref().unwrap(), &layouts)
+            .vk_expect(
+                "vkAllocateDescriptorSets",
+                "creating the scene descriptor sets",
+            );
         for i in 0..MAX_FLIGHT_FENCES {
-            let buffer_info = vec![DescriptorBufferInfo::default()
-                .buffer(self.uniform_buffers[i as usize])
-                .offset(0)
-                .range(size_of::<UniformBufferObject>() as u64)];
-
+            self.write_entry_descriptors(self.descriptor_sets[i as usize], i as usize);
             let image_info = vec![DescriptorImageInfo::default()
                 .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .image_view(self.texture_image_view)
+                .image_view(self.texture.as_ref().unwrap().view())
                 .sampler(self.texture_sampler)];
+            let light_buffer_info = [self.light_buffer_info(i as usize)];
+            let joint_buffer_info = [self.joint_buffer_info(i as usize)];
+            let morph_buffer_info = [self.morph_buffer_info()];
+            let ibl = self.ibl.as_ref().unwrap();
+            let ibl_info = [&ibl.irradiance, &ibl.prefiltered, &ibl.brdf_lut].map(|texture| {
+                [DescriptorImageInfo::default()
+                    .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(texture.view())
+                    .sampler(ibl.sampler)]
+            });
             let write_dst_set = vec![
                 WriteDescriptorSet::default()
                     .dst_set(self.descriptor_sets[i as usize])
-                    .dst_binding(0)
+                    .dst_binding(1)
+                    .dst_array_element(0)
+                    .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&image_info),
+                WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_sets[i as usize])
+                    .dst_binding(2)
                     .dst_array_element(0)
-                    .descriptor_type(DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(&buffer_info),
+                    .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&light_buffer_info),
                 WriteDescriptorSet::default()
                     .dst_set(self.descriptor_sets[i as usize])
-                    .dst_binding(1)
+                    .dst_binding(3)
+                    .dst_array_element(0)
+                    .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&joint_buffer_info),
+                WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_sets[i as usize])
+                    .dst_binding(4)
+                    .dst_array_element(0)
+                    .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&morph_buffer_info),
+                WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_sets[i as usize])
+                    .dst_binding(5)
                     .dst_array_element(0)
                     .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .image_info(&image_info),
+                    .image_info(&ibl_info[0]),
+                WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_sets[i as usize])
+                    .dst_binding(6)
+                    .dst_array_element(0)
+                    .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&ibl_info[1]),
+                WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_sets[i as usize])
+                    .dst_binding(7)
+                    .dst_array_element(0)
+                    .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&ibl_info[2]),
             ];
             unsafe {
                 self.device
@@ -1629,53 +3007,47 @@ impl Configuration {
     }
 
     pub fn create_depth_resources(&mut self) -> Result<&mut Configuration, ()> {
-        let extent = self.extent.unwrap();
-        let texture = Texture::new(extent.width, extent.height, 0, 1);
-        let depth_format = self.find_depth_format();
-        (self.depth_image, self.depth_image_memory) = self
-            .create_image(
-                texture,
-                depth_format,
-                ImageTiling::OPTIMAL,
-                ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-                MemoryPropertyFlags::DEVICE_LOCAL,
-            )
-            .unwrap();
-
-        debug!("{:?}", self.depth_image);
-        self.depth_image_view = self
-            .create_image_view(&self.depth_image, depth_format, ImageAspectFlags::DEPTH)
-            .unwrap();
-        self.transition_image_layout(
-            self.depth_image,
-            depth_format,
-            ImageLayout::UNDEFINED,
-            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-        )
-        .unwrap();
+        self.for_each_surface(Self::create_surface_depth_resources);
         Ok(self)
     }
 
     fn has_stencil_component(format: Format) -> bool {
-        debug!(
-            "{}",
-            format.eq(&Format::D32_SFLOAT_S8_UINT) || format.eq(&Format::D24_UNORM_S8_UINT)
-        );
-        format.eq(&Format::D32_SFLOAT_S8_UINT) || format.eq(&Format::D24_UNORM_S8_UINT)
+        format.eq(&Format::D32_SFLOAT_S8_UINT)
+            || format.eq(&Format::D24_UNORM_S8_UINT)
+            || format.eq(&Format::D16_UNORM_S8_UINT)
     }
 
+    // `config.stencil` asks for a stencil aspect, depth only formats are the
+    // fallback when the device has none
     fn find_depth_format(&self) -> Format {
-        return self
-            .find_supported_format(
-                vec![
-                    Format::D32_SFLOAT,
-                    Format::D32_SFLOAT_S8_UINT,
-                    Format::D24_UNORM_S8_UINT,
-                ],
-                ImageTiling::OPTIMAL,
-                FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
-            )
-            .unwrap();
+        let stencil_format = self
+            .config
+            .stencil
+            .then(|| {
+                self.find_supported_format(
+                    vec![
+                        Format::D32_SFLOAT_S8_UINT,
+                        Format::D24_UNORM_S8_UINT,
+                        Format::D16_UNORM_S8_UINT,
+                    ],
+                    ImageTiling::OPTIMAL,
+                    FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+                )
+            })
+            .flatten();
+        if let Some(format) = stencil_format {
+            return format;
+        }
+        self.find_supported_format(
+            vec![
+                Format::D32_SFLOAT,
+                Format::D32_SFLOAT_S8_UINT,
+                Format::D24_UNORM_S8_UINT,
+            ],
+            ImageTiling::OPTIMAL,
+            FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        )
+        .unwrap()
     }
 
     fn find_supported_format(
@@ -1692,128 +3064,73 @@ impl Configuration {
                     .get_physical_device_format_properties(self.physical_device.unwrap(), format)
             };
 
-            if tiling.eq(&ImageTiling::LINEAR)
-                && (physical_device_format_properties.linear_tiling_features & format_feature_flags)
-                    == format_feature_flags
-            {
-                return Some(format);
-            } else if tiling.eq(&ImageTiling::OPTIMAL)
-                && (physical_device_format_properties.optimal_tiling_features
-                    & format_feature_flags)
-                    == format_feature_flags
-            {
+            let tiling_features = match tiling {
+                ImageTiling::LINEAR => physical_device_format_properties.linear_tiling_features,
+                ImageTiling::OPTIMAL => physical_device_format_properties.optimal_tiling_features,
+                _ => continue,
+            };
+            if (tiling_features & format_feature_flags) == format_feature_flags {
                 return Some(format);
             }
         }
         None
     }
 
+    pub fn image_aspect(format: Format) -> ImageAspectFlags {
+        match format {
+            Format::D16_UNORM | Format::D32_SFLOAT | Format::X8_D24_UNORM_PACK32 => {
+                ImageAspectFlags::DEPTH
+            }
+            _ if Self::has_stencil_component(format) => {
+                ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
+            }
+            _ => ImageAspectFlags::COLOR,
+        }
+    }
+
+    // Records into `command_buffer` when given, otherwise submits and waits
+    // on a single time command
     fn transition_image_layout(
         &self,
         image: Image,
-        format: Format,
         old_image_layout: ImageLayout,
         new_image_layout: ImageLayout,
-    ) -> Result<(), &str> {
-        let command = self.single_time_command().unwrap();
-
-        let aspect_flag = if new_image_layout == ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL {
-            if Self::has_stencil_component(format) {
-                ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
-            } else {
-                ImageAspectFlags::DEPTH
+        subresource_range: ImageSubresourceRange,
+        command_buffer: Option<CommandBuffer>,
+    ) -> Result<(), String> {
+        let barrier =
+            ImageBarrier::transition(image, old_image_layout, new_image_layout, subresource_range)?;
+
+        match command_buffer {
+            Some(command_buffer) => self.cmd_image_barrier(command_buffer, barrier),
+            None => {
+                let command = self.gpu_context().begin_single_time_command();
+                self.cmd_image_barrier(command.command_buffer(), barrier);
+                command.submit();
             }
-        } else {
-            ImageAspectFlags::COLOR
-        };
-        let (src_access_mask, dst_access_mask, src_stage_mask, dst_stage_mask) =
-            match (old_image_layout, new_image_layout) {
-                (ImageLayout::UNDEFINED, ImageLayout::TRANSFER_DST_OPTIMAL) => (
-                    AccessFlags::empty(),
-                    AccessFlags::TRANSFER_WRITE,
-                    PipelineStageFlags::TOP_OF_PIPE,
-                    PipelineStageFlags::TRANSFER,
-                ),
-                (ImageLayout::TRANSFER_DST_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
-                    AccessFlags::TRANSFER_WRITE,
-                    AccessFlags::SHADER_READ,
-                    PipelineStageFlags::TRANSFER,
-                    PipelineStageFlags::FRAGMENT_SHADER,
-                ),
-                (ImageLayout::UNDEFINED, ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL) => (
-                    AccessFlags::empty(),
-                    AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                        | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                    PipelineStageFlags::TOP_OF_PIPE,
-                    PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-                ),
-                _ => return Err("Unsupported image layout transition"),
-            };
-
-        let sub_resource_range = ImageSubresourceRange::default()
-            .aspect_mask(aspect_flag)
-            .base_mip_level(0)
-            .level_count(1)
-            .base_array_layer(0)
-            .layer_count(1);
-
-        let pipeline = vec![ImageMemoryBarrier::default()
-            .old_layout(old_image_layout)
-            .new_layout(new_image_layout)
-            .src_queue_family_index(QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
-            .image(image)
-            .subresource_range(sub_resource_range)
-            .src_access_mask(src_access_mask)
-            .dst_access_mask(dst_access_mask)];
-
-        unsafe {
-            self.device.as_ref().unwrap().cmd_pipeline_barrier(
-                command,
-                src_stage_mask,
-                dst_stage_mask,
-                DependencyFlags::empty(),
-                &[] as &[MemoryBarrier],
-                &[] as &[BufferMemoryBarrier],
-                &pipeline,
-            )
-        };
-
-        self.end_single_time_command(command);
+        }
         Ok(())
     }
 
-    fn copy_buffer_to_image(&self, buffer: Buffer, image: Image, texture: Texture) {
-        let command_buffer = self.single_time_command().unwrap();
-
-        let image_subresource_range = ImageSubresourceLayers::default()
-            .aspect_mask(ImageAspectFlags::COLOR)
-            .mip_level(0)
-            .base_array_layer(0)
-            .layer_count(1);
-
-        let region = BufferImageCopy::default()
-            .buffer_offset(0)
-            .buffer_row_length(0)
-            .buffer_image_height(0)
-            .image_subresource(image_subresource_range)
-            .image_offset(Offset3D::default().x(0).y(0).z(0))
-            .image_extent(texture.into());
-
+    fn copy_buffer_to_image(&self, buffer: Buffer, image: Image, regions: &[BufferImageCopy]) {
+        let command = self.gpu_context().begin_single_time_command();
         unsafe {
             self.device.as_ref().unwrap().cmd_copy_buffer_to_image(
-                command_buffer,
+                command.command_buffer(),
                 buffer,
                 image,
                 ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[region],
+                regions,
             )
         };
-        self.end_single_time_command(command_buffer);
+        command.submit();
     }
 
     pub fn build(&mut self) -> Configuration {
+        #[cfg(feature = "profiling")]
+        self.create_gpu_profiler();
         Configuration {
+            config: self.config.clone(),
             vulkan_entry: self.vulkan_entry.clone(),
             instance: self.instance.clone(),
             physical_device: self.physical_device,
@@ -1822,137 +3139,319 @@ impl Configuration {
             device: self.device.clone(),
             graphics_queue: self.graphics_queue,
             presentation_queue: self.presentation_queue,
+            transfer_queue: self.transfer_queue,
+            timeline_semaphore: self.timeline_semaphore.clone(),
             device_extensions: self.device_extensions.clone(),
+            instance_extensions: self.instance_extensions.clone(),
+            enabled_layers: self.enabled_layers.clone(),
+            synchronization2: self.synchronization2.clone(),
+            buffer_device_address: self.buffer_device_address.clone(),
+            draw_indirect_count: self.draw_indirect_count.clone(),
+            full_screen_exclusive: self.full_screen_exclusive.clone(),
+            display_timing: self.display_timing.clone(),
+            present_wait: self.present_wait.clone(),
+            present_stats: std::mem::take(&mut self.present_stats),
             surface_instance: self.surface_instance.clone(),
-            surface: self.surface,
             surface_format: self.surface_format,
-            present_mode: self.present_mode,
-            extent: self.extent,
-            image_count: self.image_count,
-            swapchain_support_details: self.swapchain_support_details.clone(),
             swapchain_device: self.swapchain_device.clone(),
-            swapchain: self.swapchain,
-            swapchain_images: self.swapchain_images.clone(),
-            image_views: self.image_views.clone(),
+            surfaces: std::mem::take(&mut self.surfaces),
+            swapchain_recreations: self.swapchain_recreations,
+            present_mode: self.present_mode,
             viewports: self.viewports.clone(),
             scissors: self.scissors.clone(),
 
             render_pass: self.render_pass,
+            scaled_render_pass: self.scaled_render_pass,
+            ui_pass: self.ui_pass,
             pipeline_layout: self.pipeline_layout,
             graphics_pipelines: self.graphics_pipelines.clone(),
+            pipeline_options: self.pipeline_options,
+            pipeline_variants: std::mem::take(&mut self.pipeline_variants),
+            error_pipelines: self.error_pipelines.clone(),
+            shader_watcher: std::mem::take(&mut self.shader_watcher),
+            outline_stencil: self.outline_stencil,
+            outline_pipelines: self.outline_pipelines.clone(),
 
-            framebuffers: self.framebuffers.clone(),
             command_pool: self.command_pool,
-            command_buffer: self.command_buffer.clone(),
-
-            image_available_semaphores: self.image_available_semaphores.clone(),
-            render_finished_semaphores: self.render_finished_semaphores.clone(),
-            in_flight_fences: self.in_flight_fences.clone(),
+            transient_command_pool: self.transient_command_pool,
 
-            descriptor_pool: self.descriptor_pool.clone(),
+            descriptor_allocator: self.descriptor_allocator.clone(),
             descriptor_set_layout: self.descriptor_set_layout.clone(),
+            shader_reflection: self.shader_reflection.clone(),
+            shader_cache: std::mem::take(&mut self.shader_cache),
             descriptor_sets: self.descriptor_sets.clone(),
 
-            vertices: self.vertices.clone(),
-            vertex_buffer: self.vertex_buffer.clone(),
-            vertex_buffer_memory: self.vertex_buffer_memory,
-
-            indices: self.indices.clone(),
-            index_buffer: self.index_buffer.clone(),
-            index_buffer_memory: self.index_buffer_memory,
+            model: self.model.take(),
+            texture: self.texture.take(),
+            preload: jobs::Preload::default(),
+            asset_cache: std::mem::take(&mut self.asset_cache),
+            deletion_queue: self.deletion_queue.clone(),
+            texture_streamer: self.texture_streamer.take(),
+            fallback_texture: self.fallback_texture.take(),
+            stale_texture_descriptors: self.stale_texture_descriptors,
+            texture_watcher: std::mem::take(&mut self.texture_watcher),
+
+            frame_timer: self.frame_timer.take(),
+            resolution_scaler: self.resolution_scaler,
+            render_scale_override: self.render_scale_override,
+            gpu_frame_time: self.gpu_frame_time,
+
+            bloom: self.bloom.take(),
+            ssao: self.ssao.take(),
+            deferred: self.deferred.take(),
+
+            bounds_buffer: self.bounds_buffer.take(),
+            show_bounds: self.show_bounds,
+            show_lods: self.show_lods,
+            platform_quirks: self.platform_quirks,
+            device_limits: self.device_limits,
+            limit_adjustments: std::mem::take(&mut self.limit_adjustments),
+
+            uniform_entries: std::mem::take(&mut self.uniform_entries),
+            upload_ring: self.upload_ring.take(),
+            light_ranges: std::mem::take(&mut self.light_ranges),
+            joint_buffers: std::mem::take(&mut self.joint_buffers),
+            morph_pool: self.morph_pool.take(),
+            ibl: self.ibl.take(),
+            materials: self.materials.take(),
+            bindless_capacity: self.bindless_capacity,
+            entry_variants: std::mem::take(&mut self.entry_variants),
+            culling: self.culling.take(),
+            culled_frames: std::mem::take(&mut self.culled_frames),
+            culling_stats: self.culling_stats,
+            uniform_buffer_stride: self.uniform_buffer_stride,
 
-            uniform_buffers: self.uniform_buffers.clone(),
-            uniform_buffer_memory: self.uniform_buffer_memory.clone(),
-
-            texture_image: self.texture_image,
-            texture_image_view: self.texture_image_view,
-            texture_image_memory: self.texture_image_memory,
             texture_sampler: self.texture_sampler,
 
-            depth_image: self.depth_image.clone(),
-            depth_image_memory: self.depth_image_memory.clone(),
-            depth_image_view: self.depth_image_view.clone(),
-
-            width: self.width,
-            height: self.height,
-
-            window_resized: self.window_resized,
+            sampled_depth: self.sampled_depth,
 
             debug_instance: self.debug_instance.clone(),
             debug_messenger: self.debug_messenger,
-        }
-    }
 
-    pub fn recreate_swapchain(&mut self) {
-        unsafe {
-            self.device.as_ref().unwrap().device_wait_idle().unwrap();
+            screenshot: None,
+            recording: None,
 
-            self.destroy_swapchain();
-            let _ = self
-                .create_swap_chain()
-                .unwrap()
-                .create_swapchain_image_views()
-                .unwrap()
-                .create_render_pass()
-                .unwrap()
-                .create_graphics_pipeline()
-                .unwrap()
-                .create_depth_resources()
-                .unwrap()
-                .create_framebuffers()
-                .unwrap()
-                .create_uniform_buffer()
-                .unwrap()
-                .create_descriptor_pool()
-                .unwrap()
-                .create_descriptor_sets()
-                .unwrap()
-                .create_command_buffer()
-                .unwrap();
+            picking: self.picking.take(),
+            gizmos: self.gizmos.take(),
+            text: self.text.take(),
+            highlighted_objects: HashSet::new(),
+            test_panic: false,
+            notifications: Vec::new(),
+
+            #[cfg(feature = "profiling")]
+            gpu_profiler: self.gpu_profiler.take(),
         }
     }
 
-    fn destroy_swapchain(&mut self) {
+    pub fn destroy(&mut self) {
         unsafe {
-            let device = self.device.as_ref().unwrap();
-            device.destroy_image_view(self.depth_image_view, None);
-            device.free_memory(self.depth_image_memory, None);
-            device.destroy_image(self.depth_image, None);
-            self.uniform_buffers
-                .iter()
-                .for_each(|b| device.destroy_buffer(*b, None));
-            self.uniform_buffer_memory
-                .iter()
-                .for_each(|ub| device.free_memory(*ub, None));
-            self.framebuffers
-                .iter()
-                .for_each(|f| device.destroy_framebuffer(*f, None));
-            self.framebuffers.clear();
-            device.free_command_buffers(self.command_pool.unwrap(), &self.command_buffer);
-            device.destroy_pipeline(self.graphics_pipelines[0], None);
-            device.destroy_render_pass(self.render_pass.unwrap(), None);
-            self.image_views
-                .iter()
-                .for_each(|v| device.destroy_image_view(*v, None));
-            self.image_views.clear();
-
-            self.swapchain_device
+            self.device
                 .as_ref()
                 .unwrap()
-                .destroy_swapchain(self.swapchain.unwrap(), None);
-            self.in_flight_fences
-                .resize(self.swapchain_images.len(), Fence::null());
-        }
-    }
-
-    pub fn destroy(&mut self) {
-        self.destroy_swapchain();
+                .device_wait_idle()
+                .vk_expect("vkDeviceWaitIdle", "shutting down")
+        };
+        self.stop_recording();
+        #[cfg(feature = "profiling")]
+        self.destroy_gpu_profiler();
+        self.destroy_frame_timer();
+        let mut surfaces = std::mem::take(&mut self.surfaces);
+        surfaces
+            .iter_mut()
+            .for_each(|ctx| self.destroy_surface_context(ctx));
+        self.uniform_entries.clear();
+        self.upload_ring = None;
+        self.light_ranges.clear();
+        self.joint_buffers.clear();
+        self.morph_pool = None;
+        self.destroy_environment_maps();
+        self.destroy_materials();
+        self.bounds_buffer = None;
+        self.destroy_texture_streamer();
+        self.model = None;
+        self.texture = None;
+        self.screenshot = None;
+        self.destroy_picking_pass();
+        self.destroy_gizmo_pass();
+        self.destroy_text_pass();
+        self.destroy_bloom_pass();
+        self.destroy_ssao_pass();
+        self.destroy_deferred_pass();
+        self.destroy_culling_pass();
+        let scene_pipelines = self.take_pipeline_variants();
         let device = self.device.as_ref().unwrap();
-        let instance = self.instance.as_ref().unwrap();
         unsafe {
-            device.destroy_image(self.texture_image, None);
-            device.free_memory(self.texture_image_memory, None);
-            device.destroy_image_view(self.texture_image_view, None);
+            scene_pipelines
+                .into_iter()
+                .chain(self.outline_pipelines.drain(..))
+                .chain(self.error_pipelines.drain(..))
+                .for_each(|pipeline| device.destroy_pipeline(pipeline, None));
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.pipeline_layout = PipelineLayout::null();
+            self.descriptor_set_layout
+                .drain(..)
+                .for_each(|layout| device.destroy_descriptor_set_layout(layout, None));
+            for render_pass in [
+                self.render_pass.take(),
+                self.scaled_render_pass.take(),
+                self.ui_pass.take(),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                device.destroy_render_pass(render_pass, None);
+            }
+            device.destroy_sampler(self.texture_sampler, None);
+            if let Some(pool) = self.transient_command_pool.take() {
+                device.destroy_command_pool(pool, None);
+            }
+        };
+        self.descriptor_allocator.destroy(device);
+        self.shader_cache.destroy(device);
+        self.deletion_queue.destroy_all(device);
+    }
+}
+
+// Shaders, topology, depth and rasterizer state and variant bits of a
+// pipeline on the scene's layout, see `create_pipeline_variant`
+type PipelineSpec<'a> = (
+    (&'a str, &'a str),
+    PrimitiveTopology,
+    (DepthStencil, PipelineOptions),
+    usize,
+);
+
+// Depth and stencil state of a pipeline. With a stencil test the reference
+// and write mask are dynamic state, set before drawing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DepthStencil {
+    depth_test: bool,
+    depth_write: bool,
+    stencil: Option<StencilTest>,
+}
+
+// The same for both faces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StencilTest {
+    compare_op: CompareOp,
+    fail_op: StencilOp,
+    pass_op: StencilOp,
+    depth_fail_op: StencilOp,
+}
+
+impl DepthStencil {
+    const NONE: DepthStencil = DepthStencil {
+        depth_test: false,
+        depth_write: false,
+        stencil: None,
+    };
+    const TESTED: DepthStencil = DepthStencil {
+        depth_test: true,
+        depth_write: true,
+        stencil: None,
+    };
+    // Hidden behind what was drawn before, without covering anything drawn
+    // after
+    const READ_ONLY: DepthStencil = DepthStencil {
+        depth_test: true,
+        depth_write: false,
+        stencil: None,
+    };
+    // Depth tested, writes the reference over the whole silhouette, hidden
+    // parts included, where the write mask lets it
+    const MASK: DepthStencil = DepthStencil {
+        depth_test: true,
+        depth_write: true,
+        stencil: Some(StencilTest {
+            compare_op: CompareOp::ALWAYS,
+            fail_op: StencilOp::KEEP,
+            pass_op: StencilOp::REPLACE,
+            depth_fail_op: StencilOp::REPLACE,
+        }),
+    };
+    // On top of everything, only where the stencil differs from the reference
+    const OUTSIDE_MASK: DepthStencil = DepthStencil {
+        depth_test: false,
+        depth_write: false,
+        stencil: Some(StencilTest {
+            compare_op: CompareOp::NOT_EQUAL,
+            fail_op: StencilOp::KEEP,
+            pass_op: StencilOp::KEEP,
+            depth_fail_op: StencilOp::KEEP,
+        }),
+    };
+
+    fn create_info(&self) -> PipelineDepthStencilStateCreateInfo<'static> {
+        let stencil_state = self.stencil.map(|stencil| {
+            StencilOpState::default()
+                .compare_op(stencil.compare_op)
+                .fail_op(stencil.fail_op)
+                .pass_op(stencil.pass_op)
+                .depth_fail_op(stencil.depth_fail_op)
+                .compare_mask(0xff)
+        });
+        PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_write)
+            .depth_bounds_test_enable(false)
+            .min_depth_bounds(0.0)
+            .max_depth_bounds(1.0)
+            .depth_compare_op(CompareOp::LESS)
+            .stencil_test_enable(stencil_state.is_some())
+            .front(stencil_state.unwrap_or_default())
+            .back(stencil_state.unwrap_or_default())
+    }
+}
+
+// Index of the triangle pipeline drawing `variant` of `layout` in the scene
+// pipelines, past the bounds pipeline for the packed layouts
+fn scene_pipeline(layout: VertexLayout, variant: usize) -> usize {
+    match layout.index() {
+        0 => variant,
+        index => BOUNDS_PIPELINE + 1 + (index - 1) * VERTEX_VARIANTS + variant,
+    }
+}
+
+fn outline_pipeline(layout: VertexLayout, variant: usize) -> usize {
+    layout.index() * VERTEX_VARIANTS + variant
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn models_without_faces_have_no_geometry() {
+        let path = env::temp_dir().join(format!("caterpie-no-faces-{}.obj", std::process::id()));
+        fs::write(&path, "v 0 0 0\nv 1 0 0\nv 0 1 0\n").unwrap();
+        let err = Configuration::read_mesh(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("model produced no geometry: {}", path.display())
+        );
+    }
+
+    #[test]
+    fn one_family_for_graphics_and_presentation_is_listed_once() {
+        let indices = QueueFamilyIndices {
+            graphics_queue: Some(2),
+            presentation_queue: Some(2),
+            transfer_queue: Some(1),
+        };
+        assert_eq!(indices.unique_families(), [2]);
+    }
+
+    #[test]
+    fn split_families_are_listed_in_order() {
+        let indices = QueueFamilyIndices {
+            graphics_queue: Some(3),
+            presentation_queue: Some(0),
+            transfer_queue: None,
         };
+        assert_eq!(indices.unique_families(), [0, 3]);
+        assert!(QueueFamilyIndices::default().unique_families().is_empty());
     }
 }
@@ -0,0 +1,135 @@
+use anyhow::{anyhow, Error};
+use ash::vk::{BufferUsageFlags, DescriptorBufferInfo, WHOLE_SIZE};
+use bytemuck::{Pod, Zeroable};
+use cgmath::Vector3;
+use log::info;
+
+use super::{
+    buffer_types::gpu_buffer::{GpuBuffer, GpuBufferError},
+    Configuration,
+};
+use crate::{engine::mesh::MorphTarget, logging};
+
+// Deltas of every mesh's morph targets together, one per target and vertex
+pub const MAX_MORPH_DELTAS: usize = 1 << 18;
+// Targets blended per object, the heaviest ones win
+pub const MAX_ACTIVE_MORPH_TARGETS: usize = 8;
+
+// shader.vert's MorphDelta
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpuMorphDelta {
+    position: [f32; 4],
+    normal: [f32; 4],
+}
+
+unsafe impl Zeroable for GpuMorphDelta {}
+unsafe impl Pod for GpuMorphDelta {}
+
+// Where a mesh's targets are in the morph pool, target by target with a
+// delta per vertex each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MorphTargets {
+    pub first_delta: u32,
+    pub vertex_count: u32,
+    pub count: u32,
+}
+
+// Written once per mesh as it is uploaded, never while a frame reads it.
+// Ranges aren't handed out again when their mesh is dropped
+pub(super) struct MorphPool {
+    buffer: GpuBuffer<GpuMorphDelta>,
+    used: usize,
+}
+
+// The targets to blend for `weights`, heaviest first. Targets the mesh
+// doesn't have and zero weights are skipped
+pub fn active_morph_targets(weights: &[f32], target_count: u32) -> Vec<(u32, f32)> {
+    let mut active = weights
+        .iter()
+        .copied()
+        .enumerate()
+        .take(target_count as usize)
+        .filter(|(_, weight)| *weight != 0.0)
+        .map(|(target, weight)| (target as u32, weight))
+        .collect::<Vec<(u32, f32)>>();
+    active.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+    active.truncate(MAX_ACTIVE_MORPH_TARGETS);
+    active
+}
+
+fn delta(vector: Option<&Vector3<f32>>) -> [f32; 4] {
+    vector.map_or([0.0; 4], |vector| vector.extend(0.0).into())
+}
+
+impl Configuration {
+    pub fn create_morph_pool(&mut self) -> Result<&mut Configuration, GpuBufferError> {
+        let buffer = GpuBuffer::host_visible(
+            &self.gpu_context(),
+            MAX_MORPH_DELTAS,
+            BufferUsageFlags::STORAGE_BUFFER,
+        )?;
+        self.morph_pool = Some(MorphPool { buffer, used: 0 });
+        info!(
+            target: logging::UPLOAD,
+            "Morph pool has been created ({MAX_MORPH_DELTAS} deltas)"
+        );
+        Ok(self)
+    }
+
+    pub(super) fn upload_morph_targets(
+        &mut self,
+        vertex_count: usize,
+        targets: &[MorphTarget],
+    ) -> Result<Option<MorphTargets>, Error> {
+        if targets.is_empty() {
+            return Ok(None);
+        }
+        for (index, target) in targets.iter().enumerate() {
+            if target.positions.len() != vertex_count
+                || !(target.normals.is_empty() || target.normals.len() == vertex_count)
+            {
+                return Err(anyhow!(
+                    "Morph target {index} has {} positions and {} normals for {vertex_count} vertices",
+                    target.positions.len(),
+                    target.normals.len()
+                ));
+            }
+        }
+        let pool = self
+            .morph_pool
+            .as_mut()
+            .ok_or_else(|| anyhow!("No morph pool to upload the targets into"))?;
+        let deltas = targets
+            .iter()
+            .flat_map(|target| {
+                (0..vertex_count).map(|vertex| GpuMorphDelta {
+                    position: delta(target.positions.get(vertex)),
+                    normal: delta(target.normals.get(vertex)),
+                })
+            })
+            .collect::<Vec<GpuMorphDelta>>();
+        if pool.used + deltas.len() > MAX_MORPH_DELTAS {
+            return Err(anyhow!(
+                "{} morph deltas don't fit, {} of {MAX_MORPH_DELTAS} are taken",
+                deltas.len(),
+                pool.used
+            ));
+        }
+        let first_delta = pool.used;
+        pool.buffer.write_at(first_delta, &deltas);
+        pool.used += deltas.len();
+        Ok(Some(MorphTargets {
+            first_delta: first_delta as u32,
+            vertex_count: vertex_count as u32,
+            count: targets.len() as u32,
+        }))
+    }
+
+    pub(super) fn morph_buffer_info(&self) -> DescriptorBufferInfo {
+        DescriptorBufferInfo::default()
+            .buffer(self.morph_pool.as_ref().unwrap().buffer.buffer())
+            .offset(0)
+            .range(WHOLE_SIZE)
+    }
+}
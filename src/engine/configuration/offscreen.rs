@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use ash::vk::{
+    AccessFlags, BufferImageCopy, BufferMemoryBarrier, BufferUsageFlags, CommandBuffer,
+    DependencyFlags, Extent3D, ImageAspectFlags, ImageLayout, ImageMemoryBarrier,
+    ImageSubresourceLayers, MemoryBarrier, Offset3D, PipelineStageFlags, Rect2D, SubmitInfo,
+};
+
+use crate::{
+    engine::error::{EngineError, VkContext},
+    utils::embedded::RgbaImage,
+};
+
+use super::{buffer_types::gpu_buffer::GpuBuffer, Configuration, MeshResource};
+
+impl Configuration {
+    // Draws one frame into the offscreen target and reads it back. The render
+    // pass leaves the image in TRANSFER_SRC_OPTIMAL for the copy
+    pub fn render_offscreen(
+        &self,
+        current_frame: usize,
+        object_meshes: &[Option<Arc<MeshResource>>],
+        regions: &[Rect2D],
+    ) -> Result<RgbaImage, EngineError> {
+        let ctx = &self.surfaces[0];
+        let device = self.device.as_ref().unwrap();
+        let fence = ctx.in_flight_fences[current_frame];
+        let detail = "rendering offscreen";
+        unsafe {
+            device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .vk_ctx("vkWaitForFences", detail)?;
+            device
+                .reset_fences(&[fence])
+                .vk_ctx("vkResetFences", detail)?;
+        }
+        self.record_command_buffer(0, 0, current_frame, object_meshes, regions, 0)?;
+        let command_buffers = [ctx.command_buffers[current_frame]];
+        let submit_info = [SubmitInfo::default().command_buffers(&command_buffers)];
+        unsafe {
+            device
+                .queue_submit(self.graphics_queue.unwrap(), &submit_info, fence)
+                .vk_ctx("vkQueueSubmit", detail)?;
+            device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .vk_ctx("vkWaitForFences", detail)?;
+        }
+
+        let extent = ctx.extent;
+        let buffer = GpuBuffer::<u8>::host_visible(
+            &self.gpu_context(),
+            (extent.width * extent.height * 4) as usize,
+            BufferUsageFlags::TRANSFER_DST,
+        )?;
+        let command = self.gpu_context().begin_single_time_command();
+        self.cmd_copy_offscreen_image(command.command_buffer(), &buffer);
+        command.submit();
+
+        Ok(RgbaImage {
+            width: extent.width,
+            height: extent.height,
+            pixels: buffer.read(),
+        })
+    }
+
+    fn cmd_copy_offscreen_image(&self, command_buffer: CommandBuffer, buffer: &GpuBuffer<u8>) {
+        let ctx = &self.surfaces[0];
+        let device = self.device.as_ref().unwrap();
+        let region = BufferImageCopy::default()
+            .image_subresource(
+                ImageSubresourceLayers::default()
+                    .aspect_mask(ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1),
+            )
+            .image_offset(Offset3D::default())
+            .image_extent(Extent3D {
+                width: ctx.extent.width,
+                height: ctx.extent.height,
+                depth: 1,
+            });
+        // The frame was submitted separately, this makes its color writes
+        // visible to the copy
+        let transfer_read = [MemoryBarrier::default()
+            .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(AccessFlags::TRANSFER_READ)];
+        let host_read = [MemoryBarrier::default()
+            .src_access_mask(AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(AccessFlags::HOST_READ)];
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                PipelineStageFlags::TRANSFER,
+                DependencyFlags::empty(),
+                &transfer_read,
+                &[] as &[BufferMemoryBarrier],
+                &[] as &[ImageMemoryBarrier],
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                ctx.image(0),
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer(),
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                PipelineStageFlags::TRANSFER,
+                PipelineStageFlags::HOST,
+                DependencyFlags::empty(),
+                &host_read,
+                &[] as &[BufferMemoryBarrier],
+                &[] as &[ImageMemoryBarrier],
+            );
+        }
+    }
+}
@@ -0,0 +1,430 @@
+use std::sync::Arc;
+
+use anyhow::Error;
+use ash::vk::{
+    AccessFlags, AttachmentDescription, AttachmentLoadOp, AttachmentReference, AttachmentStoreOp,
+    BufferImageCopy, BufferMemoryBarrier, BufferUsageFlags, ClearColorValue,
+    ClearDepthStencilValue, ClearValue, CommandBuffer, DependencyFlags, DeviceMemory, Extent2D,
+    Extent3D, Format, Framebuffer, FramebufferCreateInfo, Image, ImageAspectFlags, ImageLayout,
+    ImageMemoryBarrier, ImageSubresourceLayers, ImageTiling, ImageUsageFlags, ImageView,
+    MemoryBarrier, MemoryPropertyFlags, Offset2D, Offset3D, Pipeline, PipelineBindPoint,
+    PipelineLayout, PipelineLayoutCreateInfo, PipelineStageFlags, PrimitiveTopology, Rect2D,
+    RenderPass, RenderPassBeginInfo, RenderPassCreateInfo, SampleCountFlags, ShaderStageFlags,
+    SubpassContents, SubpassDependency, SubpassDescription, SUBPASS_EXTERNAL,
+};
+use log::info;
+
+use crate::engine::{
+    error::{EngineError, VkContext},
+    viewport::viewport,
+};
+
+use super::{
+    buffer_types::gpu_buffer::GpuBuffer, culling::is_drawn, shader_reflection::ShaderReflection,
+    textures::Texture, Configuration, DepthStencil, MeshResource, PipelineOptions,
+    LAYOUT_VARIANT_SHIFT, MAX_OBJECTS, MAX_VIEWPORTS, VERTEX_SHADER_PATH,
+};
+
+pub(super) const PICKING_SHADER_PATH: &str = "src/assets/picking.spv";
+const ID_FORMAT: Format = Format::R32_UINT;
+
+// Draws object IDs instead of colors. Only built when picking is enabled and
+// only recorded when something is picked, frames never pay for it
+pub struct PickingPass {
+    render_pass: RenderPass,
+    pipeline_layout: PipelineLayout,
+    // One per vertex layout
+    pipelines: Vec<Pipeline>,
+}
+
+// Attachments of a single pick, sized like the surface so the viewports and
+// the uniform buffer of the regular frame can be reused as they are
+struct PickTarget {
+    images: [(Image, DeviceMemory); 2],
+    views: [ImageView; 2],
+    framebuffer: Framebuffer,
+}
+
+impl Configuration {
+    pub fn picking_enabled(&self) -> bool {
+        self.picking.is_some()
+    }
+
+    pub fn create_picking_pass(&mut self) -> Result<&mut Configuration, Error> {
+        if !self.config.picking {
+            return Ok(self);
+        }
+        let render_pass = self.create_picking_render_pass()?;
+        let reflection = ShaderReflection::reflect(
+            self.shader_cache.code(PICKING_SHADER_PATH)?,
+            ShaderStageFlags::FRAGMENT,
+        )?;
+        let pipeline_layout_create_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(&self.descriptor_set_layout)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        let pipeline_layout = unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .vk_ctx("vkCreatePipelineLayout", "creating the picking pass")?
+        };
+        let pipelines = self
+            .vertex_layouts()
+            .into_iter()
+            .map(|layout| {
+                self.create_pipeline_variant(
+                    (VERTEX_SHADER_PATH, PICKING_SHADER_PATH),
+                    PrimitiveTopology::TRIANGLE_LIST,
+                    (render_pass, 1),
+                    pipeline_layout,
+                    (DepthStencil::TESTED, PipelineOptions::default()),
+                    layout.index() << LAYOUT_VARIANT_SHIFT,
+                )
+            })
+            .collect::<Result<Vec<Pipeline>, Error>>()?;
+        self.picking = Some(PickingPass {
+            render_pass,
+            pipeline_layout,
+            pipelines,
+        });
+        info!("Picking pass has been created!");
+        Ok(self)
+    }
+
+    fn create_picking_render_pass(&self) -> Result<RenderPass, EngineError> {
+        let attachments = [
+            AttachmentDescription::default()
+                .format(ID_FORMAT)
+                .samples(SampleCountFlags::TYPE_1)
+                .load_op(AttachmentLoadOp::CLEAR)
+                .store_op(AttachmentStoreOp::STORE)
+                .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+                .initial_layout(ImageLayout::UNDEFINED)
+                .final_layout(ImageLayout::TRANSFER_SRC_OPTIMAL),
+            AttachmentDescription::default()
+                .format(self.find_depth_format())
+                .samples(SampleCountFlags::TYPE_1)
+                .load_op(AttachmentLoadOp::CLEAR)
+                .store_op(AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+                .initial_layout(ImageLayout::UNDEFINED)
+                .final_layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        ];
+        let color_reference = [AttachmentReference::default()
+            .attachment(0)
+            .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let depth_reference = AttachmentReference::default()
+            .attachment(1)
+            .layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        let subpasses = [SubpassDescription::default()
+            .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_reference)
+            .depth_stencil_attachment(&depth_reference)];
+        // The ID image is copied out right after the pass, the second
+        // dependency makes its writes visible to the transfer
+        let dependencies = [
+            SubpassDependency::default()
+                .src_subpass(SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .dst_stage_mask(
+                    PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ),
+            SubpassDependency::default()
+                .src_subpass(0)
+                .dst_subpass(SUBPASS_EXTERNAL)
+                .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .dst_stage_mask(PipelineStageFlags::TRANSFER)
+                .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(AccessFlags::TRANSFER_READ),
+        ];
+        let render_pass_create_info = RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_render_pass(&render_pass_create_info, None)
+                .vk_ctx("vkCreateRenderPass", "creating the picking pass")
+        }
+    }
+
+    // Index of the object drawn at `position` of the primary window, using the
+    // uniform entries of `current_frame`. Renders and reads back one texel,
+    // only objects that are `pickable` are drawn
+    pub fn pick(
+        &self,
+        current_frame: usize,
+        position: Offset2D,
+        (object_meshes, pickable): (&[Option<Arc<MeshResource>>], &[bool]),
+        regions: &[Rect2D],
+    ) -> Result<Option<u32>, EngineError> {
+        let Some(picking) = &self.picking else {
+            return Ok(None);
+        };
+        let Some(&descriptor_set) = self.descriptor_sets.get(current_frame) else {
+            return Err(EngineError::FrameOutOfRange {
+                frame: current_frame,
+                descriptor_sets: self.descriptor_sets.len(),
+            });
+        };
+        let object_count = object_meshes.len() as u32;
+        let pickable = |index: u32| is_drawn(pickable, index as usize);
+        if !(0..object_count)
+            .any(|index| pickable(index) && self.object_mesh(object_meshes, index).is_some())
+        {
+            return Ok(None);
+        }
+        let ctx = &self.surfaces[0];
+        let window_extent = ctx.window_extent();
+        let texel = ctx.rotation.framebuffer_region(
+            &Rect2D::default()
+                .offset(position)
+                .extent(Extent2D::default().width(1).height(1)),
+            window_extent,
+        );
+        // Only the region under the cursor is drawn, scissored to the texel
+        let Some((region_index, region)) = regions
+            .iter()
+            .take(MAX_VIEWPORTS as usize)
+            .map(|region| ctx.rotation.framebuffer_region(region, window_extent))
+            .enumerate()
+            .find(|(_, region)| contains(region, texel.offset))
+        else {
+            return Ok(None);
+        };
+
+        let target = self.create_pick_target(picking.render_pass, ctx.extent)?;
+        let buffer =
+            GpuBuffer::<u32>::host_visible(&self.gpu_context(), 1, BufferUsageFlags::TRANSFER_DST);
+        let buffer = match buffer {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                self.destroy_pick_target(target);
+                return Err(err.into());
+            }
+        };
+        let command = self.gpu_context().begin_single_time_command();
+        let command_buffer = command.command_buffer();
+        let device = self.device.as_ref().unwrap();
+        let clear_values = [
+            ClearValue {
+                color: ClearColorValue { uint32: [0; 4] },
+            },
+            ClearValue {
+                depth_stencil: ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = RenderPassBeginInfo::default()
+            .render_pass(picking.render_pass)
+            .framebuffer(target.framebuffer)
+            .render_area(texel)
+            .clear_values(&clear_values);
+        let object_count = object_count.min(MAX_OBJECTS);
+        let capacity = self.uniform_entry_capacity(current_frame);
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                SubpassContents::INLINE,
+            );
+            let mut bound_pipeline = None;
+            let mut bound = None;
+            device.cmd_set_viewport(command_buffer, 0, &[viewport(&region)]);
+            device.cmd_set_scissor(command_buffer, 0, &[texel]);
+            for object_index in 0..object_count {
+                let entry = region_index as u32 * object_count + object_index;
+                if entry >= capacity {
+                    break;
+                }
+                if !pickable(object_index) {
+                    continue;
+                }
+                let Some(mesh) = self.object_mesh(object_meshes, object_index) else {
+                    continue;
+                };
+                let pipeline = picking.pipelines[mesh.vertex_layout().index()];
+                if bound_pipeline != Some(pipeline) {
+                    device.cmd_bind_pipeline(command_buffer, PipelineBindPoint::GRAPHICS, pipeline);
+                    bound_pipeline = Some(pipeline);
+                }
+                if bound != Some(mesh.vertex_buffer()) {
+                    self.cmd_bind_mesh(command_buffer, mesh);
+                    bound = Some(mesh.vertex_buffer());
+                }
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    PipelineBindPoint::GRAPHICS,
+                    picking.pipeline_layout,
+                    0,
+                    &[descriptor_set],
+                    &[entry * self.uniform_buffer_stride as u32],
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    picking.pipeline_layout,
+                    ShaderStageFlags::FRAGMENT,
+                    0,
+                    &object_index.to_ne_bytes(),
+                );
+                device.cmd_draw_indexed(command_buffer, mesh.index_count(), 1, 0, 0, 0);
+            }
+            device.cmd_end_render_pass(command_buffer);
+        }
+        self.cmd_copy_texel(command_buffer, target.images[0].0, texel.offset, &buffer);
+        command.submit();
+        self.destroy_pick_target(target);
+
+        // 0 is the cleared background
+        Ok(buffer.read()[0].checked_sub(1))
+    }
+
+    fn create_pick_target(
+        &self,
+        render_pass: RenderPass,
+        extent: Extent2D,
+    ) -> Result<PickTarget, EngineError> {
+        let texture = Texture::new(extent.width, extent.height, 1);
+        let depth_format = self.find_depth_format();
+        let images = [
+            (
+                ID_FORMAT,
+                ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC,
+            ),
+            (depth_format, ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT),
+        ]
+        .map(|(format, usage)| {
+            self.create_image(
+                texture,
+                format,
+                ImageTiling::OPTIMAL,
+                usage,
+                MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+        });
+        let [color, depth] = images;
+        let images = [color?, depth?];
+        let views = [
+            self.create_image_view(&images[0].0, ID_FORMAT, ImageAspectFlags::COLOR)?,
+            self.create_image_view(&images[1].0, depth_format, Self::image_aspect(depth_format))?,
+        ];
+        let framebuffer_create_info = FramebufferCreateInfo::default()
+            .attachments(&views)
+            .render_pass(render_pass)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_framebuffer(&framebuffer_create_info, None)
+                .vk_ctx("vkCreateFramebuffer", "creating the picking framebuffer")?
+        };
+        Ok(PickTarget {
+            images,
+            views,
+            framebuffer,
+        })
+    }
+
+    fn destroy_pick_target(&self, target: PickTarget) {
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.destroy_framebuffer(target.framebuffer, None);
+            for view in target.views {
+                device.destroy_image_view(view, None);
+            }
+            for (image, memory) in target.images {
+                device.destroy_image(image, None);
+                device.free_memory(memory, None);
+            }
+        }
+    }
+
+    fn cmd_copy_texel(
+        &self,
+        command_buffer: CommandBuffer,
+        image: Image,
+        texel: Offset2D,
+        buffer: &GpuBuffer<u32>,
+    ) {
+        let device = self.device.as_ref().unwrap();
+        let region = BufferImageCopy::default()
+            .image_subresource(
+                ImageSubresourceLayers::default()
+                    .aspect_mask(ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1),
+            )
+            .image_offset(Offset3D {
+                x: texel.x,
+                y: texel.y,
+                z: 0,
+            })
+            .image_extent(Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            });
+        let host_read = [MemoryBarrier::default()
+            .src_access_mask(AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(AccessFlags::HOST_READ)];
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer(),
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                PipelineStageFlags::TRANSFER,
+                PipelineStageFlags::HOST,
+                DependencyFlags::empty(),
+                &host_read,
+                &[] as &[BufferMemoryBarrier],
+                &[] as &[ImageMemoryBarrier],
+            );
+        }
+    }
+
+    pub(super) fn destroy_picking_pass(&mut self) {
+        let Some(picking) = self.picking.take() else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            for pipeline in picking.pipelines {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(picking.pipeline_layout, None);
+            device.destroy_render_pass(picking.render_pass, None);
+        }
+    }
+}
+
+fn contains(region: &Rect2D, point: Offset2D) -> bool {
+    point.x >= region.offset.x
+        && point.y >= region.offset.y
+        && point.x < region.offset.x + region.extent.width as i32
+        && point.y < region.offset.y + region.extent.height as i32
+}
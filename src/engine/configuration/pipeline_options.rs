@@ -0,0 +1,212 @@
+use ash::vk::{CullModeFlags, FrontFace, Pipeline, PolygonMode};
+use log::info;
+
+use super::{scene_shaders::SceneVariant, Configuration};
+
+// Rasterizer and depth state of the scene pipelines that can be switched
+// while running. Every combination used gets its own set of pipelines, kept
+// until shutdown so switching back is free
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineOptions {
+    pub cull_mode: CullModeFlags,
+    pub front_face: FrontFace,
+    // LINE and POINT need the fillModeNonSolid feature, LINE draws with the
+    // wide line width where the device has wideLines
+    pub polygon_mode: PolygonMode,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    // Source alpha over what is already drawn, for text and the transparent
+    // and overlay queues. The opaque scene pipelines leave it off
+    pub alpha_blend: bool,
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        PipelineOptions {
+            cull_mode: CullModeFlags::BACK,
+            // Counter clockwise in y up clip space, the flipped viewport
+            // keeps it that way on screen
+            front_face: FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: PolygonMode::FILL,
+            depth_test: true,
+            depth_write: true,
+            alpha_blend: false,
+        }
+    }
+}
+
+impl PipelineOptions {
+    // Between filled and wireframe triangles
+    pub fn toggle_wireframe(self) -> Self {
+        let polygon_mode = if self.polygon_mode == PolygonMode::FILL {
+            PolygonMode::LINE
+        } else {
+            PolygonMode::FILL
+        };
+        PipelineOptions {
+            polygon_mode,
+            ..self
+        }
+    }
+
+    // Back faces, front faces, then none culled
+    pub fn next_cull_mode(self) -> Self {
+        let cull_mode = match self.cull_mode {
+            CullModeFlags::BACK => CullModeFlags::FRONT,
+            CullModeFlags::FRONT => CullModeFlags::NONE,
+            _ => CullModeFlags::BACK,
+        };
+        PipelineOptions { cull_mode, ..self }
+    }
+}
+
+impl Configuration {
+    pub fn pipeline_options(&self) -> PipelineOptions {
+        self.pipeline_options
+    }
+
+    // The forward path's scene pipelines for `options`, created on the first
+    // use of a combination. The deferred path keeps the default options.
+    // Shaders that fail to build for them leave the scene drawn with the
+    // error pipelines, that isn't an error here
+    pub fn set_pipeline_options(&mut self, options: PipelineOptions) -> Result<(), String> {
+        if options.polygon_mode != PolygonMode::FILL
+            && !self.platform_quirks.supports_non_solid_fill
+        {
+            return Err(format!(
+                "{:?} polygons need the fillModeNonSolid feature",
+                options.polygon_mode
+            ));
+        }
+        if !self.pipeline_variants.contains_key(&options) {
+            info!("Creating the scene pipelines for {options:?}");
+            let variant = self.create_scene_variant(options);
+            self.pipeline_variants.insert(options, variant);
+        }
+        self.graphics_pipelines = self.variant_pipelines(options);
+        self.pipeline_options = options;
+        Ok(())
+    }
+
+    // Every set of scene pipelines created so far, the current one included.
+    // The error pipelines stay
+    pub(super) fn take_pipeline_variants(&mut self) -> Vec<Pipeline> {
+        self.graphics_pipelines.clear();
+        self.pipeline_variants
+            .drain()
+            .flat_map(|(_, variant)| match variant {
+                SceneVariant::Built(pipelines) => pipelines,
+                SceneVariant::Failed(_) => Vec::new(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::{Hash, Hasher},
+    };
+
+    use super::*;
+
+    fn hash(options: PipelineOptions) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        options.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Every combination the wireframe and cull mode keys reach
+    fn reachable() -> Vec<PipelineOptions> {
+        let mut options = PipelineOptions::default();
+        let mut all = Vec::new();
+        for _ in 0..2 {
+            for _ in 0..3 {
+                all.push(options);
+                options = options.next_cull_mode();
+            }
+            options = options.toggle_wireframe();
+        }
+        all
+    }
+
+    #[test]
+    fn toggling_back_gives_the_same_key() {
+        let options = PipelineOptions::default();
+        let back = options.toggle_wireframe().toggle_wireframe();
+        assert_eq!(back, options);
+        assert_eq!(hash(back), hash(options));
+
+        let cycled = (0..3).fold(options, |options, _| options.next_cull_mode());
+        assert_eq!(cycled, options);
+        assert_eq!(hash(cycled), hash(options));
+    }
+
+    #[test]
+    fn every_field_is_part_of_the_key() {
+        let options = PipelineOptions::default();
+        let changed = [
+            PipelineOptions {
+                cull_mode: CullModeFlags::NONE,
+                ..options
+            },
+            PipelineOptions {
+                front_face: FrontFace::CLOCKWISE,
+                ..options
+            },
+            PipelineOptions {
+                polygon_mode: PolygonMode::LINE,
+                ..options
+            },
+            PipelineOptions {
+                depth_test: false,
+                ..options
+            },
+            PipelineOptions {
+                depth_write: false,
+                ..options
+            },
+            PipelineOptions {
+                alpha_blend: true,
+                ..options
+            },
+        ];
+        let mut variants: HashMap<PipelineOptions, usize> = HashMap::new();
+        for (i, options) in std::iter::once(options).chain(changed).enumerate() {
+            assert_eq!(variants.insert(options, i), None, "{options:?}");
+        }
+        assert_eq!(variants.len(), 7);
+    }
+
+    #[test]
+    fn switching_back_reuses_the_variant() {
+        // Mirrors `set_pipeline_options`: created on the first use of a
+        // combination and never evicted
+        let mut variants: HashMap<PipelineOptions, usize> = HashMap::new();
+        let mut created = 0;
+        let mut switch = |options: PipelineOptions| {
+            *variants.entry(options).or_insert_with(|| {
+                created += 1;
+                created
+            })
+        };
+
+        let reachable = reachable();
+        let first: Vec<_> = reachable.iter().map(|options| switch(*options)).collect();
+        assert_eq!(first, [1, 2, 3, 4, 5, 6]);
+        // A hundred more key presses build nothing new
+        let mut options = PipelineOptions::default();
+        for press in 0..100 {
+            options = if press % 3 == 0 {
+                options.toggle_wireframe()
+            } else {
+                options.next_cull_mode()
+            };
+            let variant = switch(options);
+            let position = reachable.iter().position(|reached| *reached == options);
+            assert_eq!(Some(variant - 1), position);
+        }
+        assert_eq!(created, 6);
+    }
+}
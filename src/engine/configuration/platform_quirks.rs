@@ -0,0 +1,353 @@
+use anyhow::{anyhow, Error};
+use ash::vk::{
+    DriverId, PhysicalDeviceDriverProperties, PhysicalDeviceFeatures, PhysicalDeviceFeatures2,
+    PhysicalDevicePortabilitySubsetFeaturesKHR, PhysicalDevicePortabilitySubsetPropertiesKHR,
+    PhysicalDeviceProperties, PhysicalDeviceProperties2, PushConstantRange,
+};
+use log::info;
+
+use crate::engine::diagnostics::CapabilityReport;
+
+use super::Configuration;
+
+// What the picked device does differently from what the engine would
+// otherwise assume. Detected once after the device is picked, the rest of
+// the engine asks here instead of probing on its own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlatformQuirks {
+    // None when the driver doesn't report VK_KHR_driver_properties
+    pub driver_id: Option<DriverId>,
+    // VK_KHR_portability_subset is advertised, so it has to be enabled.
+    // MoltenVK on macOS
+    pub needs_portability_subset: bool,
+    // Line widths other than 1
+    pub supports_wide_lines: bool,
+    pub line_width_range: [f32; 2],
+    // Line and point polygon modes
+    pub supports_non_solid_fill: bool,
+    // BC block formats can be sampled at all, Apple GPUs mostly can't
+    pub supports_bc: bool,
+    pub max_push_constant_size: u32,
+    // Portability subset features, always true on a full implementation
+    pub supports_triangle_fans: bool,
+    pub supports_separate_stencil_mask_ref: bool,
+    pub supports_image_view_format_swizzle: bool,
+    // Alignment vertex binding strides need, 1 on a full implementation
+    pub vertex_stride_alignment: u32,
+}
+
+impl Default for PlatformQuirks {
+    // Vulkan's guaranteed minimum
+    fn default() -> Self {
+        PlatformQuirks {
+            driver_id: None,
+            needs_portability_subset: false,
+            supports_wide_lines: false,
+            line_width_range: [1.0, 1.0],
+            supports_non_solid_fill: false,
+            supports_bc: false,
+            max_push_constant_size: 128,
+            supports_triangle_fans: true,
+            supports_separate_stencil_mask_ref: true,
+            supports_image_view_format_swizzle: true,
+            vertex_stride_alignment: 1,
+        }
+    }
+}
+
+impl PlatformQuirks {
+    // What every device reports, the portability subset is filled in by
+    // `restrict_to_subset`
+    fn from_device(
+        properties: &PhysicalDeviceProperties,
+        features: &PhysicalDeviceFeatures,
+        needs_portability_subset: bool,
+    ) -> Self {
+        PlatformQuirks {
+            supports_wide_lines: features.wide_lines != 0,
+            line_width_range: properties.limits.line_width_range,
+            supports_non_solid_fill: features.fill_mode_non_solid != 0,
+            supports_bc: features.texture_compression_bc != 0,
+            max_push_constant_size: properties.limits.max_push_constants_size,
+            needs_portability_subset,
+            ..Default::default()
+        }
+    }
+
+    fn restrict_to_subset(
+        &mut self,
+        features: &PhysicalDevicePortabilitySubsetFeaturesKHR,
+        properties: &PhysicalDevicePortabilitySubsetPropertiesKHR,
+    ) {
+        self.supports_triangle_fans = features.triangle_fans != 0;
+        self.supports_separate_stencil_mask_ref = features.separate_stencil_mask_ref != 0;
+        self.supports_image_view_format_swizzle = features.image_view_format_swizzle != 0;
+        self.vertex_stride_alignment = properties.min_vertex_input_binding_stride_alignment;
+    }
+
+    // `width` where the device draws it, 1 otherwise
+    pub fn line_width(&self, width: f32) -> f32 {
+        if self.supports_wide_lines {
+            width.clamp(self.line_width_range[0], self.line_width_range[1])
+        } else {
+            1.0
+        }
+    }
+
+    // Every push constant range has to fit the device's limit
+    pub fn check_push_constants(&self, ranges: &[PushConstantRange]) -> Result<(), Error> {
+        let size = ranges
+            .iter()
+            .map(|range| range.offset + range.size)
+            .max()
+            .unwrap_or(0);
+        if size > self.max_push_constant_size {
+            return Err(anyhow!(
+                "Shaders use {size} bytes of push constants, the device allows {}",
+                self.max_push_constant_size
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn capabilities(&self) -> Vec<CapabilityReport> {
+        let driver = self.driver_id.map_or_else(
+            || "unknown".to_string(),
+            |driver_id| format!("{driver_id:?}"),
+        );
+        [
+            ("driver", driver),
+            (
+                "needs_portability_subset",
+                self.needs_portability_subset.to_string(),
+            ),
+            ("supports_wide_lines", self.supports_wide_lines.to_string()),
+            (
+                "line_width_range",
+                format!("{}..{}", self.line_width_range[0], self.line_width_range[1]),
+            ),
+            (
+                "supports_non_solid_fill",
+                self.supports_non_solid_fill.to_string(),
+            ),
+            ("supports_bc", self.supports_bc.to_string()),
+            (
+                "max_push_constant_size",
+                self.max_push_constant_size.to_string(),
+            ),
+            (
+                "supports_triangle_fans",
+                self.supports_triangle_fans.to_string(),
+            ),
+            (
+                "supports_separate_stencil_mask_ref",
+                self.supports_separate_stencil_mask_ref.to_string(),
+            ),
+            (
+                "supports_image_view_format_swizzle",
+                self.supports_image_view_format_swizzle.to_string(),
+            ),
+            (
+                "vertex_stride_alignment",
+                self.vertex_stride_alignment.to_string(),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| CapabilityReport {
+            name: name.to_string(),
+            value,
+        })
+        .collect()
+    }
+}
+
+impl Configuration {
+    pub fn detect_platform_quirks(&mut self) -> Result<&mut Configuration, Error> {
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let (properties, features, extensions) = unsafe {
+            (
+                instance.get_physical_device_properties(physical_device),
+                instance.get_physical_device_features(physical_device),
+                instance.enumerate_device_extension_properties(physical_device)?,
+            )
+        };
+        let has_extension = |name: &std::ffi::CStr| {
+            extensions
+                .iter()
+                .any(|property| property.extension_name_as_c_str() == Ok(name))
+        };
+        let mut quirks = PlatformQuirks::from_device(
+            &properties,
+            &features,
+            has_extension(ash::khr::portability_subset::NAME),
+        );
+
+        let properties2 = ash::khr::get_physical_device_properties2::Instance::new(
+            self.vulkan_entry.as_ref().unwrap(),
+            instance,
+        );
+        if has_extension(ash::khr::driver_properties::NAME) {
+            let mut driver_properties = PhysicalDeviceDriverProperties::default();
+            let mut properties =
+                PhysicalDeviceProperties2::default().push_next(&mut driver_properties);
+            unsafe {
+                properties2.get_physical_device_properties2(physical_device, &mut properties)
+            };
+            quirks.driver_id = Some(driver_properties.driver_id);
+        }
+        if quirks.needs_portability_subset {
+            let mut subset_features = PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+            let mut features = PhysicalDeviceFeatures2::default().push_next(&mut subset_features);
+            unsafe { properties2.get_physical_device_features2(physical_device, &mut features) };
+            let mut subset_properties = PhysicalDevicePortabilitySubsetPropertiesKHR::default();
+            let mut properties =
+                PhysicalDeviceProperties2::default().push_next(&mut subset_properties);
+            unsafe {
+                properties2.get_physical_device_properties2(physical_device, &mut properties)
+            };
+            quirks.restrict_to_subset(&subset_features, &subset_properties);
+        }
+        info!("Platform quirks: {quirks:?}");
+        self.platform_quirks = quirks;
+        Ok(self)
+    }
+
+    pub fn platform_quirks(&self) -> PlatformQuirks {
+        self.platform_quirks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{PhysicalDeviceLimits, ShaderStageFlags, TRUE};
+
+    use super::*;
+
+    // A desktop GPU with wide lines, wireframes and BC textures
+    fn desktop() -> PlatformQuirks {
+        let properties = PhysicalDeviceProperties {
+            limits: PhysicalDeviceLimits {
+                line_width_range: [1.0, 8.0],
+                max_push_constants_size: 256,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let features = PhysicalDeviceFeatures {
+            wide_lines: TRUE,
+            fill_mode_non_solid: TRUE,
+            texture_compression_bc: TRUE,
+            ..Default::default()
+        };
+        PlatformQuirks::from_device(&properties, &features, false)
+    }
+
+    #[test]
+    fn missing_features_leave_the_minimum() {
+        let properties = PhysicalDeviceProperties {
+            limits: PhysicalDeviceLimits {
+                line_width_range: [1.0, 1.0],
+                max_push_constants_size: 128,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let quirks =
+            PlatformQuirks::from_device(&properties, &PhysicalDeviceFeatures::default(), false);
+        assert_eq!(quirks, PlatformQuirks::default());
+    }
+
+    #[test]
+    fn wide_lines_clamp_to_the_device_range() {
+        let quirks = desktop();
+        assert!(quirks.supports_wide_lines);
+        assert_eq!(quirks.line_width_range, [1.0, 8.0]);
+        assert_eq!(quirks.line_width(3.0), 3.0);
+        assert_eq!(quirks.line_width(12.0), 8.0);
+        assert_eq!(quirks.line_width(0.5), 1.0);
+        assert_eq!(PlatformQuirks::default().line_width(3.0), 1.0);
+    }
+
+    #[test]
+    fn non_solid_fill_and_bc_follow_their_features() {
+        let quirks = desktop();
+        assert!(quirks.supports_non_solid_fill);
+        assert!(quirks.supports_bc);
+        assert_eq!(quirks.driver_id, None);
+    }
+
+    #[test]
+    fn push_constants_past_the_limit_are_rejected() {
+        let range = |offset, size| PushConstantRange {
+            stage_flags: ShaderStageFlags::VERTEX,
+            offset,
+            size,
+        };
+        let quirks = desktop();
+        assert_eq!(quirks.max_push_constant_size, 256);
+        assert!(quirks
+            .check_push_constants(&[range(0, 128), range(128, 128)])
+            .is_ok());
+        assert!(quirks.check_push_constants(&[range(192, 128)]).is_err());
+        assert!(PlatformQuirks::default()
+            .check_push_constants(&[range(0, 256)])
+            .is_err());
+    }
+
+    #[test]
+    fn portability_subsets_report_what_they_leave_out() {
+        let properties = PhysicalDeviceProperties::default();
+        let features = PhysicalDeviceFeatures::default();
+        let mut quirks = PlatformQuirks::from_device(&properties, &features, true);
+        assert!(quirks.needs_portability_subset);
+        let subset_properties = PhysicalDevicePortabilitySubsetPropertiesKHR {
+            min_vertex_input_binding_stride_alignment: 4,
+            ..Default::default()
+        };
+        quirks.restrict_to_subset(
+            &PhysicalDevicePortabilitySubsetFeaturesKHR::default(),
+            &subset_properties,
+        );
+        assert!(!quirks.supports_triangle_fans);
+        assert!(!quirks.supports_separate_stencil_mask_ref);
+        assert!(!quirks.supports_image_view_format_swizzle);
+        assert_eq!(quirks.vertex_stride_alignment, 4);
+
+        let subset_features = PhysicalDevicePortabilitySubsetFeaturesKHR {
+            triangle_fans: TRUE,
+            separate_stencil_mask_ref: TRUE,
+            image_view_format_swizzle: TRUE,
+            ..Default::default()
+        };
+        quirks.restrict_to_subset(&subset_features, &subset_properties);
+        assert!(quirks.supports_triangle_fans);
+        assert!(quirks.supports_separate_stencil_mask_ref);
+        assert!(quirks.supports_image_view_format_swizzle);
+    }
+
+    #[test]
+    fn every_quirk_is_reported() {
+        let names = desktop()
+            .capabilities()
+            .into_iter()
+            .map(|report| report.name)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            names,
+            [
+                "driver",
+                "needs_portability_subset",
+                "supports_wide_lines",
+                "line_width_range",
+                "supports_non_solid_fill",
+                "supports_bc",
+                "max_push_constant_size",
+                "supports_triangle_fans",
+                "supports_separate_stencil_mask_ref",
+                "supports_image_view_format_swizzle",
+                "vertex_stride_alignment",
+            ]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::VecDeque,
+    ffi::CStr,
+    time::{Duration, Instant},
+};
+
+use ash::{
+    google::display_timing,
+    khr::{present_id, present_wait},
+    vk::{
+        self, PhysicalDeviceFeatures2, PhysicalDevicePresentIdFeaturesKHR,
+        PhysicalDevicePresentWaitFeaturesKHR,
+    },
+};
+use log::{debug, info};
+
+use super::Configuration;
+use crate::engine::present_stats::{PresentStats, PresentStatsAccumulator, PresentTimingSource};
+
+// Presents of one swapchain that haven't been reported back yet. Ids keep
+// increasing across rebuilds, the rest starts over with every swapchain
+#[derive(Debug, Default)]
+pub struct PresentQueue {
+    next_id: u64,
+    // Id and when it was queued
+    pending: VecDeque<(u64, Instant)>,
+    // The last image shown, nanoseconds on the display timing clock
+    last_shown_ns: Option<u64>,
+    last_shown: Option<Instant>,
+}
+
+impl PresentQueue {
+    pub(super) fn reset(&mut self) {
+        self.pending.clear();
+        self.last_shown_ns = None;
+        self.last_shown = None;
+    }
+}
+
+impl Configuration {
+    fn has_device_extensions(&self, names: &[&CStr]) -> bool {
+        let instance = self.instance.as_ref().unwrap();
+        let Ok(extensions) = (unsafe {
+            instance.enumerate_device_extension_properties(self.physical_device.unwrap())
+        }) else {
+            return false;
+        };
+        names.iter().all(|name| {
+            extensions
+                .iter()
+                .any(|property| property.extension_name_as_c_str() == Ok(*name))
+        })
+    }
+
+    pub(super) fn supports_display_timing(&self) -> bool {
+        !self.headless() && self.has_device_extensions(&[display_timing::NAME])
+    }
+
+    // Both extensions and both features
+    pub(super) fn supports_present_wait(&self) -> bool {
+        if self.headless() || !self.has_device_extensions(&[present_id::NAME, present_wait::NAME]) {
+            return false;
+        }
+        let properties2 = ash::khr::get_physical_device_properties2::Instance::new(
+            self.vulkan_entry.as_ref().unwrap(),
+            self.instance.as_ref().unwrap(),
+        );
+        let mut present_id_features = PhysicalDevicePresentIdFeaturesKHR::default();
+        let mut present_wait_features = PhysicalDevicePresentWaitFeaturesKHR::default();
+        let mut features = PhysicalDeviceFeatures2::default()
+            .push_next(&mut present_id_features)
+            .push_next(&mut present_wait_features);
+        unsafe {
+            properties2.get_physical_device_features2(self.physical_device.unwrap(), &mut features)
+        };
+        present_id_features.present_id != 0 && present_wait_features.present_wait != 0
+    }
+
+    // Display timing is preferred, it reports when images were actually shown
+    pub(super) fn enable_present_timing(&mut self, display_timing: bool, present_wait: bool) {
+        let instance = self.instance.as_ref().unwrap();
+        let device = self.device.as_ref().unwrap();
+        let source = if display_timing {
+            self.display_timing = Some(display_timing::Device::new(instance, device));
+            PresentTimingSource::DisplayTiming
+        } else if present_wait {
+            self.present_wait = Some(present_wait::Device::new(instance, device));
+            PresentTimingSource::PresentWait
+        } else {
+            debug!("Neither VK_GOOGLE_display_timing nor VK_KHR_present_wait is available");
+            PresentTimingSource::CpuFrameTime
+        };
+        info!("Present statistics come from {source:?}");
+        self.present_stats = PresentStatsAccumulator::new(source);
+    }
+
+    pub(super) fn present_ids(&self) -> bool {
+        self.display_timing.is_some() || self.present_wait.is_some()
+    }
+
+    pub fn present_timing_source(&self) -> PresentTimingSource {
+        self.present_stats.source()
+    }
+
+    // Right before the surface's next present, the id to present it with
+    pub fn queue_present(&mut self, index: usize) -> Option<u64> {
+        if !self.present_ids() {
+            return None;
+        }
+        let presents = &mut self.surfaces[index].presents;
+        presents.next_id += 1;
+        let id = presents.next_id;
+        presents.pending.push_back((id, Instant::now()));
+        if index == 0 {
+            self.present_stats.queued(presents.pending.len());
+        }
+        Some(id)
+    }
+
+    // Once per frame, takes in what the primary surface reported since the
+    // last call. `refresh` and `frame_time` stand in without feedback
+    pub fn collect_present_timing(&mut self, refresh: Option<Duration>, frame_time: Duration) {
+        let Some(ctx) = self.surfaces.first_mut() else {
+            return;
+        };
+        let swapchain = ctx.swapchain.handle;
+        let presents = &mut ctx.presents;
+        let stats = &mut self.present_stats;
+        if let Some(display_timing) = &self.display_timing {
+            let refresh = unsafe { display_timing.get_refresh_cycle_duration(swapchain) }
+                .ok()
+                .map(|refresh| Duration::from_nanos(refresh.refresh_duration))
+                .or(refresh);
+            let timings = unsafe { display_timing.get_past_presentation_timing(swapchain) }
+                .unwrap_or_default();
+            for timing in timings {
+                presents
+                    .pending
+                    .retain(|(id, _)| *id as u32 > timing.present_id);
+                let interval = presents.last_shown_ns.map(|last| {
+                    Duration::from_nanos(timing.actual_present_time.saturating_sub(last))
+                });
+                presents.last_shown_ns = Some(timing.actual_present_time);
+                stats.shown(interval, refresh, None);
+            }
+        } else if let Some(present_wait) = &self.present_wait {
+            while let Some(&(id, queued)) = presents.pending.front() {
+                match unsafe { present_wait.wait_for_present(swapchain, id, 0) } {
+                    Ok(()) => {
+                        let now = Instant::now();
+                        presents.pending.pop_front();
+                        let interval = presents.last_shown.map(|last| now - last);
+                        presents.last_shown = Some(now);
+                        stats.shown(interval, refresh, Some(now - queued));
+                    }
+                    Err(vk::Result::TIMEOUT) => break,
+                    // Out of date, the rebuild starts over
+                    Err(_) => {
+                        presents.pending.clear();
+                        break;
+                    }
+                }
+            }
+        } else {
+            stats.cpu_frame(frame_time, refresh);
+        }
+    }
+
+    pub fn present_stats(&self) -> PresentStats {
+        self.present_stats.stats()
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    cell::Cell,
+    fs,
+    path::PathBuf,
+    sync::mpsc::{sync_channel, SyncSender, TrySendError},
+};
+
+use ash::vk::{CommandBuffer, Extent2D};
+use log::{warn, Level};
+
+use crate::{
+    engine::{error::VkContext, viewport::ViewId},
+    utils::embedded::RgbaImage,
+};
+
+use super::{
+    buffer_types::gpu_buffer::GpuBuffer, jobs::Job, screenshot::read_image, Configuration,
+};
+
+// Frames read back and waiting for the PNG writer before `RecordOverflow`
+// decides what happens to the next one
+const RECORD_QUEUE: usize = 8;
+
+// What a recording does when the PNG writer falls behind the frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordOverflow {
+    // Waits for the writer, every frame is kept but the frame rate drops
+    #[default]
+    Block,
+    // Leaves the frame out with a warning, the sequence gets gaps
+    Drop,
+}
+
+// Every frame the primary surface presents, copied out and written as
+// numbered PNGs on a thread of their own. Each frame slot has its own
+// readback buffer, read when the slot comes around again and its fence has
+// been waited for anyway, so the copies never stall the frame
+pub(super) struct Recording {
+    dir: PathBuf,
+    buffers: Vec<GpuBuffer<u8>>,
+    // Number of the frame in each slot's buffer until it has been read
+    pending: Vec<Cell<Option<u32>>>,
+    extent: Extent2D,
+    bgra: bool,
+    // Frames copied so far, and how many to stop at
+    copied: Cell<u32>,
+    frame_limit: Option<u32>,
+    overflow: RecordOverflow,
+    dropped: u32,
+    sender: SyncSender<(u32, RgbaImage)>,
+    // Joined for the number of frames written
+    writer: Job<u32>,
+}
+
+impl Recording {
+    fn wants_frame(&self) -> bool {
+        self.frame_limit
+            .is_none_or(|frame_limit| self.copied.get() < frame_limit)
+    }
+}
+
+impl Configuration {
+    // Writes every frame of the primary surface into `dir`, which is created
+    // if needed, until `frame_limit` frames have been copied or
+    // `stop_recording`
+    pub fn start_recording(
+        &mut self,
+        dir: PathBuf,
+        frame_limit: Option<u32>,
+        overflow: RecordOverflow,
+        frames_in_flight: u32,
+    ) -> Result<(), String> {
+        if self.recording.is_some() {
+            return Err("Already recording".to_string());
+        }
+        let (extent, bgra) = self.primary_readback()?;
+        fs::create_dir_all(&dir).map_err(|err| format!("Can't create {}: {err}", dir.display()))?;
+        let buffers = (0..frames_in_flight)
+            .map(|_| self.readback_buffer(extent))
+            .collect::<Result<Vec<GpuBuffer<u8>>, String>>()?;
+        let (sender, receiver) = sync_channel::<(u32, RgbaImage)>(RECORD_QUEUE);
+        let frames_dir = dir.clone();
+        let writer = Job::spawn("recording writer", move || {
+            let mut written = 0;
+            for (number, image) in receiver {
+                let path = frames_dir.join(format!("frame{number:05}.png"));
+                match image.write_png(&path) {
+                    Ok(()) => written += 1,
+                    Err(err) => warn!("Failed to write {}: {err}", path.display()),
+                }
+            }
+            written
+        });
+        self.recording = Some(Recording {
+            dir,
+            pending: buffers.iter().map(|_| Cell::new(None)).collect(),
+            buffers,
+            extent,
+            bgra,
+            copied: Cell::new(0),
+            frame_limit,
+            overflow,
+            dropped: 0,
+            sender,
+            writer,
+        });
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Whether the primary surface's next frame is copied out
+    pub(super) fn recording_wants_frame(&self) -> bool {
+        self.recording.as_ref().is_some_and(Recording::wants_frame)
+    }
+
+    // Every frame is copied and nothing is left to read back
+    pub fn recording_done(&self) -> bool {
+        self.recording.as_ref().is_some_and(|recording| {
+            !recording.wants_frame() && recording.pending.iter().all(|slot| slot.get().is_none())
+        })
+    }
+
+    // Recorded after the render pass, like the screenshot copy. Frames of a
+    // resized surface are skipped, the buffers keep the starting size
+    pub(super) fn record_frame_copy(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        image_index: u32,
+        current_frame: usize,
+    ) {
+        let ctx = &self.surfaces[surface_index];
+        let Some(recording) = self.recording.as_ref() else {
+            return;
+        };
+        if ctx.id != ViewId::PRIMARY || !recording.wants_frame() || ctx.extent != recording.extent {
+            return;
+        }
+        let number = recording.copied.get();
+        recording.copied.set(number + 1);
+        recording.pending[current_frame].set(Some(number));
+        self.cmd_copy_to_host(
+            command_buffer,
+            ctx.image(image_index),
+            ctx.extent,
+            recording.buffers[current_frame].buffer(),
+        );
+    }
+
+    // Hands the frame this slot copied last time to the writer. Called once
+    // the slot's fences have been waited for
+    pub fn collect_recorded_frame(&mut self, current_frame: usize) {
+        let Some(mut recording) = self.recording.take() else {
+            return;
+        };
+        if let Some(number) = recording.pending[current_frame].take() {
+            let image = read_image(
+                &recording.buffers[current_frame],
+                recording.extent,
+                recording.bgra,
+            );
+            match recording.overflow {
+                RecordOverflow::Block => {
+                    if recording.sender.send((number, image)).is_err() {
+                        warn!("The recording writer stopped, frame {number} is lost");
+                    }
+                }
+                RecordOverflow::Drop => match recording.sender.try_send((number, image)) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        if recording.dropped == 0 {
+                            self.notify(
+                                Level::Warn,
+                                "The recording can't keep up, dropping frames".to_string(),
+                            );
+                        }
+                        recording.dropped += 1;
+                    }
+                    Err(TrySendError::Disconnected(_)) => {
+                        warn!("The recording writer stopped, frame {number} is lost");
+                    }
+                },
+            }
+        }
+        self.recording = Some(recording);
+    }
+
+    // Waits for the copies still in flight and for the writer to finish
+    // the files
+    pub fn stop_recording(&mut self) {
+        if self.recording.is_none() {
+            return;
+        }
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .device_wait_idle()
+                .vk_expect("vkDeviceWaitIdle", "finishing a recording")
+        };
+        let recording = self.recording.as_mut().unwrap();
+        // The last frames are worth waiting for
+        recording.overflow = RecordOverflow::Block;
+        let mut slots = recording
+            .pending
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, number)| number.get().map(|number| (number, slot)))
+            .collect::<Vec<(u32, usize)>>();
+        slots.sort_unstable();
+        for (_, slot) in slots {
+            self.collect_recorded_frame(slot);
+        }
+        let Recording {
+            dir,
+            dropped,
+            sender,
+            writer,
+            ..
+        } = self.recording.take().unwrap();
+        drop(sender);
+        let written = writer.join();
+        let dropped = if dropped > 0 {
+            format!(", {dropped} dropped")
+        } else {
+            String::new()
+        };
+        self.notify(
+            Level::Info,
+            format!("Recorded {written} frames to {}{dropped}", dir.display()),
+        );
+    }
+}
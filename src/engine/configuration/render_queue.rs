@@ -0,0 +1,277 @@
+use ash::vk::Pipeline;
+use log::info;
+
+use super::{pipeline_options::PipelineOptions, scene_shaders::SceneVariant, Configuration};
+
+pub(super) const RENDER_QUEUES: usize = 4;
+
+// Sort keys compare as integers. From the top: 2 bits of queue, 24 of depth
+// or priority, 22 of material id and 16 of object index, so no two draws of
+// a region share a key
+const QUEUE_SHIFT: u32 = 62;
+const ORDER_SHIFT: u32 = 38;
+const MATERIAL_SHIFT: u32 = 16;
+const ORDER_MASK: u64 = (1 << 24) - 1;
+const MATERIAL_MASK: u64 = (1 << 22) - 1;
+const OBJECT_MASK: u64 = (1 << 16) - 1;
+// Opaque depth keeps the exponent and 3 mantissa bits of the float, steps
+// of about an eighth of the distance. Draws within one step are ordered by
+// material
+const COARSE_DEPTH_SHIFT: u32 = 20;
+// Blended draws need the order right, they keep 23 bits
+const FINE_DEPTH_SHIFT: u32 = 8;
+
+// The groups a region's draws are made in, in this order. Each has its own
+// pipeline state and ordering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum RenderQueue {
+    // Front to back so hidden surfaces fail the depth test early
+    #[default]
+    Opaque,
+    // Front to back after the opaque draws. The built-in shaders have no
+    // cutoff, so it is drawn with the opaque pipelines
+    AlphaTest,
+    // Back to front over the opaque scene, blended and without depth writes
+    Transparent,
+    // On top of everything by the material's priority, blended and without
+    // the depth test
+    Overlay,
+}
+
+impl RenderQueue {
+    pub const ALL: [RenderQueue; RENDER_QUEUES] = [
+        RenderQueue::Opaque,
+        RenderQueue::AlphaTest,
+        RenderQueue::Transparent,
+        RenderQueue::Overlay,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderQueue::Opaque => "opaque",
+            RenderQueue::AlphaTest => "alpha test",
+            RenderQueue::Transparent => "transparent",
+            RenderQueue::Overlay => "overlay",
+        }
+    }
+
+    // The scene's options changed for the queue, None where the scene
+    // pipelines are drawn with
+    fn pipeline_options(self, options: PipelineOptions) -> Option<PipelineOptions> {
+        match self {
+            RenderQueue::Opaque | RenderQueue::AlphaTest => None,
+            RenderQueue::Transparent => Some(PipelineOptions {
+                depth_write: false,
+                alpha_blend: true,
+                ..options
+            }),
+            RenderQueue::Overlay => Some(PipelineOptions {
+                depth_test: false,
+                depth_write: false,
+                alpha_blend: true,
+                ..options
+            }),
+        }
+    }
+}
+
+// What one queue drew in the last frame. A state change is a draw whose
+// pipeline, mesh or material differs from the draw before it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub draws: u32,
+    pub state_changes: u32,
+}
+
+// A draw of the per object loop, sorted by `key` within its region
+#[derive(Debug, Clone, Copy)]
+pub(super) struct QueuedDraw {
+    pub(super) key: u64,
+    pub(super) entry: u32,
+    pub(super) object_index: u32,
+}
+
+// `view_depth` is the distance in front of the eye, `priority` only orders
+// the overlay queue and `material` groups the draws of the same depth
+pub(super) fn sort_key(
+    queue: RenderQueue,
+    view_depth: f32,
+    (material, priority): (u32, u16),
+    object_index: u32,
+) -> u64 {
+    // Positive floats order like their bits, NaN and anything behind the
+    // eye count as on it
+    let depth = view_depth.max(0.0).to_bits();
+    let order = match queue {
+        RenderQueue::Opaque | RenderQueue::AlphaTest => u64::from(depth >> COARSE_DEPTH_SHIFT),
+        RenderQueue::Transparent => ORDER_MASK - u64::from(depth >> FINE_DEPTH_SHIFT),
+        RenderQueue::Overlay => u64::from(priority),
+    };
+    (queue as u64) << QUEUE_SHIFT
+        | (order & ORDER_MASK) << ORDER_SHIFT
+        | (u64::from(material) & MATERIAL_MASK) << MATERIAL_SHIFT
+        | u64::from(object_index) & OBJECT_MASK
+}
+
+pub(super) fn key_queue(key: u64) -> RenderQueue {
+    RenderQueue::ALL[(key >> QUEUE_SHIFT) as usize]
+}
+
+// The draws of a sorted region that belong to `queue`
+pub(super) fn queue_draws(draws: &[QueuedDraw], queue: RenderQueue) -> &[QueuedDraw] {
+    let start = draws.partition_point(|draw| key_queue(draw.key) < queue);
+    let end = draws.partition_point(|draw| key_queue(draw.key) <= queue);
+    &draws[start..end]
+}
+
+impl Configuration {
+    // Creates the pipelines of the queues in use for the current options,
+    // the first time they are needed
+    pub(super) fn create_queue_pipelines(&mut self, queues: [bool; RENDER_QUEUES]) {
+        if self.render_pass.is_none() || self.graphics_pipelines.is_empty() {
+            return;
+        }
+        for (queue, used) in RenderQueue::ALL.into_iter().zip(queues) {
+            let Some(options) = queue
+                .pipeline_options(self.pipeline_options)
+                .filter(|_| used)
+            else {
+                continue;
+            };
+            if !self.pipeline_variants.contains_key(&options) {
+                info!("Creating the {} queue's pipelines", queue.name());
+                let variant = self.create_scene_variant(options);
+                self.pipeline_variants.insert(options, variant);
+            }
+        }
+    }
+
+    // What the queue is drawn with on the forward path, None for the scene
+    // pipelines. Queues whose pipelines aren't there fall back on them
+    pub(super) fn queue_pipelines(&self, queue: RenderQueue) -> Option<&[Pipeline]> {
+        let options = queue.pipeline_options(self.pipeline_options)?;
+        match self.pipeline_variants.get(&options)? {
+            SceneVariant::Built(pipelines) => Some(pipelines),
+            SceneVariant::Failed(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::configuration::MAX_OBJECTS;
+
+    fn key(queue: RenderQueue, depth: f32) -> u64 {
+        sort_key(queue, depth, (0, 0), 0)
+    }
+
+    #[test]
+    fn opaque_keys_ascend_with_depth() {
+        for queue in [RenderQueue::Opaque, RenderQueue::AlphaTest] {
+            let keys = [0.5, 1.0, 4.0, 100.0, 5000.0].map(|depth| key(queue, depth));
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]), "{queue:?}");
+        }
+    }
+
+    #[test]
+    fn transparent_keys_descend_with_depth() {
+        let keys =
+            [0.5, 1.0, 1.01, 4.0, 100.0, 5000.0].map(|depth| key(RenderQueue::Transparent, depth));
+        assert!(keys.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    // Behind the eye and NaN sort as if on it
+    #[test]
+    fn negative_and_nan_depths_count_as_zero() {
+        for queue in RenderQueue::ALL {
+            assert_eq!(key(queue, -3.0), key(queue, 0.0));
+            assert_eq!(key(queue, f32::NAN), key(queue, 0.0));
+        }
+    }
+
+    // However near, far or heavy the fields below, every key of a queue sorts
+    // before those of the next one
+    #[test]
+    fn queue_bits_dominate() {
+        let max = |queue| sort_key(queue, f32::MAX, (u32::MAX, u16::MAX), u32::MAX);
+        let min = |queue| sort_key(queue, 0.0, (0, 0), 0).min(sort_key(queue, f32::MAX, (0, 0), 0));
+        for pair in RenderQueue::ALL.windows(2) {
+            assert!(max(pair[0]) < min(pair[1]), "{:?} {:?}", pair[0], pair[1]);
+        }
+        for queue in RenderQueue::ALL {
+            assert_eq!(key_queue(max(queue)), queue);
+            assert_eq!(key_queue(min(queue)), queue);
+        }
+    }
+
+    // Opaque depths within an eighth of each other share a bucket, the
+    // material decides there and keeps state changes down
+    #[test]
+    fn materials_group_within_a_depth_bucket() {
+        let draw =
+            |depth, material, object| sort_key(RenderQueue::Opaque, depth, (material, 0), object);
+        let mut keys = [
+            (draw(10.0, 2, 0), 0),
+            (draw(10.5, 1, 1), 1),
+            (draw(10.2, 2, 2), 2),
+            (draw(10.7, 1, 3), 3),
+        ];
+        keys.sort();
+        assert_eq!(keys.map(|(_, object)| object), [1, 3, 0, 2]);
+        // A bucket further out still comes after, whatever its material
+        assert!(draw(10.7, 9, 0) < draw(40.0, 0, 0));
+    }
+
+    // Every object index the engine hands out has its own key, and a large
+    // one doesn't spill into the material
+    #[test]
+    fn object_indices_up_to_the_limit_stay_distinct() {
+        let draw = |material, object| sort_key(RenderQueue::Opaque, 1.0, (material, 0), object);
+        let last = MAX_OBJECTS - 1;
+        assert_ne!(draw(0, last), draw(0, last - 1));
+        assert_ne!(draw(0, last), draw(0, 0));
+        assert!(draw(0, last) < draw(1, 0));
+    }
+
+    #[test]
+    fn overlay_orders_by_priority() {
+        let draw =
+            |depth, priority, object| sort_key(RenderQueue::Overlay, depth, (0, priority), object);
+        let mut keys = [
+            (draw(1.0, 5, 0), 0),
+            (draw(50.0, 0, 1), 1),
+            (draw(0.1, 9, 2), 2),
+            (draw(2.0, 5, 3), 3),
+        ];
+        keys.sort();
+        // Depth plays no part, equal priorities keep the object order
+        assert_eq!(keys.map(|(_, object)| object), [1, 0, 3, 2]);
+    }
+
+    #[test]
+    fn draws_split_by_queue() {
+        let mut draws = [
+            (RenderQueue::Overlay, 0),
+            (RenderQueue::Opaque, 1),
+            (RenderQueue::Transparent, 2),
+            (RenderQueue::Opaque, 3),
+        ]
+        .map(|(queue, object_index)| QueuedDraw {
+            key: sort_key(queue, 1.0, (0, 0), object_index),
+            entry: object_index,
+            object_index,
+        });
+        draws.sort_by_key(|draw| draw.key);
+        let objects = |queue| {
+            queue_draws(&draws, queue)
+                .iter()
+                .map(|draw| draw.object_index)
+                .collect::<Vec<u32>>()
+        };
+        assert_eq!(objects(RenderQueue::Opaque), [1, 3]);
+        assert!(objects(RenderQueue::AlphaTest).is_empty());
+        assert_eq!(objects(RenderQueue::Transparent), [2]);
+        assert_eq!(objects(RenderQueue::Overlay), [0]);
+    }
+}
@@ -0,0 +1,475 @@
+use std::cell::Cell;
+
+use ash::{
+    vk::{
+        CommandBuffer, Extent2D, Filter, FormatFeatureFlags, Framebuffer, Image, ImageAspectFlags,
+        ImageBlit, ImageLayout, ImageSubresourceLayers, ImageUsageFlags, ImageView, Offset2D,
+        Offset3D, PipelineStageFlags, QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType,
+        Rect2D,
+    },
+    Device,
+};
+use log::{info, warn};
+
+use crate::{engine::error::EngineError, logging};
+
+use super::{
+    bloom::HDR_FORMAT,
+    frame_graph::TransientDesc,
+    surface_context::SurfaceContext,
+    synchronization::{subresource_range, ImageBarrier},
+    ui_pass::FrameResource,
+    Configuration, MAX_FLIGHT_FENCES,
+};
+
+pub const MIN_RENDER_SCALE: f32 = 0.5;
+const SCALE_STEP: f32 = 0.125;
+// Samples averaged after a change before the scale may move again
+const SETTLE_FRAMES: u32 = 30;
+// Weight of the newest sample in the moving average
+const SMOOTHING: f32 = 0.1;
+// Only scales back up with this much of the budget to spare, so a frame
+// time right at the budget doesn't flip between two steps
+const HEADROOM: f32 = 0.75;
+
+// The scene target of a surface, a pooled image with the surface's depth
+// buffer attached
+pub struct SceneTarget {
+    pub image: Image,
+    pub view: ImageView,
+    pub framebuffer: Framebuffer,
+}
+
+// Picks the render scale from a moving average of the GPU frame time
+#[derive(Debug, Clone, Copy)]
+pub struct ResolutionScaler {
+    budget: f32,
+    average: Option<f32>,
+    samples: u32,
+    scale: f32,
+}
+
+impl ResolutionScaler {
+    pub fn new(budget: f32) -> Self {
+        ResolutionScaler {
+            budget,
+            average: None,
+            samples: 0,
+            scale: 1.0,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // Takes the GPU time of one frame in milliseconds, returns the scale for
+    // the next ones
+    pub fn update(&mut self, frame_time: f32) -> f32 {
+        let average = self.average.map_or(frame_time, |average| {
+            average + (frame_time - average) * SMOOTHING
+        });
+        self.average = Some(average);
+        self.samples += 1;
+        if self.samples < SETTLE_FRAMES {
+            return self.scale;
+        }
+        let scale = if average > self.budget {
+            (self.scale - SCALE_STEP).max(MIN_RENDER_SCALE)
+        } else if average < self.budget * HEADROOM {
+            (self.scale + SCALE_STEP).min(1.0)
+        } else {
+            self.scale
+        };
+        if scale != self.scale {
+            self.scale = scale;
+            self.average = None;
+            self.samples = 0;
+        }
+        self.scale
+    }
+}
+
+// Two timestamps per frame slot around the primary window's command buffer
+pub struct FrameTimer {
+    query_pool: QueryPool,
+    // Nanoseconds per tick
+    timestamp_period: f32,
+    timestamp_mask: u64,
+    // Bit per frame slot with timestamps waiting to be read back
+    written: Cell<u32>,
+}
+
+impl FrameTimer {
+    // Milliseconds, None until the GPU has written both timestamps
+    fn read_back(&self, device: &Device, current_frame: usize) -> Option<f32> {
+        let bit = 1 << current_frame;
+        if self.written.get() & bit == 0 {
+            return None;
+        }
+        let mut timestamps = [0u64; 2];
+        unsafe {
+            device
+                .get_query_pool_results(
+                    self.query_pool,
+                    current_frame as u32 * 2,
+                    &mut timestamps,
+                    QueryResultFlags::TYPE_64,
+                )
+                .ok()?;
+        }
+        self.written.set(self.written.get() & !bit);
+        let ticks = timestamps[1].wrapping_sub(timestamps[0]) & self.timestamp_mask;
+        Some(ticks as f32 * self.timestamp_period / 1_000_000.0)
+    }
+}
+
+// Scales the edges rather than offset and size so neighbouring viewport
+// regions still meet exactly
+pub(super) fn scale_rect(rect: Rect2D, scale: f32) -> Rect2D {
+    if scale >= 1.0 {
+        return rect;
+    }
+    let edge = |value: i64| (value as f32 * scale).round() as i32;
+    let (x, y) = (rect.offset.x as i64, rect.offset.y as i64);
+    let (left, top) = (edge(x), edge(y));
+    let right = edge(x + rect.extent.width as i64);
+    let bottom = edge(y + rect.extent.height as i64);
+    Rect2D {
+        offset: Offset2D { x: left, y: top },
+        extent: Extent2D {
+            width: (right - left).max(1) as u32,
+            height: (bottom - top).max(1) as u32,
+        },
+    }
+}
+
+impl Configuration {
+    // Upscaling blits the scene target, which needs linear blits of the
+    // surface format
+    pub(super) fn supports_scaled_rendering(&self) -> bool {
+        if self.headless() {
+            return false;
+        }
+        let properties = unsafe {
+            self.instance
+                .as_ref()
+                .unwrap()
+                .get_physical_device_format_properties(
+                    self.physical_device.unwrap(),
+                    self.surface_format.unwrap().format,
+                )
+        };
+        properties.optimal_tiling_features.contains(
+            FormatFeatureFlags::BLIT_SRC
+                | FormatFeatureFlags::BLIT_DST
+                | FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+    }
+
+    pub fn create_frame_timer(&mut self) -> Result<&mut Configuration, &str> {
+        if let Some(budget) = self.config.frame_budget_ms {
+            self.resolution_scaler = Some(ResolutionScaler::new(budget));
+        }
+        if self.headless() {
+            return Ok(self);
+        }
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let graphics_family = self.queue_family_indices.unwrap().graphics_queue.unwrap();
+        let (timestamp_period, timestamp_valid_bits) = unsafe {
+            let properties = instance.get_physical_device_properties(physical_device);
+            let queue_families =
+                instance.get_physical_device_queue_family_properties(physical_device);
+            (
+                properties.limits.timestamp_period,
+                queue_families[graphics_family as usize].timestamp_valid_bits,
+            )
+        };
+        if timestamp_valid_bits == 0 {
+            if self.resolution_scaler.take().is_some() {
+                warn!(
+                    "The graphics queue has no timestamp support, adaptive resolution is disabled"
+                );
+            }
+            return Ok(self);
+        }
+        let query_pool_info = QueryPoolCreateInfo::default()
+            .query_type(QueryType::TIMESTAMP)
+            .query_count(MAX_FLIGHT_FENCES * 2);
+        let query_pool = unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_query_pool(&query_pool_info, None)
+                .map_err(|_| "Failed to create the frame timer query pool")?
+        };
+        self.frame_timer = Some(FrameTimer {
+            query_pool,
+            timestamp_period,
+            timestamp_mask: u64::MAX >> (64 - timestamp_valid_bits.min(64)),
+            written: Cell::new(0),
+        });
+        Ok(self)
+    }
+
+    pub(super) fn destroy_frame_timer(&mut self) {
+        if let Some(timer) = self.frame_timer.take() {
+            unsafe {
+                self.device
+                    .as_ref()
+                    .unwrap()
+                    .destroy_query_pool(timer.query_pool, None)
+            };
+        }
+    }
+
+    pub(super) fn cmd_begin_frame_timer(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        current_frame: usize,
+    ) {
+        let Some(timer) = self.frame_timer.as_ref().filter(|_| surface_index == 0) else {
+            return;
+        };
+        let query = current_frame as u32 * 2;
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_reset_query_pool(command_buffer, timer.query_pool, query, 2);
+            device.cmd_write_timestamp(
+                command_buffer,
+                PipelineStageFlags::TOP_OF_PIPE,
+                timer.query_pool,
+                query,
+            );
+        }
+    }
+
+    pub(super) fn cmd_end_frame_timer(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        current_frame: usize,
+    ) {
+        let Some(timer) = self.frame_timer.as_ref().filter(|_| surface_index == 0) else {
+            return;
+        };
+        unsafe {
+            self.device.as_ref().unwrap().cmd_write_timestamp(
+                command_buffer,
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                timer.query_pool,
+                current_frame as u32 * 2 + 1,
+            );
+        }
+        timer.written.set(timer.written.get() | 1 << current_frame);
+    }
+
+    // Reads back the timestamps of the frame slot that was just waited on and
+    // feeds them to the scaler
+    pub fn update_render_scale(&mut self, current_frame: usize) {
+        let Some(timer) = &self.frame_timer else {
+            return;
+        };
+        let Some(frame_time) = timer.read_back(self.device.as_ref().unwrap(), current_frame) else {
+            return;
+        };
+        self.gpu_frame_time = Some(frame_time);
+        if self.render_scale_override.is_some() {
+            return;
+        }
+        if let Some(scaler) = &mut self.resolution_scaler {
+            let previous = scaler.scale();
+            let scale = scaler.update(frame_time);
+            if scale != previous {
+                info!(
+                    target: logging::FRAME,
+                    "Render scale {previous} -> {scale} at {frame_time:.2} ms GPU time"
+                );
+            }
+        }
+    }
+
+    // 1.0 unless the scaler or an override lowered it
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale_override
+            .or(self.resolution_scaler.map(|scaler| scaler.scale()))
+            .unwrap_or(1.0)
+    }
+
+    // Pins the scale, None hands it back to the scaler
+    pub fn set_render_scale(&mut self, render_scale: Option<f32>) {
+        self.render_scale_override =
+            render_scale.map(|render_scale| render_scale.clamp(MIN_RENDER_SCALE, 1.0));
+    }
+
+    pub fn render_scale_override(&self) -> Option<f32> {
+        self.render_scale_override
+    }
+
+    pub fn gpu_frame_time(&self) -> Option<f32> {
+        self.gpu_frame_time
+    }
+
+    // Surfaces without a scene target always render at full resolution
+    pub(super) fn surface_render_scale(&self, ctx: &SurfaceContext) -> f32 {
+        if ctx.scene_target.is_some() {
+            self.render_scale()
+        } else {
+            1.0
+        }
+    }
+
+    // What the scene is drawn into at reduced resolution and the layout the
+    // scene pass leaves it in, None where the scene always goes straight
+    // into the swapchain image. Full size, only the viewport shrinks. With
+    // bloom it holds the HDR scene at every scale and the composite pass
+    // does the upscaling
+    pub(super) fn scene_target_desc(
+        &self,
+        ctx: &SurfaceContext,
+    ) -> Option<(TransientDesc, ImageLayout)> {
+        let (format, usage, layout) = if self.bloom.is_some() {
+            (
+                HDR_FORMAT,
+                ImageUsageFlags::SAMPLED,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+        } else if self.scaled_render_pass.is_some() && ctx.upscalable {
+            (
+                self.surface_format.unwrap().format,
+                ImageUsageFlags::TRANSFER_SRC,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )
+        } else {
+            return None;
+        };
+        let desc = TransientDesc {
+            extent: ctx.extent,
+            format,
+            usage,
+        };
+        Some((desc, layout))
+    }
+
+    // Takes the scene target from the surface's transient pool and attaches
+    // the surface's depth buffer to it. The pool is filled for the frame
+    // graph below full scale, so scale changes never reallocate
+    pub(super) fn create_surface_scene_target(
+        &self,
+        ctx: &mut SurfaceContext,
+    ) -> Result<(), EngineError> {
+        let schedule = self.frame_graph(ctx, false, true).compile();
+        let released = ctx
+            .transients
+            .allocate(&schedule, |desc| self.create_transient_image(desc))?;
+        for image in released {
+            self.destroy_transient_image(image);
+        }
+        let render_pass = if self.bloom.is_some() {
+            self.render_pass
+        } else {
+            self.scaled_render_pass
+        };
+        let (Some(image), Some(render_pass)) =
+            (ctx.transients.get(FrameResource::SceneColor), render_pass)
+        else {
+            return Ok(());
+        };
+        let framebuffer =
+            self.create_framebuffer(&[image.view, ctx.depth_image_view], render_pass, ctx.extent)?;
+        ctx.scene_target = Some(SceneTarget {
+            image: image.image,
+            view: image.view,
+            framebuffer,
+        });
+        Ok(())
+    }
+
+    // The image stays in the pool for the next build of the surface
+    pub(super) fn destroy_surface_scene_target(&self, ctx: &mut SurfaceContext) {
+        if let Some(target) = ctx.scene_target.take() {
+            unsafe {
+                self.device
+                    .as_ref()
+                    .unwrap()
+                    .destroy_framebuffer(target.framebuffer, None)
+            };
+        }
+    }
+
+    pub(super) fn destroy_surface_transients(&self, ctx: &mut SurfaceContext) {
+        for image in ctx.transients.drain() {
+            self.destroy_transient_image(image);
+        }
+    }
+
+    // Stretches the scaled scene over the whole swapchain image with a linear
+    // blit, leaving it ready to present
+    pub(super) fn cmd_upscale(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        image_index: u32,
+    ) {
+        let ctx = &self.surfaces[surface_index];
+        let render_scale = self.surface_render_scale(ctx);
+        let Some(target) = ctx.scene_target.as_ref().filter(|_| render_scale < 1.0) else {
+            return;
+        };
+        let swapchain_image = ctx.image(image_index);
+        let range = subresource_range(ImageAspectFlags::COLOR, 0, 1);
+        // Chained to the acquire semaphore, which is waited on at the color
+        // output stage
+        let mut to_transfer = ImageBarrier::transition(
+            swapchain_image,
+            ImageLayout::UNDEFINED,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            range,
+        )
+        .unwrap();
+        to_transfer.src_stage = PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        // Screenshot copies wait on the color output stage, chaining through
+        // it keeps them behind the blit
+        let mut to_present = ImageBarrier::transition(
+            swapchain_image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::PRESENT_SRC_KHR,
+            range,
+        )
+        .unwrap();
+        to_present.dst_stage = PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+
+        let full = Rect2D::default().extent(ctx.extent);
+        let scaled = scale_rect(full, render_scale).extent;
+        let layers = ImageSubresourceLayers::default()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+        let corner = |extent: Extent2D| Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        };
+        let blit = ImageBlit::default()
+            .src_subresource(layers)
+            .src_offsets([Offset3D::default(), corner(scaled)])
+            .dst_subresource(layers)
+            .dst_offsets([Offset3D::default(), corner(ctx.extent)]);
+        self.cmd_image_barrier(command_buffer, to_transfer);
+        unsafe {
+            self.device.as_ref().unwrap().cmd_blit_image(
+                command_buffer,
+                target.image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swapchain_image,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                Filter::LINEAR,
+            );
+        }
+        self.cmd_image_barrier(command_buffer, to_present);
+    }
+}
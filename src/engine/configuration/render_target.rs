@@ -0,0 +1,340 @@
+use std::ffi::CStr;
+
+use ash::vk::{
+    AccessFlags, AttachmentDescription, AttachmentLoadOp, AttachmentReference, AttachmentStoreOp,
+    BlendFactor, BlendOp, ColorComponentFlags, CommandBuffer, CullModeFlags, DeviceMemory,
+    DynamicState, Extent2D, Format, Framebuffer, FramebufferCreateInfo, FrontFace,
+    GraphicsPipelineCreateInfo, Image, ImageAspectFlags, ImageLayout, ImageTiling, ImageUsageFlags,
+    ImageView, MemoryPropertyFlags, Pipeline, PipelineBindPoint, PipelineCache,
+    PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo,
+    PipelineDepthStencilStateCreateInfo, PipelineDynamicStateCreateInfo,
+    PipelineInputAssemblyStateCreateInfo, PipelineLayout, PipelineMultisampleStateCreateInfo,
+    PipelineRasterizationStateCreateInfo, PipelineShaderStageCreateInfo, PipelineStageFlags,
+    PipelineVertexInputStateCreateInfo, PipelineViewportStateCreateInfo, PolygonMode,
+    PrimitiveTopology, RenderPass, RenderPassCreateInfo, SampleCountFlags, ShaderModule,
+    ShaderStageFlags, SpecializationInfo, SpecializationMapEntry, SubpassDependency,
+    SubpassDescription, SUBPASS_EXTERNAL,
+};
+
+use crate::engine::error::{EngineError, VkContext};
+
+use super::{
+    frame_graph::TransientDesc,
+    synchronization::{subresource_range, ImageBarrier},
+    textures::Texture,
+    Configuration,
+};
+
+// Vertex shader of every fullscreen pass, a single triangle covering the
+// viewport
+pub(super) const FULLSCREEN_SHADER_PATH: &str = "src/assets/fullscreen.spv";
+
+// Color image a render pass draws into before it is sampled or blitted by a
+// later pass. Shared by the scene target and the post processing chains
+pub struct RenderTarget {
+    pub image: Image,
+    memory: DeviceMemory,
+    pub view: ImageView,
+    pub framebuffer: Framebuffer,
+    pub extent: Extent2D,
+}
+
+// Color images drawn by one render pass at once, like a G-buffer. They are
+// attached in order, followed by the depth view if there is one
+pub struct AttachmentSet {
+    pub images: Vec<Image>,
+    memories: Vec<DeviceMemory>,
+    pub views: Vec<ImageView>,
+    pub framebuffer: Framebuffer,
+    pub extent: Extent2D,
+}
+
+// An image of the transient pool. Framebuffers are made by whoever draws
+// into it, the pool only hands out the memory
+pub struct TransientImage {
+    pub image: Image,
+    memory: DeviceMemory,
+    pub view: ImageView,
+}
+
+impl Configuration {
+    // `depth_view` is attached second, for render passes that depth test
+    pub(super) fn create_render_target(
+        &self,
+        extent: Extent2D,
+        format: Format,
+        usage: ImageUsageFlags,
+        render_pass: RenderPass,
+        depth_view: Option<ImageView>,
+    ) -> Result<RenderTarget, EngineError> {
+        let set = self.create_attachment_set(extent, &[format], usage, render_pass, depth_view)?;
+        Ok(RenderTarget {
+            image: set.images[0],
+            memory: set.memories[0],
+            view: set.views[0],
+            framebuffer: set.framebuffer,
+            extent,
+        })
+    }
+
+    pub(super) fn destroy_render_target(&self, target: RenderTarget) {
+        self.destroy_attachment_set(AttachmentSet {
+            images: vec![target.image],
+            memories: vec![target.memory],
+            views: vec![target.view],
+            framebuffer: target.framebuffer,
+            extent: target.extent,
+        });
+    }
+
+    // One color image per entry of `formats`, all with `usage`
+    pub(super) fn create_attachment_set(
+        &self,
+        extent: Extent2D,
+        formats: &[Format],
+        usage: ImageUsageFlags,
+        render_pass: RenderPass,
+        depth_view: Option<ImageView>,
+    ) -> Result<AttachmentSet, EngineError> {
+        let mut images = Vec::with_capacity(formats.len());
+        let mut memories = Vec::with_capacity(formats.len());
+        let mut views = Vec::with_capacity(formats.len());
+        for format in formats {
+            let (image, memory) = self.create_image(
+                Texture::new(extent.width, extent.height, 1),
+                *format,
+                ImageTiling::OPTIMAL,
+                usage | ImageUsageFlags::COLOR_ATTACHMENT,
+                MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+            images.push(image);
+            memories.push(memory);
+            views.push(self.create_image_view(&image, *format, ImageAspectFlags::COLOR)?);
+        }
+        let attachments = views.iter().copied().chain(depth_view).collect::<Vec<_>>();
+        let framebuffer = self.create_framebuffer(&attachments, render_pass, extent)?;
+        Ok(AttachmentSet {
+            images,
+            memories,
+            views,
+            framebuffer,
+            extent,
+        })
+    }
+
+    pub(super) fn create_framebuffer(
+        &self,
+        attachments: &[ImageView],
+        render_pass: RenderPass,
+        extent: Extent2D,
+    ) -> Result<Framebuffer, EngineError> {
+        let framebuffer_create_info = FramebufferCreateInfo::default()
+            .attachments(attachments)
+            .render_pass(render_pass)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        Ok(unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_framebuffer(&framebuffer_create_info, None)
+                .vk_ctx("vkCreateFramebuffer", "creating a render target")?
+        })
+    }
+
+    // Every transient is drawn into, COLOR_ATTACHMENT is added to `usage`
+    pub(super) fn create_transient_image(
+        &self,
+        desc: TransientDesc,
+    ) -> Result<TransientImage, EngineError> {
+        let (image, memory) = self.create_image(
+            Texture::new(desc.extent.width, desc.extent.height, 1),
+            desc.format,
+            ImageTiling::OPTIMAL,
+            desc.usage | ImageUsageFlags::COLOR_ATTACHMENT,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let view = self.create_image_view(&image, desc.format, ImageAspectFlags::COLOR)?;
+        Ok(TransientImage {
+            image,
+            memory,
+            view,
+        })
+    }
+
+    pub(super) fn destroy_transient_image(&self, transient: TransientImage) {
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.destroy_image_view(transient.view, None);
+            device.destroy_image(transient.image, None);
+            device.free_memory(transient.memory, None);
+        }
+    }
+
+    pub(super) fn destroy_attachment_set(&self, set: AttachmentSet) {
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.destroy_framebuffer(set.framebuffer, None);
+            for ((image, memory), view) in set.images.into_iter().zip(set.memories).zip(set.views) {
+                device.destroy_image_view(view, None);
+                device.destroy_image(image, None);
+                device.free_memory(memory, None);
+            }
+        }
+    }
+
+    // Single color attachment passes for post processing. The external
+    // dependency also waits for the previous frame's reads, every surface
+    // reuses its targets each frame
+    pub(super) fn create_color_pass(
+        &self,
+        format: Format,
+        load_op: AttachmentLoadOp,
+        initial_layout: ImageLayout,
+        final_layout: ImageLayout,
+    ) -> Result<RenderPass, EngineError> {
+        let attachments = [AttachmentDescription::default()
+            .format(format)
+            .samples(SampleCountFlags::TYPE_1)
+            .load_op(load_op)
+            .store_op(AttachmentStoreOp::STORE)
+            .stencil_load_op(AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(AttachmentStoreOp::DONT_CARE)
+            .initial_layout(initial_layout)
+            .final_layout(final_layout)];
+        let color_reference = [AttachmentReference::default()
+            .attachment(0)
+            .layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let subpasses = [SubpassDescription::default()
+            .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_reference)];
+        let dependencies = [SubpassDependency::default()
+            .src_subpass(SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::FRAGMENT_SHADER,
+            )
+            .dst_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(
+                AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )];
+        let render_pass_create_info = RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_render_pass(&render_pass_create_info, None)
+                .vk_ctx(
+                    "vkCreateRenderPass",
+                    format_args!("creating a {format:?} color pass"),
+                )
+        }
+    }
+
+    // Fullscreen triangle without vertex input or depth test. `pass` goes
+    // into the fragment shader's first specialization constant, `blend` is
+    // the source and destination color factor when the result is blended
+    pub(super) fn create_fullscreen_pipeline(
+        &self,
+        (vertex_shader_module, fragment_shader_module): (ShaderModule, ShaderModule),
+        pass: u32,
+        blend: Option<(BlendFactor, BlendFactor)>,
+        render_pass: RenderPass,
+        layout: PipelineLayout,
+    ) -> Result<Pipeline, EngineError> {
+        let name_main: &CStr = c"main";
+        let specialization_entries = [SpecializationMapEntry::default()
+            .constant_id(0)
+            .offset(0)
+            .size(size_of::<u32>())];
+        let specialization_info = SpecializationInfo::default()
+            .map_entries(&specialization_entries)
+            .data(bytemuck::bytes_of(&pass));
+        let stages = [
+            PipelineShaderStageCreateInfo::default()
+                .module(vertex_shader_module)
+                .stage(ShaderStageFlags::VERTEX)
+                .name(name_main),
+            PipelineShaderStageCreateInfo::default()
+                .module(fragment_shader_module)
+                .stage(ShaderStageFlags::FRAGMENT)
+                .name(name_main)
+                .specialization_info(&specialization_info),
+        ];
+        let vertex_input_state = PipelineVertexInputStateCreateInfo::default();
+        let input_assembly_state = PipelineInputAssemblyStateCreateInfo::default()
+            .topology(PrimitiveTopology::TRIANGLE_LIST);
+        let viewport_state = PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterization_state = PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(CullModeFlags::NONE)
+            .front_face(FrontFace::COUNTER_CLOCKWISE);
+        let multisample_state = PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(SampleCountFlags::TYPE_1);
+        // Blended passes keep the destination's alpha
+        let (src_factor, dst_factor) = blend.unwrap_or((BlendFactor::ONE, BlendFactor::ZERO));
+        let color_blend_attachments = [PipelineColorBlendAttachmentState::default()
+            .color_write_mask(ColorComponentFlags::RGBA)
+            .blend_enable(blend.is_some())
+            .src_color_blend_factor(src_factor)
+            .dst_color_blend_factor(dst_factor)
+            .color_blend_op(BlendOp::ADD)
+            .src_alpha_blend_factor(BlendFactor::ZERO)
+            .dst_alpha_blend_factor(BlendFactor::ONE)
+            .alpha_blend_op(BlendOp::ADD)];
+        let color_blend_state =
+            PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+        let depth_stencil_state = PipelineDepthStencilStateCreateInfo::default();
+        let dynamic_states = [DynamicState::VIEWPORT, DynamicState::SCISSOR];
+        let dynamic_state =
+            PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+        let pipeline_create_info = GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .dynamic_state(&dynamic_state)
+            .layout(layout)
+            .render_pass(render_pass)
+            .subpass(0);
+        let pipelines = unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_graphics_pipelines(PipelineCache::null(), &[pipeline_create_info], None)
+                .map_err(|(_, result)| result)
+                .vk_ctx(
+                    "vkCreateGraphicsPipelines",
+                    format_args!("building fullscreen pass {pass}"),
+                )?
+        };
+        Ok(pipelines[0])
+    }
+
+    // The color passes already left `image` in SHADER_READ_ONLY_OPTIMAL, the
+    // next pass samples it so its writes have to be made visible
+    pub(super) fn cmd_target_written(&self, command_buffer: CommandBuffer, image: Image) {
+        let mut barrier = ImageBarrier::transition(
+            image,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            subresource_range(ImageAspectFlags::COLOR, 0, 1),
+        )
+        .unwrap();
+        barrier.src_stage = PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        barrier.src_access = AccessFlags::COLOR_ATTACHMENT_WRITE;
+        self.cmd_image_barrier(command_buffer, barrier);
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use ash::vk::{CullModeFlags, Pipeline, ShaderStageFlags};
+use log::{warn, Level};
+
+use super::{
+    deletion_queue::PendingDeletion, pipeline_options::PipelineOptions,
+    shader_reflection::ShaderReflection, Configuration, BOUNDS_SHADER_PATH,
+};
+use crate::utils::embedded;
+
+// Never read from disk, the embedded SPIR-V is cached under these
+const ERROR_VERTEX_SHADER_PATH: &str = "src/assets/error_vertices.spv";
+const ERROR_SHADER_PATH: &str = "src/assets/error.spv";
+
+// The scene pipelines of one set of options. When the scene shaders fail to
+// build the error is kept in their place, so the options aren't tried again
+// before the shaders change
+pub(super) enum SceneVariant {
+    Built(Vec<Pipeline>),
+    Failed(String),
+}
+
+impl Configuration {
+    // The forward scene shaders, `EngineConfig`'s overrides where set
+    pub(super) fn scene_shader_paths(&self) -> (String, String) {
+        let path = |path: &Option<PathBuf>, built_in: &str| {
+            path.as_ref().map_or_else(
+                || built_in.to_string(),
+                |path| path.to_string_lossy().into_owned(),
+            )
+        };
+        (
+            path(&self.config.vertex_shader, self.vertex_shader_path()),
+            path(&self.config.fragment_shader, self.fragment_shader_path()),
+        )
+    }
+
+    // Flat magenta in the shape of the scene pipelines, built before them
+    // from embedded SPIR-V so there is always something to draw with
+    pub(super) fn create_error_pipelines(&mut self) -> Result<(), Error> {
+        self.shader_cache
+            .embed(ERROR_VERTEX_SHADER_PATH, embedded::ERROR_VERTEX_SPV)?;
+        self.shader_cache
+            .embed(ERROR_SHADER_PATH, embedded::ERROR_FRAGMENT_SPV)?;
+        self.check_scene_shaders((ERROR_VERTEX_SHADER_PATH, ERROR_SHADER_PATH))?;
+        self.error_pipelines = self.create_scene_pipelines(
+            (
+                ERROR_VERTEX_SHADER_PATH,
+                ERROR_SHADER_PATH,
+                ERROR_SHADER_PATH,
+            ),
+            (self.render_pass.unwrap(), 1),
+            // A broken shader may well have flipped the winding
+            PipelineOptions {
+                cull_mode: CullModeFlags::NONE,
+                ..PipelineOptions::default()
+            },
+        )?;
+        Ok(())
+    }
+
+    // The pipeline layout is made from the built-in shaders, anything else
+    // drawn with it has to fit. Also catches files that aren't SPIR-V at
+    // all before they reach the driver
+    fn check_scene_shaders(&mut self, (vertex, fragment): (&str, &str)) -> Result<(), Error> {
+        let mut reflection =
+            ShaderReflection::reflect(self.shader_cache.code(vertex)?, ShaderStageFlags::VERTEX)?;
+        reflection.merge(ShaderReflection::reflect(
+            self.shader_cache.code(fragment)?,
+            ShaderStageFlags::FRAGMENT,
+        )?)?;
+        reflection.check_fits(&self.shader_reflection)
+    }
+
+    // The forward scene pipelines for `options`. A failure is logged in
+    // full and shown as a toast, the error pipelines are drawn with instead
+    pub(super) fn create_scene_variant(&mut self, options: PipelineOptions) -> SceneVariant {
+        let (vertex, fragment) = self.scene_shader_paths();
+        let pipelines = self
+            .check_scene_shaders((&vertex, &fragment))
+            .and_then(|()| {
+                self.create_scene_pipelines(
+                    (&vertex, &fragment, BOUNDS_SHADER_PATH),
+                    (self.render_pass.unwrap(), 1),
+                    options,
+                )
+            });
+        match pipelines {
+            Ok(pipelines) => SceneVariant::Built(pipelines),
+            Err(err) => {
+                let err = format!("{err:#}");
+                self.notify(
+                    Level::Error,
+                    format!("The scene shaders failed to build, drawing in magenta: {err}"),
+                );
+                SceneVariant::Failed(err)
+            }
+        }
+    }
+
+    // What the scene is drawn with for `options`, which have a variant
+    pub(super) fn variant_pipelines(&self, options: PipelineOptions) -> Vec<Pipeline> {
+        match &self.pipeline_variants[&options] {
+            SceneVariant::Built(pipelines) => pipelines.clone(),
+            SceneVariant::Failed(_) => self.error_pipelines.clone(),
+        }
+    }
+
+    // Why the scene shaders didn't build for the current options, the scene
+    // is drawn with the error pipelines meanwhile
+    pub fn scene_shader_error(&self) -> Option<&str> {
+        match self.pipeline_variants.get(&self.pipeline_options) {
+            Some(SceneVariant::Failed(err)) => Some(err),
+            _ => None,
+        }
+    }
+
+    // Only the selected object is drawn with them, without outlines is
+    // better than failing
+    pub(super) fn rebuild_outline_pipelines(&mut self) {
+        if !self.outline_stencil {
+            return;
+        }
+        self.outline_pipelines = self.create_outline_pipelines().unwrap_or_else(|err| {
+            warn!("The selection isn't outlined: {err:#}");
+            Vec::new()
+        });
+    }
+
+    // Builds the scene pipelines again once an overridden scene shader
+    // changed on disk. Only the current options are, the others come back
+    // as they are switched to
+    pub fn hot_reload_shaders(&mut self) {
+        if !self.config.hot_reload {
+            return;
+        }
+        let paths = [&self.config.vertex_shader, &self.config.fragment_shader]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        let changed = self
+            .shader_watcher
+            .poll(&paths.iter().map(PathBuf::as_path).collect::<Vec<&Path>>());
+        let device = self.device.as_ref().unwrap();
+        // A file that fails to read counts as changed, the rebuild reports it
+        let mut reload = false;
+        for path in &changed {
+            reload |= self.shader_cache.reload(device, path).unwrap_or(true);
+        }
+        if !reload {
+            return;
+        }
+        // Frames in flight may still be drawing with the old ones
+        for pipeline in self
+            .take_pipeline_variants()
+            .into_iter()
+            .chain(std::mem::take(&mut self.outline_pipelines))
+        {
+            self.deletion_queue
+                .push(PendingDeletion::Pipeline(pipeline));
+        }
+        let options = self.pipeline_options;
+        let variant = self.create_scene_variant(options);
+        if matches!(variant, SceneVariant::Built(_)) {
+            self.notify(Level::Info, "Rebuilt the scene shaders".to_string());
+        }
+        self.pipeline_variants.insert(options, variant);
+        self.graphics_pipelines = self.variant_pipelines(options);
+        self.rebuild_outline_pipelines();
+    }
+}
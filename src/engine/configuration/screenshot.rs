@@ -0,0 +1,231 @@
+use std::{cell::Cell, path::PathBuf};
+
+use ash::vk::{
+    AccessFlags, Buffer, BufferImageCopy, BufferMemoryBarrier, BufferUsageFlags, CommandBuffer,
+    DependencyFlags, Extent2D, Extent3D, Format, Image, ImageAspectFlags, ImageLayout,
+    ImageMemoryBarrier, ImageSubresourceLayers, MemoryBarrier, Offset3D, PipelineStageFlags,
+};
+use log::Level;
+
+use crate::{
+    engine::{error::VkContext, viewport::ViewId},
+    utils::embedded::RgbaImage,
+};
+
+use super::{
+    buffer_types::gpu_buffer::GpuBuffer,
+    synchronization::{subresource_range, ImageBarrier},
+    Configuration,
+};
+
+// What a readback buffer holds once its copy is done, swizzled to RGBA
+pub(super) fn read_image(buffer: &GpuBuffer<u8>, extent: Extent2D, bgra: bool) -> RgbaImage {
+    let mut pixels = buffer.read();
+    if bgra {
+        pixels
+            .chunks_exact_mut(4)
+            .for_each(|pixel| pixel.swap(0, 2));
+    }
+    RgbaImage {
+        width: extent.width,
+        height: extent.height,
+        pixels,
+    }
+}
+
+// A copy of the primary surface requested for the next frame it renders
+pub(super) struct PendingScreenshot {
+    path: PathBuf,
+    buffer: GpuBuffer<u8>,
+    extent: Extent2D,
+    bgra: bool,
+    // Frame slot whose command buffer picked up the request
+    recorded_in: Cell<Option<usize>>,
+    // False when the surface was resized in between and nothing was copied
+    copied: Cell<bool>,
+}
+
+impl Configuration {
+    // Size of the primary surface's images and whether they are BGRA, when
+    // they can be copied out
+    pub(super) fn primary_readback(&self) -> Result<(Extent2D, bool), String> {
+        let Some(ctx) = self.surfaces.iter().find(|ctx| ctx.id == ViewId::PRIMARY) else {
+            return Err("There is no primary surface to capture".to_string());
+        };
+        if !ctx.readable {
+            return Err("The swapchain images of this surface can't be read back".to_string());
+        }
+        let bgra = match self.surface_format.unwrap().format {
+            Format::B8G8R8A8_SRGB | Format::B8G8R8A8_UNORM => true,
+            Format::R8G8B8A8_SRGB | Format::R8G8B8A8_UNORM => false,
+            format => return Err(format!("Can't capture surfaces in {format:?}")),
+        };
+        Ok((ctx.extent, bgra))
+    }
+
+    // Host visible, one RGBA8 image of `extent`
+    pub(super) fn readback_buffer(&self, extent: Extent2D) -> Result<GpuBuffer<u8>, String> {
+        GpuBuffer::host_visible(
+            &self.gpu_context(),
+            (extent.width * extent.height * 4) as usize,
+            BufferUsageFlags::TRANSFER_DST,
+        )
+        .map_err(|err| err.to_string())
+    }
+
+    // Saves what the primary surface shows after the next frame as a PNG
+    pub fn request_screenshot(&mut self, path: PathBuf) -> Result<(), String> {
+        let (extent, bgra) = self.primary_readback()?;
+        let buffer = self.readback_buffer(extent)?;
+        self.screenshot = Some(PendingScreenshot {
+            path,
+            buffer,
+            extent,
+            bgra,
+            recorded_in: Cell::new(None),
+            copied: Cell::new(false),
+        });
+        Ok(())
+    }
+
+    // Recorded after the render pass, which leaves the image in PRESENT_SRC_KHR
+    pub(super) fn record_screenshot_copy(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        image_index: u32,
+        current_frame: usize,
+    ) {
+        let ctx = &self.surfaces[surface_index];
+        let Some(screenshot) = &self.screenshot else {
+            return;
+        };
+        if ctx.id != ViewId::PRIMARY || screenshot.recorded_in.get().is_some() {
+            return;
+        }
+        screenshot.recorded_in.set(Some(current_frame));
+        // A resize since the request leaves the buffer the wrong size
+        if ctx.extent != screenshot.extent {
+            return;
+        }
+        screenshot.copied.set(true);
+        self.cmd_copy_to_host(
+            command_buffer,
+            ctx.image(image_index),
+            ctx.extent,
+            screenshot.buffer.buffer(),
+        );
+    }
+
+    // Copies a swapchain image the render pass left in PRESENT_SRC_KHR into
+    // `buffer` and makes it visible to the host once the frame's fence is
+    // signaled
+    pub(super) fn cmd_copy_to_host(
+        &self,
+        command_buffer: CommandBuffer,
+        image: Image,
+        extent: Extent2D,
+        buffer: Buffer,
+    ) {
+        let range = subresource_range(ImageAspectFlags::COLOR, 0, 1);
+        let mut to_transfer = ImageBarrier::transition(
+            image,
+            ImageLayout::PRESENT_SRC_KHR,
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+            range,
+        )
+        .unwrap();
+        // The render pass only makes its writes available to presentation
+        to_transfer.src_stage = PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        to_transfer.src_access = AccessFlags::COLOR_ATTACHMENT_WRITE;
+        let to_present = ImageBarrier::transition(
+            image,
+            ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ImageLayout::PRESENT_SRC_KHR,
+            range,
+        )
+        .unwrap();
+
+        let region = BufferImageCopy::default()
+            .image_subresource(
+                ImageSubresourceLayers::default()
+                    .aspect_mask(ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1),
+            )
+            .image_offset(Offset3D::default())
+            .image_extent(Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            });
+        let host_read = [MemoryBarrier::default()
+            .src_access_mask(AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(AccessFlags::HOST_READ)];
+        let device = self.device.as_ref().unwrap();
+        self.cmd_image_barrier(command_buffer, to_transfer);
+        unsafe {
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                PipelineStageFlags::TRANSFER,
+                PipelineStageFlags::HOST,
+                DependencyFlags::empty(),
+                &host_read,
+                &[] as &[BufferMemoryBarrier],
+                &[] as &[ImageMemoryBarrier],
+            );
+        }
+        self.cmd_image_barrier(command_buffer, to_present);
+    }
+
+    // Blocks on the frame that recorded the copy and writes the file
+    pub fn finish_screenshot(&mut self, current_frame: usize) {
+        let recorded = self
+            .screenshot
+            .as_ref()
+            .is_some_and(|screenshot| screenshot.recorded_in.get() == Some(current_frame));
+        if !recorded {
+            return;
+        }
+        let screenshot = self.screenshot.take().unwrap();
+        let Some(ctx) = self.surfaces.iter().find(|ctx| ctx.id == ViewId::PRIMARY) else {
+            return;
+        };
+        if !screenshot.copied.get() {
+            self.notify(
+                Level::Warn,
+                "The window was resized, dropping the screenshot".to_string(),
+            );
+            return;
+        }
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device
+                .wait_for_fences(&[ctx.in_flight_fences[current_frame]], true, u64::MAX)
+                .vk_expect("vkWaitForFences", "reading back a screenshot");
+        }
+
+        let image = read_image(&screenshot.buffer, screenshot.extent, screenshot.bgra);
+        match image.write_png(&screenshot.path) {
+            Ok(()) => self.notify(
+                Level::Info,
+                format!("Saved screenshot to {}", screenshot.path.display()),
+            ),
+            Err(err) => self.notify(
+                Level::Warn,
+                format!(
+                    "Failed to write screenshot {}: {err}",
+                    screenshot.path.display()
+                ),
+            ),
+        }
+    }
+}
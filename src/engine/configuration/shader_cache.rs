@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use ash::{
+    util::read_spv,
+    vk::{ShaderModule, ShaderModuleCreateInfo},
+    Device,
+};
+use log::debug;
+
+use crate::{engine::error::VkContext, utils};
+
+use super::jobs::Job;
+
+struct CachedShader {
+    // Tells a reloaded file apart from the one the module was built from
+    hash: u64,
+    code: Vec<u32>,
+    module: ShaderModule,
+    users: u32,
+}
+
+type PreloadedShaders = Vec<(PathBuf, Vec<u32>)>;
+
+// Owns every shader module. The SPIR-V of a path is read once and kept, the
+// modules themselves only live while a pipeline build holds them
+#[derive(Default)]
+pub struct ShaderCache {
+    shaders: HashMap<PathBuf, CachedShader>,
+    // SPIR-V read ahead of time, merged in on the first lookup
+    preloading: Option<Job<PreloadedShaders>>,
+}
+
+fn hash_code(code: &[u32]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    code.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn read_code(path: &Path) -> Result<Vec<u32>, Error> {
+    let bytes = utils::io::read_asset(path)
+        .map_err(|err| anyhow!("Failed to read shader {}: {err}", path.display()))?;
+    read_spv(&mut Cursor::new(&bytes))
+        .map_err(|err| anyhow!("{} is not valid SPIR-V: {err}", path.display()))
+}
+
+impl ShaderCache {
+    // Reads `paths` on a worker thread. Files that fail to read are left out
+    // and fail again, with their error, once they are used
+    pub fn preload(&mut self, paths: impl IntoIterator<Item = impl AsRef<Path>>) {
+        let paths = paths
+            .into_iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect::<Vec<PathBuf>>();
+        self.preloading = Some(Job::spawn("preload-shaders", move || {
+            paths
+                .into_iter()
+                .filter_map(|path| {
+                    let code = read_code(&path).ok()?;
+                    Some((path, code))
+                })
+                .collect()
+        }));
+    }
+
+    fn insert(&mut self, path: PathBuf, code: Vec<u32>) {
+        self.shaders.entry(path).or_insert_with(|| CachedShader {
+            hash: hash_code(&code),
+            code,
+            module: ShaderModule::null(),
+            users: 0,
+        });
+    }
+
+    // SPIR-V compiled into the binary, looked up under `path` as if it had
+    // been read from there
+    pub fn embed(&mut self, path: impl AsRef<Path>, bytes: &[u8]) -> Result<(), Error> {
+        let path = path.as_ref();
+        let code = read_spv(&mut Cursor::new(bytes))
+            .map_err(|err| anyhow!("Embedded {} is not valid SPIR-V: {err}", path.display()))?;
+        self.insert(path.to_path_buf(), code);
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) -> Result<&mut CachedShader, Error> {
+        if let Some(preloading) = self.preloading.take() {
+            for (path, code) in preloading.join() {
+                self.insert(path, code);
+            }
+        }
+        if !self.shaders.contains_key(path) {
+            self.insert(path.to_path_buf(), read_code(path)?);
+        }
+        Ok(self.shaders.get_mut(path).unwrap())
+    }
+
+    pub fn code(&mut self, path: impl AsRef<Path>) -> Result<&[u32], Error> {
+        Ok(&self.load(path.as_ref())?.code)
+    }
+
+    // Hands out the module of `path`, creating it if needed. Every call has to
+    // be paired with a `release`
+    pub fn acquire(
+        &mut self,
+        device: &Device,
+        path: impl AsRef<Path>,
+    ) -> Result<ShaderModule, Error> {
+        let path = path.as_ref();
+        let shader = self.load(path)?;
+        if shader.module == ShaderModule::null() {
+            let create_info = ShaderModuleCreateInfo::default().code(&shader.code);
+            shader.module = unsafe { device.create_shader_module(&create_info, None) }.vk_ctx(
+                "vkCreateShaderModule",
+                format_args!("loading {}", path.display()),
+            )?;
+            debug!(
+                "Created shader module for {} ({:016x})",
+                path.display(),
+                shader.hash
+            );
+        }
+        shader.users += 1;
+        Ok(shader.module)
+    }
+
+    // Reads `path` again, true unless it still holds the SPIR-V read before.
+    // A read that fails leaves nothing cached, so the error comes up again on
+    // the next lookup. The old module has to be unused
+    pub fn reload(&mut self, device: &Device, path: &Path) -> Result<bool, Error> {
+        let old = self.shaders.remove(path);
+        if let Some(module) = old
+            .as_ref()
+            .map(|shader| shader.module)
+            .filter(|module| *module != ShaderModule::null())
+        {
+            unsafe { device.destroy_shader_module(module, None) };
+        }
+        let hash = self.load(path)?.hash;
+        Ok(old.is_none_or(|old| old.hash != hash))
+    }
+
+    pub fn release(&mut self, module: ShaderModule) {
+        if let Some(shader) = self
+            .shaders
+            .values_mut()
+            .find(|shader| shader.module == module)
+        {
+            shader.users = shader.users.saturating_sub(1);
+        }
+    }
+
+    // Pipelines don't need their modules once created, unreferenced ones are
+    // destroyed while their SPIR-V stays cached
+    pub fn purge(&mut self, device: &Device) {
+        for shader in self.shaders.values_mut() {
+            if shader.users == 0 && shader.module != ShaderModule::null() {
+                unsafe { device.destroy_shader_module(shader.module, None) };
+                shader.module = ShaderModule::null();
+            }
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        for shader in self.shaders.values() {
+            if shader.module != ShaderModule::null() {
+                unsafe { device.destroy_shader_module(shader.module, None) };
+            }
+        }
+        self.shaders.clear();
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Error};
+use ash::vk::{DescriptorSetLayoutBinding, DescriptorType, PushConstantRange, ShaderStageFlags};
+use rspirv::{
+    dr::{load_words, Instruction, Module, Operand},
+    spirv::{Decoration, Op, StorageClass},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: DescriptorType,
+    pub count: u32,
+    pub stages: ShaderStageFlags,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ShaderReflection {
+    pub bindings: BTreeMap<(u32, u32), ReflectedBinding>,
+    pub push_constant_ranges: Vec<PushConstantRange>,
+}
+
+fn id_operand(operand: &Operand) -> Option<u32> {
+    match operand {
+        Operand::IdRef(id) => Some(*id),
+        _ => None,
+    }
+}
+
+fn literal_operand(operand: &Operand) -> Option<u32> {
+    match operand {
+        Operand::LiteralInt32(value) => Some(*value),
+        _ => None,
+    }
+}
+
+struct SpirvModule {
+    module: Module,
+    definitions: HashMap<u32, usize>,
+}
+
+impl SpirvModule {
+    fn new(module: Module) -> Self {
+        let definitions = module
+            .types_global_values
+            .iter()
+            .enumerate()
+            .filter_map(|(index, instruction)| instruction.result_id.map(|id| (id, index)))
+            .collect();
+        Self {
+            module,
+            definitions,
+        }
+    }
+
+    fn definition(&self, id: u32) -> Option<&Instruction> {
+        self.definitions
+            .get(&id)
+            .map(|index| &self.module.types_global_values[*index])
+    }
+
+    fn decoration(&self, target: u32, decoration: Decoration) -> Option<u32> {
+        self.module.annotations.iter().find_map(|annotation| {
+            match (annotation.class.opcode, annotation.operands.as_slice()) {
+                (Op::Decorate, [Operand::IdRef(id), Operand::Decoration(d), value, ..])
+                    if *id == target && *d == decoration =>
+                {
+                    literal_operand(value)
+                }
+                (Op::Decorate, [Operand::IdRef(id), Operand::Decoration(d)])
+                    if *id == target && *d == decoration =>
+                {
+                    Some(0)
+                }
+                _ => None,
+            }
+        })
+    }
+
+    fn member_decoration(&self, target: u32, member: u32, decoration: Decoration) -> Option<u32> {
+        self.module.annotations.iter().find_map(|annotation| {
+            match (annotation.class.opcode, annotation.operands.as_slice()) {
+                (
+                    Op::MemberDecorate,
+                    [Operand::IdRef(id), Operand::LiteralInt32(m), Operand::Decoration(d), value, ..],
+                ) if *id == target && *m == member && *d == decoration => literal_operand(value),
+                _ => None,
+            }
+        })
+    }
+
+    fn constant_value(&self, id: u32) -> Option<u32> {
+        let constant = self.definition(id)?;
+        match constant.class.opcode {
+            Op::Constant | Op::SpecConstant => constant.operands.first().and_then(literal_operand),
+            _ => None,
+        }
+    }
+
+    fn strip_arrays(&self, type_id: u32) -> (u32, u32) {
+        let mut count = 1;
+        let mut current = type_id;
+        while let Some(instruction) = self.definition(current) {
+            match instruction.class.opcode {
+                Op::TypeArray => {
+                    count *= instruction
+                        .operands
+                        .get(1)
+                        .and_then(id_operand)
+                        .and_then(|length| self.constant_value(length))
+                        .unwrap_or(1);
+                    current = instruction.operands[0].unwrap_id_ref();
+                }
+                Op::TypeRuntimeArray => {
+                    count = 0;
+                    current = instruction.operands[0].unwrap_id_ref();
+                }
+                _ => break,
+            }
+        }
+        (current, count)
+    }
+
+    fn descriptor_type(&self, storage_class: StorageClass, type_id: u32) -> Option<DescriptorType> {
+        let instruction = self.definition(type_id)?;
+        match (storage_class, instruction.class.opcode) {
+            (StorageClass::Uniform, Op::TypeStruct) => {
+                if self.decoration(type_id, Decoration::BufferBlock).is_some() {
+                    Some(DescriptorType::STORAGE_BUFFER)
+                } else {
+                    Some(DescriptorType::UNIFORM_BUFFER)
+                }
+            }
+            (StorageClass::StorageBuffer, Op::TypeStruct) => Some(DescriptorType::STORAGE_BUFFER),
+            (StorageClass::UniformConstant, Op::TypeSampledImage) => {
+                Some(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            }
+            (StorageClass::UniformConstant, Op::TypeSampler) => Some(DescriptorType::SAMPLER),
+            (StorageClass::UniformConstant, Op::TypeImage) => {
+                match instruction.operands.get(5).and_then(literal_operand) {
+                    Some(2) => Some(DescriptorType::STORAGE_IMAGE),
+                    _ => Some(DescriptorType::SAMPLED_IMAGE),
+                }
+            }
+            (StorageClass::UniformConstant, Op::TypeAccelerationStructureKHR) => {
+                Some(DescriptorType::ACCELERATION_STRUCTURE_KHR)
+            }
+            _ => None,
+        }
+    }
+
+    fn first_member_offset(&self, type_id: u32) -> u32 {
+        let members = self
+            .definition(type_id)
+            .map_or(0, |instruction| instruction.operands.len());
+        (0..members as u32)
+            .filter_map(|member| self.member_decoration(type_id, member, Decoration::Offset))
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn type_size(&self, type_id: u32) -> u32 {
+        let Some(instruction) = self.definition(type_id) else {
+            return 0;
+        };
+        match instruction.class.opcode {
+            Op::TypeInt | Op::TypeFloat => {
+                instruction
+                    .operands
+                    .first()
+                    .and_then(literal_operand)
+                    .unwrap_or(32)
+                    / 8
+            }
+            Op::TypeBool => 4,
+            // Only physical storage buffer pointers can be block members
+            Op::TypePointer => 8,
+            Op::TypeVector | Op::TypeMatrix => {
+                let component = instruction.operands[0].unwrap_id_ref();
+                let count = instruction
+                    .operands
+                    .get(1)
+                    .and_then(literal_operand)
+                    .unwrap_or(1);
+                self.type_size(component) * count
+            }
+            Op::TypeArray => {
+                let element = instruction.operands[0].unwrap_id_ref();
+                let length = instruction
+                    .operands
+                    .get(1)
+                    .and_then(id_operand)
+                    .and_then(|length| self.constant_value(length))
+                    .unwrap_or(1);
+                let stride = self
+                    .decoration(type_id, Decoration::ArrayStride)
+                    .unwrap_or_else(|| self.type_size(element));
+                stride * length
+            }
+            Op::TypeStruct => instruction
+                .operands
+                .iter()
+                .enumerate()
+                .filter_map(|(member, operand)| {
+                    let member_type = id_operand(operand)?;
+                    let offset = self
+                        .member_decoration(type_id, member as u32, Decoration::Offset)
+                        .unwrap_or(0);
+                    let size = match self.member_decoration(
+                        type_id,
+                        member as u32,
+                        Decoration::MatrixStride,
+                    ) {
+                        Some(stride) => {
+                            let columns = self
+                                .definition(member_type)
+                                .and_then(|matrix| matrix.operands.get(1))
+                                .and_then(literal_operand)
+                                .unwrap_or(1);
+                            stride * columns
+                        }
+                        None => self.type_size(member_type),
+                    };
+                    Some(offset + size)
+                })
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+impl ShaderReflection {
+    pub fn reflect(code: &[u32], stage: ShaderStageFlags) -> Result<ShaderReflection, Error> {
+        let module = load_words(code).map_err(|err| anyhow!("Failed to parse SPIR-V: {err}"))?;
+        let spirv = SpirvModule::new(module);
+        let mut reflection = ShaderReflection::default();
+
+        for variable in spirv
+            .module
+            .types_global_values
+            .iter()
+            .filter(|instruction| instruction.class.opcode == Op::Variable)
+        {
+            let (Some(variable_id), Some(pointer_type)) =
+                (variable.result_id, variable.result_type)
+            else {
+                continue;
+            };
+            let Some(Operand::StorageClass(storage_class)) = variable.operands.first() else {
+                continue;
+            };
+            let Some(pointee) = spirv
+                .definition(pointer_type)
+                .and_then(|pointer| pointer.operands.get(1))
+                .and_then(id_operand)
+            else {
+                continue;
+            };
+
+            if *storage_class == StorageClass::PushConstant {
+                // Blocks can leave the start to another stage's constants
+                let offset = spirv.first_member_offset(pointee);
+                let size = spirv.type_size(pointee);
+                reflection.add_push_constant_range(
+                    PushConstantRange::default()
+                        .stage_flags(stage)
+                        .offset(offset)
+                        .size(size - offset),
+                );
+                continue;
+            }
+
+            let (Some(set), Some(binding)) = (
+                spirv.decoration(variable_id, Decoration::DescriptorSet),
+                spirv.decoration(variable_id, Decoration::Binding),
+            ) else {
+                continue;
+            };
+            let (element_type, count) = spirv.strip_arrays(pointee);
+            let Some(descriptor_type) = spirv.descriptor_type(*storage_class, element_type) else {
+                continue;
+            };
+            reflection.bindings.insert(
+                (set, binding),
+                ReflectedBinding {
+                    set,
+                    binding,
+                    descriptor_type,
+                    count,
+                    stages: stage,
+                },
+            );
+        }
+
+        Ok(reflection)
+    }
+
+    fn add_push_constant_range(&mut self, range: PushConstantRange) {
+        match self
+            .push_constant_ranges
+            .iter_mut()
+            .find(|existing| existing.offset == range.offset && existing.size == range.size)
+        {
+            Some(existing) => existing.stage_flags |= range.stage_flags,
+            None => self.push_constant_ranges.push(range),
+        }
+    }
+
+    pub fn merge(&mut self, other: ShaderReflection) -> Result<(), Error> {
+        for (key, binding) in other.bindings {
+            match self.bindings.get_mut(&key) {
+                Some(existing) if existing.descriptor_type != binding.descriptor_type => {
+                    return Err(anyhow!(
+                        "Shader stages disagree on set {} binding {}: {:?} vs {:?}",
+                        key.0,
+                        key.1,
+                        existing.descriptor_type,
+                        binding.descriptor_type
+                    ));
+                }
+                Some(existing) => {
+                    existing.stages |= binding.stages;
+                    existing.count = existing.count.max(binding.count);
+                }
+                None => {
+                    self.bindings.insert(key, binding);
+                }
+            }
+        }
+        for range in other.push_constant_ranges {
+            self.add_push_constant_range(range);
+        }
+        Ok(())
+    }
+
+    pub fn set_layout_bindings(
+        &self,
+        set: u32,
+        dynamic_uniform_bindings: &[u32],
+    ) -> Vec<DescriptorSetLayoutBinding<'static>> {
+        self.bindings
+            .values()
+            .filter(|binding| binding.set == set)
+            .map(|binding| {
+                let descriptor_type = if binding.descriptor_type == DescriptorType::UNIFORM_BUFFER
+                    && dynamic_uniform_bindings.contains(&binding.binding)
+                {
+                    DescriptorType::UNIFORM_BUFFER_DYNAMIC
+                } else {
+                    binding.descriptor_type
+                };
+                DescriptorSetLayoutBinding::default()
+                    .binding(binding.binding)
+                    .descriptor_type(descriptor_type)
+                    .descriptor_count(binding.count.max(1))
+                    .stage_flags(binding.stages)
+            })
+            .collect()
+    }
+
+    pub fn validate(&self, set: u32, expected: &[(u32, DescriptorType)]) -> Result<(), Error> {
+        for (binding, descriptor_type) in expected {
+            let Some(reflected) = self.bindings.get(&(set, *binding)) else {
+                return Err(anyhow!(
+                    "Shaders do not declare set {set} binding {binding} ({descriptor_type:?}) which the engine writes"
+                ));
+            };
+            let compatible = reflected.descriptor_type == *descriptor_type
+                || (reflected.descriptor_type == DescriptorType::UNIFORM_BUFFER
+                    && *descriptor_type == DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                || (reflected.descriptor_type == DescriptorType::STORAGE_BUFFER
+                    && *descriptor_type == DescriptorType::STORAGE_BUFFER_DYNAMIC);
+            if !compatible {
+                return Err(anyhow!(
+                    "Set {set} binding {binding} is {:?} in the shaders but the engine binds {descriptor_type:?}",
+                    reflected.descriptor_type
+                ));
+            }
+        }
+        for reflected in self.bindings.values().filter(|b| b.set == set) {
+            if !expected
+                .iter()
+                .any(|(binding, _)| *binding == reflected.binding)
+            {
+                return Err(anyhow!(
+                    "Shaders expect set {set} binding {} ({:?}) which the engine never writes",
+                    reflected.binding,
+                    reflected.descriptor_type
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // For shaders drawing with a pipeline layout built from `layout`'s.
+    // Everything they declare has to be in it with the same type and visible
+    // to their stages, and their push constants inside its ranges
+    pub fn check_fits(&self, layout: &ShaderReflection) -> Result<(), Error> {
+        for ((set, binding), reflected) in &self.bindings {
+            let Some(existing) = layout.bindings.get(&(*set, *binding)) else {
+                return Err(anyhow!(
+                    "Set {set} binding {binding} ({:?}) is not in the pipeline layout",
+                    reflected.descriptor_type
+                ));
+            };
+            if existing.descriptor_type != reflected.descriptor_type {
+                return Err(anyhow!(
+                    "Set {set} binding {binding} is {:?} in the shaders but {:?} in the pipeline layout",
+                    reflected.descriptor_type,
+                    existing.descriptor_type
+                ));
+            }
+            if !existing.stages.contains(reflected.stages) {
+                return Err(anyhow!(
+                    "Set {set} binding {binding} is read by {:?} but only visible to {:?}",
+                    reflected.stages,
+                    existing.stages
+                ));
+            }
+        }
+        for range in &self.push_constant_ranges {
+            if !layout.push_constant_ranges.iter().any(|existing| {
+                existing.stage_flags.contains(range.stage_flags)
+                    && existing.offset <= range.offset
+                    && range.offset + range.size <= existing.offset + existing.size
+            }) {
+                return Err(anyhow!(
+                    "Push constants {}..{} of {:?} are outside the pipeline layout's",
+                    range.offset,
+                    range.offset + range.size,
+                    range.stage_flags
+                ));
+            }
+        }
+        Ok(())
+    }
+}
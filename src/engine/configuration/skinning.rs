@@ -0,0 +1,67 @@
+use ash::vk::{BufferUsageFlags, DescriptorBufferInfo, WHOLE_SIZE};
+use cgmath::Matrix4;
+use log::{info, warn};
+
+use super::{
+    buffer_types::gpu_buffer::{GpuBuffer, GpuBufferError},
+    Configuration, MAX_FLIGHT_FENCES,
+};
+use crate::logging;
+
+// Joint matrices of all skinned objects together, per frame
+pub const MAX_JOINTS: usize = 4096;
+
+impl Configuration {
+    // One storage buffer per frame in flight, the palettes of every skinned
+    // object one after another
+    pub fn create_joint_buffers(&mut self) -> Result<&mut Configuration, GpuBufferError> {
+        let ctx = self.gpu_context();
+        self.joint_buffers = (0..MAX_FLIGHT_FENCES)
+            .map(|_| GpuBuffer::host_visible(&ctx, MAX_JOINTS, BufferUsageFlags::STORAGE_BUFFER))
+            .collect::<Result<Vec<GpuBuffer<[[f32; 4]; 4]>>, GpuBufferError>>()?;
+        info!(
+            target: logging::UPLOAD,
+            "Joint buffers have been created ({MAX_JOINTS} joints)"
+        );
+        Ok(self)
+    }
+
+    // Packs every object's joint palette, returning where each one starts.
+    // Objects without a palette, or whose palette doesn't fit anymore, are
+    // drawn unskinned
+    pub fn update_joints(
+        &mut self,
+        current_frame: usize,
+        palettes: &[Option<Vec<Matrix4<f32>>>],
+    ) -> Vec<Option<u32>> {
+        let mut joints = Vec::<[[f32; 4]; 4]>::new();
+        let offsets = palettes
+            .iter()
+            .map(|palette| {
+                let palette = palette.as_ref().filter(|palette| !palette.is_empty())?;
+                if joints.len() + palette.len() > MAX_JOINTS {
+                    warn!("Joint limit of {MAX_JOINTS} reached, drawing a skin in its bind pose");
+                    return None;
+                }
+                let offset = joints.len() as u32;
+                joints.extend(
+                    palette
+                        .iter()
+                        .map(|matrix| -> [[f32; 4]; 4] { (*matrix).into() }),
+                );
+                Some(offset)
+            })
+            .collect::<Vec<Option<u32>>>();
+        if let Some(buffer) = self.joint_buffers.get_mut(current_frame) {
+            buffer.write(&joints);
+        }
+        offsets
+    }
+
+    pub(super) fn joint_buffer_info(&self, frame: usize) -> DescriptorBufferInfo {
+        DescriptorBufferInfo::default()
+            .buffer(self.joint_buffers[frame].buffer())
+            .offset(0)
+            .range(WHOLE_SIZE)
+    }
+}
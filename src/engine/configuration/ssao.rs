@@ -0,0 +1,603 @@
+use anyhow::Error;
+use ash::vk::{
+    AttachmentLoadOp, BlendFactor, BorderColor, BufferUsageFlags, CommandBuffer, CompareOp,
+    DescriptorBufferInfo, DescriptorImageInfo, DescriptorSet, DescriptorSetLayout,
+    DescriptorSetLayoutCreateInfo, DescriptorType, Extent2D, Filter, Format, Framebuffer,
+    ImageLayout, ImageUsageFlags, ImageView, Offset2D, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineLayoutCreateInfo, Rect2D, RenderPass, RenderPassBeginInfo, Sampler, SamplerAddressMode,
+    SamplerCreateInfo, SamplerMipmapMode, ShaderStageFlags, SubpassContents, WriteDescriptorSet,
+    WHOLE_SIZE,
+};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, SquareMatrix};
+use log::info;
+
+#[cfg(feature = "profiling")]
+use super::gpu_profiler::GpuZone;
+use super::{
+    asset_cache::TextureResource,
+    buffer_types::gpu_buffer::{GpuBuffer, GpuBufferError},
+    descriptor_allocator::DescriptorAllocator,
+    render_target::{RenderTarget, FULLSCREEN_SHADER_PATH},
+    shader_reflection::ShaderReflection,
+    surface_context::SurfaceContext,
+    textures::ColorSpaceHint,
+    Configuration, MAX_FLIGHT_FENCES,
+};
+use crate::{
+    engine::{
+        error::{EngineError, VkContext},
+        rng::Rng,
+        viewport::{fullscreen_viewport, MAX_VIEWPORTS, MAX_VIEWS},
+    },
+    utils::embedded::RgbaImage,
+};
+
+pub(super) const SSAO_SHADER_PATH: &str = "src/assets/ssao.spv";
+const SSAO_DESCRIPTOR_BINDINGS: [(u32, DescriptorType); 4] = [
+    (0, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (1, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (2, DescriptorType::COMBINED_IMAGE_SAMPLER),
+    (3, DescriptorType::UNIFORM_BUFFER),
+];
+// Samples taken around every pixel, the shader's KERNEL_SIZE
+const KERNEL_SIZE: usize = 16;
+// Side of the rotation noise tile, matched by the blur
+const NOISE_SIZE: u32 = 4;
+const OCCLUSION_FORMAT: Format = Format::R8_UNORM;
+// Every region of every surface has its own projection, the shader's
+// MAX_REGIONS
+const MAX_REGIONS: usize = (MAX_VIEWS * MAX_VIEWPORTS) as usize;
+
+// Tweakable while running, radius and bias only change push constants
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    // View space distance occluders are searched within
+    pub radius: f32,
+    // Depth difference ignored to keep flat surfaces from occluding themselves
+    pub bias: f32,
+}
+
+// Value of the shader's PASS specialization constant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SsaoStage {
+    Occlusion,
+    Blur,
+    Apply,
+}
+
+const SSAO_STAGES: [SsaoStage; 3] = [SsaoStage::Occlusion, SsaoStage::Blur, SsaoStage::Apply];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SsaoUniforms {
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    projection: [[[f32; 4]; 4]; MAX_REGIONS],
+    inverse_projection: [[[f32; 4]; 4]; MAX_REGIONS],
+}
+
+unsafe impl Zeroable for SsaoUniforms {}
+unsafe impl Pod for SsaoUniforms {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SsaoParams {
+    region: [f32; 4],
+    index: i32,
+    radius: f32,
+    bias: f32,
+}
+
+unsafe impl Zeroable for SsaoParams {}
+unsafe impl Pod for SsaoParams {}
+
+// Pipelines, render passes and per frame uniforms shared by every surface
+pub struct SsaoPass {
+    // Overwrites an occlusion target and leaves it ready to sample
+    occlusion_pass: RenderPass,
+    // Load the finished scene and multiply the occlusion onto it, one for the
+    // main scene pass and one for the scaled one
+    apply_pass: RenderPass,
+    scaled_apply_pass: Option<RenderPass>,
+    descriptor_set_layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+    // One variant of the SSAO shader per SsaoStage
+    pipelines: [Pipeline; 3],
+    sampler: Sampler,
+    noise: TextureResource,
+    uniforms: SsaoUniforms,
+    uniform_buffers: Vec<GpuBuffer<u8>>,
+    pub settings: SsaoSettings,
+}
+
+// A surface's occlusion targets, rebuilt with its swapchain
+pub struct SsaoTargets {
+    occlusion: RenderTarget,
+    blurred: RenderTarget,
+    descriptor_allocator: DescriptorAllocator,
+    // Per frame in flight, one set per SsaoStage. Each samples the depth
+    // buffer except the stage's own input
+    sets: Vec<[DescriptorSet; 3]>,
+}
+
+// Points in the +z hemisphere, scaled so more of them end up close to the
+// center where occluders matter most
+fn hemisphere_kernel(rng: &mut Rng) -> [[f32; 4]; KERNEL_SIZE] {
+    std::array::from_fn(|index| {
+        let scale = index as f32 / KERNEL_SIZE as f32;
+        let sample = rng.hemisphere_vector() * rng.next_f32() * (0.1 + 0.9 * scale * scale);
+        [sample.x, sample.y, sample.z, 0.0]
+    })
+}
+
+// Random xy directions to rotate the kernel around the normal with
+fn rotation_noise(rng: &mut Rng) -> RgbaImage {
+    let pixels = (0..NOISE_SIZE * NOISE_SIZE)
+        .flat_map(|_| {
+            let mut channel = || (rng.next_f32() * 255.0).round() as u8;
+            [channel(), channel(), 0x00, 0xff]
+        })
+        .collect();
+    RgbaImage {
+        width: NOISE_SIZE,
+        height: NOISE_SIZE,
+        pixels,
+    }
+}
+
+impl Configuration {
+    // Depth has to stay sampled after the scene pass, so this has to be known
+    // before it is created
+    pub(super) fn ssao_requested(&self) -> bool {
+        self.config.ssao && !self.headless()
+    }
+
+    // Uploads the noise texture, so it runs after the command pools exist
+    pub fn create_ssao_pass(&mut self) -> Result<&mut Configuration, Error> {
+        if !self.ssao_requested() {
+            return Ok(self);
+        }
+        let mut reflection = ShaderReflection::reflect(
+            self.shader_cache.code(FULLSCREEN_SHADER_PATH)?,
+            ShaderStageFlags::VERTEX,
+        )?;
+        reflection.merge(ShaderReflection::reflect(
+            self.shader_cache.code(SSAO_SHADER_PATH)?,
+            ShaderStageFlags::FRAGMENT,
+        )?)?;
+        reflection.validate(0, &SSAO_DESCRIPTOR_BINDINGS)?;
+
+        let occlusion_pass = self.create_color_pass(
+            OCCLUSION_FORMAT,
+            AttachmentLoadOp::DONT_CARE,
+            ImageLayout::UNDEFINED,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+        let (scene_format, scene_final_layout) = self.scene_color();
+        let apply_pass = self.scene_render_pass(
+            &[scene_format],
+            scene_final_layout,
+            AttachmentLoadOp::LOAD,
+            AttachmentLoadOp::LOAD,
+        );
+        let scaled_apply_pass = self.scaled_render_pass.map(|_| {
+            self.scene_render_pass(
+                &[scene_format],
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                AttachmentLoadOp::LOAD,
+                AttachmentLoadOp::LOAD,
+            )
+        });
+
+        let device = self.device.as_ref().unwrap();
+        let bindings = reflection.set_layout_bindings(0, &[]);
+        let descriptor_set_layout_create_info =
+            DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .vk_ctx(
+                    "vkCreateDescriptorSetLayout",
+                    "creating the ambient occlusion pass",
+                )?
+        };
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_create_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .vk_ctx(
+                    "vkCreatePipelineLayout",
+                    "creating the ambient occlusion pass",
+                )?
+        };
+        // Every texel is fetched directly, the sampler only has to exist
+        let sampler_create_info = SamplerCreateInfo::default()
+            .mag_filter(Filter::NEAREST)
+            .min_filter(Filter::NEAREST)
+            .mipmap_mode(SamplerMipmapMode::NEAREST)
+            .address_mode_u(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(BorderColor::FLOAT_OPAQUE_WHITE)
+            .compare_op(CompareOp::ALWAYS)
+            .max_lod(0.0);
+        let sampler = unsafe {
+            device
+                .create_sampler(&sampler_create_info, None)
+                .vk_ctx("vkCreateSampler", "creating the ambient occlusion pass")?
+        };
+
+        let vertex_shader_module = self.shader_cache.acquire(device, FULLSCREEN_SHADER_PATH)?;
+        let fragment_shader_module = self.shader_cache.acquire(device, SSAO_SHADER_PATH)?;
+        let pipelines = SSAO_STAGES.map(|stage| {
+            // The apply pipeline works with both apply passes, their
+            // attachments only differ in layout
+            let (render_pass, blend) = match stage {
+                SsaoStage::Apply => (
+                    apply_pass,
+                    Some((BlendFactor::DST_COLOR, BlendFactor::ZERO)),
+                ),
+                _ => (occlusion_pass, None),
+            };
+            self.create_fullscreen_pipeline(
+                (vertex_shader_module, fragment_shader_module),
+                stage as u32,
+                blend,
+                render_pass,
+                pipeline_layout,
+            )
+        });
+        self.shader_cache.release(vertex_shader_module);
+        self.shader_cache.release(fragment_shader_module);
+        self.shader_cache.purge(device);
+
+        // Forked off the engine seed so the kernel and noise are the same on
+        // every run
+        let mut rng = Rng::new(self.config.seed).fork("ssao");
+        let kernel = hemisphere_kernel(&mut rng);
+        let noise = self.upload_rgba(&rotation_noise(&mut rng), ColorSpaceHint::Linear)?;
+        let ctx = self.gpu_context();
+        let uniform_buffers = (0..MAX_FLIGHT_FENCES)
+            .map(|_| {
+                GpuBuffer::host_visible(
+                    &ctx,
+                    size_of::<SsaoUniforms>(),
+                    BufferUsageFlags::UNIFORM_BUFFER,
+                )
+            })
+            .collect::<Result<Vec<GpuBuffer<u8>>, GpuBufferError>>()?;
+        let mut uniforms = SsaoUniforms::zeroed();
+        uniforms.kernel = kernel;
+
+        self.ssao = Some(SsaoPass {
+            occlusion_pass,
+            apply_pass,
+            scaled_apply_pass,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipelines: pipelines
+                .into_iter()
+                .collect::<Result<Vec<Pipeline>, _>>()?
+                .try_into()
+                .unwrap(),
+            sampler,
+            noise,
+            uniforms,
+            uniform_buffers,
+            settings: SsaoSettings {
+                enabled: true,
+                radius: self.config.ssao_radius,
+                bias: self.config.ssao_bias,
+            },
+        });
+        info!("SSAO pass has been created!");
+        Ok(self)
+    }
+
+    pub(super) fn destroy_ssao_pass(&mut self) {
+        let Some(ssao) = self.ssao.take() else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            for pipeline in ssao.pipelines {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(ssao.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(ssao.descriptor_set_layout, None);
+            device.destroy_sampler(ssao.sampler, None);
+            for render_pass in [ssao.occlusion_pass, ssao.apply_pass]
+                .into_iter()
+                .chain(ssao.scaled_apply_pass)
+            {
+                device.destroy_render_pass(render_pass, None);
+            }
+        }
+    }
+
+    pub fn ssao_settings(&self) -> Option<SsaoSettings> {
+        self.ssao.as_ref().map(|ssao| ssao.settings)
+    }
+
+    pub fn set_ssao_settings(&mut self, settings: SsaoSettings) {
+        if let Some(ssao) = &mut self.ssao {
+            ssao.settings = SsaoSettings {
+                enabled: settings.enabled,
+                radius: settings.radius.max(0.01),
+                bias: settings.bias.max(0.0),
+            };
+        }
+    }
+
+    // `projections` holds every surface's region projections, including the
+    // surface's pre-rotation, indexed like the uniform entries
+    pub fn update_ssao_uniforms(
+        &mut self,
+        current_frame: usize,
+        projections: &[Vec<Matrix4<f32>>],
+    ) {
+        let Some(ssao) = &mut self.ssao else {
+            return;
+        };
+        for (surface_index, regions) in projections.iter().take(MAX_VIEWS as usize).enumerate() {
+            for (region_index, projection) in
+                regions.iter().take(MAX_VIEWPORTS as usize).enumerate()
+            {
+                let index = surface_index * MAX_VIEWPORTS as usize + region_index;
+                ssao.uniforms.projection[index] = (*projection).into();
+                ssao.uniforms.inverse_projection[index] =
+                    projection.invert().unwrap_or(Matrix4::identity()).into();
+            }
+        }
+        ssao.uniform_buffers[current_frame].write(bytemuck::bytes_of(&ssao.uniforms));
+    }
+
+    // Needs the depth buffer, so runs after it in create_surface_framebuffers
+    pub(super) fn create_surface_ssao_targets(
+        &self,
+        ctx: &mut SurfaceContext,
+    ) -> Result<(), EngineError> {
+        let Some(ssao) = &self.ssao else {
+            return Ok(());
+        };
+        let occlusion_target = || {
+            self.create_render_target(
+                ctx.extent,
+                OCCLUSION_FORMAT,
+                ImageUsageFlags::SAMPLED,
+                ssao.occlusion_pass,
+                None,
+            )
+        };
+        let occlusion = occlusion_target()?;
+        let blurred = occlusion_target()?;
+
+        let device = self.device.as_ref().unwrap();
+        let set_count = SSAO_STAGES.len() * MAX_FLIGHT_FENCES as usize;
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            set_count as u32,
+            vec![
+                (DescriptorType::COMBINED_IMAGE_SAMPLER, 3.0),
+                (DescriptorType::UNIFORM_BUFFER, 1.0),
+            ],
+        );
+        let layouts = vec![ssao.descriptor_set_layout; set_count];
+        let sets = descriptor_allocator.allocate(device, &layouts).vk_ctx(
+            "vkAllocateDescriptorSets",
+            "creating the ambient occlusion targets",
+        )?;
+        let sources = [
+            (
+                ctx.depth_sample_view,
+                ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ),
+            (occlusion.view, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            (blurred.view, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        ];
+        let sets = sets
+            .chunks_exact(SSAO_STAGES.len())
+            .zip(&ssao.uniform_buffers)
+            .map(|(frame_sets, uniform_buffer)| {
+                for (set, source) in frame_sets.iter().zip(sources) {
+                    self.write_ssao_descriptor_set(
+                        ssao,
+                        *set,
+                        ctx.depth_sample_view,
+                        source,
+                        uniform_buffer,
+                    );
+                }
+                [frame_sets[0], frame_sets[1], frame_sets[2]]
+            })
+            .collect();
+        ctx.ssao_targets = Some(SsaoTargets {
+            occlusion,
+            blurred,
+            descriptor_allocator,
+            sets,
+        });
+        Ok(())
+    }
+
+    fn write_ssao_descriptor_set(
+        &self,
+        ssao: &SsaoPass,
+        set: DescriptorSet,
+        depth_view: ImageView,
+        source: (ImageView, ImageLayout),
+        uniform_buffer: &GpuBuffer<u8>,
+    ) {
+        let image_infos = [
+            (depth_view, ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+            (ssao.noise.view(), ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            source,
+        ]
+        .map(|(view, layout)| {
+            [DescriptorImageInfo::default()
+                .image_layout(layout)
+                .image_view(view)
+                .sampler(ssao.sampler)]
+        });
+        let buffer_info = [DescriptorBufferInfo::default()
+            .buffer(uniform_buffer.buffer())
+            .offset(0)
+            .range(WHOLE_SIZE)];
+        let writes = SSAO_DESCRIPTOR_BINDINGS.map(|(binding, descriptor_type)| {
+            let write = WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(binding)
+                .descriptor_type(descriptor_type);
+            match descriptor_type {
+                DescriptorType::UNIFORM_BUFFER => write.buffer_info(&buffer_info),
+                _ => write.image_info(&image_infos[binding as usize]),
+            }
+        });
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .update_descriptor_sets(&writes, &[])
+        };
+    }
+
+    pub(super) fn destroy_surface_ssao_targets(&self, ctx: &mut SurfaceContext) {
+        let Some(mut targets) = ctx.ssao_targets.take() else {
+            return;
+        };
+        targets
+            .descriptor_allocator
+            .destroy(self.device.as_ref().unwrap());
+        self.destroy_render_target(targets.occlusion);
+        self.destroy_render_target(targets.blurred);
+    }
+
+    // Runs after the scene pass and before bloom or upscaling: computes the
+    // occlusion of every region, blurs it and multiplies it onto the scene
+    pub(super) fn cmd_ssao(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        image_index: u32,
+        current_frame: usize,
+        regions: &[Rect2D],
+    ) {
+        let ctx = &self.surfaces[surface_index];
+        let (Some(ssao), Some(targets)) = (&self.ssao, &ctx.ssao_targets) else {
+            return;
+        };
+        if !ssao.settings.enabled {
+            return;
+        }
+        #[cfg(feature = "profiling")]
+        self.begin_gpu_zone(command_buffer, current_frame, surface_index, GpuZone::Ssao);
+        let first_index = surface_index as i32 * MAX_VIEWPORTS as i32;
+        let regions = self
+            .framebuffer_regions(ctx, regions)
+            .zip(first_index..)
+            .collect::<Vec<(Rect2D, i32)>>();
+        let sets = targets.sets[current_frame];
+        let (framebuffer, scaled) =
+            self.scene_framebuffer(ctx, ctx.swapchain.framebuffers[image_index as usize]);
+        let apply_pass = match ssao.scaled_apply_pass {
+            Some(scaled_apply_pass) if scaled => scaled_apply_pass,
+            _ => ssao.apply_pass,
+        };
+
+        self.cmd_ssao_stage(
+            command_buffer,
+            SsaoStage::Occlusion,
+            (ssao.occlusion_pass, targets.occlusion.framebuffer),
+            sets[SsaoStage::Occlusion as usize],
+            ctx.extent,
+            &regions,
+        );
+        self.cmd_target_written(command_buffer, targets.occlusion.image);
+        self.cmd_ssao_stage(
+            command_buffer,
+            SsaoStage::Blur,
+            (ssao.occlusion_pass, targets.blurred.framebuffer),
+            sets[SsaoStage::Blur as usize],
+            ctx.extent,
+            &regions,
+        );
+        self.cmd_target_written(command_buffer, targets.blurred.image);
+        self.cmd_ssao_stage(
+            command_buffer,
+            SsaoStage::Apply,
+            (apply_pass, framebuffer),
+            sets[SsaoStage::Apply as usize],
+            ctx.extent,
+            &regions,
+        );
+        #[cfg(feature = "profiling")]
+        self.end_gpu_zone(command_buffer, current_frame, surface_index, GpuZone::Ssao);
+    }
+
+    // Draws the stage once per region, `regions` pairs each framebuffer
+    // region with its projection index
+    fn cmd_ssao_stage(
+        &self,
+        command_buffer: CommandBuffer,
+        stage: SsaoStage,
+        (render_pass, framebuffer): (RenderPass, Framebuffer),
+        descriptor_set: DescriptorSet,
+        extent: Extent2D,
+        regions: &[(Rect2D, i32)],
+    ) {
+        let ssao = self.ssao.as_ref().unwrap();
+        let render_area = Rect2D::default().offset(Offset2D::default()).extent(extent);
+        let render_pass_begin_info = RenderPassBeginInfo::default()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(render_area);
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                ssao.pipelines[stage as usize],
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                ssao.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            for (region, index) in regions {
+                let params = SsaoParams {
+                    region: [
+                        region.offset.x as f32,
+                        region.offset.y as f32,
+                        region.extent.width as f32,
+                        region.extent.height as f32,
+                    ],
+                    index: *index,
+                    radius: ssao.settings.radius,
+                    bias: ssao.settings.bias,
+                };
+                device.cmd_set_viewport(command_buffer, 0, &[fullscreen_viewport(region)]);
+                device.cmd_set_scissor(command_buffer, 0, &[*region]);
+                device.cmd_push_constants(
+                    command_buffer,
+                    ssao.pipeline_layout,
+                    ShaderStageFlags::FRAGMENT,
+                    0,
+                    bytemuck::bytes_of(&params),
+                );
+                device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            }
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
+}
@@ -0,0 +1,981 @@
+use ash::vk::{
+    CommandBuffer, CommandBufferAllocateInfo, CommandBufferLevel, CompositeAlphaFlagsKHR,
+    DeviceMemory, Extent2D, Fence, Framebuffer, FramebufferCreateInfo, FullScreenExclusiveEXT,
+    Image, ImageAspectFlags, ImageTiling, ImageUsageFlags, ImageView, MemoryPropertyFlags,
+    PresentModeKHR, Semaphore, SharingMode, SurfaceCapabilitiesKHR, SurfaceFormatKHR,
+    SurfaceFullScreenExclusiveInfoEXT, SurfaceFullScreenExclusiveWin32InfoEXT, SurfaceKHR,
+    SurfaceTransformFlagsKHR, SwapchainCreateInfoKHR, SwapchainKHR,
+};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use winit::{
+    dpi::PhysicalSize,
+    raw_window_handle::{HasDisplayHandle, HasWindowHandle},
+    window::Window,
+};
+
+use crate::{
+    engine::{
+        error::{EngineError, VkContext},
+        gpu_device::FrameSlot,
+        viewport::{SurfaceRotation, ViewId},
+    },
+    logging::{self, span},
+};
+
+use super::{
+    bloom::BloomChain, deferred::GBuffer, frame_graph::TransientPool, present_timing::PresentQueue,
+    render_scale::SceneTarget, render_target::TransientImage, ssao::SsaoTargets, textures::Texture,
+    ui_pass::FrameResource, Configuration, SwapchainSupportDetails, MAX_FLIGHT_FENCES,
+};
+
+// Everything there is one of per swapchain image. Rebuilt as a whole with
+// the swapchain, image indices only mean something for the generation they
+// were acquired from
+#[derive(Default)]
+pub struct SwapchainResources {
+    pub handle: SwapchainKHR,
+    images: Vec<Image>,
+    // Backs `images` when rendering offscreen without a swapchain
+    offscreen_memory: Vec<DeviceMemory>,
+    image_views: Vec<ImageView>,
+    pub framebuffers: Vec<Framebuffer>,
+    // Color only, for the UI pass drawn after the scene passes
+    pub ui_framebuffers: Vec<Framebuffer>,
+    // Presentation may still read the semaphore after the frame fence is
+    // signalled, so there is one per swapchain image
+    pub render_finished_semaphores: Vec<Semaphore>,
+    generation: u64,
+}
+
+impl SwapchainResources {
+    pub fn image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub(super) fn image_views(&self) -> &[ImageView] {
+        &self.image_views
+    }
+
+    // Every per image list has to match the image count
+    fn check_counts(&self) -> Result<(), String> {
+        let counts = [
+            ("image views", self.image_views.len()),
+            ("framebuffers", self.framebuffers.len()),
+            (
+                "render finished semaphores",
+                self.render_finished_semaphores.len(),
+            ),
+        ];
+        match counts.iter().find(|(_, count)| *count != self.images.len()) {
+            Some((name, count)) => Err(format!("{count} {name} for {} images", self.images.len())),
+            None => Ok(()),
+        }
+    }
+}
+
+// Everything tied to one window. The device, pipelines and scene buffers are
+// shared by all surfaces
+pub struct SurfaceContext {
+    pub id: ViewId,
+    pub surface: SurfaceKHR,
+    pub present_mode: PresentModeKHR,
+    // Swapchain extent, `window_extent` is the one the window sees
+    pub extent: Extent2D,
+    pub rotation: SurfaceRotation,
+    // Latest size the window asked for
+    width: u32,
+    height: u32,
+    pub resized: bool,
+    last_resize: Instant,
+    // Swapchain images can be copied out for screenshots
+    pub readable: bool,
+    // Swapchain images can be blitted to, needed to render below full
+    // resolution
+    pub(super) upscalable: bool,
+    // Anything but OPAQUE and INHERIT lets the desktop show through where
+    // the frame's alpha is below 1
+    pub composite_alpha: CompositeAlphaFlagsKHR,
+    // Win32 monitor the window is exclusive fullscreen on, the swapchain is
+    // created for VK_EXT_full_screen_exclusive while set
+    pub(super) exclusive_monitor: Option<isize>,
+    pub(super) exclusive_acquired: bool,
+    pub(super) presents: PresentQueue,
+    // Asked for when the swapchain was last created, the actual count is
+    // `image_count`
+    pub(super) requested_image_count: u32,
+    // As of the last swapchain creation
+    pub(super) capabilities: SurfaceCapabilitiesKHR,
+
+    pub swapchain: SwapchainResources,
+    depth_image: Image,
+    depth_image_memory: DeviceMemory,
+    pub depth_image_view: ImageView,
+    // Depth aspect only, what later passes sample. The attachment view
+    // itself when the format has no stencil
+    pub(super) depth_sample_view: ImageView,
+    pub(super) scene_target: Option<SceneTarget>,
+    pub(super) transients: TransientPool<FrameResource, TransientImage>,
+    pub(super) bloom_chain: Option<BloomChain>,
+    pub(super) ssao_targets: Option<SsaoTargets>,
+    pub(super) gbuffer: Option<GBuffer>,
+
+    pub command_buffers: Vec<CommandBuffer>,
+    pub image_available_semaphores: Vec<Semaphore>,
+    pub in_flight_fences: Vec<Fence>,
+}
+
+impl SurfaceContext {
+    pub fn image_count(&self) -> usize {
+        self.swapchain.image_count()
+    }
+
+    pub fn image(&self, index: u32) -> Image {
+        self.swapchain.images[index as usize]
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        !self
+            .composite_alpha
+            .intersects(CompositeAlphaFlagsKHR::OPAQUE | CompositeAlphaFlagsKHR::INHERIT)
+    }
+
+    // What the scene is cleared to, see through on transparent windows
+    pub fn clear_color(&self) -> [f32; 4] {
+        let alpha = if self.is_transparent() { 0.0 } else { 1.0 };
+        [0.0, 0.0, 0.0, alpha]
+    }
+
+    pub fn window_extent(&self) -> Extent2D {
+        self.rotation.rotate_extent(self.extent)
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn frame_slot(&self, frame_index: usize) -> FrameSlot {
+        FrameSlot {
+            swapchain: self.swapchain.handle,
+            image_count: self.image_count() as u32,
+            image_available: self.image_available_semaphores[frame_index],
+            in_flight: self.in_flight_fences[frame_index],
+            command_buffer: self.command_buffers[frame_index],
+        }
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.resize_at(size, Instant::now());
+    }
+
+    // Only the latest size is kept. Coming back to the size the swapchain
+    // already has, as spurious Resized events do, cancels the rebuild
+    fn resize_at(&mut self, size: PhysicalSize<u32>, now: Instant) {
+        self.width = size.width;
+        self.height = size.height;
+        self.last_resize = now;
+        let extent = self.window_extent();
+        self.resized = (extent.width, extent.height) != (size.width, size.height);
+    }
+
+    // While resizes keep coming a suboptimal swapchain is kept, it is
+    // rebuilt once the size hasn't changed for `debounce`
+    fn rebuild_due(&self, suboptimal: bool, debounce: Duration, now: Instant) -> bool {
+        if !self.resized {
+            return suboptimal;
+        }
+        now.saturating_duration_since(self.last_resize) >= debounce
+    }
+
+    fn new(id: ViewId, surface: SurfaceKHR, width: u32, height: u32) -> Self {
+        SurfaceContext {
+            id,
+            surface,
+            present_mode: PresentModeKHR::FIFO,
+            extent: Extent2D::default(),
+            rotation: SurfaceRotation::Identity,
+            width,
+            height,
+            resized: false,
+            last_resize: Instant::now(),
+            readable: false,
+            upscalable: false,
+            composite_alpha: CompositeAlphaFlagsKHR::OPAQUE,
+            exclusive_monitor: None,
+            exclusive_acquired: false,
+            presents: PresentQueue::default(),
+            requested_image_count: 0,
+            capabilities: SurfaceCapabilitiesKHR::default(),
+            swapchain: SwapchainResources::default(),
+            depth_image: Image::null(),
+            depth_image_memory: DeviceMemory::null(),
+            depth_image_view: ImageView::null(),
+            depth_sample_view: ImageView::null(),
+            scene_target: None,
+            transients: TransientPool::new(),
+            bloom_chain: None,
+            ssao_targets: None,
+            gbuffer: None,
+            command_buffers: Vec::new(),
+            image_available_semaphores: Vec::new(),
+            in_flight_fences: Vec::new(),
+        }
+    }
+
+    // Renders into a single image of its own instead of a window's swapchain
+    pub(super) fn offscreen(id: ViewId, extent: Extent2D) -> Self {
+        let mut ctx = Self::new(id, SurfaceKHR::null(), extent.width, extent.height);
+        ctx.extent = extent;
+        ctx.readable = true;
+        ctx
+    }
+
+    pub fn is_offscreen(&self) -> bool {
+        self.surface == SurfaceKHR::null()
+    }
+}
+
+impl Configuration {
+    pub(super) fn create_surface_context(&self, window: &Window, id: ViewId) -> SurfaceContext {
+        let surface = unsafe {
+            ash_window::create_surface(
+                self.vulkan_entry.as_ref().unwrap(),
+                self.instance.as_ref().unwrap(),
+                window.display_handle().unwrap().as_raw(),
+                window.window_handle().unwrap().as_raw(),
+                None,
+            )
+            .unwrap()
+        };
+        let size = window.inner_size();
+        info!(target: logging::SWAPCHAIN, "Surface has been created for {:?}", id);
+        SurfaceContext::new(id, surface, size.width, size.height)
+    }
+
+    pub(super) fn query_swapchain_support(&self, surface: SurfaceKHR) -> SwapchainSupportDetails {
+        SwapchainSupportDetails::query_swapchain_support(
+            self.surface_instance.as_ref().unwrap(),
+            &surface,
+            self.physical_device.as_ref().unwrap(),
+        )
+    }
+
+    // The render pass is shared, so every surface has to present the format
+    // it was created with
+    pub(super) fn surface_supports_format(
+        &self,
+        surface: SurfaceKHR,
+        surface_format: SurfaceFormatKHR,
+    ) -> bool {
+        let presentation_queue = self
+            .queue_family_indices
+            .unwrap()
+            .presentation_queue
+            .unwrap();
+        let presentable = unsafe {
+            self.surface_instance
+                .as_ref()
+                .unwrap()
+                .get_physical_device_surface_support(
+                    self.physical_device.unwrap(),
+                    presentation_queue,
+                    surface,
+                )
+                .unwrap_or(false)
+        };
+        presentable
+            && self
+                .query_swapchain_support(surface)
+                .formats
+                .contains(&surface_format)
+    }
+
+    pub(super) fn create_surface_swapchain(&self, ctx: &mut SurfaceContext) {
+        let swapchain_support_details = self.query_swapchain_support(ctx.surface);
+        ctx.present_mode = self
+            .present_mode
+            .filter(|present_mode| {
+                swapchain_support_details
+                    .present_modes
+                    .contains(present_mode)
+            })
+            .unwrap_or_else(|| swapchain_support_details.choose_present_mode(self.config.vsync));
+        let capabilities = &swapchain_support_details.capabilities;
+        ctx.rotation = SurfaceRotation::from_transform(capabilities.current_transform)
+            .filter(|rotation| {
+                capabilities
+                    .supported_transforms
+                    .contains(rotation.transform())
+            })
+            .unwrap_or_default();
+        // Both the reported extent and the window size are in the rotated
+        // orientation, the images are allocated unrotated
+        ctx.extent = ctx
+            .rotation
+            .rotate_extent(swapchain_support_details.choose_swap_extent(ctx.width, ctx.height));
+        let pre_transform = if ctx.rotation != SurfaceRotation::Identity
+            || capabilities
+                .supported_transforms
+                .contains(SurfaceTransformFlagsKHR::IDENTITY)
+        {
+            ctx.rotation.transform()
+        } else {
+            capabilities.current_transform
+        };
+
+        let image_count = swapchain_support_details.choose_image_count(self.config.min_image_count);
+        if let Some(requested) = self
+            .config
+            .min_image_count
+            .filter(|requested| *requested != image_count)
+        {
+            warn!(
+                target: logging::SWAPCHAIN,
+                "{:?} can't have {requested} swapchain images, asking for {image_count} ({}..{})",
+                ctx.id,
+                capabilities.min_image_count,
+                capabilities.max_image_count
+            );
+        }
+        ctx.requested_image_count = image_count;
+        ctx.capabilities = *capabilities;
+
+        let queue_families = self.queue_family_indices.unwrap().unique_families();
+        let surface_format = self.surface_format.unwrap();
+        ctx.readable = swapchain_support_details
+            .capabilities
+            .supported_usage_flags
+            .contains(ImageUsageFlags::TRANSFER_SRC);
+        ctx.upscalable = self.supports_scaled_rendering()
+            && swapchain_support_details
+                .capabilities
+                .supported_usage_flags
+                .contains(ImageUsageFlags::TRANSFER_DST);
+        let mut image_usage = ImageUsageFlags::COLOR_ATTACHMENT;
+        if ctx.readable {
+            image_usage |= ImageUsageFlags::TRANSFER_SRC;
+        }
+        if ctx.upscalable {
+            image_usage |= ImageUsageFlags::TRANSFER_DST;
+        } else if self.config.frame_budget_ms.is_some() && !self.bloom_requested() {
+            warn!(
+                target: logging::SWAPCHAIN,
+                "Swapchain for {:?} can't be blitted to, it always renders at full resolution",
+                ctx.id
+            );
+        }
+
+        // Android compositors commonly only offer INHERIT. The scene's colors
+        // aren't premultiplied, but the background they are blended over is
+        // cleared to 0 so either blending mode looks the same around it
+        let transparent = [
+            CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        ]
+        .into_iter()
+        .find(|alpha| capabilities.supported_composite_alpha.contains(*alpha))
+        .filter(|_| self.config.transparent_window);
+        if self.config.transparent_window
+            && transparent.is_none()
+            && ctx.swapchain.handle == SwapchainKHR::null()
+        {
+            warn!(
+                target: logging::SWAPCHAIN,
+                "{:?} can't be transparent, the compositor only blends {:?}",
+                ctx.id,
+                capabilities.supported_composite_alpha
+            );
+        }
+        let composite_alpha = transparent.unwrap_or_else(|| {
+            [
+                CompositeAlphaFlagsKHR::OPAQUE,
+                CompositeAlphaFlagsKHR::INHERIT,
+            ]
+            .into_iter()
+            .find(|alpha| capabilities.supported_composite_alpha.contains(*alpha))
+            .unwrap_or(CompositeAlphaFlagsKHR::OPAQUE)
+        });
+        ctx.composite_alpha = composite_alpha;
+
+        let mut swapchain_create_info = SwapchainCreateInfoKHR::default()
+            .surface(ctx.surface)
+            .min_image_count(image_count)
+            .image_format(surface_format.format)
+            .image_color_space(surface_format.color_space)
+            .image_extent(ctx.extent)
+            .image_array_layers(1)
+            .image_usage(image_usage)
+            .pre_transform(pre_transform)
+            .composite_alpha(composite_alpha)
+            .present_mode(ctx.present_mode)
+            .clipped(true)
+            .old_swapchain(ctx.swapchain.handle);
+
+        if queue_families.len() > 1 {
+            swapchain_create_info = swapchain_create_info
+                .image_sharing_mode(SharingMode::CONCURRENT)
+                .queue_family_indices(&queue_families);
+        } else {
+            swapchain_create_info =
+                swapchain_create_info.image_sharing_mode(SharingMode::EXCLUSIVE);
+        }
+        let exclusive = self.wants_full_screen_exclusive(ctx);
+        let mut exclusive_info = SurfaceFullScreenExclusiveInfoEXT::default()
+            .full_screen_exclusive(FullScreenExclusiveEXT::APPLICATION_CONTROLLED);
+        let mut exclusive_win32_info = SurfaceFullScreenExclusiveWin32InfoEXT::default()
+            .hmonitor(ctx.exclusive_monitor.unwrap_or_default() as _);
+        if exclusive {
+            swapchain_create_info = swapchain_create_info
+                .push_next(&mut exclusive_info)
+                .push_next(&mut exclusive_win32_info);
+        }
+        let swapchain_device = self.swapchain_device.as_ref().unwrap();
+        unsafe {
+            let swapchain = swapchain_device
+                .create_swapchain(&swapchain_create_info, None)
+                .vk_expect(
+                    "vkCreateSwapchainKHR",
+                    format_args!(
+                        "creating a {}x{} swapchain for {:?}",
+                        ctx.extent.width, ctx.extent.height, ctx.id
+                    ),
+                );
+            // Retired by the create call, its images can't be acquired anymore
+            if ctx.swapchain.handle != SwapchainKHR::null() {
+                self.release_full_screen_exclusive(ctx);
+                swapchain_device.destroy_swapchain(ctx.swapchain.handle, None);
+            }
+            ctx.swapchain.handle = swapchain;
+            if exclusive {
+                self.acquire_full_screen_exclusive(ctx);
+            }
+            info!(target: logging::SWAPCHAIN, "Swapchain created!");
+            ctx.swapchain.images = swapchain_device
+                .get_swapchain_images(ctx.swapchain.handle)
+                .vk_expect(
+                    "vkGetSwapchainImagesKHR",
+                    format_args!("creating the swapchain for {:?}", ctx.id),
+                );
+        }
+        // Everything per image is sized from the images returned, drivers
+        // are free to create more than were asked for
+        info!(
+            target: logging::SWAPCHAIN,
+            "Swapchain images retrieved, {} for {} requested",
+            ctx.swapchain.image_count(),
+            image_count
+        );
+    }
+
+    pub(super) fn create_offscreen_images(&self, ctx: &mut SurfaceContext) {
+        let (image, memory) = self
+            .create_image(
+                Texture::new(ctx.extent.width, ctx.extent.height, 1),
+                self.surface_format.unwrap().format,
+                ImageTiling::OPTIMAL,
+                ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC,
+                MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+        ctx.swapchain.images = vec![image];
+        ctx.swapchain.offscreen_memory = vec![memory];
+        info!(target: logging::SWAPCHAIN, "Offscreen target created");
+    }
+
+    pub(super) fn create_surface_image_views(&self, ctx: &mut SurfaceContext) {
+        ctx.swapchain.image_views = ctx
+            .swapchain
+            .images
+            .iter()
+            .map(|image| {
+                self.create_image_view(
+                    image,
+                    self.surface_format.unwrap().format,
+                    ImageAspectFlags::COLOR,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<ImageView>>();
+    }
+
+    pub(super) fn create_surface_depth_resources(&self, ctx: &mut SurfaceContext) {
+        let texture = Texture::new(ctx.extent.width, ctx.extent.height, 1);
+        let depth_format = self.find_depth_format();
+        let mut usage = ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+        if self.sampled_depth {
+            usage |= ImageUsageFlags::SAMPLED;
+        }
+        (ctx.depth_image, ctx.depth_image_memory) = self
+            .create_image(
+                texture,
+                depth_format,
+                ImageTiling::OPTIMAL,
+                usage,
+                MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+        ctx.depth_image_view = self
+            .create_image_view(
+                &ctx.depth_image,
+                depth_format,
+                Self::image_aspect(depth_format),
+            )
+            .unwrap();
+        ctx.depth_sample_view = if self.sampled_depth && Self::has_stencil_component(depth_format) {
+            self.create_image_view(&ctx.depth_image, depth_format, ImageAspectFlags::DEPTH)
+                .unwrap()
+        } else {
+            ctx.depth_image_view
+        };
+    }
+
+    pub(super) fn create_surface_framebuffers(
+        &self,
+        ctx: &mut SurfaceContext,
+    ) -> Result<(), EngineError> {
+        let device = self.device.as_ref().unwrap();
+        // With bloom the scene is drawn into the scene target, the swapchain
+        // images only receive the composite
+        let (render_pass, depth_image_view) = match &self.bloom {
+            Some(bloom) => (bloom.composite_pass, None),
+            None => (self.render_pass.unwrap(), Some(ctx.depth_image_view)),
+        };
+        ctx.swapchain.framebuffers = ctx
+            .swapchain
+            .image_views
+            .iter()
+            .map(|image_view| {
+                let attachments = [*image_view]
+                    .into_iter()
+                    .chain(depth_image_view)
+                    .collect::<Vec<ImageView>>();
+                let framebuffer_create_info = FramebufferCreateInfo::default()
+                    .attachments(&attachments)
+                    .render_pass(render_pass)
+                    .width(ctx.extent.width)
+                    .height(ctx.extent.height)
+                    .layers(1);
+                unsafe {
+                    device
+                        .create_framebuffer(&framebuffer_create_info, None)
+                        .vk_expect(
+                            "vkCreateFramebuffer",
+                            format_args!("creating the framebuffers for {:?}", ctx.id),
+                        )
+                }
+            })
+            .collect();
+        self.create_surface_ui_framebuffers(ctx);
+        self.create_surface_scene_target(ctx)?;
+        self.create_surface_bloom_chain(ctx)?;
+        self.create_surface_ssao_targets(ctx)?;
+        self.create_surface_gbuffer(ctx)?;
+        info!(target: logging::SWAPCHAIN, "Framebuffers created");
+        self.log_frame_graph(ctx);
+        Ok(())
+    }
+
+    pub(super) fn create_surface_command_buffers(&self, ctx: &mut SurfaceContext) {
+        let command_buffer_allocate_info = CommandBufferAllocateInfo::default()
+            .command_pool(self.command_pool.unwrap())
+            .level(CommandBufferLevel::PRIMARY)
+            .command_buffer_count(MAX_FLIGHT_FENCES);
+
+        ctx.command_buffers = unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .allocate_command_buffers(&command_buffer_allocate_info)
+                .vk_expect(
+                    "vkAllocateCommandBuffers",
+                    format_args!("creating the command buffers for {:?}", ctx.id),
+                )
+        };
+    }
+
+    pub(super) fn create_surface_sync_objects(&self, ctx: &mut SurfaceContext) {
+        for _ in 0..MAX_FLIGHT_FENCES {
+            ctx.image_available_semaphores
+                .push(self.create_semaphore().unwrap());
+            ctx.in_flight_fences.push(self.create_fence().unwrap());
+        }
+        self.create_render_finished_semaphores(ctx);
+    }
+
+    fn create_render_finished_semaphores(&self, ctx: &mut SurfaceContext) {
+        ctx.swapchain.render_finished_semaphores = (0..ctx.swapchain.images.len())
+            .map(|_| self.create_semaphore().unwrap())
+            .collect();
+    }
+
+    // Runs `f` on every surface while still allowing `self` to be borrowed
+    pub(super) fn for_each_surface(
+        &mut self,
+        mut f: impl FnMut(&Configuration, &mut SurfaceContext),
+    ) {
+        let mut surfaces = std::mem::take(&mut self.surfaces);
+        surfaces.iter_mut().for_each(|ctx| f(self, ctx));
+        self.surfaces = surfaces;
+    }
+
+    // Adds a window after initialization, sharing the device, render pass and
+    // pipelines with the existing surfaces
+    pub fn add_surface(&mut self, window: &Window, id: ViewId) -> Result<(), String> {
+        let mut ctx = self.create_surface_context(window, id);
+        if !self.surface_supports_format(ctx.surface, self.surface_format.unwrap()) {
+            self.destroy_surface_context(&mut ctx);
+            return Err(format!(
+                "Surface for {:?} can't present {:?}",
+                id,
+                self.surface_format.unwrap()
+            ));
+        }
+        self.create_surface_swapchain(&mut ctx);
+        self.create_surface_image_views(&mut ctx);
+        self.create_surface_depth_resources(&mut ctx);
+        if let Err(err) = self.create_surface_framebuffers(&mut ctx) {
+            self.destroy_surface_context(&mut ctx);
+            return Err(format!("Surface for {id:?} has no framebuffers: {err}"));
+        }
+        self.create_surface_command_buffers(&mut ctx);
+        self.create_surface_sync_objects(&mut ctx);
+        self.check_swapchain(&ctx);
+        self.surfaces.push(ctx);
+        Ok(())
+    }
+
+    pub fn remove_surface(&mut self, id: ViewId) {
+        let Some(index) = self.surfaces.iter().position(|ctx| ctx.id == id) else {
+            return;
+        };
+        unsafe {
+            self.device.as_ref().unwrap().device_wait_idle().vk_expect(
+                "vkDeviceWaitIdle",
+                format_args!("removing the surface for {id:?}"),
+            )
+        };
+        let mut ctx = self.surfaces.remove(index);
+        self.destroy_surface_context(&mut ctx);
+        info!(target: logging::SWAPCHAIN, "Surface for {:?} has been destroyed", id);
+    }
+
+    pub fn surface_mut(&mut self, id: ViewId) -> Option<&mut SurfaceContext> {
+        self.surfaces.iter_mut().find(|ctx| ctx.id == id)
+    }
+
+    // Whether the swapchain should be rebuilt after presenting. While the
+    // window is still being resized a suboptimal swapchain is kept until the
+    // size settles for `resize_debounce_ms`
+    pub fn swapchain_stale(&self, index: usize, suboptimal: bool) -> bool {
+        let debounce = Duration::from_millis(self.config.resize_debounce_ms.into());
+        self.surfaces[index].rebuild_due(suboptimal, debounce, Instant::now())
+    }
+
+    pub fn swapchain_recreations(&self) -> u64 {
+        self.swapchain_recreations
+    }
+
+    pub fn recreate_swapchain(&mut self, index: usize) -> Result<(), EngineError> {
+        span!("recreate_swapchain");
+        self.swapchain_recreations += 1;
+        info!(target: logging::SWAPCHAIN, "Recreating swapchain for {:?}", self.surfaces[index].id);
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .device_wait_idle()
+                .vk_ctx("vkDeviceWaitIdle", "recreating the swapchain")?
+        };
+        let mut surfaces = std::mem::take(&mut self.surfaces);
+        let ctx = &mut surfaces[index];
+        ctx.resized = false;
+        self.destroy_surface_depth_resources(ctx);
+        self.rebuild_swapchain(ctx);
+        self.create_surface_depth_resources(ctx);
+        let created = self.create_surface_framebuffers(ctx);
+        self.create_render_finished_semaphores(ctx);
+        self.check_swapchain(ctx);
+        self.surfaces = surfaces;
+        created
+    }
+
+    // Switches every window to `present_mode`. Only the swapchains and what
+    // points at their images are rebuilt, pipelines and descriptors stay and
+    // so does the depth buffer unless the extent changed along the way
+    pub fn set_present_mode(&mut self, present_mode: PresentModeKHR) -> Result<(), String> {
+        span!("set_present_mode");
+        let start = Instant::now();
+        if let Some(ctx) = self.surfaces.iter().find(|ctx| {
+            !ctx.is_offscreen()
+                && !self
+                    .query_swapchain_support(ctx.surface)
+                    .present_modes
+                    .contains(&present_mode)
+        }) {
+            return Err(format!(
+                "{present_mode:?} isn't supported by the surface for {:?}",
+                ctx.id
+            ));
+        }
+        self.present_mode = Some(present_mode);
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .device_wait_idle()
+                .vk_ctx(
+                    "vkDeviceWaitIdle",
+                    format_args!("switching to {present_mode:?}"),
+                )
+                .map_err(|err| err.to_string())?
+        };
+        let mut rebuilt = Ok(());
+        self.for_each_surface(|config, ctx| {
+            if rebuilt.is_err()
+                || ctx.is_offscreen()
+                || ctx.is_minimized()
+                || ctx.present_mode == present_mode
+            {
+                return;
+            }
+            let extent = ctx.extent;
+            config.rebuild_swapchain(ctx);
+            if ctx.extent != extent {
+                config.destroy_surface_depth_resources(ctx);
+                config.create_surface_depth_resources(ctx);
+            }
+            rebuilt = config.create_surface_framebuffers(ctx);
+            config.create_render_finished_semaphores(ctx);
+            config.check_swapchain(ctx);
+        });
+        rebuilt.map_err(|err| err.to_string())?;
+        info!(
+            target: logging::SWAPCHAIN,
+            "Switched to {present_mode:?} in {:.2} ms",
+            start.elapsed().as_secs_f64() * 1000.0
+        );
+        Ok(())
+    }
+
+    // Replaces the swapchain and its images with an empty next generation.
+    // Nothing of the old one outlives this but its handle, which is passed on
+    // as the old swapchain and destroyed once the new one exists. Framebuffers
+    // and semaphores are filled in after the depth buffer matches again
+    fn rebuild_swapchain(&self, ctx: &mut SurfaceContext) {
+        self.destroy_swapchain_views(ctx);
+        ctx.swapchain = SwapchainResources {
+            handle: ctx.swapchain.handle,
+            generation: ctx.swapchain.generation + 1,
+            ..Default::default()
+        };
+        ctx.presents.reset();
+        self.create_surface_swapchain(ctx);
+        self.create_surface_image_views(ctx);
+    }
+
+    // The image count can change with every rebuild, a list left over from
+    // the previous swapchain would be indexed out of bounds later on
+    pub(super) fn check_swapchain(&self, ctx: &SurfaceContext) {
+        if let Err(err) = ctx.swapchain.check_counts() {
+            panic!("Swapchain for {:?} is inconsistent: {err}", ctx.id);
+        }
+        info!(
+            target: logging::SWAPCHAIN,
+            "Swapchain for {:?} has {} images, generation {}",
+            ctx.id,
+            ctx.swapchain.image_count(),
+            ctx.swapchain.generation()
+        );
+    }
+
+    // Everything created from the swapchain images, the swapchain itself is
+    // kept to be passed as the old one
+    fn destroy_swapchain_views(&self, ctx: &mut SurfaceContext) {
+        self.destroy_surface_gbuffer(ctx);
+        self.destroy_surface_ssao_targets(ctx);
+        self.destroy_surface_bloom_chain(ctx);
+        self.destroy_surface_scene_target(ctx);
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            ctx.swapchain
+                .framebuffers
+                .drain(..)
+                .chain(ctx.swapchain.ui_framebuffers.drain(..))
+                .for_each(|f| device.destroy_framebuffer(f, None));
+            ctx.swapchain
+                .image_views
+                .drain(..)
+                .for_each(|v| device.destroy_image_view(v, None));
+            ctx.swapchain
+                .render_finished_semaphores
+                .drain(..)
+                .for_each(|s| device.destroy_semaphore(s, None));
+        }
+    }
+
+    fn destroy_surface_depth_resources(&self, ctx: &mut SurfaceContext) {
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            if ctx.depth_sample_view != ctx.depth_image_view {
+                device.destroy_image_view(ctx.depth_sample_view, None);
+            }
+            device.destroy_image_view(ctx.depth_image_view, None);
+            device.free_memory(ctx.depth_image_memory, None);
+            device.destroy_image(ctx.depth_image, None);
+        }
+    }
+
+    fn destroy_swapchain_resources(&self, ctx: &mut SurfaceContext) {
+        self.destroy_swapchain_views(ctx);
+        self.destroy_surface_depth_resources(ctx);
+        let device = self.device.as_ref().unwrap();
+        self.release_full_screen_exclusive(ctx);
+        unsafe {
+            if ctx.swapchain.handle != SwapchainKHR::null() {
+                self.swapchain_device
+                    .as_ref()
+                    .unwrap()
+                    .destroy_swapchain(ctx.swapchain.handle, None);
+            }
+            ctx.swapchain.handle = SwapchainKHR::null();
+            // Swapchain images belong to the swapchain, only offscreen ones
+            // are ours to destroy
+            for (image, memory) in ctx
+                .swapchain
+                .images
+                .iter()
+                .zip(&ctx.swapchain.offscreen_memory)
+            {
+                device.destroy_image(*image, None);
+                device.free_memory(*memory, None);
+            }
+            ctx.swapchain.offscreen_memory.clear();
+            ctx.swapchain.images.clear();
+        }
+    }
+
+    pub(super) fn destroy_surface_context(&self, ctx: &mut SurfaceContext) {
+        self.destroy_swapchain_resources(ctx);
+        self.destroy_surface_transients(ctx);
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            if !ctx.command_buffers.is_empty() {
+                device.free_command_buffers(self.command_pool.unwrap(), &ctx.command_buffers);
+                ctx.command_buffers.clear();
+            }
+            ctx.image_available_semaphores
+                .drain(..)
+                .for_each(|s| device.destroy_semaphore(s, None));
+            ctx.in_flight_fences
+                .drain(..)
+                .for_each(|f| device.destroy_fence(f, None));
+            if !ctx.is_offscreen() {
+                self.surface_instance
+                    .as_ref()
+                    .unwrap()
+                    .destroy_surface(ctx.surface, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Semaphore;
+
+    use super::*;
+    use crate::engine::gpu_device::{mock::MockGpuDevice, present_frame};
+
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+    const FRAME: Duration = Duration::from_millis(16);
+
+    fn surface(width: u32, height: u32) -> SurfaceContext {
+        let mut ctx = SurfaceContext::new(ViewId::PRIMARY, SurfaceKHR::null(), width, height);
+        ctx.extent = Extent2D { width, height };
+        ctx
+    }
+
+    // Presents a frame at `now`, rebuilding the way `recreate_swapchain`
+    // does when it comes back stale
+    fn present(ctx: &mut SurfaceContext, gpu: &MockGpuDevice, now: Instant) -> bool {
+        let stale = ctx.rebuild_due(false, DEBOUNCE, now);
+        let slot = MockGpuDevice::slot(0);
+        let rebuild = present_frame(gpu, &slot, 0, Semaphore::null(), None, stale).unwrap();
+        if rebuild {
+            ctx.resized = false;
+            ctx.extent = Extent2D {
+                width: ctx.width,
+                height: ctx.height,
+            };
+        }
+        rebuild
+    }
+
+    // A drag sends a resize every frame, the swapchain is rebuilt once at
+    // the last size when they stop
+    #[test]
+    fn resizes_are_coalesced() {
+        let gpu = MockGpuDevice::default();
+        let mut ctx = surface(800, 600);
+        let start = Instant::now();
+        let mut rebuilds = Vec::new();
+        for frame in 0..30u32 {
+            let now = start + FRAME * frame;
+            if frame < 10 {
+                ctx.resize_at(PhysicalSize::new(800 + frame * 10, 600), now);
+            }
+            if present(&mut ctx, &gpu, now) {
+                rebuilds.push(frame);
+            }
+        }
+        // 9 is the last resize, rebuilt once 100ms have passed
+        assert_eq!(rebuilds, [16]);
+        assert_eq!(
+            ctx.extent,
+            Extent2D {
+                width: 890,
+                height: 600
+            }
+        );
+    }
+
+    #[test]
+    fn resizing_back_cancels_the_rebuild() {
+        let gpu = MockGpuDevice::default();
+        let mut ctx = surface(800, 600);
+        let start = Instant::now();
+        ctx.resize_at(PhysicalSize::new(1024, 768), start);
+        ctx.resize_at(PhysicalSize::new(800, 600), start + FRAME);
+        assert!(!(0..20).any(|frame| present(&mut ctx, &gpu, start + FRAME * frame)));
+    }
+
+    // Checked before the command buffer is touched, so no device is needed
+    #[test]
+    fn out_of_range_images_fail_the_recording() {
+        let mut config = Configuration::default();
+        let mut ctx = surface(800, 600);
+        ctx.swapchain.framebuffers = vec![Framebuffer::null(); 3];
+        config.surfaces.push(ctx);
+        assert_eq!(
+            config.record_command_buffer(0, 3, 0, &[], &[], 0),
+            Err(EngineError::ImageIndexOutOfRange {
+                index: 3,
+                framebuffers: 3
+            })
+        );
+    }
+
+    // Outside of resizes a suboptimal swapchain is rebuilt right away
+    #[test]
+    fn suboptimal_rebuilds_without_resizes() {
+        let ctx = surface(800, 600);
+        let now = Instant::now();
+        assert!(ctx.rebuild_due(true, DEBOUNCE, now));
+        assert!(!ctx.rebuild_due(false, DEBOUNCE, now));
+    }
+}
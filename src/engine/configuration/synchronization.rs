@@ -0,0 +1,497 @@
+use ash::vk::{
+    AccessFlags, AccessFlags2, Buffer, BufferMemoryBarrier, BufferMemoryBarrier2, CommandBuffer,
+    CommandBufferSubmitInfo, DependencyFlags, DependencyInfo, DeviceSize, Fence, Image,
+    ImageAspectFlags, ImageLayout, ImageMemoryBarrier, ImageMemoryBarrier2, ImageSubresourceRange,
+    MemoryBarrier, PhysicalDeviceFeatures2, PhysicalDeviceSynchronization2Features,
+    PipelineStageFlags, PipelineStageFlags2, Semaphore, SemaphoreSubmitInfo, SubmitInfo,
+    SubmitInfo2, KHR_SYNCHRONIZATION2_NAME, QUEUE_FAMILY_IGNORED, REMAINING_ARRAY_LAYERS,
+};
+use log::info;
+
+use crate::engine::error::VkContext;
+
+use super::Configuration;
+
+// The legacy flag bits are a subset of the synchronization2 ones with the same values
+fn stage2(stage: PipelineStageFlags) -> PipelineStageFlags2 {
+    PipelineStageFlags2::from_raw(stage.as_raw() as u64)
+}
+
+fn access2(access: AccessFlags) -> AccessFlags2 {
+    AccessFlags2::from_raw(access.as_raw() as u64)
+}
+
+// Stage and access a layout implies, split so the source side of a barrier
+// only has to make writes available
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutUsage {
+    pub stage: PipelineStageFlags,
+    pub read_access: AccessFlags,
+    pub write_access: AccessFlags,
+}
+
+impl LayoutUsage {
+    pub fn of(layout: ImageLayout) -> Option<LayoutUsage> {
+        let (stage, read_access, write_access) = match layout {
+            ImageLayout::UNDEFINED => (
+                PipelineStageFlags::TOP_OF_PIPE,
+                AccessFlags::empty(),
+                AccessFlags::empty(),
+            ),
+            ImageLayout::PREINITIALIZED => (
+                PipelineStageFlags::HOST,
+                AccessFlags::empty(),
+                AccessFlags::HOST_WRITE,
+            ),
+            // Only storage images of compute passes are kept in GENERAL
+            ImageLayout::GENERAL => (
+                PipelineStageFlags::COMPUTE_SHADER,
+                AccessFlags::SHADER_READ,
+                AccessFlags::SHADER_WRITE,
+            ),
+            ImageLayout::TRANSFER_SRC_OPTIMAL => (
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::TRANSFER_READ,
+                AccessFlags::empty(),
+            ),
+            ImageLayout::TRANSFER_DST_OPTIMAL => (
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::empty(),
+                AccessFlags::TRANSFER_WRITE,
+            ),
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+                PipelineStageFlags::FRAGMENT_SHADER | PipelineStageFlags::COMPUTE_SHADER,
+                AccessFlags::SHADER_READ,
+                AccessFlags::empty(),
+            ),
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                AccessFlags::COLOR_ATTACHMENT_READ,
+                AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            | ImageLayout::DEPTH_ATTACHMENT_OPTIMAL => (
+                PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL | ImageLayout::DEPTH_READ_ONLY_OPTIMAL => {
+                (
+                    PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                        | PipelineStageFlags::LATE_FRAGMENT_TESTS
+                        | PipelineStageFlags::FRAGMENT_SHADER
+                        | PipelineStageFlags::COMPUTE_SHADER,
+                    AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | AccessFlags::SHADER_READ,
+                    AccessFlags::empty(),
+                )
+            }
+            // Presentation is ordered through semaphores, not access masks
+            ImageLayout::PRESENT_SRC_KHR => (
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                AccessFlags::empty(),
+                AccessFlags::empty(),
+            ),
+            _ => return None,
+        };
+        Some(LayoutUsage {
+            stage,
+            read_access,
+            write_access,
+        })
+    }
+}
+
+pub fn subresource_range(
+    aspect_mask: ImageAspectFlags,
+    base_mip_level: u32,
+    level_count: u32,
+) -> ImageSubresourceRange {
+    ImageSubresourceRange::default()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(base_mip_level)
+        .level_count(level_count)
+        .base_array_layer(0)
+        .layer_count(REMAINING_ARRAY_LAYERS)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImageBarrier {
+    pub image: Image,
+    pub old_layout: ImageLayout,
+    pub new_layout: ImageLayout,
+    pub subresource_range: ImageSubresourceRange,
+    pub src_stage: PipelineStageFlags,
+    pub src_access: AccessFlags,
+    pub dst_stage: PipelineStageFlags,
+    pub dst_access: AccessFlags,
+    // Source and destination family of a queue family ownership transfer,
+    // QUEUE_FAMILY_IGNORED for both otherwise
+    pub queue_families: [u32; 2],
+}
+
+// A range of a buffer written in one stage and read in another
+#[derive(Debug, Clone, Copy)]
+pub struct BufferBarrier {
+    pub buffer: Buffer,
+    pub offset: DeviceSize,
+    pub size: DeviceSize,
+    pub src_stage: PipelineStageFlags,
+    pub src_access: AccessFlags,
+    pub dst_stage: PipelineStageFlags,
+    pub dst_access: AccessFlags,
+}
+
+impl ImageBarrier {
+    pub fn transition(
+        image: Image,
+        old_layout: ImageLayout,
+        new_layout: ImageLayout,
+        subresource_range: ImageSubresourceRange,
+    ) -> Result<ImageBarrier, String> {
+        let (Some(src), Some(dst)) = (LayoutUsage::of(old_layout), LayoutUsage::of(new_layout))
+        else {
+            return Err(format!(
+                "Unsupported image layout transition {:?} -> {:?}",
+                old_layout, new_layout
+            ));
+        };
+        Ok(ImageBarrier {
+            image,
+            old_layout,
+            new_layout,
+            subresource_range,
+            src_stage: src.stage,
+            src_access: src.write_access,
+            dst_stage: dst.stage,
+            dst_access: dst.read_access | dst.write_access,
+            queue_families: [QUEUE_FAMILY_IGNORED; 2],
+        })
+    }
+
+    // The half of an ownership transfer recorded on the `from` family. Its
+    // destination scope is left to the acquire
+    pub fn release(self, from: u32, to: u32) -> ImageBarrier {
+        ImageBarrier {
+            dst_stage: PipelineStageFlags::BOTTOM_OF_PIPE,
+            dst_access: AccessFlags::empty(),
+            queue_families: [from, to],
+            ..self
+        }
+    }
+
+    // The half recorded on the `to` family, in a submission that waited for
+    // the release at ALL_COMMANDS
+    pub fn acquire(self, from: u32, to: u32) -> ImageBarrier {
+        ImageBarrier {
+            src_stage: PipelineStageFlags::ALL_COMMANDS,
+            src_access: AccessFlags::empty(),
+            queue_families: [from, to],
+            ..self
+        }
+    }
+}
+
+impl Configuration {
+    pub(super) fn supports_synchronization2(&self) -> bool {
+        if !self.config.synchronization2 {
+            return false;
+        }
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let extension_available = unsafe {
+            instance
+                .enumerate_device_extension_properties(physical_device)
+                .unwrap()
+                .iter()
+                .any(|property| property.extension_name_as_c_str() == Ok(KHR_SYNCHRONIZATION2_NAME))
+        };
+        if !extension_available {
+            return false;
+        }
+
+        let properties2 = ash::khr::get_physical_device_properties2::Instance::new(
+            self.vulkan_entry.as_ref().unwrap(),
+            instance,
+        );
+        let mut synchronization2_features = PhysicalDeviceSynchronization2Features::default();
+        let mut features =
+            PhysicalDeviceFeatures2::default().push_next(&mut synchronization2_features);
+        unsafe { properties2.get_physical_device_features2(physical_device, &mut features) };
+        synchronization2_features.synchronization2 != 0
+    }
+
+    pub(super) fn enable_synchronization2(&mut self) {
+        self.synchronization2 = Some(ash::khr::synchronization2::Device::new(
+            self.instance.as_ref().unwrap(),
+            self.device.as_ref().unwrap(),
+        ));
+        info!("Using VK_KHR_synchronization2 for barriers and submits");
+    }
+
+    pub fn cmd_image_barrier(&self, command_buffer: CommandBuffer, barrier: ImageBarrier) {
+        let device = self.device.as_ref().unwrap();
+        match &self.synchronization2 {
+            Some(synchronization2) => {
+                let image_memory_barriers = [ImageMemoryBarrier2::default()
+                    .src_stage_mask(stage2(barrier.src_stage))
+                    .src_access_mask(access2(barrier.src_access))
+                    .dst_stage_mask(stage2(barrier.dst_stage))
+                    .dst_access_mask(access2(barrier.dst_access))
+                    .old_layout(barrier.old_layout)
+                    .new_layout(barrier.new_layout)
+                    .src_queue_family_index(barrier.queue_families[0])
+                    .dst_queue_family_index(barrier.queue_families[1])
+                    .image(barrier.image)
+                    .subresource_range(barrier.subresource_range)];
+                let dependency_info =
+                    DependencyInfo::default().image_memory_barriers(&image_memory_barriers);
+                unsafe { synchronization2.cmd_pipeline_barrier2(command_buffer, &dependency_info) };
+            }
+            None => {
+                let image_memory_barriers = [ImageMemoryBarrier::default()
+                    .old_layout(barrier.old_layout)
+                    .new_layout(barrier.new_layout)
+                    .src_queue_family_index(barrier.queue_families[0])
+                    .dst_queue_family_index(barrier.queue_families[1])
+                    .image(barrier.image)
+                    .subresource_range(barrier.subresource_range)
+                    .src_access_mask(barrier.src_access)
+                    .dst_access_mask(barrier.dst_access)];
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        barrier.src_stage,
+                        barrier.dst_stage,
+                        DependencyFlags::empty(),
+                        &[] as &[MemoryBarrier],
+                        &[] as &[BufferMemoryBarrier],
+                        &image_memory_barriers,
+                    )
+                };
+            }
+        }
+    }
+
+    pub fn cmd_buffer_barrier(&self, command_buffer: CommandBuffer, barrier: BufferBarrier) {
+        let device = self.device.as_ref().unwrap();
+        match &self.synchronization2 {
+            Some(synchronization2) => {
+                let buffer_memory_barriers = [BufferMemoryBarrier2::default()
+                    .src_stage_mask(stage2(barrier.src_stage))
+                    .src_access_mask(access2(barrier.src_access))
+                    .dst_stage_mask(stage2(barrier.dst_stage))
+                    .dst_access_mask(access2(barrier.dst_access))
+                    .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .buffer(barrier.buffer)
+                    .offset(barrier.offset)
+                    .size(barrier.size)];
+                let dependency_info =
+                    DependencyInfo::default().buffer_memory_barriers(&buffer_memory_barriers);
+                unsafe { synchronization2.cmd_pipeline_barrier2(command_buffer, &dependency_info) };
+            }
+            None => {
+                let buffer_memory_barriers = [BufferMemoryBarrier::default()
+                    .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                    .buffer(barrier.buffer)
+                    .offset(barrier.offset)
+                    .size(barrier.size)
+                    .src_access_mask(barrier.src_access)
+                    .dst_access_mask(barrier.dst_access)];
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        barrier.src_stage,
+                        barrier.dst_stage,
+                        DependencyFlags::empty(),
+                        &[] as &[MemoryBarrier],
+                        &buffer_memory_barriers,
+                        &[] as &[ImageMemoryBarrier],
+                    )
+                };
+            }
+        }
+    }
+
+    pub fn submit_frame(
+        &self,
+        command_buffer: CommandBuffer,
+        wait_semaphore: Semaphore,
+        wait_stage: PipelineStageFlags,
+        signal_semaphore: Semaphore,
+        fence: Fence,
+    ) {
+        let device = self.device.as_ref().unwrap();
+        let queue = self.graphics_queue.unwrap();
+        match &self.synchronization2 {
+            Some(synchronization2) => {
+                let wait_semaphore_infos = [SemaphoreSubmitInfo::default()
+                    .semaphore(wait_semaphore)
+                    .stage_mask(stage2(wait_stage))];
+                let signal_semaphore_infos = [SemaphoreSubmitInfo::default()
+                    .semaphore(signal_semaphore)
+                    .stage_mask(PipelineStageFlags2::ALL_COMMANDS)];
+                let command_buffer_infos =
+                    [CommandBufferSubmitInfo::default().command_buffer(command_buffer)];
+                let submit_info = [SubmitInfo2::default()
+                    .wait_semaphore_infos(&wait_semaphore_infos)
+                    .command_buffer_infos(&command_buffer_infos)
+                    .signal_semaphore_infos(&signal_semaphore_infos)];
+                unsafe {
+                    synchronization2
+                        .queue_submit2(queue, &submit_info, fence)
+                        .vk_expect("vkQueueSubmit2", "submitting a frame")
+                };
+            }
+            None => {
+                let wait_semaphores = [wait_semaphore];
+                let wait_stages = [wait_stage];
+                let signal_semaphores = [signal_semaphore];
+                let command_buffers = [command_buffer];
+                let submit_info = [SubmitInfo::default()
+                    .wait_semaphores(&wait_semaphores)
+                    .wait_dst_stage_mask(&wait_stages)
+                    .command_buffers(&command_buffers)
+                    .signal_semaphores(&signal_semaphores)];
+                unsafe {
+                    device
+                        .queue_submit(queue, &submit_info, fence)
+                        .vk_expect("vkQueueSubmit", "submitting a frame")
+                };
+            }
+        }
+    }
+
+    // Gives up on an acquired image without rendering to it. The empty submit
+    // still consumes the acquire semaphore and signals the frame fence
+    pub fn skip_frame(&self, wait_semaphore: Semaphore, fence: Fence) {
+        let wait_semaphores = [wait_semaphore];
+        let wait_stages = [PipelineStageFlags::ALL_COMMANDS];
+        let submit_info = [SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)];
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .queue_submit(self.graphics_queue.unwrap(), &submit_info, fence)
+                .vk_expect("vkQueueSubmit", "skipping a frame")
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Layout, stage, read access, write access
+    fn table() -> [(ImageLayout, PipelineStageFlags, AccessFlags, AccessFlags); 12] {
+        let depth_tests =
+            PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let shaders = PipelineStageFlags::FRAGMENT_SHADER | PipelineStageFlags::COMPUTE_SHADER;
+        let depth_read = AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | AccessFlags::SHADER_READ;
+        [
+            (
+                ImageLayout::UNDEFINED,
+                PipelineStageFlags::TOP_OF_PIPE,
+                AccessFlags::empty(),
+                AccessFlags::empty(),
+            ),
+            (
+                ImageLayout::PREINITIALIZED,
+                PipelineStageFlags::HOST,
+                AccessFlags::empty(),
+                AccessFlags::HOST_WRITE,
+            ),
+            (
+                ImageLayout::GENERAL,
+                PipelineStageFlags::COMPUTE_SHADER,
+                AccessFlags::SHADER_READ,
+                AccessFlags::SHADER_WRITE,
+            ),
+            (
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::TRANSFER_READ,
+                AccessFlags::empty(),
+            ),
+            (
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                PipelineStageFlags::TRANSFER,
+                AccessFlags::empty(),
+                AccessFlags::TRANSFER_WRITE,
+            ),
+            (
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                shaders,
+                AccessFlags::SHADER_READ,
+                AccessFlags::empty(),
+            ),
+            (
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                AccessFlags::COLOR_ATTACHMENT_READ,
+                AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+            (
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                depth_tests,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            (
+                ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+                depth_tests,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            (
+                ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                depth_tests | shaders,
+                depth_read,
+                AccessFlags::empty(),
+            ),
+            (
+                ImageLayout::DEPTH_READ_ONLY_OPTIMAL,
+                depth_tests | shaders,
+                depth_read,
+                AccessFlags::empty(),
+            ),
+            (
+                ImageLayout::PRESENT_SRC_KHR,
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                AccessFlags::empty(),
+                AccessFlags::empty(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn every_layout_maps_to_its_stage_and_access() {
+        for (layout, stage, read_access, write_access) in table() {
+            let usage = LayoutUsage::of(layout).unwrap();
+            assert_eq!(usage.stage, stage, "{layout:?}");
+            assert_eq!(usage.read_access, read_access, "{layout:?}");
+            assert_eq!(usage.write_access, write_access, "{layout:?}");
+        }
+    }
+
+    // ALL_COMMANDS would serialize every barrier against the whole queue
+    #[test]
+    fn no_layout_waits_on_all_commands() {
+        for (layout, ..) in table() {
+            let stage = LayoutUsage::of(layout).unwrap().stage;
+            assert!(
+                !stage.contains(PipelineStageFlags::ALL_COMMANDS),
+                "{layout:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unused_layouts_have_no_usage() {
+        assert!(LayoutUsage::of(ImageLayout::STENCIL_ATTACHMENT_OPTIMAL).is_none());
+        assert!(LayoutUsage::of(ImageLayout::SHARED_PRESENT_KHR).is_none());
+    }
+}
@@ -0,0 +1,303 @@
+use std::sync::Arc;
+
+use anyhow::Error;
+use ash::vk::{
+    BorderColor, Buffer, CommandBuffer, CompareOp, CullModeFlags, DescriptorImageInfo,
+    DescriptorSet, DescriptorSetLayout, DescriptorSetLayoutCreateInfo, DescriptorType, DeviceSize,
+    Filter, ImageLayout, ImageView, Pipeline, PipelineBindPoint, PipelineLayout,
+    PipelineLayoutCreateInfo, PrimitiveTopology, Sampler, SamplerAddressMode, SamplerCreateInfo,
+    SamplerMipmapMode, ShaderStageFlags, WriteDescriptorSet,
+};
+use cgmath::{vec2, vec3, EuclideanSpace, Matrix4, Point3};
+use log::{info, warn};
+
+use super::{
+    asset_cache::TextureResource, buffer_types::vertex::Vertex,
+    descriptor_allocator::DescriptorAllocator, pipeline_options::PipelineOptions,
+    shader_reflection::ShaderReflection, textures::ColorSpaceHint, Configuration, DepthStencil,
+    MAX_FLIGHT_FENCES,
+};
+use crate::engine::{error::VkContext, sdf_font::SdfFont, viewport::viewport};
+
+pub(super) const TEXT_VERTEX_SHADER_PATH: &str = "src/assets/text_vertices.spv";
+pub(super) const TEXT_SHADER_PATH: &str = "src/assets/text.spv";
+const TEXT_DESCRIPTOR_BINDINGS: [(u32, DescriptorType); 2] = [
+    (0, DescriptorType::SAMPLED_IMAGE),
+    (1, DescriptorType::SAMPLER),
+];
+// Six per glyph, a screen full of log text fits easily
+const MAX_TEXT_VERTICES: usize = 6 << 13;
+
+// Screen text drawn from a signed distance field font over everything else
+// in the primary window, in the UI pass so it stays sharp at any render
+// scale. Only created when `EngineConfig::text_font` loads, the stroke text
+// of the gizmo pass is used otherwise
+pub struct TextPass {
+    font: SdfFont,
+    descriptor_set_layout: DescriptorSetLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline: Pipeline,
+    sampler: Sampler,
+    descriptor_allocator: DescriptorAllocator,
+    // Per frame in flight, each with the atlas texture it was last written
+    // with so one frame's draws never see another's texture swapped out
+    descriptor_sets: Vec<DescriptorSet>,
+    bound_textures: Vec<Option<Arc<TextureResource>>>,
+    // The atlas as of the last upload
+    texture: Option<Arc<TextureResource>>,
+    // Per frame in flight, the ring buffer, offset and vertex count
+    vertices: Vec<Option<(Buffer, DeviceSize, u32)>>,
+}
+
+impl Configuration {
+    pub fn create_text_pass(&mut self) -> Result<&mut Configuration, Error> {
+        if self.headless() {
+            return Ok(self);
+        }
+        let Some(path) = self.config.text_font.clone() else {
+            return Ok(self);
+        };
+        let font = match SdfFont::load(&path) {
+            Ok(font) => font,
+            Err(err) => {
+                warn!("Falling back to the stroke font: {err}");
+                return Ok(self);
+            }
+        };
+        let mut reflection = ShaderReflection::reflect(
+            self.shader_cache.code(TEXT_VERTEX_SHADER_PATH)?,
+            ShaderStageFlags::VERTEX,
+        )?;
+        reflection.merge(ShaderReflection::reflect(
+            self.shader_cache.code(TEXT_SHADER_PATH)?,
+            ShaderStageFlags::FRAGMENT,
+        )?)?;
+        reflection.validate(0, &TEXT_DESCRIPTOR_BINDINGS)?;
+
+        let device = self.device.as_ref().unwrap();
+        let bindings = reflection.set_layout_bindings(0, &[]);
+        let descriptor_set_layout_create_info =
+            DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
+                .vk_ctx("vkCreateDescriptorSetLayout", "creating the text pass")?
+        };
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_create_info = PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .vk_ctx("vkCreatePipelineLayout", "creating the text pass")?
+        };
+        // Linear filtering is what makes the distance field scale, clamping
+        // keeps glyphs at the atlas' edges from sampling the opposite side
+        let sampler_create_info = SamplerCreateInfo::default()
+            .mag_filter(Filter::LINEAR)
+            .min_filter(Filter::LINEAR)
+            .mipmap_mode(SamplerMipmapMode::NEAREST)
+            .address_mode_u(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(BorderColor::FLOAT_TRANSPARENT_BLACK)
+            .compare_op(CompareOp::ALWAYS)
+            .max_lod(0.0);
+        let sampler = unsafe {
+            device
+                .create_sampler(&sampler_create_info, None)
+                .vk_ctx("vkCreateSampler", "creating the text pass")?
+        };
+        let mut descriptor_allocator = DescriptorAllocator::new(
+            MAX_FLIGHT_FENCES,
+            vec![
+                (DescriptorType::SAMPLED_IMAGE, 1.0),
+                (DescriptorType::SAMPLER, 1.0),
+            ],
+        );
+        let descriptor_sets = descriptor_allocator.allocate(
+            device,
+            &vec![descriptor_set_layout; MAX_FLIGHT_FENCES as usize],
+        )?;
+
+        let pipeline = self.create_pipeline_variant(
+            (TEXT_VERTEX_SHADER_PATH, TEXT_SHADER_PATH),
+            PrimitiveTopology::TRIANGLE_LIST,
+            (self.ui_pass.unwrap(), 1),
+            pipeline_layout,
+            (
+                DepthStencil::NONE,
+                PipelineOptions {
+                    cull_mode: CullModeFlags::NONE,
+                    alpha_blend: true,
+                    ..PipelineOptions::default()
+                },
+            ),
+            0,
+        )?;
+        self.text = Some(TextPass {
+            font,
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+            sampler,
+            descriptor_allocator,
+            descriptor_sets,
+            bound_textures: vec![None; MAX_FLIGHT_FENCES as usize],
+            texture: None,
+            vertices: vec![None; MAX_FLIGHT_FENCES as usize],
+        });
+        info!("Text pass has been created!");
+        Ok(self)
+    }
+
+    pub(super) fn destroy_text_pass(&mut self) {
+        let Some(mut text) = self.text.take() else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        text.descriptor_allocator.destroy(device);
+        unsafe {
+            device.destroy_pipeline(text.pipeline, None);
+            device.destroy_pipeline_layout(text.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(text.descriptor_set_layout, None);
+            device.destroy_sampler(text.sampler, None);
+        }
+    }
+
+    // The font screen text is laid out with, None when it is drawn with
+    // the stroke font
+    pub fn text_font_mut(&mut self) -> Option<&mut SdfFont> {
+        self.text.as_mut().map(|text| &mut text.font)
+    }
+
+    // `glyphs` are the frame's quads as laid out by the font, triangle list
+    // vertices in 0..1 window coordinates with y up. Uploads whatever the
+    // layout added to the atlas first
+    pub fn update_text(
+        &mut self,
+        current_frame: usize,
+        glyphs: &[(Point3<f32>, [f32; 3], [f32; 2])],
+    ) {
+        let Some(mut text) = self.text.take() else {
+            return;
+        };
+        match self.upload_atlas(
+            text.font.atlas_mut(),
+            ColorSpaceHint::Linear,
+            text.texture.take(),
+        ) {
+            Ok(texture) => text.texture = Some(texture),
+            Err(err) => warn!("Failed to upload the text atlas: {err}"),
+        }
+        if let Some(texture) = text.texture.as_ref().filter(|texture| {
+            !text.bound_textures[current_frame]
+                .as_ref()
+                .is_some_and(|bound| Arc::ptr_eq(bound, texture))
+        }) {
+            self.write_text_descriptor_set(
+                text.descriptor_sets[current_frame],
+                texture.view(),
+                text.sampler,
+            );
+            text.bound_textures[current_frame] = Some(texture.clone());
+        }
+        if glyphs.len() > MAX_TEXT_VERTICES {
+            warn!(
+                "{} text vertices, only drawing the first {MAX_TEXT_VERTICES}",
+                glyphs.len()
+            );
+        }
+        let count = glyphs.len().min(MAX_TEXT_VERTICES) / 6 * 6;
+        let vertices = glyphs[..count]
+            .iter()
+            .map(|(position, color, [u, v])| {
+                Vertex::new(position.to_vec(), (*color).into(), vec2(*u, *v))
+            })
+            .collect::<Vec<Vertex>>();
+        text.vertices[current_frame] = self
+            .upload_ring
+            .as_mut()
+            .filter(|_| count > 0 && text.bound_textures[current_frame].is_some())
+            .and_then(|ring| ring.push(current_frame, &vertices, size_of::<Vertex>() as DeviceSize))
+            .map(|allocation| (allocation.buffer, allocation.offset, count as u32));
+        self.text = Some(text);
+    }
+
+    // Whether the frame has text to draw
+    pub(super) fn text_pending(&self, current_frame: usize) -> bool {
+        self.text
+            .as_ref()
+            .is_some_and(|text| text.vertices[current_frame].is_some())
+    }
+
+    fn write_text_descriptor_set(&self, set: DescriptorSet, view: ImageView, sampler: Sampler) {
+        let image_infos = [
+            [DescriptorImageInfo::default()
+                .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(view)],
+            [DescriptorImageInfo::default().sampler(sampler)],
+        ];
+        let writes = TEXT_DESCRIPTOR_BINDINGS.map(|(binding, descriptor_type)| {
+            WriteDescriptorSet::default()
+                .dst_set(set)
+                .dst_binding(binding)
+                .descriptor_type(descriptor_type)
+                .image_info(&image_infos[binding as usize])
+        });
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .update_descriptor_sets(&writes, &[])
+        };
+    }
+
+    // Inside the UI pass of the primary window, after the screen lines
+    pub(super) fn cmd_text(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        current_frame: usize,
+    ) {
+        let Some(text) = &self.text else {
+            return;
+        };
+        let Some((vertex_buffer, offset, vertex_count)) = text.vertices[current_frame] else {
+            return;
+        };
+        if surface_index != 0 {
+            return;
+        }
+        let ctx = &self.surfaces[surface_index];
+        let region = self.ui_region(ctx);
+        // 0..1 onto clip space, y already points up through the viewport
+        let screen = Matrix4::from_translation(vec3(-1.0, -1.0, 0.0))
+            * Matrix4::from_nonuniform_scale(2.0, 2.0, 1.0);
+        let view_projection: [[f32; 4]; 4] = (ctx.rotation.matrix() * screen).into();
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, PipelineBindPoint::GRAPHICS, text.pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                PipelineBindPoint::GRAPHICS,
+                text.pipeline_layout,
+                0,
+                &[text.descriptor_sets[current_frame]],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[offset]);
+            device.cmd_set_viewport(command_buffer, 0, &[viewport(&region)]);
+            device.cmd_set_scissor(command_buffer, 0, &[region]);
+            device.cmd_push_constants(
+                command_buffer,
+                text.pipeline_layout,
+                ShaderStageFlags::VERTEX,
+                0,
+                bytemuck::bytes_of(&view_projection),
+            );
+            device.cmd_draw(command_buffer, vertex_count, 1, 0, 0);
+        }
+    }
+}
@@ -0,0 +1,838 @@
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Error};
+use ash::{
+    vk::{
+        BufferImageCopy, BufferUsageFlags, CommandBuffer, CommandBufferAllocateInfo,
+        CommandBufferBeginInfo, CommandBufferLevel, CommandBufferResetFlags,
+        CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags, CommandPoolCreateInfo,
+        DescriptorImageInfo, DescriptorType, DeviceSize, Extent3D, Fence, Format, ImageAspectFlags,
+        ImageLayout, ImageSubresourceLayers, Offset3D, PipelineStageFlags, Semaphore, SubmitInfo,
+        WriteDescriptorSet, REMAINING_MIP_LEVELS,
+    },
+    Device,
+};
+use log::{debug, info, warn, Level};
+
+use crate::{
+    engine::error::VkContext,
+    logging::{self, span},
+};
+
+use super::{
+    asset_cache::TextureResource,
+    buffer_types::gpu_buffer::GpuBuffer,
+    synchronization::{subresource_range, ImageBarrier},
+    textures::{block_layout, ColorSpaceHint, DecodedImage, MipLevel, TextureSlot},
+    transfer::TimelinePoint,
+    Configuration, MAX_FLIGHT_FENCES,
+};
+
+// Bytes copied into streaming textures per frame, so a batch of large
+// textures is spread over many frames instead of stalling one
+const STREAMING_BUDGET: DeviceSize = 4 << 20;
+const MAX_DECODE_THREADS: usize = 4;
+
+type StreamedTexture = (PathBuf, ColorSpaceHint, TextureResource);
+
+struct Decoded {
+    path: PathBuf,
+    color_space: ColorSpaceHint,
+    image: Result<DecodedImage, io::Error>,
+}
+
+// A texture whose mip levels are copied a few block rows per frame
+struct PendingUpload {
+    path: PathBuf,
+    color_space: ColorSpaceHint,
+    texture: TextureResource,
+    staging: GpuBuffer<u8>,
+    format: Format,
+    levels: Vec<MipLevel>,
+    // Position of the next copy
+    level: usize,
+    rows_copied: u32,
+}
+
+impl PendingUpload {
+    fn row_size(&self) -> DeviceSize {
+        self.levels[self.level].row_size(self.format) as DeviceSize
+    }
+
+    fn rows_left(&self) -> u32 {
+        self.levels[self.level].block_rows(self.format) - self.rows_copied
+    }
+
+    fn is_done(&self) -> bool {
+        self.level == self.levels.len()
+    }
+}
+
+// Decodes on worker threads and copies from the frame loop, on command
+// buffers of its own so the views' submissions stay untouched
+pub(super) struct TextureStreamer {
+    jobs: Option<Sender<(PathBuf, ColorSpaceHint)>>,
+    decoded: Receiver<Decoded>,
+    cancelled: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+    uploads: VecDeque<PendingUpload>,
+    // On the transfer queue's own pool when there is one
+    command_buffers: Vec<CommandBuffer>,
+    fences: Vec<Fence>,
+    // Staging buffers of copies submitted with that frame slot's fence
+    retired: Vec<Vec<GpuBuffer<u8>>>,
+    transfer: Option<TransferUploads>,
+}
+
+// Copies run on the transfer queue while the graphics queue renders. Every
+// submission signals the next timeline value, finished textures are
+// released to the graphics family and acquired there by a submission that
+// waits for that value. A texture is bound once the timeline shows the
+// acquire is done, until then objects keep the placeholder
+struct TransferUploads {
+    command_pool: CommandPool,
+    timeline: Semaphore,
+    // Per frame slot, on the graphics pool
+    acquire_command_buffers: Vec<CommandBuffer>,
+    values: UploadTimeline<StreamedTexture>,
+}
+
+// The timeline values copies and acquires signal, and what is ready at
+// which value
+#[derive(Debug)]
+struct UploadTimeline<T> {
+    last_value: u64,
+    // Per frame slot, the value its last acquire signals
+    acquired: Vec<u64>,
+    // With the value they are ready at
+    in_flight: Vec<(u64, T)>,
+}
+
+impl<T> UploadTimeline<T> {
+    fn new(frames: usize) -> Self {
+        UploadTimeline {
+            last_value: 0,
+            acquired: vec![0; frames],
+            in_flight: Vec::new(),
+        }
+    }
+
+    // What the next copy submission signals
+    fn copy(&mut self) -> u64 {
+        self.last_value += 1;
+        self.last_value
+    }
+
+    // The slot's previous acquire has to be done before it is recorded again
+    fn slot_done_at(&self, current_frame: usize) -> u64 {
+        self.acquired[current_frame]
+    }
+
+    // The acquire of `released` waits for `copied`, they are ready at the
+    // value it signals
+    fn acquire(
+        &mut self,
+        current_frame: usize,
+        copied: u64,
+        released: impl IntoIterator<Item = T>,
+    ) -> u64 {
+        let acquired = copied + 1;
+        self.last_value = acquired;
+        self.acquired[current_frame] = acquired;
+        self.in_flight
+            .extend(released.into_iter().map(|item| (acquired, item)));
+        acquired
+    }
+
+    // Everything ready once the timeline has `reached` a value, in the order
+    // it was released
+    fn take_ready(&mut self, reached: u64) -> Vec<T> {
+        let (ready, waiting) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(value, _)| *value <= reached);
+        self.in_flight = waiting;
+        ready.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+impl TransferUploads {
+    fn new(config: &Configuration) -> Result<TransferUploads, Error> {
+        let device = config.device.as_ref().unwrap();
+        let pool_create_info = CommandPoolCreateInfo::default()
+            .queue_family_index(config.upload_family())
+            .flags(CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = unsafe {
+            device
+                .create_command_pool(&pool_create_info, None)
+                .vk_ctx("vkCreateCommandPool", "setting up transfer queue uploads")?
+        };
+        let allocate_info = CommandBufferAllocateInfo::default()
+            .level(CommandBufferLevel::PRIMARY)
+            .command_pool(config.command_pool.unwrap())
+            .command_buffer_count(MAX_FLIGHT_FENCES);
+        Ok(TransferUploads {
+            command_pool,
+            timeline: config.create_timeline_semaphore()?,
+            acquire_command_buffers: unsafe {
+                device.allocate_command_buffers(&allocate_info).vk_ctx(
+                    "vkAllocateCommandBuffers",
+                    "setting up transfer queue uploads",
+                )?
+            },
+            values: UploadTimeline::new(MAX_FLIGHT_FENCES as usize),
+        })
+    }
+
+    fn point(&self, value: u64) -> TimelinePoint {
+        TimelinePoint {
+            semaphore: self.timeline,
+            value,
+        }
+    }
+
+    // Hands `released` over to the graphics queue once the transfer
+    // submission signaling `copied` is done
+    fn acquire(
+        &mut self,
+        config: &Configuration,
+        current_frame: usize,
+        copied: TimelinePoint,
+        released: Vec<StreamedTexture>,
+    ) {
+        let device = config.device.as_ref().unwrap();
+        config.wait_timeline(self.point(self.values.slot_done_at(current_frame)));
+        let command_buffer = self.acquire_command_buffers[current_frame];
+        let graphics_family = config.queue_family_indices.unwrap().graphics_queue.unwrap();
+        let detail = "handing streamed textures to the graphics queue";
+        unsafe {
+            device
+                .reset_command_buffer(command_buffer, CommandBufferResetFlags::empty())
+                .vk_expect("vkResetCommandBuffer", detail);
+            device
+                .begin_command_buffer(
+                    command_buffer,
+                    &CommandBufferBeginInfo::default()
+                        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .vk_expect("vkBeginCommandBuffer", detail);
+        }
+        for (_, _, texture) in &released {
+            let barrier = texture_barrier(texture).acquire(config.upload_family(), graphics_family);
+            config.cmd_image_barrier(command_buffer, barrier);
+        }
+        unsafe {
+            device
+                .end_command_buffer(command_buffer)
+                .vk_expect("vkEndCommandBuffer", detail)
+        };
+        let acquired = self.values.acquire(current_frame, copied.value, released);
+        config.submit_timeline(
+            config.graphics_queue.unwrap(),
+            command_buffer,
+            Some((copied, PipelineStageFlags::ALL_COMMANDS)),
+            self.point(acquired),
+            Fence::null(),
+        );
+    }
+
+    // Textures whose acquire is done, in the order they finished
+    fn take_ready(&mut self, config: &Configuration) -> Vec<StreamedTexture> {
+        if self.values.in_flight.is_empty() {
+            return Vec::new();
+        }
+        self.values.take_ready(config.timeline_value(self.timeline))
+    }
+
+    // Only once the device is idle
+    fn destroy(self, device: &Device, config: &Configuration) {
+        unsafe {
+            device
+                .free_command_buffers(config.command_pool.unwrap(), &self.acquire_command_buffers);
+            device.destroy_command_pool(self.command_pool, None);
+            device.destroy_semaphore(self.timeline, None);
+        }
+    }
+}
+
+// The transition out of the copies, the same on both sides of an ownership
+// transfer
+fn texture_barrier(texture: &TextureResource) -> ImageBarrier {
+    let range = subresource_range(ImageAspectFlags::COLOR, 0, REMAINING_MIP_LEVELS);
+    ImageBarrier::transition(
+        texture.image,
+        ImageLayout::TRANSFER_DST_OPTIMAL,
+        ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        range,
+    )
+    .unwrap()
+}
+
+impl TextureStreamer {
+    fn new(config: &Configuration) -> TextureStreamer {
+        let (jobs, job_receiver) = mpsc::channel::<(PathBuf, ColorSpaceHint)>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (decoded_sender, decoded) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let supported_formats = Arc::new(config.supported_texture_formats());
+        let threads = thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(MAX_DECODE_THREADS);
+        let workers = (0..threads)
+            .map(|index| {
+                let job_receiver = job_receiver.clone();
+                let decoded_sender = decoded_sender.clone();
+                let cancelled = cancelled.clone();
+                let supported_formats = supported_formats.clone();
+                thread::Builder::new()
+                    .name(format!("texture-decode-{index}"))
+                    .spawn(move || loop {
+                        let Ok((path, color_space)) = job_receiver.lock().unwrap().recv() else {
+                            break;
+                        };
+                        if cancelled.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let image = DecodedImage::load(&path, color_space, &supported_formats);
+                        let decoded = Decoded {
+                            path,
+                            color_space,
+                            image,
+                        };
+                        if decoded_sender.send(decoded).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("Failed to spawn a texture decoding thread")
+            })
+            .collect();
+
+        let device = config.device.as_ref().unwrap();
+        let transfer = config
+            .transfer_queue
+            .and_then(|_| match TransferUploads::new(config) {
+                Ok(transfer) => Some(transfer),
+                Err(err) => {
+                    warn!(target: logging::UPLOAD, "Streaming on the graphics queue: {err}");
+                    None
+                }
+            });
+        let allocate_info = CommandBufferAllocateInfo::default()
+            .level(CommandBufferLevel::PRIMARY)
+            .command_pool(
+                transfer
+                    .as_ref()
+                    .map_or(config.command_pool.unwrap(), |transfer| {
+                        transfer.command_pool
+                    }),
+            )
+            .command_buffer_count(MAX_FLIGHT_FENCES);
+        let command_buffers = unsafe {
+            device
+                .allocate_command_buffers(&allocate_info)
+                .vk_expect("vkAllocateCommandBuffers", "starting texture streaming")
+        };
+        let fences = (0..MAX_FLIGHT_FENCES)
+            .map(|_| config.create_fence().unwrap())
+            .collect();
+        debug!(target: logging::UPLOAD, "Texture streaming started with {threads} decoding threads");
+        TextureStreamer {
+            jobs: Some(jobs),
+            decoded,
+            cancelled,
+            workers,
+            uploads: VecDeque::new(),
+            command_buffers,
+            fences,
+            retired: (0..MAX_FLIGHT_FENCES).map(|_| Vec::new()).collect(),
+            transfer,
+        }
+    }
+
+    fn request(&self, path: &Path, color_space: ColorSpaceHint) {
+        if let Some(jobs) = &self.jobs {
+            jobs.send((path.to_path_buf(), color_space)).unwrap();
+        }
+    }
+
+    // Records up to STREAMING_BUDGET bytes of copies and submits them.
+    // Returns the textures that are in SHADER_READ_ONLY_OPTIMAL on the
+    // graphics queue for anything submitted afterwards
+    fn submit_uploads(
+        &mut self,
+        config: &Configuration,
+        current_frame: usize,
+    ) -> Vec<StreamedTexture> {
+        let device = config.device.as_ref().unwrap();
+        let fence = self.fences[current_frame];
+        let command_buffer = self.command_buffers[current_frame];
+        let detail = "uploading streamed textures";
+        unsafe {
+            device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .vk_expect("vkWaitForFences", detail);
+        }
+        self.retired[current_frame].clear();
+        let mut ready = self
+            .transfer
+            .as_mut()
+            .map_or_else(Vec::new, |transfer| transfer.take_ready(config));
+        if self.uploads.is_empty() {
+            return ready;
+        }
+        unsafe {
+            device
+                .reset_fences(&[fence])
+                .vk_expect("vkResetFences", detail);
+            device
+                .reset_command_buffer(command_buffer, CommandBufferResetFlags::empty())
+                .vk_expect("vkResetCommandBuffer", detail);
+            device
+                .begin_command_buffer(
+                    command_buffer,
+                    &CommandBufferBeginInfo::default()
+                        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .vk_expect("vkBeginCommandBuffer", detail);
+        }
+
+        let mut budget = STREAMING_BUDGET;
+        let mut finished = Vec::new();
+        while budget > 0 {
+            let Some(upload) = self.uploads.front_mut() else {
+                break;
+            };
+            let row_size = upload.row_size();
+            let rows = (budget / row_size).clamp(1, upload.rows_left() as DeviceSize) as u32;
+            config.cmd_copy_rows(command_buffer, upload, rows);
+            budget = budget.saturating_sub(rows as DeviceSize * row_size);
+            if upload.is_done() {
+                let upload = self.uploads.pop_front().unwrap();
+                let barrier = texture_barrier(&upload.texture);
+                let barrier = match self.transfer {
+                    Some(_) => barrier.release(
+                        config.upload_family(),
+                        config.queue_family_indices.unwrap().graphics_queue.unwrap(),
+                    ),
+                    None => barrier,
+                };
+                config.cmd_image_barrier(command_buffer, barrier);
+                self.retired[current_frame].push(upload.staging);
+                finished.push((upload.path, upload.color_space, upload.texture));
+            }
+        }
+
+        unsafe {
+            device
+                .end_command_buffer(command_buffer)
+                .vk_expect("vkEndCommandBuffer", detail)
+        };
+        let Some(transfer) = &mut self.transfer else {
+            let command_buffers = [command_buffer];
+            let submit_info = [SubmitInfo::default().command_buffers(&command_buffers)];
+            unsafe {
+                device
+                    .queue_submit(config.graphics_queue.unwrap(), &submit_info, fence)
+                    .vk_expect("vkQueueSubmit", detail);
+            }
+            ready.extend(finished);
+            return ready;
+        };
+        let copied = transfer.values.copy();
+        let copied = transfer.point(copied);
+        config.submit_timeline(
+            config.transfer_queue.unwrap(),
+            command_buffer,
+            None,
+            copied,
+            fence,
+        );
+        if !finished.is_empty() {
+            transfer.acquire(config, current_frame, copied, finished);
+        }
+        ready
+    }
+
+    // Only once the device is idle. Decoding threads finish the image they
+    // are on and skip the rest of the queue
+    fn shutdown(mut self, device: &Device, config: &Configuration) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        if !self.uploads.is_empty() {
+            debug!(
+                target: logging::UPLOAD,
+                "Dropping {} unfinished texture uploads",
+                self.uploads.len()
+            );
+        }
+        self.uploads.clear();
+        self.retired.clear();
+        unsafe {
+            self.fences
+                .iter()
+                .for_each(|fence| device.destroy_fence(*fence, None));
+        }
+        match self.transfer.take() {
+            // Freed with the pool
+            Some(transfer) => transfer.destroy(device, config),
+            None => unsafe {
+                device.free_command_buffers(config.command_pool.unwrap(), &self.command_buffers)
+            },
+        }
+    }
+}
+
+impl Configuration {
+    // Decodes and uploads `path` in the background. Objects are drawn with a
+    // checkerboard until it is done and then switch over to it
+    pub fn load_texture_async(&mut self, path: &Path) -> Result<(), Error> {
+        let color_space = TextureSlot::Albedo.color_space();
+        if let Some(texture) = self.asset_cache.texture(path, color_space) {
+            self.config.texture_path = path.to_path_buf();
+            self.bind_texture(texture);
+            return Ok(());
+        }
+        if self.fallback_texture.is_none() {
+            let fallback = self.upload_decoded(&DecodedImage::checkerboard())?;
+            self.fallback_texture = Some(Arc::new(fallback));
+        }
+        if self.texture_streamer.is_none() {
+            self.texture_streamer = Some(TextureStreamer::new(self));
+        }
+        self.bind_texture(self.fallback_texture.clone().unwrap());
+        self.texture_streamer
+            .as_ref()
+            .unwrap()
+            .request(path, color_space);
+        Ok(())
+    }
+
+    // Streams the bound texture in again once its file changed on disk. The
+    // old image stays bound until the new one is complete
+    fn hot_reload_texture(&mut self) {
+        if !self.config.hot_reload {
+            return;
+        }
+        let path = self.config.texture_path.clone();
+        for path in self.texture_watcher.poll(&[&path]) {
+            self.notify(
+                Level::Info,
+                format!("{} changed, reloading it", path.display()),
+            );
+            if self.texture_streamer.is_none() {
+                self.texture_streamer = Some(TextureStreamer::new(self));
+            }
+            self.texture_streamer
+                .as_ref()
+                .unwrap()
+                .request(&path, TextureSlot::Albedo.color_space());
+        }
+    }
+
+    pub fn pending_texture_uploads(&self) -> usize {
+        self.texture_streamer.as_ref().map_or(0, |streamer| {
+            streamer.uploads.len()
+                + streamer
+                    .transfer
+                    .as_ref()
+                    .map_or(0, |transfer| transfer.values.in_flight.len())
+        })
+    }
+
+    // Called once the fences of `current_frame` have been waited on, before
+    // any of its command buffers are submitted
+    pub fn stream_textures(&mut self, current_frame: usize) {
+        self.hot_reload_texture();
+        if let Some(mut streamer) = self.texture_streamer.take() {
+            span!("stream_textures");
+            for decoded in streamer.decoded.try_iter().collect::<Vec<Decoded>>() {
+                match decoded.image.map_err(Error::from).and_then(|image| {
+                    self.begin_upload(decoded.path.clone(), decoded.color_space, image)
+                }) {
+                    Ok(upload) => streamer.uploads.push_back(upload),
+                    Err(err) => self.notify(
+                        Level::Warn,
+                        format!("Failed to stream {}: {err}", decoded.path.display()),
+                    ),
+                }
+            }
+            let finished = streamer.submit_uploads(self, current_frame);
+            self.texture_streamer = Some(streamer);
+            for (path, color_space, texture) in finished {
+                info!(target: logging::UPLOAD, "Streamed in {}", path.display());
+                let texture = Arc::new(texture);
+                self.asset_cache
+                    .insert_texture(&path, color_space, &texture);
+                self.config.texture_path = path;
+                self.bind_texture(texture);
+            }
+        }
+        if std::mem::take(&mut self.stale_texture_descriptors[current_frame]) {
+            self.write_texture_descriptor(current_frame);
+        }
+    }
+
+    fn begin_upload(
+        &self,
+        path: PathBuf,
+        color_space: ColorSpaceHint,
+        image: DecodedImage,
+    ) -> Result<PendingUpload, Error> {
+        if image.width == 0 || image.height == 0 {
+            return Err(anyhow!("the image is empty"));
+        }
+        let mut staging = GpuBuffer::host_visible(
+            &self.gpu_context(),
+            image.data.len(),
+            BufferUsageFlags::TRANSFER_SRC,
+        )?;
+        staging.write(&image.data);
+        let texture = self.create_texture_resource(
+            image.texture(),
+            image.format,
+            image.data.len() as DeviceSize,
+        );
+        Ok(PendingUpload {
+            path,
+            color_space,
+            texture,
+            staging,
+            format: image.format,
+            levels: image.levels,
+            level: 0,
+            rows_copied: 0,
+        })
+    }
+
+    // Copies `rows` block rows of the current mip level and moves on to the
+    // next level once this one is complete
+    fn cmd_copy_rows(&self, command_buffer: CommandBuffer, upload: &mut PendingUpload, rows: u32) {
+        if upload.level == 0 && upload.rows_copied == 0 {
+            self.cmd_texture_transition(
+                command_buffer,
+                &upload.texture,
+                ImageLayout::UNDEFINED,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+        }
+        let level = upload.levels[upload.level];
+        let (block, _) = block_layout(upload.format);
+        let y = upload.rows_copied * block;
+        let region = BufferImageCopy::default()
+            .buffer_offset(
+                level.offset as DeviceSize + upload.rows_copied as DeviceSize * upload.row_size(),
+            )
+            .image_subresource(
+                ImageSubresourceLayers::default()
+                    .aspect_mask(ImageAspectFlags::COLOR)
+                    .mip_level(upload.level as u32)
+                    .base_array_layer(0)
+                    .layer_count(1),
+            )
+            .image_offset(Offset3D::default().y(y as i32))
+            .image_extent(
+                Extent3D::default()
+                    .width(level.width)
+                    .height((rows * block).min(level.height - y))
+                    .depth(1),
+            );
+        unsafe {
+            self.device.as_ref().unwrap().cmd_copy_buffer_to_image(
+                command_buffer,
+                upload.staging.buffer(),
+                upload.texture.image,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            )
+        };
+        upload.rows_copied += rows;
+        if upload.rows_left() == 0 {
+            upload.level += 1;
+            upload.rows_copied = 0;
+        }
+    }
+
+    fn cmd_texture_transition(
+        &self,
+        command_buffer: CommandBuffer,
+        texture: &TextureResource,
+        old_layout: ImageLayout,
+        new_layout: ImageLayout,
+    ) {
+        let range = subresource_range(ImageAspectFlags::COLOR, 0, REMAINING_MIP_LEVELS);
+        let barrier =
+            ImageBarrier::transition(texture.image, old_layout, new_layout, range).unwrap();
+        self.cmd_image_barrier(command_buffer, barrier);
+    }
+
+    // Frames in flight still sample the previous texture, so each slot's
+    // descriptor set is only rewritten once its fence has been waited on
+    fn bind_texture(&mut self, texture: Arc<TextureResource>) {
+        self.texture = Some(texture);
+        self.stale_texture_descriptors = [true; MAX_FLIGHT_FENCES as usize];
+    }
+
+    fn write_texture_descriptor(&self, frame: usize) {
+        let image_info = [DescriptorImageInfo::default()
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(self.texture.as_ref().unwrap().view())
+            .sampler(self.texture_sampler)];
+        let write = [WriteDescriptorSet::default()
+            .dst_set(self.descriptor_sets[frame])
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info)];
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .update_descriptor_sets(&write, &[])
+        };
+    }
+
+    pub(super) fn destroy_texture_streamer(&mut self) {
+        if let Some(streamer) = self.texture_streamer.take() {
+            streamer.shutdown(self.device.as_ref().unwrap(), self);
+        }
+        self.fallback_texture = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{DeviceMemory, Handle, Image, ImageView};
+
+    use super::*;
+    use crate::engine::{
+        configuration::deletion_queue::{DeletionQueue, PendingDeletion},
+        rng::{Rng, DEFAULT_SEED},
+    };
+
+    // Frames a copy takes on the transfer queue, and the acquire after it on
+    // the graphics queue
+    const COPY_FRAMES: usize = 2;
+    const ACQUIRE_FRAMES: usize = 1;
+    // Frames from spawning an object until its texture replaces the
+    // placeholder
+    const MAX_LATENCY: usize = 6;
+    // Frames an object stays before it is despawned
+    const LIFETIME: usize = 30;
+
+    fn texture_image(object: usize) -> Image {
+        Image::from_raw(object as u64 + 1)
+    }
+
+    // An object with a freshly streamed texture spawns every frame and
+    // despawns LIFETIME frames later. Copies keep to the budget, the host
+    // only ever waits for timeline values that are already done, every
+    // object is textured soon after it spawned and its texture is freed once
+    // the frames drawing it are done
+    #[test]
+    fn objects_spawn_and_despawn_every_frame_for_a_thousand_frames() {
+        const FRAMES: usize = 1000;
+        let frames_in_flight = MAX_FLIGHT_FENCES as usize;
+        let deletion_queue = DeletionQueue::default();
+        let mut freed_at = vec![None; FRAMES];
+        let mut rng = Rng::new(DEFAULT_SEED).fork("streaming");
+        let mut timeline = UploadTimeline::new(frames_in_flight);
+        // Signaled values with the frame the GPU is done with them
+        let mut signaled = VecDeque::<(u64, usize)>::new();
+        let mut reached = 0;
+        // Spawned objects and the bytes of their texture still to copy
+        let mut uploads = VecDeque::<(usize, DeviceSize)>::new();
+        let mut textured_at = vec![None; FRAMES];
+
+        let mut frame = 0;
+        while frame < FRAMES + LIFETIME + frames_in_flight {
+            let current_frame = frame % frames_in_flight;
+            while signaled.front().is_some_and(|(_, done)| *done <= frame) {
+                reached = signaled.pop_front().unwrap().0;
+            }
+            deletion_queue.flush_with(current_frame, frames_in_flight, |deletion| {
+                let PendingDeletion::Image { image, .. } = deletion else {
+                    unreachable!();
+                };
+                freed_at[image.as_raw() as usize - 1] = Some(frame);
+            });
+            for object in timeline.take_ready(reached) {
+                textured_at[object] = Some(frame);
+            }
+            if let Some(object) = frame
+                .checked_sub(LIFETIME)
+                .filter(|object| *object < FRAMES)
+            {
+                assert!(
+                    textured_at[object].is_some(),
+                    "object {object} never textured"
+                );
+                deletion_queue.push(PendingDeletion::Image {
+                    image: texture_image(object),
+                    memory: DeviceMemory::null(),
+                    view: ImageView::null(),
+                });
+            }
+            if frame < FRAMES {
+                let budget = STREAMING_BUDGET as u32;
+                let size = rng.range_u32(budget / 8..budget);
+                uploads.push_back((frame, size as DeviceSize));
+            }
+
+            let mut budget = STREAMING_BUDGET;
+            let mut finished = Vec::new();
+            while let Some((object, left)) = uploads.front_mut().filter(|_| budget > 0) {
+                let copied = (*left).min(budget);
+                (*left, budget) = (*left - copied, budget - copied);
+                if *left == 0 {
+                    finished.push(*object);
+                    uploads.pop_front();
+                }
+            }
+            if budget < STREAMING_BUDGET {
+                let copied = timeline.copy();
+                signaled.push_back((copied, frame + COPY_FRAMES));
+                if !finished.is_empty() {
+                    assert!(
+                        timeline.slot_done_at(current_frame) <= reached,
+                        "frame {frame} waits for the acquire of frame {}",
+                        frame - frames_in_flight
+                    );
+                    let acquired = timeline.acquire(current_frame, copied, finished);
+                    signaled.push_back((acquired, frame + COPY_FRAMES + ACQUIRE_FRAMES));
+                }
+            }
+            frame += 1;
+        }
+
+        let textured_at = textured_at
+            .into_iter()
+            .map(Option::unwrap)
+            .collect::<Vec<usize>>();
+        for (object, frame) in textured_at.iter().enumerate() {
+            assert!(*frame >= object + COPY_FRAMES + ACQUIRE_FRAMES);
+            assert!(
+                frame - object <= MAX_LATENCY,
+                "object {object} textured at {frame}"
+            );
+        }
+        assert!(textured_at.is_sorted());
+        // Freed when the slot of the frame it was despawned in comes around
+        // after the next flush
+        for (object, freed_at) in freed_at.into_iter().enumerate() {
+            let despawned_at = object + LIFETIME;
+            assert_eq!(freed_at, Some(despawned_at + frames_in_flight.max(2)));
+        }
+    }
+}
@@ -1,147 +1,563 @@
 use std::{
-    borrow::BorrowMut,
-    fs::File,
-    io::{Error, ErrorKind},
+    io::{Cursor, Error},
+    path::Path,
+    sync::Arc,
 };
 
-use anyhow::anyhow;
-use ash::{
-    vk::{
-        self, AccessFlags, BorderColor, Buffer, BufferImageCopy, BufferMemoryBarrier,
-        BufferUsageFlags, CommandBuffer, CommandPool, CompareOp, DependencyFlags, DeviceMemory,
-        DeviceSize, Extent3D, Filter, Format, Image, ImageAspectFlags, ImageCreateFlags,
-        ImageCreateInfo, ImageLayout, ImageMemoryBarrier, ImageSubresourceLayers,
-        ImageSubresourceRange, ImageTiling, ImageType, ImageUsageFlags, ImageView,
-        ImageViewCreateInfo, ImageViewType, MemoryAllocateInfo, MemoryBarrier, MemoryMapFlags,
-        MemoryPropertyFlags, Offset3D, PhysicalDevice, PipelineStageFlags, Queue,
-        QueueFamilyProperties, QueueFlags, SampleCountFlags, SamplerAddressMode, SamplerCreateInfo,
-        SamplerMipmapMode, SharingMode, QUEUE_FAMILY_IGNORED,
-    },
-    Device, Instance,
+use ash::vk::{
+    BorderColor, BufferImageCopy, BufferUsageFlags, CompareOp, DeviceSize, Extent3D, Filter,
+    Format, FormatFeatureFlags, ImageAspectFlags, ImageLayout, ImageSubresourceLayers, ImageTiling,
+    ImageUsageFlags, MemoryPropertyFlags, Offset3D, SamplerAddressMode, SamplerCreateInfo,
+    SamplerMipmapMode, LOD_CLAMP_NONE,
 };
 use log::{debug, info, warn};
 use png::BitDepth;
 
-use crate::engine::configuration::QueueFamilyIndices;
+use crate::{
+    engine::{
+        error::VkContext,
+        texture_atlas::{AtlasUpdate, TextureAtlas},
+        textures::{self, TextureKind},
+    },
+    logging::{self, span},
+    utils::{self, embedded::RgbaImage},
+};
 
-use super::Configuration;
+use super::{
+    asset_cache::TextureResource, bc, buffer_types::gpu_buffer::GpuBuffer, ktx,
+    synchronization::subresource_range, Configuration,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Texture {
     width: u32,
     height: u32,
-    channels: u32,
     depth: BitDepth,
+    mip_levels: u32,
 }
 
 impl Texture {
-    pub fn new(width: u32, height: u32, channels: u32, depth: u8) -> Texture {
+    pub fn new(width: u32, height: u32, depth: u8) -> Texture {
         Self {
             width,
             height,
-            channels,
             depth: match BitDepth::from_u8(depth) {
                 Some(depth) => depth,
                 None => BitDepth::One,
             },
+            mip_levels: 1,
         }
     }
+
+    pub fn with_mip_levels(mut self, mip_levels: u32) -> Texture {
+        self.mip_levels = mip_levels;
+        self
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
 }
 
-impl Into<Extent3D> for Texture {
-    fn into(self) -> Extent3D {
+impl From<Texture> for Extent3D {
+    fn from(texture: Texture) -> Self {
         Extent3D::default()
-            .depth(self.depth as u32)
-            .height(self.height)
-            .width(self.width)
+            .depth(texture.depth as u32)
+            .height(texture.height)
+            .width(texture.width)
     }
 }
 
-impl Configuration {
-    pub fn create_texture_image(&mut self) -> Result<&mut Configuration, Error> {
-        let device = self.device.as_ref().unwrap();
-        let image = png::Decoder::new(match File::open("src/resources/viking_room.png") {
-            Ok(file) => file,
-            Err(err) => {
-                return Err(err);
+// Edge of a texel block in pixels and its size in bytes
+pub(super) fn block_layout(format: Format) -> (u32, u32) {
+    match format {
+        Format::BC1_RGB_UNORM_BLOCK
+        | Format::BC1_RGB_SRGB_BLOCK
+        | Format::BC1_RGBA_UNORM_BLOCK
+        | Format::BC1_RGBA_SRGB_BLOCK => (4, 8),
+        Format::BC3_UNORM_BLOCK
+        | Format::BC3_SRGB_BLOCK
+        | Format::BC5_UNORM_BLOCK
+        | Format::BC7_UNORM_BLOCK
+        | Format::BC7_SRGB_BLOCK => (4, 16),
+        _ => (1, 4),
+    }
+}
+
+pub(super) fn is_block_compressed(format: Format) -> bool {
+    block_layout(format).0 > 1
+}
+
+// How texel values are meant to be read. Colors are stored sRGB encoded,
+// normals, roughness and other data are linear
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColorSpaceHint {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl ColorSpaceHint {
+    // The variant of `format` read in this color space. Formats without an
+    // sRGB variant are returned as they are
+    pub(super) fn apply(self, format: Format) -> Format {
+        let (srgb, linear) = match format {
+            Format::R8G8B8A8_SRGB | Format::R8G8B8A8_UNORM => {
+                (Format::R8G8B8A8_SRGB, Format::R8G8B8A8_UNORM)
             }
-        });
+            Format::BC1_RGB_SRGB_BLOCK | Format::BC1_RGB_UNORM_BLOCK => {
+                (Format::BC1_RGB_SRGB_BLOCK, Format::BC1_RGB_UNORM_BLOCK)
+            }
+            Format::BC1_RGBA_SRGB_BLOCK | Format::BC1_RGBA_UNORM_BLOCK => {
+                (Format::BC1_RGBA_SRGB_BLOCK, Format::BC1_RGBA_UNORM_BLOCK)
+            }
+            Format::BC3_SRGB_BLOCK | Format::BC3_UNORM_BLOCK => {
+                (Format::BC3_SRGB_BLOCK, Format::BC3_UNORM_BLOCK)
+            }
+            Format::BC7_SRGB_BLOCK | Format::BC7_UNORM_BLOCK => {
+                (Format::BC7_SRGB_BLOCK, Format::BC7_UNORM_BLOCK)
+            }
+            _ => return format,
+        };
+        match self {
+            ColorSpaceHint::Srgb => srgb,
+            ColorSpaceHint::Linear => linear,
+        }
+    }
+
+    fn other(self) -> ColorSpaceHint {
+        match self {
+            ColorSpaceHint::Srgb => ColorSpaceHint::Linear,
+            ColorSpaceHint::Linear => ColorSpaceHint::Srgb,
+        }
+    }
+}
+
+// Material slots a texture can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureSlot {
+    Albedo,
+    Normal,
+    OcclusionRoughnessMetalness,
+    Emissive,
+}
+
+impl TextureSlot {
+    pub fn color_space(self) -> ColorSpaceHint {
+        match self {
+            TextureSlot::Albedo | TextureSlot::Emissive => ColorSpaceHint::Srgb,
+            TextureSlot::Normal | TextureSlot::OcclusionRoughnessMetalness => {
+                ColorSpaceHint::Linear
+            }
+        }
+    }
+}
+
+// Where one mip level sits in `DecodedImage::data`
+#[derive(Debug, Clone, Copy)]
+pub(super) struct MipLevel {
+    pub offset: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl MipLevel {
+    // Partial blocks at the edges still take up a whole block
+    pub fn block_rows(&self, format: Format) -> u32 {
+        self.height.div_ceil(block_layout(format).0)
+    }
+
+    pub fn row_size(&self, format: Format) -> usize {
+        let (block, block_size) = block_layout(format);
+        (self.width.div_ceil(block) * block_size) as usize
+    }
+
+    pub fn size(&self, format: Format) -> usize {
+        self.block_rows(format) as usize * self.row_size(format)
+    }
+}
+
+// A decoded texture with all of its mip levels, not yet on the GPU
+pub(super) struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub format: Format,
+    // Every mip level back to back, largest first
+    pub data: Vec<u8>,
+    pub levels: Vec<MipLevel>,
+}
+
+impl DecodedImage {
+    // `read` followed by `select_format`. Safe to call from any thread, it
+    // doesn't touch the device
+    pub fn load(
+        path: &Path,
+        color_space: ColorSpaceHint,
+        supported_formats: &[Format],
+    ) -> Result<DecodedImage, Error> {
+        Self::read(path)?.select_format(path, color_space, supported_formats)
+    }
+
+    // Picks the decoder from the extension, the format is the file's own
+    pub fn read(path: &Path) -> Result<DecodedImage, Error> {
+        let is_ktx2 = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ktx2"));
+        if is_ktx2 {
+            ktx::decode_ktx2(&utils::io::read_asset(path)?)
+        } else {
+            Self::decode_png(path)
+        }
+    }
+
+    // Picks the format variant from `color_space`. A format missing from
+    // `supported_formats` falls back to the other color space, then to
+    // decompressing into RGBA8
+    pub fn select_format(
+        self,
+        path: &Path,
+        color_space: ColorSpaceHint,
+        supported_formats: &[Format],
+    ) -> Result<DecodedImage, Error> {
+        let mut image = self;
+        image.format = color_space.apply(image.format);
+        if supported_formats.contains(&image.format) {
+            return Ok(image);
+        }
+        let fallback = color_space.other().apply(image.format);
+        if fallback != image.format && supported_formats.contains(&fallback) {
+            warn!(
+                target: logging::UPLOAD,
+                "{:?} can't be sampled, reading {} as {fallback:?} instead",
+                image.format,
+                path.display()
+            );
+            image.format = fallback;
+            return Ok(image);
+        }
+        if is_block_compressed(image.format) {
+            debug!(
+                target: logging::UPLOAD,
+                "{:?} is not supported by the device, decompressing {}",
+                image.format,
+                path.display()
+            );
+            return bc::decompress(&image);
+        }
+        Ok(image)
+    }
+
+    pub fn decode_png(path: &Path) -> Result<DecodedImage, Error> {
+        let image = png::Decoder::new(Cursor::new(utils::io::read_asset(path)?));
         let mut read_info = image.read_info()?;
-        let (tex_width, tex_height) = read_info.info().size();
+        let (width, height) = read_info.info().size();
         let mut pixels = vec![0; read_info.info().raw_bytes()];
         read_info.next_frame(&mut pixels)?;
-        let texture = Texture::new(tex_width, tex_height, 0, 1);
-        let buffer_size = vec![read_info.info().raw_bytes() as u64];
-        let mut staging_buffer_memory: DeviceMemory = DeviceMemory::null();
-        let staging_buffer = Self::allocate_buffer(
-            self.instance.as_ref().unwrap(),
-            self.physical_device.unwrap(),
-            device,
-            buffer_size[0],
+        Ok(Self::rgba(width, height, pixels))
+    }
+
+    // Shown in place of textures that are still streaming in
+    pub fn checkerboard() -> DecodedImage {
+        let image = textures::generate(TextureKind::Checker { cells: 8 }, 8);
+        Self::rgba(image.width, image.height, image.pixels)
+    }
+
+    // Adds the full mip chain to a single level RGBA8 image. Odd sizes round
+    // down, the last row and column are folded into their neighbours
+    pub fn with_mip_chain(mut self) -> DecodedImage {
+        let (mut width, mut height) = (self.width, self.height);
+        while width > 1 || height > 1 {
+            let source = *self.levels.last().unwrap();
+            let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+            let offset = self.data.len();
+            for y in 0..next_height {
+                for x in 0..next_width {
+                    // Up to 3x3 source pixels so nothing is skipped at odd
+                    // edges
+                    let xs = 2 * x..(2 * x + 2 + (x + 1 == next_width) as u32).min(width);
+                    let ys = 2 * y..(2 * y + 2 + (y + 1 == next_height) as u32).min(height);
+                    let mut sum = [0u32; 4];
+                    let mut count = 0;
+                    for sy in ys {
+                        for sx in xs.clone() {
+                            let start = source.offset + ((sy * width + sx) * 4) as usize;
+                            for (channel, value) in sum.iter_mut().zip(&self.data[start..start + 4])
+                            {
+                                *channel += *value as u32;
+                            }
+                            count += 1;
+                        }
+                    }
+                    self.data
+                        .extend(sum.map(|channel| ((channel + count / 2) / count) as u8));
+                }
+            }
+            self.levels.push(MipLevel {
+                offset,
+                width: next_width,
+                height: next_height,
+            });
+            (width, height) = (next_width, next_height);
+        }
+        self
+    }
+
+    fn rgba(width: u32, height: u32, pixels: Vec<u8>) -> DecodedImage {
+        DecodedImage {
+            width,
+            height,
+            format: Format::R8G8B8A8_SRGB,
+            data: pixels,
+            levels: vec![MipLevel {
+                offset: 0,
+                width,
+                height,
+            }],
+        }
+    }
+
+    pub fn texture(&self) -> Texture {
+        Texture::new(self.width, self.height, 1).with_mip_levels(self.levels.len() as u32)
+    }
+}
+
+impl Configuration {
+    // Decodes the PNG or KTX2 file at `path` and uploads it into a sampled
+    // image
+    pub(super) fn upload_texture(
+        &self,
+        path: &Path,
+        color_space: ColorSpaceHint,
+    ) -> Result<TextureResource, Error> {
+        span!("upload_texture");
+        let supported_formats = self.supported_texture_formats();
+        self.upload_decoded(&DecodedImage::load(path, color_space, &supported_formats)?)
+    }
+
+    // Uploads pixels generated or decoded by the application with their full
+    // mip chain
+    pub(super) fn upload_rgba(
+        &self,
+        image: &RgbaImage,
+        color_space: ColorSpaceHint,
+    ) -> Result<TextureResource, Error> {
+        let mut decoded = DecodedImage::rgba(image.width, image.height, image.pixels.clone());
+        decoded.format = color_space.apply(decoded.format);
+        self.upload_decoded(&decoded.with_mip_chain())
+    }
+
+    // Uploads what changed in `atlas` since the last call into `texture`, or
+    // into a new texture when there is none yet or the atlas grew. Without
+    // mips, an entry's smaller levels would bleed into its neighbours
+    pub fn upload_atlas(
+        &self,
+        atlas: &mut TextureAtlas,
+        color_space: ColorSpaceHint,
+        texture: Option<Arc<TextureResource>>,
+    ) -> Result<Arc<TextureResource>, Error> {
+        let (regions, texture) = match (atlas.take_update(), texture) {
+            (AtlasUpdate::Unchanged, Some(texture)) => return Ok(texture),
+            (AtlasUpdate::Regions(regions), Some(texture)) => (regions, texture),
+            _ => {
+                let [width, height] = atlas.size();
+                let mut decoded = DecodedImage::rgba(width, height, atlas.pixels().to_vec());
+                decoded.format = color_space.apply(decoded.format);
+                return Ok(Arc::new(self.upload_decoded(&decoded)?));
+            }
+        };
+
+        let mut data = Vec::new();
+        let copies = regions
+            .iter()
+            .map(|rect| {
+                let offset = data.len() as DeviceSize;
+                data.extend(atlas.region_pixels(*rect));
+                BufferImageCopy::default()
+                    .buffer_offset(offset)
+                    .image_subresource(
+                        ImageSubresourceLayers::default()
+                            .aspect_mask(ImageAspectFlags::COLOR)
+                            .mip_level(0)
+                            .base_array_layer(0)
+                            .layer_count(1),
+                    )
+                    .image_offset(Offset3D {
+                        x: rect.x as i32,
+                        y: rect.y as i32,
+                        z: 0,
+                    })
+                    .image_extent(Extent3D {
+                        width: rect.width,
+                        height: rect.height,
+                        depth: 1,
+                    })
+            })
+            .collect::<Vec<BufferImageCopy>>();
+        let mut staging_buffer = GpuBuffer::host_visible(
+            &self.gpu_context(),
+            data.len(),
             BufferUsageFlags::TRANSFER_SRC,
-            MemoryPropertyFlags::HOST_COHERENT | MemoryPropertyFlags::HOST_VISIBLE,
-            &mut staging_buffer_memory,
+        )
+        .map_err(Error::other)?;
+        staging_buffer.write(&data);
+
+        let range = subresource_range(ImageAspectFlags::COLOR, 0, 1);
+        self.transition_image_layout(
+            texture.image,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            range,
+            None,
+        )
+        .map_err(Error::other)?;
+        self.copy_buffer_to_image(staging_buffer.buffer(), texture.image, &copies);
+        self.transition_image_layout(
+            texture.image,
+            ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            range,
+            None,
+        )
+        .map_err(Error::other)?;
+        debug!(
+            target: logging::UPLOAD,
+            "Atlas regions uploaded ({} regions, {} bytes)",
+            copies.len(),
+            data.len()
         );
+        Ok(texture)
+    }
 
-        unsafe {
-            let data = device
-                .map_memory(
-                    staging_buffer_memory,
-                    0,
-                    buffer_size[0],
-                    MemoryMapFlags::empty(),
-                )
-                .unwrap();
-            std::ptr::copy_nonoverlapping(pixels.as_ptr(), data.cast(), pixels.len());
-            device.unmap_memory(staging_buffer_memory);
-        }
+    // The texture formats the device can sample from
+    pub(super) fn supported_texture_formats(&self) -> Vec<Format> {
+        let instance = self.instance.as_ref().unwrap();
+        let required =
+            FormatFeatureFlags::SAMPLED_IMAGE | FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        // Some portability drivers list BC formats without the feature
+        let compressed = if self.platform_quirks.supports_bc {
+            &ktx::COMPRESSED_FORMATS[..]
+        } else {
+            &[]
+        };
+        compressed
+            .iter()
+            .copied()
+            .chain(ktx::RGBA_FORMATS)
+            .filter(|format| {
+                let properties = unsafe {
+                    instance.get_physical_device_format_properties(
+                        self.physical_device.unwrap(),
+                        *format,
+                    )
+                };
+                properties.optimal_tiling_features.contains(required)
+            })
+            .collect()
+    }
 
-        let (image, image_memory) = self
+    // Creates the image and its view, left in UNDEFINED layout
+    pub(super) fn create_texture_resource(
+        &self,
+        texture: Texture,
+        format: Format,
+        size: DeviceSize,
+    ) -> TextureResource {
+        let (image, memory) = self
             .create_image(
                 texture,
-                Format::R8G8B8A8_SRGB,
+                format,
                 ImageTiling::OPTIMAL,
                 ImageUsageFlags::TRANSFER_DST | ImageUsageFlags::SAMPLED,
                 MemoryPropertyFlags::DEVICE_LOCAL,
             )
             .unwrap();
+        let view = self
+            .create_image_view(&image, format, ImageAspectFlags::COLOR)
+            .unwrap();
+        TextureResource {
+            image,
+            memory,
+            view,
+            size,
+            deletion_queue: self.deletion_queue.clone(),
+        }
+    }
 
-        self.texture_image = image;
-        self.texture_image_memory = image_memory;
+    // Uploads every mip level in one go and waits for it
+    pub(super) fn upload_decoded(&self, decoded: &DecodedImage) -> Result<TextureResource, Error> {
+        let data = &decoded.data;
+        let mut staging_buffer = GpuBuffer::host_visible(
+            &self.gpu_context(),
+            data.len(),
+            BufferUsageFlags::TRANSFER_SRC,
+        )
+        .map_err(Error::other)?;
+        staging_buffer.write(data);
+
+        let resource = self.create_texture_resource(
+            decoded.texture(),
+            decoded.format,
+            data.len() as DeviceSize,
+        );
+        let image = resource.image;
 
+        let range = subresource_range(ImageAspectFlags::COLOR, 0, decoded.levels.len() as u32);
         self.transition_image_layout(
             image,
-            Format::R8G8B8A8_SRGB,
             ImageLayout::UNDEFINED,
             ImageLayout::TRANSFER_DST_OPTIMAL,
+            range,
+            None,
         )
         .unwrap();
-        self.copy_buffer_to_image(staging_buffer, image, texture);
+        let regions = decoded
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(mip_level, level)| {
+                BufferImageCopy::default()
+                    .buffer_offset(level.offset as DeviceSize)
+                    .image_subresource(
+                        ImageSubresourceLayers::default()
+                            .aspect_mask(ImageAspectFlags::COLOR)
+                            .mip_level(mip_level as u32)
+                            .base_array_layer(0)
+                            .layer_count(1),
+                    )
+                    .image_extent(
+                        Extent3D::default()
+                            .width(level.width)
+                            .height(level.height)
+                            .depth(1),
+                    )
+            })
+            .collect::<Vec<BufferImageCopy>>();
+        self.copy_buffer_to_image(staging_buffer.buffer(), image, &regions);
         self.transition_image_layout(
             image,
-            Format::R8G8B8A8_SRGB,
             ImageLayout::TRANSFER_DST_OPTIMAL,
             ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            range,
+            None,
         )
         .unwrap();
-        unsafe {
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_buffer_memory, None)
-        };
-        info!("Texture Image has been created");
-        Ok(self)
+        info!(
+            target: logging::UPLOAD,
+            "Texture Image has been created ({:?}, {} mip levels, {} bytes)",
+            decoded.format,
+            decoded.levels.len(),
+            data.len()
+        );
+        Ok(resource)
     }
 
-    pub fn create_texture_image_view(&mut self) -> Result<&mut Configuration, ()> {
-        self.texture_image_view = self
-            .clone()
-            .create_image_view(
-                &self.texture_image,
-                Format::R8G8B8A8_SRGB,
-                ImageAspectFlags::COLOR,
-            )
-            .unwrap();
-        debug!("Texture Image View created");
+    pub fn create_texture_image(&mut self) -> Result<&mut Configuration, anyhow::Error> {
+        let path = self.config.texture_path.clone();
+        let color_space = TextureSlot::Albedo.color_space();
+        self.texture = Some(match self.preload.take_texture(&path) {
+            Some(job) => self.insert_decoded_texture(&path, color_space, job.join()?)?,
+            None => self.load_texture(&path, color_space)?,
+        });
         Ok(self)
     }
 
@@ -169,9 +585,13 @@ impl Configuration {
             .mipmap_mode(SamplerMipmapMode::LINEAR)
             .mip_lod_bias(0.0)
             .min_lod(0.0)
-            .max_lod(0.0);
+            .max_lod(LOD_CLAMP_NONE);
 
-        self.texture_sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+        self.texture_sampler = unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .vk_expect("vkCreateSampler", "creating the texture sampler")
+        };
         debug!("Texture Sampler created");
         Ok(self)
     }
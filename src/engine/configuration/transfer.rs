@@ -0,0 +1,170 @@
+use anyhow::Error;
+use ash::vk::{
+    CommandBuffer, Fence, PhysicalDeviceFeatures2, PhysicalDeviceTimelineSemaphoreFeatures,
+    PipelineStageFlags, Queue, QueueFamilyProperties, QueueFlags, Semaphore, SemaphoreCreateInfo,
+    SemaphoreType, SemaphoreTypeCreateInfo, SemaphoreWaitInfo, SubmitInfo,
+    TimelineSemaphoreSubmitInfo,
+};
+use log::{debug, info};
+
+use crate::engine::error::VkContext;
+
+use super::Configuration;
+
+// A family that copies without drawing, usually the GPU's DMA engines.
+// Families that can also compute are only taken when there is nothing else.
+// Textures are streamed a few rows at a time, so copies have to be allowed
+// at any texel
+pub(super) fn transfer_family(queue_families: &[QueueFamilyProperties]) -> Option<u32> {
+    let transfer_only = |family: &&QueueFamilyProperties, excluded: QueueFlags| {
+        let granularity = family.min_image_transfer_granularity;
+        family.queue_flags.contains(QueueFlags::TRANSFER)
+            && !family.queue_flags.intersects(excluded)
+            && (granularity.width, granularity.height, granularity.depth) == (1, 1, 1)
+    };
+    [
+        QueueFlags::GRAPHICS | QueueFlags::COMPUTE,
+        QueueFlags::GRAPHICS,
+    ]
+    .into_iter()
+    .find_map(|excluded| {
+        queue_families
+            .iter()
+            .position(|family| transfer_only(&family, excluded))
+    })
+    .map(|index| index as u32)
+}
+
+// A semaphore a queue signals a value on, waited for on another queue or
+// the host
+#[derive(Debug, Clone, Copy)]
+pub(super) struct TimelinePoint {
+    pub semaphore: Semaphore,
+    pub value: u64,
+}
+
+impl Configuration {
+    // Uploads only leave the graphics queue when they can be ordered against
+    // it with a timeline semaphore
+    pub(super) fn supports_transfer_queue(&self) -> bool {
+        if !self.config.transfer_queue {
+            return false;
+        }
+        let queue_family_indices = self.queue_family_indices.unwrap();
+        if queue_family_indices.transfer_queue.is_none() {
+            debug!("No dedicated transfer queue family");
+            return false;
+        }
+        let instance = self.instance.as_ref().unwrap();
+        let physical_device = self.physical_device.unwrap();
+        let Ok(extensions) =
+            (unsafe { instance.enumerate_device_extension_properties(physical_device) })
+        else {
+            return false;
+        };
+        if !extensions.iter().any(|property| {
+            property.extension_name_as_c_str() == Ok(ash::khr::timeline_semaphore::NAME)
+        }) {
+            debug!("VK_KHR_timeline_semaphore is not available");
+            return false;
+        }
+        let properties2 = ash::khr::get_physical_device_properties2::Instance::new(
+            self.vulkan_entry.as_ref().unwrap(),
+            instance,
+        );
+        let mut timeline_features = PhysicalDeviceTimelineSemaphoreFeatures::default();
+        let mut features = PhysicalDeviceFeatures2::default().push_next(&mut timeline_features);
+        unsafe { properties2.get_physical_device_features2(physical_device, &mut features) };
+        timeline_features.timeline_semaphore != 0
+    }
+
+    pub(super) fn enable_transfer_queue(&mut self) {
+        let family = self.queue_family_indices.unwrap().transfer_queue.unwrap();
+        self.timeline_semaphore = Some(ash::khr::timeline_semaphore::Device::new(
+            self.instance.as_ref().unwrap(),
+            self.device.as_ref().unwrap(),
+        ));
+        self.transfer_queue = self.find_device_queue(family);
+        info!("Streaming textures on transfer queue family {family}");
+    }
+
+    // The family uploads are recorded for, the graphics one without a
+    // transfer queue
+    pub(super) fn upload_family(&self) -> u32 {
+        let queue_family_indices = self.queue_family_indices.unwrap();
+        match self.transfer_queue {
+            Some(_) => queue_family_indices.transfer_queue.unwrap(),
+            None => queue_family_indices.graphics_queue.unwrap(),
+        }
+    }
+
+    pub(super) fn create_timeline_semaphore(&self) -> Result<Semaphore, Error> {
+        let mut type_create_info = SemaphoreTypeCreateInfo::default()
+            .semaphore_type(SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let create_info = SemaphoreCreateInfo::default().push_next(&mut type_create_info);
+        Ok(unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .create_semaphore(&create_info, None)
+                .vk_ctx("vkCreateSemaphore", "creating a timeline semaphore")?
+        })
+    }
+
+    // The last value signaled on `semaphore`
+    pub(super) fn timeline_value(&self, semaphore: Semaphore) -> u64 {
+        let timeline = self.timeline_semaphore.as_ref().unwrap();
+        unsafe { timeline.get_semaphore_counter_value(semaphore) }.unwrap_or(0)
+    }
+
+    pub(super) fn wait_timeline(&self, point: TimelinePoint) {
+        let timeline = self.timeline_semaphore.as_ref().unwrap();
+        let semaphores = [point.semaphore];
+        let values = [point.value];
+        let wait_info = SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+        unsafe { timeline.wait_semaphores(&wait_info, u64::MAX) }.vk_expect(
+            "vkWaitSemaphores",
+            format_args!("waiting for timeline value {}", point.value),
+        );
+    }
+
+    // Runs `command_buffer` on `queue` once `wait` has been reached at
+    // `wait_stage` and signals `signal` when done
+    pub(super) fn submit_timeline(
+        &self,
+        queue: Queue,
+        command_buffer: CommandBuffer,
+        wait: Option<(TimelinePoint, PipelineStageFlags)>,
+        signal: TimelinePoint,
+        fence: Fence,
+    ) {
+        let wait_semaphores = wait.map(|(point, _)| point.semaphore);
+        let wait_values = wait.map(|(point, _)| point.value);
+        let wait_stages = wait.map(|(_, stage)| stage);
+        let signal_semaphores = [signal.semaphore];
+        let signal_values = [signal.value];
+        let mut timeline_info = TimelineSemaphoreSubmitInfo::default()
+            .wait_semaphore_values(wait_values.as_slice())
+            .signal_semaphore_values(&signal_values);
+        let command_buffers = [command_buffer];
+        let submit_info = [SubmitInfo::default()
+            .wait_semaphores(wait_semaphores.as_slice())
+            .wait_dst_stage_mask(wait_stages.as_slice())
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores)
+            .push_next(&mut timeline_info)];
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .queue_submit(queue, &submit_info, fence)
+                .vk_expect(
+                    "vkQueueSubmit",
+                    format_args!("submitting timeline value {}", signal.value),
+                )
+        };
+    }
+}
@@ -0,0 +1,316 @@
+use ash::vk::{
+    AccessFlags, AttachmentLoadOp, CommandBuffer, Framebuffer, FramebufferCreateInfo, Image,
+    ImageAspectFlags, ImageLayout, PipelineStageFlags, Rect2D, RenderPassBeginInfo,
+    SubpassContents,
+};
+use log::{debug, info};
+
+use super::{
+    bloom::BloomStage,
+    frame_graph::{Access, FrameGraph, Schedule, Transition},
+    surface_context::SurfaceContext,
+    synchronization::{subresource_range, ImageBarrier},
+    Configuration,
+};
+use crate::{
+    engine::{
+        error::{EngineError, VkContext},
+        viewport::ViewId,
+    },
+    logging,
+};
+
+// What a frame records. The frame graph puts them in order from what each
+// one reads and writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FramePass {
+    // Every region's objects, lit on the deferred path, and the gizmos. Into
+    // the scene target if there is one, the swapchain image otherwise
+    Scene,
+    // Multiplies the occlusion from the depth buffer onto the scene target
+    Ssao,
+    // A stage of the bloom chain and the mip it draws into. The composite
+    // draws the HDR scene target into the swapchain image, upscaling on
+    // the way
+    Bloom(BloomStage, usize),
+    // Blits the scene target over the swapchain image below full scale
+    Upscale,
+    // Screen text and overlay lines, straight onto the swapchain image at
+    // full resolution whatever the render scale
+    Ui,
+    // Copies the finished image out for a requested screenshot
+    Screenshot,
+    // Copies the finished image out for the recording
+    Record,
+}
+
+// What the passes read and write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum FrameResource {
+    // The surface's scene target, with bloom or below full scale
+    SceneColor,
+    // A level of the bloom chain, the first is half the surface size
+    BloomMip(usize),
+    Swapchain,
+    ScreenshotBuffer,
+    RecordBuffer,
+}
+
+impl FrameResource {
+    fn image(self, ctx: &SurfaceContext, image_index: u32) -> Option<Image> {
+        match self {
+            FrameResource::SceneColor => ctx
+                .transients
+                .get(FrameResource::SceneColor)
+                .map(|transient| transient.image),
+            FrameResource::BloomMip(level) => ctx.bloom_chain.as_ref()?.mip_image(level),
+            FrameResource::Swapchain => Some(ctx.image(image_index)),
+            FrameResource::ScreenshotBuffer | FrameResource::RecordBuffer => None,
+        }
+    }
+}
+
+impl Configuration {
+    // Draws on top of whatever the scene passes presented, in the surface
+    // format. Headless frames have no screen text or overlay
+    pub(super) fn create_ui_render_pass(&mut self) -> Result<(), EngineError> {
+        if self.headless() {
+            return Ok(());
+        }
+        self.ui_pass = Some(self.create_color_pass(
+            self.surface_format.unwrap().format,
+            AttachmentLoadOp::LOAD,
+            ImageLayout::PRESENT_SRC_KHR,
+            ImageLayout::PRESENT_SRC_KHR,
+        )?);
+        Ok(())
+    }
+
+    // One per swapchain image, color only
+    pub(super) fn create_surface_ui_framebuffers(&self, ctx: &mut SurfaceContext) {
+        let Some(ui_pass) = self.ui_pass else {
+            return;
+        };
+        let device = self.device.as_ref().unwrap();
+        ctx.swapchain.ui_framebuffers = ctx
+            .swapchain
+            .image_views()
+            .iter()
+            .map(|image_view| {
+                let attachments = [*image_view];
+                let framebuffer_create_info = FramebufferCreateInfo::default()
+                    .attachments(&attachments)
+                    .render_pass(ui_pass)
+                    .width(ctx.extent.width)
+                    .height(ctx.extent.height)
+                    .layers(1);
+                unsafe {
+                    device
+                        .create_framebuffer(&framebuffer_create_info, None)
+                        .vk_expect(
+                            "vkCreateFramebuffer",
+                            format_args!("creating the UI framebuffers for {:?}", ctx.id),
+                        )
+                }
+            })
+            .collect::<Vec<Framebuffer>>();
+        info!(target: logging::SWAPCHAIN, "UI framebuffers created");
+    }
+
+    // The passes of this surface and frame, passes with nothing to do are
+    // left out
+    pub(super) fn frame_passes(
+        &self,
+        surface_index: usize,
+        current_frame: usize,
+    ) -> Schedule<FramePass, FrameResource> {
+        let ctx = &self.surfaces[surface_index];
+        let ui = surface_index == 0
+            && (self.text_pending(current_frame) || self.screen_gizmos_pending(current_frame));
+        self.frame_graph(ctx, ui, self.surface_render_scale(ctx) < 1.0)
+            .compile()
+    }
+
+    // `scaled` draws the scene below full resolution. The scene target is
+    // only used with bloom or then, otherwise the scene pass draws straight
+    // into the swapchain image
+    pub(super) fn frame_graph(
+        &self,
+        ctx: &SurfaceContext,
+        ui: bool,
+        scaled: bool,
+    ) -> FrameGraph<FramePass, FrameResource> {
+        let presented = if self.headless() {
+            ImageLayout::TRANSFER_SRC_OPTIMAL
+        } else {
+            ImageLayout::PRESENT_SRC_KHR
+        };
+        let mut graph = FrameGraph::new();
+        graph.output(FrameResource::Swapchain, presented);
+        let (scene, scene_layout) = match self.scene_target_desc(ctx) {
+            Some((desc, layout)) if self.bloom.is_some() || scaled => {
+                graph.transient(FrameResource::SceneColor, desc);
+                (FrameResource::SceneColor, layout)
+            }
+            _ => (FrameResource::Swapchain, presented),
+        };
+        // The render pass clears the target and leaves it in its final layout
+        graph.add_pass(
+            FramePass::Scene,
+            &[(
+                scene,
+                Access::write(ImageLayout::UNDEFINED).leaving(scene_layout),
+            )],
+        );
+
+        if self.ssao.is_some() {
+            graph.add_pass(
+                FramePass::Ssao,
+                &[(scene, Access::read_write(scene_layout))],
+            );
+        }
+        if scene == FrameResource::SceneColor {
+            if self.bloom.is_some() {
+                self.add_bloom_passes(&mut graph, ctx, scene_layout);
+            } else {
+                // Draws over the whole swapchain image, leaving it ready to
+                // present
+                graph.add_pass(
+                    FramePass::Upscale,
+                    &[
+                        (
+                            FrameResource::Swapchain,
+                            Access::write(ImageLayout::UNDEFINED)
+                                .leaving(ImageLayout::PRESENT_SRC_KHR),
+                        ),
+                        (scene, Access::read(scene_layout)),
+                    ],
+                );
+            }
+        }
+        if self.ui_pass.is_some() && ui {
+            graph.add_pass(
+                FramePass::Ui,
+                &[(
+                    FrameResource::Swapchain,
+                    Access::read_write(ImageLayout::PRESENT_SRC_KHR),
+                )],
+            );
+        }
+        if self.screenshot.is_some() {
+            graph.add_side_effect_pass(
+                FramePass::Screenshot,
+                &[
+                    (FrameResource::Swapchain, Access::read(presented)),
+                    (
+                        FrameResource::ScreenshotBuffer,
+                        Access::write(ImageLayout::UNDEFINED),
+                    ),
+                ],
+            );
+        }
+        if ctx.id == ViewId::PRIMARY && self.recording_wants_frame() {
+            graph.add_side_effect_pass(
+                FramePass::Record,
+                &[
+                    (FrameResource::Swapchain, Access::read(presented)),
+                    (
+                        FrameResource::RecordBuffer,
+                        Access::write(ImageLayout::UNDEFINED),
+                    ),
+                ],
+            );
+        }
+        graph
+    }
+
+    // What the frame graph makes of a surface once its targets exist
+    pub(super) fn log_frame_graph(&self, ctx: &SurfaceContext) {
+        let schedule = self
+            .frame_graph(
+                ctx,
+                self.ui_pass.is_some() && ctx.id == ViewId::PRIMARY,
+                self.surface_render_scale(ctx) < 1.0,
+            )
+            .compile();
+        debug!(
+            target: logging::SWAPCHAIN,
+            "Frame graph for {:?}: {:?}, {} transient images in {} pooled",
+            ctx.id,
+            schedule
+                .passes
+                .iter()
+                .map(|scheduled| scheduled.pass)
+                .collect::<Vec<FramePass>>(),
+            schedule.aliases.len(),
+            schedule.slots.len(),
+        );
+    }
+
+    // Barriers the frame graph asks for that no pass records itself
+    pub(super) fn cmd_frame_transitions(
+        &self,
+        command_buffer: CommandBuffer,
+        ctx: &SurfaceContext,
+        image_index: u32,
+        transitions: &[Transition<FrameResource>],
+    ) {
+        for transition in transitions {
+            let Some(image) = transition.resource.image(ctx, image_index) else {
+                continue;
+            };
+            let mut barrier = ImageBarrier::transition(
+                image,
+                transition.from,
+                transition.to,
+                subresource_range(ImageAspectFlags::COLOR, 0, 1),
+            )
+            .expect("The frame graph asked for an unsupported transition");
+            // Every pass writes its images as color attachments. Without a
+            // layout change only those writes are made visible
+            if transition.written && transition.from == transition.to {
+                barrier.src_stage = PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+                barrier.src_access = AccessFlags::COLOR_ATTACHMENT_WRITE;
+            }
+            self.cmd_image_barrier(command_buffer, barrier);
+        }
+    }
+
+    // The whole window as drawn into the swapchain image, never scaled
+    pub(super) fn ui_region(&self, ctx: &SurfaceContext) -> Rect2D {
+        let window_extent = ctx.window_extent();
+        ctx.rotation
+            .framebuffer_region(&Rect2D::default().extent(window_extent), window_extent)
+    }
+
+    pub(super) fn cmd_ui(
+        &self,
+        command_buffer: CommandBuffer,
+        surface_index: usize,
+        image_index: u32,
+        current_frame: usize,
+    ) {
+        let ctx = &self.surfaces[surface_index];
+        let (Some(ui_pass), Some(framebuffer)) = (
+            self.ui_pass,
+            ctx.swapchain.ui_framebuffers.get(image_index as usize),
+        ) else {
+            return;
+        };
+        let render_pass_begin_info = RenderPassBeginInfo::default()
+            .render_pass(ui_pass)
+            .framebuffer(*framebuffer)
+            .render_area(Rect2D::default().extent(ctx.extent));
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                SubpassContents::INLINE,
+            );
+        }
+        self.cmd_screen_gizmos(command_buffer, surface_index, current_frame);
+        self.cmd_text(command_buffer, surface_index, current_frame);
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+    }
+}
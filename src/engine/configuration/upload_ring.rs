@@ -0,0 +1,385 @@
+use ash::vk::{Buffer, BufferUsageFlags, DescriptorBufferInfo, DeviceAddress, DeviceSize};
+use log::{debug, info, warn};
+
+use super::{
+    buffer_types::{
+        gpu_buffer::{GpuBuffer, GpuBufferError},
+        uniform_buffer_types::grown_capacity,
+    },
+    culling::culling_upload_size,
+    lights::LIGHTS_SIZE,
+    Configuration, MAX_FLIGHT_FENCES, UNIFORM_BUFFER_ENTRIES,
+};
+use crate::logging;
+
+// Bytes every frame in flight can upload besides its uniform entries,
+// lights and culling records, the gizmo lines take most of it
+const UPLOAD_RING_SIZE: DeviceSize = 8 << 20;
+// Ring allocations bound through a descriptor start at most this far past
+// the previous one
+const MAX_DESCRIPTOR_ALIGNMENT: DeviceSize = 256;
+
+// Where an upload landed. `ptr` points at `offset` in the mapped buffer and
+// stays valid until the frame slot comes around again
+#[derive(Debug, Clone, Copy)]
+pub struct UploadAllocation {
+    pub buffer: Buffer,
+    pub offset: DeviceSize,
+    pub size: DeviceSize,
+    pub ptr: *mut u8,
+}
+
+impl UploadAllocation {
+    pub fn range(&self) -> UploadRange {
+        UploadRange {
+            buffer: self.buffer,
+            offset: self.offset,
+            size: self.size,
+        }
+    }
+}
+
+// The part of a frame's ring buffer a descriptor points at, kept until the
+// frame slot's next upload
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UploadRange {
+    pub buffer: Buffer,
+    pub offset: DeviceSize,
+    pub size: DeviceSize,
+}
+
+impl UploadRange {
+    pub fn info(&self) -> DescriptorBufferInfo {
+        DescriptorBufferInfo::default()
+            .buffer(self.buffer)
+            .offset(self.offset)
+            .range(self.size)
+    }
+}
+
+// The bump offset into every frame's buffer, apart from the buffers
+#[derive(Debug, Default)]
+struct RingHeads {
+    heads: Vec<DeviceSize>,
+    // Most bytes one frame has used
+    high_water: DeviceSize,
+}
+
+impl RingHeads {
+    fn new(frames: usize) -> Self {
+        RingHeads {
+            heads: vec![0; frames],
+            high_water: 0,
+        }
+    }
+
+    // Where `size` bytes at a multiple of `alignment` start in the frame's
+    // buffer of `capacity` bytes, None when they don't fit
+    fn bump(
+        &mut self,
+        frame: usize,
+        size: DeviceSize,
+        alignment: DeviceSize,
+        capacity: DeviceSize,
+    ) -> Option<DeviceSize> {
+        let offset = self.heads[frame].next_multiple_of(alignment.max(1));
+        if offset + size > capacity {
+            return None;
+        }
+        self.heads[frame] = offset + size;
+        Some(offset)
+    }
+
+    // Rewinds the frame, returning its bytes when they are a new high
+    // water mark
+    fn reset(&mut self, frame: usize) -> Option<DeviceSize> {
+        let used = std::mem::take(&mut self.heads[frame]);
+        (used > self.high_water).then(|| {
+            self.high_water = used;
+            used
+        })
+    }
+}
+
+// One persistently mapped host visible buffer per frame in flight, bump
+// allocated while the frame is recorded and rewound once its fences have
+// been waited on
+pub struct PerFrameUploadRing {
+    buffers: Vec<GpuBuffer<u8>>,
+    heads: RingHeads,
+    // What ranges bound through a descriptor are aligned to, both the
+    // uniform and the storage buffer offset alignment
+    descriptor_alignment: DeviceSize,
+}
+
+impl PerFrameUploadRing {
+    pub fn new(buffers: Vec<GpuBuffer<u8>>, descriptor_alignment: DeviceSize) -> Self {
+        PerFrameUploadRing {
+            heads: RingHeads::new(buffers.len()),
+            buffers,
+            descriptor_alignment,
+        }
+    }
+
+    pub fn capacity(&self, frame: usize) -> DeviceSize {
+        self.buffers.get(frame).map_or(0, GpuBuffer::size)
+    }
+
+    pub fn descriptor_alignment(&self) -> DeviceSize {
+        self.descriptor_alignment
+    }
+
+    pub fn device_address(&self, frame: usize) -> Option<DeviceAddress> {
+        self.buffers.get(frame)?.device_address()
+    }
+
+    // All of the frame's buffer, what descriptors point at before the first
+    // upload
+    pub fn whole(&self, frame: usize) -> UploadRange {
+        UploadRange {
+            buffer: self.buffers[frame].buffer(),
+            offset: 0,
+            size: self.capacity(frame),
+        }
+    }
+
+    // Only once the fences of `frame` have been waited on
+    pub fn reset(&mut self, frame: usize) {
+        if let Some(used) = self.heads.reset(frame) {
+            if used > self.capacity(frame) / 2 {
+                info!(
+                    "Upload ring high water mark {used} of {} bytes",
+                    self.capacity(frame)
+                );
+            }
+        }
+    }
+
+    // Swaps the frame's buffer for `buffer`. Only right after `reset`,
+    // nothing may have been allocated from the old one this frame
+    pub fn replace(&mut self, frame: usize, buffer: GpuBuffer<u8>) {
+        debug_assert_eq!(self.heads.heads[frame], 0);
+        self.buffers[frame] = buffer;
+    }
+
+    // `size` bytes at a multiple of `alignment`, None once the frame's
+    // buffer is exhausted
+    pub fn allocate(
+        &mut self,
+        frame: usize,
+        size: DeviceSize,
+        alignment: DeviceSize,
+    ) -> Option<UploadAllocation> {
+        let buffer = &self.buffers[frame];
+        let Some(offset) = self.heads.bump(frame, size, alignment, buffer.size()) else {
+            warn!(
+                "Upload ring exhausted, {size} bytes don't fit after {} of {}",
+                self.heads.heads[frame],
+                buffer.size()
+            );
+            return None;
+        };
+        Some(UploadAllocation {
+            buffer: buffer.buffer(),
+            offset,
+            size,
+            ptr: unsafe { buffer.mapped_ptr()?.add(offset as usize) },
+        })
+    }
+
+    // Copies `data` into a fresh allocation
+    pub fn push<T: bytemuck::Pod>(
+        &mut self,
+        frame: usize,
+        data: &[T],
+        alignment: DeviceSize,
+    ) -> Option<UploadAllocation> {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let allocation = self.allocate(frame, bytes.len() as DeviceSize, alignment)?;
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), allocation.ptr, bytes.len()) };
+        Some(allocation)
+    }
+
+    // `push` at the descriptor alignment. A descriptor can't point at zero
+    // bytes, so an empty `data` still takes one zeroed element
+    pub fn push_bound<T: bytemuck::Pod>(
+        &mut self,
+        frame: usize,
+        data: &[T],
+    ) -> Option<UploadRange> {
+        let zeroed = [T::zeroed()];
+        let data = if data.is_empty() { &zeroed[..] } else { data };
+        self.push(frame, data, self.descriptor_alignment)
+            .map(|allocation| allocation.range())
+    }
+}
+
+// Ring bytes a frame of `entries` uniform entries of `stride` bytes uploads,
+// with every other upload and the padding between them
+fn frame_upload_size(entries: usize, stride: DeviceSize) -> DeviceSize {
+    entries as DeviceSize * stride
+        + culling_upload_size(entries)
+        + LIGHTS_SIZE as DeviceSize
+        + UPLOAD_RING_SIZE
+        + 4 * MAX_DESCRIPTOR_ALIGNMENT
+}
+
+// Uniform entries of `stride` bytes a storage descriptor of at most
+// `max_range` bytes can view
+fn bindable_entries(stride: DeviceSize, max_range: u32) -> usize {
+    (max_range as DeviceSize / stride.max(1)) as usize
+}
+
+impl Configuration {
+    // Before the light, uniform and culling descriptors are written, they
+    // point into the ring
+    pub fn create_upload_ring(&mut self) -> Result<&mut Configuration, GpuBufferError> {
+        let limits = unsafe {
+            self.instance
+                .as_ref()
+                .unwrap()
+                .get_physical_device_properties(self.physical_device.unwrap())
+                .limits
+        };
+        let descriptor_alignment = limits
+            .min_uniform_buffer_offset_alignment
+            .max(limits.min_storage_buffer_offset_alignment);
+        let size = frame_upload_size(0, 0);
+        let buffers = (0..MAX_FLIGHT_FENCES)
+            .map(|_| self.allocate_upload_buffer(size))
+            .collect::<Result<Vec<GpuBuffer<u8>>, GpuBufferError>>()?;
+        let ring = PerFrameUploadRing::new(buffers, descriptor_alignment);
+        self.uniform_entries = (0..MAX_FLIGHT_FENCES as usize)
+            .map(|frame| ring.whole(frame))
+            .collect();
+        self.light_ranges = self.uniform_entries.clone();
+        self.upload_ring = Some(ring);
+        info!(
+            target: logging::UPLOAD,
+            "Upload ring has been created ({size} bytes per frame)"
+        );
+        Ok(self)
+    }
+
+    fn allocate_upload_buffer(&self, size: DeviceSize) -> Result<GpuBuffer<u8>, GpuBufferError> {
+        GpuBuffer::host_visible(
+            &self.gpu_context(),
+            size as usize,
+            BufferUsageFlags::VERTEX_BUFFER
+                | BufferUsageFlags::INDEX_BUFFER
+                | BufferUsageFlags::UNIFORM_BUFFER
+                | BufferUsageFlags::STORAGE_BUFFER,
+        )
+    }
+
+    // Called once the fences of `current_frame` have been waited on
+    pub fn reset_uploads(&mut self, current_frame: usize) {
+        if let Some(ring) = &mut self.upload_ring {
+            ring.reset(current_frame);
+        }
+    }
+
+    // Grows the frame's ring buffer so `entries` uniform entries and
+    // everything else the frame uploads fit. Before the frame's first upload,
+    // the old buffer is done with once the slot's fence has been waited on.
+    // Returns the entries that can be uploaded, all of them unless growing
+    // failed or binding 8 couldn't view them all
+    pub(super) fn reserve_uploads(&mut self, current_frame: usize, entries: usize) -> usize {
+        let stride = self.uniform_buffer_stride;
+        let entries = entries.min(UNIFORM_BUFFER_ENTRIES as usize);
+        let bindable = bindable_entries(stride, self.device_limits.max_storage_buffer_range);
+        if entries > bindable {
+            warn!(
+                target: logging::UPLOAD,
+                "Only the first {bindable} of {entries} uniform entries are drawn: \
+                 maxStorageBufferRange"
+            );
+        }
+        let entries = entries.min(bindable);
+        let Some(capacity) = self
+            .upload_ring
+            .as_ref()
+            .map(|ring| ring.capacity(current_frame))
+        else {
+            return 0;
+        };
+        let needed = frame_upload_size(entries, stride);
+        let Some(grown) = grown_capacity(capacity as usize, needed as usize, usize::MAX) else {
+            return entries;
+        };
+        match self.allocate_upload_buffer(grown as DeviceSize) {
+            Ok(buffer) => {
+                debug!(
+                    target: logging::UPLOAD,
+                    "Upload ring {current_frame} grown to {grown} bytes for {entries} entries"
+                );
+                self.upload_ring
+                    .as_mut()
+                    .unwrap()
+                    .replace(current_frame, buffer);
+                entries
+            }
+            Err(err) => {
+                let per_entry = frame_upload_size(1, stride) - frame_upload_size(0, stride);
+                let fitting =
+                    (capacity.saturating_sub(frame_upload_size(0, stride)) / per_entry) as usize;
+                warn!(
+                    target: logging::UPLOAD,
+                    "Only the first {fitting} of {entries} uniform entries are drawn: {err}"
+                );
+                fitting
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_round_up_to_the_alignment() {
+        let mut heads = RingHeads::new(1);
+        assert_eq!(heads.bump(0, 3, 256, 1024), Some(0));
+        assert_eq!(heads.bump(0, 10, 256, 1024), Some(256));
+        assert_eq!(heads.bump(0, 4, 4, 1024), Some(268));
+        // Zero is taken as no alignment
+        assert_eq!(heads.bump(0, 1, 0, 1024), Some(272));
+    }
+
+    #[test]
+    fn exhausted_frames_return_none() {
+        let mut heads = RingHeads::new(1);
+        assert_eq!(heads.bump(0, 1000, 16, 1024), Some(0));
+        // The alignment pushes it past the end even though 24 bytes are left
+        assert_eq!(heads.bump(0, 20, 64, 1024), None);
+        // A failed allocation doesn't move the head
+        assert_eq!(heads.bump(0, 24, 8, 1024), Some(1000));
+        assert_eq!(heads.bump(0, 1, 1, 1024), None);
+    }
+
+    #[test]
+    fn resets_only_rewind_their_frame() {
+        let mut heads = RingHeads::new(2);
+        heads.bump(0, 512, 1, 1024);
+        heads.bump(1, 100, 1, 1024);
+        assert_eq!(heads.reset(0), Some(512));
+        assert_eq!(heads.bump(0, 1024, 1, 1024), Some(0));
+        assert_eq!(heads.bump(1, 1, 256, 1024), Some(256));
+        // Only growing past the high water mark is reported
+        assert_eq!(heads.reset(1), None);
+        assert_eq!(heads.reset(0), Some(1024));
+    }
+
+    #[test]
+    fn bound_entries_stay_within_the_storage_range() {
+        // Vulkan's guaranteed minimum doesn't hold every entry at 320 bytes
+        let bindable = bindable_entries(320, 1 << 27);
+        assert_eq!(bindable, 419_430);
+        assert!(bindable < UNIFORM_BUFFER_ENTRIES as usize);
+        assert!(bindable as DeviceSize * 320 <= 1 << 27);
+        assert!((bindable + 1) as DeviceSize * 320 > 1 << 27);
+        assert_eq!(bindable_entries(256, 1 << 16), 256);
+        assert_eq!(bindable_entries(0, 1 << 16), 1 << 16);
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    backtrace::Backtrace,
+    fmt::Write as _,
+    fs, io,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ash::Device;
+
+use crate::engine::diagnostics::DiagnosticsReport;
+
+// What a panic hook can still reach of the running engine. Set once the
+// renderer is initialized and cleared before its device is destroyed
+struct CrashHandle {
+    device: Device,
+    report: DiagnosticsReport,
+}
+
+static CRASH_HANDLE: Mutex<Option<CrashHandle>> = Mutex::new(None);
+
+pub(crate) fn register(device: Device, report: DiagnosticsReport) {
+    *CRASH_HANDLE.lock().unwrap_or_else(|err| err.into_inner()) =
+        Some(CrashHandle { device, report });
+}
+
+pub(crate) fn unregister() {
+    CRASH_HANDLE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .take();
+}
+
+fn crash_log(report: &DiagnosticsReport, message: &str) -> String {
+    let mut log = report.to_string();
+    let _ = writeln!(log, "\nPanic: {message}");
+    let _ = writeln!(log, "\nBacktrace:\n{}", Backtrace::force_capture());
+    log
+}
+
+// Waits for the GPU to finish what was submitted, then writes the
+// diagnostics with `message` and a backtrace to caterpie-crash-<unix
+// time>.log. Meant to be called from a panic hook, None when no engine is
+// running or the handle is held by the panicking thread
+pub(crate) fn emergency_shutdown(message: &str) -> Option<io::Result<PathBuf>> {
+    let guard = CRASH_HANDLE.try_lock().ok()?;
+    let handle = guard.as_ref()?;
+    // Whatever state the device is in, nothing more is submitted to it
+    let _ = unsafe { handle.device.device_wait_idle() };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let path = PathBuf::from(format!("caterpie-crash-{timestamp}.log"));
+    Some(fs::write(&path, crash_log(&handle.report, message)).map(|()| path))
+}
@@ -0,0 +1,204 @@
+use cgmath::{point3, vec4, EuclideanSpace, Matrix4, Point3, SquareMatrix, Transform};
+
+use crate::engine::{
+    debug_palette::DebugPalette,
+    gizmo,
+    raycast::Ray,
+    scene::{box_edges, Aabb},
+    text::TextBox,
+};
+
+// How far `Engine::set_show_debug_queries` draws the last cursor ray
+pub(crate) const DEBUG_RAY_LENGTH: f32 = 50.0;
+
+// Line list endpoints with their color
+pub type DebugLines = Vec<(Point3<f32>, [f32; 3])>;
+
+// Text over the primary window, laid out once the window size is known
+pub(crate) type ScreenText = Vec<(String, TextBox, [f32; 3])>;
+
+// Outlines over the primary window between two corners in pixels
+pub(crate) type ScreenRects = Vec<([f32; 2], [f32; 2], [f32; 3])>;
+
+/// Transient lines for the current frame. They can be added from anywhere in
+/// the update code and are gone once the frame has been drawn. Lines are drawn
+/// on top of the scene unless `depth_test` was turned on before adding them,
+/// text always is
+///
+/// ```no_run
+/// use caterpie::{engine::raycast::Ray, Engine};
+/// use cgmath::{point3, vec3};
+///
+/// fn update(engine: &mut Engine, ray: &Ray) {
+///     let bounds = engine.debug_palette().bounds;
+///     engine
+///         .debug_draw()
+///         .ray(ray, 10.0, [1.0, 1.0, 0.0])
+///         .depth_test(true)
+///         .aabb(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0), bounds)
+///         .wire_sphere(point3(0.0, 2.0, 0.0), 0.5, [0.0, 1.0, 1.0]);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct DebugDraw {
+    overlay: DebugLines,
+    depth_tested: DebugLines,
+    screen_text: ScreenText,
+    screen_rects: ScreenRects,
+    depth_test: bool,
+    palette: DebugPalette,
+}
+
+impl DebugDraw {
+    pub(crate) fn new(palette: DebugPalette) -> Self {
+        DebugDraw {
+            palette,
+            ..Default::default()
+        }
+    }
+
+    // The engine's debug colors, to draw in the same colors as its own lines
+    pub fn palette(&self) -> &DebugPalette {
+        &self.palette
+    }
+
+    pub(crate) fn set_palette(&mut self, palette: DebugPalette) {
+        self.palette = palette;
+    }
+
+    /// Whether the lines added after this are hidden behind the scene, until
+    /// the end of the frame
+    ///
+    /// ```
+    /// use caterpie::engine::debug_draw::DebugDraw;
+    /// use cgmath::point3;
+    ///
+    /// let mut debug_draw = DebugDraw::default();
+    /// let (a, b) = (point3(0.0, 0.0, 0.0), point3(1.0, 0.0, 0.0));
+    /// // Drawn over everything
+    /// debug_draw.line(a, b, [1.0, 0.0, 0.0]);
+    /// // Hidden where the scene is in front of it
+    /// debug_draw.depth_test(true).line(a, b, [0.0, 1.0, 0.0]);
+    /// ```
+    pub fn depth_test(&mut self, depth_test: bool) -> &mut Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    fn lines(&mut self) -> &mut DebugLines {
+        if self.depth_test {
+            &mut self.depth_tested
+        } else {
+            &mut self.overlay
+        }
+    }
+
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 3]) -> &mut Self {
+        self.lines().extend([(a, color), (b, color)]);
+        self
+    }
+
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 3]) -> &mut Self {
+        let edges = Aabb { min, max }.edges();
+        self.lines()
+            .extend(edges.into_iter().map(|point| (point, color)));
+        self
+    }
+
+    pub fn wire_sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 3]) -> &mut Self {
+        self.lines()
+            .extend(gizmo::sphere_lines(center, radius, color));
+        self
+    }
+
+    // The x, y and z axes of `transform` in the palette's axis colors
+    pub fn axes(&mut self, transform: Matrix4<f32>, length: f32) -> &mut Self {
+        let origin = transform.transform_point(point3(0.0, 0.0, 0.0));
+        for (axis, color) in self.palette.axes.into_iter().enumerate() {
+            let end =
+                transform.transform_point(Point3::from_vec(gizmo::axis_direction(axis) * length));
+            self.line(origin, end, color);
+        }
+        self
+    }
+
+    // Edges of the volume a camera with `view_projection` sees, between
+    // depth 0 and 1 as the culling frustum
+    pub fn frustum(&mut self, view_projection: Matrix4<f32>, color: [f32; 3]) -> &mut Self {
+        let Some(inverse) = view_projection.invert() else {
+            return self;
+        };
+        let corners = std::array::from_fn(|index| {
+            let ndc = |bit: usize| if index & bit == 0 { -1.0 } else { 1.0 };
+            let depth = if index & 4 == 0 { 0.0 } else { 1.0 };
+            let point = inverse * vec4(ndc(1), ndc(2), depth, 1.0);
+            point3(point.x, point.y, point.z) / point.w
+        });
+        self.lines()
+            .extend(box_edges(&corners).into_iter().map(|point| (point, color)));
+        self
+    }
+
+    pub fn ray(&mut self, ray: &Ray, length: f32, color: [f32; 3]) -> &mut Self {
+        self.line(ray.origin, ray.at(length), color)
+    }
+
+    // `text` wrapped to the columns of `text_box` over the primary window
+    pub fn text(&mut self, text: &str, text_box: TextBox, color: [f32; 3]) -> &mut Self {
+        self.screen_text.push((text.to_string(), text_box, color));
+        self
+    }
+
+    // Outline of the rectangle between the pixels `from` and `to` of the
+    // primary window, like a selection being dragged
+    pub fn screen_rect(&mut self, from: [f32; 2], to: [f32; 2], color: [f32; 3]) -> &mut Self {
+        self.screen_rects.push((from, to, color));
+        self
+    }
+
+    // The overlay and the depth tested lines, the text and the rectangles of
+    // the frame, leaving it empty for the next one
+    pub(crate) fn take(&mut self) -> (DebugLines, DebugLines, ScreenText, ScreenRects) {
+        self.depth_test = false;
+        (
+            std::mem::take(&mut self.overlay),
+            std::mem::take(&mut self.depth_tested),
+            std::mem::take(&mut self.screen_text),
+            std::mem::take(&mut self.screen_rects),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::text::Align;
+
+    #[test]
+    fn frames_grow_past_their_capacity_and_start_empty() {
+        let mut debug_draw = DebugDraw::default();
+        let (a, b) = (point3(0.0, 0.0, 0.0), point3(1.0, 0.0, 0.0));
+        for frame in 1..=3 {
+            for _ in 0..frame * 1000 {
+                debug_draw.line(a, b, [1.0; 3]);
+            }
+            debug_draw.depth_test(true).aabb(a, b, [1.0; 3]);
+            let text_box = TextBox {
+                origin: [0.0, 1.0],
+                columns: 20,
+                align: Align::Left,
+                scale: 1.0,
+            };
+            debug_draw.text("frame", text_box, [1.0; 3]);
+
+            let (overlay, depth_tested, text, rects) = debug_draw.take();
+            assert_eq!(overlay.len(), frame * 2000);
+            assert_eq!(depth_tested.len(), 24);
+            assert_eq!((text.len(), rects.len()), (1, 0));
+            // Nothing carries over, depth testing included
+            debug_draw.line(a, b, [1.0; 3]);
+            let (overlay, depth_tested, _, _) = debug_draw.take();
+            assert_eq!((overlay.len(), depth_tested.len()), (2, 0));
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// Every color the debug visualizations draw with, by what it marks. The
+// overlay lines, bounds, outline and level of detail tints all read from the
+// engine's palette so it can be swapped as a whole
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugPalette {
+    // Outline, bounds and tint of the selected object
+    pub selection: [f32; 3],
+    // The gizmo axis under the cursor
+    pub hovered: [f32; 3],
+    pub bounds: [f32; 3],
+    // Gizmo tripod, x y z
+    pub axes: [[f32; 3]; 3],
+    pub frustum: [f32; 3],
+    pub ray: [f32; 3],
+    // Levels of detail 1 to 3 while they are shown, the full mesh isn't tinted
+    pub lods: [[f32; 3]; 3],
+    pub graph_cpu: [f32; 3],
+    pub graph_gpu: [f32; 3],
+    pub graph_guide: [f32; 3],
+    pub graph_border: [f32; 3],
+    // On screen text like the log view
+    pub text: [f32; 3],
+    // Frames over the 60 and 30 fps guides, and logged warnings and errors
+    pub caution: [f32; 3],
+    pub warning: [f32; 3],
+}
+
+impl DebugPalette {
+    pub const DEFAULT: DebugPalette = DebugPalette {
+        selection: [1.0, 0.6, 0.1],
+        hovered: [1.0, 1.0, 0.2],
+        bounds: [0.35, 0.35, 0.35],
+        axes: [[1.0, 0.2, 0.2], [0.2, 1.0, 0.2], [0.3, 0.4, 1.0]],
+        frustum: [1.0, 0.6, 0.1],
+        ray: [1.0, 0.1, 0.8],
+        lods: [[0.2, 0.9, 0.2], [0.9, 0.9, 0.2], [0.9, 0.2, 0.2]],
+        graph_cpu: [0.3, 0.9, 0.4],
+        graph_gpu: [0.3, 0.6, 1.0],
+        graph_guide: [0.4, 0.4, 0.4],
+        graph_border: [0.7, 0.7, 0.7],
+        text: [0.9, 0.9, 0.9],
+        caution: [1.0, 0.8, 0.2],
+        warning: [1.0, 0.2, 0.2],
+    };
+
+    // Okabe-Ito colors, nothing tells two things apart by red against green
+    pub const COLOR_BLIND: DebugPalette = DebugPalette {
+        selection: [0.9, 0.6, 0.0],
+        hovered: [1.0, 1.0, 1.0],
+        bounds: [0.5, 0.5, 0.5],
+        axes: [[0.84, 0.37, 0.0], [0.94, 0.89, 0.26], [0.0, 0.45, 0.7]],
+        frustum: [0.9, 0.6, 0.0],
+        ray: [0.8, 0.47, 0.65],
+        lods: [[0.34, 0.71, 0.91], [0.94, 0.89, 0.26], [0.84, 0.37, 0.0]],
+        graph_cpu: [0.0, 0.62, 0.45],
+        graph_gpu: [0.34, 0.71, 0.91],
+        graph_guide: [0.4, 0.4, 0.4],
+        graph_border: [0.7, 0.7, 0.7],
+        text: [0.9, 0.9, 0.9],
+        caution: [0.94, 0.89, 0.26],
+        warning: [0.84, 0.37, 0.0],
+    };
+
+    const BUILT_IN: [(&'static str, DebugPalette); 2] = [
+        ("default", DebugPalette::DEFAULT),
+        ("color-blind", DebugPalette::COLOR_BLIND),
+    ];
+
+    // A built-in palette by name
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let value = value.to_ascii_lowercase().replace('_', "-");
+        DebugPalette::BUILT_IN
+            .iter()
+            .find(|(name, _)| *name == value)
+            .map(|(_, palette)| *palette)
+    }
+
+    // "custom" for anything that isn't a built-in palette
+    pub fn name(&self) -> &'static str {
+        DebugPalette::BUILT_IN
+            .iter()
+            .find(|(_, palette)| palette == self)
+            .map_or("custom", |(name, _)| name)
+    }
+
+    // The built-in palette after this one, the first after a custom one
+    pub fn next(&self) -> Self {
+        let index = DebugPalette::BUILT_IN
+            .iter()
+            .position(|(_, palette)| palette == self)
+            .map_or(0, |index| (index + 1) % DebugPalette::BUILT_IN.len());
+        DebugPalette::BUILT_IN[index].1
+    }
+
+    // With an alpha for the shaders' object color
+    pub(crate) fn rgba(color: [f32; 3], alpha: f32) -> [f32; 4] {
+        [color[0], color[1], color[2], alpha]
+    }
+}
+
+impl Default for DebugPalette {
+    fn default() -> Self {
+        DebugPalette::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_names_parse_loosely() {
+        for name in ["color-blind", "COLOR_BLIND", "Color-Blind", "color_blind"] {
+            assert_eq!(DebugPalette::parse(name), Some(DebugPalette::COLOR_BLIND));
+        }
+        assert_eq!(DebugPalette::parse("Default"), Some(DebugPalette::DEFAULT));
+    }
+
+    #[test]
+    fn malformed_names_are_rejected() {
+        for name in [
+            "",
+            " default",
+            "colorblind",
+            "color blind",
+            "custom",
+            "défault",
+        ] {
+            assert_eq!(DebugPalette::parse(name), None, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn names_round_trip_and_cycle() {
+        for (name, palette) in DebugPalette::BUILT_IN {
+            assert_eq!(DebugPalette::parse(palette.name()), Some(palette));
+            assert_eq!(palette.name(), name);
+        }
+        assert_eq!(DebugPalette::DEFAULT.next(), DebugPalette::COLOR_BLIND);
+        assert_eq!(DebugPalette::COLOR_BLIND.next(), DebugPalette::DEFAULT);
+        let custom = DebugPalette {
+            text: [0.1, 0.2, 0.3],
+            ..DebugPalette::COLOR_BLIND
+        };
+        assert_eq!(custom.name(), "custom");
+        assert_eq!(custom.next(), DebugPalette::DEFAULT);
+    }
+}
@@ -0,0 +1,291 @@
+use std::{fmt, fs, io, path::Path};
+
+pub const DIAGNOSTICS_FILE: &str = "caterpie-diagnostics.txt";
+
+// Environment snapshot for bug reports. Everything is optional since it is
+// also gathered from a configuration that failed halfway through init
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "diagnostics-json", derive(serde::Serialize))]
+pub struct DiagnosticsReport {
+    pub instance_version: Option<String>,
+    pub instance_extensions: Vec<String>,
+    pub layers: Vec<String>,
+    pub device: Option<DeviceReport>,
+    pub device_extensions: Vec<String>,
+    pub queue_families: Vec<QueueFamilyReport>,
+    pub graphics_queue_family: Option<u32>,
+    pub presentation_queue_family: Option<u32>,
+    pub msaa_samples: u32,
+    pub surfaces: Vec<SurfaceReport>,
+    pub assets: AssetReport,
+    // The platform quirks of the picked device, name and value
+    pub capabilities: Vec<CapabilityReport>,
+    // The limits the configuration was checked against, and what was lowered
+    // to fit them
+    pub limits: Vec<CapabilityReport>,
+    pub limit_adjustments: Vec<String>,
+    // Set when initialization failed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "diagnostics-json", derive(serde::Serialize))]
+pub struct DeviceReport {
+    pub name: String,
+    pub device_type: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: String,
+    pub driver_version: String,
+    // Empty when the device doesn't report VK_KHR_driver_properties
+    pub driver_id: String,
+    pub driver_name: String,
+    pub driver_info: String,
+    pub max_image_dimension_2d: u32,
+    pub max_bound_descriptor_sets: u32,
+    pub max_push_constants_size: u32,
+    pub max_uniform_buffer_range: u32,
+    pub min_uniform_buffer_offset_alignment: u64,
+    pub max_sampler_anisotropy: f32,
+    pub max_viewports: u32,
+    pub timestamp_period: f32,
+    pub framebuffer_color_sample_counts: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "diagnostics-json", derive(serde::Serialize))]
+pub struct QueueFamilyReport {
+    pub index: u32,
+    pub flags: String,
+    pub queue_count: u32,
+    pub timestamp_valid_bits: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "diagnostics-json", derive(serde::Serialize))]
+pub struct SurfaceReport {
+    pub view: u32,
+    pub format: String,
+    pub color_space: String,
+    pub present_mode: String,
+    pub width: u32,
+    pub height: u32,
+    // What the swapchain was created with, `requested_image_count` is what
+    // was asked for
+    pub image_count: usize,
+    pub requested_image_count: u32,
+    pub capabilities: SurfaceCapabilitiesReport,
+}
+
+// VkSurfaceCapabilitiesKHR as of the last swapchain creation
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "diagnostics-json", derive(serde::Serialize))]
+pub struct SurfaceCapabilitiesReport {
+    pub min_image_count: u32,
+    // None when the surface has no upper limit
+    pub max_image_count: Option<u32>,
+    // None when the swapchain decides, as on Wayland
+    pub current_extent: Option<[u32; 2]>,
+    pub min_image_extent: [u32; 2],
+    pub max_image_extent: [u32; 2],
+    pub max_image_array_layers: u32,
+    pub supported_transforms: String,
+    pub current_transform: String,
+    pub supported_composite_alpha: String,
+    pub supported_usage_flags: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "diagnostics-json", derive(serde::Serialize))]
+pub struct CapabilityReport {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "diagnostics-json", derive(serde::Serialize))]
+pub struct AssetReport {
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub live_textures: usize,
+    pub live_meshes: usize,
+    pub live_bytes: u64,
+    pub retained_cpu_bytes: u64,
+}
+
+impl DiagnosticsReport {
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    #[cfg(feature = "diagnostics-json")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn list(names: &[String]) -> String {
+    if names.is_empty() {
+        return "none".to_string();
+    }
+    names.join(", ")
+}
+
+impl fmt::Display for DiagnosticsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "caterpie {}", env!("CARGO_PKG_VERSION"))?;
+        if let Some(error) = &self.error {
+            writeln!(f, "Initialization failed: {error}")?;
+        }
+        writeln!(
+            f,
+            "Vulkan instance: {}",
+            self.instance_version.as_deref().unwrap_or("unknown")
+        )?;
+        writeln!(
+            f,
+            "Instance extensions: {}",
+            list(&self.instance_extensions)
+        )?;
+        writeln!(f, "Layers: {}", list(&self.layers))?;
+
+        match &self.device {
+            Some(device) => {
+                writeln!(f, "Device: {} ({})", device.name, device.device_type)?;
+                writeln!(
+                    f,
+                    "  vendor {:#06x}, device {:#06x}, api {}, driver {}",
+                    device.vendor_id, device.device_id, device.api_version, device.driver_version
+                )?;
+                if !device.driver_id.is_empty() {
+                    writeln!(
+                        f,
+                        "  driver {} ({}) {}",
+                        device.driver_name, device.driver_id, device.driver_info
+                    )?;
+                }
+                writeln!(
+                    f,
+                    "  max image 2d {}, descriptor sets {}, push constants {} bytes",
+                    device.max_image_dimension_2d,
+                    device.max_bound_descriptor_sets,
+                    device.max_push_constants_size
+                )?;
+                writeln!(
+                    f,
+                    "  uniform range {}, uniform alignment {}, anisotropy {}, viewports {}",
+                    device.max_uniform_buffer_range,
+                    device.min_uniform_buffer_offset_alignment,
+                    device.max_sampler_anisotropy,
+                    device.max_viewports
+                )?;
+                writeln!(
+                    f,
+                    "  timestamp period {} ns, color sample counts {}",
+                    device.timestamp_period, device.framebuffer_color_sample_counts
+                )?;
+            }
+            None => writeln!(f, "Device: none selected")?,
+        }
+        writeln!(f, "Device extensions: {}", list(&self.device_extensions))?;
+        if !self.capabilities.is_empty() {
+            writeln!(f, "Capabilities:")?;
+            let width = self
+                .capabilities
+                .iter()
+                .map(|capability| capability.name.len())
+                .max()
+                .unwrap_or(0);
+            for capability in &self.capabilities {
+                writeln!(f, "  {:width$}  {}", capability.name, capability.value)?;
+            }
+        }
+        if !self.limits.is_empty() {
+            writeln!(f, "Device limits:")?;
+            let width = self
+                .limits
+                .iter()
+                .map(|limit| limit.name.len())
+                .max()
+                .unwrap_or(0);
+            for limit in &self.limits {
+                writeln!(f, "  {:width$}  {}", limit.name, limit.value)?;
+            }
+        }
+        for adjustment in &self.limit_adjustments {
+            writeln!(f, "Adjusted: {adjustment}")?;
+        }
+
+        writeln!(f, "Queue families:")?;
+        for family in &self.queue_families {
+            let mut roles = Vec::new();
+            if self.graphics_queue_family == Some(family.index) {
+                roles.push("graphics");
+            }
+            if self.presentation_queue_family == Some(family.index) {
+                roles.push("present");
+            }
+            writeln!(
+                f,
+                "  {}: {} x{}, timestamp bits {}{}",
+                family.index,
+                family.flags,
+                family.queue_count,
+                family.timestamp_valid_bits,
+                if roles.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", roles.join(", "))
+                }
+            )?;
+        }
+
+        writeln!(f, "MSAA: {}x", self.msaa_samples)?;
+        writeln!(f, "Surfaces:")?;
+        for surface in &self.surfaces {
+            writeln!(
+                f,
+                "  view {}: {} {}, {}, {}x{}, {} images ({} requested)",
+                surface.view,
+                surface.format,
+                surface.color_space,
+                surface.present_mode,
+                surface.width,
+                surface.height,
+                surface.image_count,
+                surface.requested_image_count
+            )?;
+            let capabilities = &surface.capabilities;
+            let extent = |[width, height]: [u32; 2]| format!("{width}x{height}");
+            writeln!(
+                f,
+                "    images {}..{}, extent {} in {}..{}, {} layers, transform {} of {}, alpha {}, usage {}",
+                capabilities.min_image_count,
+                capabilities
+                    .max_image_count
+                    .map_or("any".to_string(), |count| count.to_string()),
+                capabilities
+                    .current_extent
+                    .map_or("any".to_string(), extent),
+                extent(capabilities.min_image_extent),
+                extent(capabilities.max_image_extent),
+                capabilities.max_image_array_layers,
+                capabilities.current_transform,
+                capabilities.supported_transforms,
+                capabilities.supported_composite_alpha,
+                capabilities.supported_usage_flags
+            )?;
+        }
+        writeln!(
+            f,
+            "Assets: {} textures, {} meshes, {} bytes live, {} bytes kept on the CPU, {} cache hits, {} misses",
+            self.assets.live_textures,
+            self.assets.live_meshes,
+            self.assets.live_bytes,
+            self.assets.retained_cpu_bytes,
+            self.assets.cache_hits,
+            self.assets.cache_misses
+        )?;
+        Ok(())
+    }
+}
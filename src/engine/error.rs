@@ -0,0 +1,178 @@
+use std::fmt;
+
+use ash::vk;
+
+use super::configuration::buffer_types::gpu_buffer::GpuBufferError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    // The swapchain handed out an image we have no framebuffer for, usually
+    // right after a resize raced the acquire
+    ImageIndexOutOfRange {
+        index: u32,
+        framebuffers: usize,
+    },
+    FrameOutOfRange {
+        frame: usize,
+        descriptor_sets: usize,
+    },
+    // `op` is the Vulkan entry point that failed, `detail` what it was
+    // called for. Empty when a bare vk::Result was converted
+    Vulkan {
+        op: &'static str,
+        detail: String,
+        result: vk::Result,
+    },
+    Buffer(GpuBufferError),
+    // Neither the system's Vulkan loader nor the SDK's could be opened
+    LoaderMissing(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::ImageIndexOutOfRange {
+                index,
+                framebuffers,
+            } => write!(
+                f,
+                "Swapchain image {index} is out of range, there are {framebuffers} framebuffers"
+            ),
+            EngineError::FrameOutOfRange {
+                frame,
+                descriptor_sets,
+            } => write!(
+                f,
+                "Frame slot {frame} is out of range, there are {descriptor_sets} descriptor sets"
+            ),
+            EngineError::Vulkan { op, detail, result } if detail.is_empty() => {
+                write!(f, "{op} failed: {result:?}")
+            }
+            EngineError::Vulkan { op, detail, result } => {
+                write!(f, "{op} failed while {detail}: {result:?}")
+            }
+            EngineError::Buffer(err) => err.fmt(f),
+            EngineError::LoaderMissing(reason) => {
+                write!(
+                    f,
+                    "Failed to find the Vulkan loader on this machine: {reason}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<vk::Result> for EngineError {
+    fn from(result: vk::Result) -> Self {
+        EngineError::Vulkan {
+            op: "Vulkan call",
+            detail: String::new(),
+            result,
+        }
+    }
+}
+
+impl From<GpuBufferError> for EngineError {
+    fn from(err: GpuBufferError) -> Self {
+        match err {
+            GpuBufferError::Vulkan(err) => *err,
+            err => EngineError::Buffer(err),
+        }
+    }
+}
+
+// Names the Vulkan call behind a bare vk::Result and what it was for, like
+// `.vk_ctx("vkAllocateMemory", "allocating a vertex buffer")`
+pub trait VkContext<T> {
+    fn vk_ctx(self, op: &'static str, detail: impl fmt::Display) -> Result<T, EngineError>;
+
+    // For calls whose failure the engine can't recover from, panics with
+    // the same message
+    fn vk_expect(self, op: &'static str, detail: impl fmt::Display) -> T;
+}
+
+impl<T> VkContext<T> for Result<T, vk::Result> {
+    fn vk_ctx(self, op: &'static str, detail: impl fmt::Display) -> Result<T, EngineError> {
+        self.map_err(|result| EngineError::Vulkan {
+            op,
+            detail: detail.to_string(),
+            result,
+        })
+    }
+
+    fn vk_expect(self, op: &'static str, detail: impl fmt::Display) -> T {
+        self.vk_ctx(op, detail)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+}
+
+// A size in bytes for error details, like "12.4 MiB"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit + 1 < UNITS.len() {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vulkan_errors_name_the_call_and_what_it_was_for() {
+        let err = Err::<(), _>(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+            .vk_ctx(
+                "vkAllocateMemory",
+                format_args!("allocating {}", ByteSize(3 << 20)),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "vkAllocateMemory failed while allocating 3.0 MiB: ERROR_OUT_OF_DEVICE_MEMORY"
+        );
+        // A bare vk::Result has no detail to add
+        assert_eq!(
+            EngineError::from(vk::Result::ERROR_DEVICE_LOST).to_string(),
+            "Vulkan call failed: ERROR_DEVICE_LOST"
+        );
+    }
+
+    #[test]
+    fn buffer_errors_unwrap_to_the_vulkan_error() {
+        let vulkan = EngineError::Vulkan {
+            op: "vkCreateBuffer",
+            detail: String::new(),
+            result: vk::Result::ERROR_OUT_OF_HOST_MEMORY,
+        };
+        assert_eq!(
+            EngineError::from(GpuBufferError::from(vulkan.clone())),
+            vulkan
+        );
+    }
+
+    #[test]
+    fn byte_sizes_pick_the_largest_unit_below_the_size() {
+        assert_eq!(ByteSize(0).to_string(), "0 B");
+        assert_eq!(ByteSize(1023).to_string(), "1023 B");
+        assert_eq!(ByteSize(1024).to_string(), "1.0 KiB");
+        assert_eq!(ByteSize(1536).to_string(), "1.5 KiB");
+        assert_eq!(ByteSize((1 << 20) - 1).to_string(), "1024.0 KiB");
+        assert_eq!(ByteSize(12 << 30).to_string(), "12.0 GiB");
+        // Stays in TiB past the last unit
+        assert_eq!(ByteSize(2048 << 40).to_string(), "2048.0 TiB");
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use crate::engine::frame_pacer::frame_budget;
+
+// Longest frame fed into the accumulator. A stall beyond that, a breakpoint
+// or a window drag, is dropped instead of being caught up with a burst of
+// ticks that only makes the next frame slower
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+// Turns variable frame times into a whole number of fixed ticks. Works in
+// Durations so exact multiples of the step never lose a tick to rounding
+#[derive(Debug, Clone, Copy)]
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: u32) -> Self {
+        Self {
+            step: frame_budget(tick_rate.max(1)),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    // Adds the time of one frame and returns how many ticks are now due
+    pub fn advance(&mut self, frame_time: Duration) -> u32 {
+        self.accumulator += frame_time.min(MAX_FRAME_TIME);
+        let ticks = (self.accumulator.as_nanos() / self.step.as_nanos()) as u32;
+        self.accumulator -= self.step * ticks;
+        ticks
+    }
+
+    // Position of the frame between the last tick and the next one, in 0..1
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_frames_are_clamped() {
+        // 20ms steps, the 250ms clamp is 12 ticks and half of the next
+        let mut timestep = FixedTimestep::new(50);
+        assert_eq!(timestep.advance(Duration::from_secs(5)), 12);
+        assert_eq!(timestep.alpha(), 0.5);
+        assert_eq!(timestep.advance(MAX_FRAME_TIME * 2), 13);
+    }
+
+    #[test]
+    fn tiny_frames_add_up() {
+        let mut timestep = FixedTimestep::new(60);
+        let frame = Duration::from_millis(1);
+        let ticks = (0..16)
+            .map(|_| timestep.advance(frame))
+            .collect::<Vec<u32>>();
+        assert!(ticks.iter().all(|ticks| *ticks == 0));
+        assert_eq!(timestep.advance(frame), 1);
+        assert_eq!(timestep.advance(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn exact_multiples_lose_no_tick() {
+        for tick_rate in [30, 60, 120, 144] {
+            let mut timestep = FixedTimestep::new(tick_rate);
+            let step = timestep.step();
+            let ticks: u32 = (0..1000)
+                .map(|frame| timestep.advance(step * (frame % 3)))
+                .sum();
+            assert_eq!(ticks, 999, "{tick_rate} Hz");
+            assert_eq!(timestep.alpha(), 0.0);
+        }
+    }
+
+    #[test]
+    fn alpha_stays_below_one() {
+        let mut timestep = FixedTimestep::new(60);
+        for frame in 0..500u64 {
+            timestep.advance(Duration::from_micros(frame * 7919 % 40_000));
+            let alpha = timestep.alpha();
+            assert!((0.0..1.0).contains(&alpha), "{alpha}");
+        }
+    }
+
+    #[test]
+    fn zero_tick_rate_ticks_once_a_second() {
+        let timestep = FixedTimestep::new(0);
+        assert_eq!(timestep.step(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,94 @@
+use log::{info, warn};
+#[cfg(feature = "renderdoc")]
+use renderdoc::{RenderDoc, V110};
+
+// Captures whole frames through RenderDoc's in-application API, from the
+// start of `draw_frame` to the last present. Only does anything when the
+// process runs under RenderDoc and was built with the renderdoc feature
+pub struct FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc<V110>>,
+    // The next frame drawn is captured
+    requested: bool,
+    capturing: bool,
+    // Counted from the first frame drawn, see `EngineConfig::capture_frame`
+    capture_frame: Option<u64>,
+    frames: u64,
+}
+
+impl FrameCapture {
+    pub fn new(capture_frame: Option<u64>) -> Self {
+        #[cfg(feature = "renderdoc")]
+        let renderdoc = match RenderDoc::new() {
+            Ok(renderdoc) => {
+                info!("RenderDoc is attached, frames can be captured");
+                Some(renderdoc)
+            }
+            Err(err) => {
+                info!("Not running under RenderDoc, frame captures are off: {err}");
+                None
+            }
+        };
+        FrameCapture {
+            #[cfg(feature = "renderdoc")]
+            renderdoc,
+            requested: false,
+            capturing: false,
+            capture_frame,
+            frames: 0,
+        }
+    }
+
+    #[cfg(feature = "renderdoc")]
+    pub fn available(&self) -> bool {
+        self.renderdoc.is_some()
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    pub fn available(&self) -> bool {
+        false
+    }
+
+    // Captures the next frame drawn
+    pub fn request(&mut self) -> Result<(), &'static str> {
+        if !self.available() {
+            return Err(if cfg!(feature = "renderdoc") {
+                "the process isn't running under RenderDoc"
+            } else {
+                "built without the renderdoc feature"
+            });
+        }
+        self.requested = true;
+        Ok(())
+    }
+
+    // Right before the frame records anything
+    pub(crate) fn begin_frame(&mut self) {
+        self.frames += 1;
+        if self.capture_frame == Some(self.frames) {
+            match self.request() {
+                Ok(()) => info!("Capturing frame {} as configured", self.frames),
+                Err(err) => warn!("Can't capture frame {}: {err}", self.frames),
+            }
+        }
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = self.renderdoc.as_mut().filter(|_| self.requested) {
+            // Null device and window capture whatever the frame touches
+            renderdoc.start_frame_capture(std::ptr::null(), std::ptr::null());
+            self.capturing = true;
+        }
+        self.requested = false;
+    }
+
+    // After the frame's last present. True when it ended a capture
+    pub(crate) fn end_frame(&mut self) -> bool {
+        if !std::mem::take(&mut self.capturing) {
+            return false;
+        }
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = &mut self.renderdoc {
+            renderdoc.end_frame_capture(std::ptr::null(), std::ptr::null());
+        }
+        true
+    }
+}
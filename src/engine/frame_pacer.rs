@@ -0,0 +1,342 @@
+use std::{
+    hint::spin_loop,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use ash::vk::PresentModeKHR;
+use log::info;
+
+use crate::{
+    engine::config::BackgroundBehavior,
+    logging::{self, span},
+};
+
+const FPS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+// Low latency mode aims to be done with a frame this long before the refresh
+const PRESENT_MARGIN: Duration = Duration::from_millis(2);
+
+pub fn frame_budget(fps: u32) -> Duration {
+    Duration::from_secs_f64(1.0 / fps as f64)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTimer {
+    last_frame: Instant,
+    // Between the last two ticks
+    frame_time: Duration,
+    window_start: Instant,
+    window_frames: u32,
+}
+
+impl Default for FrameTimer {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            last_frame: now,
+            frame_time: Duration::ZERO,
+            window_start: now,
+            window_frames: 0,
+        }
+    }
+}
+
+impl FrameTimer {
+    // Returns the average fps and the window it was taken over whenever a
+    // full averaging window has passed
+    pub fn tick(&mut self) -> Option<(f32, Duration)> {
+        let now = Instant::now();
+        self.frame_time = now - self.last_frame;
+        self.last_frame = now;
+        self.window_frames += 1;
+
+        let window = now - self.window_start;
+        if window < FPS_LOG_INTERVAL {
+            return None;
+        }
+        let fps = self.window_frames as f32 / window.as_secs_f32();
+        self.window_start = now;
+        self.window_frames = 0;
+        Some((fps, window))
+    }
+
+    pub fn last_frame(&self) -> Instant {
+        self.last_frame
+    }
+
+    pub fn since_last_frame(&self) -> Duration {
+        self.last_frame.elapsed()
+    }
+
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacingMode {
+    // FIFO presentation blocks in queue_present, no extra waiting needed
+    Vsync,
+    // fps_limit enforced by the pacer, below the refresh rate when vsynced
+    Capped(u32),
+    Uncapped,
+    // Unfocused or occluded, frames are capped to the background fps
+    Throttled,
+    // Unfocused or occluded, nothing is drawn until the window comes back
+    Paused,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FramePacer {
+    timer: FrameTimer,
+    fps_limit: Option<u32>,
+    background_fps: u32,
+    present_mode: Option<PresentModeKHR>,
+    refresh_rate: Option<u32>,
+    throttled: Option<BackgroundBehavior>,
+    low_latency: bool,
+    fps: Option<f32>,
+    // Swapchain recreations at the start of the averaging window
+    window_recreations: u64,
+    recreation_rate: Option<f32>,
+    // When the last paced frame was let through, the next one is due a
+    // budget later
+    deadline: Option<Instant>,
+}
+
+// How long to sleep before spinning out the rest of the frame budget. Sleeps
+// overshoot by up to a scheduler tick so the last SPIN_MARGIN is busy waited
+pub fn sleep_duration(budget: Duration, elapsed: Duration) -> Duration {
+    budget.saturating_sub(elapsed).saturating_sub(SPIN_MARGIN)
+}
+
+// One budget after the last deadline, so the time spent on the frame itself
+// counts towards the budget. A frame that is already late starts the
+// schedule over instead of letting the following ones catch up
+pub fn next_deadline(last: Option<Instant>, budget: Duration, now: Instant) -> Instant {
+    match last {
+        Some(last) if last + budget >= now => last + budget,
+        _ => now,
+    }
+}
+
+impl FramePacer {
+    pub fn new(fps_limit: Option<u32>, background_fps: u32) -> Self {
+        Self {
+            fps_limit: fps_limit.filter(|fps| *fps > 0),
+            background_fps: background_fps.max(1),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_present_mode(&mut self, present_mode: PresentModeKHR) {
+        self.present_mode = Some(present_mode);
+        self.log_ignored_limit();
+    }
+
+    pub fn set_refresh_rate(&mut self, refresh_rate: Option<u32>) {
+        self.refresh_rate = refresh_rate;
+        self.log_ignored_limit();
+    }
+
+    // None while the monitor's refresh rate is unknown
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_rate.map(frame_budget)
+    }
+
+    // Takes effect from the next frame on, 0 or None means uncapped
+    pub fn set_fps_limit(&mut self, fps_limit: Option<u32>) {
+        self.fps_limit = fps_limit.filter(|fps| *fps > 0);
+        self.log_ignored_limit();
+        info!(target: logging::FRAME, "Frame pacing: {:?}", self.mode());
+    }
+
+    pub fn fps_limit(&self) -> Option<u32> {
+        self.fps_limit
+    }
+
+    pub fn set_throttled(&mut self, throttled: Option<BackgroundBehavior>) {
+        self.throttled = throttled;
+    }
+
+    pub fn set_low_latency(&mut self, low_latency: bool) {
+        self.low_latency = low_latency;
+    }
+
+    pub fn low_latency(&self) -> bool {
+        self.low_latency
+    }
+
+    fn vsync(&self) -> bool {
+        matches!(
+            self.present_mode,
+            Some(PresentModeKHR::FIFO) | Some(PresentModeKHR::FIFO_RELAXED)
+        )
+    }
+
+    // FIFO already caps at the refresh rate, a limit at or above it does nothing
+    fn limit_exceeds_refresh(&self) -> bool {
+        match (self.fps_limit, self.refresh_rate) {
+            (Some(fps_limit), Some(refresh_rate)) => self.vsync() && fps_limit >= refresh_rate,
+            _ => false,
+        }
+    }
+
+    fn log_ignored_limit(&self) {
+        if self.limit_exceeds_refresh() {
+            info!(
+                target: logging::FRAME,
+                "fps limit of {} is at or above the {} Hz refresh rate, vsync already limits the frame rate",
+                self.fps_limit.unwrap(),
+                self.refresh_rate.unwrap()
+            );
+        }
+    }
+
+    pub fn mode(&self) -> PacingMode {
+        match self.throttled {
+            Some(BackgroundBehavior::Throttle) => return PacingMode::Throttled,
+            Some(BackgroundBehavior::Pause) => return PacingMode::Paused,
+            Some(BackgroundBehavior::Continue) | None => {}
+        }
+        match self.fps_limit {
+            Some(_) if self.limit_exceeds_refresh() => PacingMode::Vsync,
+            Some(fps_limit) => PacingMode::Capped(fps_limit),
+            None if self.vsync() => PacingMode::Vsync,
+            None => PacingMode::Uncapped,
+        }
+    }
+
+    pub fn target_frame_time(&self) -> Option<Duration> {
+        match self.mode() {
+            PacingMode::Capped(fps_limit) => Some(frame_budget(fps_limit)),
+            PacingMode::Throttled => Some(frame_budget(self.background_fps)),
+            _ => None,
+        }
+    }
+
+    // Sleeps until shortly before the deadline and spins the remainder for
+    // sub-millisecond accuracy
+    pub fn wait(&mut self) {
+        span!("wait");
+        let Some(budget) = self.target_frame_time() else {
+            self.deadline = None;
+            return;
+        };
+        let now = Instant::now();
+        let deadline = next_deadline(self.deadline, budget, now);
+        self.deadline = Some(deadline);
+        let sleep_for = sleep_duration(budget, budget.saturating_sub(deadline - now));
+        if !sleep_for.is_zero() {
+            sleep(sleep_for);
+        }
+        while Instant::now() < deadline {
+            spin_loop();
+        }
+    }
+
+    // With vsync a frame starts right after the last one was presented and
+    // its image then waits for the display. In low latency mode the start is
+    // pushed back so that `work`, the last frame's CPU and GPU time, ends
+    // PRESENT_MARGIN before the next refresh instead
+    pub fn wait_for_present_margin(&self, work: Duration) {
+        if !self.low_latency || self.mode() != PacingMode::Vsync {
+            return;
+        }
+        let Some(refresh_rate) = self.refresh_rate else {
+            return;
+        };
+        let sleep_for = frame_budget(refresh_rate)
+            .saturating_sub(self.timer.since_last_frame())
+            .saturating_sub(work + PRESENT_MARGIN);
+        if !sleep_for.is_zero() {
+            span!("present_margin");
+            sleep(sleep_for);
+        }
+    }
+
+    // `recreations` counts swapchain rebuilds since startup
+    pub fn frame_finished(&mut self, recreations: u64) {
+        if let Some((fps, window)) = self.timer.tick() {
+            let recreation_rate =
+                (recreations - self.window_recreations) as f32 / window.as_secs_f32();
+            info!(
+                target: logging::FRAME,
+                "{:.1} fps ({:?}), {:.1} swapchain recreations/s",
+                fps,
+                self.mode(),
+                recreation_rate
+            );
+            self.fps = Some(fps);
+            self.window_recreations = recreations;
+            self.recreation_rate = Some(recreation_rate);
+        }
+    }
+
+    // Average over the last logging window, None until one has passed
+    pub fn fps(&self) -> Option<f32> {
+        self.fps
+    }
+
+    pub fn recreation_rate(&self) -> Option<f32> {
+        self.recreation_rate
+    }
+
+    // Wall clock time of the last frame, waiting included
+    pub fn frame_time(&self) -> Duration {
+        self.timer.frame_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Duration = Duration::from_micros(16_667);
+
+    #[test]
+    fn sleeps_until_the_spin_margin() {
+        let work = Duration::from_millis(5);
+        assert_eq!(sleep_duration(BUDGET, work), BUDGET - work - SPIN_MARGIN);
+    }
+
+    #[test]
+    fn no_sleep_close_to_or_past_the_budget() {
+        assert_eq!(
+            sleep_duration(BUDGET, BUDGET - SPIN_MARGIN / 2),
+            Duration::ZERO
+        );
+        assert_eq!(sleep_duration(BUDGET, BUDGET), Duration::ZERO);
+        assert_eq!(sleep_duration(BUDGET, BUDGET * 2), Duration::ZERO);
+    }
+
+    // Frames that take part of the budget to render still come out a budget
+    // apart, the render time isn't added on top
+    #[test]
+    fn deadlines_keep_the_frame_rate() {
+        let start = Instant::now();
+        let mut deadline = next_deadline(None, BUDGET, start);
+        assert_eq!(deadline, start);
+        for frame in 1..=120u32 {
+            let work = Duration::from_millis(u64::from(frame % 12));
+            let next = next_deadline(Some(deadline), BUDGET, deadline + work);
+            assert_eq!(next - deadline, BUDGET);
+            assert_eq!(
+                sleep_duration(BUDGET, work),
+                BUDGET.saturating_sub(work + SPIN_MARGIN)
+            );
+            deadline = next;
+        }
+        assert_eq!(deadline - start, BUDGET * 120);
+    }
+
+    #[test]
+    fn late_frames_restart_the_schedule() {
+        let start = Instant::now();
+        let late = start + BUDGET * 3;
+        assert_eq!(next_deadline(Some(start), BUDGET, late), late);
+        let next = next_deadline(Some(late), BUDGET, late + Duration::from_millis(4));
+        assert_eq!(next - late, BUDGET);
+    }
+}
@@ -0,0 +1,180 @@
+use cgmath::{vec3, InnerSpace, Point3, Vector3};
+
+use crate::engine::{debug_palette::DebugPalette, mesh::primitives, raycast::Ray, scene::ObjectId};
+
+// World space size of the sphere drawn at every point light
+pub const LIGHT_GIZMO_RADIUS: f32 = 0.08;
+// World space length of each axis of the tripod
+pub const AXIS_LENGTH: f32 = 0.6;
+// How close the cursor ray has to pass an axis to grab it, as a fraction of
+// the distance to the camera so handles stay as easy to hit when zoomed out
+const AXIS_PICK_TOLERANCE: f32 = 0.03;
+
+// What the axis tripod is attached to and drags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoTarget {
+    Object(ObjectId),
+    // Index into `Engine::lights`
+    Light(usize),
+}
+
+// A drag in progress along one world axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisDrag {
+    pub target: GizmoTarget,
+    // 0 x, 1 y, 2 z
+    pub axis: usize,
+    // Where along the axis the handle was grabbed, relative to the target's
+    // position, so the target doesn't jump to the cursor
+    pub grab_offset: f32,
+}
+
+// Parameters `s` and `t` of the closest points `a.origin + s * a.direction`
+// and `b.origin + t * b.direction` of two infinite lines. The directions
+// don't have to be normalized. None when the lines are parallel
+pub fn closest_line_parameters(a: &Ray, b: &Ray) -> Option<(f32, f32)> {
+    let offset = a.origin - b.origin;
+    let aa = a.direction.dot(a.direction);
+    let ab = a.direction.dot(b.direction);
+    let bb = b.direction.dot(b.direction);
+    let a_offset = a.direction.dot(offset);
+    let b_offset = b.direction.dot(offset);
+    let denominator = aa * bb - ab * ab;
+    if denominator.abs() <= f32::EPSILON * aa * bb {
+        return None;
+    }
+    let s = (ab * b_offset - bb * a_offset) / denominator;
+    let t = (aa * b_offset - ab * a_offset) / denominator;
+    Some((s, t))
+}
+
+pub fn axis_direction(axis: usize) -> Vector3<f32> {
+    let mut direction = vec3(0.0, 0.0, 0.0);
+    direction[axis] = 1.0;
+    direction
+}
+
+// The tripod axis the ray passes closest to within the pick tolerance, and
+// how far along it from `origin`
+pub fn pick_axis(ray: &Ray, origin: Point3<f32>) -> Option<(usize, f32)> {
+    (0..3)
+        .filter_map(|axis| {
+            let line = Ray {
+                origin,
+                direction: axis_direction(axis),
+            };
+            let (along_ray, along_axis) = closest_line_parameters(ray, &line)?;
+            if along_ray < 0.0 || !(0.0..=AXIS_LENGTH).contains(&along_axis) {
+                return None;
+            }
+            let miss = (ray.at(along_ray) - line.at(along_axis)).magnitude();
+            (miss <= AXIS_PICK_TOLERANCE * along_ray).then_some((axis, along_axis, miss))
+        })
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(axis, along_axis, _)| (axis, along_axis))
+}
+
+// Line list endpoints of the light's sphere in the light's color
+pub fn light_lines(position: Point3<f32>, color: [f32; 3]) -> Vec<(Point3<f32>, [f32; 3])> {
+    sphere_lines(position, LIGHT_GIZMO_RADIUS, color)
+}
+
+// Line list endpoints of a sphere, the edges of the UV sphere primitive
+pub fn sphere_lines(
+    center: Point3<f32>,
+    radius: f32,
+    color: [f32; 3],
+) -> Vec<(Point3<f32>, [f32; 3])> {
+    let (vertices, indices) = primitives::uv_sphere(radius, 8, 4);
+    let mut edges = indices
+        .chunks_exact(3)
+        .flat_map(|triangle| {
+            [
+                (triangle[0], triangle[1]),
+                (triangle[1], triangle[2]),
+                (triangle[2], triangle[0]),
+            ]
+        })
+        .map(|(a, b)| (a.min(b), a.max(b)))
+        .collect::<Vec<(u32, u32)>>();
+    edges.sort_unstable();
+    edges.dedup();
+    edges
+        .into_iter()
+        .flat_map(|(a, b)| [a, b])
+        .map(|index| (center + vertices[index as usize].position(), color))
+        .collect()
+}
+
+// Line list endpoints of the world aligned tripod at `origin`, `hovered`
+// is highlighted
+pub fn axis_lines(
+    origin: Point3<f32>,
+    hovered: Option<usize>,
+    palette: &DebugPalette,
+) -> Vec<(Point3<f32>, [f32; 3])> {
+    (0..3)
+        .flat_map(|axis| {
+            let color = if hovered == Some(axis) {
+                palette.hovered
+            } else {
+                palette.axes[axis]
+            };
+            [
+                (origin, color),
+                (origin + axis_direction(axis) * AXIS_LENGTH, color),
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::point3;
+
+    use super::*;
+
+    fn line(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+        Ray {
+            origin: origin.into(),
+            direction: direction.into(),
+        }
+    }
+
+    #[test]
+    fn parallel_lines_have_no_closest_points() {
+        let a = line([0.0, 0.0, 0.0], [1.0, 2.0, 0.0]);
+        for direction in [[1.0, 2.0, 0.0], [-3.0, -6.0, 0.0], [0.5, 1.0, 0.0]] {
+            let b = line([4.0, -1.0, 2.0], direction);
+            assert_eq!(closest_line_parameters(&a, &b), None);
+        }
+        assert_eq!(closest_line_parameters(&a, &a), None);
+    }
+
+    #[test]
+    fn skew_lines_meet_at_their_common_perpendicular() {
+        // Along x through the origin and along z one unit above it, with an
+        // unnormalized direction
+        let a = line([2.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let b = line([0.0, 1.0, 5.0], [0.0, 0.0, 2.0]);
+        let (s, t) = closest_line_parameters(&a, &b).unwrap();
+        assert_eq!((s, t), (-2.0, -2.5));
+        assert_eq!(a.at(s), point3(0.0, 0.0, 0.0));
+        assert_eq!(b.at(t), point3(0.0, 1.0, 0.0));
+
+        // Lines at an angle, the gap is perpendicular to both
+        let a = line([1.0, 2.0, 3.0], [1.0, 1.0, 0.0]);
+        let b = line([-2.0, 0.5, 1.0], [0.0, 1.0, 1.0]);
+        let (s, t) = closest_line_parameters(&a, &b).unwrap();
+        let gap = a.at(s) - b.at(t);
+        assert!(gap.dot(a.direction).abs() < 1e-5);
+        assert!(gap.dot(b.direction).abs() < 1e-5);
+    }
+
+    #[test]
+    fn crossing_lines_meet() {
+        let a = line([-3.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let b = line([0.0, -2.0, 0.0], [0.0, 1.0, 0.0]);
+        assert_eq!(closest_line_parameters(&a, &b), Some((3.0, 2.0)));
+    }
+}
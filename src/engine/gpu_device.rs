@@ -0,0 +1,900 @@
+use ash::{
+    prelude::VkResult,
+    vk::{
+        self, CommandBuffer, CommandBufferResetFlags, Fence, PipelineStageFlags, PresentIdKHR,
+        PresentInfoKHR, PresentTimeGOOGLE, PresentTimesInfoGOOGLE, Semaphore, SwapchainKHR,
+    },
+};
+
+use log::warn;
+
+use super::{
+    configuration::Configuration,
+    error::{EngineError, VkContext},
+    present_stats::PresentTimingSource,
+};
+use crate::logging::{self, span};
+
+// The device and swapchain calls the frame loop makes, so it can run against
+// something other than a real device
+pub trait GpuDevice {
+    // Blocks until every fence is signaled
+    fn wait_for_fences(&self, fences: &[Fence]) -> VkResult<()>;
+
+    fn reset_fences(&self, fences: &[Fence]) -> VkResult<()>;
+
+    fn reset_command_buffer(&self, command_buffer: CommandBuffer) -> VkResult<()>;
+
+    // Index of the next image and whether the swapchain is suboptimal
+    fn acquire_next_image(
+        &self,
+        swapchain: SwapchainKHR,
+        signal_semaphore: Semaphore,
+    ) -> VkResult<(u32, bool)>;
+
+    fn submit(
+        &self,
+        command_buffer: CommandBuffer,
+        wait_semaphore: Semaphore,
+        wait_stage: PipelineStageFlags,
+        signal_semaphore: Semaphore,
+        fence: Fence,
+    );
+
+    // True when the swapchain is suboptimal. `present_id` tags the present
+    // for timing feedback where the device reports any
+    fn present(
+        &self,
+        swapchain: SwapchainKHR,
+        image_index: u32,
+        wait_semaphore: Semaphore,
+        present_id: Option<u64>,
+    ) -> VkResult<bool>;
+}
+
+// The handles a surface uses for one frame slot
+#[derive(Debug, Clone, Copy)]
+pub struct FrameSlot {
+    pub swapchain: SwapchainKHR,
+    pub image_count: u32,
+    pub image_available: Semaphore,
+    pub in_flight: Fence,
+    pub command_buffer: CommandBuffer,
+}
+
+// What acquiring a slot's next image came to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acquired {
+    // The fence and command buffer are reset and ready for recording
+    Image { index: u32, suboptimal: bool },
+    // Nothing can be drawn until the swapchain is rebuilt
+    OutOfDate,
+    // An index past the swapchain's images. The fence is reset, the signaled
+    // semaphore still has to be waited on
+    OutOfRange(u32),
+}
+
+// One surface's share of a frame, from acquiring its image to presenting it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameContext {
+    // Slot of the fences, command buffers and uniform buffer in flight
+    pub frame_index: usize,
+    // Swapchain image, indexes framebuffers and render finished semaphores
+    pub image_index: u32,
+    // Of the swapchain the image was acquired from, the index means nothing
+    // once it has been rebuilt
+    pub generation: u64,
+    pub command_buffer: CommandBuffer,
+    // The image can still be presented but the swapchain should be rebuilt
+    pub suboptimal: bool,
+}
+
+// The engine's side of a frame, the work between the device calls below.
+// The frame loop only goes through this, so the tests run it on the mock
+pub trait FrameHost {
+    fn gpu(&self) -> &dyn GpuDevice;
+
+    fn surface_count(&self) -> usize;
+
+    fn is_minimized(&self, surface_index: usize) -> bool;
+
+    fn frame_slot(&self, surface_index: usize, frame_index: usize) -> FrameSlot;
+
+    fn swapchain_generation(&self, surface_index: usize) -> u64;
+
+    fn render_finished(&self, surface_index: usize, image_index: u32) -> Semaphore;
+
+    // Writes the slot's per frame buffers. Every surface's fence of the slot
+    // has been waited on
+    fn update_frame(&mut self, frame_index: usize);
+
+    fn record_frame(
+        &mut self,
+        surface_index: usize,
+        frame: &FrameContext,
+    ) -> Result<(), EngineError>;
+
+    // Whether the swapchain is due a rebuild after presenting, and the id the
+    // present is tagged with
+    fn prepare_present(&mut self, surface_index: usize, suboptimal: bool) -> (bool, Option<u64>);
+
+    // Submits nothing but the wait on `slot`'s semaphore and signals its fence
+    fn skip_frame(&mut self, slot: &FrameSlot);
+
+    fn recreate_swapchain(&mut self, surface_index: usize) -> Result<(), EngineError>;
+}
+
+// Slots are reused round robin
+pub fn next_frame(frame: u32, frames_in_flight: u32) -> u32 {
+    (frame + 1) % frames_in_flight
+}
+
+// The fence is only reset once an image was acquired, there is something to
+// submit that signals it again then
+pub fn acquire_frame(gpu: &dyn GpuDevice, slot: &FrameSlot) -> Result<Acquired, EngineError> {
+    let (index, suboptimal) = match gpu.acquire_next_image(slot.swapchain, slot.image_available) {
+        Ok(acquired) => acquired,
+        Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(Acquired::OutOfDate),
+        Err(result) => return Err(result).vk_ctx("vkAcquireNextImageKHR", "acquiring an image"),
+    };
+    gpu.reset_fences(&[slot.in_flight])
+        .vk_ctx("vkResetFences", "acquiring an image")?;
+    if index >= slot.image_count {
+        return Ok(Acquired::OutOfRange(index));
+    }
+    gpu.reset_command_buffer(slot.command_buffer)
+        .vk_ctx("vkResetCommandBuffer", "acquiring an image")?;
+    Ok(Acquired::Image { index, suboptimal })
+}
+
+// Whether the swapchain has to be rebuilt after presenting: it was `stale`
+// already, came back suboptimal or out of date, or exclusive fullscreen was
+// lost
+pub fn present_frame(
+    gpu: &dyn GpuDevice,
+    slot: &FrameSlot,
+    image_index: u32,
+    wait_semaphore: Semaphore,
+    present_id: Option<u64>,
+    stale: bool,
+) -> VkResult<bool> {
+    match gpu.present(slot.swapchain, image_index, wait_semaphore, present_id) {
+        Ok(suboptimal) => Ok(suboptimal || stale),
+        Err(
+            vk::Result::ERROR_OUT_OF_DATE_KHR
+            | vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT,
+        ) => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+// The uniform buffer of a frame slot is shared by every surface, so all of
+// their previous submissions have to be done with it
+pub fn wait_for_slot(host: &dyn FrameHost, frame_index: usize) -> Result<(), EngineError> {
+    let fences = (0..host.surface_count())
+        .map(|surface_index| host.frame_slot(surface_index, frame_index).in_flight)
+        .collect::<Vec<Fence>>();
+    host.gpu().wait_for_fences(&fences).vk_ctx(
+        "vkWaitForFences",
+        format_args!("waiting for frame slot {frame_index}"),
+    )
+}
+
+// Everything of a frame after `wait_for_slot`: the slot's buffers are
+// written, then every surface acquires, records, submits and presents
+pub fn draw_frame(host: &mut dyn FrameHost, frame_index: usize) -> Result<(), EngineError> {
+    host.update_frame(frame_index);
+    for surface_index in 0..host.surface_count() {
+        let Some(frame) = begin_frame(host, surface_index, frame_index)? else {
+            continue;
+        };
+        if let Err(err) = host.record_frame(surface_index, &frame) {
+            warn!(target: logging::FRAME, "Skipping frame: {err}");
+            host.skip_frame(&host.frame_slot(surface_index, frame_index));
+            host.recreate_swapchain(surface_index)?;
+            continue;
+        }
+        end_frame(host, surface_index, frame)?;
+    }
+    Ok(())
+}
+
+// Acquires the next image of a surface and readies the frame slot's fence
+// and command buffer for recording. None when there is nothing to draw to
+fn begin_frame(
+    host: &mut dyn FrameHost,
+    surface_index: usize,
+    frame_index: usize,
+) -> Result<Option<FrameContext>, EngineError> {
+    if host.is_minimized(surface_index) {
+        return Ok(None);
+    }
+    let slot = host.frame_slot(surface_index, frame_index);
+    let acquired = {
+        span!("acquire");
+        acquire_frame(host.gpu(), &slot)?
+    };
+    let (image_index, suboptimal) = match acquired {
+        Acquired::Image { index, suboptimal } => (index, suboptimal),
+        Acquired::OutOfDate => {
+            host.recreate_swapchain(surface_index)?;
+            return Ok(None);
+        }
+        Acquired::OutOfRange(index) => {
+            warn!(
+                target: logging::FRAME,
+                "Acquired image {index} of {}, rebuilding the swapchain",
+                slot.image_count
+            );
+            host.skip_frame(&slot);
+            host.recreate_swapchain(surface_index)?;
+            return Ok(None);
+        }
+    };
+    Ok(Some(FrameContext {
+        frame_index,
+        image_index,
+        generation: host.swapchain_generation(surface_index),
+        command_buffer: slot.command_buffer,
+        suboptimal,
+    }))
+}
+
+// Submits the recorded command buffer and presents, recreating the
+// swapchain afterwards if it no longer matches the surface
+fn end_frame(
+    host: &mut dyn FrameHost,
+    surface_index: usize,
+    frame: FrameContext,
+) -> Result<(), EngineError> {
+    let slot = host.frame_slot(surface_index, frame.frame_index);
+    if host.swapchain_generation(surface_index) != frame.generation {
+        warn!(
+            target: logging::FRAME,
+            "Swapchain was rebuilt while recording, dropping image {}",
+            frame.image_index
+        );
+        host.skip_frame(&slot);
+        return Ok(());
+    }
+    let render_finished = host.render_finished(surface_index, frame.image_index);
+    {
+        span!("submit");
+        host.gpu().submit(
+            frame.command_buffer,
+            slot.image_available,
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            render_finished,
+            slot.in_flight,
+        );
+    }
+    let (stale, present_id) = host.prepare_present(surface_index, frame.suboptimal);
+    let presented = {
+        span!("present");
+        present_frame(
+            host.gpu(),
+            &host.frame_slot(surface_index, frame.frame_index),
+            frame.image_index,
+            render_finished,
+            present_id,
+            stale,
+        )
+    };
+    if presented.vk_ctx("vkQueuePresentKHR", "presenting")? {
+        host.recreate_swapchain(surface_index)?;
+    }
+    Ok(())
+}
+
+impl GpuDevice for Configuration {
+    fn wait_for_fences(&self, fences: &[Fence]) -> VkResult<()> {
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .wait_for_fences(fences, true, u64::MAX)
+        }
+    }
+
+    fn reset_fences(&self, fences: &[Fence]) -> VkResult<()> {
+        unsafe { self.device.as_ref().unwrap().reset_fences(fences) }
+    }
+
+    fn reset_command_buffer(&self, command_buffer: CommandBuffer) -> VkResult<()> {
+        unsafe {
+            self.device
+                .as_ref()
+                .unwrap()
+                .reset_command_buffer(command_buffer, CommandBufferResetFlags::default())
+        }
+    }
+
+    fn acquire_next_image(
+        &self,
+        swapchain: SwapchainKHR,
+        signal_semaphore: Semaphore,
+    ) -> VkResult<(u32, bool)> {
+        unsafe {
+            self.swapchain_device.as_ref().unwrap().acquire_next_image(
+                swapchain,
+                u64::MAX,
+                signal_semaphore,
+                Fence::null(),
+            )
+        }
+    }
+
+    fn submit(
+        &self,
+        command_buffer: CommandBuffer,
+        wait_semaphore: Semaphore,
+        wait_stage: PipelineStageFlags,
+        signal_semaphore: Semaphore,
+        fence: Fence,
+    ) {
+        self.submit_frame(
+            command_buffer,
+            wait_semaphore,
+            wait_stage,
+            signal_semaphore,
+            fence,
+        );
+    }
+
+    fn present(
+        &self,
+        swapchain: SwapchainKHR,
+        image_index: u32,
+        wait_semaphore: Semaphore,
+        present_id: Option<u64>,
+    ) -> VkResult<bool> {
+        let wait_semaphores = [wait_semaphore];
+        let swapchains = [swapchain];
+        let image_indices = [image_index];
+        let mut present_info = PresentInfoKHR::default()
+            .wait_semaphores(&wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+        let present_ids = present_id.map_or([0], |id| [id]);
+        let mut present_id_info = PresentIdKHR::default().present_ids(&present_ids);
+        // Display timing ids are 32 bit, they only have to tell recent
+        // presents apart
+        let present_times = [PresentTimeGOOGLE::default().present_id(present_ids[0] as u32)];
+        let mut present_times_info = PresentTimesInfoGOOGLE::default().times(&present_times);
+        match (present_id, self.present_timing_source()) {
+            (Some(_), PresentTimingSource::PresentWait) => {
+                present_info = present_info.push_next(&mut present_id_info);
+            }
+            (Some(_), PresentTimingSource::DisplayTiming) => {
+                present_info = present_info.push_next(&mut present_times_info);
+            }
+            _ => {}
+        }
+        unsafe {
+            self.swapchain_device
+                .as_ref()
+                .unwrap()
+                .queue_present(self.presentation_queue.unwrap(), &present_info)
+        }
+    }
+}
+
+// Stands in for the device in tests. Every call is recorded, and the fences
+// are tracked the way the GPU would move them along, so waiting on a fence
+// nothing will signal or resetting one that is still in flight fails the test
+#[cfg(test)]
+pub mod mock {
+    use std::{
+        cell::{Cell, RefCell},
+        collections::{HashMap, VecDeque},
+    };
+
+    use ash::vk::Handle;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Call {
+        WaitForFences(Vec<Fence>),
+        ResetFences(Vec<Fence>),
+        ResetCommandBuffer(CommandBuffer),
+        Acquire(SwapchainKHR),
+        Submit {
+            command_buffer: CommandBuffer,
+            fence: Fence,
+        },
+        Present {
+            swapchain: SwapchainKHR,
+            image_index: u32,
+        },
+        // Recorded by the test for writes to a frame slot's buffers
+        UpdateUniforms(usize),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    enum FenceState {
+        // Fences are created signaled
+        #[default]
+        Signaled,
+        Reset,
+        // Submitted, the GPU is done once it is waited on
+        Pending(CommandBuffer),
+    }
+
+    // Swapchain images of every slot
+    pub const IMAGES: u32 = 3;
+
+    #[derive(Debug, Default)]
+    pub struct MockGpuDevice {
+        calls: RefCell<Vec<Call>>,
+        fences: RefCell<HashMap<Fence, FenceState>>,
+        next_image: Cell<u32>,
+        // Results of the next acquires and presents. Once they run out the
+        // images are handed out in turn and present fine
+        pub acquires: RefCell<VecDeque<VkResult<(u32, bool)>>>,
+        pub presents: RefCell<VecDeque<VkResult<bool>>>,
+    }
+
+    impl MockGpuDevice {
+        // Distinct handles for every slot
+        pub fn slot(frame_index: usize) -> FrameSlot {
+            let raw = frame_index as u64 + 1;
+            FrameSlot {
+                swapchain: SwapchainKHR::from_raw(1),
+                image_count: IMAGES,
+                image_available: Semaphore::from_raw(0x100 + raw),
+                in_flight: Fence::from_raw(0x200 + raw),
+                command_buffer: CommandBuffer::from_raw(0x300 + raw),
+            }
+        }
+
+        pub fn take_calls(&self) -> Vec<Call> {
+            std::mem::take(&mut self.calls.borrow_mut())
+        }
+
+        pub fn is_pending(&self, fence: Fence) -> bool {
+            matches!(self.state(fence), FenceState::Pending(_))
+        }
+
+        // Fences the GPU is still working on
+        pub fn pending_fences(&self) -> Vec<Fence> {
+            self.fences
+                .borrow()
+                .iter()
+                .filter(|(_, state)| matches!(state, FenceState::Pending(_)))
+                .map(|(fence, _)| *fence)
+                .collect()
+        }
+
+        // Only while the slot's previous submission is done with the buffers
+        pub fn update_uniforms(&self, frame_index: usize) {
+            let fence = Self::slot(frame_index).in_flight;
+            assert!(
+                !self.is_pending(fence),
+                "slot {frame_index} written while the GPU may read it"
+            );
+            self.calls
+                .borrow_mut()
+                .push(Call::UpdateUniforms(frame_index));
+        }
+
+        fn state(&self, fence: Fence) -> FenceState {
+            self.fences
+                .borrow()
+                .get(&fence)
+                .copied()
+                .unwrap_or_default()
+        }
+
+        fn set_state(&self, fence: Fence, state: FenceState) {
+            self.fences.borrow_mut().insert(fence, state);
+        }
+    }
+
+    impl GpuDevice for MockGpuDevice {
+        fn wait_for_fences(&self, fences: &[Fence]) -> VkResult<()> {
+            self.calls
+                .borrow_mut()
+                .push(Call::WaitForFences(fences.to_vec()));
+            for fence in fences {
+                assert_ne!(
+                    self.state(*fence),
+                    FenceState::Reset,
+                    "waiting on {fence:?} that nothing signals"
+                );
+                self.set_state(*fence, FenceState::Signaled);
+            }
+            Ok(())
+        }
+
+        fn reset_fences(&self, fences: &[Fence]) -> VkResult<()> {
+            self.calls
+                .borrow_mut()
+                .push(Call::ResetFences(fences.to_vec()));
+            for fence in fences {
+                assert!(!self.is_pending(*fence), "reset {fence:?} while in flight");
+                self.set_state(*fence, FenceState::Reset);
+            }
+            Ok(())
+        }
+
+        fn reset_command_buffer(&self, command_buffer: CommandBuffer) -> VkResult<()> {
+            self.calls
+                .borrow_mut()
+                .push(Call::ResetCommandBuffer(command_buffer));
+            assert!(
+                !self
+                    .fences
+                    .borrow()
+                    .values()
+                    .any(|state| *state == FenceState::Pending(command_buffer)),
+                "reset {command_buffer:?} while in flight"
+            );
+            Ok(())
+        }
+
+        fn acquire_next_image(
+            &self,
+            swapchain: SwapchainKHR,
+            _signal_semaphore: Semaphore,
+        ) -> VkResult<(u32, bool)> {
+            self.calls.borrow_mut().push(Call::Acquire(swapchain));
+            self.acquires.borrow_mut().pop_front().unwrap_or_else(|| {
+                let index = self.next_image.get();
+                self.next_image.set((index + 1) % IMAGES);
+                Ok((index, false))
+            })
+        }
+
+        fn submit(
+            &self,
+            command_buffer: CommandBuffer,
+            _wait_semaphore: Semaphore,
+            _wait_stage: PipelineStageFlags,
+            _signal_semaphore: Semaphore,
+            fence: Fence,
+        ) {
+            self.calls.borrow_mut().push(Call::Submit {
+                command_buffer,
+                fence,
+            });
+            assert_eq!(
+                self.state(fence),
+                FenceState::Reset,
+                "submitted with {fence:?} that isn't reset"
+            );
+            self.set_state(fence, FenceState::Pending(command_buffer));
+        }
+
+        fn present(
+            &self,
+            swapchain: SwapchainKHR,
+            image_index: u32,
+            _wait_semaphore: Semaphore,
+            _present_id: Option<u64>,
+        ) -> VkResult<bool> {
+            self.calls.borrow_mut().push(Call::Present {
+                swapchain,
+                image_index,
+            });
+            self.presents.borrow_mut().pop_front().unwrap_or(Ok(false))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::{
+        mock::{Call, MockGpuDevice, IMAGES},
+        *,
+    };
+
+    // One surface on the mock device, standing in for the engine. Counts
+    // the rebuilds and skipped frames the frame loop asks for
+    #[derive(Default)]
+    struct MockHost {
+        gpu: MockGpuDevice,
+        // Results of the next recordings, fine once they run out
+        records: VecDeque<Result<(), EngineError>>,
+        recreated: usize,
+        skipped: usize,
+    }
+
+    impl FrameHost for MockHost {
+        fn gpu(&self) -> &dyn GpuDevice {
+            &self.gpu
+        }
+
+        fn surface_count(&self) -> usize {
+            1
+        }
+
+        fn is_minimized(&self, _surface_index: usize) -> bool {
+            false
+        }
+
+        fn frame_slot(&self, _surface_index: usize, frame_index: usize) -> FrameSlot {
+            MockGpuDevice::slot(frame_index)
+        }
+
+        fn swapchain_generation(&self, _surface_index: usize) -> u64 {
+            self.recreated as u64
+        }
+
+        fn render_finished(&self, _surface_index: usize, _image_index: u32) -> Semaphore {
+            Semaphore::null()
+        }
+
+        fn update_frame(&mut self, frame_index: usize) {
+            self.gpu.update_uniforms(frame_index);
+        }
+
+        fn record_frame(
+            &mut self,
+            _surface_index: usize,
+            _frame: &FrameContext,
+        ) -> Result<(), EngineError> {
+            self.records.pop_front().unwrap_or(Ok(()))
+        }
+
+        fn prepare_present(
+            &mut self,
+            _surface_index: usize,
+            suboptimal: bool,
+        ) -> (bool, Option<u64>) {
+            (suboptimal, None)
+        }
+
+        // Signals the fence like the empty submit would
+        fn skip_frame(&mut self, slot: &FrameSlot) {
+            self.skipped += 1;
+            self.gpu.submit(
+                CommandBuffer::null(),
+                slot.image_available,
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                Semaphore::null(),
+                slot.in_flight,
+            );
+        }
+
+        fn recreate_swapchain(&mut self, _surface_index: usize) -> Result<(), EngineError> {
+            self.recreated += 1;
+            Ok(())
+        }
+    }
+
+    // A frame the way `Engine::draw_frame` makes it. True when the swapchain
+    // was recreated
+    fn run_frame(host: &mut MockHost, frame: u32) -> bool {
+        let recreated = host.recreated;
+        wait_for_slot(host, frame as usize).unwrap();
+        draw_frame(host, frame as usize).unwrap();
+        host.recreated > recreated
+    }
+
+    #[test]
+    fn frame_index_wraps_around() {
+        let frames = |frames_in_flight| {
+            std::iter::successors(Some(0), |frame| Some(next_frame(*frame, frames_in_flight)))
+                .take(6)
+                .collect::<Vec<u32>>()
+        };
+        assert_eq!(frames(1), [0, 0, 0, 0, 0, 0]);
+        assert_eq!(frames(2), [0, 1, 0, 1, 0, 1]);
+        assert_eq!(frames(3), [0, 1, 2, 0, 1, 2]);
+    }
+
+    // Each slot waits on its own fence, the one it submitted with
+    // `frames_in_flight` frames earlier
+    #[test]
+    fn frames_wait_on_their_slot() {
+        let mut host = MockHost::default();
+        let mut frame = 0;
+        let mut waited = Vec::new();
+        for _ in 0..6 {
+            assert!(!run_frame(&mut host, frame));
+            for call in host.gpu.take_calls() {
+                match call {
+                    Call::WaitForFences(fences) => waited.extend(fences),
+                    Call::Submit { fence, .. } => {
+                        assert_eq!(fence, MockGpuDevice::slot(frame as usize).in_flight)
+                    }
+                    _ => {}
+                }
+            }
+            frame = next_frame(frame, 2);
+        }
+        let slots = [0, 1, 0, 1, 0, 1].map(|frame| MockGpuDevice::slot(frame).in_flight);
+        assert_eq!(waited, slots);
+        // The last two frames are still in flight
+        assert_eq!(host.gpu.pending_fences().len(), 2);
+    }
+
+    #[test]
+    fn out_of_date_acquire_recreates_once() {
+        let mut host = MockHost::default();
+        host.gpu
+            .acquires
+            .borrow_mut()
+            .push_back(Err(vk::Result::ERROR_OUT_OF_DATE_KHR));
+        let recreated = (0..4)
+            .map(|frame| run_frame(&mut host, frame % 2))
+            .collect::<Vec<bool>>();
+        assert_eq!(recreated, [true, false, false, false]);
+        // Nothing was submitted in the skipped frame, so its fence was left
+        // signaled for the next wait
+        let calls = host.gpu.take_calls();
+        assert_eq!(
+            calls
+                .iter()
+                .filter(|call| matches!(call, Call::Submit { .. }))
+                .count(),
+            3
+        );
+        assert!(!calls[..3]
+            .iter()
+            .any(|call| matches!(call, Call::ResetFences(_))));
+    }
+
+    // A suboptimal acquire followed by an out of date present is one
+    // rebuild, not one for each
+    #[test]
+    fn out_of_date_present_recreates_once() {
+        let mut host = MockHost::default();
+        host.gpu.acquires.borrow_mut().push_back(Ok((0, true)));
+        host.gpu
+            .presents
+            .borrow_mut()
+            .push_back(Err(vk::Result::ERROR_OUT_OF_DATE_KHR));
+        let recreated = (0..4)
+            .map(|frame| run_frame(&mut host, frame % 2))
+            .filter(|recreated| *recreated)
+            .count();
+        assert_eq!(recreated, 1);
+    }
+
+    #[test]
+    fn present_results() {
+        let gpu = MockGpuDevice::default();
+        let slot = MockGpuDevice::slot(0);
+        let present = |result, stale| {
+            gpu.presents.borrow_mut().push_back(result);
+            present_frame(&gpu, &slot, 0, Semaphore::null(), None, stale)
+        };
+        assert_eq!(present(Ok(false), false), Ok(false));
+        assert_eq!(present(Ok(false), true), Ok(true));
+        assert_eq!(present(Ok(true), false), Ok(true));
+        assert_eq!(
+            present(
+                Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT),
+                false
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            present(Err(vk::Result::ERROR_DEVICE_LOST), false),
+            Err(vk::Result::ERROR_DEVICE_LOST)
+        );
+    }
+
+    #[test]
+    fn out_of_range_images_are_not_recorded() {
+        let gpu = MockGpuDevice::default();
+        let slot = MockGpuDevice::slot(0);
+        gpu.acquires.borrow_mut().push_back(Ok((IMAGES, false)));
+        assert_eq!(acquire_frame(&gpu, &slot), Ok(Acquired::OutOfRange(IMAGES)));
+        // The fence is reset for the empty submit that skips the frame
+        assert_eq!(
+            gpu.take_calls(),
+            [
+                Call::Acquire(slot.swapchain),
+                Call::ResetFences(vec![slot.in_flight]),
+            ]
+        );
+    }
+
+    // Out of date is the only acquire failure a rebuild fixes, anything
+    // else is passed on with the fence left alone
+    #[test]
+    fn lost_devices_fail_the_acquire() {
+        let mut host = MockHost::default();
+        let slot = MockGpuDevice::slot(0);
+        host.gpu
+            .acquires
+            .borrow_mut()
+            .push_back(Err(vk::Result::ERROR_DEVICE_LOST));
+        assert_eq!(
+            acquire_frame(&host.gpu, &slot),
+            Err(EngineError::Vulkan {
+                op: "vkAcquireNextImageKHR",
+                detail: "acquiring an image".to_string(),
+                result: vk::Result::ERROR_DEVICE_LOST,
+            })
+        );
+        assert_eq!(host.gpu.take_calls(), [Call::Acquire(slot.swapchain)]);
+
+        host.gpu
+            .acquires
+            .borrow_mut()
+            .push_back(Err(vk::Result::ERROR_DEVICE_LOST));
+        wait_for_slot(&host, 0).unwrap();
+        assert!(draw_frame(&mut host, 0).is_err());
+        assert_eq!(host.recreated, 0);
+    }
+
+    // The slot's fence is still signaled by the empty submit, so the next
+    // frame in the slot doesn't wait forever
+    #[test]
+    fn out_of_range_images_skip_the_frame() {
+        let mut host = MockHost::default();
+        host.gpu
+            .acquires
+            .borrow_mut()
+            .push_back(Ok((IMAGES, false)));
+        assert!(run_frame(&mut host, 0));
+        assert_eq!(host.skipped, 1);
+        assert!(!run_frame(&mut host, 0));
+        assert_eq!(host.skipped, 1);
+    }
+
+    #[test]
+    fn failed_recordings_skip_the_frame_and_rebuild() {
+        let mut host = MockHost::default();
+        host.records
+            .push_back(Err(EngineError::ImageIndexOutOfRange {
+                index: 0,
+                framebuffers: 0,
+            }));
+        assert!(run_frame(&mut host, 0));
+        assert_eq!(host.skipped, 1);
+        let slot = MockGpuDevice::slot(0);
+        // Nothing recorded is submitted or presented
+        assert!(!host.gpu.take_calls().iter().any(|call| matches!(
+            call,
+            Call::Submit { command_buffer, .. } if *command_buffer == slot.command_buffer
+        ) || matches!(
+            call,
+            Call::Present { .. }
+        )));
+        assert!(!run_frame(&mut host, 0));
+    }
+
+    // The per frame buffers of a slot are written once its fence was waited
+    // on and before the image is acquired, and submitted after
+    #[test]
+    fn uniforms_are_written_between_wait_and_submit() {
+        let mut host = MockHost::default();
+        for (image_index, frame) in [0, 1, 0].into_iter().enumerate() {
+            run_frame(&mut host, frame);
+            let slot = MockGpuDevice::slot(frame as usize);
+            assert_eq!(
+                host.gpu.take_calls(),
+                [
+                    Call::WaitForFences(vec![slot.in_flight]),
+                    Call::UpdateUniforms(frame as usize),
+                    Call::Acquire(slot.swapchain),
+                    Call::ResetFences(vec![slot.in_flight]),
+                    Call::ResetCommandBuffer(slot.command_buffer),
+                    Call::Submit {
+                        command_buffer: slot.command_buffer,
+                        fence: slot.in_flight,
+                    },
+                    Call::Present {
+                        swapchain: slot.swapchain,
+                        image_index: image_index as u32,
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "written while the GPU may read it")]
+    fn uniforms_of_a_slot_in_flight_are_rejected() {
+        let mut host = MockHost::default();
+        run_frame(&mut host, 0);
+        host.gpu.update_uniforms(0);
+    }
+}
@@ -0,0 +1,45 @@
+use ash::vk::Extent2D;
+use log::Level;
+
+use crate::{
+    engine::{
+        debug_palette::DebugPalette,
+        text::{self, Align, TextBox, TextRows},
+    },
+    logging,
+};
+
+// Rows of wrapped log text shown at most, the newest at the bottom
+pub const LOG_VIEW_ROWS: usize = 12;
+// Top left corner and width of the view, as fractions of the window with y up
+const LOG_VIEW_ORIGIN: [f32; 2] = [0.02, 0.98];
+const LOG_VIEW_WIDTH: f32 = 0.96;
+const LOG_VIEW_SCALE: f32 = 1.5;
+
+// The last log records wrapped to the window's width and where they go,
+// errors and warnings in the palette's warning and caution colors
+pub(crate) fn text(palette: &DebugPalette, window: Extent2D) -> (TextBox, TextRows) {
+    let columns = text::columns_in(window.width as f32 * LOG_VIEW_WIDTH, LOG_VIEW_SCALE);
+    let mut rows = Vec::new();
+    for record in logging::recent(LOG_VIEW_ROWS) {
+        let color = match record.level {
+            Level::Error => palette.warning,
+            Level::Warn => palette.caution,
+            _ => palette.text,
+        };
+        let message = format!("{:<5} {}", record.level, record.message);
+        rows.extend(
+            text::wrap(&message, columns)
+                .into_iter()
+                .map(|line| (line, color)),
+        );
+    }
+    let text_box = TextBox {
+        origin: LOG_VIEW_ORIGIN,
+        columns,
+        align: Align::Left,
+        scale: LOG_VIEW_SCALE,
+    };
+    rows.drain(..rows.len().saturating_sub(LOG_VIEW_ROWS));
+    (text_box, rows)
+}
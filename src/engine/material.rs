@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use crate::engine::configuration::{RenderQueue, TextureResource};
+
+// A glTF metallic-roughness material. Every factor multiplies its texture,
+// a missing texture counts as white, or as a flat normal for `normal_texture`
+#[derive(Debug, Clone)]
+pub struct Material {
+    // Linear RGBA, alpha is written out as the surface's alpha
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    // Linear RGB added on top of the lit surface
+    pub emissive_factor: [f32; 3],
+    // Scales the x and y of the sampled tangent space normal
+    pub normal_scale: f32,
+    // 0 ignores the occlusion texture, 1 applies it fully
+    pub occlusion_strength: f32,
+    // sRGB
+    pub base_color_texture: Option<Arc<TextureResource>>,
+    // Roughness in green and metalness in blue, linear. glTF files often pack
+    // occlusion into red of the same image
+    pub metallic_roughness_texture: Option<Arc<TextureResource>>,
+    // Tangent space, linear
+    pub normal_texture: Option<Arc<TextureResource>>,
+    // Red channel, linear
+    pub occlusion_texture: Option<Arc<TextureResource>>,
+    // sRGB
+    pub emissive_texture: Option<Arc<TextureResource>>,
+    // When and how objects with the material are drawn
+    pub queue: RenderQueue,
+    // Within the overlay queue higher priorities are drawn later, on top
+    pub priority: u16,
+}
+
+// glTF's defaults, a white fully metallic and fully rough surface
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0; 4],
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            emissive_factor: [0.0; 3],
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+            queue: RenderQueue::Opaque,
+            priority: 0,
+        }
+    }
+}
+
+impl Material {
+    // An untextured material
+    pub fn new(base_color: [f32; 3], metallic: f32, roughness: f32) -> Self {
+        Self {
+            base_color_factor: [base_color[0], base_color[1], base_color[2], 1.0],
+            metallic_factor: metallic,
+            roughness_factor: roughness,
+            ..Default::default()
+        }
+    }
+}
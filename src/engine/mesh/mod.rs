@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+
+use cgmath::Vector3;
+
+pub use crate::engine::configuration::buffer_types::vertex::Vertex;
+
+pub mod primitives;
+pub mod simplify;
+
+// Shapes generated on the CPU instead of read from a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Primitive {
+    Cube {
+        size: f32,
+    },
+    UvSphere {
+        radius: f32,
+        slices: u32,
+        stacks: u32,
+    },
+    Plane {
+        size: f32,
+        subdivisions: u32,
+    },
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+    },
+}
+
+impl Primitive {
+    pub fn generate(&self) -> (Vec<Vertex>, Vec<u32>) {
+        match *self {
+            Primitive::Cube { size } => primitives::cube(size),
+            Primitive::UvSphere {
+                radius,
+                slices,
+                stacks,
+            } => primitives::uv_sphere(radius, slices, stacks),
+            Primitive::Plane { size, subdivisions } => primitives::plane(size, subdivisions),
+            Primitive::Torus {
+                major_radius,
+                minor_radius,
+                major_segments,
+                minor_segments,
+            } => primitives::torus(major_radius, minor_radius, major_segments, minor_segments),
+        }
+    }
+}
+
+// What a mesh keeps on the CPU once its buffers are uploaded. Meshes
+// from the same file or primitive are shared, a request for more than the
+// cached mesh kept uploads it again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CpuMeshData {
+    // Only the bounds, ray casts hit the bounding box
+    Discard,
+    // Every vertex attribute and the indices
+    Keep,
+    // Positions and indices, enough for exact ray casts
+    #[default]
+    KeepPositionsOnly,
+}
+
+impl CpuMeshData {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "discard" => Some(CpuMeshData::Discard),
+            "keep" => Some(CpuMeshData::Keep),
+            "positions" => Some(CpuMeshData::KeepPositionsOnly),
+            _ => None,
+        }
+    }
+
+    // Whether a mesh kept with `self` has everything `other` keeps
+    pub fn covers(self, other: CpuMeshData) -> bool {
+        match self {
+            CpuMeshData::Keep => true,
+            CpuMeshData::KeepPositionsOnly => other != CpuMeshData::Keep,
+            CpuMeshData::Discard => other == CpuMeshData::Discard,
+        }
+    }
+}
+
+// What an object is drawn with. `Model` is the scene's model from
+// `EngineConfig::model_path`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum MeshSource {
+    #[default]
+    Model,
+    Path(PathBuf),
+    Primitive(Primitive),
+    // Built by the app, uploaded for this object alone
+    Geometry {
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        morph_targets: Vec<MorphTarget>,
+    },
+}
+
+// A blend shape, offsets added to every vertex scaled by the target's weight
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MorphTarget {
+    // One per vertex
+    pub positions: Vec<Vector3<f32>>,
+    // One per vertex, or empty to leave the normals alone
+    pub normals: Vec<Vector3<f32>>,
+}
+
+impl From<Primitive> for MeshSource {
+    fn from(primitive: Primitive) -> Self {
+        MeshSource::Primitive(primitive)
+    }
+}
+
+impl From<PathBuf> for MeshSource {
+    fn from(path: PathBuf) -> Self {
+        MeshSource::Path(path)
+    }
+}
+
+impl From<&Path> for MeshSource {
+    fn from(path: &Path) -> Self {
+        MeshSource::Path(path.to_path_buf())
+    }
+}
@@ -0,0 +1,212 @@
+use std::f32::consts::{PI, TAU};
+
+use cgmath::{vec2, vec3, InnerSpace, Vector3};
+
+use super::Vertex;
+
+// Triangles wind counter-clockwise seen from outside, matching the pipeline's
+// front face. Texture coordinates have v pointing down like the OBJ loader's
+
+const WHITE: Vector3<f32> = vec3(1.0, 1.0, 1.0);
+
+fn vertex(pos: Vector3<f32>, normal: Vector3<f32>, u: f32, v: f32) -> Vertex {
+    Vertex::new(pos, WHITE, vec2(u, v)).with_normal(normal)
+}
+
+// Quads between the rows of a (columns + 1) wide grid of vertices
+fn grid_indices(columns: u32, rows: u32, mut quad: impl FnMut(u32, [u32; 4])) {
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = row * (columns + 1) + column;
+            let b = a + columns + 1;
+            quad(row, [a, a + 1, b, b + 1]);
+        }
+    }
+}
+
+// Centered on the origin, each face has its own vertices so the normals stay
+// flat
+pub fn cube(size: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let half = size / 2.0;
+    // Normal and the two axes across the face, u x v = normal
+    let faces = [
+        (Vector3::unit_x(), -Vector3::unit_z(), Vector3::unit_y()),
+        (-Vector3::unit_x(), Vector3::unit_z(), Vector3::unit_y()),
+        (Vector3::unit_y(), Vector3::unit_x(), -Vector3::unit_z()),
+        (-Vector3::unit_y(), Vector3::unit_x(), Vector3::unit_z()),
+        (Vector3::unit_z(), Vector3::unit_x(), Vector3::unit_y()),
+        (-Vector3::unit_z(), -Vector3::unit_x(), Vector3::unit_y()),
+    ];
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, u, v) in faces {
+        let first = vertices.len() as u32;
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let pos = (normal + u * x + v * y) * half;
+            vertices.push(vertex(pos, normal, (x + 1.0) / 2.0, (1.0 - y) / 2.0));
+        }
+        indices.extend([0, 1, 2, 0, 2, 3].map(|index| first + index));
+    }
+    (vertices, indices)
+}
+
+// Poles on the y axis. The seam is duplicated so the texture wraps once
+pub fn uv_sphere(radius: f32, slices: u32, stacks: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let (slices, stacks) = (slices.max(3), stacks.max(2));
+    let mut vertices = Vec::with_capacity(((slices + 1) * (stacks + 1)) as usize);
+    for stack in 0..=stacks {
+        let v = stack as f32 / stacks as f32;
+        let (sin_phi, cos_phi) = (v * PI).sin_cos();
+        for slice in 0..=slices {
+            let u = slice as f32 / slices as f32;
+            let (sin_theta, cos_theta) = (u * TAU).sin_cos();
+            let normal = vec3(sin_phi * cos_theta, cos_phi, -sin_phi * sin_theta);
+            vertices.push(vertex(normal * radius, normal, u, v));
+        }
+    }
+    let mut indices = Vec::new();
+    grid_indices(slices, stacks, |stack, [a, a1, b, b1]| {
+        // The triangles touching a pole would have no area
+        if stack != 0 {
+            indices.extend([a, b, a1]);
+        }
+        if stack != stacks - 1 {
+            indices.extend([a1, b, b1]);
+        }
+    });
+    (vertices, indices)
+}
+
+// Flat on the xz plane facing +y, `subdivisions` quads along each side
+pub fn plane(size: f32, subdivisions: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let subdivisions = subdivisions.max(1);
+    let half = size / 2.0;
+    let mut vertices = Vec::with_capacity(((subdivisions + 1) * (subdivisions + 1)) as usize);
+    for row in 0..=subdivisions {
+        let v = row as f32 / subdivisions as f32;
+        for column in 0..=subdivisions {
+            let u = column as f32 / subdivisions as f32;
+            let pos = vec3(u * size - half, 0.0, v * size - half);
+            vertices.push(vertex(pos, Vector3::unit_y(), u, v));
+        }
+    }
+    let mut indices = Vec::new();
+    grid_indices(subdivisions, subdivisions, |_, [a, a1, b, b1]| {
+        indices.extend([a, b, a1, a1, b, b1]);
+    });
+    (vertices, indices)
+}
+
+// Lying on the xz plane around the y axis. `major_segments` go around the
+// ring, `minor_segments` around the tube
+pub fn torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+    let mut vertices = Vec::with_capacity(((major_segments + 1) * (minor_segments + 1)) as usize);
+    for minor in 0..=minor_segments {
+        let v = minor as f32 / minor_segments as f32;
+        let (sin_phi, cos_phi) = (v * TAU).sin_cos();
+        for major in 0..=major_segments {
+            let u = major as f32 / major_segments as f32;
+            let (sin_theta, cos_theta) = (u * TAU).sin_cos();
+            let direction = vec3(cos_theta, 0.0, -sin_theta);
+            let normal = (direction * cos_phi + Vector3::unit_y() * sin_phi).normalize();
+            let pos = direction * major_radius + normal * minor_radius;
+            vertices.push(vertex(pos, normal, u, v));
+        }
+    }
+    let mut indices = Vec::new();
+    grid_indices(major_segments, minor_segments, |_, [a, a1, b, b1]| {
+        indices.extend([a, a1, b, a1, b1, b]);
+    });
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    type Key = [i32; 3];
+
+    // Seams and poles duplicate vertices, edges are matched by position
+    fn key(vertex: &Vertex) -> Key {
+        let pos = vertex.position();
+        [pos.x, pos.y, pos.z].map(|coord| (coord * 1e4).round() as i32)
+    }
+
+    // Directed edges used by no triangle in the other direction
+    fn open_edges((vertices, indices): &(Vec<Vertex>, Vec<u32>)) -> usize {
+        let mut edges = HashMap::<(Key, Key), i32>::new();
+        for triangle in indices.chunks(3) {
+            for corner in 0..3 {
+                let from = key(&vertices[triangle[corner] as usize]);
+                let to = key(&vertices[triangle[(corner + 1) % 3] as usize]);
+                *edges.entry((from, to)).or_default() += 1;
+                *edges.entry((to, from)).or_default() -= 1;
+            }
+        }
+        edges.values().filter(|count| **count > 0).count()
+    }
+
+    // Unit normals, and triangles wound counter-clockwise around them
+    fn check_normals((vertices, indices): &(Vec<Vertex>, Vec<u32>)) {
+        for vertex in vertices {
+            assert!((vertex.normal().magnitude() - 1.0).abs() < 1e-5);
+        }
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| vertices[triangle[corner] as usize]);
+            let face = (b.position() - a.position()).cross(c.position() - a.position());
+            assert!(face.magnitude() > 0.0, "degenerate triangle {triangle:?}");
+            let normal = a.normal() + b.normal() + c.normal();
+            assert!(
+                face.dot(normal) > 0.0,
+                "triangle {triangle:?} faces inwards"
+            );
+        }
+    }
+
+    #[test]
+    fn cubes_are_closed_with_flat_faces() {
+        let cube = cube(2.0);
+        assert_eq!((cube.0.len(), cube.1.len()), (24, 36));
+        assert_eq!(open_edges(&cube), 0);
+        check_normals(&cube);
+    }
+
+    #[test]
+    fn spheres_are_closed_without_pole_slivers() {
+        let (slices, stacks) = (8, 5);
+        let sphere = uv_sphere(1.5, slices, stacks);
+        assert_eq!(sphere.0.len(), 9 * 6);
+        assert_eq!(
+            sphere.1.len(),
+            3 * slices as usize * (2 * stacks as usize - 2)
+        );
+        assert_eq!(open_edges(&sphere), 0);
+        check_normals(&sphere);
+        // Too few segments are raised to the minimum
+        assert_eq!(uv_sphere(1.0, 0, 0).0.len(), 4 * 3);
+    }
+
+    #[test]
+    fn planes_are_open_along_their_border() {
+        let plane = plane(4.0, 3);
+        assert_eq!((plane.0.len(), plane.1.len()), (16, 54));
+        assert_eq!(open_edges(&plane), 4 * 3);
+        check_normals(&plane);
+    }
+
+    #[test]
+    fn tori_are_closed() {
+        let torus = torus(1.0, 0.25, 12, 6);
+        assert_eq!((torus.0.len(), torus.1.len()), (13 * 7, 6 * 12 * 6));
+        assert_eq!(open_edges(&torus), 0);
+        check_normals(&torus);
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use cgmath::{InnerSpace, Vector3};
+
+// Meshes with fewer triangles are drawn at full detail at any distance
+pub const LOD_MIN_TRIANGLES: usize = 256;
+// Every level aims for this fraction of the previous one's triangles
+const LOD_RATIO: f32 = 0.5;
+// Levels stop once one keeps more than this fraction of the previous one,
+// the rest of the mesh is locked in place
+const MIN_REDUCTION: f32 = 0.8;
+
+// Up to `levels` index lists at decreasing detail, each about half of the
+// one before. Edges are collapsed by their quadric error onto one of their
+// vertices, so the lists index the original vertices and share the vertex
+// buffer. Vertices on open edges, UV seams included, never move
+pub fn simplify_lods(positions: &[Vector3<f32>], indices: &[u32], levels: usize) -> Vec<Vec<u32>> {
+    if indices.len() / 3 < LOD_MIN_TRIANGLES {
+        return Vec::new();
+    }
+    let mut simplifier = Simplifier::new(positions, indices);
+    let mut previous = simplifier.live;
+    let mut lods = Vec::new();
+    for _ in 0..levels {
+        simplifier.collapse_to((previous as f32 * LOD_RATIO) as usize);
+        if simplifier.live == 0 || simplifier.live as f32 > previous as f32 * MIN_REDUCTION {
+            break;
+        }
+        previous = simplifier.live;
+        lods.push(simplifier.indices());
+    }
+    lods
+}
+
+// Sum of squared distances to a set of planes, the upper triangle of the
+// symmetric 4x4 matrix
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(normal: Vector3<f64>, distance: f64, weight: f64) -> Self {
+        let [a, b, c, d] = [normal.x, normal.y, normal.z, distance];
+        Quadric(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|term| term * weight),
+        )
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (term, other) in self.0.iter_mut().zip(other.0) {
+            *term += other;
+        }
+    }
+
+    fn error(&self, point: Vector3<f64>) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        let Vector3 { x, y, z } = point;
+        x * x * aa
+            + 2.0 * x * y * ab
+            + 2.0 * x * z * ac
+            + 2.0 * x * ad
+            + y * y * bb
+            + 2.0 * y * z * bc
+            + 2.0 * y * bd
+            + z * z * cc
+            + 2.0 * z * cd
+            + dd
+    }
+}
+
+// Moving `from` onto `to`, valid while neither vertex changed since
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, the heap pops the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+struct Simplifier {
+    positions: Vec<Vector3<f64>>,
+    quadrics: Vec<Quadric>,
+    triangles: Vec<[u32; 3]>,
+    removed: Vec<bool>,
+    // Triangles around every vertex, removed ones are dropped lazily
+    adjacency: Vec<Vec<u32>>,
+    locked: Vec<bool>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+    live: usize,
+}
+
+impl Simplifier {
+    fn new(positions: &[Vector3<f32>], indices: &[u32]) -> Self {
+        let positions = positions
+            .iter()
+            .map(|position| position.cast::<f64>().unwrap())
+            .collect::<Vec<Vector3<f64>>>();
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .filter(|[a, b, c]| {
+                a != b
+                    && b != c
+                    && a != c
+                    && [a, b, c]
+                        .iter()
+                        .all(|index| (**index as usize) < positions.len())
+            })
+            .collect::<Vec<[u32; 3]>>();
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut adjacency = vec![Vec::new(); positions.len()];
+        let mut edges = HashMap::<(u32, u32), u32>::new();
+        for (index, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = triangle.map(|vertex| positions[vertex as usize]);
+            let normal = (b - a).cross(c - a);
+            let area = normal.magnitude();
+            if area > 0.0 {
+                let normal = normal / area;
+                let quadric = Quadric::plane(normal, -normal.dot(a), area * 0.5);
+                for vertex in triangle {
+                    quadrics[*vertex as usize].add(&quadric);
+                }
+            }
+            for (corner, vertex) in triangle.iter().enumerate() {
+                adjacency[*vertex as usize].push(index as u32);
+                let next = triangle[(corner + 1) % 3];
+                *edges
+                    .entry(((*vertex).min(next), (*vertex).max(next)))
+                    .or_default() += 1;
+            }
+        }
+        // Open and non-manifold edges keep their vertices
+        let mut locked = vec![false; positions.len()];
+        for ((a, b), count) in &edges {
+            if *count != 2 {
+                locked[*a as usize] = true;
+                locked[*b as usize] = true;
+            }
+        }
+        let live = triangles.len();
+        let mut simplifier = Simplifier {
+            versions: vec![0; positions.len()],
+            positions,
+            quadrics,
+            removed: vec![false; triangles.len()],
+            triangles,
+            adjacency,
+            locked,
+            heap: BinaryHeap::new(),
+            live,
+        };
+        // Sorted so equal costs collapse in the same order on every load
+        let mut edges = edges.into_keys().collect::<Vec<(u32, u32)>>();
+        edges.sort_unstable();
+        for (a, b) in edges {
+            simplifier.push_collapse(a, b);
+        }
+        simplifier
+    }
+
+    // The cheaper direction of the edge, if either vertex may move
+    fn push_collapse(&mut self, a: u32, b: u32) {
+        let mut quadric = self.quadrics[a as usize];
+        quadric.add(&self.quadrics[b as usize]);
+        let collapse = [(a, b), (b, a)]
+            .into_iter()
+            .filter(|(from, _)| !self.locked[*from as usize])
+            .map(|(from, to)| Collapse {
+                cost: quadric.error(self.positions[to as usize]),
+                from,
+                to,
+                versions: (self.versions[from as usize], self.versions[to as usize]),
+            })
+            .min_by(|first, second| first.cost.total_cmp(&second.cost));
+        if let Some(collapse) = collapse {
+            self.heap.push(collapse);
+        }
+    }
+
+    fn live_triangles(&self, vertex: u32) -> impl Iterator<Item = u32> + '_ {
+        self.adjacency[vertex as usize]
+            .iter()
+            .copied()
+            .filter(|triangle| !self.removed[*triangle as usize])
+    }
+
+    fn neighbors(&self, vertex: u32) -> Vec<u32> {
+        let mut neighbors = self
+            .live_triangles(vertex)
+            .flat_map(|triangle| self.triangles[triangle as usize])
+            .filter(|neighbor| *neighbor != vertex)
+            .collect::<Vec<u32>>();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    // Whether the edge can collapse without pinching the surface or folding
+    // a triangle over
+    fn is_valid(&self, Collapse { from, to, .. }: &Collapse) -> bool {
+        // Only the two triangles on the edge may lose a corner, more common
+        // neighbors would make the surface non-manifold
+        let shared = self
+            .live_triangles(*from)
+            .filter(|triangle| self.triangles[*triangle as usize].contains(to))
+            .count();
+        let to_neighbors = self.neighbors(*to);
+        let common = self
+            .neighbors(*from)
+            .iter()
+            .filter(|neighbor| to_neighbors.binary_search(neighbor).is_ok())
+            .count();
+        if shared == 0 || common != shared {
+            return false;
+        }
+        let target = self.positions[*to as usize];
+        self.live_triangles(*from).all(|triangle| {
+            let corners = self.triangles[triangle as usize];
+            if corners.contains(to) {
+                return true;
+            }
+            let [a, b, c] = corners.map(|vertex| self.positions[vertex as usize]);
+            let [moved_a, moved_b, moved_c] = corners.map(|vertex| {
+                if vertex == *from {
+                    target
+                } else {
+                    self.positions[vertex as usize]
+                }
+            });
+            let before = (b - a).cross(c - a);
+            let after = (moved_b - moved_a).cross(moved_c - moved_a);
+            before.dot(after) > 0.0
+        })
+    }
+
+    fn collapse_to(&mut self, target: usize) {
+        while self.live > target {
+            let Some(collapse) = self.heap.pop() else {
+                return;
+            };
+            let (from, to) = (collapse.from as usize, collapse.to as usize);
+            if collapse.versions != (self.versions[from], self.versions[to])
+                || !self.is_valid(&collapse)
+            {
+                continue;
+            }
+            for triangle in std::mem::take(&mut self.adjacency[from]) {
+                if self.removed[triangle as usize] {
+                    continue;
+                }
+                let corners = &mut self.triangles[triangle as usize];
+                if corners.contains(&collapse.to) {
+                    self.removed[triangle as usize] = true;
+                    self.live -= 1;
+                } else {
+                    corners
+                        .iter_mut()
+                        .filter(|vertex| **vertex == collapse.from)
+                        .for_each(|vertex| *vertex = collapse.to);
+                    self.adjacency[to].push(triangle);
+                }
+            }
+            let quadric = self.quadrics[from];
+            self.quadrics[to].add(&quadric);
+            self.versions[from] += 1;
+            self.versions[to] += 1;
+            let removed = &self.removed;
+            self.adjacency[to].retain(|triangle| !removed[*triangle as usize]);
+            for neighbor in self.neighbors(collapse.to) {
+                self.push_collapse(collapse.to, neighbor);
+            }
+        }
+    }
+
+    fn indices(&self) -> Vec<u32> {
+        self.triangles
+            .iter()
+            .zip(&self.removed)
+            .filter(|(_, removed)| !**removed)
+            .flat_map(|(triangle, _)| *triangle)
+            .collect()
+    }
+}
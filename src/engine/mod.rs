@@ -1,235 +1,2094 @@
-use std::ops::Add;
-use std::time::Instant;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use ash::vk::CommandBufferResetFlags;
-use ash::vk::{Handle, MemoryMapFlags, PipelineStageFlags, PresentInfoKHR, SubmitInfo};
-use cgmath::{perspective, point3, vec3, Deg, Matrix4};
+use animation::{AnimationTarget, Animator, Transform};
+use ash::vk;
+use ash::vk::{Extent2D, Offset2D, Rect2D, Semaphore};
+use cgmath::{point3, vec3, Deg, EuclideanSpace, Matrix4, Point3, Vector3};
 use configuration::buffer_types::uniform_buffer_types::UniformBufferObject;
-use log::{debug, error};
+use configuration::{active_morph_targets, MAX_ACTIVE_MORPH_TARGETS};
+use debug_draw::{DebugDraw, DEBUG_RAY_LENGTH};
+use debug_palette::DebugPalette;
+use gizmo::{AxisDrag, GizmoTarget};
+use log::{error, info, log, warn, Level};
+use material::Material;
+use mesh::{CpuMeshData, MeshSource};
+use present_stats::PresentStats;
+use raycast::{Ray, RayHit};
+use rng::Rng;
+use scene::{Aabb, Camera, LayerMasks, ObjectId, PointLight, RenderObject, Scene, ScreenRect};
+use sdf_font::GlyphVertices;
+use selection::{removed_ids, Selection};
+use skinning::{Skeleton, Skin};
+use spatial::UniformGrid;
+use stats_graph::FrameTimeGraph;
+use text::TextRows;
+use toast::Toasts;
+use viewport::{aspect_ratio, pixel_ndc, View, ViewId, ViewportLayout, MAX_VIEWPORTS, MAX_VIEWS};
 use winit::dpi::PhysicalSize;
+use winit::error::EventLoopError;
+use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::Window;
 
+use crate::app::{CaterpieApp, Runner};
+use crate::logging::{self, span};
+
+use crate::engine::config::{BackgroundBehavior, EngineConfig};
 use crate::engine::configuration::Configuration;
-use crate::engine::configuration::MAX_FLIGHT_FENCES;
+use crate::engine::configuration::{MAX_LIGHTS, MAX_OBJECTS};
+use crate::engine::diagnostics::{DiagnosticsReport, DIAGNOSTICS_FILE};
+use crate::engine::error::EngineError;
+use crate::engine::fixed_step::FixedTimestep;
+use crate::engine::frame_capture::FrameCapture;
+use crate::engine::frame_pacer::{FramePacer, PacingMode};
+use crate::engine::gpu_device::{
+    draw_frame, next_frame, wait_for_slot, FrameContext, FrameHost, FrameSlot, GpuDevice,
+};
+use crate::engine::texture_atlas::TextureAtlas;
+
+pub use crate::engine::configuration::{
+    AssetStats, BloomSettings, ColorSpaceHint, CullingStats, DeviceLimits, EngineRequirements,
+    LimitAdjustment, MaterialResource, MeshResource, PipelineOptions, PlatformQuirks, QueueStats,
+    RecordOverflow, RenderQueue, ShadingPath, SsaoSettings, TextureResource, TextureSlot,
+};
+pub use crate::utils::embedded::RgbaImage;
 
+pub mod animation;
+pub mod config;
+#[cfg(feature = "config-file")]
+mod config_file;
 mod configuration;
-#[derive(Default)]
+mod crash;
+pub mod debug_draw;
+pub mod debug_palette;
+pub mod diagnostics;
+pub mod error;
+pub mod fixed_step;
+pub mod frame_capture;
+pub mod frame_pacer;
+pub mod gizmo;
+pub mod gpu_device;
+pub mod log_view;
+pub mod material;
+pub mod mesh;
+pub mod present_stats;
+pub mod raycast;
+pub mod rng;
+pub mod scene;
+#[cfg(feature = "scene-file")]
+mod scene_file;
+pub mod sdf_font;
+pub mod selection;
+pub mod skinning;
+pub mod spatial;
+pub mod stats_graph;
+pub mod text;
+pub mod texture_atlas;
+pub mod textures;
+pub mod toast;
+pub mod viewport;
+
+// Weight of the newest frame in `Engine::input_latency`
+const INPUT_LATENCY_SMOOTHING: f32 = 0.1;
+// Edge of the cells world queries hash object bounds into
+const WORLD_QUERY_CELL_SIZE: f32 = 2.0;
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    if let Some(message) = panic.downcast_ref::<String>() {
+        return message.clone();
+    }
+    "unknown panic".to_string()
+}
+
 pub struct Engine {
     configuration: Configuration,
-    start: Option<Instant>,
+    timestep: FixedTimestep,
+    // Animation clock, advanced by fixed ticks. Frames are drawn between the
+    // previous and the current tick
+    previous_time: f32,
+    time: f32,
     frame: u32,
+    frames_in_flight: u32,
+    objects: Vec<RenderObject>,
+    // Parallel to `objects`, None draws the object with the model
+    object_meshes: Vec<Option<Arc<MeshResource>>>,
+    // Parallel to `objects`, skinned objects are drawn in their skin's pose
+    object_skins: Vec<Option<Skin>>,
+    // Parallel to `objects`, one weight per target of the object's mesh
+    object_morph_weights: Vec<Vec<f32>>,
+    // Parallel to `objects`, None draws the object with the default material
+    object_materials: Vec<Option<Arc<MaterialResource>>>,
+    // Every view's uniform entries of the last frame, kept so the next one
+    // reuses the allocation
+    object_ubos: Vec<UniformBufferObject>,
+    // Every surface's viewport regions this frame, in surface order
+    view_regions: Vec<Vec<Rect2D>>,
+    // Which objects each pass draws, by their layers
+    layer_masks: LayerMasks,
+    // Shared by every view, in world space
+    lights: Vec<PointLight>,
+    // Light spheres and the axis tripod of `gizmo_target`
+    show_gizmos: bool,
+    gizmo_target: Option<GizmoTarget>,
+    gizmo_drag: Option<AxisDrag>,
+    // Highlighted objects
+    selection: Selection,
+    debug_draw: DebugDraw,
+    // Frusta of every camera and the last cursor ray of `pick` or `raycast`,
+    // drawn through `debug_draw`
+    show_debug_queries: bool,
+    last_query_ray: Cell<Option<Ray>>,
+    // World bounds of the objects `query_aabb` and `query_sphere` see, as of
+    // the last query
+    world_grid: RefCell<UniformGrid>,
+    frame_time_graph: FrameTimeGraph,
+    show_frame_graph: bool,
+    // The last log records over the top of the primary window
+    show_log_view: bool,
+    toasts: Toasts,
+    frame_capture: FrameCapture,
+    // Advanced every frame, their tracks overwrite the transforms they target
+    animators: Vec<Animator>,
+    pacer: FramePacer,
+    // Set once `wait_for_frame` ran ahead of the app's update
+    frame_waited: bool,
+    // When the current frame's waiting was done, and how long the last frame
+    // took on the CPU from there
+    frame_started: Option<Instant>,
+    frame_work: Duration,
+    // When the app last read its input, for the latency estimate
+    input_sampled: Option<Instant>,
+    input_latency: Option<f32>,
+    background_behavior: BackgroundBehavior,
+    paused: bool,
+    // Holds the clock so frames can be reproduced exactly
+    time_frozen: bool,
+    // Advances the clock by this much every frame instead of the real frame
+    // time, for recordings that play back at a steady speed
+    fixed_frame_time: Option<Duration>,
+    views: Vec<View>,
+    next_view_id: u32,
+    view_requests: Vec<Camera>,
+    exit_requested: bool,
+    // Between `suspend` and `resume` there is no surface to draw to
+    suspended: bool,
 }
 
 impl Engine {
-    pub fn init(window: &Window) -> Result<Engine, &str> {
-        let configuration = Configuration::default()
-            .create_instance(window)
-            .unwrap()
-            .create_surface(window)
-            .unwrap()
-            .pick_physical_device()
-            .unwrap()
-            .create_device()
-            .unwrap()
-            .create_swap_chain()
-            .unwrap()
-            .create_swapchain_image_views()
-            .unwrap()
-            .create_render_pass()
-            .unwrap()
-            .create_descriptor_set_layout()
-            .unwrap()
-            .load_model()
-            .unwrap()
-            .create_graphics_pipeline()
-            .unwrap()
-            .create_command_pool()
-            .unwrap()
-            .create_depth_resources()
-            .unwrap()
-            .create_framebuffers()
-            .unwrap()
-            .create_texture_image()
-            .unwrap()
-            .create_texture_image_view()
-            .unwrap()
-            .create_texture_sampler()
-            .unwrap()
-            .create_vertex_buffer()
-            .unwrap()
-            .create_index_buffer()
-            .unwrap()
-            .create_uniform_buffer()
-            .unwrap()
-            .create_descriptor_pool()
-            .unwrap()
-            .create_descriptor_sets()
-            .unwrap()
-            .create_command_buffer()
-            .unwrap()
-            .create_sync_objects()
-            .unwrap()
-            .build();
-        Ok(Self {
+    pub fn init(window: &Window, config: EngineConfig) -> Result<Engine, &str> {
+        let mut pacer = FramePacer::new(config.fps_limit, config.background_fps);
+        pacer.set_low_latency(config.low_latency);
+        pacer.set_refresh_rate(
+            window
+                .current_monitor()
+                .and_then(|monitor| monitor.refresh_rate_millihertz())
+                .map(|millihertz| millihertz.div_ceil(1000)),
+        );
+        let background_behavior = config.background_behavior;
+        let frames_in_flight = config.frames_in_flight;
+        let timestep = FixedTimestep::new(config.tick_rate);
+        let configuration = Self::build_configuration(config, |builder| {
+            builder
+                .create_instance(Some(window))
+                .unwrap_or_else(|err| panic!("{err}"))
+                .create_surface(window)
+                .unwrap();
+        })?;
+        pacer.set_present_mode(configuration.surfaces[0].present_mode);
+        info!(target: logging::FRAME, "Frame pacing: {:?}", pacer.mode());
+        Ok(Self::with_configuration(
+            configuration,
+            pacer,
+            background_behavior,
+            frames_in_flight,
+            timestep,
+        ))
+    }
+
+    // Renders a single frame of `scene` through the primary camera without
+    // opening a window or creating a swapchain. The animation is held at its
+    // start so the same scene always gives the same image
+    pub fn render_offscreen(
+        config: EngineConfig,
+        width: u32,
+        height: u32,
+        scene: &[RenderObject],
+    ) -> Result<RgbaImage, String> {
+        let pacer = FramePacer::new(config.fps_limit, config.background_fps);
+        let background_behavior = config.background_behavior;
+        let frames_in_flight = config.frames_in_flight;
+        let timestep = FixedTimestep::new(config.tick_rate);
+        let configuration = Self::build_configuration(config, |builder| {
+            builder
+                .create_instance(None)
+                .unwrap_or_else(|err| panic!("{err}"))
+                .create_offscreen_target(Extent2D { width, height })
+                .unwrap();
+        })?;
+        let mut engine = Self::with_configuration(
+            configuration,
+            pacer,
+            background_behavior,
+            frames_in_flight,
+            timestep,
+        );
+        engine.set_time(Some(0.0));
+        for object in scene {
+            engine.add_object(MeshSource::Model, *object);
+        }
+        let view_regions = engine.viewport_regions();
+        engine.update_uniform_buffer(0, &view_regions);
+        let image =
+            engine
+                .configuration
+                .render_offscreen(0, &engine.object_meshes, &view_regions[0]);
+        engine.destroy();
+        image.map_err(|err| err.to_string())
+    }
+
+    // `create_target` sets up the instance and what is rendered to, the rest
+    // of the initialization is the same with or without a window
+    fn build_configuration(
+        config: EngineConfig,
+        create_target: impl FnOnce(&mut Configuration),
+    ) -> Result<Configuration, &'static str> {
+        let start = Instant::now();
+        let mut builder = Configuration::default();
+        builder.with_config(config).preload_assets();
+        // Init still panics on the first failing step, the partially built
+        // configuration is kept around to describe how far it got
+        let built = panic::catch_unwind(AssertUnwindSafe(|| {
+            create_target(&mut builder);
+            builder
+                .pick_physical_device()
+                .unwrap()
+                .detect_platform_quirks()
+                .unwrap()
+                .validate_device_limits()
+                .unwrap()
+                .create_device()
+                .unwrap()
+                .create_swap_chain()
+                .unwrap()
+                .create_swapchain_image_views()
+                .unwrap()
+                .create_render_pass()
+                .unwrap()
+                .create_descriptor_set_layout()
+                .unwrap()
+                .create_pipeline_layout()
+                .unwrap()
+                .create_graphics_pipeline()
+                .unwrap()
+                .create_picking_pass()
+                .unwrap()
+                .create_gizmo_pass()
+                .unwrap()
+                .create_text_pass()
+                .unwrap()
+                .create_bloom_pass()
+                .unwrap()
+                .create_command_pool()
+                .unwrap()
+                .create_upload_ring()
+                .unwrap()
+                .create_joint_buffers()
+                .unwrap()
+                .create_morph_pool()
+                .unwrap()
+                .create_deferred_pass()
+                .unwrap()
+                .create_ssao_pass()
+                .unwrap()
+                .create_depth_resources()
+                .unwrap()
+                .create_framebuffers()
+                .unwrap()
+                .create_texture_image()
+                .unwrap()
+                .create_texture_sampler()
+                .unwrap()
+                .create_environment_maps()
+                .unwrap()
+                .create_materials()
+                .unwrap()
+                .load_model()
+                .unwrap()
+                .create_uniform_buffer()
+                .unwrap()
+                .create_descriptor_pool()
+                .unwrap()
+                .create_descriptor_sets()
+                .unwrap()
+                .create_culling_pass()
+                .unwrap()
+                .create_command_buffer()
+                .unwrap()
+                .create_sync_objects()
+                .unwrap()
+                .create_frame_timer()
+                .unwrap()
+                .build()
+        }));
+        match built {
+            Ok(configuration) => {
+                info!(
+                    "Renderer initialized in {:.1} ms",
+                    start.elapsed().as_secs_f64() * 1000.0
+                );
+                let report = configuration.diagnostics();
+                info!("{report}");
+                if let Some(device) = &configuration.device {
+                    crash::register(device.clone(), report);
+                }
+                Ok(configuration)
+            }
+            Err(panic) => {
+                let mut report = builder.diagnostics();
+                report.error = Some(panic_message(panic.as_ref()));
+                match report.write_to_file(DIAGNOSTICS_FILE) {
+                    Ok(()) => {
+                        error!("Initialization failed, diagnostics written to {DIAGNOSTICS_FILE}")
+                    }
+                    Err(err) => {
+                        error!("Initialization failed, could not write {DIAGNOSTICS_FILE}: {err}")
+                    }
+                }
+                Err("Failed to initialize the renderer")
+            }
+        }
+    }
+
+    fn with_configuration(
+        configuration: Configuration,
+        pacer: FramePacer,
+        background_behavior: BackgroundBehavior,
+        frames_in_flight: u32,
+        timestep: FixedTimestep,
+    ) -> Self {
+        let debug_palette = configuration.debug_palette();
+        let frame_capture = FrameCapture::new(configuration.capture_frame());
+        Self {
             configuration,
-            start: Some(Instant::now()),
+            timestep,
+            previous_time: 0.0,
+            time: 0.0,
             frame: 0,
-        })
+            frames_in_flight,
+            objects: Vec::new(),
+            object_meshes: Vec::new(),
+            object_skins: Vec::new(),
+            object_morph_weights: Vec::new(),
+            object_materials: Vec::new(),
+            object_ubos: Vec::new(),
+            view_regions: Vec::new(),
+            layer_masks: LayerMasks::default(),
+            lights: Vec::new(),
+            show_gizmos: false,
+            gizmo_target: None,
+            gizmo_drag: None,
+            selection: Selection::default(),
+            debug_draw: DebugDraw::new(debug_palette),
+            show_debug_queries: false,
+            frame_time_graph: FrameTimeGraph::default(),
+            show_frame_graph: false,
+            show_log_view: false,
+            toasts: Toasts::default(),
+            frame_capture,
+            last_query_ray: Cell::new(None),
+            world_grid: RefCell::new(UniformGrid::new(WORLD_QUERY_CELL_SIZE)),
+            animators: Vec::new(),
+            pacer,
+            frame_waited: false,
+            frame_started: None,
+            frame_work: Duration::ZERO,
+            input_sampled: None,
+            input_latency: None,
+            background_behavior,
+            paused: false,
+            time_frozen: false,
+            fixed_frame_time: None,
+            views: vec![View::new(ViewId::PRIMARY)],
+            next_view_id: ViewId::PRIMARY.0 + 1,
+            view_requests: Vec::new(),
+            exit_requested: false,
+            suspended: false,
+        }
     }
 
-    pub fn window_resized(&mut self, size: PhysicalSize<u32>) {
-        self.configuration.window_resized(size);
+    // Environment summary to attach to bug reports
+    pub fn diagnostics(&self) -> DiagnosticsReport {
+        self.configuration.diagnostics()
     }
 
-    fn update_uniform_buffer(&mut self, current_image: u32) {
-        let time= self.start.unwrap().elapsed().as_secs_f32();
+    // Opens the window, initializes the engine and drives `app` until the
+    // primary window is closed
+    pub fn run<A: CaterpieApp>(
+        event_loop: EventLoop<()>,
+        config: EngineConfig,
+        app: A,
+    ) -> Result<(), EventLoopError> {
+        let mut runner = Runner::new(config, app);
+        event_loop.set_control_flow(ControlFlow::Poll);
+        event_loop.run_app(&mut runner)
+    }
 
-        let device = self.configuration.device.as_ref().unwrap();
+    // Draws `object` with the model, a mesh file or a generated primitive.
+    // Meshes go through the asset cache, None if the limit is reached or the
+    // mesh can't be loaded
+    pub fn add_object(
+        &mut self,
+        mesh: impl Into<MeshSource>,
+        object: RenderObject,
+    ) -> Option<ObjectId> {
+        let cpu_data = self.configuration.mesh_cpu_data();
+        self.add_object_with(mesh, object, cpu_data)
+    }
 
-        let model = Matrix4::from_axis_angle(vec3(0.0, 0.0, 1.0), Deg(85.0) * time * 0.5);
+    // `add_object` with what a newly loaded mesh keeps on the CPU. Ray casts
+    // against a discarded mesh only hit its bounds
+    pub fn add_object_with(
+        &mut self,
+        mesh: impl Into<MeshSource>,
+        object: RenderObject,
+        cpu_data: CpuMeshData,
+    ) -> Option<ObjectId> {
+        if self.objects.len() >= MAX_OBJECTS as usize {
+            warn!("Object limit of {MAX_OBJECTS} reached, ignoring new object");
+            return None;
+        }
+        let mesh = match mesh.into() {
+            MeshSource::Model => Ok(None),
+            MeshSource::Path(path) => self.configuration.load_mesh(&path, cpu_data).map(Some),
+            MeshSource::Primitive(primitive) => self
+                .configuration
+                .load_primitive(primitive, cpu_data)
+                .map(Some),
+            MeshSource::Geometry {
+                vertices,
+                indices,
+                morph_targets,
+            } => self
+                .configuration
+                .load_geometry(vertices, indices, &morph_targets, cpu_data)
+                .map(Some),
+        };
+        let mesh = match mesh {
+            Ok(mesh) => mesh,
+            Err(err) => {
+                self.notify(
+                    Level::Warn,
+                    format!("Ignoring new object, its mesh failed to load: {err}"),
+                );
+                return None;
+            }
+        };
+        self.objects.push(object);
+        self.object_meshes.push(mesh);
+        self.object_skins.push(None);
+        self.object_morph_weights.push(Vec::new());
+        self.object_materials.push(None);
+        Some(ObjectId(self.objects.len() - 1))
+    }
 
-        let view = Matrix4::look_at_rh(
-            point3(2.0, 2.0, 2.0),
-            point3(0.0, 0.0, 0.0),
-            vec3(0.0, 0.0, 1.0),
-        );
+    // Draws the object's mesh blended by the skeleton's joints, starting in
+    // the rest pose. Joint animation tracks pose it from then on
+    pub fn set_object_skeleton(&mut self, object: ObjectId, skeleton: Skeleton) -> bool {
+        let Some(skin) = self.object_skins.get_mut(object.0) else {
+            return false;
+        };
+        *skin = Some(Skin::new(skeleton));
+        true
+    }
 
-        let mut proj = perspective(
-            Deg(45.0),
-            self.configuration.extent.unwrap().width as f32
-                / self.configuration.extent.unwrap().height as f32,
-            0.1,
-            10.0,
-        );
+    pub fn object_skin(&self, object: ObjectId) -> Option<&Skin> {
+        self.object_skins.get(object.0)?.as_ref()
+    }
+
+    // Hidden objects aren't drawn or picked, they keep their layers
+    pub fn set_object_visible(&mut self, object: ObjectId, visible: bool) -> bool {
+        let Some(object) = self.objects.get_mut(object.0) else {
+            return false;
+        };
+        object.visible = visible;
+        true
+    }
 
-        proj[1][1] *= -1.0;
+    pub fn object_visible(&self, object: ObjectId) -> Option<bool> {
+        Some(self.objects.get(object.0)?.visible)
+    }
 
-        let ubo = UniformBufferObject {
-            model,
-            view,
-            projection: proj,
+    // Bit flags of the layers the object is on, see `LayerMasks`
+    pub fn set_object_layers(&mut self, object: ObjectId, layers: u32) -> bool {
+        let Some(object) = self.objects.get_mut(object.0) else {
+            return false;
         };
-        unsafe {
-            let mem = device
-                .map_memory(
-                    self.configuration.uniform_buffer_memory[current_image as usize],
-                    0,
-                    size_of::<UniformBufferObject>() as u64,
-                    MemoryMapFlags::empty(),
-                )
-                .unwrap();
-            std::ptr::copy_nonoverlapping(&ubo, mem.cast(), 1);
+        object.layers = layers;
+        true
+    }
+
+    pub fn object_layers(&self, object: ObjectId) -> Option<u32> {
+        Some(self.objects.get(object.0)?.layers)
+    }
 
-            device.unmap_memory(self.configuration.uniform_buffer_memory[current_image as usize]);
+    pub fn set_layer_masks(&mut self, layer_masks: LayerMasks) {
+        self.layer_masks = layer_masks;
+    }
+
+    pub fn layer_masks(&self) -> LayerMasks {
+        self.layer_masks
+    }
+
+    // Per object, whether the pass drawing the layers of `mask` draws it
+    fn objects_drawn_by(&self, mask: u32) -> Vec<bool> {
+        self.objects
+            .iter()
+            .map(|object| object.drawn_by(mask))
+            .collect()
+    }
+
+    // Blends the morph targets of the object's mesh, the heaviest
+    // MAX_ACTIVE_MORPH_TARGETS of them. Weights past the mesh's targets are
+    // ignored
+    pub fn set_morph_weights(&mut self, object: ObjectId, weights: Vec<f32>) -> bool {
+        let Some(object_weights) = self.object_morph_weights.get_mut(object.0) else {
+            return false;
         };
+        *object_weights = weights;
+        true
     }
 
-    pub fn draw_frame(&mut self) {
-        let current_frame = self.frame as usize;
-        let device = self.configuration.device.clone().unwrap();
-        let fences = self.configuration.in_flight_fences.clone();
-        let command_buffer = self.configuration.command_buffer[current_frame];
-        unsafe {
-            match device.wait_for_fences(&[fences[current_frame]], true, u64::MAX) {
-                Ok(_) => {}
-                Err(_) => {
-                    error!("Failed to wait for fences! Aborting!");
-                    panic!("Failed to wait 4 fences");
+    pub fn morph_weights(&self, object: ObjectId) -> &[f32] {
+        self.object_morph_weights
+            .get(object.0)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    // Uploads the factors and binds the textures once, the handle can be
+    // shared by any number of objects
+    pub fn create_material(
+        &mut self,
+        material: &Material,
+    ) -> Result<Arc<MaterialResource>, String> {
+        self.configuration
+            .create_material(material)
+            .map_err(|err| err.to_string())
+    }
+
+    // Reads the materials of an MTL file, named as in the file
+    pub fn load_materials(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<(String, Material)>, String> {
+        self.configuration
+            .load_mtl_materials(path.as_ref())
+            .map_err(|err| err.to_string())
+    }
+
+    // None goes back to the default material, the model texture on a rough
+    // dielectric
+    pub fn set_object_material(
+        &mut self,
+        object: ObjectId,
+        material: Option<Arc<MaterialResource>>,
+    ) -> bool {
+        let Some(object_material) = self.object_materials.get_mut(object.0) else {
+            return false;
+        };
+        *object_material = material;
+        true
+    }
+
+    // Lights both shading paths, false once MAX_LIGHTS are in the scene
+    pub fn add_light(&mut self, light: PointLight) -> bool {
+        if self.lights.len() >= MAX_LIGHTS {
+            warn!("Light limit of {MAX_LIGHTS} reached, ignoring new light");
+            return false;
+        }
+        self.lights.push(light);
+        true
+    }
+
+    // Without lights the scene is drawn unlit
+    pub fn clear_lights(&mut self) {
+        self.lights.clear();
+    }
+
+    pub fn lights(&self) -> &[PointLight] {
+        &self.lights
+    }
+
+    // Returns the animator's index
+    pub fn add_animator(&mut self, animator: Animator) -> usize {
+        self.animators.push(animator);
+        self.animators.len() - 1
+    }
+
+    pub fn animator_mut(&mut self, index: usize) -> Option<&mut Animator> {
+        self.animators.get_mut(index)
+    }
+
+    // Targets keep the pose they were last given
+    pub fn clear_animators(&mut self) {
+        self.animators.clear();
+    }
+
+    pub fn set_show_gizmos(&mut self, show_gizmos: bool) {
+        self.show_gizmos = show_gizmos;
+    }
+
+    pub fn show_gizmos(&self) -> bool {
+        self.show_gizmos
+    }
+
+    // Lines for the frame being updated, see `DebugDraw`
+    pub fn debug_draw(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    pub fn set_show_debug_queries(&mut self, show_debug_queries: bool) {
+        self.show_debug_queries = show_debug_queries;
+    }
+
+    pub fn show_debug_queries(&self) -> bool {
+        self.show_debug_queries
+    }
+
+    // Colors of every debug line, the bounds, outline and tints
+    pub fn debug_palette(&self) -> DebugPalette {
+        self.configuration.debug_palette()
+    }
+
+    pub fn set_debug_palette(&mut self, debug_palette: DebugPalette) {
+        self.configuration.set_debug_palette(debug_palette);
+        self.debug_draw.set_palette(debug_palette);
+    }
+
+    // Draws the last frame times over the primary window's bottom left corner
+    pub fn set_show_frame_graph(&mut self, show_frame_graph: bool) {
+        self.show_frame_graph = show_frame_graph;
+    }
+
+    pub fn show_frame_graph(&self) -> bool {
+        self.show_frame_graph
+    }
+
+    // Draws the last log records over the top of the primary window, so
+    // errors show up without a terminal
+    pub fn set_show_log_view(&mut self, show_log_view: bool) {
+        self.show_log_view = show_log_view;
+    }
+
+    pub fn show_log_view(&self) -> bool {
+        self.show_log_view
+    }
+
+    // Logs `message` and shows it in the primary window's bottom right corner
+    // for a few seconds
+    pub fn notify(&mut self, level: Level, message: impl Into<String>) {
+        let message = message.into();
+        log!(level, "{message}");
+        self.toasts.push(level, message, Instant::now());
+    }
+
+    // Where the axis tripod is drawn, ends any drag of the previous target
+    pub fn set_gizmo_target(&mut self, target: Option<GizmoTarget>) {
+        if self.gizmo_target != target {
+            self.gizmo_drag = None;
+        }
+        self.gizmo_target = target;
+    }
+
+    pub fn gizmo_target(&self) -> Option<GizmoTarget> {
+        self.gizmo_target
+    }
+
+    fn gizmo_position(&self, target: GizmoTarget) -> Option<Point3<f32>> {
+        match target {
+            GizmoTarget::Object(ObjectId(index)) => self
+                .objects
+                .get(index)
+                .map(|object| Point3::from_vec(object.transform.w.truncate())),
+            GizmoTarget::Light(index) => self.lights.get(index).map(|light| light.position),
+        }
+    }
+
+    fn move_gizmo_target(&mut self, target: GizmoTarget, position: Point3<f32>) {
+        match target {
+            GizmoTarget::Object(ObjectId(index)) => {
+                if let Some(object) = self.objects.get_mut(index) {
+                    object.transform.w = position.to_homogeneous();
                 }
             }
+            GizmoTarget::Light(index) => {
+                if let Some(light) = self.lights.get_mut(index) {
+                    light.position = position;
+                }
+            }
+        }
+    }
 
-            let next_image_query_result = self
-                .configuration
-                .swapchain_device
-                .as_ref()
-                .unwrap()
-                .acquire_next_image(
-                    self.configuration.swapchain.unwrap(),
-                    u64::MAX,
-                    self.configuration.image_available_semaphores[current_frame],
-                    fences[current_frame],
-                );
+    // The ray through the pixel at `x`, `y` of the primary window, from the
+    // camera of the viewport region under it
+    pub fn cursor_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        let view = self.views.first()?;
+        let regions = self.viewport_regions().swap_remove(0);
+        let (region, camera) = regions
+            .iter()
+            .zip(view.cameras.iter())
+            .find(|(region, _)| {
+                let (left, top) = (region.offset.x as f32, region.offset.y as f32);
+                (left..left + region.extent.width as f32).contains(&x)
+                    && (top..top + region.extent.height as f32).contains(&y)
+            })?;
+        Ray::from_ndc(
+            camera.projection(aspect_ratio(region)) * camera.view(),
+            pixel_ndc(region, [x, y]),
+        )
+    }
 
-            let mut next_image_index: u32 = 0;
-            match next_image_query_result {
-                Ok(next_image) => {
-                    next_image_index = next_image.0;
-                }
-                Err(_) => {
-                    self.configuration.recreate_swapchain();
-                    return;
+    // The closest object under the pixel at `x`, `y` of the primary window,
+    // tested against its triangles on the CPU. Works without
+    // `EngineConfig::picking` and doesn't wait for the GPU
+    pub fn raycast(&self, x: f32, y: f32) -> Option<RayHit> {
+        span!("raycast");
+        let ray = self.cursor_ray(x, y)?;
+        self.last_query_ray.set(Some(ray));
+        let rotation = self.model_rotation();
+        self.objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| object.drawn_by(self.layer_masks.picking))
+            .filter_map(|(index, object)| {
+                let mesh = self
+                    .configuration
+                    .object_mesh(&self.object_meshes, index as u32)?;
+                let distance = mesh.raycast(&ray.transformed(object.transform * rotation)?)?;
+                Some(RayHit {
+                    object: ObjectId(index),
+                    position: ray.at(distance),
+                    distance,
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    // World space box around the object as it is drawn this frame. None for
+    // objects whose mesh has no bounds or that are off the picking layers,
+    // like for `raycast`. Skinned and morphed meshes keep their rest bounds
+    pub fn object_bounds(&self, object: ObjectId) -> Option<Aabb> {
+        let ObjectId(index) = object;
+        let render_object = self
+            .objects
+            .get(index)
+            .filter(|render_object| render_object.drawn_by(self.layer_masks.picking))?;
+        let bounds = self
+            .configuration
+            .object_mesh(&self.object_meshes, index as u32)?
+            .bounds()?;
+        Some(bounds.world(&(render_object.transform * self.model_rotation())))
+    }
+
+    // Moves what changed since the last query in the grid
+    fn update_world_grid(&self) -> std::cell::Ref<'_, UniformGrid> {
+        {
+            let mut grid = self.world_grid.borrow_mut();
+            let removed = grid
+                .objects()
+                .filter(|ObjectId(index)| *index >= self.objects.len())
+                .collect::<Vec<ObjectId>>();
+            for object in removed {
+                grid.remove(object);
+            }
+            for index in 0..self.objects.len() {
+                let object = ObjectId(index);
+                match self.object_bounds(object) {
+                    Some(bounds) if grid.bounds(object) != Some(bounds) => {
+                        grid.insert(object, bounds)
+                    }
+                    Some(_) => {}
+                    None => {
+                        grid.remove(object);
+                    }
                 }
             }
+        }
+        self.world_grid.borrow()
+    }
+
+    // Objects whose world bounds overlap the box from `min` to `max`, in id
+    // order. Only the bounds are tested, not the triangles
+    pub fn query_aabb(&self, min: Point3<f32>, max: Point3<f32>) -> Vec<ObjectId> {
+        span!("query_aabb");
+        let bounds = Aabb::from_points([min, max]).unwrap();
+        self.update_world_grid().query_aabb(&bounds)
+    }
+
+    // Objects whose world bounds reach into the sphere, in id order
+    pub fn query_sphere(&self, center: Point3<f32>, radius: f32) -> Vec<ObjectId> {
+        span!("query_sphere");
+        self.update_world_grid().query_sphere(center, radius)
+    }
+
+    // Whether the world bounds of both objects overlap, false when either
+    // has none
+    pub fn overlaps(&self, a: ObjectId, b: ObjectId) -> bool {
+        match (self.object_bounds(a), self.object_bounds(b)) {
+            (Some(a), Some(b)) => a.overlaps(&b),
+            _ => false,
+        }
+    }
+
+    // Grabs the axis of the tripod under the cursor, or else makes the
+    // nearest light under it the gizmo target. False when neither was hit
+    pub fn begin_gizmo_drag(&mut self, x: f32, y: f32) -> bool {
+        if !self.show_gizmos {
+            return false;
+        }
+        let Some(ray) = self.cursor_ray(x, y) else {
+            return false;
+        };
+        if let Some(target) = self.gizmo_target {
+            let grabbed = self
+                .gizmo_position(target)
+                .and_then(|origin| gizmo::pick_axis(&ray, origin));
+            if let Some((axis, grab_offset)) = grabbed {
+                self.gizmo_drag = Some(AxisDrag {
+                    target,
+                    axis,
+                    grab_offset,
+                });
+                return true;
+            }
+        }
+        let light = self
+            .lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, light)| {
+                ray.intersect_sphere(light.position, gizmo::LIGHT_GIZMO_RADIUS)
+                    .map(|distance| (index, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match light {
+            Some((index, _)) => {
+                self.set_gizmo_target(Some(GizmoTarget::Light(index)));
+                true
+            }
+            None => false,
+        }
+    }
 
-            device
-                .reset_fences(&[fences[current_frame]])
-                .expect("Failed to reset fences");
+    // Moves the dragged target to where its axis passes closest to the
+    // cursor ray
+    pub fn drag_gizmo(&mut self, x: f32, y: f32) {
+        let Some(drag) = self.gizmo_drag else {
+            return;
+        };
+        let (Some(ray), Some(origin)) = (self.cursor_ray(x, y), self.gizmo_position(drag.target))
+        else {
+            return;
+        };
+        let axis = Ray {
+            origin,
+            direction: gizmo::axis_direction(drag.axis),
+        };
+        // Looking straight down the axis there is nowhere to move along it
+        if let Some((_, along_axis)) = gizmo::closest_line_parameters(&ray, &axis) {
+            self.move_gizmo_target(drag.target, axis.at(along_axis - drag.grab_offset));
+        }
+    }
 
-            device
-                .reset_command_buffer(command_buffer, CommandBufferResetFlags::default())
-                .unwrap();
+    pub fn end_gizmo_drag(&mut self) {
+        self.gizmo_drag = None;
+    }
+
+    pub fn is_dragging_gizmo(&self) -> bool {
+        self.gizmo_drag.is_some()
+    }
+
+    // World space line list of everything `show_gizmos` draws
+    fn gizmo_lines(&self) -> Vec<(Point3<f32>, [f32; 3])> {
+        if !self.show_gizmos {
+            return Vec::new();
+        }
+        let mut lines = self
+            .lights
+            .iter()
+            .flat_map(|light| gizmo::light_lines(light.position, light.color))
+            .collect::<Vec<_>>();
+        if let Some(origin) = self
+            .gizmo_target
+            .and_then(|target| self.gizmo_position(target))
+        {
+            let hovered = self.gizmo_drag.map(|drag| drag.axis);
+            lines.extend(gizmo::axis_lines(
+                origin,
+                hovered,
+                self.debug_draw.palette(),
+            ));
+        }
+        lines
+    }
+
+    // Forward unless the engine was started with `EngineConfig::deferred`
+    // and switched over
+    pub fn shading_path(&self) -> ShadingPath {
+        self.configuration.shading_path()
+    }
+
+    // Takes effect on the next frame, deferred needs `EngineConfig::deferred`
+    pub fn set_shading_path(&mut self, shading_path: ShadingPath) -> Result<(), String> {
+        self.configuration.set_shading_path(shading_path)
+    }
+
+    // Cull mode, polygon mode and depth state of the forward path's scene
+    // pipelines. A combination's pipelines are created the first time it is
+    // set, which stalls that frame
+    pub fn set_pipeline_options(&mut self, options: PipelineOptions) -> Result<(), String> {
+        self.configuration.set_pipeline_options(options)
+    }
+
+    pub fn pipeline_options(&self) -> PipelineOptions {
+        self.configuration.pipeline_options()
+    }
+
+    // Whether `pick` can be used, otherwise `raycast` finds objects instead
+    pub fn picking_enabled(&self) -> bool {
+        self.configuration.picking_enabled()
+    }
+
+    // Object under the pixel at `x`, `y` of the primary window as of the last
+    // drawn frame. Needs `EngineConfig::picking`, blocks until the ID has been
+    // read back
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<ObjectId> {
+        if !self.configuration.picking_enabled() {
+            warn!("Picking is disabled, enable it with CATERPIE_PICKING=1");
+            return None;
+        }
+        span!("pick");
+        self.last_query_ray.set(self.cursor_ray(x as f32, y as f32));
+        let last_frame = (self.frame + self.frames_in_flight - 1) % self.frames_in_flight;
+        let regions = self.viewport_regions().swap_remove(0);
+        let position = Offset2D {
+            x: x as i32,
+            y: y as i32,
+        };
+        let pickable = self.objects_drawn_by(self.layer_masks.picking);
+        match self.configuration.pick(
+            last_frame as usize,
+            position,
+            (&self.object_meshes, &pickable),
+            &regions,
+        ) {
+            Ok(object) => object.map(|index| ObjectId(index as usize)),
+            Err(err) => {
+                warn!("Picking failed: {err}");
+                None
+            }
+        }
+    }
+
+    // Highlights `object` in every view, None clears the selection
+    pub fn select(&mut self, object: Option<ObjectId>) {
+        self.set_selection(object);
+    }
+
+    // The first selected object
+    pub fn selected(&self) -> Option<ObjectId> {
+        self.selection.first()
+    }
+
+    pub fn selection(&self) -> &[ObjectId] {
+        self.selection.objects()
+    }
+
+    // Highlights `objects` in every view instead of the current selection
+    pub fn set_selection(&mut self, objects: impl IntoIterator<Item = ObjectId>) {
+        self.selection.take();
+        self.extend_selection(objects);
+    }
+
+    // Objects that are already selected keep their place, ids past the last
+    // object are ignored
+    pub fn extend_selection(&mut self, objects: impl IntoIterator<Item = ObjectId>) {
+        self.selection.extend(objects, self.objects.len());
+        self.highlight_selection();
+    }
+
+    fn highlight_selection(&mut self) {
+        self.configuration.highlighted_objects = self
+            .selection
+            .objects()
+            .iter()
+            .map(|ObjectId(index)| *index as u32)
+            .collect();
+    }
+
+    // Objects whose bounds overlap the rectangle between the pixels `from`
+    // and `to` of the primary window on screen, in any viewport region it
+    // covers, in id order. Like `object_bounds` only objects on the picking
+    // layers count, and only their bounds are tested
+    pub fn objects_in_rect(&self, from: [f32; 2], to: [f32; 2]) -> Vec<ObjectId> {
+        span!("objects_in_rect");
+        let Some(view) = self.views.first() else {
+            return Vec::new();
+        };
+        let regions = self.viewport_regions().swap_remove(0);
+        let rects = regions
+            .iter()
+            .zip(view.cameras.iter())
+            .filter_map(|(region, camera)| {
+                let rect = ScreenRect::from_corners(pixel_ndc(region, from), pixel_ndc(region, to))
+                    .clamped(&ScreenRect::NDC)?;
+                Some((
+                    rect,
+                    camera.projection(aspect_ratio(region)) * camera.view(),
+                ))
+            })
+            .collect::<Vec<(ScreenRect, Matrix4<f32>)>>();
+        (0..self.objects.len())
+            .map(ObjectId)
+            .filter(|object| {
+                let Some(bounds) = self.object_bounds(*object) else {
+                    return false;
+                };
+                rects.iter().any(|(rect, view_projection)| {
+                    bounds
+                        .screen_rect(view_projection)
+                        .is_some_and(|bounds| bounds.overlaps(rect))
+                })
+            })
+            .collect()
+    }
+
+    // Hides the selected objects and clears the selection, the gizmo too if
+    // it was on one of them. Returns how many were hidden
+    pub fn hide_selected(&mut self) -> usize {
+        let selection = self.selection.take();
+        for object in &selection {
+            self.set_object_visible(*object, false);
+        }
+        if let Some(GizmoTarget::Object(object)) = self.gizmo_target {
+            if selection.contains(&object) {
+                self.set_gizmo_target(None);
+            }
+        }
+        self.set_selection([]);
+        selection.len()
+    }
+
+    // Removes the selected objects, see `remove_objects`. Returns how many
+    // were removed
+    pub fn delete_selected(&mut self) -> usize {
+        let selection = self.selection.take();
+        self.remove_objects(&selection);
+        selection.len()
+    }
+
+    // Adds a copy of every selected object moved by `offset` and selects the
+    // copies instead. They share the meshes and materials of the originals.
+    // Stops at the object limit
+    pub fn duplicate_selected(&mut self, offset: Vector3<f32>) -> Vec<ObjectId> {
+        let mut copies = Vec::new();
+        for &ObjectId(index) in self.selection.objects() {
+            if self.objects.len() >= MAX_OBJECTS as usize {
+                warn!("Object limit of {MAX_OBJECTS} reached, not every object was duplicated");
+                break;
+            }
+            let mut object = self.objects[index];
+            object.transform.w += offset.extend(0.0);
+            self.objects.push(object);
+            self.object_meshes.push(self.object_meshes[index].clone());
+            self.object_skins.push(self.object_skins[index].clone());
+            self.object_morph_weights
+                .push(self.object_morph_weights[index].clone());
+            self.object_materials
+                .push(self.object_materials[index].clone());
+            copies.push(ObjectId(self.objects.len() - 1));
+        }
+        self.set_selection(copies.iter().copied());
+        copies
+    }
+
+    // Removes the objects, the ones after them move down so ids stay dense
+    // and ids taken before are stale. Animation tracks, the gizmo target and
+    // the selection follow their objects, tracks of removed objects are
+    // dropped. Meshes and materials no other object uses are freed once the
+    // frames in flight are done with them
+    pub fn remove_objects(&mut self, objects: &[ObjectId]) {
+        let moved_to = removed_ids(self.objects.len(), objects);
+        let moved = |ObjectId(index): ObjectId| moved_to.get(index).copied().flatten();
+        let removed = moved_to.iter().map(Option::is_none).collect::<Vec<bool>>();
+
+        fn retain_kept<T>(items: &mut Vec<T>, removed: &[bool]) {
+            let mut index = 0;
+            items.retain(|_| {
+                index += 1;
+                !removed[index - 1]
+            });
+        }
+        retain_kept(&mut self.objects, &removed);
+        retain_kept(&mut self.object_meshes, &removed);
+        retain_kept(&mut self.object_skins, &removed);
+        retain_kept(&mut self.object_morph_weights, &removed);
+        retain_kept(&mut self.object_materials, &removed);
+
+        for animator in &mut self.animators {
+            animator
+                .clip
+                .tracks
+                .retain_mut(|track| match &mut track.target {
+                    AnimationTarget::Object(object) | AnimationTarget::Joint { object, .. } => {
+                        moved(*object).map(|id| *object = id).is_some()
+                    }
+                    AnimationTarget::Light(_) => true,
+                });
+            animator
+                .clip
+                .weight_tracks
+                .retain_mut(|track| moved(track.object).map(|id| track.object = id).is_some());
+        }
+        if let Some(GizmoTarget::Object(object)) = self.gizmo_target {
+            self.set_gizmo_target(moved(object).map(GizmoTarget::Object));
+        }
+        self.selection.remap(&moved_to);
+        self.highlight_selection();
+    }
+
+    // Loads through the asset cache, a path that is still in use somewhere
+    // returns the same handle without touching the disk or the GPU
+    pub fn load_texture(
+        &mut self,
+        path: impl AsRef<Path>,
+        color_space: ColorSpaceHint,
+    ) -> Result<Arc<TextureResource>, String> {
+        self.configuration
+            .load_texture(path.as_ref(), color_space)
+            .map_err(|err| err.to_string())
+    }
+
+    // Uploads a generated or decoded image with its mip chain, bypassing the
+    // asset cache since there is no path to key it by
+    pub fn create_texture(
+        &mut self,
+        image: &RgbaImage,
+        color_space: ColorSpaceHint,
+    ) -> Result<Arc<TextureResource>, String> {
+        self.configuration
+            .create_texture(image, color_space)
+            .map_err(|err| err.to_string())
+    }
+
+    // Uploads what was added to `atlas` since the last call. Pass the texture
+    // the last call returned, a new one is made when the atlas grew
+    pub fn upload_atlas(
+        &mut self,
+        atlas: &mut TextureAtlas,
+        color_space: ColorSpaceHint,
+        texture: Option<Arc<TextureResource>>,
+    ) -> Result<Arc<TextureResource>, String> {
+        self.configuration
+            .upload_atlas(atlas, color_space, texture)
+            .map_err(|err| err.to_string())
+    }
+
+    // Returns right away, the texture replaces the current one once it has
+    // been decoded and uploaded over the next frames
+    pub fn load_texture_async(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        self.configuration
+            .load_texture_async(path.as_ref())
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn pending_texture_uploads(&self) -> usize {
+        self.configuration.pending_texture_uploads()
+    }
+
+    pub fn load_model(&mut self, path: impl AsRef<Path>) -> Result<Arc<MeshResource>, String> {
+        let cpu_data = self.configuration.mesh_cpu_data();
+        self.load_model_with(path, cpu_data)
+    }
+
+    pub fn load_model_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        cpu_data: CpuMeshData,
+    ) -> Result<Arc<MeshResource>, String> {
+        self.configuration
+            .load_mesh(path.as_ref(), cpu_data)
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn asset_stats(&self) -> AssetStats {
+        self.configuration.asset_stats()
+    }
+
+    // Snapshot of the primary window's scene, see `Scene::save`
+    pub fn scene(&self) -> Scene {
+        let view = self.views[0];
+        Scene {
+            model_path: self.configuration.model_path().to_path_buf(),
+            texture_path: self.configuration.texture_path().to_path_buf(),
+            objects: self.objects.clone(),
+            cameras: view.cameras,
+            viewport_layout: view.layout,
+            fps_limit: self.fps_limit(),
+            show_bounds: self.show_bounds(),
+            layer_masks: self.layer_masks,
+        }
+    }
+
+    // Replaces the objects, primary cameras and settings with `scene`. The
+    // model and texture are only reloaded when their paths changed.
+    // Animators moving objects are dropped since their objects are gone
+    pub fn load_scene(&mut self, scene: Scene) -> Result<(), String> {
+        if scene.model_path != self.configuration.model_path()
+            || scene.texture_path != self.configuration.texture_path()
+        {
             self.configuration
-                .record_command_buffer(&command_buffer, next_image_index);
-            let wait_semaphores =
-                vec![self.configuration.image_available_semaphores[current_frame]];
-            let signal_semaphores =
-                vec![self.configuration.render_finished_semaphores[current_frame]];
-            let command_buffer = vec![self.configuration.command_buffer[current_frame]];
-            let wait_stages = vec![PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-            let swapchains = vec![self.configuration.swapchain.unwrap()];
-
-            self.update_uniform_buffer(next_image_index);
-
-            let submit_info = vec![SubmitInfo::default()
-                .wait_semaphores(&wait_semaphores)
-                .wait_dst_stage_mask(&wait_stages)
-                .command_buffers(&command_buffer)
-                .signal_semaphores(&signal_semaphores)];
-            let image_indices = vec![next_image_index];
-            
-            device
-                .queue_submit(
-                    self.configuration.presentation_queue.unwrap(),
-                    &submit_info,
-                    fences[current_frame],
-                )
-                .expect("Failed to submit queue");
-
-            let present_info = PresentInfoKHR::default()
-                .wait_semaphores(&signal_semaphores)
-                .swapchains(&swapchains)
-                .image_indices(&image_indices);
-            match self
-                .configuration
-                .swapchain_device
-                .as_ref()
-                .unwrap()
-                .queue_present(
-                    self.configuration.presentation_queue.unwrap(),
-                    &present_info,
-                ) {
-                Ok(outdated) => match outdated {
-                    true => {
-                        return self.configuration.recreate_swapchain();
+                .reload_assets(scene.model_path, scene.texture_path)
+                .map_err(|err| err.to_string())?;
+        }
+        self.objects.clear();
+        self.object_meshes.clear();
+        self.object_skins.clear();
+        self.object_morph_weights.clear();
+        self.object_materials.clear();
+        self.animators.retain(|animator| {
+            animator.clip.weight_tracks.is_empty()
+                && animator
+                    .clip
+                    .tracks
+                    .iter()
+                    .all(|track| matches!(track.target, AnimationTarget::Light(_)))
+        });
+        for object in scene.objects {
+            if self.add_object(MeshSource::Model, object).is_none() {
+                break;
+            }
+        }
+        self.select(None);
+        self.views[0].cameras = scene.cameras;
+        self.views[0].layout = scene.viewport_layout;
+        self.set_fps_limit(scene.fps_limit);
+        self.set_show_bounds(scene.show_bounds);
+        self.layer_masks = scene.layer_masks;
+        Ok(())
+    }
+
+    // Outlines the mesh bounds of every object, the selected ones stand out
+    pub fn set_show_bounds(&mut self, show_bounds: bool) {
+        if let Err(err) = self.configuration.set_show_bounds(show_bounds) {
+            warn!("Can't show the object bounds: {err}");
+        }
+    }
+
+    pub fn show_bounds(&self) -> bool {
+        self.configuration.show_bounds()
+    }
+
+    // What the device was found to lack or need, also in the diagnostics
+    // report
+    pub fn platform_quirks(&self) -> PlatformQuirks {
+        self.configuration.platform_quirks()
+    }
+
+    // The limits of the device the configuration was checked against at
+    // startup, also in the diagnostics report
+    pub fn device_limits(&self) -> DeviceLimits {
+        self.configuration.device_limits()
+    }
+
+    // Tints every object by its level of detail, from green for the first
+    // simplified one to red for the coarsest
+    pub fn set_show_lods(&mut self, show_lods: bool) {
+        self.configuration.set_show_lods(show_lods);
+    }
+
+    pub fn show_lods(&self) -> bool {
+        self.configuration.show_lods()
+    }
+
+    pub fn window_resized(&mut self, view: ViewId, size: PhysicalSize<u32>) {
+        if let Some(ctx) = self.configuration.surface_mut(view) {
+            ctx.resize(size);
+        }
+    }
+
+    // Called with the Win32 monitor once the primary window went exclusive
+    // fullscreen and with None before it leaves, so the swapchain can own the
+    // display through VK_EXT_full_screen_exclusive. Does nothing elsewhere
+    pub fn set_exclusive_fullscreen(&mut self, hmonitor: Option<isize>) {
+        self.configuration
+            .set_exclusive_monitor(ViewId::PRIMARY, hmonitor);
+    }
+
+    // Opens another surface on `window` that renders the same scene through
+    // its own cameras
+    pub fn create_view(&mut self, window: &Window) -> Result<ViewId, String> {
+        if self.views.len() >= MAX_VIEWS as usize {
+            return Err(format!("View limit of {MAX_VIEWS} reached"));
+        }
+        let id = ViewId(self.next_view_id);
+        self.configuration.add_surface(window, id)?;
+        self.next_view_id += 1;
+        self.views.push(View::new(id));
+        Ok(id)
+    }
+
+    // Windows can only be created from the event loop, the runner opens one
+    // per request after the current frame
+    pub fn request_view(&mut self, camera: Camera) {
+        self.view_requests.push(camera);
+    }
+
+    pub(crate) fn take_view_requests(&mut self) -> Vec<Camera> {
+        std::mem::take(&mut self.view_requests)
+    }
+
+    // Only tears down the resources of that window, the others keep rendering
+    pub fn destroy_view(&mut self, view: ViewId) {
+        self.configuration.remove_surface(view);
+        self.views.retain(|v| v.id != view);
+    }
+
+    // Tears down every surface and swapchain when the platform takes the
+    // windows away. The device, pipelines and uploaded buffers are kept for
+    // `resume`, as are the primary view's cameras and layout
+    pub fn suspend(&mut self) {
+        if self.suspended {
+            return;
+        }
+        for view in self
+            .views
+            .iter()
+            .map(|view| view.id)
+            .collect::<Vec<ViewId>>()
+        {
+            self.configuration.remove_surface(view);
+        }
+        self.views.retain(|view| view.id == ViewId::PRIMARY);
+        self.suspended = true;
+        info!("Engine suspended");
+    }
+
+    // Rebuilds the primary surface on the new `window`, a no-op unless suspended
+    pub fn resume(&mut self, window: &Window) -> Result<(), String> {
+        if !self.suspended {
+            return Ok(());
+        }
+        self.configuration.add_surface(window, ViewId::PRIMARY)?;
+        self.pacer
+            .set_present_mode(self.configuration.surfaces[0].present_mode);
+        self.suspended = false;
+        info!("Engine resumed");
+        Ok(())
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    fn view_mut(&mut self, view: ViewId) -> Option<&mut View> {
+        self.views.iter_mut().find(|v| v.id == view)
+    }
+
+    // Applies the configured background behavior while the window is
+    // unfocused or occluded. Pausing freezes the animation clock so the
+    // scene resumes where it left off
+    pub fn set_throttled(&mut self, throttled: bool) {
+        let behavior = throttled.then_some(self.background_behavior);
+        self.pacer.set_throttled(behavior);
+        self.paused = behavior == Some(BackgroundBehavior::Pause);
+    }
+
+    // Freezes the animation at `time` seconds, None lets it run on from there
+    pub fn set_time(&mut self, time: Option<f32>) {
+        if let Some(time) = time {
+            self.previous_time = time;
+            self.time = time;
+        }
+        self.time_frozen = time.is_some();
+    }
+
+    // Every frame moves the clock on by `frame_time` however long it really
+    // took, None goes back to the real frame time
+    pub fn set_fixed_frame_time(&mut self, frame_time: Option<Duration>) {
+        self.fixed_frame_time = frame_time;
+    }
+
+    pub fn fixed_frame_time(&self) -> Option<Duration> {
+        self.fixed_frame_time
+    }
+
+    // Adds a frame's worth of real time and returns how many fixed updates
+    // are due. None are while paused, so the scene resumes where it left off
+    pub(crate) fn advance_time(&mut self, frame_time: Duration) -> u32 {
+        if self.paused {
+            return 0;
+        }
+        self.timestep.advance(frame_time)
+    }
+
+    // The engine's own share of a fixed update, run after the app's
+    pub(crate) fn update_fixed(&mut self) {
+        if self.time_frozen {
+            return;
+        }
+        self.previous_time = self.time;
+        self.time += self.tick_duration();
+    }
+
+    // The engine's share of a frame, run after the app's `update`. Animations
+    // follow the frame time rather than fixed ticks so they stay smooth at any
+    // tick rate, and stop with the clock while paused or frozen
+    pub(crate) fn update(&mut self, dt: f32) {
+        self.input_sampled = Some(Instant::now());
+        if self.paused || self.time_frozen {
+            return;
+        }
+        for animator in &mut self.animators {
+            animator.advance(dt);
+            let skins = &self.object_skins;
+            let poses = animator
+                .sample(|target| match target {
+                    AnimationTarget::Joint {
+                        object: ObjectId(object),
+                        joint,
+                    } => skins
+                        .get(object)
+                        .and_then(Option::as_ref)
+                        .and_then(|skin| skin.skeleton().joints().get(joint))
+                        .map_or(Transform::default(), |joint| joint.rest),
+                    _ => Transform::default(),
+                })
+                .collect::<Vec<(AnimationTarget, Transform)>>();
+            for (target, transform) in poses {
+                match target {
+                    AnimationTarget::Object(ObjectId(index)) => {
+                        if let Some(object) = self.objects.get_mut(index) {
+                            object.transform = transform.matrix();
+                        }
+                    }
+                    AnimationTarget::Light(index) => {
+                        if let Some(light) = self.lights.get_mut(index) {
+                            light.position = transform.position();
+                        }
+                    }
+                    AnimationTarget::Joint {
+                        object: ObjectId(object),
+                        joint,
+                    } => {
+                        if let Some(Some(skin)) = self.object_skins.get_mut(object) {
+                            skin.set_joint_pose(joint, transform);
+                        }
                     }
-                    false => {}
-                },
-                Err(err) => {
-                    error!("Error: {err}");
-                    panic!();
                 }
             }
-
-            if self.configuration.window_resized {
-                self.configuration.window_resized = false;
-                self.configuration.recreate_swapchain();
+            for (ObjectId(object), weights) in animator.sample_weights() {
+                if let Some(object_weights) = self.object_morph_weights.get_mut(object) {
+                    *object_weights = weights;
+                }
             }
+        }
+    }
+
+    // Seconds simulated by each fixed update
+    pub fn tick_duration(&self) -> f32 {
+        self.timestep.step().as_secs_f32()
+    }
+
+    // How far the frame about to be drawn is between the last two fixed
+    // updates, for blending transforms that only change per tick
+    pub fn interpolation(&self) -> f32 {
+        self.timestep.alpha()
+    }
+
+    pub fn pacing_mode(&self) -> PacingMode {
+        self.pacer.mode()
+    }
+
+    pub fn fps(&self) -> Option<f32> {
+        self.pacer.fps()
+    }
+
+    // Swapchain rebuilds per second over the same window as `fps`
+    pub fn swapchain_recreation_rate(&self) -> Option<f32> {
+        self.pacer.recreation_rate()
+    }
+
+    pub fn target_frame_time(&self) -> Option<Duration> {
+        self.pacer.target_frame_time()
+    }
+
+    pub fn fps_limit(&self) -> Option<u32> {
+        self.pacer.fps_limit()
+    }
+
+    pub fn set_fps_limit(&mut self, fps_limit: Option<u32>) {
+        self.pacer.set_fps_limit(fps_limit);
+    }
+
+    // Swaps between FIFO and MAILBOX without rebuilding the pipelines, the
+    // mode is kept for windows created later
+    pub fn set_present_mode(&mut self, present_mode: vk::PresentModeKHR) -> Result<(), String> {
+        self.configuration.set_present_mode(present_mode)?;
+        if let Some(ctx) = self.configuration.surfaces.first() {
+            self.pacer.set_present_mode(ctx.present_mode);
+        }
+        Ok(())
+    }
+
+    // Mode of the primary window, None while suspended
+    pub fn present_mode(&self) -> Option<vk::PresentModeKHR> {
+        self.configuration
+            .surfaces
+            .first()
+            .map(|ctx| ctx.present_mode)
+    }
+
+    // Fraction of the window resolution the scene is rendered at
+    pub fn render_scale(&self) -> f32 {
+        self.configuration.render_scale()
+    }
+
+    // Pins the render scale between 0.5 and 1.0, None goes back to following
+    // `EngineConfig::frame_budget_ms`
+    pub fn set_render_scale(&mut self, render_scale: Option<f32>) {
+        self.configuration.set_render_scale(render_scale);
+    }
+
+    pub fn render_scale_override(&self) -> Option<f32> {
+        self.configuration.render_scale_override()
+    }
+
+    // Set while the scene shaders fail to build and the scene is drawn in
+    // flat magenta, see `EngineConfig::fragment_shader`
+    pub fn scene_shader_error(&self) -> Option<&str> {
+        self.configuration.scene_shader_error()
+    }
+
+    // None unless the engine was started with `EngineConfig::bloom`
+    pub fn bloom_settings(&self) -> Option<BloomSettings> {
+        self.configuration.bloom_settings()
+    }
+
+    // Takes effect on the next frame, ignored without bloom
+    pub fn set_bloom_settings(&mut self, settings: BloomSettings) {
+        self.configuration.set_bloom_settings(settings);
+    }
+
+    // None unless the engine was started with `EngineConfig::ssao`
+    pub fn ssao_settings(&self) -> Option<SsaoSettings> {
+        self.configuration.ssao_settings()
+    }
+
+    // Takes effect on the next frame, ignored without SSAO
+    pub fn set_ssao_settings(&mut self, settings: SsaoSettings) {
+        self.configuration.set_ssao_settings(settings);
+    }
+
+    // Milliseconds the GPU spent on the primary window's last read back frame
+    pub fn culling_stats(&self) -> CullingStats {
+        self.configuration.culling_stats()
+    }
+
+    pub fn gpu_frame_time(&self) -> Option<f32> {
+        self.configuration.gpu_frame_time()
+    }
+
+    // Milliseconds the last frame took on the CPU, from its fence wait to
+    // its present
+    pub fn cpu_frame_time(&self) -> f32 {
+        self.frame_work.as_secs_f32() * 1000.0
+    }
+
+    // Whether objects are culled by the compute pass into indirect draws,
+    // reading their entries from a storage buffer
+    pub fn gpu_culling(&self) -> bool {
+        self.configuration.gpu_culling()
+    }
+
+    // Estimated milliseconds from the app reading its input to the frame
+    // being on screen, smoothed over the last frames
+    pub fn input_latency(&self) -> Option<f32> {
+        self.input_latency
+    }
+
+    pub fn low_latency(&self) -> bool {
+        self.pacer.low_latency()
+    }
+
+    // Missed vsyncs, queue depth and display latency since startup
+    pub fn present_stats(&self) -> PresentStats {
+        self.configuration.present_stats()
+    }
+
+    pub fn set_low_latency(&mut self, low_latency: bool) {
+        self.pacer.set_low_latency(low_latency);
+    }
 
-            self.frame = (self.frame.add(1)) % MAX_FLIGHT_FENCES;
+    pub fn set_viewport_layout(&mut self, view: ViewId, viewport_layout: ViewportLayout) {
+        if let Some(view) = self.view_mut(view) {
+            view.layout = viewport_layout;
+        }
+    }
+
+    pub fn viewport_layout(&self, view: ViewId) -> Option<ViewportLayout> {
+        self.views.iter().find(|v| v.id == view).map(|v| v.layout)
+    }
+
+    // Camera used for the viewport region at `index`, region 0 is also the
+    // camera of the single viewport layout
+    pub fn set_camera(&mut self, view: ViewId, index: usize, camera: Camera) {
+        match self
+            .view_mut(view)
+            .and_then(|view| view.cameras.get_mut(index))
+        {
+            Some(slot) => *slot = camera,
+            None => warn!("No viewport region {index} on {view:?}, ignoring camera"),
+        }
+    }
+
+    // Views and surfaces are kept in the same order
+    fn viewport_regions(&self) -> Vec<Vec<Rect2D>> {
+        self.views
+            .iter()
+            .zip(self.configuration.surfaces.iter())
+            .map(|(view, ctx)| view.layout.regions(ctx.window_extent()))
+            .collect()
+    }
+
+    // The spin every object is drawn with on top of its transform, as of the
+    // frame about to be drawn
+    fn model_rotation(&self) -> Matrix4<f32> {
+        let time = self.previous_time + (self.time - self.previous_time) * self.interpolation();
+        Matrix4::from_axis_angle(vec3(0.0, 0.0, 1.0), Deg(85.0) * time * 0.5)
+    }
+
+    fn update_uniform_buffer(&mut self, current_frame: usize, view_regions: &[Vec<Rect2D>]) {
+        span!("update_uniform_buffer");
+        let rotation = self.model_rotation();
+
+        // Each region gets the aspect of its own size, not the full extent
+        let projections = self
+            .views
+            .iter()
+            .zip(&self.configuration.surfaces)
+            .zip(view_regions)
+            .map(|((view, ctx), regions)| {
+                let pre_rotation = ctx.rotation.matrix();
+                regions
+                    .iter()
+                    .zip(view.cameras.iter())
+                    .map(|(region, camera)| pre_rotation * camera.projection(aspect_ratio(region)))
+                    .collect::<Vec<Matrix4<f32>>>()
+            })
+            .collect::<Vec<_>>();
+        let palettes = self
+            .object_skins
+            .iter()
+            .map(|skin| skin.as_ref().map(Skin::joint_matrices))
+            .collect::<Vec<_>>();
+        let first_joints = self.configuration.update_joints(current_frame, &palettes);
+        let joints = first_joints
+            .iter()
+            .zip(&palettes)
+            .map(|(first_joint, palette)| match (first_joint, palette) {
+                (Some(first_joint), Some(palette)) => [*first_joint, palette.len() as u32, 0, 0],
+                _ => [0; 4],
+            })
+            .collect::<Vec<[u32; 4]>>();
+        let morphs = (0..self.objects.len())
+            .map(|index| {
+                let morph_targets = self
+                    .configuration
+                    .object_mesh(&self.object_meshes, index as u32)
+                    .and_then(MeshResource::morph_targets);
+                let mut morph = (
+                    [0; 4],
+                    [0; MAX_ACTIVE_MORPH_TARGETS],
+                    [0.0; MAX_ACTIVE_MORPH_TARGETS],
+                );
+                if let Some(targets) = morph_targets {
+                    let active =
+                        active_morph_targets(&self.object_morph_weights[index], targets.count);
+                    morph.0 = [
+                        targets.first_delta,
+                        targets.vertex_count,
+                        active.len() as u32,
+                        0,
+                    ];
+                    for (slot, (target, weight)) in active.into_iter().enumerate() {
+                        morph.1[slot] = target;
+                        morph.2[slot] = weight;
+                    }
+                }
+                morph
+            })
+            .collect::<Vec<_>>();
+        let mut object_ubos = std::mem::take(&mut self.object_ubos);
+        object_ubos.clear();
+        object_ubos.extend(
+            self.views
+                .iter()
+                .zip(&projections)
+                .flat_map(|(view, projections)| view.cameras.iter().zip(projections))
+                .flat_map(|(camera, projection)| {
+                    let view = camera.view();
+                    let projection = *projection;
+                    self.objects.iter().zip(&joints).zip(&morphs).map(
+                        move |((object, joints), (morph, targets, weights))| UniformBufferObject {
+                            model: object.transform * rotation,
+                            view,
+                            projection,
+                            joints: *joints,
+                            morph: *morph,
+                            morph_targets: *targets,
+                            morph_weights: *weights,
+                        },
+                    )
+                }),
+        );
+        self.configuration
+            .update_uniform_buffer(current_frame, &object_ubos);
+        self.configuration
+            .update_ssao_uniforms(current_frame, &projections);
+        self.configuration
+            .update_lights(current_frame, &self.lights);
+        self.configuration
+            .update_materials(current_frame, &self.object_materials);
+        let drawn = self.objects_drawn_by(self.layer_masks.main);
+        self.configuration.update_culling(
+            current_frame,
+            &object_ubos,
+            (&self.object_meshes, &drawn),
+        );
+        self.object_ubos = object_ubos;
+        let view_projections = self
+            .views
+            .iter()
+            .zip(view_regions)
+            .map(|(view, regions)| {
+                regions
+                    .iter()
+                    .zip(view.cameras.iter())
+                    .map(|(region, camera)| camera.projection(aspect_ratio(region)) * camera.view())
+                    .collect()
+            })
+            .collect::<Vec<Vec<Matrix4<f32>>>>();
+        if self.show_debug_queries {
+            self.draw_debug_queries(&view_projections);
+        }
+        let (overlay, depth_tested, screen_text, screen_rects) = self.debug_draw.take();
+        let mut gizmo_lines = self.gizmo_lines();
+        gizmo_lines.extend(overlay);
+        let now = Instant::now();
+        for (level, message) in self.configuration.take_notifications() {
+            self.toasts.push(level, message, now);
+        }
+        self.toasts.expire(now);
+        let palette = self.debug_draw.palette();
+        let mut screen_lines = if self.show_frame_graph {
+            self.frame_time_graph.lines(palette)
+        } else {
+            Vec::new()
         };
+        let mut glyphs = GlyphVertices::new();
+        if let Some(window) = self
+            .configuration
+            .surfaces
+            .first()
+            .map(|ctx| ctx.window_extent())
+        {
+            let mut texts = Vec::new();
+            if self.show_log_view {
+                texts.push(log_view::text(palette, window));
+            }
+            texts.extend(self.toasts.text(palette, window, now));
+            // Screen lines are fractions of the window with y up
+            let (width, height) = (window.width as f32, window.height as f32);
+            let corner = |[x, y]: [f32; 2]| point3(x / width, 1.0 - y / height, 0.0);
+            for (from, to, color) in screen_rects {
+                let (a, b) = (corner(from), corner(to));
+                let (c, d) = (point3(a.x, b.y, 0.0), point3(b.x, a.y, 0.0));
+                for (start, end) in [(a, c), (c, b), (b, d), (d, a)] {
+                    screen_lines.extend([(start, color), (end, color)]);
+                }
+            }
+            for (text, text_box, color) in screen_text {
+                let rows = text::wrap(&text, text_box.columns)
+                    .into_iter()
+                    .map(|line| (line, color))
+                    .collect::<TextRows>();
+                texts.push((text_box, rows));
+            }
+            // Drawn with the stroke font unless a distance field one loaded
+            for (text_box, rows) in texts {
+                match self.configuration.text_font_mut() {
+                    Some(font) => glyphs.extend(text_box.glyphs(font, &rows, window)),
+                    None => screen_lines.extend(text_box.lines(&rows, window)),
+                }
+            }
+        }
+        self.configuration.update_text(current_frame, &glyphs);
+        self.configuration.update_gizmos(
+            current_frame,
+            &gizmo_lines,
+            &depth_tested,
+            &screen_lines,
+            view_projections,
+        );
+    }
+
+    fn draw_debug_queries(&mut self, view_projections: &[Vec<Matrix4<f32>>]) {
+        let palette = *self.debug_draw.palette();
+        for view_projection in view_projections.iter().flatten() {
+            self.debug_draw
+                .depth_test(false)
+                .frustum(*view_projection, palette.frustum);
+        }
+        if let Some(ray) = self.last_query_ray.get() {
+            self.debug_draw
+                .depth_test(true)
+                .ray(&ray, DEBUG_RAY_LENGTH, palette.ray);
+        }
+    }
+
+    // Paces the frame and waits until its slot is free again. `draw_frame`
+    // does this itself unless it already happened, the runner calls it ahead
+    // of the app's update in low latency mode so the input is read after the
+    // waiting rather than before it
+    pub fn wait_for_frame(&mut self) {
+        if self.suspended || self.frame_waited {
+            return;
+        }
+        self.pacer.wait();
+        let current_frame = self.frame as usize;
+
+        if let Err(err) = wait_for_slot(self, current_frame) {
+            error!(target: logging::FRAME, "{err}, aborting");
+            panic!("{err}");
+        }
+        let gpu_time = self.gpu_frame_time().map_or(Duration::ZERO, |gpu_ms| {
+            Duration::from_secs_f32(gpu_ms / 1000.0)
+        });
+        self.pacer
+            .wait_for_present_margin(self.frame_work + gpu_time);
+        self.frame_started = Some(Instant::now());
+        self.frame_waited = true;
+    }
+
+    pub fn draw_frame(&mut self) {
+        if self.suspended {
+            self.debug_draw.take();
+            return;
+        }
+        span!("draw_frame");
+        self.wait_for_frame();
+        self.frame_waited = false;
+        let current_frame = self.frame as usize;
+        self.frame_capture.begin_frame();
+        self.configuration.collect_recorded_frame(current_frame);
+        if self.configuration.recording_done() {
+            self.configuration.stop_recording();
+        }
+
+        if let Err(err) = draw_frame(self, current_frame) {
+            error!(target: logging::FRAME, "{err}");
+            panic!("{err}");
+        }
+        self.configuration.finish_screenshot(current_frame);
+        if self.frame_capture.end_frame() {
+            self.notify(Level::Info, "Frame captured in RenderDoc");
+        }
+
+        self.frame = next_frame(self.frame, self.frames_in_flight);
+        self.pacer
+            .frame_finished(self.configuration.swapchain_recreations());
+        self.frame_time_graph
+            .push(self.pacer.frame_time(), self.gpu_frame_time());
+        self.configuration
+            .collect_present_timing(self.pacer.refresh_interval(), self.pacer.frame_time());
+        if let Some(frame_started) = self.frame_started.take() {
+            self.frame_work = frame_started.elapsed();
+        }
+        // Presentation feedback isn't available, the image is taken to reach
+        // the screen once the GPU is done with it after the present call
+        if let Some(input_sampled) = self.input_sampled.take() {
+            let latency = input_sampled.elapsed().as_secs_f32() * 1000.0
+                + self.gpu_frame_time().unwrap_or(0.0);
+            self.input_latency = Some(match self.input_latency {
+                Some(average) => average + (latency - average) * INPUT_LATENCY_SMOOTHING,
+                None => latency,
+            });
+        }
+        #[cfg(feature = "profiling")]
+        if let Some(client) = tracy_client::Client::running() {
+            client.frame_mark();
+        }
+    }
+
+    // Writes every frame the primary view draws to `dir` as numbered PNGs,
+    // until `frames` have been written or `stop_recording`. The copies are
+    // read back a few frames later and written on a thread of their own
+    pub fn start_recording(
+        &mut self,
+        dir: impl Into<PathBuf>,
+        frames: Option<u32>,
+        overflow: RecordOverflow,
+    ) -> Result<(), String> {
+        self.configuration
+            .start_recording(dir.into(), frames, overflow, self.frames_in_flight)
+    }
+
+    // Blocks until the frames recorded so far are written
+    pub fn stop_recording(&mut self) {
+        self.configuration.stop_recording();
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.configuration.is_recording()
+    }
+
+    // Written after the next frame the primary view draws
+    pub fn request_screenshot(&mut self, path: impl Into<PathBuf>) {
+        if let Err(err) = self.configuration.request_screenshot(path.into()) {
+            self.notify(Level::Warn, format!("Can't take a screenshot: {err}"));
+        }
+    }
+
+    // Captures the next frame in RenderDoc when the process runs under it
+    pub fn request_frame_capture(&mut self) {
+        if let Err(err) = self.frame_capture.request() {
+            self.notify(Level::Warn, format!("Can't capture a frame: {err}"));
+        }
+    }
+
+    // Closes every window and returns from `Engine::run` after this frame
+    pub fn request_exit(&mut self) {
+        self.exit_requested = true;
+    }
+
+    pub fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+    // For panic hooks: waits for the GPU to go idle and writes the
+    // diagnostics, `message` and a backtrace to a caterpie-crash-*.log file.
+    // Returns where it was written, None when no engine is running
+    pub fn emergency_shutdown(message: &str) -> Option<std::io::Result<PathBuf>> {
+        crash::emergency_shutdown(message)
+    }
+
+    // A random stream for `subsystem` derived from the configured seed, the
+    // same on every run and every call
+    pub fn rng(&self, subsystem: &str) -> Rng {
+        Rng::new(self.configuration.seed()).fork(subsystem)
+    }
+
+    // Panics while recording the next frame, to try out panic hooks
+    pub fn request_test_panic(&mut self) {
+        self.configuration.test_panic = true;
     }
 
     pub fn destroy(&mut self) {
+        crash::unregister();
+        // Freed through the deletion queue the configuration flushes
+        self.object_meshes.clear();
+        self.object_skins.clear();
+        self.object_morph_weights.clear();
+        self.object_materials.clear();
         self.configuration.destroy();
     }
 }
+
+// The frame loop's view of the engine. The slot's buffers are written once
+// for every surface, which then draw their regions' entries
+impl FrameHost for Engine {
+    fn gpu(&self) -> &dyn GpuDevice {
+        &self.configuration
+    }
+
+    fn surface_count(&self) -> usize {
+        self.configuration.surfaces.len()
+    }
+
+    fn is_minimized(&self, surface_index: usize) -> bool {
+        self.configuration.surfaces[surface_index].is_minimized()
+    }
+
+    fn frame_slot(&self, surface_index: usize, frame_index: usize) -> FrameSlot {
+        self.configuration.surfaces[surface_index].frame_slot(frame_index)
+    }
+
+    fn swapchain_generation(&self, surface_index: usize) -> u64 {
+        self.configuration.surfaces[surface_index]
+            .swapchain
+            .generation()
+    }
+
+    fn render_finished(&self, surface_index: usize, image_index: u32) -> Semaphore {
+        self.configuration.surfaces[surface_index]
+            .swapchain
+            .render_finished_semaphores[image_index as usize]
+    }
+
+    fn update_frame(&mut self, frame_index: usize) {
+        self.configuration
+            .flush_deletions(frame_index, self.frames_in_flight);
+        self.configuration.reset_uploads(frame_index);
+        self.configuration.stream_textures(frame_index);
+        self.configuration.hot_reload_shaders();
+        self.configuration.update_render_scale(frame_index);
+
+        self.view_regions = self.viewport_regions();
+        let view_regions = std::mem::take(&mut self.view_regions);
+        self.update_uniform_buffer(frame_index, &view_regions);
+        self.view_regions = view_regions;
+    }
+
+    // Entries are laid out surface by surface, each with one block per region
+    fn record_frame(
+        &mut self,
+        surface_index: usize,
+        frame: &FrameContext,
+    ) -> Result<(), EngineError> {
+        let first_entry = self.view_regions[..surface_index]
+            .iter()
+            .map(|regions| regions.len().min(MAX_VIEWPORTS as usize) * self.objects.len())
+            .sum::<usize>() as u32;
+        self.configuration.record_command_buffer(
+            surface_index,
+            frame.image_index,
+            frame.frame_index,
+            &self.object_meshes,
+            &self.view_regions[surface_index],
+            first_entry,
+        )
+    }
+
+    fn prepare_present(&mut self, surface_index: usize, suboptimal: bool) -> (bool, Option<u64>) {
+        let stale = self
+            .configuration
+            .swapchain_stale(surface_index, suboptimal);
+        (stale, self.configuration.queue_present(surface_index))
+    }
+
+    fn skip_frame(&mut self, slot: &FrameSlot) {
+        self.configuration
+            .skip_frame(slot.image_available, slot.in_flight);
+    }
+
+    fn recreate_swapchain(&mut self, surface_index: usize) -> Result<(), EngineError> {
+        self.configuration.recreate_swapchain(surface_index)
+    }
+}
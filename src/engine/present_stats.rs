@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+// Where `PresentStats` come from, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentTimingSource {
+    // VK_GOOGLE_display_timing, when the display engine actually showed
+    // every image
+    DisplayTiming,
+    // VK_KHR_present_wait, when presents were seen to complete at the start
+    // of the following frames. Only as precise as the frame rate
+    PresentWait,
+    // Neither is available, CPU frame times against the refresh interval
+    #[default]
+    CpuFrameTime,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PresentStats {
+    pub source: PresentTimingSource,
+    // Images the numbers below were taken over
+    pub frames: u64,
+    // Refreshes that showed the previous image again. A frame rate capped
+    // below the refresh rate misses some by design
+    pub missed_vsyncs: u64,
+    // Presents queued but not on screen yet, averaged over every present
+    pub average_queue_depth: Option<f32>,
+    // Milliseconds from the present call to the image being shown
+    pub display_latency_ms: Option<f32>,
+}
+
+// Refreshes between two images beyond the one that should have shown the
+// second, rounded so jitter around the interval doesn't count
+pub fn missed_refreshes(interval: Duration, refresh: Duration) -> u64 {
+    if refresh.is_zero() {
+        return 0;
+    }
+    let refreshes = (interval.as_secs_f64() / refresh.as_secs_f64()).round() as u64;
+    refreshes.saturating_sub(1)
+}
+
+// Totals since startup, fed by whichever source the device supports
+#[derive(Debug, Default)]
+pub struct PresentStatsAccumulator {
+    source: PresentTimingSource,
+    frames: u64,
+    missed_vsyncs: u64,
+    queue_depth_sum: u64,
+    queue_depth_samples: u64,
+    latency_sum: Duration,
+    latency_samples: u64,
+}
+
+impl PresentStatsAccumulator {
+    pub fn new(source: PresentTimingSource) -> Self {
+        PresentStatsAccumulator {
+            source,
+            ..Default::default()
+        }
+    }
+
+    pub fn source(&self) -> PresentTimingSource {
+        self.source
+    }
+
+    // Presents still waiting to be shown, the new one included
+    pub fn queued(&mut self, queue_depth: usize) {
+        self.queue_depth_sum += queue_depth as u64;
+        self.queue_depth_samples += 1;
+    }
+
+    // An image reached the screen `interval` after the one before it, None
+    // for the first one shown
+    pub fn shown(
+        &mut self,
+        interval: Option<Duration>,
+        refresh: Option<Duration>,
+        latency: Option<Duration>,
+    ) {
+        self.frames += 1;
+        if let (Some(interval), Some(refresh)) = (interval, refresh) {
+            self.missed_vsyncs += missed_refreshes(interval, refresh);
+        }
+        if let Some(latency) = latency {
+            self.latency_sum += latency;
+            self.latency_samples += 1;
+        }
+    }
+
+    // Without presentation feedback, a CPU frame longer than the refresh
+    // interval is taken to have missed the refreshes it spanned
+    pub fn cpu_frame(&mut self, frame_time: Duration, refresh: Option<Duration>) {
+        self.shown(Some(frame_time), refresh, None);
+    }
+
+    pub fn stats(&self) -> PresentStats {
+        PresentStats {
+            source: self.source,
+            frames: self.frames,
+            missed_vsyncs: self.missed_vsyncs,
+            average_queue_depth: (self.queue_depth_samples > 0)
+                .then(|| self.queue_depth_sum as f32 / self.queue_depth_samples as f32),
+            display_latency_ms: (self.latency_samples > 0)
+                .then(|| self.latency_sum.as_secs_f32() * 1000.0 / self.latency_samples as f32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFRESH: Duration = Duration::from_micros(16_667);
+
+    // Shows images `intervals` microseconds apart on a 60 Hz display
+    fn shown(intervals: &[u64]) -> PresentStats {
+        let mut stats = PresentStatsAccumulator::new(PresentTimingSource::DisplayTiming);
+        stats.shown(None, Some(REFRESH), None);
+        for interval in intervals {
+            stats.shown(Some(Duration::from_micros(*interval)), Some(REFRESH), None);
+        }
+        stats.stats()
+    }
+
+    #[test]
+    fn jitter_around_the_refresh_is_not_a_miss() {
+        let stats = shown(&[16_667, 15_900, 17_400, 16_100, 24_000]);
+        assert_eq!((stats.frames, stats.missed_vsyncs), (6, 0));
+    }
+
+    #[test]
+    fn stutters_count_every_refresh_they_repeat() {
+        // One image held for two refreshes, one for three
+        let stats = shown(&[16_667, 33_300, 16_667, 50_000, 16_667]);
+        assert_eq!(stats.missed_vsyncs, 3);
+        // A 30 fps cap misses every other refresh by design
+        assert_eq!(shown(&[33_333; 10]).missed_vsyncs, 10);
+    }
+
+    #[test]
+    fn unknown_refresh_rates_miss_nothing() {
+        assert_eq!(
+            missed_refreshes(Duration::from_millis(50), Duration::ZERO),
+            0
+        );
+        let mut stats = PresentStatsAccumulator::default();
+        stats.shown(Some(Duration::from_millis(50)), None, None);
+        assert_eq!(stats.stats().missed_vsyncs, 0);
+    }
+
+    #[test]
+    fn queue_depth_and_latency_are_averaged() {
+        let mut stats = PresentStatsAccumulator::new(PresentTimingSource::PresentWait);
+        assert_eq!(stats.stats().average_queue_depth, None);
+        assert_eq!(stats.stats().display_latency_ms, None);
+        for (depth, latency) in [(1, 10), (2, 20), (3, 30), (2, 40)] {
+            stats.queued(depth);
+            stats.shown(None, None, Some(Duration::from_millis(latency)));
+        }
+        let stats = stats.stats();
+        assert_eq!(stats.average_queue_depth, Some(2.0));
+        assert_eq!(stats.display_latency_ms, Some(25.0));
+        assert_eq!(stats.source, PresentTimingSource::PresentWait);
+    }
+
+    #[test]
+    fn cpu_frames_over_the_refresh_count_as_misses() {
+        let mut stats = PresentStatsAccumulator::default();
+        for frame_time in [16, 17, 40, 16, 70] {
+            stats.cpu_frame(Duration::from_millis(frame_time), Some(REFRESH));
+        }
+        let stats = stats.stats();
+        assert_eq!((stats.frames, stats.missed_vsyncs), (5, 1 + 3));
+        assert_eq!(stats.display_latency_ms, None);
+    }
+}
@@ -0,0 +1,305 @@
+use cgmath::{point3, vec4, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+
+use crate::engine::scene::{Aabb, ObjectId};
+
+// Triangles below this are tested one by one, building a BVH doesn't pay off
+pub const BVH_MIN_TRIANGLES: usize = 64;
+// Triangles per BVH leaf
+const LEAF_TRIANGLES: usize = 4;
+// Hits closer than this to the ray origin are ignored, so a ray starting on a
+// surface doesn't hit it again
+const MIN_DISTANCE: f32 = 1e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    // Normalized for world space rays. Rays moved into an object's space keep
+    // the world scale, so their distances stay comparable
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    // The ray through `ndc`, in -1..1 with y pointing up, of a
+    // camera with `view_projection`. None for a singular matrix
+    pub fn from_ndc(view_projection: Matrix4<f32>, ndc: [f32; 2]) -> Option<Ray> {
+        let inverse = view_projection.invert()?;
+        let unproject = |depth: f32| {
+            let point = inverse * vec4(ndc[0], ndc[1], depth, 1.0);
+            point3(point.x, point.y, point.z) / point.w
+        };
+        let near = unproject(0.0);
+        let far = unproject(1.0);
+        let direction = far - near;
+        (direction.magnitude2() > 0.0).then(|| Ray {
+            origin: near,
+            direction: direction.normalize(),
+        })
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    // Into the space `transform` maps from, None when it can't be inverted
+    pub fn transformed(&self, transform: Matrix4<f32>) -> Option<Ray> {
+        let inverse = transform.invert()?;
+        let origin = inverse * self.origin.to_homogeneous();
+        Some(Ray {
+            origin: Point3::from_homogeneous(origin),
+            direction: (inverse * self.direction.extend(0.0)).truncate(),
+        })
+    }
+
+    // Distance along the ray to the first hit of the sphere, None when it
+    // misses or the sphere is behind the origin
+    pub fn intersect_sphere(&self, center: Point3<f32>, radius: f32) -> Option<f32> {
+        let to_center = center - self.origin;
+        let along = to_center.dot(self.direction);
+        let distance2 = to_center.magnitude2() - along * along;
+        if distance2 > radius * radius {
+            return None;
+        }
+        let half_chord = (radius * radius - distance2).sqrt();
+        [along - half_chord, along + half_chord]
+            .into_iter()
+            .find(|distance| *distance >= 0.0)
+    }
+
+    // Slab test, the distance the ray enters the box at or 0 when it starts
+    // inside. None when it misses or only enters beyond `max_distance`
+    pub fn intersect_aabb(&self, bounds: &Aabb, max_distance: f32) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let mut t0 = (bounds.min[axis] - self.origin[axis]) * inverse;
+            let mut t1 = (bounds.max[axis] - self.origin[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN from a ray lying in a slab plane keeps the previous bounds
+            near = if t0 > near { t0 } else { near };
+            far = if t1 < far { t1 } else { far };
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    // Möller–Trumbore. Only counter-clockwise triangles facing the ray are
+    // hit, matching the back face culling of the scene pipeline. Hits on an
+    // edge count
+    pub fn intersect_triangle(&self, [p0, p1, p2]: &[Point3<f32>; 3]) -> Option<f32> {
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        // Back facing, parallel or degenerate
+        if determinant <= f32::EPSILON * edge1.magnitude() * edge2.magnitude() {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let offset = self.origin - p0;
+        let u = offset.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = offset.cross(edge1);
+        let v = self.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge2.dot(q) * inverse;
+        (distance > MIN_DISTANCE).then_some(distance)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub object: ObjectId,
+    // World space
+    pub position: Point3<f32>,
+    // Along the cursor ray from the camera's near plane
+    pub distance: f32,
+}
+
+#[derive(Debug, Clone)]
+struct BvhNode {
+    bounds: Aabb,
+    // Leaves hold `count` triangles from `first`, other nodes have their
+    // children at `first` and `first + 1`
+    first: u32,
+    count: u32,
+}
+
+// Bounding volume hierarchy over a mesh's triangles, split at the median
+// centroid along the longest axis. The triangles are copied in leaf order
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<[Point3<f32>; 3]>,
+}
+
+fn triangle_bounds(triangles: &[[Point3<f32>; 3]]) -> Aabb {
+    Aabb::from_points(triangles.iter().flatten().copied()).unwrap()
+}
+
+fn centroid([p0, p1, p2]: &[Point3<f32>; 3]) -> Point3<f32> {
+    point3(
+        (p0.x + p1.x + p2.x) / 3.0,
+        (p0.y + p1.y + p2.y) / 3.0,
+        (p0.z + p1.z + p2.z) / 3.0,
+    )
+}
+
+impl Bvh {
+    // None without triangles
+    pub fn build(mut triangles: Vec<[Point3<f32>; 3]>) -> Option<Bvh> {
+        if triangles.is_empty() {
+            return None;
+        }
+        let mut nodes = vec![BvhNode {
+            bounds: triangle_bounds(&triangles),
+            first: 0,
+            count: triangles.len() as u32,
+        }];
+        let mut pending = vec![0];
+        while let Some(node_index) = pending.pop() {
+            let BvhNode { first, count, .. } = nodes[node_index];
+            if (count as usize) <= LEAF_TRIANGLES {
+                continue;
+            }
+            let range = first as usize..(first + count) as usize;
+            let centroids = Aabb::from_points(triangles[range.clone()].iter().map(centroid))?;
+            let size = centroids.max - centroids.min;
+            let axis = if size.x >= size.y && size.x >= size.z {
+                0
+            } else if size.y >= size.z {
+                1
+            } else {
+                2
+            };
+            let half = count / 2;
+            triangles[range].select_nth_unstable_by(half as usize, |a, b| {
+                centroid(a)[axis].total_cmp(&centroid(b)[axis])
+            });
+            let children = nodes.len() as u32;
+            for (child_first, child_count) in [(first, half), (first + half, count - half)] {
+                let child_range = child_first as usize..(child_first + child_count) as usize;
+                nodes.push(BvhNode {
+                    bounds: triangle_bounds(&triangles[child_range]),
+                    first: child_first,
+                    count: child_count,
+                });
+                pending.push(nodes.len() - 1);
+            }
+            nodes[node_index].first = children;
+            nodes[node_index].count = 0;
+        }
+        Some(Bvh { nodes, triangles })
+    }
+
+    // Distance to the closest triangle hit
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let mut closest = None::<f32>;
+        let mut stack = vec![0u32];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index as usize];
+            let max_distance = closest.unwrap_or(f32::INFINITY);
+            if ray.intersect_aabb(&node.bounds, max_distance).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.extend([node.first, node.first + 1]);
+                continue;
+            }
+            let leaf = node.first as usize..(node.first + node.count) as usize;
+            for triangle in &self.triangles[leaf] {
+                if let Some(distance) = ray.intersect_triangle(triangle) {
+                    closest = Some(closest.map_or(distance, |closest| closest.min(distance)));
+                }
+            }
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{perspective, Deg, EuclideanSpace};
+
+    use super::*;
+
+    // Counter-clockwise seen from +z
+    const TRIANGLE: [Point3<f32>; 3] = [
+        point3(0.0, 0.0, 0.0),
+        point3(1.0, 0.0, 0.0),
+        point3(0.0, 1.0, 0.0),
+    ];
+
+    fn down_at(x: f32, y: f32) -> Ray {
+        Ray {
+            origin: point3(x, y, 5.0),
+            direction: -Vector3::unit_z(),
+        }
+    }
+
+    #[test]
+    fn triangles_are_hit_from_the_front() {
+        assert_eq!(down_at(0.25, 0.25).intersect_triangle(&TRIANGLE), Some(5.0));
+        assert_eq!(down_at(0.6, 0.6).intersect_triangle(&TRIANGLE), None);
+    }
+
+    #[test]
+    fn back_faces_are_missed() {
+        let ray = Ray {
+            origin: point3(0.25, 0.25, -5.0),
+            direction: Vector3::unit_z(),
+        };
+        assert_eq!(ray.intersect_triangle(&TRIANGLE), None);
+    }
+
+    #[test]
+    fn rays_parallel_to_the_triangle_miss() {
+        for origin in [point3(-1.0, 0.25, 0.0), point3(-1.0, 0.25, 1.0)] {
+            let ray = Ray {
+                origin,
+                direction: Vector3::unit_x(),
+            };
+            assert_eq!(ray.intersect_triangle(&TRIANGLE), None);
+        }
+    }
+
+    #[test]
+    fn edges_and_corners_are_hit() {
+        for (x, y) in [(0.5, 0.0), (0.0, 0.5), (0.5, 0.5), (0.0, 0.0), (1.0, 0.0)] {
+            assert_eq!(
+                down_at(x, y).intersect_triangle(&TRIANGLE),
+                Some(5.0),
+                "({x}, {y})"
+            );
+        }
+    }
+
+    #[test]
+    fn unprojected_rays_pass_through_the_projected_point() {
+        let view = Matrix4::look_at_rh(point3(3.0, 2.0, 4.0), Point3::origin(), Vector3::unit_y());
+        let view_projection = perspective(Deg(60.0), 1.5, 0.1, 100.0) * view;
+        for target in [
+            point3(0.0, 0.0, 0.0),
+            point3(0.5, -0.3, 1.2),
+            point3(-2.0, 1.0, -1.0),
+        ] {
+            let clip = view_projection * target.to_homogeneous();
+            let ndc = [clip.x / clip.w, clip.y / clip.w];
+            let ray = Ray::from_ndc(view_projection, ndc).unwrap();
+            assert!((ray.direction.magnitude() - 1.0).abs() < 1e-5);
+            let along = (target - ray.origin).dot(ray.direction);
+            assert!(along > 0.0);
+            assert!((ray.at(along) - target).magnitude() < 1e-3, "{target:?}");
+        }
+        let singular = Matrix4::from_nonuniform_scale(1.0, 1.0, 0.0);
+        assert_eq!(Ray::from_ndc(singular, [0.0, 0.0]), None);
+    }
+}
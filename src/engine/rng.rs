@@ -0,0 +1,136 @@
+use std::ops::Range;
+
+use cgmath::{vec3, Vector3};
+
+// Engine seed when none is configured, so runs are reproducible by default
+pub const DEFAULT_SEED: u64 = 0x6361_7465_7270_6965;
+
+// SplitMix64's output function, also used to scramble fork seeds
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// FNV-1a, stable across platforms and Rust versions unlike DefaultHasher
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// SplitMix64. Every subsystem forks its own stream off the engine seed, so
+// the values one of them draws don't shift when another draws more
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    // A child stream named after its consumer, e.g. "ssao". Depends only on
+    // this stream's current state and `name`, this stream doesn't advance
+    pub fn fork(&self, name: &str) -> Rng {
+        Rng::new(mix(self.state ^ hash_name(name)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // Uniform in 0..1, 24 bits of precision
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn next_bool(&mut self) -> bool {
+        self.next_u64() >> 63 == 1
+    }
+
+    pub fn range_f32(&mut self, range: Range<f32>) -> f32 {
+        range.start + (range.end - range.start) * self.next_f32()
+    }
+
+    // Multiply and shift rather than modulo, the start for an empty range
+    pub fn range_u32(&mut self, range: Range<u32>) -> u32 {
+        let span = range.end.saturating_sub(range.start) as u64;
+        range.start + ((self.next_u32() as u64 * span) >> 32) as u32
+    }
+
+    pub fn range_i32(&mut self, range: Range<i32>) -> i32 {
+        let span = (range.end as i64 - range.start as i64).max(0) as u64;
+        (range.start as i64 + ((self.next_u32() as u64 * span) >> 32) as i64) as i32
+    }
+
+    // Uniform on the unit sphere, from a uniform height and angle
+    pub fn unit_vector(&mut self) -> Vector3<f32> {
+        let z = self.range_f32(-1.0..1.0);
+        let angle = self.next_f32() * std::f32::consts::TAU;
+        let radius = (1.0 - z * z).max(0.0).sqrt();
+        vec3(radius * angle.cos(), radius * angle.sin(), z)
+    }
+
+    // Uniform on the half of the unit sphere with z >= 0
+    pub fn hemisphere_vector(&mut self) -> Vector3<f32> {
+        let vector = self.unit_vector();
+        vec3(vector.x, vector.y, vector.z.abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_match_the_splitmix64_reference() {
+        let mut rng = Rng::new(0);
+        let drawn = [rng.next_u64(), rng.next_u64(), rng.next_u64()];
+        assert_eq!(
+            drawn,
+            [
+                0xe220_a839_7b1d_cdaf,
+                0x6e78_9e6a_a1b9_65f4,
+                0x06c4_5d18_8009_454f
+            ]
+        );
+    }
+
+    #[test]
+    fn forks_are_pinned_to_the_seed_and_name() {
+        let engine = Rng::new(DEFAULT_SEED);
+        let mut ssao = engine.fork("ssao");
+        assert_eq!(ssao, Rng::new(0x0c3b_5c18_87a6_e0d6));
+        let drawn = [ssao.next_u64(), ssao.next_u64(), ssao.next_u64()];
+        assert_eq!(
+            drawn,
+            [
+                0xcb94_c79a_1a8b_3143,
+                0xaedd_508d_d021_e7ab,
+                0x90a7_5e6c_e8b3_fa0a
+            ]
+        );
+        // Forking doesn't advance the parent, other names get other streams
+        assert_eq!(engine, Rng::new(DEFAULT_SEED));
+        assert_ne!(engine.fork("bloom"), engine.fork("ssao"));
+    }
+
+    #[test]
+    fn draws_stay_in_their_range() {
+        let mut rng = Rng::new(DEFAULT_SEED);
+        for _ in 0..1000 {
+            assert!((0.0..1.0).contains(&rng.next_f32()));
+            assert!((3..7).contains(&rng.range_u32(3..7)));
+            assert!((-5..-2).contains(&rng.range_i32(-5..-2)));
+            assert!(rng.hemisphere_vector().z >= 0.0);
+        }
+        assert_eq!(rng.range_u32(4..4), 4);
+    }
+}
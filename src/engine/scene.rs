@@ -0,0 +1,558 @@
+use std::path::{Path, PathBuf};
+
+use cgmath::{
+    perspective, point3, vec3, Deg, EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, Vector3,
+    Vector4,
+};
+
+use crate::engine::viewport::{ViewportLayout, MAX_VIEWPORTS};
+
+// Axis aligned bounding box in model space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    // None for an empty set of points
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Option<Self> {
+        points.into_iter().fold(None, |bounds, point| {
+            Some(match bounds {
+                None => Aabb {
+                    min: point,
+                    max: point,
+                },
+                Some(Aabb { min, max }) => Aabb {
+                    min: point3(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z)),
+                    max: point3(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z)),
+                },
+            })
+        })
+    }
+
+    // Bit 0 picks x, bit 1 y and bit 2 z from `max` instead of `min`
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        std::array::from_fn(|index| {
+            point3(
+                if index & 1 == 0 {
+                    self.min.x
+                } else {
+                    self.max.x
+                },
+                if index & 2 == 0 {
+                    self.min.y
+                } else {
+                    self.max.y
+                },
+                if index & 4 == 0 {
+                    self.min.z
+                } else {
+                    self.max.z
+                },
+            )
+        })
+    }
+
+    // The 12 edges as pairs of points, ready for a line list
+    pub fn edges(&self) -> [Point3<f32>; 24] {
+        box_edges(&self.corners())
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    // Half the size along each axis
+    pub fn extent(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    // Center and extent of the box around these bounds transformed by `model`
+    pub fn transformed(&self, model: &Matrix4<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        let center = model * self.center().to_homogeneous();
+        let extent = self.extent();
+        let extent = model.x.truncate().map(f32::abs) * extent.x
+            + model.y.truncate().map(f32::abs) * extent.y
+            + model.z.truncate().map(f32::abs) * extent.z;
+        (center.truncate(), extent)
+    }
+
+    // The world space box around these bounds transformed by `model`
+    pub fn world(&self, model: &Matrix4<f32>) -> Aabb {
+        let (center, extent) = self.transformed(model);
+        Aabb {
+            min: Point3::from_vec(center - extent),
+            max: Point3::from_vec(center + extent),
+        }
+    }
+
+    // Touching boxes overlap
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    // Whether the point of the box closest to `center` is within `radius`
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        let closest = point3(
+            center.x.max(self.min.x).min(self.max.x),
+            center.y.max(self.min.y).min(self.max.y),
+            center.z.max(self.min.z).min(self.max.z),
+        );
+        (closest - center).magnitude2() <= radius * radius
+    }
+
+    // Diameter of the sphere around the transformed box as a fraction of
+    // the viewport height, infinite for boxes at or behind the camera
+    pub fn screen_size(
+        &self,
+        model: &Matrix4<f32>,
+        view: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+    ) -> f32 {
+        let (center, extent) = self.transformed(model);
+        let w = (projection * view * center.extend(1.0)).w;
+        if w <= f32::EPSILON {
+            return f32::INFINITY;
+        }
+        extent.magnitude() * projection.y.y.abs() / w
+    }
+
+    // The part of the screen the box covers, from its corners projected by
+    // `view_projection` and clamped to the screen. None when it is all
+    // behind the camera or off screen. A box the camera is inside of or
+    // next to covers the whole screen
+    pub fn screen_rect(&self, view_projection: &Matrix4<f32>) -> Option<ScreenRect> {
+        let clip = self
+            .corners()
+            .map(|corner| view_projection * corner.to_homogeneous());
+        if clip.iter().all(|corner| corner.w <= f32::EPSILON) {
+            return None;
+        }
+        if clip.iter().any(|corner| corner.w <= f32::EPSILON) {
+            return Some(ScreenRect::NDC);
+        }
+        let ndc = clip.map(|corner| [corner.x / corner.w, corner.y / corner.w]);
+        let rect = ndc[1..].iter().fold(
+            ScreenRect {
+                min: ndc[0],
+                max: ndc[0],
+            },
+            |rect, point| ScreenRect {
+                min: [rect.min[0].min(point[0]), rect.min[1].min(point[1])],
+                max: [rect.max[0].max(point[0]), rect.max[1].max(point[1])],
+            },
+        );
+        rect.clamped(&ScreenRect::NDC)
+    }
+}
+
+// Rectangle in normalized device coordinates, y up like `Ray::from_ndc`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl ScreenRect {
+    // The whole screen
+    pub const NDC: ScreenRect = ScreenRect {
+        min: [-1.0, -1.0],
+        max: [1.0, 1.0],
+    };
+
+    // Between two opposite corners in any order, like the ends of a drag
+    pub fn from_corners(a: [f32; 2], b: [f32; 2]) -> Self {
+        ScreenRect {
+            min: [a[0].min(b[0]), a[1].min(b[1])],
+            max: [a[0].max(b[0]), a[1].max(b[1])],
+        }
+    }
+
+    // Touching rectangles overlap
+    pub fn overlaps(&self, other: &ScreenRect) -> bool {
+        (0..2).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    // The part inside `bounds`, None when there is none
+    pub fn clamped(&self, bounds: &ScreenRect) -> Option<ScreenRect> {
+        self.overlaps(bounds).then(|| ScreenRect {
+            min: [
+                self.min[0].max(bounds.min[0]),
+                self.min[1].max(bounds.min[1]),
+            ],
+            max: [
+                self.max[0].min(bounds.max[0]),
+                self.max[1].min(bounds.max[1]),
+            ],
+        })
+    }
+}
+
+// The planes of a clip space volume, pointing inwards. Only the depth range
+// Vulkan keeps, 0 to w, counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    // Left, right, bottom, top, near and far as (normal, distance)
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // From a projection times view matrix, or with the model matrix folded
+    // in for model space tests
+    pub fn from_matrix(matrix: Matrix4<f32>) -> Self {
+        let row = |index: usize| matrix.row(index);
+        Self {
+            planes: [
+                row(3) + row(0),
+                row(3) - row(0),
+                row(3) + row(1),
+                row(3) - row(1),
+                row(2),
+                row(3) - row(2),
+            ],
+        }
+    }
+
+    // Whether any of the model space `bounds` transformed by `model` can be
+    // inside. Conservative, boxes near a corner can pass without being seen
+    pub fn intersects(&self, bounds: &Aabb, model: &Matrix4<f32>) -> bool {
+        let (center, extent) = bounds.transformed(model);
+        self.intersects_box(center, extent)
+    }
+
+    // The same test on an axis aligned box, cull.comp runs it on the GPU
+    pub fn intersects_box(&self, center: Vector3<f32>, extent: Vector3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + plane.w + normal.map(f32::abs).dot(extent) >= 0.0
+        })
+    }
+}
+
+// The 12 edges of a box whose corners are ordered as in `Aabb::corners`, it
+// doesn't have to be axis aligned
+pub fn box_edges(corners: &[Point3<f32>; 8]) -> [Point3<f32>; 24] {
+    let mut lines = [corners[0]; 24];
+    let mut line = 0;
+    for index in 0..8 {
+        for axis in [1, 2, 4] {
+            // Every edge once, from the corner with the axis bit unset
+            if index & axis == 0 {
+                lines[line] = corners[index];
+                lines[line + 1] = corners[index | axis];
+                line += 2;
+            }
+        }
+    }
+    lines
+}
+
+// Index of an object in the order it was added to the engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectId(pub usize);
+
+// Layers are bit flags, objects start out on DEFAULT_LAYER alone
+pub const DEFAULT_LAYER: u32 = 1;
+// Geometry that is only there while debugging, left out of picking
+pub const DEBUG_LAYER: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderObject {
+    pub transform: Matrix4<f32>,
+    // Hidden objects are left out of every pass, whatever their layers
+    pub visible: bool,
+    pub layers: u32,
+}
+
+impl RenderObject {
+    pub fn new(transform: Matrix4<f32>) -> Self {
+        Self {
+            transform,
+            visible: true,
+            layers: DEFAULT_LAYER,
+        }
+    }
+
+    // Whether a pass drawing the layers of `mask` draws this object
+    pub fn drawn_by(&self, mask: u32) -> bool {
+        self.visible && self.layers & mask != 0
+    }
+}
+
+// The layers each pass draws, an object is drawn when it is visible and on
+// any of them. There is no shadow pass yet to give a mask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerMasks {
+    // Forward or deferred, the bounds and selection outline included
+    pub main: u32,
+    // `Engine::pick` and `Engine::raycast`
+    pub picking: u32,
+}
+
+impl Default for LayerMasks {
+    fn default() -> Self {
+        Self {
+            main: u32::MAX,
+            picking: !DEBUG_LAYER,
+        }
+    }
+}
+
+// Lights everything within `radius` of `position`, fading out towards the
+// edge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub position: Point3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub radius: f32,
+}
+
+// Everything needed to rebuild what the primary window shows. Every object
+// is drawn with the same model and texture
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub model_path: PathBuf,
+    pub texture_path: PathBuf,
+    pub objects: Vec<RenderObject>,
+    // One per viewport region of the primary window
+    pub cameras: [Camera; MAX_VIEWPORTS as usize],
+    pub viewport_layout: ViewportLayout,
+    pub fps_limit: Option<u32>,
+    pub show_bounds: bool,
+    pub layer_masks: LayerMasks,
+}
+
+impl Scene {
+    #[cfg(feature = "scene-file")]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = super::scene_file::SceneFile::from_scene(self).to_ron()?;
+        std::fs::write(path, text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    #[cfg(not(feature = "scene-file"))]
+    pub fn save(&self, _path: &Path) -> Result<(), String> {
+        Err("built without the scene-file feature".to_string())
+    }
+
+    #[cfg(feature = "scene-file")]
+    pub fn load(path: &Path) -> Result<Scene, String> {
+        let bytes = crate::utils::io::read_asset(path)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        let text = String::from_utf8(bytes).map_err(|err| format!("{}: {err}", path.display()))?;
+        super::scene_file::SceneFile::parse(&text)
+            .map(super::scene_file::SceneFile::into_scene)
+            .map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    #[cfg(not(feature = "scene-file"))]
+    pub fn load(_path: &Path) -> Result<Scene, String> {
+        Err("built without the scene-file feature".to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            eye: point3(2.0, 2.0, 2.0),
+            target: point3(0.0, 0.0, 0.0),
+            up: vec3(0.0, 0.0, 1.0),
+            fov: Deg(45.0),
+            near: 0.1,
+            far: 10.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn view(&self) -> Matrix4<f32> {
+        Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    // Clip space y points up, the viewport flips it into Vulkan's
+    // framebuffer coordinates
+    pub fn projection(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        perspective(self.fov, aspect_ratio, self.near, self.far)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::SquareMatrix;
+
+    use super::*;
+
+    fn object(visible: bool, layers: u32) -> RenderObject {
+        RenderObject {
+            visible,
+            layers,
+            ..RenderObject::new(Matrix4::identity())
+        }
+    }
+
+    #[test]
+    fn passes_draw_objects_sharing_a_layer() {
+        let both = DEFAULT_LAYER | DEBUG_LAYER;
+        assert!(object(true, DEFAULT_LAYER).drawn_by(both));
+        assert!(object(true, both).drawn_by(DEBUG_LAYER));
+        assert!(!object(true, DEBUG_LAYER).drawn_by(DEFAULT_LAYER));
+        assert!(!object(true, 0).drawn_by(u32::MAX));
+        assert!(!object(true, DEFAULT_LAYER).drawn_by(0));
+    }
+
+    #[test]
+    fn hidden_objects_are_never_drawn() {
+        assert!(!object(false, DEFAULT_LAYER).drawn_by(u32::MAX));
+        assert!(!object(false, u32::MAX).drawn_by(DEFAULT_LAYER));
+    }
+
+    // New objects are drawn everywhere, debug geometry isn't picked
+    #[test]
+    fn default_masks() {
+        let masks = LayerMasks::default();
+        let new = RenderObject::new(Matrix4::identity());
+        assert!(new.drawn_by(masks.main) && new.drawn_by(masks.picking));
+        let debug = object(true, DEBUG_LAYER);
+        assert!(debug.drawn_by(masks.main));
+        assert!(!debug.drawn_by(masks.picking));
+    }
+
+    fn aabb(min: [f32; 3], max: [f32; 3]) -> Aabb {
+        Aabb {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    #[test]
+    fn boxes_overlap_on_every_axis() {
+        let unit = aabb([0.0; 3], [1.0; 3]);
+        assert!(unit.overlaps(&aabb([0.5; 3], [2.0; 3])));
+        assert!(unit.overlaps(&aabb([0.25; 3], [0.75; 3])));
+        // Apart on one axis is enough to miss
+        assert!(!unit.overlaps(&aabb([0.5, 0.5, 1.5], [2.0; 3])));
+        assert!(!unit.overlaps(&aabb([-2.0, 0.0, 0.0], [-0.5, 1.0, 1.0])));
+    }
+
+    #[test]
+    fn touching_boxes_overlap() {
+        let unit = aabb([0.0; 3], [1.0; 3]);
+        let next = aabb([1.0, 0.0, 0.0], [2.0, 1.0, 1.0]);
+        assert!(unit.overlaps(&next) && next.overlaps(&unit));
+        let corner = aabb([1.0; 3], [2.0; 3]);
+        assert!(unit.overlaps(&corner));
+    }
+
+    #[test]
+    fn spheres_reach_the_closest_point() {
+        let unit = aabb([0.0; 3], [1.0; 3]);
+        // Inside, and out along an axis
+        assert!(unit.intersects_sphere(point3(0.5, 0.5, 0.5), 0.0));
+        assert!(unit.intersects_sphere(point3(2.0, 0.5, 0.5), 1.0));
+        assert!(!unit.intersects_sphere(point3(2.0, 0.5, 0.5), 0.99));
+        // Off a corner the distance is the diagonal, not the axis distance
+        let corner = point3(2.0, 2.0, 2.0);
+        assert!(!unit.intersects_sphere(corner, 1.5));
+        assert!(unit.intersects_sphere(corner, 3f32.sqrt()));
+    }
+
+    #[test]
+    fn world_boxes_follow_the_transform() {
+        let unit = aabb([-1.0; 3], [1.0; 3]);
+        let moved = unit.world(&Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0)));
+        assert_eq!(moved, aabb([4.0, -1.0, -1.0], [6.0, 1.0, 1.0]));
+        // Rotated 45 degrees the box grows to hold the turned corners
+        let turned = unit.world(&Matrix4::from_angle_z(Deg(45.0)));
+        let reach = 2f32.sqrt();
+        assert!((turned.max.x - reach).abs() < 1e-5);
+        assert!((turned.min.y + reach).abs() < 1e-5);
+        assert!((turned.max.z - 1.0).abs() < 1e-5);
+    }
+
+    // 90 degrees and square, a point's NDC is its x and y over its distance
+    fn camera() -> Matrix4<f32> {
+        perspective(Deg(90.0), 1.0, 0.1, 100.0)
+    }
+
+    fn assert_rect(rect: Option<ScreenRect>, min: [f32; 2], max: [f32; 2]) {
+        let rect = rect.expect("on screen");
+        for (actual, expected) in rect
+            .min
+            .into_iter()
+            .chain(rect.max)
+            .zip(min.into_iter().chain(max))
+        {
+            assert!((actual - expected).abs() < 1e-5, "{rect:?}");
+        }
+    }
+
+    #[test]
+    fn boxes_project_their_nearest_corners() {
+        // The near face is 4 away, its corners are a quarter out
+        let ahead = aabb([-1.0, -1.0, -6.0], [1.0, 1.0, -4.0]);
+        assert_rect(ahead.screen_rect(&camera()), [-0.25; 2], [0.25; 2]);
+        let right = aabb([1.0, 0.0, -6.0], [2.0, 1.0, -4.0]);
+        assert_rect(right.screen_rect(&camera()), [1.0 / 6.0, 0.0], [0.5, 0.25]);
+    }
+
+    #[test]
+    fn boxes_are_clamped_to_the_screen() {
+        let partly = aabb([0.0, -1.0, -3.0], [9.0, 1.0, -2.0]);
+        assert_rect(partly.screen_rect(&camera()), [0.0, -0.5], [1.0, 0.5]);
+        let off = aabb([10.0, 0.0, -3.0], [12.0, 1.0, -2.0]);
+        assert_eq!(off.screen_rect(&camera()), None);
+    }
+
+    #[test]
+    fn boxes_behind_or_around_the_camera() {
+        let behind = aabb([-1.0, -1.0, 2.0], [1.0, 1.0, 4.0]);
+        assert_eq!(behind.screen_rect(&camera()), None);
+        let around = aabb([-1.0; 3], [1.0; 3]);
+        assert_eq!(around.screen_rect(&camera()), Some(ScreenRect::NDC));
+        // Reaching from in front to behind covers the screen as well
+        let through = aabb([3.0, 3.0, -5.0], [4.0, 4.0, 1.0]);
+        assert_eq!(through.screen_rect(&camera()), Some(ScreenRect::NDC));
+    }
+
+    #[test]
+    fn screen_rects_from_any_corners() {
+        let rect = ScreenRect::from_corners([0.5, -0.5], [-0.25, 0.75]);
+        assert_eq!(rect, ScreenRect::from_corners([-0.25, 0.75], [0.5, -0.5]));
+        assert_eq!(rect.min, [-0.25, -0.5]);
+        assert_eq!(rect.max, [0.5, 0.75]);
+    }
+
+    #[test]
+    fn screen_rects_overlap() {
+        let rect = ScreenRect::from_corners([0.0, 0.0], [0.5, 0.5]);
+        assert!(rect.overlaps(&ScreenRect::from_corners([0.25; 2], [1.0; 2])));
+        assert!(rect.overlaps(&ScreenRect::from_corners([0.1; 2], [0.2; 2])));
+        // Touching counts, a drag of zero size still selects
+        assert!(rect.overlaps(&ScreenRect::from_corners([0.5, 0.2], [0.9, 0.3])));
+        assert!(rect.overlaps(&ScreenRect::from_corners([0.3; 2], [0.3; 2])));
+        assert!(!rect.overlaps(&ScreenRect::from_corners([0.6, 0.0], [0.9, 0.5])));
+        assert!(!rect.overlaps(&ScreenRect::from_corners([0.0, -0.5], [0.5, -0.1])));
+    }
+
+    #[test]
+    fn screen_rects_clamp() {
+        let rect = ScreenRect::from_corners([-2.0, 0.5], [0.5, 3.0]);
+        assert_eq!(
+            rect.clamped(&ScreenRect::NDC),
+            Some(ScreenRect::from_corners([-1.0, 0.5], [0.5, 1.0]))
+        );
+        let outside = ScreenRect::from_corners([1.5, 0.0], [2.0, 0.5]);
+        assert_eq!(outside.clamped(&ScreenRect::NDC), None);
+    }
+}
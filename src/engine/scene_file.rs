@@ -0,0 +1,189 @@
+use std::path::PathBuf;
+
+use cgmath::{Deg, Matrix4, Point3, Vector3};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    scene::{Camera, LayerMasks, RenderObject, Scene, DEFAULT_LAYER},
+    viewport::{View, ViewId, ViewportLayout},
+};
+
+// Bumped whenever a change would make older builds misread a file. Older
+// versions keep loading, newer ones are refused
+pub const SCENE_VERSION: u32 = 1;
+
+// Layout of a .ron scene. Matrices and vectors are stored as plain arrays so
+// the file doesn't depend on how cgmath serializes them
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct SceneFile {
+    version: u32,
+    assets: AssetsSection,
+    objects: Vec<ObjectEntry>,
+    cameras: Vec<CameraEntry>,
+    #[serde(default)]
+    settings: SettingsSection,
+}
+
+// Read before the rest so a newer file is reported as such instead of as
+// whatever field it happens to trip over first
+#[derive(Debug, Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AssetsSection {
+    model: PathBuf,
+    texture: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ObjectEntry {
+    // Column major, like cgmath
+    transform: [[f32; 4]; 4],
+    // Files from before layers existed have every object visible on the
+    // default layer
+    #[serde(default = "visible_default")]
+    visible: bool,
+    #[serde(default = "layers_default")]
+    layers: u32,
+}
+
+fn visible_default() -> bool {
+    true
+}
+
+fn layers_default() -> u32 {
+    DEFAULT_LAYER
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CameraEntry {
+    eye: [f32; 3],
+    target: [f32; 3],
+    up: [f32; 3],
+    // Vertical field of view in degrees
+    fov: f32,
+    near: f32,
+    far: f32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct SettingsSection {
+    viewport_layout: Option<String>,
+    fps_limit: Option<u32>,
+    show_bounds: bool,
+    // Pass masks, LayerMasks' defaults when missing
+    main_layers: Option<u32>,
+    picking_layers: Option<u32>,
+}
+
+impl From<&Camera> for CameraEntry {
+    fn from(camera: &Camera) -> Self {
+        Self {
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            up: camera.up.into(),
+            fov: camera.fov.0,
+            near: camera.near,
+            far: camera.far,
+        }
+    }
+}
+
+impl From<CameraEntry> for Camera {
+    fn from(entry: CameraEntry) -> Self {
+        Self {
+            eye: Point3::from(entry.eye),
+            target: Point3::from(entry.target),
+            up: Vector3::from(entry.up),
+            fov: Deg(entry.fov),
+            near: entry.near,
+            far: entry.far,
+        }
+    }
+}
+
+impl SceneFile {
+    pub(super) fn from_scene(scene: &Scene) -> Self {
+        Self {
+            version: SCENE_VERSION,
+            assets: AssetsSection {
+                model: scene.model_path.clone(),
+                texture: scene.texture_path.clone(),
+            },
+            objects: scene
+                .objects
+                .iter()
+                .map(|object| ObjectEntry {
+                    transform: object.transform.into(),
+                    visible: object.visible,
+                    layers: object.layers,
+                })
+                .collect(),
+            cameras: scene.cameras.iter().map(CameraEntry::from).collect(),
+            settings: SettingsSection {
+                viewport_layout: Some(scene.viewport_layout.name().to_string()),
+                fps_limit: scene.fps_limit,
+                show_bounds: scene.show_bounds,
+                main_layers: Some(scene.layer_masks.main),
+                picking_layers: Some(scene.layer_masks.picking),
+            },
+        }
+    }
+
+    pub(super) fn to_ron(&self) -> Result<String, String> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())
+    }
+
+    pub(super) fn parse(text: &str) -> Result<Self, String> {
+        let header = ron::from_str::<Header>(text).map_err(|err| err.to_string())?;
+        if header.version > SCENE_VERSION {
+            return Err(format!(
+                "scene version {} is newer than the supported version {SCENE_VERSION}",
+                header.version
+            ));
+        }
+        ron::from_str(text).map_err(|err| err.to_string())
+    }
+
+    // Missing cameras keep the defaults of a new view
+    pub(super) fn into_scene(self) -> Scene {
+        let mut cameras = View::new(ViewId::PRIMARY).cameras;
+        for (slot, camera) in cameras.iter_mut().zip(self.cameras) {
+            *slot = camera.into();
+        }
+        let defaults = LayerMasks::default();
+        let viewport_layout = match self.settings.viewport_layout {
+            Some(name) => ViewportLayout::parse(&name).unwrap_or_else(|| {
+                warn!("Ignoring unknown viewport_layout = {name:?}");
+                ViewportLayout::default()
+            }),
+            None => ViewportLayout::default(),
+        };
+        Scene {
+            model_path: self.assets.model,
+            texture_path: self.assets.texture,
+            objects: self
+                .objects
+                .into_iter()
+                .map(|object| RenderObject {
+                    visible: object.visible,
+                    layers: object.layers,
+                    ..RenderObject::new(Matrix4::from(object.transform))
+                })
+                .collect(),
+            cameras,
+            viewport_layout,
+            fps_limit: self.settings.fps_limit,
+            show_bounds: self.settings.show_bounds,
+            layer_masks: LayerMasks {
+                main: self.settings.main_layers.unwrap_or(defaults.main),
+                picking: self.settings.picking_layers.unwrap_or(defaults.picking),
+            },
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use ab_glyph::{Font, FontVec, GlyphId, PxScale, ScaleFont};
+use cgmath::Point3;
+use log::{info, warn};
+
+use crate::{
+    engine::texture_atlas::{AtlasId, TextureAtlas},
+    utils::embedded::RgbaImage,
+};
+
+// Pixel height glyphs are rasterized at before the distance transform. The
+// field stays sharp well above and below it
+const SDF_SIZE: f32 = 32.0;
+// Pixels of distance either side of the outline the field covers, also the
+// empty border around every glyph so the field has room to fall off
+const SDF_SPREAD: u32 = 4;
+// Starting size, the atlas grows once the glyphs in use don't fit
+const SDF_ATLAS_SIZE: u32 = 256;
+// Squared distance of pixels with nothing set in range
+const FAR: f64 = 1e20;
+
+// Triangle list vertices in window space, 0..1 with y up, with their color
+// and atlas UVs
+pub(crate) type GlyphVertices = Vec<(Point3<f32>, [f32; 3], [f32; 2])>;
+
+// A character as it was first laid out, metrics in pixels at SDF_SIZE
+#[derive(Debug, Clone, Copy)]
+struct CachedGlyph {
+    id: GlyphId,
+    advance: f32,
+    // The distance field and its top left corner relative to the pen on the
+    // baseline, y down. None for glyphs without an outline, like spaces
+    image: Option<(AtlasId, [f32; 2])>,
+}
+
+// A glyph quad placed by `SdfFont::layout`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlacedGlyph {
+    // [left, top, right, bottom] in pixels from the start of the baseline,
+    // y down
+    pub rect: [f32; 4],
+    // [u0, v0, u1, v1] in the font's atlas
+    pub uv: [f32; 4],
+}
+
+// A TrueType or OpenType font drawn from signed distance fields, so text
+// stays crisp at any scale. Glyphs are rendered into the atlas the first time
+// their character is laid out
+pub struct SdfFont {
+    font: FontVec,
+    atlas: TextureAtlas,
+    glyphs: HashMap<char, CachedGlyph>,
+}
+
+impl SdfFont {
+    pub fn new(data: Vec<u8>) -> Result<Self, String> {
+        let font = FontVec::try_from_vec(data).map_err(|err| err.to_string())?;
+        Ok(SdfFont {
+            font,
+            atlas: TextureAtlas::new(SDF_ATLAS_SIZE, SDF_ATLAS_SIZE),
+            glyphs: HashMap::new(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        let font = Self::new(data).map_err(|err| format!("{}: {err}", path.display()))?;
+        info!("Loaded {} for signed distance field text", path.display());
+        Ok(font)
+    }
+
+    // From the top of one line to the top of the next at `size`, the font's
+    // ascent, descent and line gap
+    pub fn line_height(&self, size: f32) -> f32 {
+        let scaled = self.font.as_scaled(PxScale::from(size));
+        scaled.height() + scaled.line_gap()
+    }
+
+    // From the top of a line down to its baseline at `size`
+    pub fn ascent(&self, size: f32) -> f32 {
+        self.font.as_scaled(PxScale::from(size)).ascent()
+    }
+
+    // The size whose lines are `line_height` pixels apart
+    pub fn size_for_line_height(&self, line_height: f32) -> f32 {
+        line_height * SDF_SIZE / self.line_height(SDF_SIZE)
+    }
+
+    // `text` on one line at `size`, kerned pair by pair, and the width the
+    // pen moved. Characters the font doesn't have are drawn as its missing
+    // glyph
+    pub fn layout(&mut self, text: &str, size: f32) -> (Vec<PlacedGlyph>, f32) {
+        // Every glyph is cached before any UV is read, adding one can grow
+        // the atlas and move all of them
+        let glyphs = text
+            .chars()
+            .filter(|c| !c.is_control())
+            .map(|c| self.glyph(c))
+            .collect::<Vec<CachedGlyph>>();
+        let scale = size / SDF_SIZE;
+        let scaled = self.font.as_scaled(PxScale::from(SDF_SIZE));
+        let mut placed = Vec::with_capacity(glyphs.len());
+        let mut pen = 0.0;
+        let mut previous = None;
+        for glyph in glyphs {
+            if let Some(previous) = previous {
+                pen += scaled.kern(previous, glyph.id) * scale;
+            }
+            let image = glyph.image.and_then(|(id, offset)| {
+                Some((self.atlas.rect(id)?, self.atlas.uv_rect(id)?, offset))
+            });
+            if let Some((rect, uv, [x, y])) = image {
+                let (left, top) = (pen + x * scale, y * scale);
+                placed.push(PlacedGlyph {
+                    rect: [
+                        left,
+                        top,
+                        left + rect.width as f32 * scale,
+                        top + rect.height as f32 * scale,
+                    ],
+                    uv,
+                });
+            }
+            pen += glyph.advance * scale;
+            previous = Some(glyph.id);
+        }
+        (placed, pen)
+    }
+
+    pub fn atlas(&self) -> &TextureAtlas {
+        &self.atlas
+    }
+
+    pub(crate) fn atlas_mut(&mut self) -> &mut TextureAtlas {
+        &mut self.atlas
+    }
+
+    // Characters laid out so far
+    pub fn cached_glyphs(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    fn glyph(&mut self, c: char) -> CachedGlyph {
+        if let Some(glyph) = self.glyphs.get(&c) {
+            return *glyph;
+        }
+        let id = self.font.glyph_id(c);
+        let image = self.rasterize(id).and_then(|(image, offset)| {
+            let added = self.atlas.add(&image);
+            if added.is_none() {
+                warn!("The text atlas is full, {c:?} is left out");
+            }
+            Some((added?, offset))
+        });
+        let glyph = CachedGlyph {
+            id,
+            advance: self.font.as_scaled(PxScale::from(SDF_SIZE)).h_advance(id),
+            image,
+        };
+        self.glyphs.insert(c, glyph);
+        glyph
+    }
+
+    // The glyph's distance field in the alpha channel of a white image, and
+    // where its top left corner goes relative to the pen
+    fn rasterize(&self, id: GlyphId) -> Option<(RgbaImage, [f32; 2])> {
+        let outline = self.font.outline_glyph(id.with_scale(SDF_SIZE))?;
+        let bounds = outline.px_bounds();
+        let width = bounds.width() as u32 + 2 * SDF_SPREAD;
+        let height = bounds.height() as u32 + 2 * SDF_SPREAD;
+        let mut coverage = vec![0.0; (width * height) as usize];
+        outline.draw(|x, y, value| {
+            let index = (y + SDF_SPREAD) * width + x + SDF_SPREAD;
+            if let Some(pixel) = coverage.get_mut(index as usize) {
+                *pixel = value;
+            }
+        });
+        let field = signed_distance_field(
+            &coverage,
+            width as usize,
+            height as usize,
+            SDF_SPREAD as f32,
+        );
+        let image = RgbaImage {
+            width,
+            height,
+            pixels: field
+                .into_iter()
+                .flat_map(|distance| [255, 255, 255, distance])
+                .collect(),
+        };
+        let spread = SDF_SPREAD as f32;
+        Some((image, [bounds.min.x - spread, bounds.min.y - spread]))
+    }
+}
+
+// Pixels with at least half coverage are inside. Every pixel gets the
+// distance to the nearest one on the other side of the outline, positive
+// inside, mapped so the outline is at 128 and `spread` pixels out and in
+// are at 0 and 255
+pub fn signed_distance_field(
+    coverage: &[f32],
+    width: usize,
+    height: usize,
+    spread: f32,
+) -> Vec<u8> {
+    let inside = coverage
+        .iter()
+        .map(|value| *value >= 0.5)
+        .collect::<Vec<bool>>();
+    let outside = inside.iter().map(|inside| !inside).collect::<Vec<bool>>();
+    let to_inside = distance_transform(&inside, width, height);
+    let to_outside = distance_transform(&outside, width, height);
+    inside
+        .iter()
+        .zip(to_inside.iter().zip(&to_outside))
+        .map(|(inside, (to_inside, to_outside))| {
+            // Pixel centers are half a pixel off the outline between them
+            let distance = if *inside {
+                to_outside - 0.5
+            } else {
+                0.5 - to_inside
+            };
+            ((0.5 + distance / (2.0 * spread)).clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+// Euclidean distance from every pixel to the nearest set one, the exact
+// separable transform of Felzenszwalb and Huttenlocher. Grids with nothing
+// set come out far from everything
+pub fn distance_transform(set: &[bool], width: usize, height: usize) -> Vec<f32> {
+    let mut grid = set
+        .iter()
+        .map(|set| if *set { 0.0 } else { FAR })
+        .collect::<Vec<f64>>();
+    let mut line = vec![0.0; width.max(height)];
+    let mut distances = vec![0.0; width.max(height)];
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = grid[y * width + x];
+        }
+        squared_distances(&line[..height], &mut distances[..height]);
+        for y in 0..height {
+            grid[y * width + x] = distances[y];
+        }
+    }
+    for row in grid.chunks_exact_mut(width.max(1)) {
+        squared_distances(row, &mut distances[..row.len()]);
+        row.copy_from_slice(&distances[..row.len()]);
+    }
+    grid.into_iter()
+        .map(|squared| squared.sqrt() as f32)
+        .collect()
+}
+
+// The lower envelope of the parabolas rooted at every sample of `f`
+fn squared_distances(f: &[f64], distances: &mut [f64]) {
+    if f.is_empty() {
+        return;
+    }
+    let square = |q: usize| (q * q) as f64;
+    // Roots of the parabolas in the envelope, and where each takes over
+    let mut roots = vec![0; f.len()];
+    let mut bounds = vec![0.0; f.len() + 1];
+    bounds[0] = f64::NEG_INFINITY;
+    bounds[1] = f64::INFINITY;
+    let mut k = 0;
+    for q in 1..f.len() {
+        // The first bound is minus infinity, so this stops at k = 0
+        let intersection = loop {
+            let r = roots[k];
+            let intersection = (f[q] + square(q) - f[r] - square(r)) / (2.0 * (q - r) as f64);
+            if intersection > bounds[k] || k == 0 {
+                break intersection;
+            }
+            k -= 1;
+        };
+        k += 1;
+        roots[k] = q;
+        bounds[k] = intersection;
+        bounds[k + 1] = f64::INFINITY;
+    }
+    let mut k = 0;
+    for (q, distance) in distances.iter_mut().enumerate() {
+        while bounds[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - roots[k] as f64;
+        *distance = f[roots[k]] + offset * offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Boxes for .notdef, A, V, o and g, 1000 units to the em with an 800
+    // ascent, 200 descent and 100 line gap. A and V are 600 wide and kern
+    // by -100 either way round
+    const BOXES: &[u8] = include_bytes!("../../tests/fonts/boxes.ttf");
+    // Pixels per font unit at SDF_SIZE, ascent and descent add up to the em
+    const UNIT: f32 = SDF_SIZE / 1000.0;
+
+    fn font() -> SdfFont {
+        SdfFont::new(BOXES.to_vec()).unwrap()
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{actual} isn't {expected}"
+        );
+    }
+
+    // Rect without the spread of field around the outline
+    fn outline(glyph: &PlacedGlyph) -> [f32; 4] {
+        let spread = SDF_SPREAD as f32;
+        let [left, top, right, bottom] = glyph.rect;
+        [left + spread, top + spread, right - spread, bottom - spread]
+    }
+
+    #[test]
+    fn line_metrics_scale_with_size() {
+        let font = font();
+        assert_near(font.line_height(SDF_SIZE), 1100.0 * UNIT);
+        assert_near(font.ascent(SDF_SIZE), 800.0 * UNIT);
+        assert_near(font.line_height(64.0), 2.0 * font.line_height(SDF_SIZE));
+        assert_near(font.size_for_line_height(font.line_height(20.0)), 20.0);
+    }
+
+    #[test]
+    fn glyphs_sit_on_the_baseline() {
+        let mut font = font();
+        let (placed, _) = font.layout("Ag", SDF_SIZE);
+        let [a, g] = [placed[0], placed[1]].map(|glyph| outline(&glyph));
+        // Outlines are rasterized to whole pixels
+        assert!((a[1] - -700.0 * UNIT).abs() <= 1.0, "{a:?}");
+        assert!(a[3].abs() <= 1.0, "{a:?}");
+        assert!((g[3] - 200.0 * UNIT).abs() <= 1.0, "{g:?}");
+    }
+
+    #[test]
+    fn pairs_are_kerned() {
+        let mut font = font();
+        let (_, apart) = font.layout("AA", SDF_SIZE);
+        let (placed, kerned) = font.layout("AV", SDF_SIZE);
+        assert_near(apart, 1200.0 * UNIT);
+        assert_near(kerned, 1100.0 * UNIT);
+        assert_near(placed[1].rect[0] - placed[0].rect[0], 500.0 * UNIT);
+        // Twice the size, twice the kerning
+        let (_, doubled) = font.layout("AV", 2.0 * SDF_SIZE);
+        assert_near(doubled, 2.0 * kerned);
+    }
+
+    #[test]
+    fn spaces_advance_without_a_quad() {
+        let mut font = font();
+        let (placed, width) = font.layout("A o", SDF_SIZE);
+        assert_eq!(placed.len(), 2);
+        assert_near(width, 1350.0 * UNIT);
+        // The o's box starts 50 units in, rounded down to a whole pixel
+        let offset = placed[1].rect[0] - placed[0].rect[0] - 850.0 * UNIT;
+        assert!((0.0..=50.0 * UNIT).contains(&offset), "{offset}");
+    }
+
+    #[test]
+    fn missing_characters_use_the_missing_glyph() {
+        let mut font = font();
+        let (placed, width) = font.layout("z\n", SDF_SIZE);
+        assert_eq!(placed.len(), 1);
+        assert_near(width, 500.0 * UNIT);
+    }
+
+    #[test]
+    fn glyphs_are_cached_once() {
+        let mut font = font();
+        let (first, _) = font.layout("AVA", SDF_SIZE);
+        assert_eq!(font.cached_glyphs(), 2);
+        assert_eq!(font.atlas().len(), 2);
+        let (again, _) = font.layout("AVA", SDF_SIZE);
+        assert_eq!(first, again);
+        assert_eq!(first[0].uv, first[2].uv);
+    }
+
+    // The alpha of a glyph's field is above the middle inside the box and
+    // falls off to nearly 0 at the edge of the spread
+    #[test]
+    fn glyph_fields_are_in_the_atlas() {
+        let mut font = font();
+        font.layout("A", SDF_SIZE);
+        let rect = font.atlas().rect(AtlasId(0)).unwrap();
+        let pixels = font.atlas().region_pixels(rect);
+        let alpha = |x: u32, y: u32| pixels[((y * rect.width + x) * 4 + 3) as usize];
+        let (center_x, center_y) = (rect.width / 2, rect.height / 2);
+        assert_eq!(alpha(center_x, center_y), 255);
+        assert!(alpha(0, center_y) < 32);
+        assert!(alpha(center_x, 0) < 32);
+        let row = (0..rect.width / 2)
+            .map(|x| alpha(x, center_y))
+            .collect::<Vec<u8>>();
+        assert!(row.windows(2).all(|pair| pair[0] <= pair[1]), "{row:?}");
+        // The outline, a spread in from the edge, is where the field crosses
+        // the middle
+        let spread = SDF_SPREAD;
+        assert!(alpha(spread - 1, center_y) < 128);
+        assert!(alpha(spread, center_y) > 128);
+    }
+
+    #[test]
+    fn distances_to_a_point() {
+        let mut set = vec![false; 25];
+        set[2 * 5 + 2] = true;
+        let distances = distance_transform(&set, 5, 5);
+        for y in 0..5 {
+            for x in 0..5 {
+                let expected = ((x as f32 - 2.0).powi(2) + (y as f32 - 2.0).powi(2)).sqrt();
+                assert_near(distances[y * 5 + x], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn distances_without_anything_set() {
+        let distances = distance_transform(&[false; 12], 4, 3);
+        assert!(distances.iter().all(|distance| *distance > 1e9));
+        assert!(distance_transform(&[], 0, 0).is_empty());
+    }
+
+    // The left half is covered, the field crosses the middle between the
+    // columns either side of the edge and saturates a spread away from it
+    #[test]
+    fn field_across_an_edge() {
+        let (width, spread) = (16, 4.0);
+        let coverage = (0..width * 2)
+            .map(|index| if index % width < 8 { 1.0 } else { 0.0 })
+            .collect::<Vec<f32>>();
+        let field = signed_distance_field(&coverage, width, 2, spread);
+        let row = &field[..width];
+        assert_eq!(row[0], 255);
+        assert_eq!(row[width - 1], 0);
+        assert!(row.windows(2).all(|pair| pair[0] >= pair[1]), "{row:?}");
+        assert_eq!(u32::from(row[7]) + u32::from(row[8]), 255);
+        assert!(row[7] > 128 && row[8] < 128);
+        assert_eq!(&field[width..], row);
+    }
+}
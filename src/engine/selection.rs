@@ -0,0 +1,131 @@
+use crate::engine::scene::ObjectId;
+
+// Selected objects in the order they were selected, without repeats
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    objects: Vec<ObjectId>,
+}
+
+impl Selection {
+    pub fn objects(&self) -> &[ObjectId] {
+        &self.objects
+    }
+
+    pub fn first(&self) -> Option<ObjectId> {
+        self.objects.first().copied()
+    }
+
+    pub fn contains(&self, object: ObjectId) -> bool {
+        self.objects.contains(&object)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // Objects that are already selected keep their place, ids at or past
+    // `object_count` are ignored
+    pub fn extend(&mut self, objects: impl IntoIterator<Item = ObjectId>, object_count: usize) {
+        for object in objects {
+            if object.0 < object_count && !self.objects.contains(&object) {
+                self.objects.push(object);
+            }
+        }
+    }
+
+    // Empties the selection, returning what was in it
+    pub fn take(&mut self) -> Vec<ObjectId> {
+        std::mem::take(&mut self.objects)
+    }
+
+    // Follows the objects to their ids after `removed_ids`, the removed ones
+    // drop out
+    pub fn remap(&mut self, moved_to: &[Option<ObjectId>]) {
+        self.objects = self
+            .objects
+            .iter()
+            .filter_map(|ObjectId(index)| moved_to.get(*index).copied().flatten())
+            .collect();
+    }
+}
+
+// Where each of `object_count` objects ends up once `removed` are taken out
+// and the rest move down to keep the ids dense. None for the removed ones,
+// ids past the end are ignored
+pub fn removed_ids(object_count: usize, removed: &[ObjectId]) -> Vec<Option<ObjectId>> {
+    let mut is_removed = vec![false; object_count];
+    for ObjectId(index) in removed {
+        if let Some(is_removed) = is_removed.get_mut(*index) {
+            *is_removed = true;
+        }
+    }
+    let mut kept = 0;
+    is_removed
+        .into_iter()
+        .map(|removed| {
+            (!removed).then(|| {
+                kept += 1;
+                ObjectId(kept - 1)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(indices: &[usize]) -> Vec<ObjectId> {
+        indices.iter().copied().map(ObjectId).collect()
+    }
+
+    #[test]
+    fn selection_keeps_the_first_place() {
+        let mut selection = Selection::default();
+        selection.extend(ids(&[3, 1, 3]), 5);
+        selection.extend(ids(&[1, 0]), 5);
+        assert_eq!(selection.objects(), ids(&[3, 1, 0]));
+        assert_eq!(selection.first(), Some(ObjectId(3)));
+        assert!(selection.contains(ObjectId(0)) && !selection.contains(ObjectId(2)));
+    }
+
+    #[test]
+    fn unknown_objects_are_not_selected() {
+        let mut selection = Selection::default();
+        selection.extend(ids(&[2, 5, 9]), 5);
+        assert_eq!(selection.objects(), ids(&[2]));
+        selection.extend(ids(&[5]), 0);
+        assert_eq!(selection.objects(), ids(&[2]));
+    }
+
+    #[test]
+    fn taking_empties_the_selection() {
+        let mut selection = Selection::default();
+        selection.extend(ids(&[4, 2]), 5);
+        assert_eq!(selection.take(), ids(&[4, 2]));
+        assert!(selection.is_empty());
+        assert_eq!(selection.first(), None);
+    }
+
+    #[test]
+    fn removal_keeps_ids_dense() {
+        let moved_to = removed_ids(5, &ids(&[1, 3, 3, 7]));
+        assert_eq!(
+            moved_to,
+            [Some(0), None, Some(1), None, Some(2)].map(|id| id.map(ObjectId))
+        );
+        assert_eq!(removed_ids(2, &[]), [Some(ObjectId(0)), Some(ObjectId(1))]);
+    }
+
+    // Removed objects drop out of the selection, the rest keep their order
+    // under their new ids
+    #[test]
+    fn selection_follows_removals() {
+        let mut selection = Selection::default();
+        selection.extend(ids(&[4, 1, 2]), 5);
+        selection.remap(&removed_ids(5, &ids(&[1, 3])));
+        assert_eq!(selection.objects(), ids(&[2, 1]));
+        selection.remap(&removed_ids(3, &ids(&[0, 1, 2])));
+        assert!(selection.is_empty());
+    }
+}
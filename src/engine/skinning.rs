@@ -0,0 +1,130 @@
+use cgmath::{Matrix4, SquareMatrix};
+
+use crate::engine::animation::Transform;
+
+// Joints per skeleton, the skinned vertex shader indexes them with u16s
+pub const MAX_SKIN_JOINTS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Joint {
+    // Has to come before the joint in the skeleton
+    pub parent: Option<usize>,
+    // Relative to the parent, what the joint is posed with until animated
+    pub rest: Transform,
+    // From model space into the joint's space at bind time. None is the
+    // identity, like a glTF skin without inverse bind matrices
+    pub inverse_bind: Option<Matrix4<f32>>,
+}
+
+impl Joint {
+    pub fn new(parent: Option<usize>, rest: Transform) -> Self {
+        Self {
+            parent,
+            rest,
+            inverse_bind: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    // Fails for too many joints or a parent that isn't listed before its
+    // child
+    pub fn new(joints: Vec<Joint>) -> Result<Self, String> {
+        if joints.len() > MAX_SKIN_JOINTS {
+            return Err(format!(
+                "{} joints, skeletons have at most {MAX_SKIN_JOINTS}",
+                joints.len()
+            ));
+        }
+        if let Some(index) = joints
+            .iter()
+            .enumerate()
+            .position(|(index, joint)| joint.parent.is_some_and(|parent| parent >= index))
+        {
+            return Err(format!("Joint {index} comes before its parent"));
+        }
+        Ok(Self { joints })
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    // Binds the mesh as modeled to the rest pose, filling every missing
+    // inverse bind matrix. Handy for skeletons built in code
+    pub fn bind_rest_pose(&mut self) {
+        let rest = self
+            .joints
+            .iter()
+            .map(|joint| joint.rest)
+            .collect::<Vec<Transform>>();
+        let globals = self.global_transforms(&rest);
+        for (joint, global) in self.joints.iter_mut().zip(globals) {
+            if joint.inverse_bind.is_none() {
+                joint.inverse_bind = Some(global.invert().unwrap_or(Matrix4::identity()));
+            }
+        }
+    }
+
+    // Model space transform of every joint, parents first so each joint
+    // builds on its parent's already finished transform
+    pub fn global_transforms(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let mut globals = Vec::<Matrix4<f32>>::with_capacity(self.joints.len());
+        for (index, joint) in self.joints.iter().enumerate() {
+            let local = pose.get(index).unwrap_or(&joint.rest).matrix();
+            let global = match joint.parent {
+                Some(parent) => globals[parent] * local,
+                None => local,
+            };
+            globals.push(global);
+        }
+        globals
+    }
+
+    // What the vertex shader blends, moving bind pose vertices to `pose`.
+    // Joints missing from `pose` stay at rest
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        self.global_transforms(pose)
+            .into_iter()
+            .zip(&self.joints)
+            .map(|(global, joint)| global * joint.inverse_bind.unwrap_or(Matrix4::identity()))
+            .collect()
+    }
+}
+
+// A skeleton attached to an object, with the pose it is drawn in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skin {
+    skeleton: Skeleton,
+    pose: Vec<Transform>,
+}
+
+impl Skin {
+    pub fn new(skeleton: Skeleton) -> Self {
+        let pose = skeleton.joints.iter().map(|joint| joint.rest).collect();
+        Self { skeleton, pose }
+    }
+
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
+    pub fn pose(&self) -> &[Transform] {
+        &self.pose
+    }
+
+    pub fn set_joint_pose(&mut self, joint: usize, transform: Transform) {
+        if let Some(pose) = self.pose.get_mut(joint) {
+            *pose = transform;
+        }
+    }
+
+    pub fn joint_matrices(&self) -> Vec<Matrix4<f32>> {
+        self.skeleton.joint_matrices(&self.pose)
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{Point3, Vector3};
+
+use crate::engine::scene::{Aabb, ObjectId};
+
+// Objects covering more cells than this are kept in a list of their own
+// and tested by every query, so one huge floor doesn't fill the grid
+const MAX_OBJECT_CELLS: i64 = 512;
+
+type Cell = [i32; 3];
+
+// Where an object was inserted
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    bounds: Aabb,
+    // Inclusive, None for oversized objects
+    cells: Option<[Cell; 2]>,
+}
+
+// World space boxes hashed into uniform cubic cells. Queries only test the
+// objects sharing a cell with them
+#[derive(Debug, Clone)]
+pub struct UniformGrid {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<ObjectId>>,
+    oversized: Vec<ObjectId>,
+    entries: HashMap<ObjectId, Entry>,
+}
+
+impl UniformGrid {
+    pub fn new(cell_size: f32) -> Self {
+        UniformGrid {
+            cell_size: cell_size.max(f32::EPSILON),
+            cells: HashMap::new(),
+            oversized: Vec::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.entries.keys().copied()
+    }
+
+    pub fn bounds(&self, object: ObjectId) -> Option<Aabb> {
+        self.entries.get(&object).map(|entry| entry.bounds)
+    }
+
+    // Moves the object when it is already in the grid
+    pub fn insert(&mut self, object: ObjectId, bounds: Aabb) {
+        self.remove(object);
+        let [min, max] = self.cell_range(&bounds);
+        let count = (0..3)
+            .map(|axis| (max[axis] as i64 - min[axis] as i64 + 1).max(0))
+            .product::<i64>();
+        let cells = (count <= MAX_OBJECT_CELLS).then_some([min, max]);
+        match cells {
+            Some(range) => {
+                for cell in cells_in(range) {
+                    self.cells.entry(cell).or_default().push(object);
+                }
+            }
+            None => self.oversized.push(object),
+        }
+        self.entries.insert(object, Entry { bounds, cells });
+    }
+
+    // False when the object wasn't in the grid
+    pub fn remove(&mut self, object: ObjectId) -> bool {
+        let Some(entry) = self.entries.remove(&object) else {
+            return false;
+        };
+        match entry.cells {
+            Some(range) => {
+                for cell in cells_in(range) {
+                    if let Some(objects) = self.cells.get_mut(&cell) {
+                        objects.retain(|other| *other != object);
+                        if objects.is_empty() {
+                            self.cells.remove(&cell);
+                        }
+                    }
+                }
+            }
+            None => self.oversized.retain(|other| *other != object),
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.oversized.clear();
+        self.entries.clear();
+    }
+
+    // Objects whose box overlaps `bounds`, in id order
+    pub fn query_aabb(&self, bounds: &Aabb) -> Vec<ObjectId> {
+        self.query(bounds, |entry| entry.overlaps(bounds))
+    }
+
+    // Objects whose box reaches into the sphere, in id order
+    pub fn query_sphere(&self, center: Point3<f32>, radius: f32) -> Vec<ObjectId> {
+        let reach = Vector3::new(radius, radius, radius);
+        let bounds = Aabb {
+            min: center - reach,
+            max: center + reach,
+        };
+        self.query(&bounds, |entry| entry.intersects_sphere(center, radius))
+    }
+
+    fn query(&self, bounds: &Aabb, hit: impl Fn(&Aabb) -> bool) -> Vec<ObjectId> {
+        let range = self.cell_range(bounds);
+        let mut candidates = HashSet::new();
+        // A query box larger than the occupied cells walks the cells instead
+        if cells_in(range).nth(self.cells.len()).is_none() {
+            for cell in cells_in(range) {
+                candidates.extend(self.cells.get(&cell).into_iter().flatten().copied());
+            }
+        } else {
+            candidates.extend(
+                self.cells
+                    .iter()
+                    .filter(|(cell, _)| {
+                        (0..3).all(|axis| (range[0][axis]..=range[1][axis]).contains(&cell[axis]))
+                    })
+                    .flat_map(|(_, objects)| objects.iter().copied()),
+            );
+        }
+        candidates.extend(self.oversized.iter().copied());
+        let mut objects = candidates
+            .into_iter()
+            .filter(|object| hit(&self.entries[object].bounds))
+            .collect::<Vec<ObjectId>>();
+        objects.sort_by_key(|ObjectId(index)| *index);
+        objects
+    }
+
+    fn cell_range(&self, bounds: &Aabb) -> [Cell; 2] {
+        let cell = |point: Point3<f32>| -> Cell {
+            // Saturates far out, NaN lands in cell 0
+            [0, 1, 2].map(|axis| (point[axis] / self.cell_size).floor() as i32)
+        };
+        [cell(bounds.min), cell(bounds.max)]
+    }
+}
+
+fn cells_in([min, max]: [Cell; 2]) -> impl Iterator<Item = Cell> {
+    (min[2]..=max[2]).flat_map(move |z| {
+        (min[1]..=max[1]).flat_map(move |y| (min[0]..=max[0]).map(move |x| [x, y, z]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::point3;
+
+    use super::*;
+
+    fn cube(center: [f32; 3], half: f32) -> Aabb {
+        let center = Point3::from(center);
+        let reach = Vector3::new(half, half, half);
+        Aabb {
+            min: center - reach,
+            max: center + reach,
+        }
+    }
+
+    // Every cell holds each of its objects once, and only those
+    fn assert_consistent(grid: &UniformGrid) {
+        for (cell, objects) in &grid.cells {
+            assert!(!objects.is_empty(), "empty cell {cell:?} kept");
+            for object in objects {
+                let range = grid.entries[object].cells.unwrap();
+                assert!((0..3).all(|axis| (range[0][axis]..=range[1][axis]).contains(&cell[axis])));
+                assert_eq!(objects.iter().filter(|other| *other == object).count(), 1);
+            }
+        }
+        for object in &grid.oversized {
+            assert!(grid.entries[object].cells.is_none());
+        }
+    }
+
+    #[test]
+    fn inserted_objects_are_found() {
+        let mut grid = UniformGrid::new(1.0);
+        grid.insert(ObjectId(0), cube([0.5; 3], 0.25));
+        grid.insert(ObjectId(1), cube([3.5, 0.5, 0.5], 0.25));
+        // Spans the cell boundaries at 1 and 2
+        grid.insert(ObjectId(2), cube([1.5, 1.5, 1.5], 0.75));
+        assert_consistent(&grid);
+        assert_eq!(grid.len(), 3);
+        assert_eq!(
+            grid.query_aabb(&cube([0.5; 3], 0.5)),
+            [ObjectId(0), ObjectId(2)]
+        );
+        assert_eq!(grid.query_aabb(&cube([3.5, 0.5, 0.5], 0.1)), [ObjectId(1)]);
+        assert!(grid.query_aabb(&cube([10.0; 3], 1.0)).is_empty());
+        // Same cell, no overlap
+        assert!(grid.query_aabb(&cube([0.9, 0.1, 0.9], 0.05)).is_empty());
+    }
+
+    #[test]
+    fn moving_leaves_the_old_cells() {
+        let mut grid = UniformGrid::new(2.0);
+        grid.insert(ObjectId(4), cube([1.0; 3], 0.5));
+        grid.insert(ObjectId(4), cube([9.0; 3], 0.5));
+        assert_consistent(&grid);
+        assert_eq!(grid.len(), 1);
+        assert_eq!(grid.cells.len(), 1);
+        assert!(grid.query_aabb(&cube([1.0; 3], 0.5)).is_empty());
+        assert_eq!(grid.query_aabb(&cube([9.0; 3], 0.5)), [ObjectId(4)]);
+        assert_eq!(grid.bounds(ObjectId(4)), Some(cube([9.0; 3], 0.5)));
+    }
+
+    #[test]
+    fn removing_empties_the_cells() {
+        let mut grid = UniformGrid::new(1.0);
+        grid.insert(ObjectId(0), cube([0.5; 3], 1.0));
+        grid.insert(ObjectId(1), cube([0.5; 3], 0.25));
+        assert!(grid.remove(ObjectId(0)));
+        assert!(!grid.remove(ObjectId(0)));
+        assert_consistent(&grid);
+        assert_eq!(grid.cells.len(), 1);
+        assert!(grid.remove(ObjectId(1)));
+        assert!(grid.is_empty());
+        assert!(grid.cells.is_empty());
+    }
+
+    // Objects over MAX_OBJECT_CELLS cells are tested by every query instead
+    #[test]
+    fn oversized_objects_stay_out_of_the_cells() {
+        let mut grid = UniformGrid::new(1.0);
+        grid.insert(ObjectId(0), cube([0.0; 3], 100.0));
+        grid.insert(ObjectId(1), cube([50.0; 3], 0.5));
+        assert_consistent(&grid);
+        assert_eq!(grid.oversized, [ObjectId(0)]);
+        assert_eq!(
+            grid.query_aabb(&cube([50.0; 3], 0.1)),
+            [ObjectId(0), ObjectId(1)]
+        );
+        assert!(grid.query_aabb(&cube([500.0; 3], 0.1)).is_empty());
+        grid.insert(ObjectId(0), cube([0.0; 3], 0.5));
+        assert!(grid.oversized.is_empty());
+        assert_consistent(&grid);
+    }
+
+    #[test]
+    fn spheres_query_by_distance() {
+        let mut grid = UniformGrid::new(1.0);
+        grid.insert(ObjectId(0), cube([0.0; 3], 0.5));
+        grid.insert(ObjectId(1), cube([3.0, 0.0, 0.0], 0.5));
+        let center = point3(1.5, 0.0, 0.0);
+        assert_eq!(grid.query_sphere(center, 1.0), [ObjectId(0), ObjectId(1)]);
+        assert!(grid.query_sphere(center, 0.9).is_empty());
+        // The sphere's bounding box reaches both corners, the sphere doesn't
+        assert!(grid.query_sphere(point3(1.5, 1.5, 0.0), 1.2).is_empty());
+    }
+
+    // Queries bigger than the occupied cells go through the cells instead,
+    // with the same results
+    #[test]
+    fn large_queries_match_small_ones() {
+        let mut grid = UniformGrid::new(1.0);
+        for index in 0..20 {
+            let x = index as f32 * 1.7 - 15.0;
+            grid.insert(ObjectId(index), cube([x, (index % 3) as f32, 0.0], 0.4));
+        }
+        let everything = grid.query_aabb(&cube([0.0; 3], 1000.0));
+        assert_eq!(everything, (0..20).map(ObjectId).collect::<Vec<_>>());
+        let near = grid.query_aabb(&cube([0.0; 3], 2.0));
+        let walked = near
+            .iter()
+            .all(|object| grid.bounds(*object).unwrap().overlaps(&cube([0.0; 3], 2.0)));
+        assert!(walked && !near.is_empty());
+    }
+}
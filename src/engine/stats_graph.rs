@@ -0,0 +1,93 @@
+use std::{collections::VecDeque, time::Duration};
+
+use cgmath::point3;
+
+use crate::engine::{debug_draw::DebugLines, debug_palette::DebugPalette};
+
+// Frames of history the graph shows
+pub const FRAME_GRAPH_SAMPLES: usize = 240;
+// Corner and size of the graph, as fractions of the window with y up
+const GRAPH_ORIGIN: [f32; 2] = [0.02, 0.02];
+const GRAPH_SIZE: [f32; 2] = [0.4, 0.2];
+// Milliseconds at the top of the graph, longer frames are clamped to it
+const GRAPH_MAX_MS: f32 = 50.0;
+// 60 and 30 fps
+const GUIDE_MS: [f32; 2] = [1000.0 / 60.0, 1000.0 / 30.0];
+
+// The last `FRAME_GRAPH_SAMPLES` frame times, CPU side from the frame timer
+// and GPU side from the timestamp queries when they are available
+#[derive(Debug, Default)]
+pub struct FrameTimeGraph {
+    // Milliseconds, the newest last
+    samples: VecDeque<(f32, Option<f32>)>,
+}
+
+impl FrameTimeGraph {
+    pub fn push(&mut self, frame_time: Duration, gpu_ms: Option<f32>) {
+        if self.samples.len() == FRAME_GRAPH_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples
+            .push_back((frame_time.as_secs_f32() * 1000.0, gpu_ms));
+    }
+
+    // Line list endpoints in window space, 0..1 with y up. The newest frame
+    // is on the right, segments over the guides in the palette's caution and
+    // warning colors
+    pub(crate) fn lines(&self, palette: &DebugPalette) -> DebugLines {
+        let [left, bottom] = GRAPH_ORIGIN;
+        let [width, height] = GRAPH_SIZE;
+        let (right, top) = (left + width, bottom + height);
+        let y = |ms: f32| bottom + ms.clamp(0.0, GRAPH_MAX_MS) / GRAPH_MAX_MS * height;
+        let mut lines = DebugLines::new();
+        let mut line = |from: [f32; 2], to: [f32; 2], color: [f32; 3]| {
+            lines.push((point3(from[0], from[1], 0.0), color));
+            lines.push((point3(to[0], to[1], 0.0), color));
+        };
+        for (from, to) in [
+            ([left, bottom], [right, bottom]),
+            ([right, bottom], [right, top]),
+            ([right, top], [left, top]),
+            ([left, top], [left, bottom]),
+        ] {
+            line(from, to, palette.graph_border);
+        }
+        for guide in GUIDE_MS {
+            line([left, y(guide)], [right, y(guide)], palette.graph_guide);
+        }
+
+        let step = width / (FRAME_GRAPH_SAMPLES - 1) as f32;
+        let first = FRAME_GRAPH_SAMPLES - self.samples.len();
+        let x = |index: usize| left + (first + index) as f32 * step;
+        let color = |base: [f32; 3], ms: f32| {
+            if ms > GUIDE_MS[1] {
+                palette.warning
+            } else if ms > GUIDE_MS[0] {
+                palette.caution
+            } else {
+                base
+            }
+        };
+        for (index, (previous, next)) in self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .enumerate()
+        {
+            let (previous_frame, next_frame) = (previous.0, next.0);
+            line(
+                [x(index), y(previous_frame)],
+                [x(index + 1), y(next_frame)],
+                color(palette.graph_cpu, previous_frame.max(next_frame)),
+            );
+            if let (Some(previous_gpu), Some(next_gpu)) = (previous.1, next.1) {
+                line(
+                    [x(index), y(previous_gpu)],
+                    [x(index + 1), y(next_gpu)],
+                    color(palette.graph_gpu, previous_gpu.max(next_gpu)),
+                );
+            }
+        }
+        lines
+    }
+}
@@ -0,0 +1,571 @@
+use ash::vk::Extent2D;
+use cgmath::point3;
+
+use crate::engine::{
+    debug_draw::DebugLines,
+    sdf_font::{GlyphVertices, SdfFont},
+};
+
+// Pixels of one character cell at scale 1. Every character takes one cell,
+// box drawing characters reach its edges so they join up with their
+// neighbours
+pub const CELL_SIZE: [f32; 2] = [6.0, 12.0];
+// Glyph strokes are on a 0..4 by 0..9 grid, moved in by this from the cell's
+// bottom left corner
+const GLYPH_OFFSET: [f32; 2] = [1.0, 1.0];
+const BASELINE: f32 = 2.0;
+const CAP_HEIGHT: f32 = 8.0;
+// Lowercase letters are the capitals shrunk down to this height
+const X_HEIGHT: f32 = 6.0;
+// Accents sit this far above the letter and are this tall
+const ACCENT_GAP: f32 = 0.6;
+const ACCENT_HEIGHT: f32 = 1.2;
+// Distance between the two strokes of double box drawing lines
+const DOUBLE_LINE_GAP: f32 = 1.0;
+// Drawn for everything without a glyph
+const MISSING_GLYPH: &str = "0848420208";
+
+// Lines of a text box from top to bottom, each in its own color
+pub type TextRows = Vec<(String, [f32; 3])>;
+
+// Horizontal placement of every line within the box's columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    // Cells a line of `width` is moved right by in a box of `columns`
+    fn offset(self, columns: usize, width: usize) -> f32 {
+        self.shift(columns.saturating_sub(width) as f32)
+    }
+
+    // How far a line is moved right when `spare` of the box's width is left
+    // over, in whatever unit `spare` is
+    fn shift(self, spare: f32) -> f32 {
+        match self {
+            Align::Left => 0.0,
+            Align::Center => (spare / 2.0).floor(),
+            Align::Right => spare,
+        }
+    }
+}
+
+// Where a block of text goes on screen. The lines run down from `origin`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextBox {
+    // Top left corner, as fractions of the window with y up
+    pub origin: [f32; 2],
+    // Width of the box in characters, what the text is wrapped and aligned to
+    pub columns: usize,
+    pub align: Align,
+    // Multiplies `CELL_SIZE`
+    pub scale: f32,
+}
+
+impl TextBox {
+    // Laid out in pixels of a window of `window`, the wrapped `rows` from top
+    // to bottom as line list endpoints in window space, 0..1 with y up
+    pub(crate) fn lines(&self, rows: &[(String, [f32; 3])], window: Extent2D) -> DebugLines {
+        let mut lines = DebugLines::new();
+        if window.width == 0 || window.height == 0 {
+            return lines;
+        }
+        // Window fractions per unit of the glyph grid
+        let unit = [
+            self.scale / window.width as f32,
+            self.scale / window.height as f32,
+        ];
+        for (row, (text, color)) in rows.iter().enumerate() {
+            let shift = self.align.offset(self.columns, text.chars().count());
+            let bottom = self.origin[1] - (row + 1) as f32 * CELL_SIZE[1] * unit[1];
+            for (column, c) in text.chars().enumerate() {
+                let left = self.origin[0] + (shift + column as f32) * CELL_SIZE[0] * unit[0];
+                let point =
+                    |[x, y]: [f32; 2]| point3(left + x * unit[0], bottom + y * unit[1], 0.0);
+                glyph(c, &mut |from, to| {
+                    lines.push((point(from), *color));
+                    lines.push((point(to), *color));
+                });
+            }
+        }
+        lines
+    }
+
+    // The same rows drawn with `font` instead of the stroke glyphs, as quads
+    // in window space. Rows are a cell height apart like the stroke text, the
+    // font is sized so its line height matches, and they are aligned within
+    // the box's width in pixels
+    pub(crate) fn glyphs(
+        &self,
+        font: &mut SdfFont,
+        rows: &[(String, [f32; 3])],
+        window: Extent2D,
+    ) -> GlyphVertices {
+        let mut vertices = GlyphVertices::new();
+        if window.width == 0 || window.height == 0 {
+            return vertices;
+        }
+        let [window_width, window_height] = [window.width as f32, window.height as f32];
+        let line_height = CELL_SIZE[1] * self.scale;
+        let size = font.size_for_line_height(line_height);
+        let box_width = self.columns as f32 * CELL_SIZE[0] * self.scale;
+        // Whole pixels from the window's top left corner, so every baseline
+        // lands on a pixel row
+        let left = (self.origin[0] * window_width).round();
+        let top = ((1.0 - self.origin[1]) * window_height).round();
+        let ascent = font.ascent(size).round();
+        for (row, (text, color)) in rows.iter().enumerate() {
+            let (placed, width) = font.layout(text, size);
+            let pen = left + self.align.shift((box_width - width).max(0.0)).round();
+            let baseline = top + row as f32 * line_height + ascent;
+            for glyph in placed {
+                let [x0, y0, x1, y1] = glyph.rect;
+                let [u0, v0, u1, v1] = glyph.uv;
+                let corner = |x: f32, y: f32, uv: [f32; 2]| {
+                    (
+                        point3(
+                            (pen + x) / window_width,
+                            1.0 - (baseline + y) / window_height,
+                            0.0,
+                        ),
+                        *color,
+                        uv,
+                    )
+                };
+                let (top_left, top_right, bottom_left, bottom_right) = (
+                    corner(x0, y0, [u0, v0]),
+                    corner(x1, y0, [u1, v0]),
+                    corner(x0, y1, [u0, v1]),
+                    corner(x1, y1, [u1, v1]),
+                );
+                vertices.extend([
+                    bottom_left,
+                    bottom_right,
+                    top_right,
+                    top_right,
+                    top_left,
+                    bottom_left,
+                ]);
+            }
+        }
+        vertices
+    }
+}
+
+// How many characters fit across `width` pixels at `scale`
+pub fn columns_in(width: f32, scale: f32) -> usize {
+    (width / (CELL_SIZE[0] * scale)).max(0.0) as usize
+}
+
+// Breaks `text` into lines of at most `columns` characters. Lines end at its
+// newlines and otherwise between words, words longer than a whole line are
+// split wherever the line is full
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let columns = columns.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut width = 0;
+        for word in paragraph.trim_end_matches('\r').split(' ') {
+            let word_width = word.chars().count();
+            if width > 0 && width + 1 + word_width <= columns {
+                line.push(' ');
+                line.push_str(word);
+                width += 1 + word_width;
+                continue;
+            }
+            if width > 0 {
+                lines.push(std::mem::take(&mut line));
+            }
+            let mut chars = word.chars().peekable();
+            width = 0;
+            while chars.peek().is_some() {
+                if width == columns {
+                    lines.push(std::mem::take(&mut line));
+                    width = 0;
+                }
+                line.extend(chars.next());
+                width += 1;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Columns of the widest line and the number of lines
+pub fn measure<S: AsRef<str>>(lines: &[S]) -> [usize; 2] {
+    let columns = lines
+        .iter()
+        .map(|line| line.as_ref().chars().count())
+        .max()
+        .unwrap_or(0);
+    [columns, lines.len()]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Accent {
+    Grave,
+    Acute,
+    Circumflex,
+    Tilde,
+    Diaeresis,
+    Ring,
+    Cedilla,
+}
+
+impl Accent {
+    // Polylines in a 0..4 by 0..1 band above the letter, below it for the
+    // cedilla
+    fn strokes(self) -> &'static [&'static [[f32; 2]]] {
+        match self {
+            Accent::Grave => &[&[[1.0, 1.0], [2.5, 0.0]]],
+            Accent::Acute => &[&[[1.5, 0.0], [3.0, 1.0]]],
+            Accent::Circumflex => &[&[[0.5, 0.0], [2.0, 1.0], [3.5, 0.0]]],
+            Accent::Tilde => &[&[[0.5, 0.2], [1.5, 1.0], [2.5, 0.2], [3.5, 1.0]]],
+            Accent::Diaeresis => &[&[[1.0, 0.3], [1.0, 1.0]], &[[3.0, 0.3], [3.0, 1.0]]],
+            Accent::Ring => &[&[[2.0, 0.0], [2.6, 0.5], [2.0, 1.0], [1.4, 0.5], [2.0, 0.0]]],
+            Accent::Cedilla => &[&[[2.0, 1.0], [2.5, 0.5], [1.5, 0.0]]],
+        }
+    }
+}
+
+// The Latin-1 letters from U+00C0 as their base letter and accent, the
+// lowercase ones from U+00E0 are in the same order. Multiplication and
+// division signs, sharp s and y with diaeresis are left to `strokes`
+const LATIN1_LETTERS: [(char, Option<Accent>); 32] = [
+    ('A', Some(Accent::Grave)),
+    ('A', Some(Accent::Acute)),
+    ('A', Some(Accent::Circumflex)),
+    ('A', Some(Accent::Tilde)),
+    ('A', Some(Accent::Diaeresis)),
+    ('A', Some(Accent::Ring)),
+    ('Æ', None),
+    ('C', Some(Accent::Cedilla)),
+    ('E', Some(Accent::Grave)),
+    ('E', Some(Accent::Acute)),
+    ('E', Some(Accent::Circumflex)),
+    ('E', Some(Accent::Diaeresis)),
+    ('I', Some(Accent::Grave)),
+    ('I', Some(Accent::Acute)),
+    ('I', Some(Accent::Circumflex)),
+    ('I', Some(Accent::Diaeresis)),
+    ('Ð', None),
+    ('N', Some(Accent::Tilde)),
+    ('O', Some(Accent::Grave)),
+    ('O', Some(Accent::Acute)),
+    ('O', Some(Accent::Circumflex)),
+    ('O', Some(Accent::Tilde)),
+    ('O', Some(Accent::Diaeresis)),
+    ('×', None),
+    ('Ø', None),
+    ('U', Some(Accent::Grave)),
+    ('U', Some(Accent::Acute)),
+    ('U', Some(Accent::Circumflex)),
+    ('U', Some(Accent::Diaeresis)),
+    ('Y', Some(Accent::Acute)),
+    ('Þ', None),
+    ('ß', None),
+];
+
+// The glyph `c` is drawn with, whether it is shrunk to a lowercase letter
+// and the accent that goes with it
+fn decompose(c: char) -> (char, bool, Option<Accent>) {
+    match c {
+        'a'..='z' => (c.to_ascii_uppercase(), true, None),
+        '×' | '÷' | 'ß' => (c, false, None),
+        'ÿ' => ('Y', true, Some(Accent::Diaeresis)),
+        '\u{C0}'..='\u{FE}' => {
+            let (base, accent) = LATIN1_LETTERS[(c as usize - 0xC0) % 32];
+            (base, c >= '\u{E0}', accent)
+        }
+        _ => (c, false, None),
+    }
+}
+
+// Polylines of the printable ASCII and Latin-1 characters, strokes separated
+// by spaces and every point two digits, x then y on the glyph grid
+fn strokes(c: char) -> Option<&'static str> {
+    Some(match c {
+        '!' => "2824 2322",
+        '"' => "1817 3837",
+        '#' => "1713 3733 0646 0444",
+        '$' => "470705454303 2822",
+        '%' => "0248 0708181707 3242433332",
+        '&' => "4215172837360403122244",
+        '\'' => "2827",
+        '(' => "38272332",
+        ')' => "18272312",
+        '*' => "2723 0644 0446",
+        '+' => "2723 0545",
+        ',' => "232211",
+        '-' | '\u{AD}' => "0545",
+        '.' => "2322",
+        '/' => "0248",
+        '0' => "0848420208 0347",
+        '1' => "172822 1232",
+        '2' => "07183847460242",
+        '3' => "084825354443321203",
+        '4' => "32380444",
+        '5' => "4808053544433202",
+        '6' => "48180703123243443505",
+        '7' => "084812",
+        '8' => "15060718384746351504031232434435",
+        '9' => "45150607183847433202",
+        ':' => "2625 2322",
+        ';' => "2625 232211",
+        '<' => "470543",
+        '=' => "0646 0444",
+        '>' => "074503",
+        '?' => "07183847462524 2322",
+        '@' => "343616144447381807031242",
+        'A' => "0206284642 0545",
+        'B' => "02083847463505 3544433202",
+        'C' => "4738180703123243",
+        'D' => "02082846442202",
+        'E' => "48080242 0535",
+        'F' => "480802 0535",
+        'G' => "47381807031232434525",
+        'H' => "0802 4842 0545",
+        'I' => "1838 2822 1232",
+        'J' => "4843321203",
+        'K' => "0802 4804 1542",
+        'L' => "080242",
+        'M' => "0208244842",
+        'N' => "02084248",
+        'O' => "120307183847433212",
+        'P' => "02083847463505",
+        'Q' => "120307183847433212 2442",
+        'R' => "02083847463505 2542",
+        'S' => "473818070615354443321203",
+        'T' => "0848 2822",
+        'U' => "080312324348",
+        'V' => "082248",
+        'W' => "0812253248",
+        'X' => "0842 4802",
+        'Y' => "082548 2522",
+        'Z' => "08480242",
+        '[' => "38282232",
+        '\\' => "0842",
+        ']' => "18282212",
+        '^' => "062846",
+        '_' => "0141",
+        '`' => "1827",
+        '{' => "38272615242332",
+        '|' => "2821",
+        '}' => "18272635242312",
+        '~' => "05163445",
+        '¡' => "2827 2521",
+        '¢' => "461605041343 2722",
+        '£' => "4738281712 0242 0535",
+        '¥' => "082548 2522 0444 0343",
+        '¦' => "2826 2422",
+        '«' => "270523 472543",
+        '¬' => "054544",
+        '¯' => "0949",
+        '°' => "2837261728",
+        '±' => "2723 0545 0242",
+        'µ' => "0106 03123243 4642",
+        '·' => "2524",
+        '»' => "072503 274523",
+        '¿' => "2827 252403123243",
+        'Æ' => "02071848 282242 0535",
+        'Ð' => "12182846442212 0525",
+        '×' => "1634 1436",
+        'Ø' => "120307183847433212 0248",
+        'Þ' => "0208 073746453404",
+        'ß' => "0207183847463525 3544433222",
+        '÷' => "0545 2726 2423",
+        _ => return None,
+    })
+}
+
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
+// Which edges of the cell a box drawing character joins, and whether it is
+// drawn with double lines. Heavy and dashed lines are drawn like light ones
+fn box_arms(c: char) -> Option<(u8, bool)> {
+    Some(match c {
+        '─' | '━' | '┄' | '┅' | '┈' | '┉' | '╌' | '╍' | '╼' | '╾' => {
+            (LEFT | RIGHT, false)
+        }
+        '│' | '┃' | '┆' | '┇' | '┊' | '┋' | '╎' | '╏' | '╽' | '╿' => {
+            (UP | DOWN, false)
+        }
+        '┌'..='┏' | '╭' => (DOWN | RIGHT, false),
+        '┐'..='┓' | '╮' => (DOWN | LEFT, false),
+        '└'..='┗' | '╰' => (UP | RIGHT, false),
+        '┘'..='┛' | '╯' => (UP | LEFT, false),
+        '├'..='┣' => (UP | DOWN | RIGHT, false),
+        '┤'..='┫' => (UP | DOWN | LEFT, false),
+        '┬'..='┳' => (DOWN | LEFT | RIGHT, false),
+        '┴'..='┻' => (UP | LEFT | RIGHT, false),
+        '┼'..='╋' => (UP | DOWN | LEFT | RIGHT, false),
+        '═' => (LEFT | RIGHT, true),
+        '║' => (UP | DOWN, true),
+        '╒'..='╔' => (DOWN | RIGHT, true),
+        '╕'..='╗' => (DOWN | LEFT, true),
+        '╘'..='╚' => (UP | RIGHT, true),
+        '╛'..='╝' => (UP | LEFT, true),
+        '╞'..='╠' => (UP | DOWN | RIGHT, true),
+        '╡'..='╣' => (UP | DOWN | LEFT, true),
+        '╤'..='╦' => (DOWN | LEFT | RIGHT, true),
+        '╧'..='╩' => (UP | LEFT | RIGHT, true),
+        '╪'..='╬' => (UP | DOWN | LEFT | RIGHT, true),
+        '╴' | '╸' => (LEFT, false),
+        '╵' | '╹' => (UP, false),
+        '╶' | '╺' => (RIGHT, false),
+        '╷' | '╻' => (DOWN, false),
+        _ => return None,
+    })
+}
+
+// Calls `segment` with the endpoints of every line of `c`, in glyph grid
+// units from the bottom left corner of its cell
+fn glyph(c: char, segment: &mut impl FnMut([f32; 2], [f32; 2])) {
+    let [width, height] = CELL_SIZE;
+    let center = [width / 2.0, height / 2.0];
+    match c {
+        '╱' => return segment([0.0, 0.0], [width, height]),
+        '╲' => return segment([0.0, height], [width, 0.0]),
+        '╳' => {
+            segment([0.0, 0.0], [width, height]);
+            return segment([0.0, height], [width, 0.0]);
+        }
+        _ => {}
+    }
+    if let Some((arms, double)) = box_arms(c) {
+        // The outer stroke of a double corner goes round the inner one
+        let sign = |arm: u8, opposite: u8| {
+            if arms & arm != 0 && arms & opposite == 0 {
+                -1.0
+            } else {
+                1.0
+            }
+        };
+        let gap = DOUBLE_LINE_GAP / 2.0;
+        let offsets: &[[f32; 2]] = if double {
+            &[
+                [sign(RIGHT, LEFT) * gap, sign(UP, DOWN) * gap],
+                [-sign(RIGHT, LEFT) * gap, -sign(UP, DOWN) * gap],
+            ]
+        } else {
+            &[[0.0, 0.0]]
+        };
+        for offset in offsets {
+            let [x, y] = [center[0] + offset[0], center[1] + offset[1]];
+            if arms & UP != 0 {
+                segment([x, y], [x, height]);
+            }
+            if arms & DOWN != 0 {
+                segment([x, 0.0], [x, y]);
+            }
+            if arms & LEFT != 0 {
+                segment([0.0, y], [x, y]);
+            }
+            if arms & RIGHT != 0 {
+                segment([x, y], [width, y]);
+            }
+        }
+        return;
+    }
+
+    let (base, small, accent) = decompose(c);
+    let Some(polylines) = strokes(base)
+        .or_else(|| (!base.is_whitespace() && !base.is_control()).then_some(MISSING_GLYPH))
+    else {
+        return;
+    };
+    let top = if small { X_HEIGHT } else { CAP_HEIGHT };
+    let scale = (top - BASELINE) / (CAP_HEIGHT - BASELINE);
+    let point = |x: f32, y: f32| {
+        [
+            GLYPH_OFFSET[0] + x,
+            GLYPH_OFFSET[1] + BASELINE + (y - BASELINE) * scale,
+        ]
+    };
+    for stroke in polylines.split(' ') {
+        let points = stroke
+            .as_bytes()
+            .chunks(2)
+            .map(|digits| point((digits[0] - b'0') as f32, (digits[1] - b'0') as f32))
+            .collect::<Vec<[f32; 2]>>();
+        for pair in points.windows(2) {
+            segment(pair[0], pair[1]);
+        }
+    }
+    let Some(accent) = accent else {
+        return;
+    };
+    let bottom = if accent == Accent::Cedilla {
+        BASELINE - ACCENT_HEIGHT
+    } else {
+        top + ACCENT_GAP
+    };
+    for stroke in accent.strokes() {
+        for pair in stroke.windows(2) {
+            let point = |[x, y]: [f32; 2]| {
+                [
+                    GLYPH_OFFSET[0] + x,
+                    GLYPH_OFFSET[1] + bottom + y * ACCENT_HEIGHT,
+                ]
+            };
+            segment(point(pair[0]), point(pair[1]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_break_between_words() {
+        assert_eq!(wrap("the quick brown fox", 10), ["the quick", "brown fox"]);
+        // A word that exactly fills the line
+        assert_eq!(wrap("abcd efgh", 4), ["abcd", "efgh"]);
+        // Runs of spaces inside a line are kept
+        assert_eq!(wrap("a  b", 10), ["a  b"]);
+    }
+
+    #[test]
+    fn words_longer_than_a_line_are_split() {
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("hi abcdefgh", 4), ["hi", "abcd", "efgh"]);
+        // At least one column
+        assert_eq!(wrap("abc", 0), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn newlines_always_end_a_line() {
+        assert_eq!(wrap("one\ntwo\r\n\nthree", 20), ["one", "two", "", "three"]);
+        assert_eq!(wrap("", 20), [""]);
+    }
+
+    #[test]
+    fn widths_count_characters_not_bytes() {
+        assert_eq!(wrap("héllo wörld", 5), ["héllo", "wörld"]);
+        assert_eq!(measure(&["ab", "héllo", ""]), [5, 3]);
+        assert_eq!(measure::<&str>(&[]), [0, 0]);
+        assert_eq!(measure(&wrap("the quick brown fox", 10)), [9, 2]);
+    }
+
+    #[test]
+    fn columns_fit_the_width_and_align_within_it() {
+        assert_eq!(columns_in(60.0, 2.0), 5);
+        assert_eq!(columns_in(59.0, 1.0), 9);
+        assert_eq!(columns_in(-10.0, 1.0), 0);
+        assert_eq!(Align::Left.offset(10, 3), 0.0);
+        assert_eq!(Align::Center.offset(10, 3), 3.0);
+        assert_eq!(Align::Right.offset(10, 3), 7.0);
+        // Lines wider than the box start at its left edge
+        assert_eq!(Align::Right.offset(3, 10), 0.0);
+    }
+}
@@ -0,0 +1,445 @@
+use crate::utils::embedded::RgbaImage;
+
+// Vulkan's guaranteed minimum maxImageDimension2D
+pub const MAX_ATLAS_SIZE: u32 = 4096;
+// Transparent pixels around every entry, so filtering at its edges doesn't
+// pick up the neighbours
+const ATLAS_PADDING: u32 = 1;
+
+// Index of an image added to a `TextureAtlas`, valid for as long as the atlas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AtlasId(pub usize);
+
+// In pixels, x right and y down from the atlas' top left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// What of the atlas changed since the last `take_update`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtlasUpdate {
+    Unchanged,
+    // The atlas was created or grew, the whole image is new
+    Full,
+    Regions(Vec<AtlasRect>),
+}
+
+// Rows of entries filled left to right, a new row starts below the last one
+// when no row is both tall enough and has room left
+#[derive(Debug, Clone, Copy)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    used: u32,
+    // Of the entries on it, shorter ones leave part of the shelf empty
+    entry_area: u64,
+}
+
+// Places rectangles on shelves. Entries are never moved, growing only adds
+// room to the right and at the bottom
+#[derive(Debug, Clone)]
+pub struct ShelfPacker {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfPacker {
+    pub fn new(width: u32, height: u32) -> Self {
+        ShelfPacker {
+            width,
+            height,
+            shelves: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+
+    // Top left corner of a free `width` x `height` area, None when it doesn't
+    // fit. Takes the existing shelf that wastes the least height
+    pub fn insert(&mut self, width: u32, height: u32) -> Option<[u32; 2]> {
+        let shelf_width = self.width;
+        let best = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= height && shelf_width - shelf.used >= width)
+            .min_by_key(|shelf| shelf.height - height);
+        if let Some(shelf) = best {
+            let position = [shelf.used, shelf.y];
+            shelf.used += width;
+            shelf.entry_area += width as u64 * height as u64;
+            return Some(position);
+        }
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        if width > self.width || height > self.height - y {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height,
+            used: width,
+            entry_area: width as u64 * height as u64,
+        });
+        Some([0, y])
+    }
+
+    // Doubles the shorter side, false once both are at `max_size`
+    pub fn grow(&mut self, max_size: u32) -> bool {
+        let side = if self.width <= self.height && self.width < max_size || self.height >= max_size
+        {
+            &mut self.width
+        } else {
+            &mut self.height
+        };
+        if *side >= max_size {
+            return false;
+        }
+        *side = (*side * 2).min(max_size);
+        true
+    }
+
+    // Share of the area covered by shelves that holds no entry
+    pub fn fragmentation(&self) -> f32 {
+        let shelf_area = self
+            .shelves
+            .iter()
+            .map(|shelf| self.width as u64 * shelf.height as u64)
+            .sum::<u64>();
+        if shelf_area == 0 {
+            return 0.0;
+        }
+        let used_area = self
+            .shelves
+            .iter()
+            .map(|shelf| shelf.entry_area)
+            .sum::<u64>();
+        1.0 - used_area as f32 / shelf_area as f32
+    }
+}
+
+// Many small RGBA8 images in one texture, so everything drawn from them
+// binds a single image. Images can be added at any time, when one doesn't fit
+// the atlas grows and `generation` changes since every UV rectangle does
+pub struct TextureAtlas {
+    packer: ShelfPacker,
+    max_size: u32,
+    // The whole atlas on the CPU, the source of every upload
+    pixels: Vec<u8>,
+    entries: Vec<AtlasRect>,
+    update: AtlasUpdate,
+    generation: u64,
+}
+
+impl TextureAtlas {
+    pub fn new(width: u32, height: u32) -> Self {
+        let (width, height) = (
+            width.clamp(1, MAX_ATLAS_SIZE),
+            height.clamp(1, MAX_ATLAS_SIZE),
+        );
+        TextureAtlas {
+            packer: ShelfPacker::new(width, height),
+            max_size: MAX_ATLAS_SIZE,
+            pixels: vec![0; (width * height * 4) as usize],
+            entries: Vec::new(),
+            update: AtlasUpdate::Full,
+            generation: 0,
+        }
+    }
+
+    // Copies `image` in, growing the atlas if it has to. None for empty
+    // images and ones that don't fit even at the largest size
+    pub fn add(&mut self, image: &RgbaImage) -> Option<AtlasId> {
+        if image.width == 0
+            || image.height == 0
+            || image.pixels.len() < (image.width * image.height * 4) as usize
+        {
+            return None;
+        }
+        let (width, height) = (
+            image.width + 2 * ATLAS_PADDING,
+            image.height + 2 * ATLAS_PADDING,
+        );
+        let [x, y] = loop {
+            if let Some(position) = self.packer.insert(width, height) {
+                break position;
+            }
+            let old_size = self.packer.size();
+            if !self.packer.grow(self.max_size) {
+                return None;
+            }
+            self.resize(old_size);
+        };
+        let rect = AtlasRect {
+            x: x + ATLAS_PADDING,
+            y: y + ATLAS_PADDING,
+            width: image.width,
+            height: image.height,
+        };
+        self.blit(rect, &image.pixels);
+        match &mut self.update {
+            AtlasUpdate::Full => {}
+            AtlasUpdate::Unchanged => self.update = AtlasUpdate::Regions(vec![rect]),
+            AtlasUpdate::Regions(regions) => regions.push(rect),
+        }
+        self.entries.push(rect);
+        Some(AtlasId(self.entries.len() - 1))
+    }
+
+    pub fn rect(&self, id: AtlasId) -> Option<AtlasRect> {
+        self.entries.get(id.0).copied()
+    }
+
+    // Texture coordinates of the entry's corners, [u0, v0, u1, v1] with v
+    // down. Stale once `generation` changed
+    pub fn uv_rect(&self, id: AtlasId) -> Option<[f32; 4]> {
+        let rect = self.rect(id)?;
+        let [width, height] = self.size().map(|side| side as f32);
+        Some([
+            rect.x as f32 / width,
+            rect.y as f32 / height,
+            (rect.x + rect.width) as f32 / width,
+            (rect.y + rect.height) as f32 / height,
+        ])
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.packer.size()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Changes every time the atlas grows
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn fragmentation(&self) -> f32 {
+        self.packer.fragmentation()
+    }
+
+    // What has to be uploaded since the last call
+    pub fn take_update(&mut self) -> AtlasUpdate {
+        std::mem::replace(&mut self.update, AtlasUpdate::Unchanged)
+    }
+
+    // Rows of `rect` back to back, the layout a buffer to image copy of it
+    // reads
+    pub fn region_pixels(&self, rect: AtlasRect) -> Vec<u8> {
+        let row_size = self.size()[0] as usize * 4;
+        (rect.y..rect.y + rect.height)
+            .flat_map(|y| {
+                let start = y as usize * row_size + rect.x as usize * 4;
+                &self.pixels[start..start + rect.width as usize * 4]
+            })
+            .copied()
+            .collect()
+    }
+
+    // Keeps the pixels where they were in the larger image
+    fn resize(&mut self, [old_width, old_height]: [u32; 2]) {
+        let [width, height] = self.size();
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        let (old_row, row) = (old_width as usize * 4, width as usize * 4);
+        for y in 0..old_height as usize {
+            pixels[y * row..y * row + old_row]
+                .copy_from_slice(&self.pixels[y * old_row..(y + 1) * old_row]);
+        }
+        self.pixels = pixels;
+        self.update = AtlasUpdate::Full;
+        self.generation += 1;
+    }
+
+    fn blit(&mut self, rect: AtlasRect, pixels: &[u8]) {
+        let row_size = self.size()[0] as usize * 4;
+        let source_row = rect.width as usize * 4;
+        for (row, source) in pixels
+            .chunks_exact(source_row)
+            .take(rect.height as usize)
+            .enumerate()
+        {
+            let start = (rect.y as usize + row) * row_size + rect.x as usize * 4;
+            self.pixels[start..start + source_row].copy_from_slice(source);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage {
+            width,
+            height,
+            pixels: vec![value; (width * height * 4) as usize],
+        }
+    }
+
+    fn overlap(a: AtlasRect, b: AtlasRect) -> bool {
+        a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    #[test]
+    fn entries_are_padded_apart() {
+        let mut atlas = TextureAtlas::new(64, 64);
+        let ids = (0..12)
+            .map(|index| atlas.add(&image(5 + index % 4, 7 + index % 3, 1)).unwrap())
+            .collect::<Vec<AtlasId>>();
+        let rects = ids
+            .iter()
+            .map(|id| atlas.rect(*id).unwrap())
+            .collect::<Vec<_>>();
+        for (index, a) in rects.iter().enumerate() {
+            let padded = AtlasRect {
+                x: a.x - ATLAS_PADDING,
+                y: a.y - ATLAS_PADDING,
+                width: a.width + 2 * ATLAS_PADDING,
+                height: a.height + 2 * ATLAS_PADDING,
+            };
+            assert!(rects[index + 1..].iter().all(|b| !overlap(padded, *b)));
+        }
+        assert_eq!(atlas.generation(), 0);
+    }
+
+    #[test]
+    fn growing_keeps_the_pixels() {
+        let mut atlas = TextureAtlas::new(16, 16);
+        let first = atlas.add(&image(10, 10, 7)).unwrap();
+        assert_eq!(atlas.take_update(), AtlasUpdate::Full);
+        let uv = atlas.uv_rect(first).unwrap();
+        let second = atlas.add(&image(10, 10, 9)).unwrap();
+        assert_eq!(atlas.generation(), 1);
+        assert_eq!(atlas.take_update(), AtlasUpdate::Full);
+        assert_ne!(atlas.uv_rect(first).unwrap(), uv);
+        let region = |id| atlas.region_pixels(atlas.rect(id).unwrap());
+        assert!(region(first).iter().all(|value| *value == 7));
+        assert!(region(second).iter().all(|value| *value == 9));
+        // Room left, only the new region is uploaded
+        let third = atlas.add(&image(2, 2, 3)).unwrap();
+        assert_eq!(
+            atlas.take_update(),
+            AtlasUpdate::Regions(vec![atlas.rect(third).unwrap()])
+        );
+    }
+
+    #[test]
+    fn uv_rects_cover_the_entry() {
+        let mut atlas = TextureAtlas::new(32, 16);
+        let id = atlas.add(&image(4, 2, 1)).unwrap();
+        let rect = atlas.rect(id).unwrap();
+        assert_eq!(
+            atlas.uv_rect(id),
+            Some([
+                rect.x as f32 / 32.0,
+                rect.y as f32 / 16.0,
+                (rect.x + 4) as f32 / 32.0,
+                (rect.y + 2) as f32 / 16.0,
+            ])
+        );
+        assert_eq!(atlas.uv_rect(AtlasId(1)), None);
+    }
+
+    #[test]
+    fn empty_and_oversized_images_are_refused() {
+        let mut atlas = TextureAtlas::new(16, 16);
+        assert_eq!(atlas.add(&image(0, 4, 1)), None);
+        assert_eq!(atlas.add(&image(MAX_ATLAS_SIZE, 1, 1)), None);
+        assert!(atlas.is_empty());
+    }
+
+    #[test]
+    fn entries_go_on_the_shelf_that_wastes_the_least_height() {
+        let mut packer = ShelfPacker::new(16, 16);
+        assert_eq!(packer.insert(8, 4), Some([0, 0]));
+        assert_eq!(packer.insert(4, 2), Some([8, 0]));
+        // Fills the first shelf exactly
+        assert_eq!(packer.insert(4, 4), Some([12, 0]));
+        assert_eq!(packer.insert(1, 1), Some([0, 4]));
+        assert_eq!(packer.insert(2, 8), Some([0, 5]));
+        // Both shelves have room, the one row high one fits best
+        assert_eq!(packer.insert(1, 1), Some([1, 4]));
+        assert_eq!(packer.insert(4, 4), Some([2, 5]));
+    }
+
+    #[test]
+    fn entries_that_dont_fit_are_refused() {
+        let mut packer = ShelfPacker::new(16, 16);
+        assert_eq!(packer.insert(17, 1), None);
+        assert_eq!(packer.insert(1, 17), None);
+        assert_eq!(packer.insert(16, 13), Some([0, 0]));
+        // 3 rows are left below the shelf
+        assert_eq!(packer.insert(16, 4), None);
+        assert_eq!(packer.insert(16, 3), Some([0, 13]));
+        assert_eq!(packer.insert(1, 1), None);
+    }
+
+    #[test]
+    fn growing_doubles_the_shorter_side_up_to_the_maximum() {
+        let sizes = |mut packer: ShelfPacker, max_size| {
+            let mut sizes = vec![packer.size()];
+            while packer.grow(max_size) {
+                sizes.push(packer.size());
+            }
+            sizes
+        };
+        assert_eq!(
+            sizes(ShelfPacker::new(16, 16), 64),
+            [[16, 16], [32, 16], [32, 32], [64, 32], [64, 64]]
+        );
+        assert_eq!(
+            sizes(ShelfPacker::new(64, 16), 64),
+            [[64, 16], [64, 32], [64, 64]]
+        );
+        // The last step is cut short at a maximum that isn't a power of two
+        assert_eq!(
+            sizes(ShelfPacker::new(48, 16), 50),
+            [[48, 16], [48, 32], [48, 50], [50, 50]]
+        );
+    }
+
+    #[test]
+    fn shelves_widen_as_the_packer_grows() {
+        let mut packer = ShelfPacker::new(8, 8);
+        assert_eq!(packer.insert(8, 8), Some([0, 0]));
+        assert_eq!(packer.insert(8, 8), None);
+        assert!(packer.grow(16));
+        assert_eq!(packer.insert(8, 8), Some([8, 0]));
+        assert!(packer.grow(16));
+        assert_eq!(packer.insert(16, 8), Some([0, 8]));
+    }
+
+    #[test]
+    fn fragmentation_counts_the_empty_shelf_area() {
+        let mut packer = ShelfPacker::new(16, 16);
+        assert_eq!(packer.fragmentation(), 0.0);
+        packer.insert(8, 4);
+        assert_eq!(packer.fragmentation(), 0.5);
+        // Half as tall as its shelf, 40 of 64 pixels are used
+        packer.insert(4, 2);
+        assert_eq!(packer.fragmentation(), 0.375);
+        packer.insert(4, 4);
+        assert_eq!(packer.fragmentation(), 0.125);
+        // A full shelf adds no empty area
+        packer.insert(16, 4);
+        assert_eq!(packer.fragmentation(), 0.0625);
+    }
+}
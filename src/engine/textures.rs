@@ -0,0 +1,156 @@
+use crate::utils::embedded::RgbaImage;
+
+// Pixel patterns that don't need a file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureKind {
+    // `cells` squares along each side, alternating magenta and dark grey
+    Checker { cells: u32 },
+    // u in the red and v in the green channel
+    Gradient,
+    // Grey value noise, the same seed always gives the same pixels
+    ValueNoise { seed: u32, octaves: u32 },
+}
+
+// Lattice cells across the texture for the first noise octave
+const NOISE_FREQUENCY: u32 = 4;
+
+// Square RGBA8 image of `size` pixels on each side
+pub fn generate(kind: TextureKind, size: u32) -> RgbaImage {
+    let size = size.max(1);
+    let pixels = (0..size * size)
+        .flat_map(|index| {
+            let (x, y) = (index % size, index / size);
+            match kind {
+                TextureKind::Checker { cells } => {
+                    let cells = cells.clamp(1, size);
+                    match (x * cells / size + y * cells / size) % 2 {
+                        0 => [0xff, 0x00, 0xff, 0xff],
+                        _ => [0x20, 0x20, 0x20, 0xff],
+                    }
+                }
+                TextureKind::Gradient => {
+                    let (u, v) = pixel_center(x, y, size);
+                    [(u * 255.0) as u8, (v * 255.0) as u8, 0x00, 0xff]
+                }
+                TextureKind::ValueNoise { seed, octaves } => {
+                    let (u, v) = pixel_center(x, y, size);
+                    let value = (value_noise(seed, octaves, u, v) * 255.0).round() as u8;
+                    [value, value, value, 0xff]
+                }
+            }
+        })
+        .collect();
+    RgbaImage {
+        width: size,
+        height: size,
+        pixels,
+    }
+}
+
+fn pixel_center(x: u32, y: u32, size: u32) -> (f32, f32) {
+    (
+        (x as f32 + 0.5) / size as f32,
+        (y as f32 + 0.5) / size as f32,
+    )
+}
+
+// Integer hash of a lattice point, so the result doesn't depend on the
+// platform's float math
+fn lattice(seed: u32, x: u32, y: u32) -> f32 {
+    let mut hash = seed ^ x.wrapping_mul(0x27d4_eb2d) ^ y.wrapping_mul(0x1656_67b1);
+    hash = (hash ^ (hash >> 15)).wrapping_mul(0x85eb_ca6b);
+    hash = (hash ^ (hash >> 13)).wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    (hash & 0xffff) as f32 / 0xffff as f32
+}
+
+// Octaves double the frequency and halve the amplitude, the lattice wraps
+// so the texture tiles. Normalized to 0..=1
+fn value_noise(seed: u32, octaves: u32, u: f32, v: f32) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut amplitudes = 0.0;
+    for octave in 0..octaves.clamp(1, 16) {
+        let frequency = NOISE_FREQUENCY << octave;
+        let (x, y) = (u * frequency as f32, v * frequency as f32);
+        let (cell_x, cell_y) = (x.floor() as u32, y.floor() as u32);
+        let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+        let (tx, ty) = (smooth(x.fract()), smooth(y.fract()));
+        let corner = |dx: u32, dy: u32| {
+            let seed = seed.wrapping_add(octave);
+            lattice(seed, (cell_x + dx) % frequency, (cell_y + dy) % frequency)
+        };
+        let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+        let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+        total += (top + (bottom - top) * ty) * amplitude;
+        amplitudes += amplitude;
+        amplitude *= 0.5;
+    }
+    total / amplitudes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red(image: &RgbaImage) -> Vec<u8> {
+        image.pixels.chunks(4).map(|pixel| pixel[0]).collect()
+    }
+
+    #[test]
+    fn noise_is_pinned_to_its_seed() {
+        let noise = TextureKind::ValueNoise {
+            seed: 7,
+            octaves: 3,
+        };
+        let image = generate(noise, 6);
+        assert_eq!(
+            red(&image)[..12],
+            [156, 152, 162, 161, 164, 179, 185, 169, 132, 136, 143, 192]
+        );
+        assert_eq!(image.pixels, generate(noise, 6).pixels);
+        // Grey and opaque
+        assert!(image
+            .pixels
+            .chunks(4)
+            .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2] && pixel[3] == 0xff));
+        let reseeded = TextureKind::ValueNoise {
+            seed: 8,
+            octaves: 3,
+        };
+        assert_ne!(image.pixels, generate(reseeded, 6).pixels);
+    }
+
+    #[test]
+    fn noise_tiles() {
+        for v in [0.0, 0.3, 0.75] {
+            assert_eq!(value_noise(3, 4, 0.0, v), value_noise(3, 4, 1.0, v));
+            assert_eq!(value_noise(3, 4, v, 0.0), value_noise(3, 4, v, 1.0));
+        }
+    }
+
+    #[test]
+    fn sizes_need_not_be_powers_of_two() {
+        for kind in [
+            TextureKind::Checker { cells: 3 },
+            TextureKind::Gradient,
+            TextureKind::ValueNoise {
+                seed: 1,
+                octaves: 2,
+            },
+        ] {
+            let image = generate(kind, 7);
+            assert_eq!((image.width, image.height), (7, 7));
+            assert_eq!(image.pixels.len(), 7 * 7 * 4);
+        }
+        // The 3 cells split 7 pixels as evenly as they can
+        let checker = red(&generate(TextureKind::Checker { cells: 3 }, 7));
+        assert_eq!(checker[..7], [0xff, 0xff, 0xff, 0x20, 0x20, 0xff, 0xff]);
+        // More cells than pixels are clamped to one per pixel
+        let checker = red(&generate(TextureKind::Checker { cells: 9 }, 3));
+        assert_eq!(
+            checker,
+            [0xff, 0x20, 0xff, 0x20, 0xff, 0x20, 0xff, 0x20, 0xff]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use ash::vk::Extent2D;
+use log::Level;
+
+use crate::engine::{
+    debug_palette::DebugPalette,
+    text::{Align, TextBox, TextRows, CELL_SIZE},
+};
+
+// How long a toast stays up, fading to black over the last part of it
+pub const TOAST_DURATION: Duration = Duration::from_secs(3);
+const TOAST_FADE: Duration = Duration::from_millis(500);
+// Shown at once, a new toast pushes out the oldest one
+pub const MAX_TOASTS: usize = 5;
+// Longer messages are cut off, they are in the log in full
+const TOAST_COLUMNS: usize = 64;
+const CLIPPED_SUFFIX: &str = "...";
+// Bottom right corner of the stack, as fractions of the window with y up
+const TOAST_CORNER: [f32; 2] = [0.98, 0.02];
+const TOAST_SCALE: f32 = 1.5;
+
+#[derive(Debug, Clone)]
+struct Toast {
+    level: Level,
+    message: String,
+    shown: Instant,
+}
+
+// Short messages about engine events over the primary window's bottom right
+// corner, the newest at the bottom. Time is passed in rather than read so the
+// queue follows whatever clock the caller has
+#[derive(Debug, Default)]
+pub struct Toasts {
+    queue: VecDeque<Toast>,
+}
+
+impl Toasts {
+    pub fn push(&mut self, level: Level, message: impl Into<String>, now: Instant) {
+        if self.queue.len() == MAX_TOASTS {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(Toast {
+            level,
+            message: clip(&message.into()),
+            shown: now,
+        });
+    }
+
+    // Drops the toasts that have been up for `TOAST_DURATION`
+    pub fn expire(&mut self, now: Instant) {
+        self.queue
+            .retain(|toast| now.saturating_duration_since(toast.shown) < TOAST_DURATION);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // The rows of the stack and where they go in a window of `window`.
+    // Errors and warnings are in the palette's warning and caution colors
+    pub(crate) fn text(
+        &self,
+        palette: &DebugPalette,
+        window: Extent2D,
+        now: Instant,
+    ) -> Option<(TextBox, TextRows)> {
+        if window.width == 0 || window.height == 0 || self.queue.is_empty() {
+            return None;
+        }
+        let width = TOAST_COLUMNS as f32 * CELL_SIZE[0] * TOAST_SCALE / window.width as f32;
+        let height = self.queue.len() as f32 * CELL_SIZE[1] * TOAST_SCALE / window.height as f32;
+        let text_box = TextBox {
+            origin: [TOAST_CORNER[0] - width, TOAST_CORNER[1] + height],
+            columns: TOAST_COLUMNS,
+            align: Align::Right,
+            scale: TOAST_SCALE,
+        };
+        let rows = self
+            .queue
+            .iter()
+            .map(|toast| {
+                let color = match toast.level {
+                    Level::Error => palette.warning,
+                    Level::Warn => palette.caution,
+                    _ => palette.text,
+                };
+                let fade = fade(now.saturating_duration_since(toast.shown));
+                (toast.message.clone(), color.map(|channel| channel * fade))
+            })
+            .collect::<TextRows>();
+        Some((text_box, rows))
+    }
+}
+
+// 1 until the fade starts, then down to 0 at `TOAST_DURATION`
+fn fade(age: Duration) -> f32 {
+    let left = TOAST_DURATION.saturating_sub(age);
+    (left.as_secs_f32() / TOAST_FADE.as_secs_f32()).min(1.0)
+}
+
+// The first line of `message`, cut to `TOAST_COLUMNS` characters
+fn clip(message: &str) -> String {
+    let line = message.lines().next().unwrap_or_default();
+    if line.chars().count() <= TOAST_COLUMNS {
+        return line.to_string();
+    }
+    let mut clipped = line
+        .chars()
+        .take(TOAST_COLUMNS - CLIPPED_SUFFIX.len())
+        .collect::<String>();
+    clipped.push_str(CLIPPED_SUFFIX);
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Extent2D = Extent2D {
+        width: 1920,
+        height: 1080,
+    };
+
+    fn messages(toasts: &Toasts, now: Instant) -> Vec<String> {
+        toasts
+            .text(&DebugPalette::DEFAULT, WINDOW, now)
+            .map_or_else(Vec::new, |(_, rows)| {
+                rows.into_iter().map(|(message, _)| message).collect()
+            })
+    }
+
+    #[test]
+    fn new_toasts_push_out_the_oldest() {
+        let start = Instant::now();
+        let mut toasts = Toasts::default();
+        for index in 0..MAX_TOASTS + 2 {
+            toasts.push(Level::Info, format!("toast {index}"), start);
+        }
+        assert_eq!(toasts.len(), MAX_TOASTS);
+        assert_eq!(
+            messages(&toasts, start),
+            ["toast 2", "toast 3", "toast 4", "toast 5", "toast 6"]
+        );
+    }
+
+    #[test]
+    fn toasts_expire_after_their_duration() {
+        let start = Instant::now();
+        let mut toasts = Toasts::default();
+        toasts.push(Level::Info, "first", start);
+        toasts.push(Level::Info, "second", start + Duration::from_secs(1));
+
+        toasts.expire(start + TOAST_DURATION - Duration::from_millis(1));
+        assert_eq!(toasts.len(), 2);
+        toasts.expire(start + TOAST_DURATION);
+        assert_eq!(messages(&toasts, start + TOAST_DURATION), ["second"]);
+        toasts.expire(start + Duration::from_secs(1) + TOAST_DURATION);
+        assert!(toasts.is_empty());
+        // A clock that went backwards keeps them
+        toasts.push(Level::Info, "third", start + Duration::from_secs(10));
+        toasts.expire(start);
+        assert_eq!(toasts.len(), 1);
+    }
+
+    #[test]
+    fn toasts_fade_out_in_their_level_color() {
+        let start = Instant::now();
+        let palette = DebugPalette::DEFAULT;
+        let mut toasts = Toasts::default();
+        toasts.push(Level::Error, "error", start);
+        toasts.push(Level::Warn, "warning", start);
+        toasts.push(Level::Info, "info", start);
+
+        let (_, rows) = toasts.text(&palette, WINDOW, start).unwrap();
+        let colors = rows.iter().map(|(_, color)| *color).collect::<Vec<_>>();
+        assert_eq!(colors, [palette.warning, palette.caution, palette.text]);
+
+        let halfway = start + TOAST_DURATION - TOAST_FADE / 2;
+        let (_, rows) = toasts.text(&palette, WINDOW, halfway).unwrap();
+        assert_eq!(rows[2].1, palette.text.map(|channel| channel * 0.5));
+        let (_, rows) = toasts
+            .text(&palette, WINDOW, start + TOAST_DURATION)
+            .unwrap();
+        assert_eq!(rows[2].1, [0.0; 3]);
+    }
+
+    #[test]
+    fn long_messages_are_clipped_to_their_first_line() {
+        let start = Instant::now();
+        let mut toasts = Toasts::default();
+        toasts.push(Level::Info, "x".repeat(100), start);
+        toasts.push(Level::Info, "first line\nsecond line", start);
+        let messages = messages(&toasts, start);
+        assert_eq!(messages[0].chars().count(), TOAST_COLUMNS);
+        assert!(messages[0].ends_with(CLIPPED_SUFFIX));
+        assert_eq!(messages[1], "first line");
+        // Nothing to lay out in a minimized window
+        assert!(toasts
+            .text(&DebugPalette::DEFAULT, Extent2D::default(), start)
+            .is_none());
+    }
+}
@@ -0,0 +1,320 @@
+use ash::vk::{Extent2D, Offset2D, Rect2D, SurfaceTransformFlagsKHR, Viewport};
+use cgmath::{point3, Deg, Matrix4, SquareMatrix};
+
+use crate::engine::scene::Camera;
+
+pub const MAX_VIEWPORTS: u32 = 2;
+pub const MAX_VIEWS: u32 = 4;
+
+// Identifies a window the engine renders into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ViewId(pub u32);
+
+impl ViewId {
+    pub const PRIMARY: ViewId = ViewId(0);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViewportLayout {
+    #[default]
+    Single,
+    // Left and right halves
+    SplitHorizontal,
+    // Top and bottom halves
+    SplitVertical,
+}
+
+impl ViewportLayout {
+    pub fn next(&self) -> ViewportLayout {
+        match self {
+            ViewportLayout::Single => ViewportLayout::SplitHorizontal,
+            ViewportLayout::SplitHorizontal => ViewportLayout::SplitVertical,
+            ViewportLayout::SplitVertical => ViewportLayout::Single,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewportLayout::Single => "single",
+            ViewportLayout::SplitHorizontal => "split_horizontal",
+            ViewportLayout::SplitVertical => "split_vertical",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "single" => Some(ViewportLayout::Single),
+            "split_horizontal" => Some(ViewportLayout::SplitHorizontal),
+            "split_vertical" => Some(ViewportLayout::SplitVertical),
+            _ => None,
+        }
+    }
+
+    pub fn regions(&self, extent: Extent2D) -> Vec<Rect2D> {
+        let region = |x: u32, y: u32, width: u32, height: u32| {
+            Rect2D::default()
+                .offset(Offset2D::default().x(x as i32).y(y as i32))
+                .extent(
+                    Extent2D::default()
+                        .width(width.max(1))
+                        .height(height.max(1)),
+                )
+        };
+        let Extent2D { width, height } = extent;
+        match self {
+            ViewportLayout::Single => vec![region(0, 0, width, height)],
+            ViewportLayout::SplitHorizontal => {
+                let left = width / 2;
+                vec![
+                    region(0, 0, left, height),
+                    region(left, 0, width - left, height),
+                ]
+            }
+            ViewportLayout::SplitVertical => {
+                let top = height / 2;
+                vec![
+                    region(0, 0, width, top),
+                    region(0, top, width, height - top),
+                ]
+            }
+        }
+    }
+}
+
+// Flipped so clip space y points up, the projection is a plain perspective
+// one. Needs VK_KHR_maintenance1
+pub fn viewport(region: &Rect2D) -> Viewport {
+    Viewport::default()
+        .x(region.offset.x as f32)
+        .y((region.offset.y + region.extent.height as i32) as f32)
+        .width(region.extent.width as f32)
+        .height(-(region.extent.height as f32))
+        .min_depth(0.0)
+        .max_depth(1.0)
+}
+
+// Unflipped, for fullscreen passes that work in framebuffer coordinates
+pub fn fullscreen_viewport(region: &Rect2D) -> Viewport {
+    Viewport::default()
+        .x(region.offset.x as f32)
+        .y(region.offset.y as f32)
+        .width(region.extent.width as f32)
+        .height(region.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0)
+}
+
+pub fn aspect_ratio(region: &Rect2D) -> f32 {
+    region.extent.width as f32 / region.extent.height as f32
+}
+
+// A window pixel in the normalized device coordinates of `region`, y up like
+// `Ray::from_ndc`. Pixels outside the region land outside -1..1
+pub fn pixel_ndc(region: &Rect2D, [x, y]: [f32; 2]) -> [f32; 2] {
+    let (left, top) = (region.offset.x as f32, region.offset.y as f32);
+    [
+        (x - left) / region.extent.width as f32 * 2.0 - 1.0,
+        1.0 - (y - top) / region.extent.height as f32 * 2.0,
+    ]
+}
+
+// How the display is rotated relative to the swapchain images. Rendering
+// straight into the rotated orientation saves the compositor a rotation pass.
+// Mirrored transforms are left to the compositor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SurfaceRotation {
+    #[default]
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl SurfaceRotation {
+    pub fn from_transform(transform: SurfaceTransformFlagsKHR) -> Option<SurfaceRotation> {
+        match transform {
+            SurfaceTransformFlagsKHR::IDENTITY => Some(SurfaceRotation::Identity),
+            SurfaceTransformFlagsKHR::ROTATE_90 => Some(SurfaceRotation::Rotate90),
+            SurfaceTransformFlagsKHR::ROTATE_180 => Some(SurfaceRotation::Rotate180),
+            SurfaceTransformFlagsKHR::ROTATE_270 => Some(SurfaceRotation::Rotate270),
+            _ => None,
+        }
+    }
+
+    pub fn transform(&self) -> SurfaceTransformFlagsKHR {
+        match self {
+            SurfaceRotation::Identity => SurfaceTransformFlagsKHR::IDENTITY,
+            SurfaceRotation::Rotate90 => SurfaceTransformFlagsKHR::ROTATE_90,
+            SurfaceRotation::Rotate180 => SurfaceTransformFlagsKHR::ROTATE_180,
+            SurfaceRotation::Rotate270 => SurfaceTransformFlagsKHR::ROTATE_270,
+        }
+    }
+
+    pub fn swaps_axes(&self) -> bool {
+        matches!(self, SurfaceRotation::Rotate90 | SurfaceRotation::Rotate270)
+    }
+
+    // Converts between the window and the swapchain extent, both directions
+    // are the same swap
+    pub fn rotate_extent(&self, extent: Extent2D) -> Extent2D {
+        if self.swaps_axes() {
+            Extent2D::default()
+                .width(extent.height)
+                .height(extent.width)
+        } else {
+            extent
+        }
+    }
+
+    // Applied in front of the projection, turns clip space the same way the
+    // compositor turns the image
+    pub fn matrix(&self) -> Matrix4<f32> {
+        match self {
+            SurfaceRotation::Identity => Matrix4::identity(),
+            // Clip space y points up, so the angles turn the other way than
+            // in the image
+            SurfaceRotation::Rotate90 => Matrix4::from_angle_z(Deg(270.0)),
+            SurfaceRotation::Rotate180 => Matrix4::from_angle_z(Deg(180.0)),
+            SurfaceRotation::Rotate270 => Matrix4::from_angle_z(Deg(90.0)),
+        }
+    }
+
+    // Where a region of the window ends up in the swapchain image, follows
+    // the clip space rotation of `matrix`
+    pub fn framebuffer_region(&self, region: &Rect2D, window_extent: Extent2D) -> Rect2D {
+        let Rect2D { offset, extent } = *region;
+        let (x, y) = (offset.x, offset.y);
+        let (width, height) = (extent.width as i32, extent.height as i32);
+        let (window_width, window_height) =
+            (window_extent.width as i32, window_extent.height as i32);
+        let (x, y) = match self {
+            SurfaceRotation::Identity => (x, y),
+            SurfaceRotation::Rotate90 => (window_height - y - height, x),
+            SurfaceRotation::Rotate180 => (window_width - x - width, window_height - y - height),
+            SurfaceRotation::Rotate270 => (y, window_width - x - width),
+        };
+        Rect2D::default()
+            .offset(Offset2D::default().x(x).y(y))
+            .extent(self.rotate_extent(extent))
+    }
+}
+
+// Per window camera setup, the scene itself is shared
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    pub id: ViewId,
+    pub layout: ViewportLayout,
+    pub cameras: [Camera; MAX_VIEWPORTS as usize],
+}
+
+impl View {
+    pub fn new(id: ViewId) -> Self {
+        Self {
+            id,
+            layout: ViewportLayout::default(),
+            cameras: [
+                Camera::default(),
+                Camera {
+                    eye: point3(-2.0, -2.0, 2.0),
+                    ..Camera::default()
+                },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{Extent2D, Offset2D};
+    use cgmath::vec4;
+
+    use super::*;
+
+    const ROTATIONS: [SurfaceRotation; 4] = [
+        SurfaceRotation::Identity,
+        SurfaceRotation::Rotate90,
+        SurfaceRotation::Rotate180,
+        SurfaceRotation::Rotate270,
+    ];
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Rect2D {
+        Rect2D {
+            offset: Offset2D { x, y },
+            extent: Extent2D { width, height },
+        }
+    }
+
+    #[test]
+    fn pixels_map_to_their_region() {
+        let region = Rect2D {
+            offset: Offset2D { x: 100, y: 50 },
+            extent: Extent2D {
+                width: 200,
+                height: 100,
+            },
+        };
+        assert_eq!(pixel_ndc(&region, [100.0, 50.0]), [-1.0, 1.0]);
+        assert_eq!(pixel_ndc(&region, [300.0, 150.0]), [1.0, -1.0]);
+        assert_eq!(pixel_ndc(&region, [200.0, 100.0]), [0.0, 0.0]);
+        // Left of and below the region
+        assert_eq!(pixel_ndc(&region, [0.0, 200.0]), [-2.0, -2.0]);
+    }
+
+    #[test]
+    fn rotations_round_trip_through_their_transform() {
+        for rotation in ROTATIONS {
+            assert_eq!(
+                SurfaceRotation::from_transform(rotation.transform()),
+                Some(rotation)
+            );
+        }
+        assert_eq!(
+            SurfaceRotation::from_transform(SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR),
+            None
+        );
+    }
+
+    #[test]
+    fn regions_move_with_the_rotation() {
+        let window = Extent2D {
+            width: 400,
+            height: 200,
+        };
+        let region = rect(10, 20, 30, 40);
+        let expected = [
+            rect(10, 20, 30, 40),
+            rect(140, 10, 40, 30),
+            rect(360, 140, 30, 40),
+            rect(20, 360, 40, 30),
+        ];
+        for (rotation, expected) in ROTATIONS.into_iter().zip(expected) {
+            assert_eq!(rotation.framebuffer_region(&region, window), expected);
+        }
+    }
+
+    #[test]
+    fn matrices_agree_with_the_framebuffer_regions() {
+        let window = Extent2D {
+            width: 400,
+            height: 200,
+        };
+        let region = rect(10, 20, 30, 40);
+        let center = |rect: &Rect2D| {
+            [
+                rect.offset.x as f32 + rect.extent.width as f32 / 2.0,
+                rect.offset.y as f32 + rect.extent.height as f32 / 2.0,
+            ]
+        };
+        for rotation in ROTATIONS {
+            let [x, y] = pixel_ndc(&rect(0, 0, window.width, window.height), center(&region));
+            let rotated = rotation.matrix() * vec4(x, y, 0.0, 1.0);
+
+            let image = rotation.rotate_extent(window);
+            let moved = rotation.framebuffer_region(&region, window);
+            let [expected_x, expected_y] =
+                pixel_ndc(&rect(0, 0, image.width, image.height), center(&moved));
+            assert!((rotated.x - expected_x).abs() < 1e-5, "{rotation:?}");
+            assert!((rotated.y - expected_y).abs() < 1e-5, "{rotation:?}");
+        }
+    }
+}
@@ -0,0 +1,9 @@
+#[cfg(all(target_os = "android", feature = "android"))]
+pub mod android;
+pub mod app;
+pub mod engine;
+pub mod logging;
+mod utils;
+
+pub use app::{CaterpieApp, InputState, UiContext};
+pub use engine::Engine;
@@ -0,0 +1,143 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+#[cfg(not(all(target_os = "android", feature = "android")))]
+use env_logger::Env;
+use log::Level;
+
+// RUST_LOG overrides this, e.g. RUST_LOG=caterpie::frame=debug
+pub const DEFAULT_FILTER: &str = "warn,caterpie=info";
+
+pub(crate) const SWAPCHAIN: &str = "caterpie::swapchain";
+pub(crate) const UPLOAD: &str = "caterpie::upload";
+pub(crate) const FRAME: &str = "caterpie::frame";
+// Messages forwarded from the validation layer
+pub(crate) const VULKAN: &str = "caterpie::vulkan";
+
+// Records kept for the on screen log view, the oldest are dropped first
+pub const LOG_HISTORY: usize = 256;
+
+static HISTORY: Mutex<VecDeque<LogRecord>> = Mutex::new(VecDeque::new());
+
+// A copy of a record that made it through the filter
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+// The last `count` records, oldest first. Only filled in once `init` ran on
+// the desktop, on Android everything only goes to logcat
+pub fn recent(count: usize) -> Vec<LogRecord> {
+    let history = HISTORY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    history
+        .iter()
+        .skip(history.len().saturating_sub(count))
+        .cloned()
+        .collect()
+}
+
+#[cfg(not(all(target_os = "android", feature = "android")))]
+fn remember(record: &log::Record) {
+    let mut history = HISTORY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if history.len() == LOG_HISTORY {
+        history.pop_front();
+    }
+    history.push_back(LogRecord {
+        level: record.level(),
+        target: record.target().to_string(),
+        message: record.args().to_string(),
+    });
+}
+
+// Passes everything on to env_logger and keeps what it lets through
+#[cfg(not(all(target_os = "android", feature = "android")))]
+struct TeeLogger {
+    inner: env_logger::Logger,
+}
+
+#[cfg(not(all(target_os = "android", feature = "android")))]
+impl log::Log for TeeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.inner.matches(record) {
+            remember(record);
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Keep alive for as long as spans should be recorded
+pub struct LoggingGuard {
+    #[cfg(feature = "trace-chrome")]
+    _chrome: tracing_chrome::FlushGuard,
+}
+
+// stderr goes nowhere on Android, the filter string still applies
+#[cfg(all(target_os = "android", feature = "android"))]
+fn init_logcat() {
+    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_FILTER.to_string());
+    android_logger::init_once(
+        android_logger::Config::default()
+            .with_tag("caterpie")
+            .with_max_level(log::LevelFilter::Trace)
+            .with_filter(android_logger::FilterBuilder::new().parse(&filter).build()),
+    );
+}
+
+pub fn init() -> LoggingGuard {
+    #[cfg(all(target_os = "android", feature = "android"))]
+    init_logcat();
+    #[cfg(not(all(target_os = "android", feature = "android")))]
+    {
+        let logger =
+            env_logger::Builder::from_env(Env::default().default_filter_or(DEFAULT_FILTER)).build();
+        let max_level = logger.filter();
+        if log::set_boxed_logger(Box::new(TeeLogger { inner: logger })).is_ok() {
+            log::set_max_level(max_level);
+        }
+    }
+
+    #[cfg(feature = "trace-chrome")]
+    let (chrome_layer, chrome_guard) = tracing_chrome::ChromeLayerBuilder::new()
+        .include_args(true)
+        .build();
+    #[cfg(any(feature = "trace-chrome", feature = "profiling"))]
+    {
+        use tracing_subscriber::prelude::*;
+
+        let registry = tracing_subscriber::registry();
+        // Starts the Tracy client, which accepts connections from then on
+        #[cfg(feature = "profiling")]
+        let registry = registry.with(tracing_tracy::TracyLayer::default());
+        #[cfg(feature = "trace-chrome")]
+        let registry = registry.with(chrome_layer);
+        let _ = registry.try_init();
+    }
+
+    LoggingGuard {
+        #[cfg(feature = "trace-chrome")]
+        _chrome: chrome_guard,
+    }
+}
+
+// Opens a CPU timeline span for the rest of the scope when the `tracing`
+// feature is enabled, otherwise compiles to nothing
+macro_rules! span {
+    ($name:literal) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($name).entered();
+    };
+}
+pub(crate) use span;
@@ -1,17 +1,236 @@
-use app::App;
-use log::{info, LevelFilter};
+use std::{panic, path::PathBuf, process};
+
+use caterpie::{
+    engine::{
+        config::{DisplayMode, EngineConfig},
+        scene::RenderObject,
+        RecordOverflow,
+    },
+    Engine,
+};
+use cgmath::{Matrix4, SquareMatrix};
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use log::{error, info, warn};
+use viewer::{RecordOptions, Viewer};
 use winit::event_loop::EventLoop;
 
-mod app;
-mod engine;
-mod utils;
+mod viewer;
+
+const SCREENSHOT_FILE: &str = "caterpie-screenshot.png";
+
+// Command line options, applied on top of caterpie.toml and CATERPIE_* variables
+#[derive(Debug, Parser)]
+#[command(version, about = "Vulkan model viewer")]
+struct Args {
+    /// OBJ model to display
+    model: Option<PathBuf>,
+    /// PNG texture for the model
+    #[arg(long, value_name = "PATH")]
+    texture: Option<PathBuf>,
+    /// Window width in pixels
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+    /// Window height in pixels
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+    /// Wait for vertical blank before presenting
+    #[arg(long, overrides_with = "no_vsync")]
+    vsync: bool,
+    /// Present as fast as possible
+    #[arg(long)]
+    no_vsync: bool,
+    /// Samples per pixel
+    #[arg(long, value_name = "N")]
+    msaa: Option<u32>,
+    /// Enable the Vulkan validation layer
+    #[arg(long)]
+    validation: bool,
+    /// Draw the model over the desktop instead of a black background
+    #[arg(long)]
+    transparent: bool,
+    /// Exclusive fullscreen in a video mode like 1920x1080@144
+    #[arg(long, value_name = "MODE")]
+    fullscreen: Option<DisplayMode>,
+    /// Render N frames, print the average frame time and exit
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    benchmark: Option<u32>,
+    /// Also write the benchmark results as JSON to PATH
+    #[arg(long, value_name = "PATH", requires = "benchmark")]
+    benchmark_json: Option<PathBuf>,
+    /// Add N thousand cubes and spheres below the model, as many as fit
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    stress: Option<u32>,
+    /// Save the Nth frame to caterpie-screenshot.png
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    screenshot_after: Option<u32>,
+    /// Render a single frame without a window, save it as a PNG and exit
+    #[arg(long, value_name = "PATH")]
+    render_to: Option<PathBuf>,
+    /// Write every frame to DIR as numbered PNGs
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+    /// Exit after recording N frames
+    #[arg(long, value_name = "N", requires = "record", value_parser = clap::value_parser!(u32).range(1..))]
+    frames: Option<u32>,
+    /// Step the animation as if running at N fps while recording
+    #[arg(long, value_name = "N", requires = "record", default_value_t = 60, value_parser = clap::value_parser!(u32).range(1..))]
+    record_fps: u32,
+    /// Drop frames the PNG writer can't keep up with instead of waiting
+    #[arg(long, requires = "record")]
+    record_drop: bool,
+}
+
+impl Args {
+    fn apply(&self, config: &mut EngineConfig) {
+        if let Some(model) = &self.model {
+            config.model_path = model.clone();
+        }
+        if let Some(texture) = &self.texture {
+            config.texture_path = texture.clone();
+        }
+        if let Some(width) = self.width {
+            config.window_width = width;
+        }
+        if let Some(height) = self.height {
+            config.window_height = height;
+        }
+        if self.vsync {
+            config.vsync = true;
+        }
+        if self.no_vsync {
+            config.vsync = false;
+        }
+        if let Some(msaa) = self.msaa {
+            config.msaa_samples = msaa;
+        }
+        if self.validation {
+            config.validation = true;
+        }
+        if self.transparent {
+            config.transparent_window = true;
+        }
+        if let Some(display_mode) = self.fullscreen {
+            config.exclusive_fullscreen = Some(display_mode);
+        }
+    }
+
+    // Fails before any window is opened, with clap's usage line and exit code
+    fn validate(&self, config: &EngineConfig) {
+        for (what, path) in [
+            ("model", &config.model_path),
+            ("texture", &config.texture_path),
+        ] {
+            if !path.is_file() {
+                Args::command()
+                    .error(
+                        ErrorKind::ValueValidation,
+                        format!("{what} {} does not exist", path.display()),
+                    )
+                    .exit();
+            }
+        }
+
+        let Some(benchmark) = self.benchmark else {
+            return;
+        };
+        if config.vsync {
+            warn!("Benchmarking with vsync caps the results at the refresh rate, pass --no-vsync");
+        }
+        if let Some(fps_limit) = config.fps_limit.filter(|fps_limit| *fps_limit > 0) {
+            warn!("Benchmarking with an fps limit of {fps_limit}");
+        }
+        if self.screenshot_after.is_some_and(|frame| frame > benchmark) {
+            warn!(
+                "--screenshot-after is past the end of the benchmark, no screenshot will be taken"
+            );
+        }
+    }
+}
+
+// Once the renderer is up a panic waits for the GPU, leaves a crash log and
+// aborts rather than unwinding past live Vulkan work. Panics during init
+// still unwind into the engine's own diagnostics
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let Some(written) = Engine::emergency_shutdown(&info.to_string()) else {
+            return;
+        };
+        match written {
+            Ok(path) => error!("Crash log written to {}", path.display()),
+            Err(err) => error!("Failed to write the crash log: {err}"),
+        }
+        log::logger().flush();
+        process::abort();
+    }));
+}
 
 fn main() {
-    let mut app = App::default();
+    let args = Args::parse();
+    let _logging = caterpie::logging::init();
+    install_panic_hook();
+
+    let mut config = EngineConfig::load_layers();
+    args.apply(&mut config);
+    let config = config.finish();
+    args.validate(&config);
+
+    if let Some(path) = &args.render_to {
+        let scene = [RenderObject::new(Matrix4::identity())];
+        let (width, height) = (config.window_width, config.window_height);
+        match Engine::render_offscreen(config, width, height, &scene)
+            .and_then(|image| image.write_png(path))
+        {
+            Ok(()) => info!("Saved {}", path.display()),
+            Err(err) => {
+                error!("Offscreen rendering failed: {err}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let viewer = Viewer::new(
+        args.benchmark,
+        args.screenshot_after
+            .map(|frame| (frame, PathBuf::from(SCREENSHOT_FILE))),
+    )
+    .with_stress(args.stress.map_or(0, |thousands| thousands as usize * 1000))
+    .with_benchmark_json(args.benchmark_json.clone())
+    .with_recording(args.record.clone().map(|dir| RecordOptions {
+        dir,
+        frames: args.frames,
+        fps: args.record_fps,
+        overflow: if args.record_drop {
+            RecordOverflow::Drop
+        } else {
+            RecordOverflow::Block
+        },
+    }));
     let event_loop = EventLoop::new().unwrap();
-    let _ = env_logger::builder().filter_level(LevelFilter::Debug).try_init();
-    
-    event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
-    event_loop.run_app(&mut app).unwrap();
-    println!("Hello, world!");
+    Engine::run(event_loop, config, viewer).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn arguments_override_the_file_and_keep_what_they_dont_set() {
+        let mut config = EngineConfig::default();
+        config
+            .apply_file(
+                "[window]\nwidth = 800\nheight = 600\n[renderer]\nvsync = true",
+                Path::new(""),
+            )
+            .unwrap();
+        let args = Args::try_parse_from(["caterpie", "--width", "640", "--no-vsync"]).unwrap();
+        args.apply(&mut config);
+        assert_eq!((config.window_width, config.window_height), (640, 600));
+        assert!(!config.vsync);
+    }
 }
@@ -0,0 +1,54 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use png::{ColorType, Transformations};
+
+// Compiled into the binary so it works without the resources directory
+pub const ICON_PNG: &[u8] = include_bytes!("../resources/icon.png");
+// The error pipelines' shaders, they have to load when nothing else does
+pub const ERROR_VERTEX_SPV: &[u8] = include_bytes!("../assets/error_vertices.spv");
+pub const ERROR_FRAGMENT_SPV: &[u8] = include_bytes!("../assets/error.spv");
+
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn write_png(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|err| err.to_string())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|err| err.to_string())
+    }
+}
+
+// Expands palette, grayscale and 16 bit images to 8 bit RGBA
+pub fn decode_png_rgba(bytes: &[u8]) -> Result<RgbaImage, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(Transformations::normalize_to_color8() | Transformations::ALPHA);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader
+        .next_frame(&mut buffer)
+        .map_err(|err| err.to_string())?;
+    buffer.truncate(frame.buffer_size());
+
+    let pixels = match frame.color_type {
+        ColorType::Rgba => buffer,
+        ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        color_type => return Err(format!("Unexpected {color_type:?} output")),
+    };
+    Ok(RgbaImage {
+        width: frame.width,
+        height: frame.height,
+        pixels,
+    })
+}
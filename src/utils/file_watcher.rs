@@ -0,0 +1,62 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+// How often modification times are looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Polls the modification times of a set of files. A change is reported once
+// the time has stayed the same for a whole poll, so a file that is still
+// being written isn't read half way through
+pub struct FileWatcher {
+    // Last seen modification time, and whether it differs from the one
+    // reported before
+    files: HashMap<PathBuf, (Option<SystemTime>, bool)>,
+    last_poll: Instant,
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        FileWatcher {
+            files: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl FileWatcher {
+    // The files of `paths` that changed since they were first watched or last
+    // reported. Files no longer in `paths` stop being watched
+    pub fn poll(&mut self, paths: &[&Path]) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+        self.files.retain(|path, _| paths.contains(&path.as_path()));
+        let mut changed = Vec::new();
+        for path in paths {
+            let time = modified(path);
+            let Some((seen, pending)) = self.files.get_mut(*path) else {
+                self.files.insert(path.to_path_buf(), (time, false));
+                continue;
+            };
+            if *seen != time {
+                *seen = time;
+                *pending = true;
+            } else if *pending && time.is_some() {
+                *pending = false;
+                changed.push(path.to_path_buf());
+            }
+        }
+        changed
+    }
+}
@@ -1,18 +1,52 @@
-use std::{fs, path::Path};
+use std::{fs, io, path::Path};
 
-use log::error;
+#[cfg(all(target_os = "android", feature = "android"))]
+static ANDROID_APP: std::sync::OnceLock<winit::platform::android::activity::AndroidApp> =
+    std::sync::OnceLock::new();
 
-pub fn read_file<P: AsRef<Path> + std::fmt::Debug + ToString>(path: &P) -> Result<Vec<u8>, &'static str>
-{
-    let file = fs::read(path.to_string());
-    match file {
-        Ok(file_contents) => Ok(file_contents),
-        Err(error_msg) => {
-            error!(
-                "Failed to read the contents of path {:?}, with following error message: '{:?}'",
-                path, error_msg
-            );
-            Err("Failed to read file")
-        }
+// Asset reads go through the APK's AssetManager once this is set
+#[cfg(all(target_os = "android", feature = "android"))]
+pub fn set_android_app(app: winit::platform::android::activity::AndroidApp) {
+    let _ = ANDROID_APP.set(app);
+}
+
+// The APK packages src/ as its asset directory, so "src/assets/x.spv" and
+// "./caterpie.toml" become "assets/x.spv" and "caterpie.toml"
+#[cfg(all(target_os = "android", feature = "android"))]
+fn read_apk_asset(
+    app: &winit::platform::android::activity::AndroidApp,
+    path: &Path,
+) -> io::Result<Vec<u8>> {
+    use std::{ffi::CString, io::Read, path::Component};
+
+    let name = path
+        .strip_prefix("src")
+        .unwrap_or(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect::<Vec<&str>>()
+        .join("/");
+    let c_name = CString::new(name.as_str())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut asset = app.asset_manager().open(&c_name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No asset {name} in the APK"),
+        )
+    })?;
+    let mut contents = Vec::new();
+    asset.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+// Reads from the file system, or from the APK on Android
+pub fn read_asset(path: &Path) -> io::Result<Vec<u8>> {
+    #[cfg(all(target_os = "android", feature = "android"))]
+    if let Some(app) = ANDROID_APP.get() {
+        return read_apk_asset(app, path);
     }
+    fs::read(path)
 }
@@ -1 +1,3 @@
+pub mod embedded;
+pub mod file_watcher;
 pub mod io;
@@ -0,0 +1,811 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use ash::vk::PresentModeKHR;
+use caterpie::{
+    engine::{
+        animation::{
+            AnimationClip, AnimationTarget, Animator, Interpolation, Keyframes, PlaybackMode,
+            Transform, TransformTrack, WeightsTrack,
+        },
+        debug_palette::DebugPalette,
+        gizmo::GizmoTarget,
+        material::Material,
+        mesh::{MeshSource, MorphTarget, Primitive, Vertex},
+        scene::{Camera, ObjectId, PointLight, RenderObject, Scene},
+        skinning::{Joint, Skeleton},
+        viewport::ViewId,
+        PipelineOptions, RecordOverflow, RenderQueue, ShadingPath,
+    },
+    CaterpieApp, Engine, InputState, UiContext,
+};
+use cgmath::{point3, vec2, vec3, Deg, Matrix4, Quaternion, Rad, Rotation3, SquareMatrix};
+use log::{info, warn, Level};
+use winit::{event::MouseButton, keyboard::KeyCode};
+
+// Written with Ctrl+S and read back with Ctrl+O
+const SCENE_FILE: &str = "caterpie-scene.ron";
+
+// Cycled through with the L key
+const FPS_LIMIT_PRESETS: [Option<u32>; 4] = [None, Some(30), Some(60), Some(144)];
+const BLOOM_INTENSITY_STEP: f32 = 0.05;
+const SSAO_RADIUS_STEP: f32 = 0.1;
+const RENDER_SCALE_PRESETS: [Option<f32>; 4] = [None, Some(1.0), Some(0.75), Some(0.5)];
+// Added with the K key, enough to tell the shading paths apart
+const LIGHT_RING_COUNT: usize = 200;
+// Seconds for the animated light to circle the model once
+const ORBIT_PERIOD: f32 = 6.0;
+const ORBIT_KEYS: usize = 16;
+// The skinned tube standing next to the model, bent by a chain of joints
+const TENTACLE_JOINTS: usize = 3;
+const TENTACLE_LENGTH: f32 = 0.9;
+const TENTACLE_RADIUS: f32 = 0.06;
+const TENTACLE_RINGS: u32 = 24;
+const TENTACLE_SEGMENTS: u32 = 12;
+// Spheres behind the model, metalness rising from bottom to top and
+// roughness from left to right
+const SPHERE_GRID: usize = 5;
+const SPHERE_SPACING: f32 = 0.4;
+const SPHERE_RADIUS: f32 = 0.15;
+// The --stress field below the model, square chunks of cubes and spheres
+// sharing a few hundred materials
+const STRESS_CHUNK: usize = 16;
+const STRESS_SPACING: f32 = 0.5;
+const STRESS_MATERIALS: usize = 256;
+const STRESS_HEIGHT: f32 = -1.5;
+// What the benchmark output measures the frame time against
+const BENCHMARK_TARGET_FPS: f64 = 60.0;
+// Share of the frame the CPU may spend on it, the rest is left to the GPU
+// working on the frame before
+const BENCHMARK_CPU_SHARE: f64 = 0.5;
+// Where Ctrl+D puts the copies of the selected objects
+const DUPLICATE_OFFSET: [f32; 3] = [0.5, 0.0, 0.0];
+
+// A dropped folder streams in every PNG and KTX2 file inside it, in name
+// order
+fn dropped_textures(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut textures = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            textures.push(path.clone());
+            continue;
+        }
+        let mut entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension().is_some_and(|ext| {
+                        ext.eq_ignore_ascii_case("png") || ext.eq_ignore_ascii_case("ktx2")
+                    })
+                })
+                .collect::<Vec<PathBuf>>(),
+            Err(err) => {
+                warn!("Failed to read {}: {err}", path.display());
+                continue;
+            }
+        };
+        entries.sort();
+        textures.extend(entries);
+    }
+    textures
+}
+
+fn next_fps_limit(current: Option<u32>) -> Option<u32> {
+    let index = FPS_LIMIT_PRESETS
+        .iter()
+        .position(|preset| *preset == current)
+        .map_or(0, |index| index + 1);
+    FPS_LIMIT_PRESETS[index % FPS_LIMIT_PRESETS.len()]
+}
+
+// Lights spiralling around the model, colored by their angle
+fn light_ring() -> impl Iterator<Item = PointLight> {
+    (0..LIGHT_RING_COUNT).map(|index| {
+        let t = index as f32 / LIGHT_RING_COUNT as f32;
+        let angle = t * std::f32::consts::TAU * 5.0;
+        let channel = |offset: f32| 0.5 + 0.5 * (angle / 5.0 + offset).cos();
+        PointLight {
+            position: point3(1.5 * angle.cos(), 1.5 * angle.sin(), 2.0 * t - 1.0),
+            color: [channel(0.0), channel(2.1), channel(4.2)],
+            intensity: 0.3,
+            radius: 1.0,
+        }
+    })
+}
+
+// Always the first light, circled around the model by `orbit_clip`
+fn orbit_light() -> PointLight {
+    PointLight {
+        position: point3(2.0, 0.0, 1.0),
+        color: [1.0, 0.85, 0.6],
+        intensity: 1.0,
+        radius: 4.0,
+    }
+}
+
+fn orbit_clip() -> AnimationClip {
+    let keys = (0..=ORBIT_KEYS).map(|key| {
+        let t = key as f32 / ORBIT_KEYS as f32;
+        let angle = t * std::f32::consts::TAU;
+        (
+            t * ORBIT_PERIOD,
+            vec3(2.0 * angle.cos(), 2.0 * angle.sin(), 1.0),
+        )
+    });
+    let mut track = TransformTrack::new(AnimationTarget::Light(0));
+    track.translation = Some(Keyframes::new(keys, Interpolation::Cubic));
+    AnimationClip {
+        tracks: vec![track],
+        ..AnimationClip::default()
+    }
+}
+
+// An open tube along z, each ring weighted between the two joints around it,
+// with a morph target that swells its middle
+fn tentacle() -> (Vec<Vertex>, Vec<u32>, MorphTarget) {
+    let joint_spacing = TENTACLE_LENGTH / TENTACLE_JOINTS as f32;
+    let mut vertices = Vec::new();
+    let mut bulge = MorphTarget::default();
+    for ring in 0..=TENTACLE_RINGS {
+        let v = ring as f32 / TENTACLE_RINGS as f32;
+        let height = v * TENTACLE_LENGTH;
+        let along = (height / joint_spacing).min(TENTACLE_JOINTS as f32 - 1.0);
+        let lower = along.floor() as u16;
+        let blend = along.fract();
+        let upper = (lower + 1).min(TENTACLE_JOINTS as u16 - 1);
+        for segment in 0..=TENTACLE_SEGMENTS {
+            let u = segment as f32 / TENTACLE_SEGMENTS as f32;
+            let angle = u * std::f32::consts::TAU;
+            let normal = vec3(angle.cos(), angle.sin(), 0.0);
+            let position = normal * TENTACLE_RADIUS + vec3(0.0, 0.0, height);
+            vertices.push(
+                Vertex::new(position, vec3(1.0, 1.0, 1.0), vec2(u, 1.0 - v))
+                    .with_normal(normal)
+                    .with_influences(&[(lower, 1.0 - blend), (upper, blend)]),
+            );
+            let swell = (v * std::f32::consts::PI).sin();
+            bulge.positions.push(normal * TENTACLE_RADIUS * swell);
+            bulge.normals.push(vec3(0.0, 0.0, 0.0));
+        }
+    }
+    let mut indices = Vec::new();
+    for ring in 0..TENTACLE_RINGS {
+        for segment in 0..TENTACLE_SEGMENTS {
+            let a = ring * (TENTACLE_SEGMENTS + 1) + segment;
+            let b = a + TENTACLE_SEGMENTS + 1;
+            indices.extend([a, a + 1, b, a + 1, b + 1, b]);
+        }
+    }
+    (vertices, indices, bulge)
+}
+
+// A chain up the tentacle, every joint a third of the way above its parent
+fn tentacle_skeleton() -> Skeleton {
+    let joint_spacing = TENTACLE_LENGTH / TENTACLE_JOINTS as f32;
+    let joints = (0..TENTACLE_JOINTS)
+        .map(|joint| {
+            let rest = Transform {
+                translation: vec3(0.0, 0.0, if joint == 0 { 0.0 } else { joint_spacing }),
+                ..Transform::default()
+            };
+            Joint::new(joint.checked_sub(1), rest)
+        })
+        .collect();
+    let mut skeleton = Skeleton::new(joints).expect("Tentacle joints are in order");
+    skeleton.bind_rest_pose();
+    skeleton
+}
+
+// Sways every joint above the root back and forth around x, swelling the
+// tentacle as it bends
+fn tentacle_clip(object: ObjectId) -> AnimationClip {
+    let tracks = (1..TENTACLE_JOINTS)
+        .map(|joint| {
+            let mut track = TransformTrack::new(AnimationTarget::Joint { object, joint });
+            track.rotation = Some(Keyframes::new(
+                [
+                    (0.0, Quaternion::from_angle_x(Deg(-35.0))),
+                    (1.5, Quaternion::from_angle_x(Deg(35.0))),
+                ],
+                Interpolation::Linear,
+            ));
+            track
+        })
+        .collect();
+    let swell = WeightsTrack {
+        object,
+        weights: Keyframes::new([(0.0, vec![0.0]), (1.5, vec![1.0])], Interpolation::Linear),
+    };
+    AnimationClip {
+        tracks,
+        weight_tracks: vec![swell],
+    }
+}
+
+fn next_render_scale(current: Option<f32>) -> Option<f32> {
+    let index = RENDER_SCALE_PRESETS
+        .iter()
+        .position(|preset| *preset == current)
+        .map_or(0, |index| index + 1);
+    RENDER_SCALE_PRESETS[index % RENDER_SCALE_PRESETS.len()]
+}
+
+// Red-brown spheres in a SPHERE_GRID square in the y-z plane behind the
+// model
+fn sphere_grid(engine: &mut Engine) {
+    let sphere = Primitive::UvSphere {
+        radius: SPHERE_RADIUS,
+        slices: 32,
+        stacks: 16,
+    };
+    let offset = (SPHERE_GRID - 1) as f32 * SPHERE_SPACING / 2.0;
+    for row in 0..SPHERE_GRID {
+        for column in 0..SPHERE_GRID {
+            let step = |index: usize| index as f32 / (SPHERE_GRID - 1) as f32;
+            let material = Material::new([0.9, 0.35, 0.2], step(row), step(column).max(0.05));
+            let position = vec3(
+                -1.2,
+                column as f32 * SPHERE_SPACING - offset,
+                row as f32 * SPHERE_SPACING - offset,
+            );
+            let Some(object) = engine.add_object(
+                sphere,
+                RenderObject::new(Matrix4::from_translation(position)),
+            ) else {
+                return;
+            };
+            match engine.create_material(&material) {
+                Ok(material) => {
+                    engine.set_object_material(object, Some(material));
+                }
+                Err(err) => warn!("Sphere drawn with the default material: {err}"),
+            }
+        }
+    }
+}
+
+// `count` cubes and spheres in chunks of STRESS_CHUNK by STRESS_CHUNK laid
+// out in a square, each turned, scaled and colored at random. Stops at the
+// object limit, returns how many were added
+fn stress_scene(engine: &mut Engine, count: usize) -> usize {
+    let mut rng = engine.rng("stress");
+    let mut materials = Vec::new();
+    for _ in 0..STRESS_MATERIALS.min(count) {
+        let color = [rng.next_f32(), rng.next_f32(), rng.next_f32()];
+        let material = Material::new(color, rng.next_f32(), rng.range_f32(0.05..1.0));
+        match engine.create_material(&material) {
+            Ok(material) => materials.push(material),
+            Err(err) => {
+                warn!("Stress objects share the materials created so far: {err}");
+                break;
+            }
+        }
+    }
+    let cube = Primitive::Cube { size: 0.3 };
+    let sphere = Primitive::UvSphere {
+        radius: 0.15,
+        slices: 16,
+        stacks: 8,
+    };
+    let chunk_objects = STRESS_CHUNK * STRESS_CHUNK;
+    let side = (count.div_ceil(chunk_objects) as f32).sqrt().ceil() as usize;
+    let offset = (side * STRESS_CHUNK - 1) as f32 * STRESS_SPACING / 2.0;
+    for index in 0..count {
+        let (chunk, within) = (index / chunk_objects, index % chunk_objects);
+        let column = chunk % side * STRESS_CHUNK + within % STRESS_CHUNK;
+        let row = chunk / side * STRESS_CHUNK + within / STRESS_CHUNK;
+        let position = vec3(
+            column as f32 * STRESS_SPACING - offset,
+            row as f32 * STRESS_SPACING - offset,
+            STRESS_HEIGHT,
+        );
+        let rotation = Matrix4::from_axis_angle(
+            rng.unit_vector(),
+            Rad(rng.range_f32(0.0..std::f32::consts::TAU)),
+        );
+        let transform = Matrix4::from_translation(position)
+            * rotation
+            * Matrix4::from_scale(rng.range_f32(0.5..1.5));
+        let mesh = if rng.next_bool() { cube } else { sphere };
+        let Some(object) = engine.add_object(mesh, RenderObject::new(transform)) else {
+            return index;
+        };
+        if !materials.is_empty() {
+            engine.set_object_material(object, Some(materials[index % materials.len()].clone()));
+        }
+    }
+    count
+}
+
+// The model viewer demo: L cycles the fps limit, V the viewport layout and N
+// opens a second window looking at the scene from another angle and B toggles
+// the bounding boxes. R cycles the render scale between adaptive and fixed
+// presets and [ ] change the bloom intensity. A toggles ambient occlusion and
+// - = change its radius. An animated light orbits the model and a skinned
+// tentacle sways and swells next to it, a grid of spheres behind it runs
+// through the material's metalness and roughness. K adds or
+// removes a ring of point lights and G switches between forward and deferred
+// shading. Ctrl+S and Ctrl+O save and
+// load the scene. A left click on a light or an object puts the axis gizmo on
+// it, objects are found with the ID buffer when picking is enabled and by
+// casting a ray otherwise, dragging an axis moves it along. Shift+drag
+// selects the objects in a rectangle, with Ctrl held adding to the selection.
+// H hides the selection, Delete removes it and Ctrl+D duplicates it. T hides
+// the gizmos and D tints objects by their level of detail, P switches to the
+// next debug palette. In debug builds F9 panics mid-frame to try out the
+// crash log
+// A --record run, every frame written to `dir` as if the frame rate was `fps`
+pub struct RecordOptions {
+    pub dir: PathBuf,
+    // Exits once this many frames are written
+    pub frames: Option<u32>,
+    pub fps: u32,
+    pub overflow: RecordOverflow,
+}
+
+#[derive(Default)]
+pub struct Viewer {
+    // Frames to render before printing the timings and exiting
+    benchmark: Option<u32>,
+    // Where the timings are also written as JSON
+    benchmark_json: Option<PathBuf>,
+    // Objects of the stress field to add below the model
+    stress: usize,
+    // Frame number and file of a one off screenshot
+    screenshot_after: Option<(u32, PathBuf)>,
+    frames: u32,
+    benchmark_start: Option<Instant>,
+    // Milliseconds the benchmark frames took on the CPU, summed
+    benchmark_cpu_time: f64,
+    // Where the cursor was pressed for a rectangle selection
+    selection_drag: Option<[f32; 2]>,
+    record: Option<RecordOptions>,
+}
+
+impl Viewer {
+    pub fn new(benchmark: Option<u32>, screenshot_after: Option<(u32, PathBuf)>) -> Self {
+        Self {
+            benchmark,
+            screenshot_after,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_stress(mut self, stress: usize) -> Self {
+        self.stress = stress;
+        self
+    }
+
+    pub fn with_benchmark_json(mut self, path: Option<PathBuf>) -> Self {
+        self.benchmark_json = path;
+        self
+    }
+
+    pub fn with_recording(mut self, record: Option<RecordOptions>) -> Self {
+        self.record = record;
+        self
+    }
+
+    fn count_frame(&mut self, engine: &mut Engine) {
+        self.frames += 1;
+        if let Some((frame, path)) = &self.screenshot_after {
+            if *frame == self.frames {
+                engine.request_screenshot(path.clone());
+            }
+        }
+
+        let Some(benchmark) = self.benchmark else {
+            return;
+        };
+        if self.frames == 1 {
+            self.benchmark_start = Some(Instant::now());
+        } else {
+            // The frame before this one's
+            self.benchmark_cpu_time += f64::from(engine.cpu_frame_time());
+        }
+        // Runs before the frame is drawn, so the benchmark frames are done
+        // once the one after them starts
+        if self.frames <= benchmark {
+            return;
+        }
+        let elapsed = self
+            .benchmark_start
+            .map_or(0.0, |start| start.elapsed().as_secs_f64());
+        let frame_time = elapsed / benchmark as f64;
+        let target_frame_time = 1.0 / BENCHMARK_TARGET_FPS;
+        println!(
+            "{benchmark} frames, {:.3} ms per frame, {:.1} fps (target {:.3} ms)",
+            frame_time * 1000.0,
+            1.0 / frame_time.max(f64::EPSILON),
+            target_frame_time * 1000.0
+        );
+        println!(
+            "{:.3} ms per frame on the CPU (target {:.3} ms), objects culled on the {}",
+            self.benchmark_cpu_time / benchmark as f64,
+            target_frame_time * BENCHMARK_CPU_SHARE * 1000.0,
+            if engine.gpu_culling() { "GPU" } else { "CPU" }
+        );
+        let present = engine.present_stats();
+        let optional = |value: Option<f32>| value.map_or("n/a".to_string(), |v| format!("{v:.2}"));
+        println!(
+            "{} missed vsyncs over {} presents, queue depth {}, display latency {} ms ({:?})",
+            present.missed_vsyncs,
+            present.frames,
+            optional(present.average_queue_depth),
+            optional(present.display_latency_ms),
+            present.source
+        );
+        let culling = engine.culling_stats();
+        println!(
+            "{} objects, {} of {} entries visible, {} triangles",
+            engine.scene().objects.len(),
+            culling.visible,
+            culling.total,
+            culling.triangles
+        );
+        if let Some(path) = &self.benchmark_json {
+            match std::fs::write(path, self.benchmark_json(engine, frame_time)) {
+                Ok(()) => info!("Benchmark results written to {}", path.display()),
+                Err(err) => warn!("Failed to write {}: {err}", path.display()),
+            }
+        }
+        engine.request_exit();
+    }
+
+    // Written by hand, the viewer doesn't pull in a JSON library for it.
+    // Missing numbers are null
+    fn benchmark_json(&self, engine: &Engine, frame_time: f64) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        let target_frame_time = 1.0 / BENCHMARK_TARGET_FPS;
+        let benchmark = self.benchmark.unwrap_or(0).max(1);
+        let cpu_frame_time = self.benchmark_cpu_time / f64::from(benchmark);
+        let cpu_target_frame_time = target_frame_time * BENCHMARK_CPU_SHARE * 1000.0;
+        let culling = engine.culling_stats();
+        let present = engine.present_stats();
+        let fields = [
+            ("frames", self.benchmark.unwrap_or(0).to_string()),
+            ("frame_time_ms", format!("{:.3}", frame_time * 1000.0)),
+            ("fps", format!("{:.1}", 1.0 / frame_time.max(f64::EPSILON))),
+            (
+                "target_frame_time_ms",
+                format!("{:.3}", target_frame_time * 1000.0),
+            ),
+            (
+                "within_target",
+                (frame_time <= target_frame_time).to_string(),
+            ),
+            ("cpu_frame_time_ms", format!("{cpu_frame_time:.3}")),
+            (
+                "cpu_target_frame_time_ms",
+                format!("{cpu_target_frame_time:.3}"),
+            ),
+            (
+                "within_cpu_target",
+                (cpu_frame_time <= cpu_target_frame_time).to_string(),
+            ),
+            ("gpu_culling", engine.gpu_culling().to_string()),
+            (
+                "gpu_frame_time_ms",
+                optional(engine.gpu_frame_time().map(|time| format!("{time:.3}"))),
+            ),
+            ("objects", engine.scene().objects.len().to_string()),
+            ("stress_objects", self.stress.to_string()),
+            ("entries", culling.total.to_string()),
+            ("visible_entries", culling.visible.to_string()),
+            (
+                "gpu_visible_entries",
+                optional(culling.gpu_visible.map(|visible| visible.to_string())),
+            ),
+            ("triangles", culling.triangles.to_string()),
+            ("missed_vsyncs", present.missed_vsyncs.to_string()),
+            ("presents", present.frames.to_string()),
+        ];
+        let fields = fields
+            .iter()
+            .map(|(name, value)| format!("  \"{name}\": {value}"))
+            .collect::<Vec<String>>();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+}
+
+impl CaterpieApp for Viewer {
+    fn setup(&mut self, engine: &mut Engine) {
+        engine.add_object(MeshSource::Model, RenderObject::new(Matrix4::identity()));
+        engine.set_show_gizmos(true);
+        engine.add_light(orbit_light());
+        engine.add_animator(Animator::new(orbit_clip(), PlaybackMode::Loop));
+        let (vertices, indices, bulge) = tentacle();
+        let tentacle = engine.add_object(
+            MeshSource::Geometry {
+                vertices,
+                indices,
+                morph_targets: vec![bulge],
+            },
+            RenderObject::new(Matrix4::from_translation(vec3(1.2, 0.0, 0.0))),
+        );
+        if let Some(tentacle) = tentacle {
+            engine.set_object_skeleton(tentacle, tentacle_skeleton());
+            engine.add_animator(Animator::new(
+                tentacle_clip(tentacle),
+                PlaybackMode::PingPong,
+            ));
+        }
+        sphere_grid(engine);
+        if self.stress > 0 {
+            let added = stress_scene(engine, self.stress);
+            if added < self.stress {
+                warn!("Only {added} of {} stress objects fit", self.stress);
+            }
+            self.stress = added;
+        }
+        if let Some(record) = &self.record {
+            engine.set_fixed_frame_time(Some(Duration::from_secs_f64(1.0 / f64::from(record.fps))));
+            if let Err(err) = engine.start_recording(&record.dir, record.frames, record.overflow) {
+                warn!("Can't record to {}: {err}", record.dir.display());
+            }
+        }
+    }
+
+    fn update(&mut self, engine: &mut Engine, _dt: f32, input: &InputState) {
+        self.count_frame(engine);
+        // The engine stops recording by itself after the last frame
+        if self
+            .record
+            .as_ref()
+            .is_some_and(|record| record.frames.is_some())
+            && !engine.is_recording()
+        {
+            engine.request_exit();
+        }
+        if input.just_pressed(KeyCode::KeyL) {
+            engine.set_fps_limit(next_fps_limit(engine.fps_limit()));
+        }
+        if input.just_pressed(KeyCode::KeyV) {
+            if let Some(layout) = engine.viewport_layout(ViewId::PRIMARY) {
+                engine.set_viewport_layout(ViewId::PRIMARY, layout.next());
+            }
+        }
+        if input.just_pressed(KeyCode::KeyN) {
+            engine.request_view(Camera {
+                eye: point3(0.0, -3.0, 1.5),
+                ..Camera::default()
+            });
+        }
+        let ctrl = input.is_held(KeyCode::ControlLeft) || input.is_held(KeyCode::ControlRight);
+        if ctrl && input.just_pressed(KeyCode::KeyS) {
+            match engine.scene().save(Path::new(SCENE_FILE)) {
+                Ok(()) => info!("Scene saved to {SCENE_FILE}"),
+                Err(err) => warn!("Failed to save the scene: {err}"),
+            }
+        }
+        if ctrl && input.just_pressed(KeyCode::KeyO) {
+            match Scene::load(Path::new(SCENE_FILE)).and_then(|scene| engine.load_scene(scene)) {
+                Ok(()) => info!("Scene loaded from {SCENE_FILE}"),
+                Err(err) => warn!("Failed to load the scene: {err}"),
+            }
+        }
+        // H hides the selected objects, Ctrl+H shows every object again
+        if input.just_pressed(KeyCode::KeyH) {
+            if ctrl {
+                for index in 0..engine.scene().objects.len() {
+                    engine.set_object_visible(ObjectId(index), true);
+                }
+            } else {
+                engine.hide_selected();
+            }
+        }
+        if input.just_pressed(KeyCode::Delete) {
+            let deleted = engine.delete_selected();
+            if deleted > 0 {
+                engine.notify(Level::Info, format!("Deleted {deleted} objects"));
+            }
+        }
+        if ctrl && input.just_pressed(KeyCode::KeyD) {
+            engine.duplicate_selected(DUPLICATE_OFFSET.into());
+            engine.set_gizmo_target(engine.selected().map(GizmoTarget::Object));
+        }
+        if input.just_pressed(KeyCode::KeyB) {
+            engine.set_show_bounds(!engine.show_bounds());
+        }
+        if !ctrl && input.just_pressed(KeyCode::KeyD) {
+            engine.set_show_lods(!engine.show_lods());
+        }
+        if input.just_pressed(KeyCode::KeyM) {
+            let present_mode = match engine.present_mode() {
+                Some(PresentModeKHR::MAILBOX) => PresentModeKHR::FIFO,
+                _ => PresentModeKHR::MAILBOX,
+            };
+            if let Err(err) = engine.set_present_mode(present_mode) {
+                warn!("Failed to switch present mode: {err}");
+            }
+        }
+        if input.just_pressed(KeyCode::KeyR) {
+            engine.set_render_scale(next_render_scale(engine.render_scale_override()));
+        }
+        if let Some(mut bloom) = engine.bloom_settings() {
+            if input.just_pressed(KeyCode::BracketLeft) {
+                bloom.intensity -= BLOOM_INTENSITY_STEP;
+            }
+            if input.just_pressed(KeyCode::BracketRight) {
+                bloom.intensity += BLOOM_INTENSITY_STEP;
+            }
+            engine.set_bloom_settings(bloom);
+        }
+        if let Some(mut ssao) = engine.ssao_settings() {
+            if input.just_pressed(KeyCode::KeyA) {
+                ssao.enabled = !ssao.enabled;
+            }
+            if input.just_pressed(KeyCode::Minus) {
+                ssao.radius -= SSAO_RADIUS_STEP;
+            }
+            if input.just_pressed(KeyCode::Equal) {
+                ssao.radius += SSAO_RADIUS_STEP;
+            }
+            engine.set_ssao_settings(ssao);
+        }
+        if input.just_pressed(KeyCode::KeyK) {
+            if engine.lights().len() <= 1 {
+                for light in light_ring() {
+                    engine.add_light(light);
+                }
+            } else {
+                engine.clear_lights();
+                engine.add_light(orbit_light());
+            }
+        }
+        if input.just_pressed(KeyCode::KeyG) {
+            let shading_path = match engine.shading_path() {
+                ShadingPath::Forward => ShadingPath::Deferred,
+                ShadingPath::Deferred => ShadingPath::Forward,
+            };
+            if let Err(err) = engine.set_shading_path(shading_path) {
+                warn!("Failed to switch shading path: {err}");
+            }
+        }
+        for texture in dropped_textures(input.dropped_files()) {
+            if let Err(err) = engine.load_texture_async(&texture) {
+                warn!("Failed to load {}: {err}", texture.display());
+            }
+        }
+        if input.just_pressed(KeyCode::KeyT) {
+            engine.set_show_gizmos(!engine.show_gizmos());
+        }
+        if input.just_pressed(KeyCode::KeyF) {
+            engine.set_show_debug_queries(!engine.show_debug_queries());
+        }
+        if input.just_pressed(KeyCode::KeyP) {
+            engine.set_debug_palette(engine.debug_palette().next());
+        }
+        let options = engine.pipeline_options();
+        let requested = if input.just_pressed(KeyCode::KeyW) {
+            options.toggle_wireframe()
+        } else if input.just_pressed(KeyCode::KeyC) {
+            options.next_cull_mode()
+        } else if input.just_pressed(KeyCode::KeyZ) {
+            PipelineOptions {
+                depth_test: !options.depth_test,
+                depth_write: !options.depth_test,
+                ..options
+            }
+        } else {
+            options
+        };
+        if requested != options {
+            match engine.set_pipeline_options(requested) {
+                Ok(()) => engine.notify(
+                    Level::Info,
+                    format!(
+                        "{:?} polygons, {:?} culled, depth test {}",
+                        requested.polygon_mode, requested.cull_mode, requested.depth_test
+                    ),
+                ),
+                Err(err) => {
+                    engine.notify(Level::Warn, format!("Pipeline options unchanged: {err}"))
+                }
+            }
+        }
+        if cfg!(debug_assertions) && input.just_pressed(KeyCode::F9) {
+            engine.request_test_panic();
+        }
+        let shift = input.is_held(KeyCode::ShiftLeft) || input.is_held(KeyCode::ShiftRight);
+        if let Some(cursor) = input.cursor_position() {
+            let (x, y) = (cursor.x as f32, cursor.y as f32);
+            if input.mouse_just_pressed(MouseButton::Left) && shift {
+                self.selection_drag = Some([x, y]);
+            } else if input.mouse_just_pressed(MouseButton::Left) && !engine.begin_gizmo_drag(x, y)
+            {
+                let object = if engine.picking_enabled() {
+                    engine.pick(cursor.x as u32, cursor.y as u32)
+                } else {
+                    engine.raycast(x, y).map(|hit| hit.object)
+                };
+                engine.select(object);
+                engine.set_gizmo_target(object.map(GizmoTarget::Object));
+            } else if input.is_mouse_held(MouseButton::Left) {
+                match self.selection_drag {
+                    Some(from) => {
+                        let color = engine.debug_palette().selection;
+                        engine.debug_draw().screen_rect(from, [x, y], color);
+                    }
+                    None => engine.drag_gizmo(x, y),
+                }
+            }
+            if let Some(from) = self
+                .selection_drag
+                .filter(|_| !input.is_mouse_held(MouseButton::Left))
+            {
+                self.selection_drag = None;
+                let objects = engine.objects_in_rect(from, [x, y]);
+                if ctrl {
+                    engine.extend_selection(objects);
+                } else {
+                    engine.set_selection(objects);
+                }
+                engine.set_gizmo_target(engine.selected().map(GizmoTarget::Object));
+            }
+        }
+        if let Some(GizmoTarget::Light(_)) = engine.gizmo_target() {
+            engine.select(None);
+        }
+        if !input.is_mouse_held(MouseButton::Left) {
+            engine.end_gizmo_drag();
+        }
+    }
+
+    fn ui(&mut self, ctx: &mut UiContext) {
+        ctx.label("caterpie");
+        if let Some(fps) = ctx.fps {
+            ctx.label(format!("{fps:.0} fps"));
+        }
+        ctx.label(format!("{:?}", ctx.pacing_mode));
+        if let Some(input_latency) = ctx.input_latency {
+            ctx.label(format!("{input_latency:.1} ms latency"));
+        }
+        if ctx.debug_palette != DebugPalette::DEFAULT {
+            ctx.label(format!("{} palette", ctx.debug_palette.name()));
+        }
+        if ctx.present.missed_vsyncs > 0 {
+            ctx.label(format!("{} missed vsyncs", ctx.present.missed_vsyncs));
+        }
+        if ctx.render_scale < 1.0 {
+            ctx.label(format!("{:.0}% res", ctx.render_scale * 100.0));
+        }
+        if let Some(bloom) = ctx.bloom {
+            ctx.label(format!(
+                "bloom {:.2} above {:.2}",
+                bloom.intensity, bloom.threshold
+            ));
+        }
+        if let Some(ssao) = ctx.ssao.filter(|ssao| ssao.enabled) {
+            ctx.label(format!("ssao r {:.2}", ssao.radius));
+        }
+        if let Some(fullscreen) = ctx.fullscreen {
+            ctx.label(format!("fullscreen {fullscreen}"));
+        }
+        if ctx.shading_path == ShadingPath::Deferred {
+            ctx.label("deferred");
+        }
+        if ctx.lights > 0 {
+            ctx.label(format!("{} lights", ctx.lights));
+        }
+        if ctx.culling.visible < ctx.culling.total {
+            ctx.label(format!(
+                "{}/{} drawn",
+                ctx.culling.visible, ctx.culling.total
+            ));
+        }
+        ctx.label(format!("{} triangles", ctx.culling.triangles));
+        for (queue, stats) in RenderQueue::ALL.into_iter().zip(ctx.culling.queues) {
+            if stats.draws > 0 {
+                ctx.label(format!(
+                    "{} {} draws {} changes",
+                    queue.name(),
+                    stats.draws,
+                    stats.state_changes
+                ));
+            }
+        }
+    }
+}
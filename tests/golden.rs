@@ -0,0 +1,286 @@
+// Renders small fixed scenes offscreen and compares them with the images in
+// tests/references. The animation is held at its start and the camera and
+// seed are the defaults, so a scene always gives the same image.
+//
+// Without a Vulkan device the renders are skipped, CATERPIE_REQUIRE_GPU=1
+// fails them instead. CATERPIE_BLESS=1 writes the renders as the new
+// references. A mismatch leaves the render and a diff image next to the
+// test binary's temporary files
+use std::{
+    env,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use caterpie::engine::{config::EngineConfig, scene::RenderObject, Engine, RgbaImage};
+use cgmath::{Deg, Matrix4, SquareMatrix, Vector3};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+// Largest channel difference a pixel may have before it counts as changed,
+// drivers round filtering and blending a little differently
+const PIXEL_TOLERANCE: u8 = 8;
+// Changed pixels an image may have, in parts of the whole. Triangle edges
+// move by a pixel between drivers
+const CHANGED_TOLERANCE: f64 = 0.002;
+
+// One Vulkan device at a time
+static RENDER: Mutex<()> = Mutex::new(());
+
+// How far a render is from its reference
+struct Diff {
+    changed: usize,
+    worst: u8,
+    // Changed pixels in red over the reference, darkened
+    image: RgbaImage,
+}
+
+impl Diff {
+    fn passes(&self) -> bool {
+        let pixels = (self.image.width * self.image.height) as f64;
+        self.changed as f64 <= pixels * CHANGED_TOLERANCE
+    }
+}
+
+fn diff(reference: &RgbaImage, actual: &RgbaImage) -> Result<Diff, String> {
+    if (reference.width, reference.height) != (actual.width, actual.height) {
+        return Err(format!(
+            "rendered {}x{}, the reference is {}x{}",
+            actual.width, actual.height, reference.width, reference.height
+        ));
+    }
+    let mut changed = 0;
+    let mut worst = 0;
+    let pixels = reference
+        .pixels
+        .chunks_exact(4)
+        .zip(actual.pixels.chunks_exact(4))
+        .flat_map(|(expected, pixel)| {
+            let distance = expected
+                .iter()
+                .zip(pixel)
+                .map(|(a, b)| a.abs_diff(*b))
+                .max()
+                .unwrap();
+            worst = worst.max(distance);
+            if distance > PIXEL_TOLERANCE {
+                changed += 1;
+                [255, 0, 0, 255]
+            } else {
+                let luma = ((u32::from(expected[0]) * 3
+                    + u32::from(expected[1]) * 6
+                    + u32::from(expected[2]))
+                    / 30) as u8;
+                [luma, luma, luma, 255]
+            }
+        })
+        .collect();
+    Ok(Diff {
+        changed,
+        worst,
+        image: RgbaImage {
+            width: reference.width,
+            height: reference.height,
+            pixels,
+        },
+    })
+}
+
+fn read_png(path: &Path) -> Result<RgbaImage, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    let mut reader = png::Decoder::new(BufReader::new(file))
+        .read_info()
+        .map_err(|err| err.to_string())?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let frame = reader
+        .next_frame(&mut pixels)
+        .map_err(|err| err.to_string())?;
+    if (frame.color_type, frame.bit_depth) != (png::ColorType::Rgba, png::BitDepth::Eight) {
+        return Err(format!("{} isn't 8 bit RGBA", path.display()));
+    }
+    pixels.truncate(frame.buffer_size());
+    Ok(RgbaImage {
+        width: frame.width,
+        height: frame.height,
+        pixels,
+    })
+}
+
+fn env_set(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| !value.is_empty() && value != "0")
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/references")
+        .join(format!("{name}.png"))
+}
+
+fn check(name: &str, scene: &[RenderObject]) {
+    let config = EngineConfig {
+        validation: false,
+        ..EngineConfig::default()
+    };
+    let rendered = {
+        let _device = RENDER
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Engine::render_offscreen(config, WIDTH, HEIGHT, scene)
+    };
+    let actual = match rendered {
+        Ok(image) => image,
+        Err(err) if !env_set("CATERPIE_REQUIRE_GPU") => {
+            eprintln!("Skipping the {name} golden image, nothing to render with: {err}");
+            return;
+        }
+        Err(err) => panic!("Rendering the {name} golden image failed: {err}"),
+    };
+
+    let reference = reference_path(name);
+    if env_set("CATERPIE_BLESS") {
+        fs::create_dir_all(reference.parent().unwrap()).unwrap();
+        actual.write_png(&reference).unwrap();
+        eprintln!("Blessed {}", reference.display());
+        return;
+    }
+    let expected = read_png(&reference).unwrap_or_else(|err| {
+        panic!("No reference for {name} ({err}), render one with CATERPIE_BLESS=1")
+    });
+    let result = diff(&expected, &actual);
+    if result.as_ref().is_ok_and(Diff::passes) {
+        return;
+    }
+    let artifacts = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&artifacts).unwrap();
+    let actual_path = artifacts.join(format!("{name}.actual.png"));
+    actual.write_png(&actual_path).unwrap();
+    match result {
+        Ok(result) => {
+            let diff_path = artifacts.join(format!("{name}.diff.png"));
+            result.image.write_png(&diff_path).unwrap();
+            panic!(
+                "{name} differs from its reference in {} pixels, by up to {}. See {} and {}",
+                result.changed,
+                result.worst,
+                actual_path.display(),
+                diff_path.display()
+            );
+        }
+        Err(err) => panic!("{name}: {err}. See {}", actual_path.display()),
+    }
+}
+
+#[test]
+fn model() {
+    check("model", &[RenderObject::new(Matrix4::identity())]);
+}
+
+// Overlapping copies, turned so their sides face the light differently
+#[test]
+fn row_of_models() {
+    let scene = [-1.5f32, 0.0, 1.5].map(|x| {
+        RenderObject::new(
+            Matrix4::from_translation(Vector3::new(x, 0.0, x * 0.5))
+                * Matrix4::from_angle_z(Deg(x * 30.0))
+                * Matrix4::from_scale(0.6),
+        )
+    });
+    check("row_of_models", &scene);
+}
+
+// The comparison on its own, without a device
+mod compare {
+    use super::*;
+
+    fn image(pixels: impl Fn(u32, u32) -> [u8; 4]) -> RgbaImage {
+        let (width, height) = (20, 10);
+        RgbaImage {
+            width,
+            height,
+            pixels: (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .flat_map(|(x, y)| pixels(x, y))
+                .collect(),
+        }
+    }
+
+    fn gradient(x: u32, y: u32) -> [u8; 4] {
+        [x as u8 * 10, y as u8 * 20, 128, 255]
+    }
+
+    #[test]
+    fn identical_images_pass() {
+        let result = diff(&image(gradient), &image(gradient)).unwrap();
+        assert_eq!((result.changed, result.worst), (0, 0));
+        assert!(result.passes());
+    }
+
+    #[test]
+    fn small_differences_are_tolerated() {
+        let noisy = image(|x, y| gradient(x, y).map(|channel| channel.saturating_add(3)));
+        let result = diff(&image(gradient), &noisy).unwrap();
+        assert_eq!((result.changed, result.worst), (0, 3));
+        assert!(result.passes());
+    }
+
+    // A changed block is reported and marked in red, nothing else is
+    #[test]
+    fn changed_pixels_fail_and_are_marked() {
+        let changed = |x: u32, y: u32| x < 3 && y < 2;
+        let actual = image(|x, y| {
+            if changed(x, y) {
+                [255, 255, 255, 255]
+            } else {
+                gradient(x, y)
+            }
+        });
+        let result = diff(&image(gradient), &actual).unwrap();
+        assert_eq!(result.changed, 6);
+        assert!(!result.passes());
+        for (index, pixel) in result.image.pixels.chunks_exact(4).enumerate() {
+            let (x, y) = (index as u32 % 20, index as u32 / 20);
+            assert_eq!(pixel == [255, 0, 0, 255], changed(x, y), "{x} {y}");
+        }
+    }
+
+    // One stray pixel in a larger image is within the tolerance
+    #[test]
+    fn a_stray_pixel_passes() {
+        let reference = RgbaImage {
+            width: 100,
+            height: 100,
+            pixels: vec![0; 100 * 100 * 4],
+        };
+        let mut actual = RgbaImage {
+            width: 100,
+            height: 100,
+            pixels: reference.pixels.clone(),
+        };
+        actual.pixels[0] = 255;
+        assert!(diff(&reference, &actual).unwrap().passes());
+    }
+
+    #[test]
+    fn sizes_have_to_match() {
+        let small = RgbaImage {
+            width: 1,
+            height: 1,
+            pixels: vec![0; 4],
+        };
+        assert!(diff(&image(gradient), &small).is_err());
+    }
+
+    #[test]
+    fn written_images_read_back() {
+        let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden_roundtrip.png");
+        let written = image(gradient);
+        written.write_png(&path).unwrap();
+        let read = read_png(&path).unwrap();
+        assert_eq!(
+            (read.width, read.height, read.pixels),
+            (written.width, written.height, written.pixels)
+        );
+    }
+}